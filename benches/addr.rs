@@ -0,0 +1,107 @@
+//! Benchmarks of the address and page helpers that are used on the page fault and mapping
+//! paths. Run with `cargo bench`, which requires the `nightly` feature.
+
+#![cfg(feature = "nightly")]
+#![feature(test)]
+
+extern crate test;
+
+use test::{black_box, Bencher};
+use x86_64::structures::paging::{Page, PageTableIndex, PhysFrame, Size2MiB, Size4KiB};
+use x86_64::{align_down, align_up, PhysAddr, VirtAddr};
+
+/// Addresses from both halves of the address space, some of them needing sign extension.
+const ADDRESSES: [u64; 8] = [
+    0x0000_0000_0000_1000,
+    0x0000_0000_dead_beef,
+    0x0000_7fff_ffff_f123,
+    0x0000_8000_0000_0000,
+    0x0000_ffff_ffff_e000,
+    0xffff_8000_0000_0001,
+    0xffff_ffff_8000_0000,
+    0xffff_ffff_ffff_f000,
+];
+
+#[bench]
+fn virt_addr_new(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            black_box(VirtAddr::new(addr));
+        }
+    });
+}
+
+#[bench]
+fn virt_addr_try_new(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            let _ = black_box(VirtAddr::try_new(addr ^ 0x0001_0000_0000_0000));
+        }
+    });
+}
+
+#[bench]
+fn virt_addr_new_truncate(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            black_box(VirtAddr::new_truncate(addr));
+        }
+    });
+}
+
+#[bench]
+fn align(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            let align = black_box(0x1000);
+            black_box(align_up(addr & 0x0000_ffff_ffff_ffff, align));
+            black_box(align_down(addr, align));
+        }
+    });
+}
+
+#[bench]
+fn page_containing_address(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            let addr = VirtAddr::new(addr);
+            black_box(Page::<Size4KiB>::containing_address(addr));
+            black_box(Page::<Size2MiB>::containing_address(addr));
+        }
+    });
+}
+
+#[bench]
+fn page_table_indices(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+            black_box((
+                page.p4_index(),
+                page.p3_index(),
+                page.p2_index(),
+                page.p1_index(),
+            ));
+        }
+    });
+}
+
+#[bench]
+fn page_from_indices(b: &mut Bencher) {
+    b.iter(|| {
+        for index in 0..black_box(64u16) {
+            let index = PageTableIndex::new_truncate(index * 8);
+            black_box(Page::from_page_table_indices(index, index, index, index));
+        }
+    });
+}
+
+#[bench]
+fn frame_containing_address(b: &mut Bencher) {
+    b.iter(|| {
+        for &addr in black_box(&ADDRESSES) {
+            let addr = PhysAddr::new_truncate(addr);
+            black_box(PhysFrame::<Size4KiB>::containing_address(addr));
+        }
+    });
+}
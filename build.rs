@@ -1,24 +1,25 @@
-#[cfg(feature = "inline_asm")]
+#[cfg(not(feature = "external_asm"))]
 fn main() {}
 
-#[cfg(all(not(feature = "inline_asm"), not(feature = "stable")))]
-fn main() {
-    compile_error!("Neither feature \"stable\" nor \"nightly\" was set!");
-}
-
-#[cfg(all(not(feature = "inline_asm"), feature = "stable"))]
+#[cfg(feature = "external_asm")]
 fn main() {
     use std::ffi::OsString;
     use std::fs;
 
     println!("cargo:rerun-if-changed=build.rs");
 
+    // the `cpu_local` section bounds are defined by ELF linkers
+    let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let target_vendor = std::env::var("CARGO_CFG_TARGET_VENDOR").unwrap_or_default();
+    let elf = !matches!(target_os.as_str(), "windows" | "uefi") && target_vendor != "apple";
+
     let entries = fs::read_dir("src/asm")
         .unwrap()
         .filter_map(|f| {
             f.ok().and_then(|e| {
                 let path = e.path();
                 match path.extension() {
+                    Some(_) if !elf && path.ends_with("cpu_local.s") => None,
                     Some(ext) if ext.eq(&OsString::from("s")) => Some(path),
                     _ => None,
                 }
@@ -1,8 +1,8 @@
+use core::convert::TryFrom;
 use core::fmt;
 use core::ops::{Add, AddAssign, Sub, SubAssign};
 
-use crate::structures::paging::{PageOffset, PageTableIndex};
-use bit_field::BitField;
+use crate::structures::paging::{PageOffset, PageTableIndex, PageTableLevel};
 
 /// A canonical 64-bit virtual memory address.
 ///
@@ -13,6 +13,17 @@ use bit_field::BitField;
 /// On `x86_64`, only the 48 lower bits of a virtual address can be used. The top 16 bits need
 /// to be copies of bit 47, i.e. the most significant bit. Addresses that fulfil this criterium
 /// are called “canonical”. This type guarantees that it always represents a canonical address.
+///
+/// With 5-level paging (CR4.LA57), the 57 lower bits can be used and the top 7 bits need to be
+/// copies of bit 56. Such addresses are created with [`new_la57`](VirtAddr::new_la57) and the
+/// related functions. The arithmetic operators always produce 4-level canonical addresses, so
+/// the `*_la57` methods such as [`add_la57`](VirtAddr::add_la57) have to be used for addresses
+/// of a 5-level address space.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u64", into = "u64")
+)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct VirtAddr(u64);
@@ -25,6 +36,11 @@ pub struct VirtAddr(u64);
 ///
 /// On `x86_64`, only the 52 lower bits of a physical address can be used. The top 12 bits need
 /// to be zero. This type guarantees that it always represents a valid physical address.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u64", into = "u64")
+)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct PhysAddr(u64);
@@ -38,6 +54,12 @@ pub struct PhysAddr(u64);
 #[derive(Debug)]
 pub struct VirtAddrNotValid(u64);
 
+impl fmt::Display for VirtAddrNotValid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid virtual address (upper bits {:#x})", self.0)
+    }
+}
+
 impl VirtAddr {
     /// Creates a new canonical virtual address.
     ///
@@ -59,10 +81,13 @@ impl VirtAddr {
     /// is returned.
     #[inline]
     pub fn try_new(addr: u64) -> Result<VirtAddr, VirtAddrNotValid> {
-        match addr.get_bits(47..64) {
-            0 | 0x1ffff => Ok(VirtAddr(addr)),     // address is canonical
-            1 => Ok(VirtAddr::new_truncate(addr)), // address needs sign extension
-            other => Err(VirtAddrNotValid(other)),
+        let truncated = VirtAddr::new_truncate(addr);
+        // the address is either canonical already or only needs sign extension because bits
+        // 48..64 are null; checked with a single branch since this is on hot paths
+        if (truncated.0 == addr) | (addr >> 48 == 0) {
+            Ok(truncated)
+        } else {
+            Err(VirtAddrNotValid(addr >> 47))
         }
     }
 
@@ -78,6 +103,39 @@ impl VirtAddr {
         VirtAddr(((addr << 16) as i64 >> 16) as u64)
     }
 
+    /// Creates a new virtual address that is canonical with 5-level paging.
+    ///
+    /// This function performs sign extension of bit 56 to make the address canonical. Panics
+    /// if the bits in the range 57 to 64 contain data (i.e. are not null and no sign extension).
+    #[inline]
+    pub fn new_la57(addr: u64) -> VirtAddr {
+        Self::try_new_la57(addr).expect(
+            "address passed to VirtAddr::new_la57 must not contain any data \
+             in bits 57 to 64",
+        )
+    }
+
+    /// Tries to create a new virtual address that is canonical with 5-level paging.
+    ///
+    /// It succeeds if bits 57 to 64 are either a correct sign extension (i.e. copies of bit 56)
+    /// or all null. Else, an error is returned.
+    #[inline]
+    pub fn try_new_la57(addr: u64) -> Result<VirtAddr, VirtAddrNotValid> {
+        let truncated = VirtAddr::new_truncate_la57(addr);
+        if (truncated.0 == addr) | (addr >> 57 == 0) {
+            Ok(truncated)
+        } else {
+            Err(VirtAddrNotValid(addr >> 56))
+        }
+    }
+
+    /// Creates a new virtual address that is canonical with 5-level paging, throwing out bits
+    /// 57..64.
+    #[inline]
+    pub const fn new_truncate_la57(addr: u64) -> VirtAddr {
+        VirtAddr(((addr << 7) as i64 >> 7) as u64)
+    }
+
     /// Alias for [`new_truncate`][VirtAddr::new_truncate] for backwards compatibility.
     #[inline]
     #[deprecated(note = "Use new_truncate or new_unsafe instead")]
@@ -140,7 +198,38 @@ impl VirtAddr {
     where
         U: Into<u64>,
     {
-        VirtAddr(align_up(self.0, align.into()))
+        // aligning the end of the lower half up yields the start of the higher half
+        VirtAddr::new_truncate(align_up(self.0, align.into()))
+    }
+
+    /// Aligns the virtual address upwards to the given alignment in a 5-level address space.
+    ///
+    /// Unlike [`align_up`](VirtAddr::align_up), this only yields the start of the higher half
+    /// when aligning up the end of the 57-bit lower half.
+    #[inline]
+    pub fn align_up_la57<U>(self, align: U) -> Self
+    where
+        U: Into<u64>,
+    {
+        VirtAddr::new_truncate_la57(align_up(self.0, align.into()))
+    }
+
+    /// Adds the given offset to the address in a 5-level address space.
+    ///
+    /// Panics if the result is not canonical with 5-level paging. Use the `+` operator for
+    /// 4-level addresses.
+    #[inline]
+    pub fn add_la57(self, rhs: u64) -> Self {
+        VirtAddr::new_la57(self.0.checked_add(rhs).unwrap())
+    }
+
+    /// Subtracts the given offset from the address in a 5-level address space.
+    ///
+    /// Panics if the result is not canonical with 5-level paging. Use the `-` operator for
+    /// 4-level addresses.
+    #[inline]
+    pub fn sub_la57(self, rhs: u64) -> Self {
+        VirtAddr::new_la57(self.0.checked_sub(rhs).unwrap())
     }
 
     /// Aligns the virtual address downwards to the given alignment.
@@ -160,7 +249,7 @@ impl VirtAddr {
     where
         U: Into<u64>,
     {
-        self.align_down(align) == self
+        is_aligned(self.0, align.into())
     }
 
     /// Returns the 12-bit page offset of this virtual address.
@@ -192,6 +281,18 @@ impl VirtAddr {
     pub const fn p4_index(self) -> PageTableIndex {
         PageTableIndex::new_truncate((self.0 >> 12 >> 9 >> 9 >> 9) as u16)
     }
+
+    /// Returns the 9-bit page table index of the given level.
+    #[inline]
+    pub const fn page_table_index(self, level: PageTableLevel) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 12 >> ((level as u8 - 1) * 9)) as u16)
+    }
+
+    /// Returns the 9-bit level 5 page table index, which is only used with 5-level paging.
+    #[inline]
+    pub const fn p5_index(self) -> PageTableIndex {
+        PageTableIndex::new_truncate((self.0 >> 12 >> 9 >> 9 >> 9 >> 9) as u16)
+    }
 }
 
 impl fmt::Debug for VirtAddr {
@@ -200,6 +301,30 @@ impl fmt::Debug for VirtAddr {
     }
 }
 
+/// Converts an address that is canonical with 4-level or 5-level paging.
+///
+/// Unlike [`VirtAddr::try_new`], this doesn't perform sign extension, so the conversion is
+/// lossless and the inverse of the conversion to `u64`.
+impl TryFrom<u64> for VirtAddr {
+    type Error = VirtAddrNotValid;
+
+    #[inline]
+    fn try_from(addr: u64) -> Result<Self, Self::Error> {
+        if VirtAddr::new_truncate(addr).0 == addr || VirtAddr::new_truncate_la57(addr).0 == addr {
+            Ok(VirtAddr(addr))
+        } else {
+            Err(VirtAddrNotValid(addr >> 47))
+        }
+    }
+}
+
+impl From<VirtAddr> for u64 {
+    #[inline]
+    fn from(addr: VirtAddr) -> u64 {
+        addr.0
+    }
+}
+
 impl Add<u64> for VirtAddr {
     type Output = Self;
     #[inline]
@@ -278,15 +403,20 @@ impl Sub<VirtAddr> for VirtAddr {
 #[derive(Debug)]
 pub struct PhysAddrNotValid(u64);
 
+impl fmt::Display for PhysAddrNotValid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid physical address (bits 52 to 64: {:#x})", self.0)
+    }
+}
+
 impl PhysAddr {
     /// Creates a new physical address.
     ///
     /// Panics if a bit in the range 52 to 64 is set.
     #[inline]
     pub fn new(addr: u64) -> PhysAddr {
-        assert_eq!(
-            addr.get_bits(52..64),
-            0,
+        assert!(
+            addr >> 52 == 0,
             "physical addresses must not have any bits in the range 52 to 64 set"
         );
         PhysAddr(addr)
@@ -313,7 +443,7 @@ impl PhysAddr {
     /// Fails if any bits in the range 52 to 64 are set.
     #[inline]
     pub fn try_new(addr: u64) -> Result<PhysAddr, PhysAddrNotValid> {
-        match addr.get_bits(52..64) {
+        match addr >> 52 {
             0 => Ok(PhysAddr(addr)), // address is valid
             other => Err(PhysAddrNotValid(other)),
         }
@@ -365,7 +495,7 @@ impl PhysAddr {
     where
         U: Into<u64>,
     {
-        self.align_down(align) == self
+        is_aligned(self.0, align.into())
     }
 }
 
@@ -375,6 +505,22 @@ impl fmt::Debug for PhysAddr {
     }
 }
 
+impl TryFrom<u64> for PhysAddr {
+    type Error = PhysAddrNotValid;
+
+    #[inline]
+    fn try_from(addr: u64) -> Result<Self, Self::Error> {
+        PhysAddr::try_new(addr)
+    }
+}
+
+impl From<PhysAddr> for u64 {
+    #[inline]
+    fn from(addr: PhysAddr) -> u64 {
+        addr.0
+    }
+}
+
 impl fmt::Binary for PhysAddr {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -475,28 +621,38 @@ impl Sub<PhysAddr> for PhysAddr {
     }
 }
 
-/// Align address downwards.
-///
-/// Returns the greatest x with alignment `align` so that x <= addr. The alignment must be
-///  a power of 2.
-#[inline]
-pub fn align_down(addr: u64, align: u64) -> u64 {
-    assert!(align.is_power_of_two(), "`align` must be a power of two");
-    addr & !(align - 1)
+const_fn! {
+    /// Align address downwards.
+    ///
+    /// Returns the greatest x with alignment `align` so that x <= addr. The alignment must be
+    ///  a power of 2.
+    #[inline]
+    pub fn align_down(addr: u64, align: u64) -> u64 {
+        assert!(align.is_power_of_two(), "`align` must be a power of two");
+        addr & !(align - 1)
+    }
 }
 
-/// Align address upwards.
-///
-/// Returns the smallest x with alignment `align` so that x >= addr. The alignment must be
-/// a power of 2.
-#[inline]
-pub fn align_up(addr: u64, align: u64) -> u64 {
-    assert!(align.is_power_of_two(), "`align` must be a power of two");
-    let align_mask = align - 1;
-    if addr & align_mask == 0 {
-        addr // already aligned
-    } else {
-        (addr | align_mask) + 1
+const_fn! {
+    /// Align address upwards.
+    ///
+    /// Returns the smallest x with alignment `align` so that x >= addr. The alignment must be
+    /// a power of 2.
+    #[inline]
+    pub fn align_up(addr: u64, align: u64) -> u64 {
+        assert!(align.is_power_of_two(), "`align` must be a power of two");
+        let align_mask = align - 1;
+        // overflows exactly if the aligned address doesn't fit into an `u64`
+        (addr + align_mask) & !align_mask
+    }
+}
+
+const_fn! {
+    /// Checks whether the address has the given alignment, which must be a power of 2.
+    #[inline]
+    fn is_aligned(addr: u64, align: u64) -> bool {
+        assert!(align.is_power_of_two(), "`align` must be a power of two");
+        addr & (align - 1) == 0
     }
 }
 
@@ -512,6 +668,124 @@ mod tests {
         assert_eq!(VirtAddr::new_truncate(123 << 47), VirtAddr(0xfffff << 47));
     }
 
+    #[test]
+    pub fn virtaddr_try_new() {
+        assert_eq!(
+            VirtAddr::try_new(0x7fff_ffff_ffff).unwrap(),
+            VirtAddr(0x7fff_ffff_ffff)
+        );
+        assert_eq!(
+            VirtAddr::try_new(0x8000_0000_0000).unwrap(),
+            VirtAddr(0xffff_8000_0000_0000)
+        );
+        assert_eq!(
+            VirtAddr::try_new(0xffff_8000_0000_0000).unwrap(),
+            VirtAddr(0xffff_8000_0000_0000)
+        );
+        assert!(VirtAddr::try_new(0xffff_0000_0000_0000).is_err());
+        assert!(VirtAddr::try_new(0x0001_8000_0000_0000).is_err());
+
+        // aligning up across the non-canonical hole yields the start of the higher half
+        let end_of_lower_half = VirtAddr::new(0x7fff_ffff_f001);
+        assert_eq!(
+            end_of_lower_half.align_up(0x1000u64),
+            VirtAddr(0xffff_8000_0000_0000)
+        );
+    }
+
+    #[test]
+    pub fn virtaddr_la57() {
+        assert_eq!(
+            VirtAddr::try_new_la57(0x0001_8000_0000_0000).unwrap(),
+            VirtAddr(0x0001_8000_0000_0000)
+        );
+        assert_eq!(
+            VirtAddr::try_new_la57(0x0100_0000_0000_0000).unwrap(),
+            VirtAddr(0xff00_0000_0000_0000)
+        );
+        assert!(VirtAddr::try_new_la57(0xfe00_0000_0000_0000).is_err());
+        assert_eq!(
+            VirtAddr::new_truncate_la57(0x1ff << 48).p5_index(),
+            PageTableIndex::new(0x1ff)
+        );
+
+        let addr = VirtAddr::new_la57(0xff11_0000_0000_0000);
+        assert_eq!(addr.add_la57(0x1000), VirtAddr(0xff11_0000_0000_1000));
+        assert_eq!(addr.sub_la57(0x1000), VirtAddr(0xff10_ffff_ffff_f000));
+    }
+
+    #[test]
+    pub fn virtaddr_la57_crosses_bit_47() {
+        let end_of_4level_half = VirtAddr::new_la57(0x7fff_ffff_f000);
+        assert_eq!(
+            end_of_4level_half.add_la57(0x1000),
+            VirtAddr(0x8000_0000_0000)
+        );
+        assert_eq!(
+            VirtAddr::new_la57(0x8000_0000_0000).sub_la57(0x1000),
+            end_of_4level_half
+        );
+        assert_eq!(
+            VirtAddr::new(0x7fff_ffff_f001).align_up_la57(0x1000u64),
+            VirtAddr(0x8000_0000_0000)
+        );
+        // the operators keep using the 4-level canonical form
+        assert_eq!(
+            end_of_4level_half + 0x1000u64,
+            VirtAddr(0xffff_8000_0000_0000)
+        );
+        // only the end of the 57-bit lower half is aligned up to the higher half
+        assert_eq!(
+            VirtAddr::new_la57(0x00ff_ffff_ffff_f001).align_up_la57(0x1000u64),
+            VirtAddr(0xff00_0000_0000_0000)
+        );
+    }
+
+    #[test]
+    pub fn conversions() {
+        assert_eq!(
+            VirtAddr::try_from(0x7fff_ffff_ffff).unwrap(),
+            VirtAddr(0x7fff_ffff_ffff)
+        );
+        assert_eq!(
+            VirtAddr::try_from(0xff11_0000_0000_0000).unwrap(),
+            VirtAddr::new_la57(0xff11_0000_0000_0000)
+        );
+        // no sign extension, unlike `VirtAddr::try_new`
+        assert!(VirtAddr::try_from(0x0100_0000_0000_0000).is_err());
+        assert_eq!(u64::from(VirtAddr::new(0x1234)), 0x1234);
+
+        assert_eq!(PhysAddr::try_from(0x1234).unwrap(), PhysAddr(0x1234));
+        assert!(PhysAddr::try_from(1 << 52).is_err());
+        assert_eq!(u64::from(PhysAddr::new(0x1234)), 0x1234);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn serde_rejects_invalid_addresses() {
+        use serde::de::{value::Error, Deserialize, IntoDeserializer};
+
+        let virt =
+            |addr: u64| VirtAddr::deserialize(addr.into_deserializer()).map_err(|_: Error| ());
+        for addr in [
+            VirtAddr::new(0xffff_8000_0000_0000),
+            VirtAddr::new_la57(0x0000_8000_0000_0000),
+            VirtAddr::new_la57(0x0001_0000_0000_0000),
+        ] {
+            // serialized as `u64::from(addr)`
+            assert_eq!(virt(u64::from(addr)), Ok(addr));
+        }
+        assert!(virt(0x0100_0000_0000_0000).is_err());
+
+        let phys =
+            |addr: u64| PhysAddr::deserialize(addr.into_deserializer()).map_err(|_: Error| ());
+        assert_eq!(
+            phys(u64::from(PhysAddr::new(0x1000))),
+            Ok(PhysAddr::new(0x1000))
+        );
+        assert!(phys(1 << 52).is_err());
+    }
+
     #[test]
     pub fn test_align_up() {
         // align 1
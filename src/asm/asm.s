@@ -82,6 +82,13 @@ _x86_64_asm_set_cs:
 1:
     retq
 
+.global _x86_64_asm_far_return
+.p2align 4
+_x86_64_asm_far_return:
+    pushq %rdi
+    pushq %rsi
+    lretq
+
 .global _x86_64_asm_get_cs
 .p2align 4
 _x86_64_asm_get_cs:
@@ -101,6 +108,12 @@ _x86_64_asm_ltr:
     ltr %dx
     retq
 
+.global _x86_64_asm_str
+.p2align 4
+_x86_64_asm_str:
+    str %ax
+    retq
+
 .global _x86_64_asm_lgdt
 .p2align 4
 _x86_64_asm_lgdt:
@@ -233,3 +246,509 @@ _x86_64_asm_wrmsr:
 _x86_64_asm_hlt:
     hlt
     retq
+
+.global _x86_64_asm_read_gs_0
+.p2align 4
+_x86_64_asm_read_gs_0:
+    movq %gs:0, %rax
+    retq
+
+.global _x86_64_asm_bochs
+.p2align 4
+_x86_64_asm_bochs:
+    xchgw %bx, %bx
+    retq
+
+.global _x86_64_asm_read_rip
+.p2align 4
+_x86_64_asm_read_rip:
+    movq (%rsp), %rax   # the return address is the instruction pointer of the caller
+    retq
+
+.global _x86_64_asm_verw
+.p2align 4
+_x86_64_asm_verw:
+    pushq %rdi          # verw only clears the buffers with a memory operand
+    verw (%rsp)
+    popq %rdi
+    retq
+
+# The VMX instructions return 0 on success, 1 on VMfailInvalid and 2 on VMfailValid.
+.p2align 4
+_x86_64_asm_vmx_status:
+    setc %al
+    setz %cl
+    addb %cl, %cl
+    orb %cl, %al
+    movzbl %al, %eax
+    retq
+
+.global _x86_64_asm_vmxon
+.p2align 4
+_x86_64_asm_vmxon:
+    vmxon (%rdi)
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmxoff
+.p2align 4
+_x86_64_asm_vmxoff:
+    vmxoff
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmclear
+.p2align 4
+_x86_64_asm_vmclear:
+    vmclear (%rdi)
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmptrld
+.p2align 4
+_x86_64_asm_vmptrld:
+    vmptrld (%rdi)
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmptrst
+.p2align 4
+_x86_64_asm_vmptrst:
+    vmptrst (%rdi)
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmread
+.p2align 4
+_x86_64_asm_vmread:
+    vmread %rdi, (%rsi)
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmwrite
+.p2align 4
+_x86_64_asm_vmwrite:
+    vmwrite %rsi, %rdi
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmlaunch
+.p2align 4
+_x86_64_asm_vmlaunch:
+    vmlaunch
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmresume
+.p2align 4
+_x86_64_asm_vmresume:
+    vmresume
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_vmcall
+.p2align 4
+_x86_64_asm_vmcall:
+    pushq %rbx          # rbx is callee-saved
+    pushq %rdi          # the hypervisor may clobber rdi
+    movq (%rdi), %rax
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    vmcall
+    popq %rdi
+    movq %rax, (%rdi)
+    movq %rbx, 8(%rdi)
+    movq %rcx, 16(%rdi)
+    movq %rdx, 24(%rdi)
+    movq %rsi, 32(%rdi)
+    popq %rbx
+    retq
+
+.global _x86_64_asm_vmmcall
+.p2align 4
+_x86_64_asm_vmmcall:
+    pushq %rbx          # rbx is callee-saved
+    pushq %rdi          # the hypervisor may clobber rdi
+    movq (%rdi), %rax
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    vmmcall
+    popq %rdi
+    movq %rax, (%rdi)
+    movq %rbx, 8(%rdi)
+    movq %rcx, 16(%rdi)
+    movq %rdx, 24(%rdi)
+    movq %rsi, 32(%rdi)
+    popq %rbx
+    retq
+
+.global _x86_64_asm_vmfunc
+.p2align 4
+_x86_64_asm_vmfunc:
+    movl %edi, %eax
+    movq %rsi, %rcx
+    vmfunc
+    retq
+
+.global _x86_64_asm_get_ss
+.p2align 4
+_x86_64_asm_get_ss:
+    mov %ss, %ax
+    retq
+
+.global _x86_64_asm_get_ds
+.p2align 4
+_x86_64_asm_get_ds:
+    mov %ds, %ax
+    retq
+
+.global _x86_64_asm_get_es
+.p2align 4
+_x86_64_asm_get_es:
+    mov %es, %ax
+    retq
+
+.global _x86_64_asm_get_fs
+.p2align 4
+_x86_64_asm_get_fs:
+    mov %fs, %ax
+    retq
+
+.global _x86_64_asm_get_gs
+.p2align 4
+_x86_64_asm_get_gs:
+    mov %gs, %ax
+    retq
+
+.global _x86_64_asm_sgdt
+.p2align 4
+_x86_64_asm_sgdt:
+    sgdt (%rdi)
+    retq
+
+.global _x86_64_asm_sidt
+.p2align 4
+_x86_64_asm_sidt:
+    sidt (%rdi)
+    retq
+
+.global _x86_64_asm_vmentry
+.p2align 4
+_x86_64_asm_vmentry:
+    pushq %rbp
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    pushq %rdi
+    movq $0x6c14, %rax
+    vmwrite %rsp, %rax
+    leaq 2f(%rip), %rcx
+    movq $0x6c16, %rax
+    vmwrite %rcx, %rax
+    testb %sil, %sil
+    movq 0(%rdi), %rax
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    movq 48(%rdi), %rbp
+    movq 56(%rdi), %r8
+    movq 64(%rdi), %r9
+    movq 72(%rdi), %r10
+    movq 80(%rdi), %r11
+    movq 88(%rdi), %r12
+    movq 96(%rdi), %r13
+    movq 104(%rdi), %r14
+    movq 112(%rdi), %r15
+    movq 40(%rdi), %rdi
+    jnz 3f
+    vmlaunch
+    jmp 4f
+3:
+    vmresume
+4:
+    setc %al
+    setz %cl
+    addb %cl, %cl
+    orb %cl, %al
+    movzbl %al, %eax
+    addq $8, %rsp
+    jmp 5f
+2:
+    pushq %rdi
+    movq 8(%rsp), %rdi
+    movq %rax, 0(%rdi)
+    movq %rbx, 8(%rdi)
+    movq %rcx, 16(%rdi)
+    movq %rdx, 24(%rdi)
+    movq %rsi, 32(%rdi)
+    movq %rbp, 48(%rdi)
+    movq %r8, 56(%rdi)
+    movq %r9, 64(%rdi)
+    movq %r10, 72(%rdi)
+    movq %r11, 80(%rdi)
+    movq %r12, 88(%rdi)
+    movq %r13, 96(%rdi)
+    movq %r14, 104(%rdi)
+    movq %r15, 112(%rdi)
+    popq 40(%rdi)
+    addq $8, %rsp
+    xorl %eax, %eax
+5:
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    popq %rbp
+    retq
+
+.global _x86_64_asm_invept
+.p2align 4
+_x86_64_asm_invept:
+    invept (%rsi), %rdi
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_invvpid
+.p2align 4
+_x86_64_asm_invvpid:
+    invvpid (%rsi), %rdi
+    jmp _x86_64_asm_vmx_status
+
+.global _x86_64_asm_hv_hypercall
+.p2align 4
+_x86_64_asm_hv_hypercall:
+    movq %rcx, %r8      # output parameter
+    movq %rsi, %rcx     # input value, the input parameter is already in rdx
+    callq *%rdi
+    retq
+
+.global _x86_64_asm_tdcall
+.p2align 4
+_x86_64_asm_tdcall:
+    pushq %rbx          # rbx and r12 to r15 are callee-saved
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    pushq %rdi          # the pointer to the registers
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    movq 48(%rdi), %r8
+    movq 56(%rdi), %r9
+    movq 64(%rdi), %r10
+    movq 72(%rdi), %r11
+    movq 80(%rdi), %r12
+    movq 88(%rdi), %r13
+    movq 96(%rdi), %r14
+    movq 104(%rdi), %r15
+    movq (%rdi), %rax
+    movq 40(%rdi), %rdi
+    .byte 0x66, 0x0f, 0x01, 0xcc    # tdcall
+    xchgq %rax, (%rsp)  # none of the following instructions modify the flags
+    movq %rbx, 8(%rax)
+    movq %rcx, 16(%rax)
+    movq %rdx, 24(%rax)
+    movq %rsi, 32(%rax)
+    movq %rdi, 40(%rax)
+    movq %r8, 48(%rax)
+    movq %r9, 56(%rax)
+    movq %r10, 64(%rax)
+    movq %r11, 72(%rax)
+    movq %r12, 80(%rax)
+    movq %r13, 88(%rax)
+    movq %r14, 96(%rax)
+    movq %r15, 104(%rax)
+    popq %rcx
+    movq %rcx, (%rax)
+    setc %al
+    movzbl %al, %eax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    retq
+
+.global _x86_64_asm_seamcall
+.p2align 4
+_x86_64_asm_seamcall:
+    pushq %rbx          # rbx and r12 to r15 are callee-saved
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    pushq %rdi          # the pointer to the registers
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    movq 48(%rdi), %r8
+    movq 56(%rdi), %r9
+    movq 64(%rdi), %r10
+    movq 72(%rdi), %r11
+    movq 80(%rdi), %r12
+    movq 88(%rdi), %r13
+    movq 96(%rdi), %r14
+    movq 104(%rdi), %r15
+    movq (%rdi), %rax
+    movq 40(%rdi), %rdi
+    .byte 0x66, 0x0f, 0x01, 0xcf    # seamcall
+    xchgq %rax, (%rsp)  # none of the following instructions modify the flags
+    movq %rbx, 8(%rax)
+    movq %rcx, 16(%rax)
+    movq %rdx, 24(%rax)
+    movq %rsi, 32(%rax)
+    movq %rdi, 40(%rax)
+    movq %r8, 48(%rax)
+    movq %r9, 56(%rax)
+    movq %r10, 64(%rax)
+    movq %r11, 72(%rax)
+    movq %r12, 80(%rax)
+    movq %r13, 88(%rax)
+    movq %r14, 96(%rax)
+    movq %r15, 104(%rax)
+    popq %rcx
+    movq %rcx, (%rax)
+    setc %al
+    movzbl %al, %eax
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    retq
+
+.global _x86_64_asm_vmware_backdoor
+.p2align 4
+_x86_64_asm_vmware_backdoor:
+    pushq %rbx          # rbx is callee-saved
+    pushq %rdi          # the backdoor returns a value in edi
+    movl (%rdi), %eax
+    movl 4(%rdi), %ebx
+    movl 8(%rdi), %ecx
+    movl 12(%rdi), %edx
+    movl 16(%rdi), %esi
+    movl 20(%rdi), %edi
+    inl %dx, %eax
+    xchgq %rdi, (%rsp)
+    movl %eax, (%rdi)
+    movl %ebx, 4(%rdi)
+    movl %ecx, 8(%rdi)
+    movl %edx, 12(%rdi)
+    movl %esi, 16(%rdi)
+    popq %rax
+    movl %eax, 20(%rdi)
+    popq %rbx
+    retq
+
+.global _x86_64_asm_vmrun
+.p2align 4
+_x86_64_asm_vmrun:
+    pushq %rbp          # rbp, rbx and r12 to r15 are callee-saved
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    movq %rdi, %rax     # the VMCB
+    movq %rsi, %rdi     # the guest registers
+    pushq %rdi
+    movq 8(%rdi), %rbx
+    movq 16(%rdi), %rcx
+    movq 24(%rdi), %rdx
+    movq 32(%rdi), %rsi
+    movq 48(%rdi), %rbp
+    movq 56(%rdi), %r8
+    movq 64(%rdi), %r9
+    movq 72(%rdi), %r10
+    movq 80(%rdi), %r11
+    movq 88(%rdi), %r12
+    movq 96(%rdi), %r13
+    movq 104(%rdi), %r14
+    movq 112(%rdi), %r15
+    movq 40(%rdi), %rdi
+    vmrun %rax
+    pushq %rdi          # #VMEXIT restores rax and rsp from the host save area
+    movq 8(%rsp), %rdi
+    movq %rbx, 8(%rdi)
+    movq %rcx, 16(%rdi)
+    movq %rdx, 24(%rdi)
+    movq %rsi, 32(%rdi)
+    movq %rbp, 48(%rdi)
+    movq %r8, 56(%rdi)
+    movq %r9, 64(%rdi)
+    movq %r10, 72(%rdi)
+    movq %r11, 80(%rdi)
+    movq %r12, 88(%rdi)
+    movq %r13, 96(%rdi)
+    movq %r14, 104(%rdi)
+    movq %r15, 112(%rdi)
+    popq 40(%rdi)
+    addq $8, %rsp
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    popq %rbp
+    retq
+
+.global _x86_64_asm_vmload
+.p2align 4
+_x86_64_asm_vmload:
+    movq %rdi, %rax
+    vmload %rax
+    retq
+
+.global _x86_64_asm_vmsave
+.p2align 4
+_x86_64_asm_vmsave:
+    movq %rdi, %rax
+    vmsave %rax
+    retq
+
+.global _x86_64_asm_stgi
+.p2align 4
+_x86_64_asm_stgi:
+    stgi
+    retq
+
+.global _x86_64_asm_clgi
+.p2align 4
+_x86_64_asm_clgi:
+    clgi
+    retq
+
+.global _x86_64_asm_invlpga
+.p2align 4
+_x86_64_asm_invlpga:
+    movq %rdi, %rax
+    movl %esi, %ecx
+    invlpga %rax, %ecx
+    retq
+
+.global _x86_64_asm_vmgexit
+.p2align 4
+_x86_64_asm_vmgexit:
+    .byte 0xf3, 0x0f, 0x01, 0xd9    # vmgexit
+    retq
+
+.global _x86_64_asm_pvalidate
+.p2align 4
+_x86_64_asm_pvalidate:
+    movq %rdi, %rax
+    movq %rcx, %r8      # the pointer to the status
+    movl %esi, %ecx
+    .byte 0xf2, 0x0f, 0x01, 0xff    # pvalidate
+    setc %cl
+    movl %eax, (%r8)
+    movzbl %cl, %eax
+    retq
+
+.global _x86_64_asm_rmpadjust
+.p2align 4
+_x86_64_asm_rmpadjust:
+    movq %rdi, %rax
+    movq %rsi, %rcx
+    .byte 0xf3, 0x0f, 0x01, 0xfe    # rmpadjust
+    retq
@@ -0,0 +1,20 @@
+# The bounds of the `cpu_local` section, which only exist for ELF targets. They are
+# referenced weakly and loaded from the GOT, so that they are zero if there is no section.
+
+.text
+.code64
+
+.weak __start_cpu_local
+.weak __stop_cpu_local
+
+.global _x86_64_asm_cpu_local_start
+.p2align 4
+_x86_64_asm_cpu_local_start:
+    movq __start_cpu_local@GOTPCREL(%rip), %rax
+    retq
+
+.global _x86_64_asm_cpu_local_stop
+.p2align 4
+_x86_64_asm_cpu_local_stop:
+    movq __stop_cpu_local@GOTPCREL(%rip), %rax
+    retq
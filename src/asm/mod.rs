@@ -72,6 +72,12 @@ extern "C" {
     )]
     pub(crate) fn x86_64_asm_set_cs(sel: u64);
 
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_far_return"
+    )]
+    pub(crate) fn x86_64_asm_far_return(sel: u64, target: u64) -> !;
+
     #[cfg_attr(
         any(target_env = "gnu", target_env = "musl"),
         link_name = "_x86_64_asm_load_ss"
@@ -132,6 +138,12 @@ extern "C" {
     )]
     pub(crate) fn x86_64_asm_ltr(sel: u16);
 
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_str"
+    )]
+    pub(crate) fn x86_64_asm_str() -> u16;
+
     #[cfg_attr(
         any(target_env = "gnu", target_env = "musl"),
         link_name = "_x86_64_asm_invlpg"
@@ -203,4 +215,259 @@ extern "C" {
         link_name = "_x86_64_asm_write_rflags"
     )]
     pub(crate) fn x86_64_asm_write_rflags(val: u64);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_read_gs_0"
+    )]
+    pub(crate) fn x86_64_asm_read_gs_0() -> u64;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_cpu_local_start"
+    )]
+    pub(crate) fn x86_64_asm_cpu_local_start() -> usize;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_cpu_local_stop"
+    )]
+    pub(crate) fn x86_64_asm_cpu_local_stop() -> usize;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_bochs"
+    )]
+    pub(crate) fn x86_64_asm_bochs();
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_read_rip"
+    )]
+    pub(crate) fn x86_64_asm_read_rip() -> u64;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_verw"
+    )]
+    pub(crate) fn x86_64_asm_verw(sel: u16);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmxon"
+    )]
+    pub(crate) fn x86_64_asm_vmxon(addr: *const u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmxoff"
+    )]
+    pub(crate) fn x86_64_asm_vmxoff() -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmclear"
+    )]
+    pub(crate) fn x86_64_asm_vmclear(addr: *const u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmptrld"
+    )]
+    pub(crate) fn x86_64_asm_vmptrld(addr: *const u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmptrst"
+    )]
+    pub(crate) fn x86_64_asm_vmptrst(addr: *mut u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmread"
+    )]
+    pub(crate) fn x86_64_asm_vmread(field: u64, value: *mut u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmwrite"
+    )]
+    pub(crate) fn x86_64_asm_vmwrite(field: u64, value: u64) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmlaunch"
+    )]
+    pub(crate) fn x86_64_asm_vmlaunch() -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmresume"
+    )]
+    pub(crate) fn x86_64_asm_vmresume() -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmcall"
+    )]
+    pub(crate) fn x86_64_asm_vmcall(regs: *mut crate::instructions::vmx::HypercallRegisters);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmmcall"
+    )]
+    pub(crate) fn x86_64_asm_vmmcall(regs: *mut crate::instructions::vmx::HypercallRegisters);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmfunc"
+    )]
+    pub(crate) fn x86_64_asm_vmfunc(leaf: u32, arg: u64);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_get_ss"
+    )]
+    pub(crate) fn x86_64_asm_get_ss() -> u16;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_get_ds"
+    )]
+    pub(crate) fn x86_64_asm_get_ds() -> u16;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_get_es"
+    )]
+    pub(crate) fn x86_64_asm_get_es() -> u16;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_get_fs"
+    )]
+    pub(crate) fn x86_64_asm_get_fs() -> u16;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_get_gs"
+    )]
+    pub(crate) fn x86_64_asm_get_gs() -> u16;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_sgdt"
+    )]
+    pub(crate) fn x86_64_asm_sgdt(gdt: *mut crate::instructions::tables::DescriptorTablePointer);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_sidt"
+    )]
+    pub(crate) fn x86_64_asm_sidt(idt: *mut crate::instructions::tables::DescriptorTablePointer);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmentry"
+    )]
+    pub(crate) fn x86_64_asm_vmentry(
+        regs: *mut crate::instructions::vmx::GuestRegisters,
+        launched: bool,
+    ) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_invept"
+    )]
+    pub(crate) fn x86_64_asm_invept(kind: u64, descriptor: *const [u64; 2]) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_invvpid"
+    )]
+    pub(crate) fn x86_64_asm_invvpid(kind: u64, descriptor: *const [u64; 2]) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_hv_hypercall"
+    )]
+    pub(crate) fn x86_64_asm_hv_hypercall(
+        page: u64,
+        input: u64,
+        input_param: u64,
+        output_param: u64,
+    ) -> u64;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_tdcall"
+    )]
+    pub(crate) fn x86_64_asm_tdcall(regs: *mut crate::instructions::tdx::TdxRegisters) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_seamcall"
+    )]
+    pub(crate) fn x86_64_asm_seamcall(regs: *mut crate::instructions::tdx::TdxRegisters) -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmware_backdoor"
+    )]
+    pub(crate) fn x86_64_asm_vmware_backdoor(regs: *mut crate::vmware::BackdoorRegisters);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmrun"
+    )]
+    pub(crate) fn x86_64_asm_vmrun(vmcb: u64, regs: *mut crate::instructions::vmx::GuestRegisters);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmload"
+    )]
+    pub(crate) fn x86_64_asm_vmload(vmcb: u64);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmsave"
+    )]
+    pub(crate) fn x86_64_asm_vmsave(vmcb: u64);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_stgi"
+    )]
+    pub(crate) fn x86_64_asm_stgi();
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_clgi"
+    )]
+    pub(crate) fn x86_64_asm_clgi();
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_invlpga"
+    )]
+    pub(crate) fn x86_64_asm_invlpga(addr: u64, asid: u32);
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_vmgexit"
+    )]
+    pub(crate) fn x86_64_asm_vmgexit();
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_pvalidate"
+    )]
+    pub(crate) fn x86_64_asm_pvalidate(addr: u64, size: u32, validate: u32, status: *mut u32)
+        -> u8;
+
+    #[cfg_attr(
+        any(target_env = "gnu", target_env = "musl"),
+        link_name = "_x86_64_asm_rmpadjust"
+    )]
+    pub(crate) fn x86_64_asm_rmpadjust(addr: u64, size: u64, attributes: u64) -> u32;
 }
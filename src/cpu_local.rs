@@ -0,0 +1,267 @@
+//! Per-CPU data accessed through the GS segment base.
+//!
+//! Per-CPU statics are declared with the [`cpu_local!`](crate::cpu_local!) macro, which places
+//! them in the `cpu_local` link section. This section is only used as a template: on every
+//! processor, [`init`] allocates a per-CPU block, copies the template into it, and points the
+//! GS base to the block. The statics are then accessed relative to the block of the current
+//! processor, so every processor sees its own copy.
+//!
+//! The first 8 bytes of the per-CPU block contain the address of the block itself, so that
+//! the block can be located by reading `gs:0`.
+//!
+//! The bounds of the template are the `__start_cpu_local` and `__stop_cpu_local` symbols that
+//! the linker defines for the section, so this module is only available for ELF targets. The
+//! symbols are referenced weakly, which yields an empty template if no per-CPU static is
+//! declared.
+//!
+//! ```ignore
+//! use core::cell::Cell;
+//! use x86_64::cpu_local;
+//!
+//! cpu_local! {
+//!     static TICKS: Cell<u64> = Cell::new(0);
+//! }
+//!
+//! fn on_timer_interrupt() {
+//!     // `cpu_local::init` was called on every processor during boot
+//!     unsafe { TICKS.with(|ticks| ticks.set(ticks.get() + 1)) };
+//! }
+//! ```
+
+use crate::instructions::interrupts;
+use crate::registers::model_specific::{GsBase, KernelGsBase};
+use crate::VirtAddr;
+use core::alloc::Layout;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::ptr;
+
+/// The size of the header of a per-CPU block, which precedes the copy of the template.
+///
+/// This is also the alignment of the per-CPU block, so per-CPU statics must not require an
+/// alignment larger than this.
+pub const HEADER_SIZE: usize = 64;
+
+/// Returns the address range of the `cpu_local` template section.
+#[inline]
+fn template() -> (usize, usize) {
+    let (start, end): (usize, usize);
+
+    // the symbols are only defined if the section exists, so they are loaded from the GOT,
+    // where undefined weak symbols resolve to zero
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        asm!(
+            ".weak __start_cpu_local",
+            ".weak __stop_cpu_local",
+            "mov {}, qword ptr [rip + __start_cpu_local@GOTPCREL]",
+            "mov {}, qword ptr [rip + __stop_cpu_local@GOTPCREL]",
+            out(reg) start,
+            out(reg) end,
+            options(pure, readonly, nostack, preserves_flags)
+        );
+    }
+
+    #[cfg(feature = "external_asm")]
+    unsafe {
+        start = crate::asm::x86_64_asm_cpu_local_start();
+        end = crate::asm::x86_64_asm_cpu_local_stop();
+    }
+
+    (start, end)
+}
+
+/// Declares one or more per-CPU statics.
+///
+/// The statics have the type [`CpuLocal<T>`](crate::cpu_local::CpuLocal) and are accessed
+/// through [`CpuLocal::with`](crate::cpu_local::CpuLocal::with). The initial value is copied
+/// bytewise to the per-CPU block of every processor by
+/// [`cpu_local::init`](crate::cpu_local::init).
+///
+/// The type of a per-CPU static must not require an alignment larger than
+/// [`HEADER_SIZE`](crate::cpu_local::HEADER_SIZE), which is checked at compile time.
+#[macro_export]
+macro_rules! cpu_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = "cpu_local"]
+            $vis static $name: $crate::cpu_local::CpuLocal<$ty> = {
+                const _: () = assert!(
+                    core::mem::align_of::<$ty>() <= $crate::cpu_local::HEADER_SIZE,
+                    "the alignment of a per-CPU static must not exceed cpu_local::HEADER_SIZE"
+                );
+                let value: $ty = $init;
+                unsafe { $crate::cpu_local::CpuLocal::new(value) }
+            };
+        )*
+    };
+}
+
+/// A per-CPU static, declared through the [`cpu_local!`](crate::cpu_local!) macro.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct CpuLocal<T> {
+    template: UnsafeCell<T>,
+}
+
+// The template is never accessed directly, every processor only accesses its own copy with
+// interrupts disabled.
+unsafe impl<T> Sync for CpuLocal<T> {}
+
+impl<T> CpuLocal<T> {
+    /// Creates a new per-CPU static with the given initial value.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the result must be stored in a static in the
+    /// `cpu_local` link section. Use the [`cpu_local!`](crate::cpu_local!) macro instead.
+    #[doc(hidden)]
+    #[inline]
+    pub const unsafe fn new(value: T) -> CpuLocal<T> {
+        CpuLocal {
+            template: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a pointer to the copy of this static for the current processor.
+    ///
+    /// The pointer is only valid as long as the current code is not moved to another
+    /// processor, e.g. while interrupts are disabled.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because [`init`] must have been called on the current
+    /// processor before.
+    #[inline]
+    pub unsafe fn as_ptr(&self) -> *mut T {
+        (current_block().as_u64() as usize + HEADER_SIZE + self.offset()) as *mut T
+    }
+
+    /// Returns the offset of this static in the template, which is also its offset after the
+    /// header of every per-CPU block.
+    #[inline]
+    fn offset(&self) -> usize {
+        let (start, _) = template();
+        self.template.get() as usize - start
+    }
+
+    /// Calls the given closure with a reference to the copy of this static for the current
+    /// processor.
+    ///
+    /// Interrupts are disabled while the closure runs, so that it is neither interrupted by
+    /// code accessing the same static nor moved to another processor. Note that non-maskable
+    /// interrupts are still delivered.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because [`init`] must have been called on the current
+    /// processor before. Otherwise, the GS base does not point to a per-CPU block.
+    #[inline]
+    pub unsafe fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        interrupts::without_interrupts(|| f(&*self.as_ptr()))
+    }
+}
+
+/// Returns the address of the per-CPU block of the current processor, read from `gs:0`.
+///
+/// ## Safety
+///
+/// This function is unsafe because [`init`] must have been called on the current processor
+/// before. Otherwise, the GS base does not point to a per-CPU block.
+#[inline]
+pub unsafe fn current_block() -> VirtAddr {
+    let block: u64;
+
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov {}, gs:[0]", out(reg) block, options(readonly, nostack, preserves_flags));
+
+    #[cfg(feature = "external_asm")]
+    {
+        block = crate::asm::x86_64_asm_read_gs_0();
+    }
+
+    VirtAddr::new(block)
+}
+
+/// Returns the layout of a per-CPU block.
+#[inline]
+pub fn block_layout() -> Layout {
+    let (start, end) = template();
+    Layout::from_size_align(HEADER_SIZE + (end - start), HEADER_SIZE)
+        .expect("invalid cpu_local section size")
+}
+
+/// Initializes the per-CPU data of the current processor.
+///
+/// Calls `alloc` to allocate a per-CPU block with the given layout, copies the initial
+/// values of all per-CPU statics into it, and writes the block address to the GS base.
+/// The kernel GS base is set to zero, so that a `swapgs` on the transition to user mode
+/// loads a null GS base for user space. Returns the address of the per-CPU block.
+///
+/// Panics if `alloc` returns a null pointer.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that it is called exactly once
+/// per processor, before any per-CPU static is accessed on it, and that the allocated block
+/// is never freed or used otherwise.
+pub unsafe fn init<F>(alloc: F) -> VirtAddr
+where
+    F: FnOnce(Layout) -> *mut u8,
+{
+    let (start, end) = template();
+    let block = alloc(block_layout());
+    assert!(!block.is_null(), "failed to allocate the per-CPU block");
+    assert_eq!(
+        block as usize % HEADER_SIZE,
+        0,
+        "per-CPU block is not aligned"
+    );
+
+    ptr::write_bytes(block, 0, HEADER_SIZE);
+    ptr::copy_nonoverlapping(start as *const u8, block.add(HEADER_SIZE), end - start);
+    ptr::write(block as *mut u64, block as u64);
+
+    let block = VirtAddr::from_ptr(block);
+    GsBase::write(block);
+    KernelGsBase::write(VirtAddr::zero());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    cpu_local! {
+        static COUNTER: Cell<u64> = Cell::new(0);
+        static FLAGS: [u8; 3] = [1, 2, 3];
+    }
+
+    #[test]
+    fn statics_are_in_template() {
+        let (start, end) = template();
+        for (address, size) in [
+            (COUNTER.template.get() as usize, 8),
+            (FLAGS.template.get() as usize, 3),
+        ] {
+            assert!(start <= address && address + size <= end);
+        }
+        assert_ne!(COUNTER.offset(), FLAGS.offset());
+        assert_eq!(COUNTER.offset() % 8, 0);
+    }
+
+    #[test]
+    fn layout() {
+        let (start, end) = template();
+        let layout = block_layout();
+        assert_eq!(layout.align(), HEADER_SIZE);
+        assert_eq!(layout.size(), HEADER_SIZE + (end - start));
+        assert!(COUNTER.offset() + 8 <= layout.size() - HEADER_SIZE);
+    }
+}
@@ -0,0 +1,186 @@
+//! Enumeration of the processor caches and their parameters.
+
+use super::{cpuid, has_leaf, CpuidResult};
+
+/// The type of a cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    /// A data cache.
+    Data,
+    /// An instruction cache.
+    Instruction,
+    /// A cache for both data and instructions.
+    Unified,
+    /// A cache type reported by the processor that is not known to this crate.
+    Unknown(u8),
+}
+
+impl CacheType {
+    /// Decodes the cache type field of CPUID leaves 4 and 0x8000_001D. Returns `None` for 0,
+    /// which marks the end of the cache list.
+    #[inline]
+    fn from_raw(raw: u8) -> Option<CacheType> {
+        match raw {
+            0 => None,
+            1 => Some(CacheType::Data),
+            2 => Some(CacheType::Instruction),
+            3 => Some(CacheType::Unified),
+            other => Some(CacheType::Unknown(other)),
+        }
+    }
+}
+
+/// The parameters of a single cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheInfo {
+    /// The cache level, starting at 1.
+    pub level: u8,
+    /// The type of the cache.
+    pub cache_type: CacheType,
+    /// The size of a cache line in bytes.
+    pub line_size: u32,
+    /// The number of physical line partitions.
+    pub partitions: u32,
+    /// The number of ways of associativity.
+    pub ways: u32,
+    /// The number of sets.
+    pub sets: u32,
+    /// The maximum number of logical processors that share this cache.
+    pub max_sharing: u32,
+    /// Whether the cache is fully associative.
+    pub fully_associative: bool,
+    /// Whether the cache is inclusive of the lower cache levels.
+    pub inclusive: bool,
+}
+
+impl CacheInfo {
+    /// Returns the size of the cache in bytes.
+    #[inline]
+    pub fn size(&self) -> u64 {
+        u64::from(self.line_size)
+            * u64::from(self.partitions)
+            * u64::from(self.ways)
+            * u64::from(self.sets)
+    }
+
+    #[inline]
+    fn from_cpuid(result: CpuidResult) -> Option<CacheInfo> {
+        let cache_type = CacheType::from_raw((result.eax & 0x1f) as u8)?;
+        Some(CacheInfo {
+            level: ((result.eax >> 5) & 0x7) as u8,
+            cache_type,
+            line_size: (result.ebx & 0xfff) + 1,
+            partitions: ((result.ebx >> 12) & 0x3ff) + 1,
+            ways: (result.ebx >> 22) + 1,
+            sets: result.ecx.wrapping_add(1),
+            max_sharing: ((result.eax >> 14) & 0xfff) + 1,
+            fully_associative: result.eax & (1 << 9) != 0,
+            inclusive: result.edx & (1 << 1) != 0,
+        })
+    }
+}
+
+/// An iterator over the caches of the current processor, created by [`caches`] or
+/// [`Caches::read_with`].
+#[derive(Debug, Clone)]
+pub struct Caches<F> {
+    leaf: Option<u32>,
+    sub_leaf: u32,
+    query: F,
+}
+
+impl<F> Caches<F>
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    /// Enumerates the caches using the given function instead of the `cpuid` instruction.
+    ///
+    /// Uses the deterministic cache parameters leaf 4, or the AMD cache topology leaf
+    /// 0x8000_001D if leaf 4 does not report any caches. If neither leaf is supported, the
+    /// iterator is empty.
+    pub fn read_with(mut query: F) -> Caches<F> {
+        let leaf = if has_leaf(4, &mut query) && query(4, 0).eax & 0x1f != 0 {
+            Some(4)
+        } else if has_leaf(0x8000_001D, &mut query) && query(0x8000_0001, 0).ecx & (1 << 22) != 0 {
+            // the cache topology leaf is part of the topology extensions (ecx bit 22)
+            Some(0x8000_001D)
+        } else {
+            None
+        };
+        Caches {
+            leaf,
+            sub_leaf: 0,
+            query,
+        }
+    }
+}
+
+impl<F> Iterator for Caches<F>
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    type Item = CacheInfo;
+
+    fn next(&mut self) -> Option<CacheInfo> {
+        let leaf = self.leaf?;
+        let info = CacheInfo::from_cpuid((self.query)(leaf, self.sub_leaf));
+        if info.is_some() {
+            self.sub_leaf += 1;
+        } else {
+            self.leaf = None;
+        }
+        info
+    }
+}
+
+/// Returns an iterator over the caches of the current processor.
+#[inline]
+pub fn caches() -> Caches<fn(u32, u32) -> CpuidResult> {
+    Caches::read_with(cpuid)
+}
+
+/// Returns the cache line size in bytes.
+///
+/// This is the line size used by `clflush`, as reported by CPUID leaf 1, or the line size of
+/// the first level 1 data cache if leaf 1 doesn't report it. It is the alignment to use for
+/// avoiding false sharing and the stride for flushing address ranges from the caches.
+pub fn cache_line_size() -> Option<u32> {
+    // leaf 1, ebx bits 15:8 contain the clflush line size in units of 8 bytes
+    let clflush = (cpuid(1, 0).ebx >> 8) & 0xff;
+    if clflush != 0 {
+        return Some(clflush * 8);
+    }
+    caches()
+        .find(|c| c.level == 1 && c.cache_type != CacheType::Instruction)
+        .map(|c| c.line_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn deterministic_cache_parameters() {
+        // a 32KiB 8-way L1 data cache and a 1MiB 16-way L2 cache, shared by two threads
+        let leaves = [
+            (0, [0x16, 0, 0, 0]),
+            (4, [0x4121, 0x01c0_003f, 63, 0]),
+            (4, [0x4143, 0x03c0_003f, 1023, 0x2]),
+        ];
+        let mut caches = Caches::read_with(query(&leaves));
+
+        let l1 = caches.next().unwrap();
+        assert_eq!((l1.level, l1.cache_type), (1, CacheType::Data));
+        assert_eq!((l1.line_size, l1.ways, l1.sets), (64, 8, 64));
+        assert_eq!(l1.size(), 32 * 1024);
+        assert_eq!(l1.max_sharing, 2);
+
+        let l2 = caches.next().unwrap();
+        assert_eq!((l2.level, l2.cache_type), (2, CacheType::Unified));
+        assert_eq!(l2.size(), 1024 * 1024);
+        assert!(l2.inclusive);
+
+        assert_eq!(caches.next(), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Detection of the hypervisor that the processor runs under.
+//!
+//! Hypervisors set bit 31 of ecx in CPUID leaf 1 and report their vendor signature and the
+//! maximum hypervisor leaf in leaf 0x4000_0000. The paravirtual interface of a hypervisor is
+//! enumerated by the leaves up to the maximum leaf and depends on the vendor.
+
+use super::{cpuid, CpuidResult};
+
+/// The first leaf of the hypervisor range.
+const HYPERVISOR_BASE_LEAF: u32 = 0x4000_0000;
+
+/// The vendor of a hypervisor, decoded from its signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HypervisorVendor {
+    /// KVM, with the signature `KVMKVMKVM`.
+    Kvm,
+    /// Microsoft Hyper-V, or a hypervisor that provides its interface, with the signature
+    /// `Microsoft Hv`.
+    HyperV,
+    /// VMware, with the signature `VMwareVMware`.
+    VMware,
+    /// Xen, with the signature `XenVMMXenVMM`.
+    Xen,
+    /// The TCG emulator of QEMU, with the signature `TCGTCGTCGTCG`.
+    QemuTcg,
+    /// A hypervisor that is not known to this crate.
+    Unknown,
+}
+
+impl HypervisorVendor {
+    /// Decodes the vendor from the 12-byte signature in ebx, ecx and edx of leaf 0x4000_0000.
+    #[inline]
+    pub fn from_signature(signature: &[u8; 12]) -> HypervisorVendor {
+        match signature {
+            b"KVMKVMKVM\0\0\0" => HypervisorVendor::Kvm,
+            b"Microsoft Hv" => HypervisorVendor::HyperV,
+            b"VMwareVMware" => HypervisorVendor::VMware,
+            b"XenVMMXenVMM" => HypervisorVendor::Xen,
+            b"TCGTCGTCGTCG" => HypervisorVendor::QemuTcg,
+            _ => HypervisorVendor::Unknown,
+        }
+    }
+}
+
+/// The hypervisor information reported by CPUID leaf 0x4000_0000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HypervisorInfo {
+    /// The vendor of the hypervisor.
+    pub vendor: HypervisorVendor,
+    /// The raw vendor signature.
+    pub signature: [u8; 12],
+    /// The maximum hypervisor leaf, starting at 0x4000_0000.
+    pub max_leaf: u32,
+}
+
+impl HypervisorInfo {
+    /// Reads the hypervisor information of the current processor.
+    ///
+    /// Returns `None` if the processor doesn't run under a hypervisor, or doesn't report it.
+    #[inline]
+    pub fn read() -> Option<HypervisorInfo> {
+        Self::read_with(cpuid)
+    }
+
+    /// Reads the hypervisor information using the given function instead of the `cpuid`
+    /// instruction.
+    pub fn read_with<F>(mut query: F) -> Option<HypervisorInfo>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        if !is_present_with(&mut query) {
+            return None;
+        }
+        let result = query(HYPERVISOR_BASE_LEAF, 0);
+        let mut signature = [0; 12];
+        signature[..4].copy_from_slice(&result.ebx.to_le_bytes());
+        signature[4..8].copy_from_slice(&result.ecx.to_le_bytes());
+        signature[8..].copy_from_slice(&result.edx.to_le_bytes());
+        let vendor = HypervisorVendor::from_signature(&signature);
+        let max_leaf = match vendor {
+            // old KVM versions report 0 instead of the features leaf 0x4000_0001
+            HypervisorVendor::Kvm if result.eax == 0 => HYPERVISOR_BASE_LEAF + 1,
+            _ => result.eax,
+        };
+        Some(HypervisorInfo {
+            vendor,
+            signature,
+            max_leaf,
+        })
+    }
+
+    /// Returns whether the given leaf of the hypervisor range is reported as supported.
+    #[inline]
+    pub fn has_leaf(&self, leaf: u32) -> bool {
+        (HYPERVISOR_BASE_LEAF..=self.max_leaf).contains(&leaf)
+    }
+}
+
+/// Returns whether the processor runs under a hypervisor, according to CPUID leaf 1, ecx
+/// bit 31.
+#[inline]
+pub fn is_present() -> bool {
+    is_present_with(&mut cpuid)
+}
+
+fn is_present_with<F>(query: &mut F) -> bool
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    query(1, 0).ecx & (1 << 31) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    // "KVMKVMKVM\0\0\0"
+    const KVM_SIGNATURE: (u32, [u32; 4]) = (0x4000_0000, [0, 0x4b4d_564b, 0x564b_4d56, 0x4d]);
+
+    #[test]
+    fn hypervisor_info() {
+        let leaves = [(1, [0, 0, 1 << 31, 0]), KVM_SIGNATURE];
+        let info = HypervisorInfo::read_with(query(&leaves)).unwrap();
+        assert_eq!(info.vendor, HypervisorVendor::Kvm);
+        assert_eq!(info.max_leaf, 0x4000_0001);
+        assert!(info.has_leaf(0x4000_0001) && !info.has_leaf(0x4000_0002));
+
+        let bare_metal = query(&[KVM_SIGNATURE]);
+        assert_eq!(HypervisorInfo::read_with(bare_metal), None);
+    }
+}
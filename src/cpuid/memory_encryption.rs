@@ -0,0 +1,127 @@
+//! Enumeration of the AMD memory encryption features SME and SEV.
+//!
+//! Memory encryption is reported by CPUID leaf 0x8000_001F, which also holds the position of
+//! the C-bit, the physical address bit that marks a page as encrypted.
+
+use super::{cpuid, has_leaf, CpuidResult};
+use bitflags::bitflags;
+
+bitflags! {
+    /// The memory encryption features in eax of CPUID leaf 0x8000_001F.
+    pub struct MemoryEncryptionFeatures: u32 {
+        /// Secure memory encryption, which encrypts pages with the C-bit set.
+        const SME =                      1;
+        /// Secure encrypted virtualization, which encrypts the memory of guests.
+        const SEV =                      1 << 1;
+        /// The page flush MSR, which flushes the cache lines of an encrypted page.
+        const PAGE_FLUSH_MSR =           1 << 2;
+        /// SEV-ES, which also encrypts the register state of guests.
+        const SEV_ES =                   1 << 3;
+        /// SEV-SNP, which adds integrity protection through the reverse map table.
+        const SEV_SNP =                  1 << 4;
+        /// Virtual machine privilege levels of SEV-SNP guests.
+        const VMPL =                     1 << 5;
+        /// The `rmpquery` instruction.
+        const RMPQUERY =                 1 << 6;
+        /// Supervisor shadow stacks per VMPL.
+        const VMPL_SSS =                 1 << 7;
+        /// A TSC of SEV-SNP guests that the hypervisor can't change.
+        const SECURE_TSC =               1 << 8;
+        /// Virtualization of TSC_AUX for SEV guests.
+        const TSC_AUX_VIRTUALIZATION =   1 << 9;
+        /// Cache coherency across encryption domains is enforced by hardware.
+        const HW_CACHE_COHERENCY =       1 << 10;
+        /// SEV guests can only run on a host in 64-bit mode.
+        const HOST_64_BIT =              1 << 11;
+        /// Restricted injection of events into SEV-ES guests.
+        const RESTRICTED_INJECTION =     1 << 12;
+        /// Alternate injection of events into SEV-ES guests.
+        const ALTERNATE_INJECTION =      1 << 13;
+        /// Full swapping of the debug registers of SEV-ES guests.
+        const DEBUG_SWAP =               1 << 14;
+        /// Prevents the host from using IBS for SEV-ES guests.
+        const PREVENT_HOST_IBS =         1 << 15;
+        /// Virtual transparent encryption.
+        const VTE =                      1 << 16;
+    }
+}
+
+impl_flags_display!(MemoryEncryptionFeatures);
+
+/// The memory encryption parameters reported by CPUID leaf 0x8000_001F.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEncryptionInfo {
+    /// The supported memory encryption features.
+    pub features: MemoryEncryptionFeatures,
+    /// The position of the C-bit in physical addresses and page table entries.
+    pub c_bit_position: u8,
+    /// The number of physical address bits that are lost when memory encryption is enabled.
+    pub physical_address_reduction: u8,
+    /// The number of VMPLs of SEV-SNP guests.
+    pub vmpl_count: u8,
+    /// The number of encrypted guests that can run at the same time.
+    pub encrypted_guests: u32,
+    /// The lowest ASID of guests that may use SEV without SEV-ES, lower ASIDs are reserved
+    /// for SEV-ES guests.
+    pub min_sev_asid: u32,
+}
+
+impl MemoryEncryptionInfo {
+    /// Reads the memory encryption parameters of the current processor.
+    ///
+    /// Returns `None` if the processor supports neither SME nor SEV.
+    #[inline]
+    pub fn read() -> Option<MemoryEncryptionInfo> {
+        Self::read_with(cpuid)
+    }
+
+    /// Reads the memory encryption parameters using the given function instead of the
+    /// `cpuid` instruction.
+    pub fn read_with<F>(mut query: F) -> Option<MemoryEncryptionInfo>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        if !has_leaf(0x8000_001f, &mut query) {
+            return None;
+        }
+        let result = query(0x8000_001f, 0);
+        let features = MemoryEncryptionFeatures::from_bits_truncate(result.eax);
+        if !features.intersects(MemoryEncryptionFeatures::SME | MemoryEncryptionFeatures::SEV) {
+            return None;
+        }
+        Some(MemoryEncryptionInfo {
+            features,
+            c_bit_position: (result.ebx & 0x3f) as u8,
+            physical_address_reduction: ((result.ebx >> 6) & 0x3f) as u8,
+            vmpl_count: ((result.ebx >> 12) & 0xf) as u8,
+            encrypted_guests: result.ecx,
+            min_sev_asid: result.edx,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn memory_encryption_info() {
+        // SME, SEV and SEV-ES with the C-bit at 51, 1 bit of reduction and 4 VMPLs
+        let info = MemoryEncryptionInfo::read_with(query(&[
+            (0x8000_0000, [0x8000_0020, 0, 0, 0]),
+            (0x8000_001f, [0b1011, 4 << 12 | 1 << 6 | 51, 509, 100]),
+        ]))
+        .unwrap();
+        assert!(info.features.contains(MemoryEncryptionFeatures::SEV_ES));
+        assert_eq!(
+            (info.c_bit_position, info.physical_address_reduction),
+            (51, 1)
+        );
+        assert_eq!(info.vmpl_count, 4);
+        assert_eq!((info.encrypted_guests, info.min_sev_asid), (509, 100));
+
+        let none = MemoryEncryptionInfo::read_with(query(&[(0x8000_0000, [0x8000_0008, 0, 0, 0])]));
+        assert_eq!(none, None);
+    }
+}
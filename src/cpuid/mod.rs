@@ -0,0 +1,47 @@
+//! Decoding of processor information reported by the `cpuid` instruction.
+
+pub use core::arch::x86_64::CpuidResult;
+
+pub mod cache;
+pub mod hypervisor;
+pub mod memory_encryption;
+pub mod perfmon;
+pub mod topology;
+
+/// Executes `cpuid` for the given leaf and sub-leaf.
+#[inline]
+#[allow(unused_unsafe)]
+pub(crate) fn cpuid(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    unsafe { core::arch::x86_64::__cpuid_count(leaf, sub_leaf) }
+}
+
+/// Returns whether the given leaf is reported as supported by the maximum leaf of its range
+/// (basic, hypervisor or extended), using `query` instead of the `cpuid` instruction.
+#[inline]
+pub(crate) fn has_leaf<F>(leaf: u32, query: &mut F) -> bool
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    // the maximum leaf of a range is reported by its first leaf, e.g. 0x8000_0000
+    let range_start = leaf & 0xf000_0000;
+    query(range_start, 0).eax >= leaf
+}
+
+/// Returns a `cpuid` replacement for tests that reports the given `[eax, ebx, ecx, edx]` values
+/// for each leaf, and zeroes for all other leaves.
+///
+/// A leaf that is given once reports its values for every sub-leaf. A leaf that is given
+/// several times reports its values for sub-leaf 0, 1 and so on in the given order, and zeroes
+/// for the following sub-leaves.
+#[cfg(test)]
+pub(crate) fn fake(leaves: &[(u32, [u32; 4])]) -> impl FnMut(u32, u32) -> CpuidResult + '_ {
+    move |leaf, sub_leaf| {
+        let matching = || leaves.iter().filter(move |(l, _)| *l == leaf);
+        let index = match matching().count() {
+            1 => 0,
+            _ => sub_leaf as usize,
+        };
+        let [eax, ebx, ecx, edx] = matching().nth(index).map_or([0; 4], |(_, values)| *values);
+        CpuidResult { eax, ebx, ecx, edx }
+    }
+}
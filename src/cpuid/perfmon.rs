@@ -0,0 +1,128 @@
+//! Enumeration of the architectural performance monitoring unit (PMU).
+
+use super::{cpuid, has_leaf, CpuidResult};
+
+/// The architectural events that can be counted by the general-purpose counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchEvent {
+    /// Core cycles while the processor is not halted.
+    UnhaltedCoreCycles,
+    /// Retired instructions.
+    InstructionsRetired,
+    /// Reference cycles at a constant frequency while the processor is not halted.
+    UnhaltedReferenceCycles,
+    /// References to the last level cache.
+    LastLevelCacheReferences,
+    /// Misses of the last level cache.
+    LastLevelCacheMisses,
+    /// Retired branch instructions.
+    BranchInstructionsRetired,
+    /// Mispredicted retired branch instructions.
+    BranchMissesRetired,
+}
+
+impl ArchEvent {
+    /// Returns the event number and the unit mask to program into the event select register.
+    #[inline]
+    pub const fn event_select(self) -> (u8, u8) {
+        match self {
+            ArchEvent::UnhaltedCoreCycles => (0x3c, 0x00),
+            ArchEvent::InstructionsRetired => (0xc0, 0x00),
+            ArchEvent::UnhaltedReferenceCycles => (0x3c, 0x01),
+            ArchEvent::LastLevelCacheReferences => (0x2e, 0x4f),
+            ArchEvent::LastLevelCacheMisses => (0x2e, 0x41),
+            ArchEvent::BranchInstructionsRetired => (0xc4, 0x00),
+            ArchEvent::BranchMissesRetired => (0xc5, 0x00),
+        }
+    }
+
+    /// Returns the bit of the event in the availability fields of CPUID leaf 0xA.
+    #[inline]
+    const fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+/// The parameters of the architectural PMU, reported by CPUID leaf 0xA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerfmonInfo {
+    /// The version of the architectural PMU. Version 2 adds the global control and status
+    /// registers.
+    pub version: u8,
+    /// The number of general-purpose counters per logical processor.
+    pub counters: u8,
+    /// The width of the general-purpose counters in bits.
+    pub counter_width: u8,
+    events_length: u8,
+    unavailable_events: u32,
+}
+
+impl PerfmonInfo {
+    /// Reads the PMU parameters of the current processor.
+    ///
+    /// Returns `None` if the processor has no architectural PMU. This includes AMD processors,
+    /// whose performance counters use different registers.
+    #[inline]
+    pub fn read() -> Option<PerfmonInfo> {
+        Self::read_with(cpuid)
+    }
+
+    /// Reads the PMU parameters using the given function instead of the `cpuid` instruction.
+    pub fn read_with<F>(mut query: F) -> Option<PerfmonInfo>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        if !has_leaf(0xA, &mut query) {
+            return None;
+        }
+        let result = query(0xA, 0);
+        let info = PerfmonInfo {
+            version: result.eax as u8,
+            counters: (result.eax >> 8) as u8,
+            counter_width: (result.eax >> 16) as u8,
+            events_length: (result.eax >> 24) as u8,
+            unavailable_events: result.ebx,
+        };
+        match info.version {
+            0 => None,
+            _ => Some(info),
+        }
+    }
+
+    /// Returns whether the given architectural event can be counted.
+    #[inline]
+    pub fn is_event_available(&self, event: ArchEvent) -> bool {
+        event.index() < u32::from(self.events_length)
+            && self.unavailable_events & (1 << event.index()) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perfmon_info() {
+        // version 4, 4 counters of 48 bits, 7 events of which branch misses are unavailable
+        let info = PerfmonInfo::read_with(|leaf, _| {
+            let (eax, ebx) = match leaf {
+                0 => (0xd, 0),
+                0xA => (0x0730_0404, 1 << 6),
+                _ => (0, 0),
+            };
+            CpuidResult {
+                eax,
+                ebx,
+                ecx: 0,
+                edx: 0,
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            (info.version, info.counters, info.counter_width),
+            (4, 4, 48)
+        );
+        assert!(info.is_event_available(ArchEvent::UnhaltedCoreCycles));
+        assert!(!info.is_event_available(ArchEvent::BranchMissesRetired));
+    }
+}
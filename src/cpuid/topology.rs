@@ -0,0 +1,317 @@
+//! Enumeration of the processor topology.
+//!
+//! The topology is reported as a list of levels, starting with the SMT (hyperthreading)
+//! level. Each level uses a number of low bits of the x2APIC ID to identify a logical
+//! processor inside one domain of the next higher level, so the ID of a processor at each
+//! level can be extracted from its x2APIC ID. All bits above the highest level identify the
+//! package.
+
+use super::{cpuid, has_leaf, CpuidResult};
+
+/// The maximum number of topology levels stored in a [`CpuTopology`].
+pub const MAX_LEVELS: usize = 8;
+
+/// The type of a topology level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelType {
+    /// Logical processors that share a core (SMT, hyperthreading).
+    Smt,
+    /// Processor cores.
+    Core,
+    /// Modules, i.e. groups of cores that share some resources.
+    Module,
+    /// Tiles, i.e. groups of modules.
+    Tile,
+    /// Dies of a multi-die package.
+    Die,
+    /// A level type reported by the processor that is not known to this crate.
+    Unknown(u8),
+}
+
+impl LevelType {
+    /// Decodes the level type field of CPUID leaves 0xB and 0x1F. Returns `None` for 0, which
+    /// marks the end of the level list.
+    #[inline]
+    fn from_raw(raw: u8) -> Option<LevelType> {
+        match raw {
+            0 => None,
+            1 => Some(LevelType::Smt),
+            2 => Some(LevelType::Core),
+            3 => Some(LevelType::Module),
+            4 => Some(LevelType::Tile),
+            5 => Some(LevelType::Die),
+            other => Some(LevelType::Unknown(other)),
+        }
+    }
+}
+
+/// A single level of the processor topology.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopologyLevel {
+    /// The type of the level.
+    pub level_type: LevelType,
+    /// The number of low x2APIC ID bits that identify a logical processor inside one domain
+    /// of the next higher level. Shifting the x2APIC ID right by this amount yields the ID of
+    /// the next higher level.
+    pub shift: u8,
+    /// The number of logical processors in one domain of this level, as reported by the
+    /// processor. This is for information only, e.g. disabled cores are still counted.
+    pub logical_processors: u16,
+}
+
+/// The CPUID leaf from which a [`CpuTopology`] was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopologySource {
+    /// The V2 extended topology leaf 0x1F.
+    ExtendedTopologyV2,
+    /// The extended topology leaf 0xB.
+    ExtendedTopology,
+    /// The AMD extended APIC ID leaf 0x8000_001E, combined with leaf 0x8000_0008.
+    AmdExtendedApicId,
+}
+
+/// The topology of the current processor, read from CPUID.
+#[derive(Debug, Clone)]
+pub struct CpuTopology {
+    source: TopologySource,
+    x2apic_id: u32,
+    levels: [TopologyLevel; MAX_LEVELS],
+    level_count: usize,
+}
+
+impl CpuTopology {
+    /// Reads the topology of the current processor.
+    ///
+    /// Uses leaf 0x1F if available, falls back to leaf 0xB, and then to the AMD extended
+    /// APIC ID leaf. Returns `None` if none of these leaves is supported.
+    #[inline]
+    pub fn read() -> Option<CpuTopology> {
+        Self::read_with(cpuid)
+    }
+
+    /// Reads the topology using the given function instead of the `cpuid` instruction.
+    ///
+    /// The function is called with a leaf and sub-leaf and must return the corresponding
+    /// CPUID result. This is useful for decoding the topology of another processor, e.g. of
+    /// a virtual machine that is emulated by a hypervisor.
+    pub fn read_with<F>(mut query: F) -> Option<CpuTopology>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        if let Some(topology) = Self::read_extended(0x1F, &mut query) {
+            return Some(topology);
+        }
+        if let Some(topology) = Self::read_extended(0xB, &mut query) {
+            return Some(topology);
+        }
+        Self::read_amd(&mut query)
+    }
+
+    fn read_extended<F>(leaf: u32, query: &mut F) -> Option<CpuTopology>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        // a leaf without any valid level reports zero logical processors in sub-leaf 0
+        if !has_leaf(leaf, query) || query(leaf, 0).ebx & 0xffff == 0 {
+            return None;
+        }
+
+        let source = match leaf {
+            0x1F => TopologySource::ExtendedTopologyV2,
+            _ => TopologySource::ExtendedTopology,
+        };
+        let mut topology = CpuTopology::empty(source, 0);
+        for sub_leaf in 0..MAX_LEVELS as u32 {
+            let result = query(leaf, sub_leaf);
+            let level_type = match LevelType::from_raw((result.ecx >> 8) as u8) {
+                Some(level_type) => level_type,
+                None => break,
+            };
+            topology.x2apic_id = result.edx;
+            topology.push(TopologyLevel {
+                level_type,
+                shift: (result.eax & 0x1f) as u8,
+                logical_processors: result.ebx as u16,
+            });
+        }
+        Some(topology)
+    }
+
+    fn read_amd<F>(query: &mut F) -> Option<CpuTopology>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        // the topology extensions are indicated by CPUID 0x8000_0001, ecx bit 22
+        if !has_leaf(0x8000_001E, query)
+            || !has_leaf(0x8000_0008, query)
+            || query(0x8000_0001, 0).ecx & (1 << 22) == 0
+        {
+            return None;
+        }
+
+        let apic_id = query(0x8000_001E, 0);
+        let threads_per_core = ((apic_id.ebx >> 8) & 0xff) + 1;
+        let size = query(0x8000_0008, 0).ecx;
+        let threads_per_package = (size & 0xff) + 1;
+        let package_shift = match (size >> 12) & 0xf {
+            0 => bits_for(threads_per_package),
+            apic_id_size => apic_id_size as u8,
+        };
+
+        let mut topology = CpuTopology::empty(TopologySource::AmdExtendedApicId, apic_id.eax);
+        topology.push(TopologyLevel {
+            level_type: LevelType::Smt,
+            shift: bits_for(threads_per_core),
+            logical_processors: threads_per_core as u16,
+        });
+        topology.push(TopologyLevel {
+            level_type: LevelType::Core,
+            shift: package_shift,
+            logical_processors: threads_per_package as u16,
+        });
+        Some(topology)
+    }
+
+    #[inline]
+    fn empty(source: TopologySource, x2apic_id: u32) -> CpuTopology {
+        const UNUSED: TopologyLevel = TopologyLevel {
+            level_type: LevelType::Smt,
+            shift: 0,
+            logical_processors: 0,
+        };
+        CpuTopology {
+            source,
+            x2apic_id,
+            levels: [UNUSED; MAX_LEVELS],
+            level_count: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, level: TopologyLevel) {
+        self.levels[self.level_count] = level;
+        self.level_count += 1;
+    }
+
+    /// Returns the CPUID leaf from which the topology was read.
+    #[inline]
+    pub fn source(&self) -> TopologySource {
+        self.source
+    }
+
+    /// Returns the x2APIC ID of the processor.
+    ///
+    /// On processors without x2APIC support, this is the extended APIC ID reported by the
+    /// topology leaf, which is identical to the xAPIC ID for small systems.
+    #[inline]
+    pub fn x2apic_id(&self) -> u32 {
+        self.x2apic_id
+    }
+
+    /// Returns the topology levels, starting with the lowest (SMT) level.
+    #[inline]
+    pub fn levels(&self) -> &[TopologyLevel] {
+        &self.levels[..self.level_count]
+    }
+
+    /// Returns the level with the given type, if it is reported by the processor.
+    #[inline]
+    pub fn level(&self, level_type: LevelType) -> Option<&TopologyLevel> {
+        self.levels().iter().find(|l| l.level_type == level_type)
+    }
+
+    /// Returns the ID of the processor at the given level, relative to the next higher level.
+    ///
+    /// For example, the ID at the SMT level is the index of the logical processor inside its
+    /// core, and the ID at the core level is the index of the core inside the next higher
+    /// level (usually the package).
+    pub fn id(&self, level_type: LevelType) -> Option<u32> {
+        let levels = self.levels();
+        let index = levels.iter().position(|l| l.level_type == level_type)?;
+        let lower_shift = match index {
+            0 => 0,
+            _ => levels[index - 1].shift,
+        };
+        Some((self.x2apic_id & low_bits(levels[index].shift)) >> lower_shift)
+    }
+
+    /// Returns the ID of the package (socket) that contains the processor.
+    #[inline]
+    pub fn package_id(&self) -> u32 {
+        match self.levels().last() {
+            Some(level) if level.shift >= 32 => 0,
+            Some(level) => self.x2apic_id >> level.shift,
+            None => self.x2apic_id,
+        }
+    }
+
+    /// Returns the number of logical processors in a package, as reported by the highest
+    /// topology level.
+    #[inline]
+    pub fn logical_processors_per_package(&self) -> u32 {
+        self.levels()
+            .last()
+            .map_or(1, |level| u32::from(level.logical_processors))
+    }
+}
+
+/// Returns the number of bits needed to number `count` items.
+#[inline]
+fn bits_for(count: u32) -> u8 {
+    match count {
+        0 | 1 => 0,
+        _ => (32 - (count - 1).leading_zeros()) as u8,
+    }
+}
+
+/// Returns a mask with the low `bits` bits set.
+#[inline]
+fn low_bits(bits: u8) -> u32 {
+    match bits {
+        0..=31 => (1 << bits) - 1,
+        _ => !0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn extended_topology() {
+        // two threads per core and eight cores per package, x2APIC ID 0x13
+        let topology = CpuTopology::read_with(query(&[
+            (0, [0xB, 0, 0, 0]),
+            (0xB, [1, 2, 0x100, 0x13]),
+            (0xB, [4, 16, 0x201, 0x13]),
+        ]))
+        .unwrap();
+
+        assert_eq!(topology.source(), TopologySource::ExtendedTopology);
+        assert_eq!(topology.levels().len(), 2);
+        assert_eq!(topology.id(LevelType::Smt), Some(1));
+        assert_eq!(topology.id(LevelType::Core), Some(1));
+        assert_eq!(topology.id(LevelType::Die), None);
+        assert_eq!(topology.package_id(), 1);
+        assert_eq!(topology.logical_processors_per_package(), 16);
+    }
+
+    #[test]
+    fn amd_topology() {
+        // two threads per core, 16 threads per package, extended APIC ID 0x25
+        let topology = CpuTopology::read_with(query(&[
+            (0, [0x7, 0, 0, 0]),
+            (0x8000_0000, [0x8000_001E, 0, 0, 0]),
+            (0x8000_0001, [0, 0, 1 << 22, 0]),
+            (0x8000_0008, [0, 0, 0x400f, 0]),
+            (0x8000_001E, [0x25, 0x100, 0, 0]),
+        ]))
+        .unwrap();
+
+        assert_eq!(topology.source(), TopologySource::AmdExtendedApicId);
+        assert_eq!(topology.id(LevelType::Smt), Some(1));
+        assert_eq!(topology.id(LevelType::Core), Some(2));
+        assert_eq!(topology.package_id(), 2);
+    }
+}
@@ -0,0 +1,452 @@
+//! The enlightenments of Hyper-V for guests.
+//!
+//! A guest checks that it runs under Hyper-V with [`HyperV::detect`], which also reads the
+//! [`HvPrivileges`] of its partition, i.e. the synthetic registers it may access.
+//!
+//! Hypercalls are made through the hypercall page, which the hypervisor fills with the code
+//! for the processor vendor. The page is enabled by writing a [`GuestOsId`] to
+//! [`HvGuestOsId`] and the frame of the page to [`HvHypercall`], see
+//! [`enable_hypercall_page`], and called with [`hypercall`].
+//!
+//! The partition reference time counts in units of 100 nanoseconds. It is read cheaply from
+//! the TSC with the parameters of the [`HvReferenceTscPage`], or through
+//! [`HvTimeRefCount`](crate::registers::model_specific::HvTimeRefCount) otherwise.
+
+use crate::cpuid::hypervisor::{HypervisorInfo, HypervisorVendor};
+use crate::cpuid::{cpuid, CpuidResult};
+use crate::registers::model_specific::{HvGuestOsId, HvHypercall};
+use crate::structures::paging::PhysFrame;
+use crate::time::read_tsc;
+use crate::VirtAddr;
+use bitflags::bitflags;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+/// The hypercall that flushes the TLBs of an address space on a set of processors.
+pub const HVCALL_FLUSH_VIRTUAL_ADDRESS_SPACE: u16 = 0x0002;
+/// The hypercall that flushes a list of virtual addresses on a set of processors.
+pub const HVCALL_FLUSH_VIRTUAL_ADDRESS_LIST: u16 = 0x0003;
+/// The hypercall that notifies the hypervisor of a long spinlock wait.
+pub const HVCALL_NOTIFY_LONG_SPIN_WAIT: u16 = 0x0008;
+/// The hypercall that sends an IPI to a set of processors.
+pub const HVCALL_SEND_IPI: u16 = 0x000b;
+/// The hypercall that posts a message to a synthetic interrupt controller.
+pub const HVCALL_POST_MESSAGE: u16 = 0x005c;
+/// The hypercall that signals an event of a synthetic interrupt controller.
+pub const HVCALL_SIGNAL_EVENT: u16 = 0x005d;
+
+bitflags! {
+    /// The privileges of the partition, reported by CPUID leaf 0x4000_0003.
+    pub struct HvPrivileges: u32 {
+        /// Access to the virtual processor run time register.
+        const ACCESS_VP_RUNTIME_REG = 1;
+        /// Access to the
+        /// [`HvTimeRefCount`](crate::registers::model_specific::HvTimeRefCount) register.
+        const ACCESS_PARTITION_REFERENCE_COUNTER = 1 << 1;
+        /// Access to the synthetic interrupt controller registers.
+        const ACCESS_SYNIC_REGS = 1 << 2;
+        /// Access to the synthetic timer registers.
+        const ACCESS_SYNTHETIC_TIMER_REGS = 1 << 3;
+        /// Access to the virtual APIC registers.
+        const ACCESS_INTR_CTRL_REGS = 1 << 4;
+        /// Access to the [`HvGuestOsId`] and [`HvHypercall`] registers.
+        const ACCESS_HYPERCALL_MSRS = 1 << 5;
+        /// Access to the [`HvVpIndex`](crate::registers::model_specific::HvVpIndex)
+        /// register.
+        const ACCESS_VP_INDEX = 1 << 6;
+        /// Access to the reset register.
+        const ACCESS_RESET_REG = 1 << 7;
+        /// Access to the statistics pages.
+        const ACCESS_STATS_REG = 1 << 8;
+        /// Access to the
+        /// [`HvReferenceTsc`](crate::registers::model_specific::HvReferenceTsc) register.
+        const ACCESS_PARTITION_REFERENCE_TSC = 1 << 9;
+        /// Access to the guest idle register.
+        const ACCESS_GUEST_IDLE_REG = 1 << 10;
+        /// Access to the TSC and APIC frequency registers.
+        const ACCESS_FREQUENCY_REGS = 1 << 11;
+    }
+}
+
+impl_flags_display!(HvPrivileges);
+
+/// The Hyper-V hypervisor that the guest runs under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HyperV {
+    privileges: HvPrivileges,
+}
+
+impl HyperV {
+    /// Detects whether the current processor runs under Hyper-V.
+    ///
+    /// Returns `None` if there is no hypervisor, or if it doesn't provide the Hyper-V
+    /// interface `Hv#1`. Other hypervisors may provide the interface, e.g. KVM with Hyper-V
+    /// enlightenments enabled.
+    #[inline]
+    pub fn detect() -> Option<HyperV> {
+        Self::detect_with(cpuid)
+    }
+
+    /// Detects Hyper-V using the given function instead of the `cpuid` instruction.
+    pub fn detect_with<F>(mut query: F) -> Option<HyperV>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        let info = HypervisorInfo::read_with(&mut query)?;
+        // "Hv#1"
+        if info.vendor != HypervisorVendor::HyperV
+            || !info.has_leaf(0x4000_0003)
+            || query(0x4000_0001, 0).eax != 0x3123_7648
+        {
+            return None;
+        }
+        let privileges = HvPrivileges::from_bits_truncate(query(0x4000_0003, 0).eax);
+        Some(HyperV { privileges })
+    }
+
+    /// Returns the privileges of the partition.
+    #[inline]
+    pub fn privileges(&self) -> HvPrivileges {
+        self.privileges
+    }
+}
+
+/// The identity of the guest operating system, written to [`HvGuestOsId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct GuestOsId(u64);
+
+impl GuestOsId {
+    /// Creates the identity of an open source operating system.
+    ///
+    /// The operating system type is e.g. 0x1 for Linux, and the build number is usually the
+    /// lower bits of the kernel version.
+    #[inline]
+    pub const fn open_source(os_type: u8, os_id: u8, version: u32, build: u16) -> Self {
+        GuestOsId(
+            1 << 63
+                | ((os_type & 0x7f) as u64) << 56
+                | (os_id as u64) << 48
+                | (version as u64) << 16
+                | build as u64,
+        )
+    }
+
+    /// Creates an identity from its raw value.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        GuestOsId(raw)
+    }
+
+    /// Returns the raw value of the identity.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+}
+
+/// Enables the hypercall page at the given frame.
+///
+/// Writes the guest identity first, since the hypercall page can't be enabled without it.
+///
+/// ## Safety
+///
+/// This function is unsafe because the hypervisor overwrites the frame with the hypercall
+/// code, and because the registers only exist if the partition has the
+/// [`ACCESS_HYPERCALL_MSRS`](HvPrivileges::ACCESS_HYPERCALL_MSRS) privilege.
+pub unsafe fn enable_hypercall_page(guest_os_id: GuestOsId, frame: PhysFrame) {
+    HvGuestOsId::write(guest_os_id.as_raw());
+    HvHypercall::write(frame, true);
+}
+
+/// The input value of a hypercall, which holds the call code and how the parameters are
+/// passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct HypercallInput(u64);
+
+impl HypercallInput {
+    /// Creates the input value of a hypercall with the given call code, whose parameters are
+    /// passed in memory.
+    #[inline]
+    pub const fn new(call_code: u16) -> Self {
+        HypercallInput(call_code as u64)
+    }
+
+    /// Makes the hypercall pass its parameters in registers instead of memory.
+    #[inline]
+    pub const fn fast(self) -> Self {
+        HypercallInput(self.0 | 1 << 16)
+    }
+
+    /// Sets the size of the variable header in 8-byte units, at most 1023.
+    #[inline]
+    pub const fn variable_header_size(self, size: u16) -> Self {
+        HypercallInput(self.0 & !(0x3ff << 17) | ((size & 0x3ff) as u64) << 17)
+    }
+
+    /// Makes a rep hypercall with the given number of elements, starting at the given index.
+    ///
+    /// Both values are at most 4095.
+    #[inline]
+    pub const fn rep(self, count: u16, start: u16) -> Self {
+        HypercallInput(
+            self.0 & !(0xfff << 32 | 0xfff << 48)
+                | ((count & 0xfff) as u64) << 32
+                | ((start & 0xfff) as u64) << 48,
+        )
+    }
+
+    /// Returns the raw input value.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+}
+
+/// The error status of a failed hypercall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HvError {
+    /// The call code is unknown.
+    InvalidHypercallCode,
+    /// A reserved bit of the input value is set, or a rep count is used for a simple call.
+    InvalidHypercallInput,
+    /// The parameter pages aren't 8-byte aligned or cross a page boundary.
+    InvalidAlignment,
+    /// A parameter is invalid.
+    InvalidParameter,
+    /// The partition doesn't have the privilege for the call.
+    AccessDenied,
+    /// The partition isn't in a valid state for the call.
+    InvalidPartitionState,
+    /// The operation isn't allowed.
+    OperationDenied,
+    /// The hypervisor doesn't have enough memory for the call.
+    InsufficientMemory,
+    /// A virtual processor index is invalid.
+    InvalidVpIndex,
+    /// A port identifier is invalid.
+    InvalidPortId,
+    /// A connection identifier is invalid.
+    InvalidConnectionId,
+    /// There are not enough message buffers.
+    InsufficientBuffers,
+    /// A status that is not known to this crate.
+    Unknown(u16),
+}
+
+impl HvError {
+    /// Decodes a nonzero status code.
+    #[inline]
+    const fn from_raw(raw: u16) -> HvError {
+        match raw {
+            0x0002 => HvError::InvalidHypercallCode,
+            0x0003 => HvError::InvalidHypercallInput,
+            0x0004 => HvError::InvalidAlignment,
+            0x0005 => HvError::InvalidParameter,
+            0x0006 => HvError::AccessDenied,
+            0x0007 => HvError::InvalidPartitionState,
+            0x0008 => HvError::OperationDenied,
+            0x000b => HvError::InsufficientMemory,
+            0x000e => HvError::InvalidVpIndex,
+            0x0011 => HvError::InvalidPortId,
+            0x0012 => HvError::InvalidConnectionId,
+            0x0013 => HvError::InsufficientBuffers,
+            other => HvError::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for HvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HvError::InvalidHypercallCode => f.write_str("invalid hypercall code"),
+            HvError::InvalidHypercallInput => f.write_str("invalid hypercall input"),
+            HvError::InvalidAlignment => f.write_str("invalid parameter alignment"),
+            HvError::InvalidParameter => f.write_str("invalid parameter"),
+            HvError::AccessDenied => f.write_str("access denied"),
+            HvError::InvalidPartitionState => f.write_str("invalid partition state"),
+            HvError::OperationDenied => f.write_str("operation denied"),
+            HvError::InsufficientMemory => f.write_str("insufficient memory"),
+            HvError::InvalidVpIndex => f.write_str("invalid virtual processor index"),
+            HvError::InvalidPortId => f.write_str("invalid port identifier"),
+            HvError::InvalidConnectionId => f.write_str("invalid connection identifier"),
+            HvError::InsufficientBuffers => f.write_str("insufficient buffers"),
+            HvError::Unknown(status) => write!(f, "unknown status {:#x}", status),
+        }
+    }
+}
+
+/// Decodes the result value of a hypercall into the number of completed reps or the error.
+#[inline]
+const fn hypercall_result(raw: u64) -> Result<u16, HvError> {
+    match raw as u16 {
+        0 => Ok((raw >> 32) as u16 & 0xfff),
+        status => Err(HvError::from_raw(status)),
+    }
+}
+
+/// Makes a hypercall through the hypercall page.
+///
+/// The input and output are the guest-physical addresses of the parameter pages, or the
+/// parameters themselves for [`fast`](HypercallInput::fast) hypercalls. Returns the number of
+/// completed reps, which is 0 for simple hypercalls.
+///
+/// ## Safety
+///
+/// This function is unsafe because the hypercall page must be enabled and mapped executable
+/// at the given address, and because the effects of a hypercall depend on its input, e.g. the
+/// memory that the hypervisor writes the output to.
+#[inline]
+pub unsafe fn hypercall(
+    page: VirtAddr,
+    input: HypercallInput,
+    input_param: u64,
+    output_param: u64,
+) -> Result<u16, HvError> {
+    let result: u64;
+
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        "call {}",
+        in(reg) page.as_u64(),
+        inout("rcx") input.as_raw() => _,
+        inout("rdx") input_param => _,
+        inout("r8") output_param => _,
+        out("rax") result,
+        out("r9") _, out("r10") _, out("r11") _,
+    );
+
+    #[cfg(feature = "external_asm")]
+    {
+        result = crate::asm::x86_64_asm_hv_hypercall(
+            page.as_u64(),
+            input.as_raw(),
+            input_param,
+            output_param,
+        );
+    }
+
+    hypercall_result(result)
+}
+
+/// The reference TSC page (`HV_REFERENCE_TSC_PAGE`), updated by the hypervisor.
+///
+/// The reference time is computed from the TSC as `(tsc * scale) >> 64 + offset`. The
+/// sequence number changes on each update and is 0 while the page can't be used, in which
+/// case the time has to be read from
+/// [`HvTimeRefCount`](crate::registers::model_specific::HvTimeRefCount).
+#[repr(C, align(4096))]
+pub struct HvReferenceTscPage {
+    /// The sequence number of the parameters.
+    pub sequence: u32,
+    _reserved1: u32,
+    /// The scale of the TSC, as a 64.64 fixed-point number.
+    pub scale: u64,
+    /// The offset added to the scaled TSC.
+    pub offset: i64,
+    _reserved2: [u64; 509],
+}
+
+impl HvReferenceTscPage {
+    /// Creates a zeroed page, whose frame can be written to
+    /// [`HvReferenceTsc`](crate::registers::model_specific::HvReferenceTsc).
+    #[inline]
+    pub const fn new() -> Self {
+        HvReferenceTscPage {
+            sequence: 0,
+            _reserved1: 0,
+            scale: 0,
+            offset: 0,
+            _reserved2: [0; 509],
+        }
+    }
+
+    /// Converts the given TSC value to the reference time in units of 100 nanoseconds, using
+    /// the current parameters of the page.
+    #[inline]
+    pub fn time_at(&self, tsc: u64) -> u64 {
+        scale_tsc(tsc, self.scale, self.offset)
+    }
+
+    /// Returns the current reference time in units of 100 nanoseconds.
+    ///
+    /// Returns `None` if the page can't be used, i.e. the sequence number is 0.
+    pub fn read(&self) -> Option<u64> {
+        loop {
+            // the hypervisor writes the page concurrently, so all reads are volatile
+            let sequence = unsafe { ptr::read_volatile(&self.sequence) };
+            if sequence == 0 {
+                return None;
+            }
+            fence(Ordering::Acquire);
+            let scale = unsafe { ptr::read_volatile(&self.scale) };
+            let offset = unsafe { ptr::read_volatile(&self.offset) };
+            let tsc = read_tsc();
+            fence(Ordering::Acquire);
+            if sequence == unsafe { ptr::read_volatile(&self.sequence) } {
+                return Some(scale_tsc(tsc, scale, offset));
+            }
+        }
+    }
+}
+
+/// Converts a TSC value to the reference time with the parameters of the reference TSC page.
+#[inline]
+fn scale_tsc(tsc: u64, scale: u64, offset: i64) -> u64 {
+    let scaled = (u128::from(tsc) * u128::from(scale)) >> 64;
+    (scaled as u64).wrapping_add(offset as u64)
+}
+
+impl Default for HvReferenceTscPage {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for HvReferenceTscPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HvReferenceTscPage")
+            .field("sequence", &self.sequence)
+            .field("scale", &format_args!("{:#x}", self.scale))
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hypercall_encoding() {
+        let input = HypercallInput::new(HVCALL_FLUSH_VIRTUAL_ADDRESS_LIST)
+            .fast()
+            .rep(3, 1);
+        assert_eq!(input.as_raw(), 0x0001_0003_0001_0003);
+        assert_eq!(hypercall_result(0x0000_0003_0000_0000), Ok(3));
+        assert_eq!(
+            hypercall_result(0x0000_0001_0000_0005),
+            Err(HvError::InvalidParameter)
+        );
+        assert_eq!(
+            GuestOsId::open_source(1, 0, 0x050f00, 0).as_raw(),
+            0x8100_0005_0f00_0000
+        );
+    }
+
+    #[test]
+    fn reference_time() {
+        let mut page = HvReferenceTscPage::new();
+        assert_eq!(page.read(), None);
+        // 0.25 units per tick
+        page.sequence = 1;
+        page.scale = 1 << 62;
+        page.offset = -100;
+        assert_eq!(page.time_at(1000), 150);
+    }
+}
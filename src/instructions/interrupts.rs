@@ -1,5 +1,8 @@
 //! Enabling and disabling interrupts
 
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+
 /// Returns whether interrupts are enabled.
 #[inline]
 pub fn are_enabled() -> bool {
@@ -13,11 +16,13 @@ pub fn are_enabled() -> bool {
 /// This is a wrapper around the `sti` instruction.
 #[inline]
 pub fn enable() {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("sti" :::: "volatile");
+        // not `nomem`, since memory accesses must not be moved across the end of a
+        // critical section
+        asm!("sti", options(nostack));
     }
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_interrupt_enable();
     }
@@ -28,12 +33,14 @@ pub fn enable() {
 /// This is a wrapper around the `cli` instruction.
 #[inline]
 pub fn disable() {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("cli" :::: "volatile");
+        // not `nomem`, since memory accesses must not be moved across the start of a
+        // critical section
+        asm!("cli", options(nostack));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_interrupt_disable();
     }
@@ -129,11 +136,11 @@ where
 /// information.
 #[inline]
 pub fn enable_interrupts_and_hlt() {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("sti; hlt" :::: "volatile");
+        asm!("sti; hlt", options(nostack));
     }
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_interrupt_enable_and_hlt();
     }
@@ -142,12 +149,12 @@ pub fn enable_interrupts_and_hlt() {
 /// Cause a breakpoint exception by invoking the `int3` instruction.
 #[inline]
 pub fn int3() {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("int3" :::: "volatile");
+        asm!("int3", options(nomem, nostack));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_int3();
     }
@@ -158,19 +165,12 @@ pub fn int3() {
 /// This currently needs to be a macro because the `int` argument needs to be an
 /// immediate. This macro will be replaced by a generic function when support for
 /// const generics is implemented in Rust.
-#[cfg(feature = "inline_asm")]
-#[macro_export]
-macro_rules! software_interrupt {
-    ($x:expr) => {{
-        asm!("int $0" :: "N" ($x) :: "volatile");
-    }};
-}
-
-/// Not implemented
-#[cfg(not(feature = "inline_asm"))]
+///
+/// The macro expands to inline assembly and is available regardless of the
+/// `external_asm` feature.
 #[macro_export]
 macro_rules! software_interrupt {
     ($x:expr) => {{
-        compile_error!("software_interrupt not implemented for non-nightly");
+        ::core::arch::asm!("int {id}", id = const $x, options(nomem, nostack));
     }};
 }
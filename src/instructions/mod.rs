@@ -1,23 +1,34 @@
-#![cfg(target_arch = "x86_64")]
+#![cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 
 //! Special x86_64 instructions.
 
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+
 pub mod interrupts;
 pub mod port;
 pub mod random;
 pub mod segmentation;
+#[cfg(target_arch = "x86_64")]
+pub mod snp;
+#[cfg(target_arch = "x86_64")]
+pub mod svm;
 pub mod tables;
+#[cfg(target_arch = "x86_64")]
+pub mod tdx;
 pub mod tlb;
+#[cfg(target_arch = "x86_64")]
+pub mod vmx;
 
 /// Halts the CPU until the next interrupt arrives.
 #[inline]
 pub fn hlt() {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("hlt" :::: "volatile");
+        asm!("hlt", options(nomem, nostack, preserves_flags));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_hlt();
     }
@@ -25,10 +36,15 @@ pub fn hlt() {
 
 /// Emits a '[magic breakpoint](https://wiki.osdev.org/Bochs#Magic_Breakpoint)' instruction for the [Bochs](http://bochs.sourceforge.net/) CPU
 /// emulator. Make sure to set `magic_break: enabled=1` in your `.bochsrc` file.
-#[cfg(feature = "inline_asm")]
 #[inline]
 pub fn bochs_breakpoint() {
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        asm!("xchg bx, bx", options(nomem, nostack, preserves_flags));
+    }
+
+    #[cfg(feature = "external_asm")]
     unsafe {
-        asm!("xchgw %bx, %bx" :::: "volatile");
+        crate::asm::x86_64_asm_bochs();
     }
 }
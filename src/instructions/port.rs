@@ -1,136 +1,281 @@
 //! Access to I/O ports
 
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
 use core::marker::PhantomData;
 
 pub use crate::structures::port::{PortRead, PortReadWrite, PortWrite};
 
 impl PortRead for u8 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u8 {
         let value: u8;
-        asm!("inb $1, $0" : "={al}"(value) : "N{dx}"(port) :: "volatile");
+        asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
         value
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u8 {
         crate::asm::x86_64_asm_read_from_port_u8(port)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn read_from_const_port<const N: u16>() -> u8 {
+        let value: u8;
+        if N < 256 {
+            // the mask keeps the immediate valid in the untaken branch for larger ports
+            asm!(
+                "in al, {port}",
+                port = const N & 0xff,
+                out("al") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            value = Self::read_from_port(N);
+        }
+        value
+    }
 }
 
 impl PortRead for u16 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u16 {
         let value: u16;
-        asm!("inw $1, $0" : "={ax}"(value) : "N{dx}"(port) :: "volatile");
+        asm!("in ax, dx", out("ax") value, in("dx") port, options(nomem, nostack, preserves_flags));
         value
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u16 {
         crate::asm::x86_64_asm_read_from_port_u16(port)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn read_from_const_port<const N: u16>() -> u16 {
+        let value: u16;
+        if N < 256 {
+            // the mask keeps the immediate valid in the untaken branch for larger ports
+            asm!(
+                "in ax, {port}",
+                port = const N & 0xff,
+                out("ax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            value = Self::read_from_port(N);
+        }
+        value
+    }
 }
 
 impl PortRead for u32 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u32 {
         let value: u32;
-        asm!("inl $1, $0" : "={eax}"(value) : "N{dx}"(port) :: "volatile");
+        asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
         value
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn read_from_port(port: u16) -> u32 {
         crate::asm::x86_64_asm_read_from_port_u32(port)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn read_from_const_port<const N: u16>() -> u32 {
+        let value: u32;
+        if N < 256 {
+            // the mask keeps the immediate valid in the untaken branch for larger ports
+            asm!(
+                "in eax, {port}",
+                port = const N & 0xff,
+                out("eax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            value = Self::read_from_port(N);
+        }
+        value
+    }
 }
 
 impl PortWrite for u8 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u8) {
-        asm!("outb $1, $0" :: "N{dx}"(port), "{al}"(value) :: "volatile");
+        asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u8) {
         crate::asm::x86_64_asm_write_to_port_u8(port, value)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn write_to_const_port<const N: u16>(value: u8) {
+        if N < 256 {
+            asm!(
+                "out {port}, al",
+                port = const N & 0xff,
+                in("al") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            Self::write_to_port(N, value);
+        }
+    }
 }
 
 impl PortWrite for u16 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u16) {
-        asm!("outw $1, $0" :: "N{dx}"(port), "{ax}"(value) :: "volatile");
+        asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack, preserves_flags));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u16) {
         crate::asm::x86_64_asm_write_to_port_u16(port, value)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn write_to_const_port<const N: u16>(value: u16) {
+        if N < 256 {
+            asm!(
+                "out {port}, ax",
+                port = const N & 0xff,
+                in("ax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            Self::write_to_port(N, value);
+        }
+    }
 }
 
 impl PortWrite for u32 {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u32) {
-        asm!("outl $1, $0" :: "N{dx}"(port), "{eax}"(value) :: "volatile");
+        asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     #[inline]
     unsafe fn write_to_port(port: u16, value: u32) {
         crate::asm::x86_64_asm_write_to_port_u32(port, value)
     }
+
+    #[cfg(not(feature = "external_asm"))]
+    #[inline]
+    unsafe fn write_to_const_port<const N: u16>(value: u32) {
+        if N < 256 {
+            asm!(
+                "out {port}, eax",
+                port = const N & 0xff,
+                in("eax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        } else {
+            Self::write_to_port(N, value);
+        }
+    }
 }
 
 impl PortReadWrite for u8 {}
 impl PortReadWrite for u16 {}
 impl PortReadWrite for u32 {}
 
-/// A read only I/O port.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct PortReadOnly<T: PortRead> {
-    port: u16,
-    phantom: PhantomData<T>,
-}
+/// A marker trait for access types which allow reading port values.
+pub trait PortReadAccess: private::Sealed {}
 
-/// A write only I/O port.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct PortWriteOnly<T: PortWrite> {
-    port: u16,
-    phantom: PhantomData<T>,
+/// A marker trait for access types which allow writing port values.
+pub trait PortWriteAccess: private::Sealed {}
+
+/// An access marker type indicating that a port is only allowed to read values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOnlyAccess(());
+
+/// An access marker type indicating that a port is only allowed to write values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOnlyAccess(());
+
+/// An access marker type indicating that a port is allowed to read or write values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadWriteAccess(());
+
+impl PortReadAccess for ReadOnlyAccess {}
+impl PortWriteAccess for WriteOnlyAccess {}
+impl PortReadAccess for ReadWriteAccess {}
+impl PortWriteAccess for ReadWriteAccess {}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for super::ReadOnlyAccess {}
+    impl Sealed for super::WriteOnlyAccess {}
+    impl Sealed for super::ReadWriteAccess {}
 }
 
-/// An I/O port.
+/// An I/O port whose access is restricted by the access marker `A`.
+///
+/// Usually, one of the [`Port`], [`PortReadOnly`], or [`PortWriteOnly`] type aliases is used.
+/// Accesses that are not allowed by the marker don't compile:
+///
+/// ```compile_fail
+/// use x86_64::instructions::port::PortWriteOnly;
+///
+/// let mut port: PortWriteOnly<u8> = PortWriteOnly::new(0x80);
+/// let _ = unsafe { port.read() };
+/// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Port<T: PortReadWrite> {
+pub struct PortGeneric<T, A> {
     port: u16,
-    phantom: PhantomData<T>,
+    phantom: PhantomData<(T, A)>,
 }
 
-impl<T: PortRead> PortReadOnly<T> {
+/// A read-write I/O port.
+pub type Port<T> = PortGeneric<T, ReadWriteAccess>;
+
+/// A read only I/O port.
+pub type PortReadOnly<T> = PortGeneric<T, ReadOnlyAccess>;
+
+/// A write only I/O port.
+pub type PortWriteOnly<T> = PortGeneric<T, WriteOnlyAccess>;
+
+impl<T, A> PortGeneric<T, A> {
     const_fn! {
-        /// Creates a read only I/O port with the given port number.
+        /// Creates an I/O port with the given port number.
         #[inline]
-        pub fn new(port: u16) -> PortReadOnly<T> {
-            PortReadOnly {
+        pub fn new(port: u16) -> PortGeneric<T, A> {
+            PortGeneric {
                 port,
                 phantom: PhantomData,
             }
         }
     }
 
+    /// Returns the port number.
+    #[inline]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl<T: PortRead, A: PortReadAccess> PortGeneric<T, A> {
     /// Reads from the port.
     ///
     /// ## Safety
@@ -143,18 +288,7 @@ impl<T: PortRead> PortReadOnly<T> {
     }
 }
 
-impl<T: PortWrite> PortWriteOnly<T> {
-    const_fn! {
-        /// Creates a write only I/O port with the given port number.
-        #[inline]
-        pub fn new(port: u16) -> PortWriteOnly<T> {
-            PortWriteOnly {
-                port,
-                phantom: PhantomData,
-            }
-        }
-    }
-
+impl<T: PortWrite, A: PortWriteAccess> PortGeneric<T, A> {
     /// Writes to the port.
     ///
     /// ## Safety
@@ -167,18 +301,31 @@ impl<T: PortWrite> PortWriteOnly<T> {
     }
 }
 
-impl<T: PortReadWrite> Port<T> {
-    const_fn! {
-        /// Creates an I/O port with the given port number.
-        #[inline]
-        pub fn new(port: u16) -> Port<T> {
-            Port {
-                port,
-                phantom: PhantomData,
-            }
+/// A read-write I/O port with a port number that is known at compile time.
+///
+/// Since the port number is a constant, the `in` and `out` instructions use the immediate
+/// encoding for ports below 256 when inline assembly is used, see
+/// [`PortRead::read_from_const_port`]. The type is zero-sized, so it can be stored in statics
+/// or device structs without overhead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstPort<const N: u16, T> {
+    phantom: PhantomData<T>,
+}
+
+impl<const N: u16, T> ConstPort<N, T> {
+    /// The port number.
+    pub const PORT: u16 = N;
+
+    /// Creates the I/O port.
+    #[inline]
+    pub const fn new() -> ConstPort<N, T> {
+        ConstPort {
+            phantom: PhantomData,
         }
     }
+}
 
+impl<const N: u16, T: PortRead> ConstPort<N, T> {
     /// Reads from the port.
     ///
     /// ## Safety
@@ -187,9 +334,23 @@ impl<T: PortReadWrite> Port<T> {
     /// safety.
     #[inline]
     pub unsafe fn read(&mut self) -> T {
-        T::read_from_port(self.port)
+        T::read_from_const_port::<N>()
+    }
+
+    /// Reads from the port without requiring exclusive access to the `ConstPort`.
+    ///
+    /// ## Safety
+    ///
+    /// In addition to the requirements of [`read`](ConstPort::read), the caller must ensure
+    /// that no other code accesses the port concurrently in a way that conflicts with this
+    /// read, e.g. through another `ConstPort` instance or a shared static.
+    #[inline]
+    pub unsafe fn read_unchecked(&self) -> T {
+        T::read_from_const_port::<N>()
     }
+}
 
+impl<const N: u16, T: PortWrite> ConstPort<N, T> {
     /// Writes to the port.
     ///
     /// ## Safety
@@ -198,6 +359,221 @@ impl<T: PortReadWrite> Port<T> {
     /// safety.
     #[inline]
     pub unsafe fn write(&mut self, value: T) {
-        T::write_to_port(self.port, value)
+        T::write_to_const_port::<N>(value)
+    }
+
+    /// Writes to the port without requiring exclusive access to the `ConstPort`.
+    ///
+    /// ## Safety
+    ///
+    /// In addition to the requirements of [`write`](ConstPort::write), the caller must ensure
+    /// that no other code accesses the port concurrently in a way that conflicts with this
+    /// write, e.g. through another `ConstPort` instance or a shared static.
+    #[inline]
+    pub unsafe fn write_unchecked(&self, value: T) {
+        T::write_to_const_port::<N>(value)
+    }
+}
+
+impl<const N: u16, T> Default for ConstPort<N, T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A contiguous range of I/O ports, e.g. the ports of a PCI I/O BAR.
+///
+/// The ports are accessed through offsets relative to the base port. All accessors panic if
+/// the accessed port (including all bytes of multi-byte accesses) is outside the range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRange {
+    base: u16,
+    len: u16,
+}
+
+impl PortRange {
+    /// Creates a range of `len` ports starting at the port `base`.
+    ///
+    /// Panics if the range extends beyond port `0xFFFF`.
+    #[inline]
+    pub fn new(base: u16, len: u16) -> PortRange {
+        assert!(
+            u32::from(base) + u32::from(len) <= 0x1_0000,
+            "port range out of bounds"
+        );
+        PortRange { base, len }
+    }
+
+    /// Returns the first port of the range.
+    #[inline]
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// Returns the number of ports in the range.
+    #[inline]
+    pub fn len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns whether the range contains no ports.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether the given port number is part of the range.
+    #[inline]
+    pub fn contains(&self, port: u16) -> bool {
+        port >= self.base && port - self.base < self.len
+    }
+
+    /// Returns the port number at the given offset for an access of type `T`.
+    #[inline]
+    fn port_number<T>(&self, offset: u16) -> u16 {
+        let size = core::mem::size_of::<T>() as u32;
+        assert!(
+            u32::from(offset) + size <= u32::from(self.len),
+            "port offset {:#x} out of range",
+            offset
+        );
+        self.base + offset
+    }
+
+    /// Returns a read-write port at the given offset.
+    #[inline]
+    pub fn port<T>(&self, offset: u16) -> Port<T> {
+        Port::new(self.port_number::<T>(offset))
+    }
+
+    /// Returns a read only port at the given offset.
+    #[inline]
+    pub fn read_only_port<T>(&self, offset: u16) -> PortReadOnly<T> {
+        PortReadOnly::new(self.port_number::<T>(offset))
+    }
+
+    /// Returns a write only port at the given offset.
+    #[inline]
+    pub fn write_only_port<T>(&self, offset: u16) -> PortWriteOnly<T> {
+        PortWriteOnly::new(self.port_number::<T>(offset))
+    }
+
+    /// Reads from the port at the given offset.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the I/O port could have side effects that violate memory
+    /// safety.
+    #[inline]
+    pub unsafe fn read<T: PortRead>(&mut self, offset: u16) -> T {
+        T::read_from_port(self.port_number::<T>(offset))
+    }
+
+    /// Writes to the port at the given offset.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the I/O port could have side effects that violate memory
+    /// safety.
+    #[inline]
+    pub unsafe fn write<T: PortWrite>(&mut self, offset: u16, value: T) {
+        T::write_to_port(self.port_number::<T>(offset), value)
+    }
+
+    /// Returns the subrange of `len` ports starting at the given offset.
+    ///
+    /// Panics if the subrange is not contained in this range.
+    #[inline]
+    pub fn subrange(&self, offset: u16, len: u16) -> PortRange {
+        assert!(
+            u32::from(offset) + u32::from(len) <= u32::from(self.len),
+            "subrange out of range"
+        );
+        PortRange {
+            base: self.base.wrapping_add(offset),
+            len,
+        }
+    }
+
+    /// Splits the range into two ranges at the given offset.
+    ///
+    /// The first range contains the ports `[0, offset)` and the second range the ports
+    /// `[offset, len)` of this range.
+    ///
+    /// Panics if `offset` is greater than the length of the range.
+    #[inline]
+    pub fn split_at(self, offset: u16) -> (PortRange, PortRange) {
+        assert!(offset <= self.len, "split offset out of range");
+        (
+            PortRange {
+                base: self.base,
+                len: offset,
+            },
+            PortRange {
+                // wraps only for an empty range after port 0xFFFF
+                base: self.base.wrapping_add(offset),
+                len: self.len - offset,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readable<A: PortReadAccess>() {}
+    fn writable<A: PortWriteAccess>() {}
+
+    #[test]
+    fn access_markers() {
+        readable::<ReadOnlyAccess>();
+        readable::<ReadWriteAccess>();
+        writable::<WriteOnlyAccess>();
+        writable::<ReadWriteAccess>();
+
+        let port: Port<u16> = Port::new(0x3f8);
+        let read_only: PortReadOnly<u8> = PortReadOnly::new(0x64);
+        let write_only: PortWriteOnly<u32> = PortWriteOnly::new(0xcf8);
+        assert_eq!(
+            (port.port(), read_only.port(), write_only.port()),
+            (0x3f8, 0x64, 0xcf8)
+        );
+        assert_eq!(port, Port::new(0x3f8));
+    }
+
+    #[test]
+    fn port_range() {
+        let range = PortRange::new(0x1f0, 8);
+        assert!(range.contains(0x1f0) && range.contains(0x1f7));
+        assert!(!range.contains(0x1ef) && !range.contains(0x1f8));
+        assert_eq!(range.port::<u8>(7).port(), 0x1f7);
+        assert_eq!(range.read_only_port::<u32>(4).port(), 0x1f4);
+        assert_eq!(range.write_only_port::<u16>(6).port(), 0x1f6);
+
+        let (low, high) = range.clone().split_at(2);
+        assert_eq!((low.base(), low.len()), (0x1f0, 2));
+        assert_eq!((high.base(), high.len()), (0x1f2, 6));
+        let sub = range.subrange(6, 2);
+        assert_eq!((sub.base(), sub.len()), (0x1f6, 2));
+        assert!(range.subrange(8, 0).is_empty());
+
+        // a range can end at the last port
+        let (_, empty) = PortRange::new(0xfff8, 8).split_at(8);
+        assert_eq!((empty.base(), empty.len()), (0, 0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn port_range_access_out_of_range() {
+        // the second byte of the access is outside the range
+        PortRange::new(0x1f0, 8).port::<u16>(7);
+    }
+
+    #[test]
+    #[should_panic]
+    fn port_range_out_of_bounds() {
+        PortRange::new(0xfff8, 9);
     }
 }
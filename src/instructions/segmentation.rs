@@ -1,13 +1,17 @@
 //! Provides functions to read and write segment registers.
 
 use crate::structures::gdt::SegmentSelector;
+#[cfg(target_arch = "x86_64")]
+use crate::VirtAddr;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
 
 /// Reload code segment register.
 ///
 /// Note this is special since we can not directly move
 /// to %cs. Instead we push the new segment selector
 /// and return value on the stack and use lretq
-/// to reload cs and continue at 1:.
+/// to reload cs and continue at the following label.
 ///
 /// ## Safety
 ///
@@ -15,17 +19,39 @@ use crate::structures::gdt::SegmentSelector;
 /// is a valid code segment descriptor.
 #[inline]
 pub unsafe fn set_cs(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(all(target_arch = "x86_64", not(feature = "external_asm")))]
     #[inline(always)]
     unsafe fn inner(sel: SegmentSelector) {
-        asm!("pushq $0; \
-              leaq  1f(%rip), %rax; \
-              pushq %rax; \
-              lretq; \
-              1:" :: "ri" (u64::from(sel.0)) : "rax" "memory");
+        asm!(
+            "push {sel}",
+            "lea {tmp}, [rip + 55f]",
+            "push {tmp}",
+            "retfq",
+            "55:",
+            sel = in(reg) u64::from(sel.0),
+            tmp = lateout(reg) _,
+            options(preserves_flags),
+        );
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    // In protected mode, the return address is computed relative to the pushed return
+    // address of a `call`, so that the code stays position independent.
+    #[cfg(target_arch = "x86")]
+    #[inline(always)]
+    unsafe fn inner(sel: SegmentSelector) {
+        asm!(
+            "push {sel}",
+            "call 55f",
+            "55:",
+            "addl $(56f - 55b), (%esp)",
+            "lretl",
+            "56:",
+            sel = in(reg) u32::from(sel.0),
+            options(att_syntax),
+        );
+    }
+
+    #[cfg(feature = "external_asm")]
     #[inline(always)]
     unsafe fn inner(sel: SegmentSelector) {
         crate::asm::x86_64_asm_set_cs(u64::from(sel.0))
@@ -34,6 +60,39 @@ pub unsafe fn set_cs(sel: SegmentSelector) {
     inner(sel)
 }
 
+/// Reload code segment register and continue execution at `target`.
+///
+/// This is the general form of [`set_cs`]: the new segment selector and the
+/// target address are pushed on the stack and loaded through a far return
+/// (`lretq`). In contrast to `set_cs`, execution does not continue after the
+/// call, but at the given `target` address, which makes it possible to jump
+/// to a different code path (e.g. to a new kernel entry point) while
+/// switching the code segment.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that `sel`
+/// is a valid code segment descriptor and that `target` points to valid code
+/// that can be executed with the current stack.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub unsafe fn far_return(sel: SegmentSelector, target: VirtAddr) -> ! {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        asm!(
+            "push {sel}",
+            "push {target}",
+            "retfq",
+            sel = in(reg) u64::from(sel.0),
+            target = in(reg) target.as_u64(),
+            options(noreturn),
+        );
+    }
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_far_return(u64::from(sel.0), target.as_u64())
+}
+
 /// Reload stack segment register.
 ///
 /// ## Safety
@@ -42,10 +101,10 @@ pub unsafe fn set_cs(sel: SegmentSelector) {
 /// is a valid stack segment descriptor.
 #[inline]
 pub unsafe fn load_ss(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("movw $0, %ss " :: "r" (sel.0) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov ss, {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_load_ss(sel.0);
 }
 
@@ -57,10 +116,10 @@ pub unsafe fn load_ss(sel: SegmentSelector) {
 /// is a valid data segment descriptor.
 #[inline]
 pub unsafe fn load_ds(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("movw $0, %ds " :: "r" (sel.0) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov ds, {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_load_ds(sel.0);
 }
 
@@ -72,10 +131,10 @@ pub unsafe fn load_ds(sel: SegmentSelector) {
 /// is a valid extra segment descriptor.
 #[inline]
 pub unsafe fn load_es(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("movw $0, %es " :: "r" (sel.0) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov es, {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_load_es(sel.0);
 }
 
@@ -87,10 +146,10 @@ pub unsafe fn load_es(sel: SegmentSelector) {
 /// is a valid fs segment descriptor.
 #[inline]
 pub unsafe fn load_fs(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("movw $0, %fs " :: "r" (sel.0) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov fs, {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_load_fs(sel.0);
 }
 
@@ -102,10 +161,10 @@ pub unsafe fn load_fs(sel: SegmentSelector) {
 /// is a valid gs segment descriptor.
 #[inline]
 pub unsafe fn load_gs(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("movw $0, %gs " :: "r" (sel.0) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("mov gs, {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_load_gs(sel.0);
 }
 
@@ -115,28 +174,231 @@ pub unsafe fn load_gs(sel: SegmentSelector) {
 ///
 /// This function is unsafe because the caller must ensure that the
 /// swap operation cannot lead to undefined behavior.
+#[cfg(target_arch = "x86_64")]
 #[inline]
 pub unsafe fn swap_gs() {
-    #[cfg(feature = "inline_asm")]
-    asm!("swapgs" ::: "memory" : "volatile");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("swapgs", options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_swapgs();
 }
 
 /// Returns the current value of the code segment register.
 #[inline]
 pub fn cs() -> SegmentSelector {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     {
         let segment: u16;
-        unsafe { asm!("mov %cs, $0" : "=r" (segment) ) };
+        unsafe {
+            asm!("mov {0:x}, cs", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
         SegmentSelector(segment)
     }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     {
         let segment: u16 = unsafe { crate::asm::x86_64_asm_get_cs() };
         SegmentSelector(segment)
     }
 }
+
+/// Returns the current value of the stack segment register.
+#[inline]
+pub fn ss() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe {
+            asm!("mov {0:x}, ss", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_get_ss() };
+        SegmentSelector(segment)
+    }
+}
+
+/// Returns the current value of the data segment register.
+#[inline]
+pub fn ds() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe {
+            asm!("mov {0:x}, ds", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_get_ds() };
+        SegmentSelector(segment)
+    }
+}
+
+/// Returns the current value of the extra segment register.
+#[inline]
+pub fn es() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe {
+            asm!("mov {0:x}, es", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_get_es() };
+        SegmentSelector(segment)
+    }
+}
+
+/// Returns the current value of the `fs` segment register.
+#[inline]
+pub fn fs() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe {
+            asm!("mov {0:x}, fs", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_get_fs() };
+        SegmentSelector(segment)
+    }
+}
+
+/// Returns the current value of the `gs` segment register.
+#[inline]
+pub fn gs() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe {
+            asm!("mov {0:x}, gs", out(reg) segment, options(nomem, nostack, preserves_flags))
+        };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_get_gs() };
+        SegmentSelector(segment)
+    }
+}
+
+/// Reload all segment registers that are used by a 64-bit kernel.
+///
+/// This function is intended to be called right after a new GDT was loaded. It
+/// reloads `cs` with `code_sel`, the `ss`, `ds` and `es` registers with `data_sel` and
+/// loads the task state segment referenced by `tss_sel` using the `ltr` instruction.
+///
+/// The `fs` and `gs` registers are not modified, since loading them would reset the
+/// `FsBase` and `GsBase` model specific registers.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the selectors point to
+/// valid code, data and TSS descriptors of the currently loaded GDT.
+#[inline]
+pub unsafe fn load_kernel_segments(
+    code_sel: SegmentSelector,
+    data_sel: SegmentSelector,
+    tss_sel: SegmentSelector,
+) {
+    use crate::instructions::tables::load_tss;
+
+    set_cs(code_sel);
+    load_ss(data_sel);
+    load_ds(data_sel);
+    load_es(data_sel);
+    load_tss(tss_sel);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_cs() {
+        // a far return to the current code segment is allowed in user mode
+        let current = cs();
+        unsafe { set_cs(current) };
+        assert_eq!(cs(), current);
+    }
+
+    #[test]
+    fn user_segments() {
+        use crate::PrivilegeLevel;
+
+        // the stack segment has the privilege level of the running code
+        assert_eq!(ss().rpl(), PrivilegeLevel::Ring3);
+        assert_eq!(ss().rpl(), cs().rpl());
+        assert_ne!(ss().index(), cs().index());
+        for selector in [ds(), es(), fs(), gs()].iter() {
+            assert!(selector.index() == 0 || selector.rpl() == PrivilegeLevel::Ring3);
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" fn jump(sel: u16, target: u64) -> ! {
+        unsafe { far_return(SegmentSelector(sel), VirtAddr::new(target)) }
+    }
+
+    // Calls `jump` with a target that restores the stack pointer and returns to the caller
+    // of the stub, so that the far return can be checked on the test thread.
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .data",
+        "x86_64_test_far_return_rsp: .quad 0",
+        ".popsection",
+        ".global x86_64_test_far_return",
+        "x86_64_test_far_return:",
+        "push rbx",
+        "push rbp",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "sub rsp, 8",
+        "mov [rip + x86_64_test_far_return_rsp], rsp",
+        "lea rsi, [rip + 2f]",
+        "call {jump}",
+        "2:",
+        "mov rsp, [rip + x86_64_test_far_return_rsp]",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbp",
+        "pop rbx",
+        "ret",
+        jump = sym jump,
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    extern "C" {
+        fn x86_64_test_far_return(sel: u16);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn far_return_to_current_segment() {
+        // a far return to the current code segment is allowed in user mode
+        let current = cs();
+        unsafe { x86_64_test_far_return(current.0) };
+        assert_eq!(cs(), current);
+    }
+}
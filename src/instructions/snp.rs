@@ -0,0 +1,209 @@
+//! Instructions of SEV-SNP guests.
+//!
+//! The memory of an SEV-SNP guest is protected by the reverse map table (RMP), which records
+//! for every frame the guest it is assigned to and whether the guest validated it. A guest
+//! validates a private page with [`pvalidate`] before using it, and rescinds the validation
+//! before it is converted to shared, see the page state change of the GHCB MSR protocol in
+//! [`sev`](crate::sev). The guest at VMPL0 grants the guests at higher VMPLs access to its
+//! pages with [`rmpadjust`].
+//!
+//! Both instructions take the virtual address of the page in RAX and the page size in RCX,
+//! and return a status code in EAX, which is decoded into an [`RmpError`].
+
+use crate::VirtAddr;
+use bitflags::bitflags;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::fmt;
+
+/// The size of the page operated on by [`pvalidate`] and [`rmpadjust`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RmpPageSize {
+    /// A 4KiB page.
+    Size4KiB = 0,
+    /// A 2MiB page, which must be mapped by a 2MiB RMP entry.
+    Size2MiB = 1,
+}
+
+bitflags! {
+    /// The permissions of a VMPL for a page, set with [`rmpadjust`].
+    pub struct VmplPermissions: u8 {
+        /// The page can be read.
+        const READ =                1;
+        /// The page can be written.
+        const WRITE =               1 << 1;
+        /// The page can be executed in user mode.
+        const EXECUTE_USER =        1 << 2;
+        /// The page can be executed in supervisor mode.
+        const EXECUTE_SUPERVISOR =  1 << 3;
+    }
+}
+
+impl_flags_display!(VmplPermissions);
+
+/// The reason why [`pvalidate`] or [`rmpadjust`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RmpError {
+    /// An operand is invalid, e.g. the page isn't assigned to the guest or the target VMPL
+    /// isn't higher than the current one.
+    Input,
+    /// The current VMPL lacks the permissions that it tries to grant.
+    Permission,
+    /// The page size doesn't match the size of the RMP entry, e.g. for a 2MiB page whose
+    /// RMP entries were split into 4KiB entries.
+    SizeMismatch,
+    /// `pvalidate` didn't change the RMP entry, since the page was already in the requested
+    /// state. This can indicate that the hypervisor remapped a validated page.
+    NoUpdate,
+    /// A status code that isn't known to this crate.
+    Unknown(u32),
+}
+
+impl RmpError {
+    /// Decodes the status code returned in EAX, where 0 means success.
+    #[inline]
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => None,
+            1 => Some(RmpError::Input),
+            2 => Some(RmpError::Permission),
+            6 => Some(RmpError::SizeMismatch),
+            code => Some(RmpError::Unknown(code)),
+        }
+    }
+}
+
+impl fmt::Display for RmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RmpError::Input => f.write_str("invalid operand"),
+            RmpError::Permission => f.write_str("insufficient permissions"),
+            RmpError::SizeMismatch => f.write_str("page size doesn't match the RMP entry"),
+            RmpError::NoUpdate => f.write_str("RMP entry wasn't changed"),
+            RmpError::Unknown(code) => write!(f, "unknown RMP status {}", code),
+        }
+    }
+}
+
+/// Validates the private page at the given address, or rescinds its validation if `validate`
+/// is false.
+///
+/// Returns [`RmpError::NoUpdate`] if the page was already validated or not validated,
+/// respectively.
+///
+/// ## Safety
+///
+/// This function is unsafe because validating a page that is already in use, e.g. after the
+/// hypervisor remapped it, breaks the integrity guarantees of SEV-SNP, and because the page
+/// must not be accessed after its validation is rescinded. It causes an invalid opcode
+/// exception outside of an SEV-SNP guest.
+#[inline]
+pub unsafe fn pvalidate(addr: VirtAddr, size: RmpPageSize, validate: bool) -> Result<(), RmpError> {
+    let status: u32;
+    let no_update: u8;
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let rax: u64;
+        asm!(
+            // `pvalidate`, written as bytes since not all assemblers know it
+            ".byte 0xf2, 0x0f, 0x01, 0xff",
+            "setc {}",
+            out(reg_byte) no_update,
+            inout("rax") addr.as_u64() => rax,
+            in("ecx") size as u32,
+            in("edx") u32::from(validate),
+            options(nostack),
+        );
+        status = rax as u32;
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let mut code = 0;
+        no_update = crate::asm::x86_64_asm_pvalidate(
+            addr.as_u64(),
+            size as u32,
+            u32::from(validate),
+            &mut code,
+        );
+        status = code;
+    }
+
+    match RmpError::from_code(status) {
+        Some(error) => Err(error),
+        None if no_update != 0 => Err(RmpError::NoUpdate),
+        None => Ok(()),
+    }
+}
+
+/// Sets the permissions of the given VMPL for the private page at the given address.
+///
+/// If `vmsa` is true, the page becomes the VM save area of a virtual processor that runs at
+/// the given VMPL, which is how the guest at VMPL0 starts processors at higher VMPLs.
+///
+/// ## Safety
+///
+/// This function is unsafe because the guest at the given VMPL gains access to the page, and
+/// because it causes an invalid opcode exception outside of an SEV-SNP guest.
+#[inline]
+pub unsafe fn rmpadjust(
+    addr: VirtAddr,
+    size: RmpPageSize,
+    vmpl: u8,
+    permissions: VmplPermissions,
+    vmsa: bool,
+) -> Result<(), RmpError> {
+    let attributes = rmpadjust_attributes(vmpl, permissions, vmsa);
+    let status: u32;
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let rax: u64;
+        asm!(
+            // `rmpadjust`, written as bytes since not all assemblers know it
+            ".byte 0xf3, 0x0f, 0x01, 0xfe",
+            inout("rax") addr.as_u64() => rax,
+            in("rcx") size as u64,
+            in("rdx") attributes,
+            options(nostack),
+        );
+        status = rax as u32;
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        status = crate::asm::x86_64_asm_rmpadjust(addr.as_u64(), size as u64, attributes);
+    }
+
+    match RmpError::from_code(status) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Encodes the attributes operand of `rmpadjust` in RDX.
+#[inline]
+fn rmpadjust_attributes(vmpl: u8, permissions: VmplPermissions, vmsa: bool) -> u64 {
+    u64::from(vmpl) | u64::from(permissions.bits()) << 8 | u64::from(vmsa) << 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rmp_operands() {
+        let permissions = VmplPermissions::READ | VmplPermissions::EXECUTE_SUPERVISOR;
+        assert_eq!(rmpadjust_attributes(1, permissions, false), 0x0901);
+        assert_eq!(
+            rmpadjust_attributes(2, VmplPermissions::empty(), true),
+            0x1_0002
+        );
+
+        assert_eq!(RmpError::from_code(0), None);
+        assert_eq!(RmpError::from_code(6), Some(RmpError::SizeMismatch));
+        assert_eq!(RmpError::from_code(3), Some(RmpError::Unknown(3)));
+    }
+}
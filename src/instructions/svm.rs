@@ -0,0 +1,231 @@
+//! Instructions of the AMD secure virtual machine extensions (SVM).
+//!
+//! Unlike the VMX instructions, the SVM instructions don't report failures: they cause an
+//! invalid opcode exception if SVM isn't enabled in EFER and a general protection fault
+//! outside of ring 0 or for invalid operands.
+//!
+//! A guest is entered with [`vmrun`], which loads the guest state from the VMCB and returns on
+//! the next `#VMEXIT`. `vmrun` and `#VMEXIT` only switch part of the processor state, the
+//! segment and system call state that isn't switched is saved and loaded with [`vmsave`]
+//! and [`vmload`]. The global interrupt flag is usually cleared with [`clgi`] around the
+//! switch and set again with [`stgi`] after the host state is restored.
+//!
+//! SVM must be enabled first, see [`svm::enable`](crate::svm::enable).
+
+use super::vmx::{GuestRegisters, HypercallRegisters};
+use crate::addr::{PhysAddr, VirtAddr};
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+
+/// Enters the guest of the given VMCB and returns on the next `#VMEXIT`.
+///
+/// The general-purpose registers of the guest are loaded from `regs` and stored back on
+/// `#VMEXIT`, except RAX, which is part of the state-save area of the VMCB. The host state
+/// that `#VMEXIT` restores must be saved to the host save area first, see the VM_HSAVE_PA
+/// register.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the VMCB is 4KiB aligned and
+/// describes a valid guest, whose execution can't break the memory safety of the host.
+///
+/// Only the general-purpose registers are switched here and `vmrun` doesn't switch the x87,
+/// SSE and AVX state either, including the control words in MXCSR and the x87 FCW. So the
+/// guest sees the extended state of the host and can modify it. The caller must switch this
+/// state itself, e.g. with `xsave` and `xrstor` in the same assembly block as `vmrun`, or
+/// ensure that neither the host nor the guest depend on it.
+#[inline]
+pub unsafe fn vmrun(vmcb: PhysAddr, regs: &mut GuestRegisters) {
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        // save the callee-saved registers that LLVM doesn't allow as operands
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "push rdi",
+        "mov rbx, [rdi + 8]",
+        "mov rcx, [rdi + 16]",
+        "mov rdx, [rdi + 24]",
+        "mov rsi, [rdi + 32]",
+        "mov rbp, [rdi + 48]",
+        "mov r8, [rdi + 56]",
+        "mov r9, [rdi + 64]",
+        "mov r10, [rdi + 72]",
+        "mov r11, [rdi + 80]",
+        "mov r12, [rdi + 88]",
+        "mov r13, [rdi + 96]",
+        "mov r14, [rdi + 104]",
+        "mov r15, [rdi + 112]",
+        "mov rdi, [rdi + 40]",
+        "vmrun rax",
+        // #VMEXIT restores RAX and RSP from the host save area
+        "push rdi",
+        "mov rdi, [rsp + 8]",
+        "mov [rdi + 8], rbx",
+        "mov [rdi + 16], rcx",
+        "mov [rdi + 24], rdx",
+        "mov [rdi + 32], rsi",
+        "mov [rdi + 48], rbp",
+        "mov [rdi + 56], r8",
+        "mov [rdi + 64], r9",
+        "mov [rdi + 72], r10",
+        "mov [rdi + 80], r11",
+        "mov [rdi + 88], r12",
+        "mov [rdi + 96], r13",
+        "mov [rdi + 104], r14",
+        "mov [rdi + 112], r15",
+        "pop qword ptr [rdi + 40]",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        inout("rdi") regs as *mut GuestRegisters => _,
+        inout("rax") vmcb.as_u64() => _,
+        clobber_abi("C"),
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmrun(vmcb.as_u64(), regs);
+}
+
+/// Loads the segment and system call state that `vmrun` doesn't switch from the given VMCB:
+/// FS, GS, TR and LDTR including their hidden state, KernelGsBase, STAR, LSTAR, CSTAR,
+/// SFMASK and the SYSENTER registers.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the VMCB is 4KiB aligned and
+/// that the loaded state doesn't break memory safety, e.g. through a different GS base.
+#[inline]
+pub unsafe fn vmload(vmcb: PhysAddr) {
+    #[cfg(not(feature = "external_asm"))]
+    asm!("vmload rax", in("rax") vmcb.as_u64(), options(nostack, preserves_flags));
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmload(vmcb.as_u64());
+}
+
+/// Saves the state loaded by [`vmload`] to the given VMCB.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the VMCB is 4KiB aligned and
+/// not in use by a running guest.
+#[inline]
+pub unsafe fn vmsave(vmcb: PhysAddr) {
+    #[cfg(not(feature = "external_asm"))]
+    asm!("vmsave rax", in("rax") vmcb.as_u64(), options(nostack, preserves_flags));
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmsave(vmcb.as_u64());
+}
+
+/// Sets the global interrupt flag, which allows interrupts, NMIs and SMIs again.
+///
+/// ## Safety
+///
+/// This function is unsafe because interrupts may be delivered afterwards, which the caller
+/// must be prepared for.
+#[inline]
+pub unsafe fn stgi() {
+    #[cfg(not(feature = "external_asm"))]
+    asm!("stgi", options(nostack, preserves_flags));
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_stgi();
+}
+
+/// Clears the global interrupt flag, which holds off all interrupts, NMIs and SMIs until
+/// [`stgi`] is executed or a guest is entered.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the flag is set again, since
+/// NMIs and machine checks can't be handled before.
+#[inline]
+pub unsafe fn clgi() {
+    #[cfg(not(feature = "external_asm"))]
+    asm!("clgi", options(nostack, preserves_flags));
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_clgi();
+}
+
+/// Invalidates the TLB entries of the given guest virtual address in the address space with
+/// the given ASID.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in ring 0 with SVM enabled.
+#[inline]
+pub unsafe fn invlpga(addr: VirtAddr, asid: u32) {
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        "invlpga rax, ecx",
+        in("rax") addr.as_u64(), in("ecx") asid,
+        options(nostack, preserves_flags),
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_invlpga(addr.as_u64(), asid);
+}
+
+/// Calls the hypervisor from a guest, passing the given registers and returning their values
+/// after the hypervisor resumes the guest.
+///
+/// This is the SVM counterpart of [`vmcall`](super::vmx::vmcall).
+///
+/// ## Safety
+///
+/// This function is unsafe because the effects of a hypercall are defined by the hypervisor,
+/// and it causes an invalid opcode exception if the hypervisor doesn't intercept it.
+#[inline]
+pub unsafe fn vmmcall(mut regs: HypercallRegisters) -> HypercallRegisters {
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        // LLVM reserves rbx, so it is swapped with a scratch register around the call
+        "xchg {rbx}, rbx",
+        "vmmcall",
+        "xchg {rbx}, rbx",
+        rbx = inout(reg) regs.rbx,
+        inout("rax") regs.rax,
+        inout("rcx") regs.rcx,
+        inout("rdx") regs.rdx,
+        inout("rsi") regs.rsi,
+        options(nostack),
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmmcall(&mut regs);
+
+    regs
+}
+
+/// Exits from an SEV-ES guest to the hypervisor, which handles the request described by the
+/// guest-host communication block (GHCB) or the GHCB MSR.
+///
+/// Unlike [`vmmcall`], no registers are passed, since the register state of an SEV-ES guest
+/// isn't visible to the hypervisor. See [`sev`](crate::sev) for the protocol.
+///
+/// ## Safety
+///
+/// This function is unsafe because the hypervisor reads and writes the shared GHCB, and
+/// because it causes an invalid opcode exception outside of an SEV-ES guest.
+#[inline]
+pub unsafe fn vmgexit() {
+    #[cfg(not(feature = "external_asm"))]
+    // `rep vmmcall`, written as bytes since not all assemblers know `vmgexit`
+    asm!(
+        ".byte 0xf3, 0x0f, 0x01, 0xd9",
+        options(nostack, preserves_flags)
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmgexit();
+}
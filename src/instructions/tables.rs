@@ -1,6 +1,8 @@
 //! Functions to load GDT, IDT, and TSS structures.
 
 use crate::structures::gdt::SegmentSelector;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
 
 pub use crate::structures::DescriptorTablePointer;
 
@@ -14,13 +16,14 @@ pub use crate::structures::DescriptorTablePointer;
 ///
 /// This function is unsafe because the caller must ensure that the given
 /// `DescriptorTablePointer` points to a valid GDT and that loading this
-/// GDT is safe.
+/// GDT is safe. The GDT must not be destroyed or moved as long as it is
+/// loaded, e.g. it must not be allocated on the stack of a function that returns.
 #[inline]
 pub unsafe fn lgdt(gdt: &DescriptorTablePointer) {
-    #[cfg(feature = "inline_asm")]
-    asm!("lgdt ($0)" :: "r" (gdt) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("lgdt [{}]", in(reg) gdt, options(readonly, nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_lgdt(gdt as *const _);
 }
 
@@ -34,16 +37,53 @@ pub unsafe fn lgdt(gdt: &DescriptorTablePointer) {
 ///
 /// This function is unsafe because the caller must ensure that the given
 /// `DescriptorTablePointer` points to a valid IDT and that loading this
-/// IDT is safe.
+/// IDT is safe. The IDT must not be destroyed or moved as long as it is
+/// loaded, e.g. it must not be allocated on the stack of a function that returns.
 #[inline]
 pub unsafe fn lidt(idt: &DescriptorTablePointer) {
-    #[cfg(feature = "inline_asm")]
-    asm!("lidt ($0)" :: "r" (idt) : "memory");
+    #[cfg(not(feature = "external_asm"))]
+    asm!("lidt [{}]", in(reg) idt, options(readonly, nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_lidt(idt as *const _);
 }
 
+/// Returns the base and limit of the currently loaded GDT, read using the `sgdt` instruction.
+#[inline]
+pub fn sgdt() -> DescriptorTablePointer {
+    let mut gdt = DescriptorTablePointer { limit: 0, base: 0 };
+
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        asm!("sgdt [{}]", in(reg) &mut gdt, options(nostack, preserves_flags))
+    };
+
+    #[cfg(feature = "external_asm")]
+    unsafe {
+        crate::asm::x86_64_asm_sgdt(&mut gdt as *mut _)
+    };
+
+    gdt
+}
+
+/// Returns the base and limit of the currently loaded IDT, read using the `sidt` instruction.
+#[inline]
+pub fn sidt() -> DescriptorTablePointer {
+    let mut idt = DescriptorTablePointer { limit: 0, base: 0 };
+
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        asm!("sidt [{}]", in(reg) &mut idt, options(nostack, preserves_flags))
+    };
+
+    #[cfg(feature = "external_asm")]
+    unsafe {
+        crate::asm::x86_64_asm_sidt(&mut idt as *mut _)
+    };
+
+    idt
+}
+
 /// Load the task state register using the `ltr` instruction.
 ///
 /// ## Safety
@@ -53,9 +93,27 @@ pub unsafe fn lidt(idt: &DescriptorTablePointer) {
 /// this TSS is safe.
 #[inline]
 pub unsafe fn load_tss(sel: SegmentSelector) {
-    #[cfg(feature = "inline_asm")]
-    asm!("ltr $0" :: "r" (sel.0));
+    #[cfg(not(feature = "external_asm"))]
+    asm!("ltr {0:x}", in(reg) sel.0, options(nostack, preserves_flags));
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     crate::asm::x86_64_asm_ltr(sel.0)
 }
+
+/// Returns the segment selector of the currently loaded task state segment, read using the
+/// `str` instruction.
+#[inline]
+pub fn tr() -> SegmentSelector {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let segment: u16;
+        unsafe { asm!("str {0:x}", out(reg) segment, options(nomem, nostack, preserves_flags)) };
+        SegmentSelector(segment)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let segment: u16 = unsafe { crate::asm::x86_64_asm_str() };
+        SegmentSelector(segment)
+    }
+}
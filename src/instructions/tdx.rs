@@ -0,0 +1,364 @@
+//! Instructions of the trust domain extensions (TDX).
+//!
+//! A trust domain (TD) guest calls the TDX module with `tdcall`, e.g. to request a service of
+//! the VMM with [`TdcallLeaf::VpVmcall`]. The VMM calls the TDX module with `seamcall` to
+//! create and run TDs. Both instructions take the leaf number in RAX and pass the operands of
+//! the leaf in the other general-purpose registers, which are collected in [`TdxRegisters`].
+//! The completion status is returned in RAX.
+
+use super::vmx::VmxError;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+
+/// The leaves of `tdcall`, i.e. the functions of the TDX module available to TD guests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum TdcallLeaf {
+    /// TDG.VP.VMCALL, which passes a request to the VMM.
+    VpVmcall = 0,
+    /// TDG.VP.INFO, which returns the TD execution environment information.
+    VpInfo = 1,
+    /// TDG.MR.RTMR.EXTEND, which extends a runtime measurement register.
+    MrRtmrExtend = 2,
+    /// TDG.VP.VEINFO.GET, which returns the information of the last virtualization exception.
+    VpVeinfoGet = 3,
+    /// TDG.MR.REPORT, which creates a TD report.
+    MrReport = 4,
+    /// TDG.VP.CPUIDVE.SET, which controls whether `cpuid` causes virtualization exceptions.
+    VpCpuidveSet = 5,
+    /// TDG.MEM.PAGE.ACCEPT, which accepts a pending private page.
+    MemPageAccept = 6,
+    /// TDG.VM.RD, which reads a TD metadata field.
+    VmRd = 7,
+    /// TDG.VM.WR, which writes a TD metadata field.
+    VmWr = 8,
+    /// TDG.VP.RD, which reads a metadata field of the virtual processor.
+    VpRd = 9,
+    /// TDG.VP.WR, which writes a metadata field of the virtual processor.
+    VpWr = 10,
+}
+
+/// The leaves of `seamcall`, i.e. the functions of the TDX module available to the VMM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SeamcallLeaf {
+    /// TDH.VP.ENTER, which enters a TD virtual processor.
+    VpEnter = 0,
+    /// TDH.MNG.ADDCX, which adds a control structure page to a TD.
+    MngAddcx = 1,
+    /// TDH.MEM.PAGE.ADD, which adds an initial private page to a TD.
+    MemPageAdd = 2,
+    /// TDH.MEM.SEPT.ADD, which adds a secure EPT page to a TD.
+    MemSeptAdd = 3,
+    /// TDH.VP.ADDCX, which adds a control structure page to a virtual processor.
+    VpAddcx = 4,
+    /// TDH.MEM.PAGE.RELOCATE, which moves a private page to another frame.
+    MemPageRelocate = 5,
+    /// TDH.MEM.PAGE.AUG, which adds a pending private page to a running TD.
+    MemPageAug = 6,
+    /// TDH.MEM.RANGE.BLOCK, which blocks the creation of translations for a range.
+    MemRangeBlock = 7,
+    /// TDH.MNG.KEY.CONFIG, which configures the memory encryption key of a TD.
+    MngKeyConfig = 8,
+    /// TDH.MNG.CREATE, which creates a TD.
+    MngCreate = 9,
+    /// TDH.VP.CREATE, which creates a virtual processor of a TD.
+    VpCreate = 10,
+    /// TDH.MNG.RD, which reads a TD metadata field.
+    MngRd = 11,
+    /// TDH.MR.EXTEND, which measures a chunk of an initial private page.
+    MrExtend = 16,
+    /// TDH.MR.FINALIZE, which finalizes the initial measurement of a TD.
+    MrFinalize = 17,
+    /// TDH.VP.FLUSH, which flushes the cached state of a virtual processor.
+    VpFlush = 18,
+    /// TDH.MNG.VPFLUSHDONE, which checks that all virtual processors were flushed.
+    MngVpflushdone = 19,
+    /// TDH.MNG.KEY.FREEID, which releases the key identifier of a TD.
+    MngKeyFreeid = 20,
+    /// TDH.MNG.INIT, which initializes the configuration of a TD.
+    MngInit = 21,
+    /// TDH.VP.INIT, which initializes a virtual processor.
+    VpInit = 22,
+    /// TDH.PHYMEM.PAGE.RDMD, which reads the metadata of a frame.
+    PhymemPageRdmd = 24,
+    /// TDH.VP.RD, which reads a metadata field of a virtual processor.
+    VpRd = 26,
+    /// TDH.PHYMEM.PAGE.RECLAIM, which reclaims a frame from the TDX module.
+    PhymemPageReclaim = 28,
+    /// TDH.MEM.PAGE.REMOVE, which removes a private page from a TD.
+    MemPageRemove = 29,
+    /// TDH.SYS.KEY.CONFIG, which configures the global memory encryption key on a package.
+    SysKeyConfig = 31,
+    /// TDH.SYS.INIT, which initializes the TDX module.
+    SysInit = 33,
+    /// TDH.SYS.RD, which reads a global metadata field of the TDX module.
+    SysRd = 34,
+    /// TDH.SYS.LP.INIT, which initializes the TDX module on a logical processor.
+    SysLpInit = 35,
+    /// TDH.SYS.TDMR.INIT, which initializes a range of TDX memory.
+    SysTdmrInit = 36,
+    /// TDH.MEM.TRACK, which increments the TLB epoch of a TD.
+    MemTrack = 38,
+    /// TDH.MEM.RANGE.UNBLOCK, which unblocks a range blocked by `MemRangeBlock`.
+    MemRangeUnblock = 39,
+    /// TDH.PHYMEM.CACHE.WB, which writes back the caches of a package.
+    PhymemCacheWb = 40,
+    /// TDH.PHYMEM.PAGE.WBINVD, which writes back and invalidates the cache lines of a frame.
+    PhymemPageWbinvd = 41,
+    /// TDH.VP.WR, which writes a metadata field of a virtual processor.
+    VpWr = 43,
+    /// TDH.SYS.CONFIG, which configures the TDX memory ranges and the global key.
+    SysConfig = 45,
+}
+
+/// The completion status of a `tdcall` or `seamcall` leaf, returned in RAX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct TdxStatus(pub u64);
+
+impl TdxStatus {
+    /// Returns whether the leaf succeeded, possibly with a warning.
+    #[inline]
+    pub const fn is_success(self) -> bool {
+        self.0 & (1 << 63) == 0
+    }
+
+    /// Returns whether the error is non-recoverable, e.g. because the TDX module is in a
+    /// fatal state.
+    #[inline]
+    pub const fn is_fatal(self) -> bool {
+        self.0 & (1 << 62) != 0
+    }
+
+    /// Returns the class and details of the status, without the operand identifier in the
+    /// lower bits.
+    #[inline]
+    pub const fn code(self) -> u64 {
+        self.0 & 0xffff_ffff_0000_0000
+    }
+
+    /// Returns the operand identifier of the status, which tells which operand caused an
+    /// error.
+    #[inline]
+    pub const fn operand(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// The general-purpose registers passed to and returned from `tdcall` and `seamcall`.
+///
+/// RSP and RBP are never used as operands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct TdxRegisters {
+    /// The value of RAX, the leaf number on entry and the completion status on return.
+    pub rax: u64,
+    /// The value of RBX.
+    pub rbx: u64,
+    /// The value of RCX.
+    pub rcx: u64,
+    /// The value of RDX.
+    pub rdx: u64,
+    /// The value of RSI.
+    pub rsi: u64,
+    /// The value of RDI.
+    pub rdi: u64,
+    /// The value of R8.
+    pub r8: u64,
+    /// The value of R9.
+    pub r9: u64,
+    /// The value of R10.
+    pub r10: u64,
+    /// The value of R11.
+    pub r11: u64,
+    /// The value of R12.
+    pub r12: u64,
+    /// The value of R13.
+    pub r13: u64,
+    /// The value of R14.
+    pub r14: u64,
+    /// The value of R15.
+    pub r15: u64,
+}
+
+impl TdxRegisters {
+    /// Creates the registers of a `tdcall` leaf, with all operands zero.
+    #[inline]
+    pub const fn tdcall(leaf: TdcallLeaf) -> Self {
+        Self::with_leaf(leaf as u64)
+    }
+
+    /// Creates the registers of a `seamcall` leaf, with all operands zero.
+    #[inline]
+    pub const fn seamcall(leaf: SeamcallLeaf) -> Self {
+        Self::with_leaf(leaf as u64)
+    }
+
+    /// Creates the registers of a TDG.VP.VMCALL with the given sub-function of the standard
+    /// guest-hypervisor communication interface, e.g. 10 for `cpuid` or 0x10001 for MapGPA,
+    /// and up to four arguments in R12 to R15.
+    ///
+    /// R10 to R15 are passed to the VMM. On return, R10 holds the status of the VMM and R11
+    /// to R15 the outputs of the sub-function.
+    #[inline]
+    pub const fn vp_vmcall(sub_function: u64, args: [u64; 4]) -> Self {
+        let mut regs = Self::tdcall(TdcallLeaf::VpVmcall);
+        // the bitmap of the registers passed to the VMM, bit n is register n in the
+        // encoding order of RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8 to R15
+        regs.rcx = 0xfc00;
+        regs.r11 = sub_function;
+        regs.r12 = args[0];
+        regs.r13 = args[1];
+        regs.r14 = args[2];
+        regs.r15 = args[3];
+        regs
+    }
+
+    /// Returns the completion status in RAX.
+    #[inline]
+    pub const fn status(&self) -> TdxStatus {
+        TdxStatus(self.rax)
+    }
+
+    const fn with_leaf(leaf: u64) -> Self {
+        TdxRegisters {
+            rax: leaf,
+            rbx: 0,
+            rcx: 0,
+            rdx: 0,
+            rsi: 0,
+            rdi: 0,
+            r8: 0,
+            r9: 0,
+            r10: 0,
+            r11: 0,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+        }
+    }
+}
+
+/// Loads the registers from `$regs`, executes the instruction given as bytes and stores the
+/// registers back, evaluating to the carry flag after the instruction.
+#[cfg(not(feature = "external_asm"))]
+macro_rules! tdx_call {
+    ($instruction:literal, $regs:expr) => {{
+        let carry: u64;
+        asm!(
+            // LLVM reserves rbx, and rax holds the pointer to the registers until the call
+            "push rbx",
+            "push rax",
+            "mov rbx, [rax + 8]",
+            "mov rcx, [rax + 16]",
+            "mov rdx, [rax + 24]",
+            "mov rsi, [rax + 32]",
+            "mov rdi, [rax + 40]",
+            "mov r8, [rax + 48]",
+            "mov r9, [rax + 56]",
+            "mov r10, [rax + 64]",
+            "mov r11, [rax + 72]",
+            "mov r12, [rax + 80]",
+            "mov r13, [rax + 88]",
+            "mov r14, [rax + 96]",
+            "mov r15, [rax + 104]",
+            "mov rax, [rax]",
+            $instruction,
+            // none of the following instructions modify the flags
+            "xchg rax, [rsp]",
+            "mov [rax + 8], rbx",
+            "mov [rax + 16], rcx",
+            "mov [rax + 24], rdx",
+            "mov [rax + 32], rsi",
+            "mov [rax + 40], rdi",
+            "mov [rax + 48], r8",
+            "mov [rax + 56], r9",
+            "mov [rax + 64], r10",
+            "mov [rax + 72], r11",
+            "mov [rax + 80], r12",
+            "mov [rax + 88], r13",
+            "mov [rax + 96], r14",
+            "mov [rax + 104], r15",
+            "pop rcx",
+            "mov [rax], rcx",
+            "pop rbx",
+            "setc cl",
+            "movzx ecx, cl",
+            inout("rax") $regs as *mut TdxRegisters => _,
+            out("rcx") carry,
+            out("rdx") _, out("rsi") _, out("rdi") _,
+            out("r8") _, out("r9") _, out("r10") _, out("r11") _,
+            out("r12") _, out("r13") _, out("r14") _, out("r15") _,
+        );
+        carry as u8
+    }};
+}
+
+/// Calls the TDX module from a TD guest with the leaf and operands in the given registers,
+/// which are overwritten with the outputs of the leaf.
+///
+/// ## Safety
+///
+/// This function is unsafe because the effects of a leaf depend on its operands, e.g. the
+/// memory that the TDX module or the VMM write to, and because it causes an invalid opcode
+/// exception outside of a TD guest.
+#[inline]
+pub unsafe fn tdcall(regs: &mut TdxRegisters) -> TdxStatus {
+    #[cfg(not(feature = "external_asm"))]
+    tdx_call!(".byte 0x66, 0x0f, 0x01, 0xcc", regs);
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_tdcall(regs);
+
+    regs.status()
+}
+
+/// Calls the TDX module from the VMM with the leaf and operands in the given registers, which
+/// are overwritten with the outputs of the leaf.
+///
+/// Returns [`VmxError::Invalid`] if no TDX module is loaded, and the completion status of the
+/// leaf otherwise.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation, and because the
+/// effects of a leaf depend on its operands, e.g. the frames that are handed to the TDX
+/// module.
+#[inline]
+pub unsafe fn seamcall(regs: &mut TdxRegisters) -> Result<TdxStatus, VmxError> {
+    #[cfg(not(feature = "external_asm"))]
+    let invalid = tdx_call!(".byte 0x66, 0x0f, 0x01, 0xcf", regs);
+
+    #[cfg(feature = "external_asm")]
+    let invalid = crate::asm::x86_64_asm_seamcall(regs);
+
+    match invalid {
+        0 => Ok(regs.status()),
+        _ => Err(VmxError::Invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vp_vmcall_registers() {
+        // MapGPA of 16 pages at 4GiB
+        let regs = TdxRegisters::vp_vmcall(0x10001, [0x1_0000_0000, 0x10000, 0, 0]);
+        assert_eq!((regs.rax, regs.rcx, regs.r10), (0, 0xfc00, 0));
+        assert_eq!(
+            (regs.r11, regs.r12, regs.r13),
+            (0x10001, 0x1_0000_0000, 0x10000)
+        );
+
+        let status = TdxStatus(0xc000_0100_0000_0002);
+        assert!(!status.is_success() && status.is_fatal());
+        assert_eq!(
+            (status.code(), status.operand()),
+            (0xc000_0100_0000_0000, 2)
+        );
+    }
+}
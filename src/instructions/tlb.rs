@@ -1,16 +1,18 @@
 //! Functions to flush the translation lookaside buffer (TLB).
 
 use crate::VirtAddr;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
 
 /// Invalidate the given address in the TLB using the `invlpg` instruction.
 #[inline]
 pub fn flush(addr: VirtAddr) {
-    #[cfg(feature = "inline_asm")]
+    #[cfg(not(feature = "external_asm"))]
     unsafe {
-        asm!("invlpg ($0)" :: "r" (addr.as_u64()) : "memory")
-    };
+        asm!("invlpg [{}]", in(reg) addr.as_u64() as usize, options(nostack, preserves_flags));
+    }
 
-    #[cfg(not(feature = "inline_asm"))]
+    #[cfg(feature = "external_asm")]
     unsafe {
         crate::asm::x86_64_asm_invlpg(addr.as_u64())
     };
@@ -0,0 +1,962 @@
+//! Instructions of the virtual machine extensions (VMX).
+//!
+//! VMX instructions report failures through RFLAGS: `VMfailInvalid` (CF set) if there is no
+//! current VMCS, and `VMfailValid` (ZF set) if there is one, in which case the reason is stored
+//! in the VM-instruction error field of the current VMCS. The wrappers of this module return
+//! both as [`VmxError`], reading and decoding the VM-instruction error field automatically.
+
+use crate::addr::PhysAddr;
+use crate::structures::vmcs::VmcsField;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::fmt;
+use core::ops::{Index, IndexMut};
+
+/// The error returned by a failed VMX instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmxError {
+    /// The instruction failed without a current VMCS (`VMfailInvalid`).
+    Invalid,
+    /// The instruction failed with a current VMCS (`VMfailValid`). Contains the decoded
+    /// VM-instruction error field.
+    Valid(VmInstructionError),
+}
+
+impl fmt::Display for VmxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmxError::Invalid => f.write_str("VMfailInvalid"),
+            VmxError::Valid(error) => write!(f, "VMfailValid: {}", error),
+        }
+    }
+}
+
+/// The reason of a `VMfailValid`, stored in the VM-instruction error field.
+///
+/// See section 30.4 "VM Instruction Error Numbers" of the Intel SDM, volume 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmInstructionError {
+    /// VMCALL executed in VMX root operation.
+    VmcallInRootOperation,
+    /// VMCLEAR with an invalid physical address.
+    VmclearInvalidAddress,
+    /// VMCLEAR with the VMXON pointer.
+    VmclearVmxonPointer,
+    /// VMLAUNCH with a non-clear VMCS.
+    VmlaunchNonClearVmcs,
+    /// VMRESUME with a non-launched VMCS.
+    VmresumeNonLaunchedVmcs,
+    /// VMRESUME after VMXOFF (VMXOFF and VMXON between VMLAUNCH and VMRESUME).
+    VmresumeAfterVmxoff,
+    /// VM entry with invalid control fields.
+    EntryInvalidControlFields,
+    /// VM entry with invalid host-state fields.
+    EntryInvalidHostStateFields,
+    /// VMPTRLD with an invalid physical address.
+    VmptrldInvalidAddress,
+    /// VMPTRLD with the VMXON pointer.
+    VmptrldVmxonPointer,
+    /// VMPTRLD with an incorrect VMCS revision identifier.
+    VmptrldIncorrectRevision,
+    /// VMREAD or VMWRITE from or to an unsupported VMCS component.
+    UnsupportedVmcsComponent,
+    /// VMWRITE to a read-only VMCS component.
+    VmwriteReadOnlyComponent,
+    /// VMXON executed in VMX root operation.
+    VmxonInRootOperation,
+    /// VM entry with an invalid executive-VMCS pointer.
+    EntryInvalidExecutiveVmcsPointer,
+    /// VM entry with a non-launched executive VMCS.
+    EntryNonLaunchedExecutiveVmcs,
+    /// VM entry with an executive-VMCS pointer that is not the VMXON pointer, when attempting
+    /// to deactivate the dual-monitor treatment of SMIs and SMM.
+    EntryExecutiveVmcsPointerNotVmxonPointer,
+    /// VMCALL with a non-clear VMCS, when attempting to activate the dual-monitor treatment of
+    /// SMIs and SMM.
+    VmcallNonClearVmcs,
+    /// VMCALL with invalid VM-exit control fields.
+    VmcallInvalidExitControlFields,
+    /// VMCALL with an incorrect MSEG revision identifier.
+    VmcallIncorrectMsegRevision,
+    /// VMXOFF under the dual-monitor treatment of SMIs and SMM.
+    VmxoffUnderDualMonitor,
+    /// VMCALL with invalid SMM-monitor features.
+    VmcallInvalidSmmMonitorFeatures,
+    /// VM entry with invalid VM-execution control fields in the executive VMCS.
+    EntryInvalidExecutiveControlFields,
+    /// VM entry with events blocked by MOV SS.
+    EntryEventsBlockedByMovSs,
+    /// Invalid operand to INVEPT or INVVPID.
+    InvalidInveptInvvpidOperand,
+    /// An error number that is not defined by the SDM.
+    Unknown(u32),
+}
+
+impl VmInstructionError {
+    /// Decodes the value of the VM-instruction error field.
+    pub fn from_raw(raw: u32) -> VmInstructionError {
+        use self::VmInstructionError::*;
+
+        match raw {
+            1 => VmcallInRootOperation,
+            2 => VmclearInvalidAddress,
+            3 => VmclearVmxonPointer,
+            4 => VmlaunchNonClearVmcs,
+            5 => VmresumeNonLaunchedVmcs,
+            6 => VmresumeAfterVmxoff,
+            7 => EntryInvalidControlFields,
+            8 => EntryInvalidHostStateFields,
+            9 => VmptrldInvalidAddress,
+            10 => VmptrldVmxonPointer,
+            11 => VmptrldIncorrectRevision,
+            12 => UnsupportedVmcsComponent,
+            13 => VmwriteReadOnlyComponent,
+            15 => VmxonInRootOperation,
+            16 => EntryInvalidExecutiveVmcsPointer,
+            17 => EntryNonLaunchedExecutiveVmcs,
+            18 => EntryExecutiveVmcsPointerNotVmxonPointer,
+            19 => VmcallNonClearVmcs,
+            20 => VmcallInvalidExitControlFields,
+            22 => VmcallIncorrectMsegRevision,
+            23 => VmxoffUnderDualMonitor,
+            24 => VmcallInvalidSmmMonitorFeatures,
+            25 => EntryInvalidExecutiveControlFields,
+            26 => EntryEventsBlockedByMovSs,
+            28 => InvalidInveptInvvpidOperand,
+            other => Unknown(other),
+        }
+    }
+
+    /// Returns the error number of the VM-instruction error field.
+    pub fn as_raw(self) -> u32 {
+        use self::VmInstructionError::*;
+
+        match self {
+            VmcallInRootOperation => 1,
+            VmclearInvalidAddress => 2,
+            VmclearVmxonPointer => 3,
+            VmlaunchNonClearVmcs => 4,
+            VmresumeNonLaunchedVmcs => 5,
+            VmresumeAfterVmxoff => 6,
+            EntryInvalidControlFields => 7,
+            EntryInvalidHostStateFields => 8,
+            VmptrldInvalidAddress => 9,
+            VmptrldVmxonPointer => 10,
+            VmptrldIncorrectRevision => 11,
+            UnsupportedVmcsComponent => 12,
+            VmwriteReadOnlyComponent => 13,
+            VmxonInRootOperation => 15,
+            EntryInvalidExecutiveVmcsPointer => 16,
+            EntryNonLaunchedExecutiveVmcs => 17,
+            EntryExecutiveVmcsPointerNotVmxonPointer => 18,
+            VmcallNonClearVmcs => 19,
+            VmcallInvalidExitControlFields => 20,
+            VmcallIncorrectMsegRevision => 22,
+            VmxoffUnderDualMonitor => 23,
+            VmcallInvalidSmmMonitorFeatures => 24,
+            EntryInvalidExecutiveControlFields => 25,
+            EntryEventsBlockedByMovSs => 26,
+            InvalidInveptInvvpidOperand => 28,
+            Unknown(raw) => raw,
+        }
+    }
+}
+
+impl fmt::Display for VmInstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VmInstructionError::*;
+
+        let description = match self {
+            VmcallInRootOperation => "VMCALL executed in VMX root operation",
+            VmclearInvalidAddress => "VMCLEAR with invalid physical address",
+            VmclearVmxonPointer => "VMCLEAR with VMXON pointer",
+            VmlaunchNonClearVmcs => "VMLAUNCH with non-clear VMCS",
+            VmresumeNonLaunchedVmcs => "VMRESUME with non-launched VMCS",
+            VmresumeAfterVmxoff => "VMRESUME after VMXOFF",
+            EntryInvalidControlFields => "VM entry with invalid control field(s)",
+            EntryInvalidHostStateFields => "VM entry with invalid host-state field(s)",
+            VmptrldInvalidAddress => "VMPTRLD with invalid physical address",
+            VmptrldVmxonPointer => "VMPTRLD with VMXON pointer",
+            VmptrldIncorrectRevision => "VMPTRLD with incorrect VMCS revision identifier",
+            UnsupportedVmcsComponent => "VMREAD/VMWRITE from/to unsupported VMCS component",
+            VmwriteReadOnlyComponent => "VMWRITE to read-only VMCS component",
+            VmxonInRootOperation => "VMXON executed in VMX root operation",
+            EntryInvalidExecutiveVmcsPointer => "VM entry with invalid executive-VMCS pointer",
+            EntryNonLaunchedExecutiveVmcs => "VM entry with non-launched executive VMCS",
+            EntryExecutiveVmcsPointerNotVmxonPointer => {
+                "VM entry with executive-VMCS pointer not VMXON pointer"
+            }
+            VmcallNonClearVmcs => "VMCALL with non-clear VMCS",
+            VmcallInvalidExitControlFields => "VMCALL with invalid VM-exit control fields",
+            VmcallIncorrectMsegRevision => "VMCALL with incorrect MSEG revision identifier",
+            VmxoffUnderDualMonitor => "VMXOFF under dual-monitor treatment of SMIs and SMM",
+            VmcallInvalidSmmMonitorFeatures => "VMCALL with invalid SMM-monitor features",
+            EntryInvalidExecutiveControlFields => {
+                "VM entry with invalid VM-execution control fields in executive VMCS"
+            }
+            EntryEventsBlockedByMovSs => "VM entry with events blocked by MOV SS",
+            InvalidInveptInvvpidOperand => "invalid operand to INVEPT/INVVPID",
+            Unknown(raw) => return write!(f, "unknown VM-instruction error {}", raw),
+        };
+        write!(f, "{} ({})", description, self.as_raw())
+    }
+}
+
+/// Converts the flags set by a VMX instruction to a result, reading the VM-instruction error
+/// field on `VMfailValid`.
+#[inline]
+fn vmx_result(fail_invalid: bool, fail_valid: bool) -> Result<(), VmxError> {
+    if fail_invalid {
+        Err(VmxError::Invalid)
+    } else if fail_valid {
+        // the error field is always readable when there is a current VMCS
+        let (raw, _, _) = unsafe { vmread_raw(VmcsField::VmInstructionError.encoding()) };
+        Err(VmxError::Valid(VmInstructionError::from_raw(raw as u32)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Converts the status returned by the external assembly functions to a result.
+#[cfg(feature = "external_asm")]
+#[inline]
+fn vmx_status(status: u8) -> Result<(), VmxError> {
+    vmx_result(status & 1 != 0, status & 2 != 0)
+}
+
+/// Enters VMX root operation, using the given VMXON region.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that VMX is enabled in CR4 and the
+/// feature control MSR, and that the region is a valid, 4KiB aligned VMXON region that stays
+/// reserved until VMX operation is left.
+#[inline]
+pub unsafe fn vmxon(region: PhysAddr) -> Result<(), VmxError> {
+    let addr = region.as_u64();
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmxon [{}]", "setc {}", "setz {}",
+            in(reg) &addr, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmxon(&addr))
+}
+
+/// Leaves VMX operation.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that no virtual machine still relies
+/// on VMX operation.
+#[inline]
+pub unsafe fn vmxoff() -> Result<(), VmxError> {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmxoff", "setc {}", "setz {}",
+            out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmxoff())
+}
+
+/// Clears the given VMCS, writing its cached state back to memory and marking it as clear.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the address points to a valid
+/// VMCS region.
+#[inline]
+pub unsafe fn vmclear(vmcs: PhysAddr) -> Result<(), VmxError> {
+    let addr = vmcs.as_u64();
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmclear [{}]", "setc {}", "setz {}",
+            in(reg) &addr, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmclear(&addr))
+}
+
+/// Makes the given VMCS the current VMCS.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the address points to a valid
+/// VMCS region with the correct revision identifier.
+#[inline]
+pub unsafe fn vmptrld(vmcs: PhysAddr) -> Result<(), VmxError> {
+    let addr = vmcs.as_u64();
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmptrld [{}]", "setc {}", "setz {}",
+            in(reg) &addr, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmptrld(&addr))
+}
+
+/// Returns the physical address of the current VMCS, or `None` if there is no current VMCS.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation.
+#[inline]
+pub unsafe fn vmptrst() -> Result<Option<PhysAddr>, VmxError> {
+    let mut addr: u64 = 0;
+
+    #[cfg(not(feature = "external_asm"))]
+    let result = {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmptrst [{}]", "setc {}", "setz {}",
+            in(reg) &mut addr, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    };
+
+    #[cfg(feature = "external_asm")]
+    let result = vmx_status(crate::asm::x86_64_asm_vmptrst(&mut addr));
+
+    result?;
+    // the processor stores `0xffff_ffff_ffff_ffff` without a current VMCS
+    Ok(PhysAddr::try_new(addr).ok())
+}
+
+/// Reads the given field of the current VMCS.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation, or in VMX
+/// non-root operation with VMCS shadowing.
+#[inline]
+pub unsafe fn vmread(field: VmcsField) -> Result<u64, VmxError> {
+    let (value, invalid, valid) = vmread_raw(field.encoding());
+    vmx_result(invalid, valid).map(|()| value)
+}
+
+/// Executes `vmread` and returns the value together with the `VMfailInvalid` and
+/// `VMfailValid` conditions.
+#[inline]
+unsafe fn vmread_raw(field: u32) -> (u64, bool, bool) {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let value: u64;
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmread {}, {}", "setc {}", "setz {}",
+            out(reg) value, in(reg) u64::from(field), out(reg_byte) invalid, out(reg_byte) valid,
+            options(nomem, nostack),
+        );
+        (value, invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    {
+        let mut value = 0;
+        let status = crate::asm::x86_64_asm_vmread(u64::from(field), &mut value);
+        (value, status & 1 != 0, status & 2 != 0)
+    }
+}
+
+/// Writes the given field of the current VMCS.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the written value doesn't break
+/// memory safety of the host, e.g. through the host-state fields.
+#[inline]
+pub unsafe fn vmwrite(field: VmcsField, value: u64) -> Result<(), VmxError> {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmwrite {}, {}", "setc {}", "setz {}",
+            in(reg) u64::from(field.encoding()), in(reg) value,
+            out(reg_byte) invalid, out(reg_byte) valid,
+            options(nomem, nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmwrite(
+        u64::from(field.encoding()),
+        value,
+    ))
+}
+
+/// Launches the guest of the current VMCS, which must be clear.
+///
+/// On success, the processor enters VMX non-root operation and this function doesn't return.
+/// A later VM exit continues at the host RIP of the VMCS instead. This function only returns
+/// if the instruction fails, with the reason of the failure. Note that a VM entry that fails
+/// while checking or loading the guest state is reported as a VM exit instead.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the host-state fields of the
+/// VMCS describe a valid host context to return to.
+#[inline]
+pub unsafe fn vmlaunch() -> VmxError {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmlaunch", "setc {}", "setz {}",
+            out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        entry_failure(vmx_result(invalid != 0, valid != 0))
+    }
+
+    #[cfg(feature = "external_asm")]
+    entry_failure(vmx_status(crate::asm::x86_64_asm_vmlaunch()))
+}
+
+/// Resumes the guest of the current VMCS, which must have been launched.
+///
+/// Like [`vmlaunch`], this function only returns if the instruction fails.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the host-state fields of the
+/// VMCS describe a valid host context to return to.
+#[inline]
+pub unsafe fn vmresume() -> VmxError {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "vmresume", "setc {}", "setz {}",
+            out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        entry_failure(vmx_result(invalid != 0, valid != 0))
+    }
+
+    #[cfg(feature = "external_asm")]
+    entry_failure(vmx_status(crate::asm::x86_64_asm_vmresume()))
+}
+
+/// The registers passed to and returned from a hypercall.
+///
+/// Hypervisors define their own calling conventions, so all registers that are commonly used
+/// for hypercalls are passed to the hypervisor and read back after it returns. For example,
+/// KVM expects the hypercall number in RAX and the arguments in RBX, RCX, RDX and RSI, and
+/// returns the result in RAX.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct HypercallRegisters {
+    /// The value of RAX, usually the hypercall number on entry and the result on return.
+    pub rax: u64,
+    /// The value of RBX.
+    pub rbx: u64,
+    /// The value of RCX.
+    pub rcx: u64,
+    /// The value of RDX.
+    pub rdx: u64,
+    /// The value of RSI.
+    pub rsi: u64,
+}
+
+impl HypercallRegisters {
+    /// Creates the registers of a hypercall with the given number in RAX and up to four
+    /// arguments in RBX, RCX, RDX and RSI.
+    #[inline]
+    pub const fn new(number: u64, args: [u64; 4]) -> Self {
+        HypercallRegisters {
+            rax: number,
+            rbx: args[0],
+            rcx: args[1],
+            rdx: args[2],
+            rsi: args[3],
+        }
+    }
+}
+
+/// Calls the hypervisor from a guest, passing the given registers and returning their values
+/// after the hypervisor resumes the guest.
+///
+/// ## Safety
+///
+/// This function is unsafe because the effects of a hypercall are defined by the hypervisor,
+/// and it causes an invalid opcode exception outside of VMX non-root operation.
+#[inline]
+pub unsafe fn vmcall(mut regs: HypercallRegisters) -> HypercallRegisters {
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        // LLVM reserves rbx, so it is swapped with a scratch register around the call
+        "xchg {rbx}, rbx",
+        "vmcall",
+        "xchg {rbx}, rbx",
+        rbx = inout(reg) regs.rbx,
+        inout("rax") regs.rax,
+        inout("rcx") regs.rcx,
+        inout("rdx") regs.rdx,
+        inout("rsi") regs.rsi,
+        options(nostack),
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmcall(&mut regs);
+
+    regs
+}
+
+/// The general-purpose registers of a guest, which aren't part of the VMCS.
+///
+/// RSP is part of the guest-state area of the VMCS instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GuestRegisters {
+    /// The value of RAX.
+    pub rax: u64,
+    /// The value of RBX.
+    pub rbx: u64,
+    /// The value of RCX.
+    pub rcx: u64,
+    /// The value of RDX.
+    pub rdx: u64,
+    /// The value of RSI.
+    pub rsi: u64,
+    /// The value of RDI.
+    pub rdi: u64,
+    /// The value of RBP.
+    pub rbp: u64,
+    /// The value of R8.
+    pub r8: u64,
+    /// The value of R9.
+    pub r9: u64,
+    /// The value of R10.
+    pub r10: u64,
+    /// The value of R11.
+    pub r11: u64,
+    /// The value of R12.
+    pub r12: u64,
+    /// The value of R13.
+    pub r13: u64,
+    /// The value of R14.
+    pub r14: u64,
+    /// The value of R15.
+    pub r15: u64,
+}
+
+/// Overwrites all registers with zeros, e.g. before the state of a destroyed guest is freed.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for GuestRegisters {
+    fn zeroize(&mut self) {
+        let GuestRegisters {
+            rax,
+            rbx,
+            rcx,
+            rdx,
+            rsi,
+            rdi,
+            rbp,
+            r8,
+            r9,
+            r10,
+            r11,
+            r12,
+            r13,
+            r14,
+            r15,
+        } = self;
+        for register in [
+            rax, rbx, rcx, rdx, rsi, rdi, rbp, r8, r9, r10, r11, r12, r13, r14, r15,
+        ] {
+            register.zeroize();
+        }
+    }
+}
+
+/// Enters the guest of the current VMCS with `vmlaunch` if `launched` is false and with
+/// `vmresume` otherwise, and returns on the next VM exit.
+///
+/// The general-purpose registers of the guest are loaded from `regs` and stored back on the
+/// VM exit. The host RSP and RIP fields of the VMCS are written with the return context of
+/// this function, all other host-state fields must be valid already. Returns an error if the
+/// VM entry instruction fails.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the VMCS describes a valid
+/// guest, whose execution can't break the memory safety of the host, and that `launched`
+/// matches the launch state of the VMCS.
+///
+/// Only the general-purpose registers are switched. The x87, SSE and AVX state, including
+/// the control words in MXCSR and the x87 FCW, stays loaded across the VM entry and exit, so
+/// the guest sees the extended state of the host and can modify it. The caller must switch
+/// this state itself, e.g. with `xsave` and `xrstor` in the same assembly block as the VM
+/// entry, or ensure that neither the host nor the guest depend on it.
+#[inline]
+pub unsafe fn vmentry(regs: &mut GuestRegisters, launched: bool) -> Result<(), VmxError> {
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let status: u64;
+        asm!(
+            // save the callee-saved registers that LLVM doesn't allow as operands
+            "push rbp",
+            "push rbx",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            "push rdi",
+            // write the host RSP and RIP
+            "mov rax, 0x6c14",
+            "vmwrite rax, rsp",
+            "lea rcx, [rip + 2f]",
+            "mov rax, 0x6c16",
+            "vmwrite rax, rcx",
+            // mov doesn't change the flags, so the test decides below
+            "test sil, sil",
+            "mov rax, [rdi + 0]",
+            "mov rbx, [rdi + 8]",
+            "mov rcx, [rdi + 16]",
+            "mov rdx, [rdi + 24]",
+            "mov rsi, [rdi + 32]",
+            "mov rbp, [rdi + 48]",
+            "mov r8, [rdi + 56]",
+            "mov r9, [rdi + 64]",
+            "mov r10, [rdi + 72]",
+            "mov r11, [rdi + 80]",
+            "mov r12, [rdi + 88]",
+            "mov r13, [rdi + 96]",
+            "mov r14, [rdi + 104]",
+            "mov r15, [rdi + 112]",
+            "mov rdi, [rdi + 40]",
+            "jnz 3f",
+            "vmlaunch",
+            "jmp 4f",
+            "3:",
+            "vmresume",
+            // the VM entry instruction failed
+            "4:",
+            "setc al",
+            "setz cl",
+            "add cl, cl",
+            "or al, cl",
+            "movzx eax, al",
+            "add rsp, 8",
+            "jmp 5f",
+            // the VM exit continues here, with the stack pointer written above
+            "2:",
+            "push rdi",
+            "mov rdi, [rsp + 8]",
+            "mov [rdi + 0], rax",
+            "mov [rdi + 8], rbx",
+            "mov [rdi + 16], rcx",
+            "mov [rdi + 24], rdx",
+            "mov [rdi + 32], rsi",
+            "mov [rdi + 48], rbp",
+            "mov [rdi + 56], r8",
+            "mov [rdi + 64], r9",
+            "mov [rdi + 72], r10",
+            "mov [rdi + 80], r11",
+            "mov [rdi + 88], r12",
+            "mov [rdi + 96], r13",
+            "mov [rdi + 104], r14",
+            "mov [rdi + 112], r15",
+            "pop qword ptr [rdi + 40]",
+            "add rsp, 8",
+            "xor eax, eax",
+            "5:",
+            "pop r15",
+            "pop r14",
+            "pop r13",
+            "pop r12",
+            "pop rbx",
+            "pop rbp",
+            inout("rdi") regs as *mut GuestRegisters => _,
+            inout("rsi") u64::from(launched) => _,
+            out("rax") status,
+            clobber_abi("C"),
+        );
+        vmx_result(status & 1 != 0, status & 2 != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_vmentry(regs, launched))
+}
+
+/// The number of EPT pointers in an [`EptpList`].
+pub const EPTP_LIST_ENTRIES: usize = 512;
+
+/// The EPTP list used by EPTP switching, referenced by the EPTP-list address field of the VMCS.
+///
+/// Entry `i` holds the EPT pointer that is loaded by `eptp_switch(i)`.
+#[derive(Clone)]
+#[repr(align(4096))]
+#[repr(C)]
+pub struct EptpList {
+    entries: [u64; EPTP_LIST_ENTRIES],
+}
+
+impl EptpList {
+    /// Creates an EPTP list with all entries zero.
+    #[inline]
+    pub const fn new() -> Self {
+        EptpList {
+            entries: [0; EPTP_LIST_ENTRIES],
+        }
+    }
+
+    /// Returns an iterator over the EPT pointers of the list.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &u64> {
+        self.entries.iter()
+    }
+}
+
+impl Default for EptpList {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for EptpList {
+    type Output = u64;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for EptpList {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}
+
+impl fmt::Debug for EptpList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.entries[..].fmt(f)
+    }
+}
+
+/// Invokes the given VM function from a guest, with the given argument in ECX.
+///
+/// ## Safety
+///
+/// This function is unsafe because VM functions change the state of the guest, e.g. its view
+/// of memory. Invoking a VM function that isn't enabled in the VMCS causes a VM exit.
+#[inline]
+pub unsafe fn vmfunc(leaf: u32, arg: u64) {
+    #[cfg(not(feature = "external_asm"))]
+    asm!("vmfunc", in("eax") leaf, in("rcx") arg, options(nostack));
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmfunc(leaf, arg);
+}
+
+/// Switches to the EPT pointer at the given index of the EPTP list, using VM function 0.
+///
+/// Panics if the index is >=512.
+///
+/// ## Safety
+///
+/// This function is unsafe because the new EPT paging structures may map the memory of the
+/// guest differently.
+#[inline]
+pub unsafe fn eptp_switch(index: u16) {
+    assert!(usize::from(index) < EPTP_LIST_ENTRIES);
+    vmfunc(0, u64::from(index));
+}
+
+/// The type of an `invept` invalidation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum InveptType {
+    /// Invalidates the mappings derived from the given EPT pointer.
+    SingleContext = 1,
+    /// Invalidates the mappings derived from all EPT pointers.
+    AllContext = 2,
+}
+
+/// The type of an `invvpid` invalidation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum InvvpidType {
+    /// Invalidates the mappings of the given linear address for the given VPID.
+    IndividualAddress = 0,
+    /// Invalidates the mappings of the given VPID.
+    SingleContext = 1,
+    /// Invalidates the mappings of all VPIDs except 0.
+    AllContext = 2,
+    /// Invalidates the mappings of the given VPID, except global translations.
+    SingleContextRetainingGlobals = 3,
+}
+
+/// Invalidates cached guest-physical and combined mappings derived from EPT.
+///
+/// The EPT pointer is ignored for all-context invalidations.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation and the type must
+/// be supported, as reported by `IA32_VMX_EPT_VPID_CAP`.
+#[inline]
+pub unsafe fn invept(kind: InveptType, eptp: u64) -> Result<(), VmxError> {
+    let descriptor = [eptp, 0u64];
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "invept {}, [{}]", "setc {}", "setz {}",
+            in(reg) kind as u64, in(reg) &descriptor, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_invept(kind as u64, &descriptor))
+}
+
+/// Invalidates cached linear and combined mappings tagged with a VPID.
+///
+/// The address is only used for individual-address invalidations, and the VPID is ignored for
+/// all-context invalidations.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation and the type must
+/// be supported, as reported by `IA32_VMX_EPT_VPID_CAP`.
+#[inline]
+pub unsafe fn invvpid(kind: InvvpidType, vpid: u16, addr: u64) -> Result<(), VmxError> {
+    let descriptor = [u64::from(vpid), addr];
+
+    #[cfg(not(feature = "external_asm"))]
+    {
+        let (invalid, valid): (u8, u8);
+        asm!(
+            "invvpid {}, [{}]", "setc {}", "setz {}",
+            in(reg) kind as u64, in(reg) &descriptor, out(reg_byte) invalid, out(reg_byte) valid,
+            options(nostack),
+        );
+        vmx_result(invalid != 0, valid != 0)
+    }
+
+    #[cfg(feature = "external_asm")]
+    vmx_status(crate::asm::x86_64_asm_invvpid(kind as u64, &descriptor))
+}
+
+/// Returns the failure of a VM entry instruction that fell through.
+#[inline]
+fn entry_failure(result: Result<(), VmxError>) -> VmxError {
+    // falling through without a failure flag doesn't happen, so treat it like a missing VMCS
+    result.err().unwrap_or(VmxError::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instruction_errors() {
+        for raw in 0..32 {
+            assert_eq!(VmInstructionError::from_raw(raw).as_raw(), raw);
+        }
+        assert_eq!(
+            VmInstructionError::from_raw(14),
+            VmInstructionError::Unknown(14)
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                VmxError::Valid(VmInstructionError::VmptrldIncorrectRevision)
+            ),
+            "VMfailValid: VMPTRLD with incorrect VMCS revision identifier (11)"
+        );
+    }
+
+    #[test]
+    fn entry_failures() {
+        // `VMfailInvalid` is reported without reading the error field of a VMCS
+        assert_eq!(vmx_result(false, false), Ok(()));
+        assert_eq!(entry_failure(vmx_result(true, false)), VmxError::Invalid);
+        assert_eq!(entry_failure(Ok(())), VmxError::Invalid);
+        #[cfg(feature = "external_asm")]
+        {
+            assert_eq!(vmx_status(0), Ok(()));
+            assert_eq!(vmx_status(1), Err(VmxError::Invalid));
+        }
+    }
+
+    #[test]
+    fn hypercall_registers() {
+        let regs = HypercallRegisters::new(9, [1, 2, 3, 4]);
+        assert_eq!((regs.rax, regs.rbx, regs.rsi), (9, 1, 4));
+        // the external assembly accesses the registers at fixed offsets
+        assert_eq!(core::mem::size_of::<HypercallRegisters>(), 40);
+    }
+
+    #[test]
+    fn guest_registers_layout() {
+        // the VM entry assembly accesses the registers at fixed offsets
+        let regs = GuestRegisters::default();
+        let base = &regs as *const _ as usize;
+        let offset = |field: &u64| field as *const _ as usize - base;
+        assert_eq!(offset(&regs.rax), 0);
+        assert_eq!(offset(&regs.rsi), 32);
+        assert_eq!(offset(&regs.rdi), 40);
+        assert_eq!(offset(&regs.rbp), 48);
+        assert_eq!(offset(&regs.r8), 56);
+        assert_eq!(offset(&regs.r15), 112);
+        assert_eq!(core::mem::size_of::<GuestRegisters>(), 120);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_guest_registers() {
+        use zeroize::Zeroize;
+
+        let mut regs = GuestRegisters {
+            rax: 1,
+            r15: 2,
+            ..Default::default()
+        };
+        regs.zeroize();
+        assert_eq!(regs, GuestRegisters::default());
+    }
+
+    #[test]
+    fn eptp_list() {
+        assert_eq!(core::mem::size_of::<EptpList>(), 4096);
+        assert_eq!(core::mem::align_of::<EptpList>(), 4096);
+    }
+}
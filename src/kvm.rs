@@ -0,0 +1,346 @@
+//! The paravirtual interface of KVM for guests.
+//!
+//! A guest checks that it runs under KVM with [`Kvm::detect`], which also reads the
+//! [`KvmFeatures`] of the hypervisor and selects the hypercall instruction of the processor
+//! vendor for [`Kvm::hypercall`].
+//!
+//! The kvmclock is a [`PvclockVcpuTimeInfo`] per processor, whose guest-physical address is
+//! written to [`KvmSystemTimeNew`](crate::registers::model_specific::KvmSystemTimeNew). The
+//! hypervisor keeps it updated with the parameters to convert the TSC of the processor to the
+//! nanoseconds since boot. The wall clock time of boot is written to a [`PvclockWallClock`]
+//! on each write to [`KvmWallClockNew`](crate::registers::model_specific::KvmWallClockNew).
+
+use crate::cpuid::hypervisor::{HypervisorInfo, HypervisorVendor};
+use crate::cpuid::{cpuid, CpuidResult};
+use crate::instructions::svm;
+use crate::instructions::vmx::{self, HypercallRegisters};
+use crate::time::read_tsc;
+use bitflags::bitflags;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use core::time::Duration;
+
+/// The hypercall that checks for pending interrupts of the virtual APIC.
+pub const HC_VAPIC_POLL_IRQ: u64 = 1;
+/// The hypercall that wakes up a processor halted in a paravirtual spinlock.
+pub const HC_KICK_CPU: u64 = 5;
+/// The hypercall that reads the host clock and the TSC at the same time.
+pub const HC_CLOCK_PAIRING: u64 = 9;
+/// The hypercall that sends an IPI to multiple processors.
+pub const HC_SEND_IPI: u64 = 10;
+/// The hypercall that yields to the processor holding a lock.
+pub const HC_SCHED_YIELD: u64 = 11;
+/// The hypercall that changes the attributes of a range of guest-physical memory.
+pub const HC_MAP_GPA_RANGE: u64 = 12;
+
+bitflags! {
+    /// The paravirtual features of KVM, reported by CPUID leaf 0x4000_0001.
+    pub struct KvmFeatures: u32 {
+        /// The kvmclock registers at the legacy MSRs 0x11 and 0x12.
+        const CLOCKSOURCE = 1;
+        /// Port 0x80 delays aren't needed.
+        const NOP_IO_DELAY = 1 << 1;
+        /// The [`KvmWallClockNew`](crate::registers::model_specific::KvmWallClockNew) and
+        /// [`KvmSystemTimeNew`](crate::registers::model_specific::KvmSystemTimeNew) registers.
+        const CLOCKSOURCE2 = 1 << 3;
+        /// Asynchronous page faults.
+        const ASYNC_PF = 1 << 4;
+        /// Reporting of the time the processor was preempted by the host.
+        const STEAL_TIME = 1 << 5;
+        /// Paravirtual end of interrupt.
+        const PV_EOI = 1 << 6;
+        /// The [`HC_KICK_CPU`] hypercall.
+        const PV_UNHALT = 1 << 7;
+        /// Paravirtual TLB flushes of preempted processors.
+        const PV_TLB_FLUSH = 1 << 9;
+        /// Asynchronous page faults delivered as VM exits in nested guests.
+        const ASYNC_PF_VMEXIT = 1 << 10;
+        /// The [`HC_SEND_IPI`] hypercall.
+        const PV_SEND_IPI = 1 << 11;
+        /// Control of host-side polling on `hlt`.
+        const POLL_CONTROL = 1 << 12;
+        /// The [`HC_SCHED_YIELD`] hypercall.
+        const PV_SCHED_YIELD = 1 << 13;
+        /// Asynchronous page fault notifications delivered as interrupts.
+        const ASYNC_PF_INT = 1 << 14;
+        /// Extended destination IDs in MSI addresses.
+        const MSI_EXT_DEST_ID = 1 << 15;
+        /// The [`HC_MAP_GPA_RANGE`] hypercall.
+        const HC_MAP_GPA_RANGE = 1 << 16;
+        /// Control of live migration.
+        const MIGRATION_CONTROL = 1 << 17;
+        /// The [`TSC_STABLE`](PvclockFlags::TSC_STABLE) flag of the kvmclock is supported.
+        const CLOCKSOURCE_STABLE_BIT = 1 << 24;
+    }
+}
+
+impl_flags_display!(KvmFeatures, PvclockFlags);
+
+/// The instruction used for hypercalls, which depends on the processor vendor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HypercallInstruction {
+    /// `vmcall`, used on Intel processors.
+    Vmcall,
+    /// `vmmcall`, used on AMD and Hygon processors.
+    Vmmcall,
+}
+
+/// The KVM hypervisor that the guest runs under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kvm {
+    features: KvmFeatures,
+    instruction: HypercallInstruction,
+}
+
+impl Kvm {
+    /// Detects whether the current processor runs under KVM.
+    ///
+    /// Returns `None` if there is no hypervisor or its signature isn't the one of KVM.
+    #[inline]
+    pub fn detect() -> Option<Kvm> {
+        Self::detect_with(cpuid)
+    }
+
+    /// Detects KVM using the given function instead of the `cpuid` instruction.
+    pub fn detect_with<F>(mut query: F) -> Option<Kvm>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        let info = HypervisorInfo::read_with(&mut query)?;
+        if info.vendor != HypervisorVendor::Kvm {
+            return None;
+        }
+        let features = if info.has_leaf(0x4000_0001) {
+            KvmFeatures::from_bits_truncate(query(0x4000_0001, 0).eax)
+        } else {
+            KvmFeatures::empty()
+        };
+
+        let result = query(0, 0);
+        let mut vendor = [0; 12];
+        vendor[..4].copy_from_slice(&result.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&result.edx.to_le_bytes());
+        vendor[8..].copy_from_slice(&result.ecx.to_le_bytes());
+        let instruction = match &vendor {
+            b"AuthenticAMD" | b"HygonGenuine" => HypercallInstruction::Vmmcall,
+            _ => HypercallInstruction::Vmcall,
+        };
+        Some(Kvm {
+            features,
+            instruction,
+        })
+    }
+
+    /// Returns the paravirtual features of the hypervisor.
+    #[inline]
+    pub fn features(&self) -> KvmFeatures {
+        self.features
+    }
+
+    /// Returns the instruction used for hypercalls.
+    #[inline]
+    pub fn hypercall_instruction(&self) -> HypercallInstruction {
+        self.instruction
+    }
+
+    /// Performs the hypercall with the given number and up to four arguments.
+    ///
+    /// Returns the result of the hypercall, which is a negative error number on failure, e.g.
+    /// -1000 for an unknown hypercall.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the effects of a hypercall depend on its number and
+    /// arguments, e.g. the guest-physical addresses that the hypervisor accesses.
+    #[inline]
+    pub unsafe fn hypercall(&self, number: u64, args: [u64; 4]) -> i64 {
+        let regs = HypercallRegisters::new(number, args);
+        let regs = match self.instruction {
+            HypercallInstruction::Vmcall => vmx::vmcall(regs),
+            HypercallInstruction::Vmmcall => svm::vmmcall(regs),
+        };
+        regs.rax as i64
+    }
+}
+
+bitflags! {
+    /// The flags of a [`PvclockVcpuTimeInfo`].
+    pub struct PvclockFlags: u8 {
+        /// The kvmclock is monotonic across all processors.
+        const TSC_STABLE = 1;
+        /// The guest was paused by the host since the flag was last cleared, e.g. to reset
+        /// watchdogs that would otherwise detect a lockup.
+        const GUEST_STOPPED = 1 << 1;
+    }
+}
+
+/// The kvmclock of a processor (`pvclock_vcpu_time_info`), updated by the hypervisor.
+///
+/// The version is odd while the hypervisor updates the structure, so [`read`](Self::read)
+/// retries until it reads the same even version before and after the other fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C, align(32))]
+pub struct PvclockVcpuTimeInfo {
+    /// The version of the structure, incremented before and after each update.
+    pub version: u32,
+    _pad0: u32,
+    /// The TSC value at which the system time was taken.
+    pub tsc_timestamp: u64,
+    /// The nanoseconds since boot at the TSC timestamp.
+    pub system_time: u64,
+    /// The multiplier that converts shifted TSC ticks to nanoseconds, as a 32.32 fixed-point
+    /// number.
+    pub tsc_to_system_mul: u32,
+    /// The shift applied to the TSC ticks before they are multiplied, negative for a right
+    /// shift.
+    pub tsc_shift: i8,
+    /// The raw [`PvclockFlags`].
+    pub flags: u8,
+    _pad: [u8; 2],
+}
+
+impl PvclockVcpuTimeInfo {
+    /// Creates a zeroed structure, whose address can be written to
+    /// [`KvmSystemTimeNew`](crate::registers::model_specific::KvmSystemTimeNew).
+    #[inline]
+    pub const fn new() -> Self {
+        PvclockVcpuTimeInfo {
+            version: 0,
+            _pad0: 0,
+            tsc_timestamp: 0,
+            system_time: 0,
+            tsc_to_system_mul: 0,
+            tsc_shift: 0,
+            flags: 0,
+            _pad: [0; 2],
+        }
+    }
+
+    /// Returns the flags of the structure.
+    #[inline]
+    pub fn flags(&self) -> PvclockFlags {
+        PvclockFlags::from_bits_truncate(self.flags)
+    }
+
+    /// Converts the given TSC value to nanoseconds since boot, using the parameters of this
+    /// copy of the structure.
+    #[inline]
+    pub fn time_at(&self, tsc: u64) -> u64 {
+        let mut delta = tsc.wrapping_sub(self.tsc_timestamp);
+        let shift = u32::from(self.tsc_shift.unsigned_abs());
+        if self.tsc_shift < 0 {
+            delta >>= shift;
+        } else {
+            delta <<= shift;
+        }
+        let scaled = (u128::from(delta) * u128::from(self.tsc_to_system_mul)) >> 32;
+        self.system_time.wrapping_add(scaled as u64)
+    }
+
+    /// Reads a consistent copy of the structure together with the current TSC value.
+    pub fn snapshot(&self) -> (PvclockVcpuTimeInfo, u64) {
+        loop {
+            // the hypervisor writes the structure concurrently, so all reads are volatile
+            let version = unsafe { ptr::read_volatile(&self.version) };
+            fence(Ordering::Acquire);
+            let snapshot = unsafe { ptr::read_volatile(self) };
+            let tsc = read_tsc();
+            fence(Ordering::Acquire);
+            if version & 1 == 0 && version == unsafe { ptr::read_volatile(&self.version) } {
+                return (snapshot, tsc);
+            }
+        }
+    }
+
+    /// Returns the current nanoseconds since boot.
+    #[inline]
+    pub fn read(&self) -> u64 {
+        let (snapshot, tsc) = self.snapshot();
+        snapshot.time_at(tsc)
+    }
+}
+
+/// The wall clock time of boot (`pvclock_wall_clock`), written by the hypervisor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct PvclockWallClock {
+    /// The version of the structure, odd while the hypervisor writes it.
+    pub version: u32,
+    /// The seconds since the Unix epoch.
+    pub sec: u32,
+    /// The nanoseconds within the second.
+    pub nsec: u32,
+}
+
+impl PvclockWallClock {
+    /// Creates a zeroed structure, whose address can be written to
+    /// [`KvmWallClockNew`](crate::registers::model_specific::KvmWallClockNew).
+    #[inline]
+    pub const fn new() -> Self {
+        PvclockWallClock {
+            version: 0,
+            sec: 0,
+            nsec: 0,
+        }
+    }
+
+    /// Reads the wall clock time of boot as the duration since the Unix epoch.
+    ///
+    /// The current wall clock time is the sum of this time and the time of the kvmclock.
+    pub fn read(&self) -> Duration {
+        loop {
+            let version = unsafe { ptr::read_volatile(&self.version) };
+            fence(Ordering::Acquire);
+            let snapshot = unsafe { ptr::read_volatile(self) };
+            fence(Ordering::Acquire);
+            if version & 1 == 0 && version == unsafe { ptr::read_volatile(&self.version) } {
+                return Duration::new(u64::from(snapshot.sec), snapshot.nsec);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn pvclock_time() {
+        let mut info = PvclockVcpuTimeInfo {
+            tsc_timestamp: 1000,
+            system_time: 5000,
+            // 0.5 nanoseconds per shifted tick
+            tsc_to_system_mul: 1 << 31,
+            tsc_shift: 1,
+            ..PvclockVcpuTimeInfo::new()
+        };
+        assert_eq!(info.time_at(3000), 7000);
+        info.tsc_shift = -1;
+        assert_eq!(info.time_at(3000), 5500);
+    }
+
+    #[test]
+    fn detect_kvm() {
+        let leaves = |vendor: [u32; 3]| {
+            [
+                (0, [0xd, vendor[0], vendor[2], vendor[1]]),
+                (1, [0, 0, 1 << 31, 0]),
+                // "KVMKVMKVM\0\0\0"
+                (0x4000_0000, [0x4000_0001, 0x4b4d_564b, 0x564b_4d56, 0x4d]),
+                (0x4000_0001, [1 << 3 | 1 << 24, 0, 0, 0]),
+            ]
+        };
+        // "GenuineIntel" and "AuthenticAMD" in ebx, edx and ecx
+        let intel = leaves([0x756e_6547, 0x4965_6e69, 0x6c65_746e]);
+        let intel = Kvm::detect_with(query(&intel)).unwrap();
+        assert_eq!(
+            intel.features(),
+            KvmFeatures::CLOCKSOURCE2 | KvmFeatures::CLOCKSOURCE_STABLE_BIT
+        );
+        assert_eq!(intel.hypercall_instruction(), HypercallInstruction::Vmcall);
+        let amd = leaves([0x6874_7541, 0x6974_6e65, 0x444d_4163]);
+        let amd = Kvm::detect_with(query(&amd)).unwrap();
+        assert_eq!(amd.hypercall_instruction(), HypercallInstruction::Vmmcall);
+    }
+}
@@ -2,9 +2,6 @@
 //! and access to various system registers.
 
 #![cfg_attr(not(test), no_std)]
-#![cfg_attr(feature = "const_fn", feature(const_fn))]
-#![cfg_attr(feature = "const_fn", feature(const_in_array_repeat_expressions))]
-#![cfg_attr(feature = "inline_asm", feature(asm))]
 #![cfg_attr(feature = "abi_x86_interrupt", feature(abi_x86_interrupt))]
 #![cfg_attr(feature = "deny-warnings", deny(warnings))]
 #![cfg_attr(feature = "deny-warnings", deny(missing_docs))]
@@ -29,19 +26,83 @@ macro_rules! const_fn {
         $(#[$attr])*
         #[cfg(not(feature = "const_fn"))]
         pub $($fn)*
-    }
+    };
+    (
+        $(#[$attr:meta])*
+        fn $($fn:tt)*
+    ) => {
+        $(#[$attr])*
+        #[cfg(feature = "const_fn")]
+        const fn $($fn)*
+
+        $(#[$attr])*
+        #[cfg(not(feature = "const_fn"))]
+        fn $($fn)*
+    };
+}
+
+/// Implements `Display` for a `bitflags` type by printing the names of the set flags,
+/// separated by `|`. Unknown bits are printed as a hexadecimal number.
+macro_rules! impl_flags_display {
+    ($($flags:ty),* $(,)?) => {$(
+        impl core::fmt::Display for $flags {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                core::fmt::Debug::fmt(self, f)
+            }
+        }
+    )*};
 }
 
-#[cfg(not(feature = "inline_asm"))]
+#[cfg(all(feature = "external_asm", target_arch = "x86_64"))]
 pub(crate) mod asm;
 
+#[cfg(all(feature = "external_asm", target_arch = "x86"))]
+compile_error!("The `external_asm` feature is only supported on x86_64 targets");
+
+// the `cpu_local` section bounds are defined by ELF linkers
+#[cfg(all(
+    target_arch = "x86_64",
+    not(any(target_os = "windows", target_os = "uefi", target_vendor = "apple"))
+))]
+pub mod cpu_local;
+#[cfg(target_arch = "x86_64")]
+pub mod cpuid;
+#[cfg(target_arch = "x86_64")]
+pub mod hyperv;
 pub mod instructions;
+#[cfg(target_arch = "x86_64")]
+pub mod kvm;
+pub mod long_mode;
+pub mod machine_check;
+#[cfg(target_arch = "x86_64")]
+pub mod mitigations;
+#[cfg(all(
+    target_arch = "x86_64",
+    not(any(target_os = "windows", target_os = "uefi", target_vendor = "apple"))
+))]
+pub mod nmi;
 pub mod registers;
+#[cfg(target_arch = "x86_64")]
+pub mod sev;
 pub mod structures;
+#[cfg(target_arch = "x86_64")]
+pub mod svm;
+#[cfg(target_arch = "x86_64")]
+pub mod time;
+#[cfg(target_arch = "x86_64")]
+pub mod vmware;
+#[cfg(target_arch = "x86_64")]
+pub mod vmx;
+#[cfg(all(
+    target_arch = "x86_64",
+    not(any(target_os = "windows", target_os = "uefi", target_vendor = "apple"))
+))]
+pub mod watchdog;
 
 mod addr;
 
 /// Represents a protection ring level.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
 pub enum PrivilegeLevel {
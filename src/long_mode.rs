@@ -0,0 +1,348 @@
+//! Helpers for switching from 32-bit protected mode to 64-bit long mode.
+//!
+//! Long mode is activated by enabling paging while long mode is enabled in the EFER register,
+//! which requires the following order:
+//!
+//! 1. Enable physical address extension (PAE) in CR4, while paging is still disabled.
+//! 2. Load the physical address of the level 4 page table into CR3. Since CR3 is only 32 bits
+//!    wide in protected mode, the table must be located below 4GiB.
+//! 3. Set `LONG_MODE_ENABLE` in EFER.
+//! 4. Enable paging in CR0. This activates long mode, but the processor keeps executing in
+//!    32-bit compatibility mode.
+//! 5. Load a GDT with a 64-bit code segment, e.g. the [`TransitionalGdt`], and far jump to
+//!    that segment.
+//!
+//! Most violations of this order cause a general protection fault. The checks of
+//! [`ModeState`] tell which requirement a register write would violate, so they can be run
+//! before the write, or after a fault to explain it. All of these helpers work when the crate
+//! is compiled for the 32-bit `x86` architecture.
+
+use crate::registers::control::{Cr0Flags, Cr4Flags, EferFlags};
+use crate::structures::gdt::{Descriptor, DescriptorFlags, GlobalDescriptorTable, SegmentSelector};
+use crate::PrivilegeLevel;
+use core::fmt;
+
+/// A requirement of the switch to long mode that a register write or a descriptor violates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LongModeError {
+    /// The processor does not support long mode, so `LONG_MODE_ENABLE` is a reserved bit.
+    Unsupported,
+    /// `LONG_MODE_ENABLE` was changed while paging is enabled.
+    LongModeChangedWithPaging,
+    /// Paging was enabled without `LONG_MODE_ENABLE`, which activates legacy 32-bit paging
+    /// instead of long mode.
+    LongModeNotEnabled,
+    /// Paging was enabled without protected mode.
+    PagingWithoutProtectedMode,
+    /// Paging was enabled with `LONG_MODE_ENABLE` set, but without PAE.
+    PagingWithoutPae,
+    /// PAE was disabled while long mode is active.
+    PaeDisabledInLongMode,
+    /// PCIDs were enabled outside of long mode.
+    PcidOutsideLongMode,
+    /// The descriptor of the target code segment is not a present code segment.
+    NotACodeSegment,
+    /// The target code segment is not a 64-bit segment, so the processor would stay in
+    /// compatibility mode.
+    NotALongModeSegment,
+    /// The target code segment has both the long mode and the default size bit set, which is
+    /// reserved.
+    LongModeSegmentWithDefaultSize,
+}
+
+impl fmt::Display for LongModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LongModeError::Unsupported => {
+                "the processor does not support long mode (CPUID 0x8000_0001, edx bit 29)"
+            }
+            LongModeError::LongModeChangedWithPaging => {
+                "EFER.LME can only be changed while CR0.PG is clear"
+            }
+            LongModeError::LongModeNotEnabled => {
+                "EFER.LME must be set before CR0.PG to activate long mode"
+            }
+            LongModeError::PagingWithoutProtectedMode => "CR0.PG requires CR0.PE to be set",
+            LongModeError::PagingWithoutPae => {
+                "CR0.PG requires CR4.PAE to be set while EFER.LME is set"
+            }
+            LongModeError::PaeDisabledInLongMode => {
+                "CR4.PAE can't be cleared while long mode is active"
+            }
+            LongModeError::PcidOutsideLongMode => {
+                "CR4.PCIDE can only be set while long mode is active"
+            }
+            LongModeError::NotACodeSegment => "the target segment is not a present code segment",
+            LongModeError::NotALongModeSegment => {
+                "the target code segment does not have the L bit set"
+            }
+            LongModeError::LongModeSegmentWithDefaultSize => {
+                "the target code segment has both the L and the D bit set"
+            }
+        })
+    }
+}
+
+/// Returns whether the processor supports long mode, as reported by CPUID leaf 0x8000_0001.
+#[inline]
+#[allow(unused_unsafe)]
+pub fn is_long_mode_supported() -> bool {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::__cpuid;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::__cpuid;
+
+    unsafe { __cpuid(0x8000_0000).eax >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 29) != 0 }
+}
+
+/// The register state that determines the operating mode of the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeState {
+    /// The CR0 flags.
+    pub cr0: Cr0Flags,
+    /// The CR4 flags.
+    pub cr4: Cr4Flags,
+    /// The EFER flags, or empty flags if long mode is not supported.
+    pub efer: EferFlags,
+    /// Whether the processor supports long mode.
+    pub long_mode_supported: bool,
+}
+
+impl ModeState {
+    /// Reads the state of the current processor.
+    ///
+    /// EFER is only read if long mode is supported, because processors without long mode
+    /// may not implement it.
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    pub fn read() -> ModeState {
+        use crate::registers::control::{Cr0, Cr4, Efer};
+
+        let long_mode_supported = is_long_mode_supported();
+        ModeState {
+            cr0: Cr0::read(),
+            cr4: Cr4::read(),
+            efer: match long_mode_supported {
+                true => Efer::read(),
+                false => EferFlags::empty(),
+            },
+            long_mode_supported,
+        }
+    }
+
+    /// Returns whether long mode is active, i.e. whether paging was enabled with
+    /// `LONG_MODE_ENABLE` set.
+    #[inline]
+    pub fn is_long_mode_active(&self) -> bool {
+        self.efer.contains(EferFlags::LONG_MODE_ACTIVE)
+    }
+
+    /// Checks whether writing the given flags to CR0 is allowed in this state.
+    pub fn check_write_cr0(&self, cr0: Cr0Flags) -> Result<(), LongModeError> {
+        if !cr0.contains(Cr0Flags::PAGING) || self.cr0.contains(Cr0Flags::PAGING) {
+            return Ok(());
+        }
+        if !cr0.contains(Cr0Flags::PROTECTED_MODE_ENABLE) {
+            return Err(LongModeError::PagingWithoutProtectedMode);
+        }
+        if self.efer.contains(EferFlags::LONG_MODE_ENABLE)
+            && !self.cr4.contains(Cr4Flags::PHYSICAL_ADDRESS_EXTENSION)
+        {
+            return Err(LongModeError::PagingWithoutPae);
+        }
+        Ok(())
+    }
+
+    /// Checks whether writing the given flags to CR4 is allowed in this state.
+    pub fn check_write_cr4(&self, cr4: Cr4Flags) -> Result<(), LongModeError> {
+        if self.is_long_mode_active() {
+            if !cr4.contains(Cr4Flags::PHYSICAL_ADDRESS_EXTENSION) {
+                return Err(LongModeError::PaeDisabledInLongMode);
+            }
+        } else if cr4.contains(Cr4Flags::PCID) {
+            return Err(LongModeError::PcidOutsideLongMode);
+        }
+        Ok(())
+    }
+
+    /// Checks whether writing the given flags to EFER is allowed in this state.
+    pub fn check_write_efer(&self, efer: EferFlags) -> Result<(), LongModeError> {
+        let enable = efer.contains(EferFlags::LONG_MODE_ENABLE);
+        if enable && !self.long_mode_supported {
+            return Err(LongModeError::Unsupported);
+        }
+        if enable != self.efer.contains(EferFlags::LONG_MODE_ENABLE)
+            && self.cr0.contains(Cr0Flags::PAGING)
+        {
+            return Err(LongModeError::LongModeChangedWithPaging);
+        }
+        Ok(())
+    }
+
+    /// Checks whether the switch to long mode can be completed from this state by enabling
+    /// paging, so that the processor is in long mode afterwards.
+    ///
+    /// Unlike [`check_write_cr0`](ModeState::check_write_cr0), this also fails if paging would
+    /// be enabled without `LONG_MODE_ENABLE`, which is valid but results in legacy 32-bit
+    /// paging.
+    pub fn check_enable_paging(&self) -> Result<(), LongModeError> {
+        if !self.long_mode_supported {
+            return Err(LongModeError::Unsupported);
+        }
+        if !self.efer.contains(EferFlags::LONG_MODE_ENABLE) {
+            return Err(LongModeError::LongModeNotEnabled);
+        }
+        self.check_write_cr0(self.cr0 | Cr0Flags::PAGING)
+    }
+}
+
+/// Checks whether the given descriptor is a valid target for the far jump into 64-bit mode.
+pub fn check_code_segment(descriptor: &Descriptor) -> Result<(), LongModeError> {
+    use self::DescriptorFlags as Flags;
+
+    let flags = match descriptor {
+        Descriptor::UserSegment(value) => Flags::from_bits_truncate(*value),
+        Descriptor::SystemSegment(..) => return Err(LongModeError::NotACodeSegment),
+    };
+    if !flags.contains(Flags::USER_SEGMENT | Flags::EXECUTABLE | Flags::PRESENT) {
+        return Err(LongModeError::NotACodeSegment);
+    }
+    if !flags.contains(Flags::LONG_MODE) {
+        return Err(LongModeError::NotALongModeSegment);
+    }
+    if flags.contains(Flags::DEFAULT_SIZE) {
+        return Err(LongModeError::LongModeSegmentWithDefaultSize);
+    }
+    Ok(())
+}
+
+/// A minimal GDT for the switch from protected mode to long mode.
+///
+/// Contains a 64-bit code segment, a flat 32-bit code segment, and a flat data segment, which
+/// is usable both in protected mode and in long mode. The 32-bit code segment allows loading
+/// the GDT and reloading the segment registers before long mode is active.
+#[derive(Debug, Clone)]
+pub struct TransitionalGdt {
+    gdt: GlobalDescriptorTable,
+}
+
+impl TransitionalGdt {
+    /// The selector of the 64-bit code segment, the target of the far jump into 64-bit mode.
+    pub const CODE_64: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+    /// The selector of the flat 32-bit code segment.
+    pub const CODE_32: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+    /// The selector of the flat data segment.
+    pub const DATA: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring0);
+
+    /// The descriptor of the 64-bit code segment.
+    pub const CODE_64_DESCRIPTOR: Descriptor = Descriptor::kernel_code_segment();
+    /// The descriptor of the flat 32-bit code segment, which is readable.
+    pub const CODE_32_DESCRIPTOR: Descriptor = Descriptor::UserSegment(
+        // the writable bit makes code segments readable
+        Self::FLAT.bits() | DescriptorFlags::EXECUTABLE.bits() | DescriptorFlags::WRITABLE.bits(),
+    );
+    /// The descriptor of the flat data segment.
+    pub const DATA_DESCRIPTOR: Descriptor =
+        Descriptor::UserSegment(Self::FLAT.bits() | DescriptorFlags::WRITABLE.bits());
+
+    // a present ring 0 segment with a 32-bit size covering 4GiB
+    const FLAT: DescriptorFlags = DescriptorFlags::from_bits_truncate(
+        DescriptorFlags::USER_SEGMENT.bits()
+            | DescriptorFlags::PRESENT.bits()
+            | DescriptorFlags::DEFAULT_SIZE.bits()
+            | DescriptorFlags::GRANULARITY.bits()
+            | DescriptorFlags::LIMIT_0_15.bits()
+            | DescriptorFlags::LIMIT_16_19.bits(),
+    );
+
+    const_fn! {
+        /// Creates the GDT.
+        ///
+        /// With the `const_fn` feature, this can be used in a constant initializer, so that
+        /// the table can be put into a `static` directly.
+        #[inline]
+        pub fn new() -> TransitionalGdt {
+            let mut gdt = GlobalDescriptorTable::new();
+            gdt.add_entry(Self::CODE_64_DESCRIPTOR);
+            gdt.add_entry(Self::CODE_32_DESCRIPTOR);
+            gdt.add_entry(Self::DATA_DESCRIPTOR);
+            TransitionalGdt { gdt }
+        }
+    }
+
+    /// Returns the underlying GDT, e.g. for loading it with
+    /// [`GlobalDescriptorTable::load`].
+    #[inline]
+    pub fn gdt(&self) -> &GlobalDescriptorTable {
+        &self.gdt
+    }
+}
+
+impl Default for TransitionalGdt {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_sequence() {
+        let mut state = ModeState {
+            cr0: Cr0Flags::PROTECTED_MODE_ENABLE,
+            cr4: Cr4Flags::empty(),
+            efer: EferFlags::empty(),
+            long_mode_supported: true,
+        };
+        assert_eq!(
+            state.check_enable_paging(),
+            Err(LongModeError::LongModeNotEnabled)
+        );
+
+        state.efer = EferFlags::LONG_MODE_ENABLE;
+        assert_eq!(
+            state.check_enable_paging(),
+            Err(LongModeError::PagingWithoutPae)
+        );
+        assert_eq!(
+            state.check_write_cr4(Cr4Flags::PCID),
+            Err(LongModeError::PcidOutsideLongMode)
+        );
+
+        state.cr4 = Cr4Flags::PHYSICAL_ADDRESS_EXTENSION;
+        assert_eq!(state.check_enable_paging(), Ok(()));
+
+        state.cr0 |= Cr0Flags::PAGING;
+        state.efer |= EferFlags::LONG_MODE_ACTIVE;
+        assert_eq!(
+            state.check_write_efer(EferFlags::empty()),
+            Err(LongModeError::LongModeChangedWithPaging)
+        );
+        assert_eq!(
+            state.check_write_cr4(Cr4Flags::empty()),
+            Err(LongModeError::PaeDisabledInLongMode)
+        );
+    }
+
+    #[test]
+    fn transitional_code_segments() {
+        assert_eq!(
+            check_code_segment(&TransitionalGdt::CODE_64_DESCRIPTOR),
+            Ok(())
+        );
+        assert_eq!(
+            check_code_segment(&TransitionalGdt::CODE_32_DESCRIPTOR),
+            Err(LongModeError::NotALongModeSegment)
+        );
+        assert_eq!(
+            check_code_segment(&TransitionalGdt::DATA_DESCRIPTOR),
+            Err(LongModeError::NotACodeSegment)
+        );
+        assert_eq!(
+            format!("{}", LongModeError::PagingWithoutPae),
+            "CR0.PG requires CR4.PAE to be set while EFER.LME is set"
+        );
+    }
+}
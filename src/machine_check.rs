@@ -0,0 +1,622 @@
+//! Decoding of the machine check banks, for logging from a machine check (`#MC`) handler.
+//!
+//! Each machine check bank reports its errors through a status register, which contains an
+//! architectural MCA error code that is decoded into an [`McaError`], and optional address and
+//! miscellaneous registers. A handler reads the valid banks with [`banks`], logs them through
+//! the `Display` implementation of [`MachineCheckBank`], clears them, and finally clears
+//! `IN_PROGRESS` in the [`McgStatus`](crate::registers::model_specific::McgStatus) register.
+
+use crate::registers::model_specific::Msr;
+use core::fmt;
+
+/// The status of a machine check bank (IA32_MCi_STATUS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct BankStatus(pub u64);
+
+impl BankStatus {
+    /// Returns whether the register contains a valid error.
+    #[inline]
+    pub fn is_valid(self) -> bool {
+        self.0 & (1 << 63) != 0
+    }
+
+    /// Returns whether an error occurred while the register still contained a valid error,
+    /// so that an error was lost.
+    #[inline]
+    pub fn is_overflow(self) -> bool {
+        self.0 & (1 << 62) != 0
+    }
+
+    /// Returns whether the processor didn't correct the error.
+    #[inline]
+    pub fn is_uncorrected(self) -> bool {
+        self.0 & (1 << 61) != 0
+    }
+
+    /// Returns whether the error was enabled to signal a machine check exception.
+    #[inline]
+    pub fn is_enabled(self) -> bool {
+        self.0 & (1 << 60) != 0
+    }
+
+    /// Returns whether the miscellaneous register of the bank contains additional information.
+    #[inline]
+    pub fn has_misc(self) -> bool {
+        self.0 & (1 << 59) != 0
+    }
+
+    /// Returns whether the address register of the bank contains the address of the error.
+    #[inline]
+    pub fn has_address(self) -> bool {
+        self.0 & (1 << 58) != 0
+    }
+
+    /// Returns whether the processor context may be corrupted by the error, so that execution
+    /// can't be restarted reliably.
+    #[inline]
+    pub fn is_processor_context_corrupt(self) -> bool {
+        self.0 & (1 << 57) != 0
+    }
+
+    /// Returns whether software must act on the error before execution continues, e.g. by
+    /// taking the affected page offline.
+    ///
+    /// Only reported if software error recovery is supported.
+    #[inline]
+    pub fn is_action_required(self) -> bool {
+        self.0 & (1 << 55) != 0
+    }
+
+    /// Returns the architectural MCA error code.
+    #[inline]
+    pub fn mca_error_code(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Returns the model specific error code.
+    #[inline]
+    pub fn model_error_code(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    /// Returns the number of corrected errors, if threshold-based error status is supported.
+    #[inline]
+    pub fn corrected_error_count(self) -> u16 {
+        ((self.0 >> 38) & 0x7fff) as u16
+    }
+
+    /// Decodes the architectural MCA error code.
+    #[inline]
+    pub fn error(self) -> McaError {
+        McaError::decode(self.mca_error_code())
+    }
+}
+
+/// The cache level of a compound MCA error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLevel {
+    /// Level 0.
+    L0,
+    /// Level 1.
+    L1,
+    /// Level 2.
+    L2,
+    /// No specific level.
+    Generic,
+}
+
+/// The transaction type of a compound MCA error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionType {
+    /// An instruction access.
+    Instruction,
+    /// A data access.
+    Data,
+    /// No specific transaction type.
+    Generic,
+}
+
+/// The request type of a compound MCA error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestType {
+    /// No specific request type.
+    Generic,
+    /// A generic read.
+    Read,
+    /// A generic write.
+    Write,
+    /// A data read.
+    DataRead,
+    /// A data write.
+    DataWrite,
+    /// An instruction fetch.
+    InstructionFetch,
+    /// A prefetch.
+    Prefetch,
+    /// An eviction.
+    Eviction,
+    /// A snoop.
+    Snoop,
+    /// A reserved request type.
+    Unknown(u8),
+}
+
+/// The operation of a memory controller error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryOperation {
+    /// No specific operation.
+    Generic,
+    /// A read.
+    Read,
+    /// A write.
+    Write,
+    /// An address or command.
+    AddressCommand,
+    /// Memory scrubbing.
+    Scrubbing,
+    /// A reserved operation.
+    Unknown(u8),
+}
+
+/// The participation of the processor in a bus or interconnect error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Participation {
+    /// The processor originated the request.
+    Originated,
+    /// The processor responded to the request.
+    Responded,
+    /// The processor observed the error as a third party.
+    Observed,
+    /// No specific participation.
+    Generic,
+}
+
+/// The address space of a bus or interconnect error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    /// A memory access.
+    Memory,
+    /// An I/O access.
+    Io,
+    /// Another access.
+    Other,
+    /// A reserved address space.
+    Reserved,
+}
+
+/// A decoded architectural MCA error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum McaError {
+    /// No error was logged.
+    NoError,
+    /// An error that is not classified.
+    Unclassified,
+    /// A parity error in the microcode ROM.
+    MicrocodeRomParity,
+    /// An error signaled by another processor.
+    External,
+    /// A functional redundancy check error.
+    FunctionalRedundancyCheck,
+    /// An internal parity error.
+    InternalParity,
+    /// An access violation of the SMM handler code.
+    SmmCodeAccessViolation,
+    /// An internal timer error, e.g. a watchdog timeout.
+    InternalTimer,
+    /// An I/O error.
+    Io,
+    /// An internal error that is not classified further.
+    InternalUnclassified,
+    /// A TLB error.
+    Tlb {
+        /// The transaction type.
+        transaction: TransactionType,
+        /// The cache level.
+        level: CacheLevel,
+    },
+    /// A memory controller error.
+    MemoryController {
+        /// The failed operation.
+        operation: MemoryOperation,
+        /// The memory channel, if specified.
+        channel: Option<u8>,
+    },
+    /// A cache (memory hierarchy) error.
+    Cache {
+        /// The request type.
+        request: RequestType,
+        /// The transaction type.
+        transaction: TransactionType,
+        /// The cache level.
+        level: CacheLevel,
+    },
+    /// A bus or interconnect error.
+    Bus {
+        /// The participation of the processor.
+        participation: Participation,
+        /// Whether the request timed out.
+        timeout: bool,
+        /// The request type.
+        request: RequestType,
+        /// The address space of the request.
+        space: AddressSpace,
+        /// The cache level.
+        level: CacheLevel,
+    },
+    /// An error code that is not defined by the architecture.
+    Unknown(u16),
+}
+
+impl McaError {
+    /// Decodes an architectural MCA error code.
+    pub fn decode(code: u16) -> McaError {
+        // bit 12 of compound error codes indicates corrected error filtering
+        let compound = code & !(1 << 12);
+        let level = || match code & 0x3 {
+            0 => CacheLevel::L0,
+            1 => CacheLevel::L1,
+            2 => CacheLevel::L2,
+            _ => CacheLevel::Generic,
+        };
+        let transaction = || match (code >> 2) & 0x3 {
+            0 => TransactionType::Instruction,
+            1 => TransactionType::Data,
+            _ => TransactionType::Generic,
+        };
+        let request = || match ((code >> 4) & 0xf) as u8 {
+            0 => RequestType::Generic,
+            1 => RequestType::Read,
+            2 => RequestType::Write,
+            3 => RequestType::DataRead,
+            4 => RequestType::DataWrite,
+            5 => RequestType::InstructionFetch,
+            6 => RequestType::Prefetch,
+            7 => RequestType::Eviction,
+            8 => RequestType::Snoop,
+            other => RequestType::Unknown(other),
+        };
+
+        match code {
+            0x0000 => McaError::NoError,
+            0x0001 => McaError::Unclassified,
+            0x0002 => McaError::MicrocodeRomParity,
+            0x0003 => McaError::External,
+            0x0004 => McaError::FunctionalRedundancyCheck,
+            0x0005 => McaError::InternalParity,
+            0x0006 => McaError::SmmCodeAccessViolation,
+            0x0400 => McaError::InternalTimer,
+            0x0e0b => McaError::Io,
+            0x0401..=0x07ff => McaError::InternalUnclassified,
+            _ if compound & 0xfff0 == 0x0010 => McaError::Tlb {
+                transaction: transaction(),
+                level: level(),
+            },
+            _ if compound & 0xff80 == 0x0080 => McaError::MemoryController {
+                operation: match ((code >> 4) & 0x7) as u8 {
+                    0 => MemoryOperation::Generic,
+                    1 => MemoryOperation::Read,
+                    2 => MemoryOperation::Write,
+                    3 => MemoryOperation::AddressCommand,
+                    4 => MemoryOperation::Scrubbing,
+                    other => MemoryOperation::Unknown(other),
+                },
+                channel: match (code & 0xf) as u8 {
+                    0xf => None,
+                    channel => Some(channel),
+                },
+            },
+            _ if compound & 0xff00 == 0x0100 => McaError::Cache {
+                request: request(),
+                transaction: transaction(),
+                level: level(),
+            },
+            _ if compound & 0xf800 == 0x0800 => McaError::Bus {
+                participation: match (code >> 9) & 0x3 {
+                    0 => Participation::Originated,
+                    1 => Participation::Responded,
+                    2 => Participation::Observed,
+                    _ => Participation::Generic,
+                },
+                timeout: code & (1 << 8) != 0,
+                request: request(),
+                space: match (code >> 2) & 0x3 {
+                    0 => AddressSpace::Memory,
+                    1 => AddressSpace::Reserved,
+                    2 => AddressSpace::Io,
+                    _ => AddressSpace::Other,
+                },
+                level: level(),
+            },
+            other => McaError::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for CacheLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CacheLevel::L0 => "L0",
+            CacheLevel::L1 => "L1",
+            CacheLevel::L2 => "L2",
+            CacheLevel::Generic => "generic",
+        })
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TransactionType::Instruction => "instruction",
+            TransactionType::Data => "data",
+            TransactionType::Generic => "generic",
+        })
+    }
+}
+
+impl fmt::Display for RequestType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestType::Generic => f.write_str("generic"),
+            RequestType::Read => f.write_str("read"),
+            RequestType::Write => f.write_str("write"),
+            RequestType::DataRead => f.write_str("data read"),
+            RequestType::DataWrite => f.write_str("data write"),
+            RequestType::InstructionFetch => f.write_str("instruction fetch"),
+            RequestType::Prefetch => f.write_str("prefetch"),
+            RequestType::Eviction => f.write_str("eviction"),
+            RequestType::Snoop => f.write_str("snoop"),
+            RequestType::Unknown(raw) => write!(f, "request {:#x}", raw),
+        }
+    }
+}
+
+impl fmt::Display for McaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            McaError::NoError => f.write_str("no error"),
+            McaError::Unclassified => f.write_str("unclassified error"),
+            McaError::MicrocodeRomParity => f.write_str("microcode ROM parity error"),
+            McaError::External => f.write_str("external error"),
+            McaError::FunctionalRedundancyCheck => f.write_str("FRC error"),
+            McaError::InternalParity => f.write_str("internal parity error"),
+            McaError::SmmCodeAccessViolation => f.write_str("SMM handler code access violation"),
+            McaError::InternalTimer => f.write_str("internal timer error"),
+            McaError::Io => f.write_str("I/O error"),
+            McaError::InternalUnclassified => f.write_str("internal unclassified error"),
+            McaError::Tlb { transaction, level } => {
+                write!(f, "{} {} TLB error", level, transaction)
+            }
+            McaError::MemoryController { operation, channel } => {
+                f.write_str("memory controller ")?;
+                match operation {
+                    MemoryOperation::Generic => f.write_str("generic")?,
+                    MemoryOperation::Read => f.write_str("read")?,
+                    MemoryOperation::Write => f.write_str("write")?,
+                    MemoryOperation::AddressCommand => f.write_str("address/command")?,
+                    MemoryOperation::Scrubbing => f.write_str("scrubbing")?,
+                    MemoryOperation::Unknown(raw) => write!(f, "operation {:#x}", raw)?,
+                }
+                match channel {
+                    Some(channel) => write!(f, " error on channel {}", channel),
+                    None => f.write_str(" error"),
+                }
+            }
+            McaError::Cache {
+                request,
+                transaction,
+                level,
+            } => write!(f, "{} {} cache {} error", level, transaction, request),
+            McaError::Bus {
+                participation,
+                timeout,
+                request,
+                space,
+                level,
+            } => {
+                write!(f, "bus {} error (", request)?;
+                f.write_str(match participation {
+                    Participation::Originated => "originated",
+                    Participation::Responded => "responded",
+                    Participation::Observed => "observed",
+                    Participation::Generic => "generic participation",
+                })?;
+                f.write_str(match space {
+                    AddressSpace::Memory => ", memory",
+                    AddressSpace::Io => ", I/O",
+                    AddressSpace::Other => ", other",
+                    AddressSpace::Reserved => ", reserved",
+                })?;
+                write!(f, ", {} level", level)?;
+                if *timeout {
+                    f.write_str(", timeout")?;
+                }
+                f.write_str(")")
+            }
+            McaError::Unknown(code) => write!(f, "unknown error {:#06x}", code),
+        }
+    }
+}
+
+/// The logged state of a machine check bank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineCheckBank {
+    /// The index of the bank.
+    pub bank: u8,
+    /// The status of the bank.
+    pub status: BankStatus,
+    /// The address of the error, if reported by the status.
+    pub address: Option<u64>,
+    /// The miscellaneous information, if reported by the status.
+    pub misc: Option<u64>,
+}
+
+impl MachineCheckBank {
+    #[inline]
+    fn msr(bank: u8, offset: u32) -> Msr {
+        Msr::new(0x400 + 4 * u32::from(bank) + offset)
+    }
+
+    /// Reads the given machine check bank. Returns `None` if the bank contains no valid error.
+    ///
+    /// The bank must be below the bank count of the
+    /// [`McgCap`](crate::registers::model_specific::McgCap) register.
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    pub fn read(bank: u8) -> Option<MachineCheckBank> {
+        let status = BankStatus(unsafe { Self::msr(bank, 1).read() });
+        if !status.is_valid() {
+            return None;
+        }
+        Some(MachineCheckBank {
+            bank,
+            status,
+            address: match status.has_address() {
+                true => Some(unsafe { Self::msr(bank, 2).read() }),
+                false => None,
+            },
+            misc: match status.has_misc() {
+                true => Some(unsafe { Self::msr(bank, 3).read() }),
+                false => None,
+            },
+        })
+    }
+
+    /// Clears the status of the given machine check bank after its error was logged.
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    #[inline]
+    pub fn clear(bank: u8) {
+        unsafe { Self::msr(bank, 1).write(0) };
+    }
+}
+
+impl fmt::Display for MachineCheckBank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = self.status;
+        let kind = match status.is_uncorrected() {
+            true => "uncorrected",
+            false => "corrected",
+        };
+        write!(f, "bank {}: {} {}", self.bank, kind, status.error())?;
+        if let Some(address) = self.address {
+            write!(f, " at {:#x}", address)?;
+        }
+        write!(f, " (model code {:#06x}", status.model_error_code())?;
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {:#x}", misc)?;
+        }
+        if status.is_processor_context_corrupt() {
+            f.write_str(", processor context corrupt")?;
+        }
+        if status.is_action_required() {
+            f.write_str(", action required")?;
+        }
+        if status.is_overflow() {
+            f.write_str(", overflow")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// An iterator over the machine check banks that contain a valid error, created by [`banks`].
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[derive(Debug, Clone)]
+pub struct Banks {
+    next: u8,
+    count: u8,
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+impl Iterator for Banks {
+    type Item = MachineCheckBank;
+
+    fn next(&mut self) -> Option<MachineCheckBank> {
+        while self.next < self.count {
+            let bank = self.next;
+            self.next += 1;
+            if let Some(bank) = MachineCheckBank::read(bank) {
+                return Some(bank);
+            }
+        }
+        None
+    }
+}
+
+/// Returns an iterator over the machine check banks that contain a valid error.
+///
+/// The number of banks is read from the
+/// [`McgCap`](crate::registers::model_specific::McgCap) register.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[inline]
+pub fn banks() -> Banks {
+    use crate::registers::model_specific::McgCap;
+
+    Banks {
+        next: 0,
+        count: McgCap::read().0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_error_codes() {
+        assert_eq!(McaError::decode(0x0005), McaError::InternalParity);
+        assert_eq!(
+            McaError::decode(0x0014),
+            McaError::Tlb {
+                transaction: TransactionType::Data,
+                level: CacheLevel::L0,
+            }
+        );
+        assert_eq!(
+            McaError::decode(0x0135),
+            McaError::Cache {
+                request: RequestType::DataRead,
+                transaction: TransactionType::Data,
+                level: CacheLevel::L1,
+            }
+        );
+        // with the corrected error filtering bit
+        assert_eq!(
+            McaError::decode(0x109f),
+            McaError::MemoryController {
+                operation: MemoryOperation::Read,
+                channel: None,
+            }
+        );
+        assert_eq!(
+            McaError::decode(0x0e0b),
+            McaError::Io,
+            "I/O error must not be decoded as a bus error"
+        );
+        assert_eq!(
+            McaError::decode(0x0c0f),
+            McaError::Bus {
+                participation: Participation::Observed,
+                timeout: false,
+                request: RequestType::Generic,
+                space: AddressSpace::Other,
+                level: CacheLevel::Generic,
+            }
+        );
+    }
+
+    #[test]
+    fn bank_report() {
+        // uncorrected memory read error on channel 2, with an address
+        let status = BankStatus(1 << 63 | 1 << 61 | 1 << 60 | 1 << 58 | 1 << 57 | 0x0001_0092);
+        let bank = MachineCheckBank {
+            bank: 7,
+            status,
+            address: Some(0x1234_5000),
+            misc: None,
+        };
+        assert_eq!(
+            format!("{}", bank),
+            "bank 7: uncorrected memory controller read error on channel 2 at 0x12345000 \
+             (model code 0x0001, processor context corrupt)"
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Helpers for mitigating speculative execution vulnerabilities and for hardening the
+//! kernel against accesses to user memory.
+//!
+//! The mechanisms are enumerated by CPUID and are only available on updated microcode, so
+//! each helper checks for support first and returns [`Unsupported`] if the mechanism is
+//! missing. Whether a mitigation is needed at all depends on the vulnerabilities of the
+//! processor, which newer processors report through [`arch_capabilities`].
+//!
+//! The helpers check CPUID on every call. Code that runs often, e.g. on every context switch,
+//! can read the [`MitigationFeatures`] once and use the model specific registers directly.
+//!
+//! The supervisor protections SMEP, SMAP, and UMIP are enabled together by
+//! [`enable_supervisor_protections`], which reports the protections that are not supported.
+
+use crate::cpuid::{cpuid, has_leaf, CpuidResult};
+use crate::registers::control::{Cr4, Cr4Flags};
+use crate::registers::model_specific::{
+    ArchCapabilities, ArchCapabilitiesFlags, FlushCmd, PredCmd, SpecCtrl, SpecCtrlFlags,
+};
+use crate::structures::gdt::SegmentSelector;
+use bitflags::bitflags;
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::fmt;
+
+bitflags! {
+    /// The speculation control mechanisms supported by the processor.
+    pub struct MitigationFeatures: u32 {
+        /// The `IBRS` bit of the [`SpecCtrl`] register.
+        const IBRS = 1;
+        /// The indirect branch prediction barrier of the [`PredCmd`] register.
+        const IBPB = 1 << 1;
+        /// The `STIBP` bit of the [`SpecCtrl`] register.
+        const STIBP = 1 << 2;
+        /// The `SSBD` bit of the [`SpecCtrl`] register.
+        const SSBD = 1 << 3;
+        /// Clearing of the microarchitectural buffers by `verw`.
+        const MD_CLEAR = 1 << 4;
+        /// The L1 data cache flush of the [`FlushCmd`] register.
+        const L1D_FLUSH = 1 << 5;
+        /// The [`ArchCapabilities`] register.
+        const ARCH_CAPABILITIES = 1 << 6;
+    }
+}
+
+impl MitigationFeatures {
+    /// Reads the mechanisms supported by the current processor.
+    #[inline]
+    pub fn read() -> MitigationFeatures {
+        Self::read_with(cpuid)
+    }
+
+    /// Reads the supported mechanisms using the given function instead of the `cpuid`
+    /// instruction.
+    ///
+    /// Uses the structured extended feature leaf 7 (Intel) and the extended leaf 0x8000_0008
+    /// (AMD).
+    pub fn read_with<F>(mut query: F) -> MitigationFeatures
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        let mut features = MitigationFeatures::empty();
+        if has_leaf(7, &mut query) {
+            let edx = query(7, 0).edx;
+            // bit 26 enumerates both IBRS and IBPB
+            features.set(Self::IBRS | Self::IBPB, edx & (1 << 26) != 0);
+            features.set(Self::STIBP, edx & (1 << 27) != 0);
+            features.set(Self::SSBD, edx & (1 << 31) != 0);
+            features.set(Self::MD_CLEAR, edx & (1 << 10) != 0);
+            features.set(Self::L1D_FLUSH, edx & (1 << 28) != 0);
+            features.set(Self::ARCH_CAPABILITIES, edx & (1 << 29) != 0);
+        }
+        if has_leaf(0x8000_0008, &mut query) {
+            let ebx = query(0x8000_0008, 0).ebx;
+            if ebx & (1 << 12) != 0 {
+                features |= Self::IBPB;
+            }
+            if ebx & (1 << 14) != 0 {
+                features |= Self::IBRS;
+            }
+            if ebx & (1 << 15) != 0 {
+                features |= Self::STIBP;
+            }
+            if ebx & (1 << 24) != 0 {
+                features |= Self::SSBD;
+            }
+        }
+        features
+    }
+}
+
+impl_flags_display!(MitigationFeatures);
+
+/// The error returned if a mitigation mechanism is not supported by the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported(MitigationFeatures);
+
+impl Unsupported {
+    /// Returns the mechanism that is not supported.
+    #[inline]
+    pub fn feature(&self) -> MitigationFeatures {
+        self.0
+    }
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not supported by the processor", self.0)
+    }
+}
+
+#[inline]
+fn require(feature: MitigationFeatures) -> Result<(), Unsupported> {
+    if MitigationFeatures::read().contains(feature) {
+        Ok(())
+    } else {
+        Err(Unsupported(feature))
+    }
+}
+
+/// Returns the vulnerabilities the processor reports to be unaffected by.
+///
+/// Returns empty flags if the [`ArchCapabilities`] register is not supported, which means
+/// that the processor has to be assumed affected by all of them.
+#[inline]
+pub fn arch_capabilities() -> ArchCapabilitiesFlags {
+    match require(MitigationFeatures::ARCH_CAPABILITIES) {
+        Ok(()) => unsafe { ArchCapabilities::read() },
+        Err(_) => ArchCapabilitiesFlags::empty(),
+    }
+}
+
+/// Issues an indirect branch prediction barrier (IBPB).
+///
+/// Prevents branch targets trained before the barrier from being used afterwards, e.g. when
+/// switching to a different address space.
+#[inline]
+pub fn ibpb() -> Result<(), Unsupported> {
+    require(MitigationFeatures::IBPB)?;
+    unsafe { PredCmd::indirect_branch_prediction_barrier() };
+    Ok(())
+}
+
+/// Enables indirect branch restricted speculation (IBRS).
+///
+/// On processors with enhanced IBRS ([`ArchCapabilitiesFlags::IBRS_ALL`]), it suffices to
+/// enable IBRS once. Otherwise it only protects against branch targets trained before it was
+/// enabled, so the Linux recipe is to set it again on every entry to the kernel.
+#[inline]
+pub fn enable_ibrs() -> Result<(), Unsupported> {
+    require(MitigationFeatures::IBRS)?;
+    unsafe { SpecCtrl::update(|flags| flags.insert(SpecCtrlFlags::IBRS)) };
+    Ok(())
+}
+
+/// Enables single thread indirect branch predictors (STIBP), which isolates the branch
+/// predictions of sibling hyperthreads.
+#[inline]
+pub fn enable_stibp() -> Result<(), Unsupported> {
+    require(MitigationFeatures::STIBP)?;
+    unsafe { SpecCtrl::update(|flags| flags.insert(SpecCtrlFlags::STIBP)) };
+    Ok(())
+}
+
+/// Enables speculative store bypass disable (SSBD).
+///
+/// Not needed if the processor reports [`ArchCapabilitiesFlags::SSB_NO`].
+#[inline]
+pub fn enable_ssbd() -> Result<(), Unsupported> {
+    require(MitigationFeatures::SSBD)?;
+    unsafe { SpecCtrl::update(|flags| flags.insert(SpecCtrlFlags::SSBD)) };
+    Ok(())
+}
+
+/// Clears the microarchitectural buffers affected by microarchitectural data sampling (MDS)
+/// using the `verw` instruction.
+///
+/// This should be called before returning to less privileged code and before entering a
+/// guest. The `selector` must refer to a writable data segment, e.g. the kernel data segment.
+/// Not needed if the processor reports [`ArchCapabilitiesFlags::MDS_NO`].
+#[inline]
+pub fn mds_clear_cpu_buffers(selector: SegmentSelector) -> Result<(), Unsupported> {
+    require(MitigationFeatures::MD_CLEAR)?;
+
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        // only the form with a memory operand clears the buffers
+        asm!("verw [{}]", in(reg) &selector.0, options(readonly, nostack));
+    }
+
+    #[cfg(feature = "external_asm")]
+    unsafe {
+        crate::asm::x86_64_asm_verw(selector.0);
+    }
+
+    Ok(())
+}
+
+/// Writes back and invalidates the L1 data cache, which mitigates the L1 terminal fault
+/// (L1TF) on entry to a guest.
+///
+/// Not needed if the processor reports [`ArchCapabilitiesFlags::RDCL_NO`] or, for VM entries,
+/// [`ArchCapabilitiesFlags::SKIP_L1DFL_VMENTRY`].
+#[inline]
+pub fn flush_l1d() -> Result<(), Unsupported> {
+    require(MitigationFeatures::L1D_FLUSH)?;
+    unsafe { FlushCmd::flush_l1d() };
+    Ok(())
+}
+
+/// The report returned by [`enable_supervisor_protections`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupervisorProtections {
+    /// The protections that are enabled in CR4 after the call.
+    pub enabled: Cr4Flags,
+    /// The protections that are not supported by the processor and therefore stay disabled.
+    pub unsupported: Cr4Flags,
+}
+
+impl SupervisorProtections {
+    /// The CR4 bits of all supervisor protections: SMEP, SMAP, and UMIP.
+    pub const ALL: Cr4Flags = Cr4Flags::from_bits_truncate(
+        Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION.bits()
+            | Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION.bits()
+            | Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION.bits(),
+    );
+
+    /// Returns the CR4 bits of the supervisor protections supported by the processor, using
+    /// the given function instead of the `cpuid` instruction.
+    ///
+    /// The protections are enumerated by the structured extended feature leaf 7.
+    pub fn supported_with<F>(mut query: F) -> Cr4Flags
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        let mut supported = Cr4Flags::empty();
+        if has_leaf(7, &mut query) {
+            let features = query(7, 0);
+            supported.set(
+                Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+                features.ebx & (1 << 7) != 0,
+            );
+            supported.set(
+                Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION,
+                features.ebx & (1 << 20) != 0,
+            );
+            supported.set(
+                Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION,
+                features.ecx & (1 << 2) != 0,
+            );
+        }
+        supported
+    }
+
+    /// Returns whether all supervisor protections are enabled.
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+}
+
+/// Enables the supervisor protections that are supported by the processor and reports which
+/// ones are enabled.
+///
+/// These are supervisor mode execution protection (SMEP), which prevents the kernel from
+/// executing user pages, supervisor mode access prevention (SMAP), which prevents the kernel
+/// from accessing user pages unless `RFlags::ALIGNMENT_CHECK` is set through `stac`, and user
+/// mode instruction prevention (UMIP), which prevents user code from executing `sgdt`, `sidt`,
+/// `sldt`, `smsw`, and `str`. Protections that were already enabled stay enabled.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that the kernel does not execute
+/// code in user pages and only accesses user pages while access is allowed with `stac`.
+#[inline]
+pub unsafe fn enable_supervisor_protections() -> SupervisorProtections {
+    let supported = SupervisorProtections::supported_with(cpuid);
+    Cr4::update(|flags| flags.insert(supported));
+    SupervisorProtections {
+        enabled: Cr4::read() & SupervisorProtections::ALL,
+        unsupported: SupervisorProtections::ALL - supported,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn supported_supervisor_protections() {
+        let supported = SupervisorProtections::supported_with(query(&[
+            (0, [7, 0, 0, 0]),
+            (7, [0, 1 << 7 | 1 << 20, 0, 0]),
+        ]));
+        assert_eq!(
+            SupervisorProtections::ALL - supported,
+            Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION
+        );
+    }
+
+    #[test]
+    fn mitigation_features() {
+        let intel = MitigationFeatures::read_with(query(&[
+            (0, [7, 0, 0, 0]),
+            (7, [0, 0, 0, 1 << 10 | 1 << 26 | 1 << 29]),
+        ]));
+        assert_eq!(
+            intel,
+            MitigationFeatures::IBRS
+                | MitigationFeatures::IBPB
+                | MitigationFeatures::MD_CLEAR
+                | MitigationFeatures::ARCH_CAPABILITIES
+        );
+
+        let amd = MitigationFeatures::read_with(query(&[
+            (0, [7, 0, 0, 0]),
+            (0x8000_0000, [0x8000_0008, 0, 0, 0]),
+            (0x8000_0008, [0, 1 << 12 | 1 << 24, 0, 0]),
+        ]));
+        assert_eq!(amd, MitigationFeatures::IBPB | MitigationFeatures::SSBD);
+
+        let error = Unsupported(MitigationFeatures::L1D_FLUSH);
+        assert_eq!(
+            format!("{}", error),
+            "L1D_FLUSH is not supported by the processor"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Support for code running in non-maskable interrupt (NMI) context.
+//!
+//! NMIs can't be disabled, so NMI handlers must not take locks that are protected by
+//! disabling interrupts, since the interrupted code could hold the lock. Instead, NMI handlers
+//! should [`defer`] such work to a safe point, where it is run by [`run_deferred`].
+//!
+//! The state is tracked per processor, so [`cpu_local::init`] must have been called before
+//! any function of this module is used, which is why they are unsafe. The [`NmiState`] type
+//! can also be used on its own, e.g. as part of a custom per-CPU structure.
+
+use crate::cpu_local;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of functions that can be deferred before [`NmiState::run_deferred`] is called.
+pub const DEFER_QUEUE_SIZE: usize = 16;
+
+/// The NMI state of a single processor: the NMI nesting depth and a queue of deferred functions.
+#[derive(Debug)]
+pub struct NmiState {
+    depth: AtomicUsize,
+    queue: [AtomicUsize; DEFER_QUEUE_SIZE],
+}
+
+impl NmiState {
+    /// Creates a new state for a processor that is not in NMI context.
+    #[inline]
+    pub const fn new() -> NmiState {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicUsize = AtomicUsize::new(0);
+        NmiState {
+            depth: AtomicUsize::new(0),
+            queue: [EMPTY; DEFER_QUEUE_SIZE],
+        }
+    }
+
+    /// Marks the start of an NMI handler. The NMI context ends when the returned guard is
+    /// dropped.
+    #[inline]
+    pub fn enter(&self) -> NmiGuard<'_> {
+        self.depth.fetch_add(1, Ordering::Acquire);
+        NmiGuard { state: self }
+    }
+
+    /// Returns whether the processor is currently running an NMI handler.
+    #[inline]
+    pub fn in_nmi(&self) -> bool {
+        self.depth.load(Ordering::Relaxed) != 0
+    }
+
+    /// Queues the given function to be run by [`run_deferred`](NmiState::run_deferred).
+    ///
+    /// Returns the function as error if the queue is full.
+    #[inline]
+    pub fn defer(&self, f: fn()) -> Result<(), fn()> {
+        let value = f as usize;
+        for slot in self.queue.iter() {
+            if slot
+                .compare_exchange(0, value, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        Err(f)
+    }
+
+    /// Runs and removes all queued functions.
+    ///
+    /// This should be called regularly at a safe point outside of NMI context, e.g. at the
+    /// end of the timer interrupt handler. Functions that are queued by an NMI while this
+    /// function runs are either run too or stay queued for the next call.
+    ///
+    /// Panics if called in NMI context.
+    #[inline]
+    pub fn run_deferred(&self) {
+        assert!(
+            !self.in_nmi(),
+            "deferred functions must not run in NMI context"
+        );
+        for slot in self.queue.iter() {
+            let value = slot.swap(0, Ordering::AcqRel);
+            if value != 0 {
+                // only function pointers are stored in the queue
+                let f: fn() = unsafe { mem::transmute(value) };
+                f();
+            }
+        }
+    }
+}
+
+impl Default for NmiState {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A guard that marks the NMI context, returned by [`NmiState::enter`] and [`enter`].
+#[derive(Debug)]
+pub struct NmiGuard<'a> {
+    state: &'a NmiState,
+}
+
+impl Drop for NmiGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.state.depth.fetch_sub(1, Ordering::Release);
+    }
+}
+
+cpu_local! {
+    static STATE: NmiState = NmiState::new();
+}
+
+/// Marks the start of an NMI handler on the current processor. The NMI context ends when the
+/// returned guard is dropped.
+///
+/// ## Safety
+///
+/// This function is unsafe because the returned guard must be dropped on the current
+/// processor, which is usually guaranteed by calling this function only in NMI handlers.
+/// Also, [`cpu_local::init`] must have been called on the current processor before.
+#[inline]
+pub unsafe fn enter() -> NmiGuard<'static> {
+    (*STATE.as_ptr()).enter()
+}
+
+/// Returns whether the current processor is running an NMI handler.
+///
+/// ## Safety
+///
+/// This function is unsafe because [`cpu_local::init`] must have been called on the current
+/// processor before.
+#[inline]
+pub unsafe fn in_nmi() -> bool {
+    STATE.with(|state| state.in_nmi())
+}
+
+/// Queues the given function to be run by [`run_deferred`] on the current processor.
+///
+/// Returns the function as error if the queue is full.
+///
+/// ## Safety
+///
+/// This function is unsafe because [`cpu_local::init`] must have been called on the current
+/// processor before.
+#[inline]
+pub unsafe fn defer(f: fn()) -> Result<(), fn()> {
+    STATE.with(|state| state.defer(f))
+}
+
+/// Runs and removes all functions queued on the current processor.
+///
+/// The functions run with interrupts disabled.
+///
+/// Panics if called in NMI context.
+///
+/// ## Safety
+///
+/// This function is unsafe because [`cpu_local::init`] must have been called on the current
+/// processor before.
+#[inline]
+pub unsafe fn run_deferred() {
+    STATE.with(|state| state.run_deferred())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn first() {
+        // runs before `second`, since it was deferred into the first free slot
+        assert_eq!(RUNS.fetch_add(1, Ordering::Relaxed) % 2, 0);
+    }
+
+    fn second() {
+        assert_eq!(RUNS.fetch_add(1, Ordering::Relaxed) % 2, 1);
+    }
+
+    #[test]
+    fn nested_nmis() {
+        let state = NmiState::new();
+        assert!(!state.in_nmi());
+        let outer = state.enter();
+        let inner = state.enter();
+        drop(inner);
+        assert!(state.in_nmi());
+        drop(outer);
+        assert!(!state.in_nmi());
+    }
+
+    #[test]
+    fn deferred_functions() {
+        let state = NmiState::new();
+        {
+            let _guard = state.enter();
+            assert!(state.defer(first).is_ok());
+            assert!(state.defer(second).is_ok());
+        }
+        state.run_deferred();
+        assert_eq!(RUNS.load(Ordering::Relaxed), 2);
+        // the queue is empty again
+        state.run_deferred();
+        assert_eq!(RUNS.load(Ordering::Relaxed), 2);
+
+        for _ in 0..DEFER_QUEUE_SIZE {
+            assert!(state.defer(first).is_ok());
+        }
+        assert!(state.defer(second).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn run_deferred_in_nmi() {
+        let state = NmiState::new();
+        let _guard = state.enter();
+        state.run_deferred();
+    }
+}
@@ -125,11 +125,15 @@ bitflags! {
     }
 }
 
-#[cfg(target_arch = "x86_64")]
+impl_flags_display!(Cr0Flags, Cr3Flags, Cr4Flags);
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 mod x86_64 {
     use super::*;
     use crate::structures::paging::PhysFrame;
     use crate::{PhysAddr, VirtAddr};
+    #[cfg(not(feature = "external_asm"))]
+    use core::arch::asm;
 
     impl Cr0 {
         /// Read the current set of CR0 flags.
@@ -143,12 +147,14 @@ mod x86_64 {
         pub fn read_raw() -> u64 {
             let value: u64;
 
-            #[cfg(feature = "inline_asm")]
+            #[cfg(not(feature = "external_asm"))]
             unsafe {
-                asm!("mov %cr0, $0" : "=r" (value));
+                let raw: usize;
+                asm!("mov {}, cr0", out(reg) raw, options(nomem, nostack, preserves_flags));
+                value = raw as u64;
             }
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             unsafe {
                 value = crate::asm::x86_64_asm_read_cr0();
             }
@@ -183,10 +189,10 @@ mod x86_64 {
         /// safety through it, e.g. by disabling paging.
         #[inline]
         pub unsafe fn write_raw(value: u64) {
-            #[cfg(feature = "inline_asm")]
-            asm!("mov $0, %cr0" :: "r" (value) : "memory");
+            #[cfg(not(feature = "external_asm"))]
+            asm!("mov cr0, {}", in(reg) value as usize, options(nostack, preserves_flags));
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             crate::asm::x86_64_asm_write_cr0(value);
         }
 
@@ -215,12 +221,14 @@ mod x86_64 {
         pub fn read() -> VirtAddr {
             let value: u64;
 
-            #[cfg(feature = "inline_asm")]
+            #[cfg(not(feature = "external_asm"))]
             unsafe {
-                asm!("mov %cr2, $0" : "=r" (value));
+                let raw: usize;
+                asm!("mov {}, cr2", out(reg) raw, options(nomem, nostack, preserves_flags));
+                value = raw as u64;
             }
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             unsafe {
                 value = crate::asm::x86_64_asm_read_cr2();
             }
@@ -235,12 +243,14 @@ mod x86_64 {
         pub fn read() -> (PhysFrame, Cr3Flags) {
             let value: u64;
 
-            #[cfg(feature = "inline_asm")]
+            #[cfg(not(feature = "external_asm"))]
             unsafe {
-                asm!("mov %cr3, $0" : "=r" (value));
+                let raw: usize;
+                asm!("mov {}, cr3", out(reg) raw, options(nomem, nostack, preserves_flags));
+                value = raw as u64;
             }
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             unsafe {
                 value = crate::asm::x86_64_asm_read_cr3();
             }
@@ -261,10 +271,10 @@ mod x86_64 {
             let addr = frame.start_address();
             let value = addr.as_u64() | flags.bits();
 
-            #[cfg(feature = "inline_asm")]
-            asm!("mov $0, %cr3" :: "r" (value) : "memory");
+            #[cfg(not(feature = "external_asm"))]
+            asm!("mov cr3, {}", in(reg) value as usize, options(nostack, preserves_flags));
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             crate::asm::x86_64_asm_write_cr3(value)
         }
     }
@@ -281,12 +291,14 @@ mod x86_64 {
         pub fn read_raw() -> u64 {
             let value: u64;
 
-            #[cfg(feature = "inline_asm")]
+            #[cfg(not(feature = "external_asm"))]
             unsafe {
-                asm!("mov %cr4, $0" : "=r" (value));
+                let raw: usize;
+                asm!("mov {}, cr4", out(reg) raw, options(nomem, nostack, preserves_flags));
+                value = raw as u64;
             }
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             unsafe {
                 value = crate::asm::x86_64_asm_read_cr4();
             }
@@ -323,10 +335,10 @@ mod x86_64 {
         /// flag.
         #[inline]
         pub unsafe fn write_raw(value: u64) {
-            #[cfg(feature = "inline_asm")]
-            asm!("mov $0, %cr4" :: "r" (value) : "memory");
+            #[cfg(not(feature = "external_asm"))]
+            asm!("mov cr4, {}", in(reg) value as usize, options(nostack, preserves_flags));
 
-            #[cfg(not(feature = "inline_asm"))]
+            #[cfg(feature = "external_asm")]
             crate::asm::x86_64_asm_write_cr4(value);
         }
 
@@ -1,20 +1,28 @@
 //! Access to various system and model specific registers.
 
+#[cfg(all(target_arch = "x86_64", not(feature = "external_asm")))]
+use core::arch::asm;
+
 pub mod control;
 pub mod model_specific;
 pub mod rflags;
 
 /// Gets the current instruction pointer. Note that this is only approximate as it requires a few
 /// instructions to execute.
-#[cfg(feature = "inline_asm")]
+#[cfg(target_arch = "x86_64")]
 #[inline(always)]
 pub fn read_rip() -> u64 {
     let rip: u64;
+
+    #[cfg(not(feature = "external_asm"))]
+    unsafe {
+        asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack, preserves_flags));
+    }
+
+    #[cfg(feature = "external_asm")]
     unsafe {
-        asm!(
-            "lea (%rip), $0"
-            : "=r"(rip) ::: "volatile"
-        );
+        rip = crate::asm::x86_64_asm_read_rip();
     }
+
     rip
 }
@@ -1,11 +1,14 @@
 //! Functions to read and write model specific registers.
 
+use crate::registers::control::Cr0Flags;
 use crate::registers::rflags::RFlags;
 use crate::structures::gdt::SegmentSelector;
+use crate::structures::paging::{ept::EptMemoryType, PageSize, Size1GiB, Size2MiB, Size4KiB};
 use crate::PrivilegeLevel;
 use bit_field::BitField;
 use bitflags::bitflags;
 use core::convert::TryInto;
+use core::fmt;
 
 /// A model specific register.
 #[derive(Debug)]
@@ -47,6 +50,222 @@ pub struct LStar;
 #[derive(Debug)]
 pub struct SFMask;
 
+/// The code segment selector of `sysenter` (IA32_SYSENTER_CS).
+#[derive(Debug)]
+pub struct SysEnterCs;
+
+/// The stack pointer loaded by `sysenter` (IA32_SYSENTER_ESP).
+#[derive(Debug)]
+pub struct SysEnterEsp;
+
+/// The instruction pointer loaded by `sysenter` (IA32_SYSENTER_EIP).
+#[derive(Debug)]
+pub struct SysEnterEip;
+
+/// The local APIC base address register (IA32_APIC_BASE).
+#[derive(Debug)]
+pub struct ApicBase;
+
+/// The TSC deadline register of the local APIC timer (IA32_TSC_DEADLINE).
+#[derive(Debug)]
+pub struct TscDeadline;
+
+/// The speculation control register (IA32_SPEC_CTRL).
+#[derive(Debug)]
+pub struct SpecCtrl;
+
+/// The prediction command register (IA32_PRED_CMD).
+#[derive(Debug)]
+pub struct PredCmd;
+
+/// The architectural capabilities register (IA32_ARCH_CAPABILITIES).
+#[derive(Debug)]
+pub struct ArchCapabilities;
+
+/// The cache flush command register (IA32_FLUSH_CMD).
+#[derive(Debug)]
+pub struct FlushCmd;
+
+/// The machine check global capability register (IA32_MCG_CAP).
+#[derive(Debug)]
+pub struct McgCap;
+
+/// The machine check global status register (IA32_MCG_STATUS).
+#[derive(Debug)]
+pub struct McgStatus;
+
+/// The event select registers of the general-purpose performance counters (IA32_PERFEVTSELx).
+#[derive(Debug)]
+pub struct PerfEvtSel;
+
+/// The general-purpose performance counters (IA32_PMCx).
+#[derive(Debug)]
+pub struct Pmc;
+
+/// The global performance counter status register (IA32_PERF_GLOBAL_STATUS).
+#[derive(Debug)]
+pub struct PerfGlobalStatus;
+
+/// The global performance counter control register (IA32_PERF_GLOBAL_CTRL).
+#[derive(Debug)]
+pub struct PerfGlobalCtrl;
+
+/// The global performance counter overflow control register (IA32_PERF_GLOBAL_OVF_CTRL).
+#[derive(Debug)]
+pub struct PerfGlobalOvfCtrl;
+
+/// The feature control register, which enables VMX and other features and is usually locked
+/// by the firmware (IA32_FEATURE_CONTROL).
+#[derive(Debug)]
+pub struct FeatureControl;
+
+/// The Intel Processor Trace control register (IA32_RTIT_CTL).
+#[derive(Debug)]
+pub struct RtitCtl;
+
+/// The Intel Processor Trace status register (IA32_RTIT_STATUS).
+#[derive(Debug)]
+pub struct RtitStatus;
+
+/// The base address of the Intel Processor Trace output region or table of physical addresses
+/// (IA32_RTIT_OUTPUT_BASE).
+#[derive(Debug)]
+pub struct RtitOutputBase;
+
+/// The size of the Intel Processor Trace output region and the current position in it
+/// (IA32_RTIT_OUTPUT_MASK_PTRS).
+#[derive(Debug)]
+pub struct RtitOutputMaskPtrs;
+
+/// The CR3 value that Intel Processor Trace is filtered on (IA32_RTIT_CR3_MATCH).
+#[derive(Debug)]
+pub struct RtitCr3Match;
+
+/// The basic VMX capability register (IA32_VMX_BASIC).
+#[derive(Debug)]
+pub struct VmxBasic;
+
+/// The capability register of the pin-based VM-execution controls (IA32_VMX_PINBASED_CTLS).
+#[derive(Debug)]
+pub struct VmxPinbasedCtls;
+
+/// The capability register of the primary processor-based VM-execution controls
+/// (IA32_VMX_PROCBASED_CTLS).
+#[derive(Debug)]
+pub struct VmxProcbasedCtls;
+
+/// The capability register of the VM-exit controls (IA32_VMX_EXIT_CTLS).
+#[derive(Debug)]
+pub struct VmxExitCtls;
+
+/// The capability register of the VM-entry controls (IA32_VMX_ENTRY_CTLS).
+#[derive(Debug)]
+pub struct VmxEntryCtls;
+
+/// The miscellaneous VMX capability register (IA32_VMX_MISC).
+#[derive(Debug)]
+pub struct VmxMisc;
+
+/// The register of the CR0 bits that are fixed to 1 in VMX operation (IA32_VMX_CR0_FIXED0).
+#[derive(Debug)]
+pub struct VmxCr0Fixed0;
+
+/// The register of the CR0 bits that may be 1 in VMX operation (IA32_VMX_CR0_FIXED1).
+#[derive(Debug)]
+pub struct VmxCr0Fixed1;
+
+/// The register of the CR4 bits that are fixed to 1 in VMX operation (IA32_VMX_CR4_FIXED0).
+#[derive(Debug)]
+pub struct VmxCr4Fixed0;
+
+/// The register of the CR4 bits that may be 1 in VMX operation (IA32_VMX_CR4_FIXED1).
+#[derive(Debug)]
+pub struct VmxCr4Fixed1;
+
+/// The capability register of the secondary processor-based VM-execution controls
+/// (IA32_VMX_PROCBASED_CTLS2).
+#[derive(Debug)]
+pub struct VmxProcbasedCtls2;
+
+/// The register of the supported EPT and VPID features (IA32_VMX_EPT_VPID_CAP).
+///
+/// The register only exists if the "enable EPT" or "enable VPID" secondary controls may be set.
+#[derive(Debug)]
+pub struct VmxEptVpidCap;
+
+/// The capability register of the pin-based VM-execution controls, including the default1
+/// controls that may be cleared (IA32_VMX_TRUE_PINBASED_CTLS).
+#[derive(Debug)]
+pub struct VmxTruePinbasedCtls;
+
+/// The capability register of the primary processor-based VM-execution controls, including
+/// the default1 controls that may be cleared (IA32_VMX_TRUE_PROCBASED_CTLS).
+#[derive(Debug)]
+pub struct VmxTrueProcbasedCtls;
+
+/// The capability register of the VM-exit controls, including the default1 controls that
+/// may be cleared (IA32_VMX_TRUE_EXIT_CTLS).
+#[derive(Debug)]
+pub struct VmxTrueExitCtls;
+
+/// The capability register of the VM-entry controls, including the default1 controls that
+/// may be cleared (IA32_VMX_TRUE_ENTRY_CTLS).
+#[derive(Debug)]
+pub struct VmxTrueEntryCtls;
+
+/// The KVM paravirtual register that makes the hypervisor write the wall clock time of boot
+/// to guest memory (MSR_KVM_WALL_CLOCK_NEW).
+#[derive(Debug)]
+pub struct KvmWallClockNew;
+
+/// The KVM paravirtual register that holds the guest-physical address of the kvmclock
+/// structure of the current processor (MSR_KVM_SYSTEM_TIME_NEW).
+#[derive(Debug)]
+pub struct KvmSystemTimeNew;
+
+/// The Hyper-V synthetic register that identifies the guest operating system
+/// (HV_X64_MSR_GUEST_OS_ID).
+#[derive(Debug)]
+pub struct HvGuestOsId;
+
+/// The Hyper-V synthetic register that enables the hypercall page (HV_X64_MSR_HYPERCALL).
+#[derive(Debug)]
+pub struct HvHypercall;
+
+/// The Hyper-V synthetic register that holds the virtual processor index of the current
+/// processor (HV_X64_MSR_VP_INDEX).
+#[derive(Debug)]
+pub struct HvVpIndex;
+
+/// The Hyper-V synthetic partition reference counter, which counts in units of 100
+/// nanoseconds since the partition was created (HV_X64_MSR_TIME_REF_COUNT).
+#[derive(Debug)]
+pub struct HvTimeRefCount;
+
+/// The Hyper-V synthetic register that enables the reference TSC page
+/// (HV_X64_MSR_REFERENCE_TSC).
+#[derive(Debug)]
+pub struct HvReferenceTsc;
+
+/// The SVM control register, which the firmware can use to disable and lock SVM (VM_CR).
+#[derive(Debug)]
+pub struct VmCr;
+
+/// The physical address of the host save area, where `vmrun` saves the host state that
+/// `#VMEXIT` restores (VM_HSAVE_PA).
+#[derive(Debug)]
+pub struct VmHsavePa;
+
+/// The register that reports which memory encryption features are active in an SEV guest
+/// (SEV_STATUS).
+#[derive(Debug)]
+pub struct SevStatus;
+
+/// The register through which an SEV-ES guest passes the address of its GHCB or a GHCB MSR
+/// protocol request to the hypervisor (SEV_ES_GHCB).
+#[derive(Debug)]
+pub struct SevEsGhcb;
+
 impl Efer {
     /// The underlying model specific register.
     pub const MSR: Msr = Msr(0xC000_0080);
@@ -82,6 +301,257 @@ impl SFMask {
     pub const MSR: Msr = Msr(0xC000_0084);
 }
 
+impl SysEnterCs {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x174);
+}
+
+impl SysEnterEsp {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x175);
+}
+
+impl SysEnterEip {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x176);
+}
+
+impl ApicBase {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x1B);
+}
+
+impl TscDeadline {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x6E0);
+}
+
+impl SpecCtrl {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48);
+}
+
+impl PredCmd {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x49);
+}
+
+impl ArchCapabilities {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x10A);
+}
+
+impl FlushCmd {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x10B);
+}
+
+impl McgCap {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x179);
+}
+
+impl McgStatus {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x17A);
+}
+
+impl PerfEvtSel {
+    /// Returns the event select register of the given general-purpose counter.
+    #[inline]
+    pub const fn msr(counter: u8) -> Msr {
+        Msr(0x186 + counter as u32)
+    }
+}
+
+impl Pmc {
+    /// Returns the register of the given general-purpose counter.
+    #[inline]
+    pub const fn msr(counter: u8) -> Msr {
+        Msr(0xC1 + counter as u32)
+    }
+}
+
+impl PerfGlobalStatus {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x38E);
+}
+
+impl PerfGlobalCtrl {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x38F);
+}
+
+impl PerfGlobalOvfCtrl {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x390);
+}
+
+impl FeatureControl {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x3A);
+}
+
+impl RtitCtl {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x570);
+}
+
+impl RtitStatus {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x571);
+}
+
+impl RtitOutputBase {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x560);
+}
+
+impl RtitOutputMaskPtrs {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x561);
+}
+
+impl RtitCr3Match {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x572);
+}
+
+impl VmxBasic {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x480);
+}
+
+impl VmxPinbasedCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x481);
+}
+
+impl VmxProcbasedCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x482);
+}
+
+impl VmxExitCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x483);
+}
+
+impl VmxEntryCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x484);
+}
+
+impl VmxMisc {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x485);
+}
+
+impl VmxCr0Fixed0 {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x486);
+}
+
+impl VmxCr0Fixed1 {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x487);
+}
+
+impl VmxCr4Fixed0 {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x488);
+}
+
+impl VmxCr4Fixed1 {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x489);
+}
+
+impl VmxProcbasedCtls2 {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48B);
+}
+
+impl VmxEptVpidCap {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48C);
+}
+
+impl VmxTruePinbasedCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48D);
+}
+
+impl VmxTrueProcbasedCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48E);
+}
+
+impl VmxTrueExitCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x48F);
+}
+
+impl VmxTrueEntryCtls {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x490);
+}
+
+impl KvmWallClockNew {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4b56_4d00);
+}
+
+impl KvmSystemTimeNew {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4b56_4d01);
+}
+
+impl HvGuestOsId {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4000_0000);
+}
+
+impl HvHypercall {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4000_0001);
+}
+
+impl HvVpIndex {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4000_0002);
+}
+
+impl HvTimeRefCount {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4000_0020);
+}
+
+impl HvReferenceTsc {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0x4000_0021);
+}
+
+impl VmCr {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0xC001_0114);
+}
+
+impl VmHsavePa {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0xC001_0117);
+}
+
+impl SevStatus {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0xC001_0131);
+}
+
+impl SevEsGhcb {
+    /// The underlying model specific register.
+    pub const MSR: Msr = Msr(0xC001_0130);
+}
+
 bitflags! {
     /// Flags of the Extended Feature Enable Register.
     pub struct EferFlags: u64 {
@@ -102,297 +572,1736 @@ bitflags! {
         /// Changes how the `invlpg` instruction operates on TLB entries of upper-level entries.
         const TRANSLATION_CACHE_EXTENSION = 1 << 15;
     }
-}
+}
+
+bitflags! {
+    /// Flags of the local APIC base address register.
+    pub struct ApicBaseFlags: u64 {
+        /// Indicates that the current processor is the bootstrap processor (BSP).
+        const BOOTSTRAP_PROCESSOR = 1 << 8;
+        /// Enables the x2APIC mode of the local APIC.
+        ///
+        /// Can only be set when `GLOBAL_ENABLE` is set too.
+        const X2APIC_ENABLE = 1 << 10;
+        /// Globally enables the local APIC.
+        ///
+        /// If this bit is cleared, the local APIC can only be enabled again by a reset.
+        const GLOBAL_ENABLE = 1 << 11;
+    }
+}
+
+bitflags! {
+    /// Flags of the speculation control register.
+    pub struct SpecCtrlFlags: u64 {
+        /// Indirect branch restricted speculation: restricts the speculation of indirect
+        /// branches to targets that were not trained by less privileged code.
+        const IBRS = 1;
+        /// Single thread indirect branch predictors: prevents the indirect branch predictions
+        /// of one logical processor from being controlled by its sibling threads.
+        const STIBP = 1 << 1;
+        /// Speculative store bypass disable: prevents loads from executing speculatively
+        /// before the addresses of older stores are known.
+        const SSBD = 1 << 2;
+    }
+}
+
+bitflags! {
+    /// Flags of the architectural capabilities register, which enumerate the speculative
+    /// execution vulnerabilities the processor is not affected by.
+    pub struct ArchCapabilitiesFlags: u64 {
+        /// The processor is not affected by rogue data cache load (Meltdown) and L1 terminal
+        /// fault.
+        const RDCL_NO = 1;
+        /// The processor supports enhanced IBRS, which only needs to be enabled once.
+        const IBRS_ALL = 1 << 1;
+        /// The processor may use alternative branch predictors when the return stack buffer
+        /// underflows.
+        const RSBA = 1 << 2;
+        /// The L1 data cache does not need to be flushed on VM entry.
+        const SKIP_L1DFL_VMENTRY = 1 << 3;
+        /// The processor is not affected by speculative store bypass.
+        const SSB_NO = 1 << 4;
+        /// The processor is not affected by microarchitectural data sampling.
+        const MDS_NO = 1 << 5;
+        /// The processor is not affected by machine check errors on page size changes.
+        const IF_PSCHANGE_MC_NO = 1 << 6;
+        /// The processor supports the TSX control register.
+        const TSX_CTRL = 1 << 7;
+        /// The processor is not affected by TSX asynchronous abort.
+        const TAA_NO = 1 << 8;
+    }
+}
+
+bitflags! {
+    /// Flags of the machine check global capability register.
+    pub struct McgCapFlags: u64 {
+        /// The machine check global control register (IA32_MCG_CTL) is present.
+        const CTL_PRESENT = 1 << 8;
+        /// The extended machine check state registers are present.
+        const EXTENDED_PRESENT = 1 << 9;
+        /// Corrected machine check error interrupts (CMCI) are supported.
+        const CMCI_PRESENT = 1 << 10;
+        /// The threshold-based error status bits of the bank status registers are present.
+        const THRESHOLD_STATUS_PRESENT = 1 << 11;
+        /// Software error recovery is supported, i.e. the `S` and `AR` bits of the bank status
+        /// registers are present.
+        const SOFTWARE_RECOVERY_PRESENT = 1 << 24;
+        /// Enhanced machine check error logging is supported.
+        const ENHANCED_LOGGING_PRESENT = 1 << 26;
+        /// Local machine check exceptions are supported.
+        const LOCAL_MCE_PRESENT = 1 << 27;
+    }
+}
+
+bitflags! {
+    /// Flags of the machine check global status register.
+    pub struct McgStatusFlags: u64 {
+        /// Restart IP valid: execution can be restarted at the instruction pointer pushed on
+        /// the stack.
+        const RESTART_IP_VALID = 1;
+        /// Error IP valid: the instruction pointer pushed on the stack is directly associated
+        /// with the error.
+        const ERROR_IP_VALID = 1 << 1;
+        /// A machine check exception is in progress. Another machine check while this flag is
+        /// set shuts down the processor.
+        const IN_PROGRESS = 1 << 2;
+        /// The machine check exception was only delivered to this logical processor.
+        const LOCAL_MCE_SIGNALED = 1 << 3;
+    }
+}
+
+bitflags! {
+    /// Flags of a performance event select register.
+    ///
+    /// The event number and the unit mask are stored in bits 0..8 and 8..16, which are
+    /// written by [`PerfEvtSel::write`].
+    pub struct PerfEvtSelFlags: u64 {
+        /// Counts while the processor is running at privilege level 1, 2, or 3.
+        const USER_MODE = 1 << 16;
+        /// Counts while the processor is running at privilege level 0.
+        const OS_MODE = 1 << 17;
+        /// Counts rising edges of the event condition instead of cycles.
+        const EDGE_DETECT = 1 << 18;
+        /// Toggles the PMi pins on events or counter overflows.
+        const PIN_CONTROL = 1 << 19;
+        /// Signals an interrupt through the local APIC when the counter overflows.
+        const APIC_INTERRUPT = 1 << 20;
+        /// Counts the events of all logical processors of the core.
+        const ANY_THREAD = 1 << 21;
+        /// Enables the counter.
+        const ENABLE = 1 << 22;
+        /// Inverts the counter mask comparison.
+        const INVERT = 1 << 23;
+    }
+}
+
+bitflags! {
+    /// Flags of the feature control register.
+    pub struct FeatureControlFlags: u64 {
+        /// Locks the register, writes cause a general protection fault until the next reset.
+        const LOCKED = 1;
+        /// Allows `vmxon` inside SMX operation, i.e. after `getsec[SENTER]`.
+        const VMX_INSIDE_SMX = 1 << 1;
+        /// Allows `vmxon` outside SMX operation.
+        const VMX_OUTSIDE_SMX = 1 << 2;
+        /// Enables `getsec[SENTER]`.
+        const SENTER_GLOBAL_ENABLE = 1 << 15;
+        /// Allows writes to the SGX launch enclave public key hash MSRs.
+        const SGX_LAUNCH_CONTROL = 1 << 17;
+        /// Enables SGX.
+        const SGX_GLOBAL_ENABLE = 1 << 18;
+        /// Enables local machine check exceptions.
+        const LMCE_ON = 1 << 20;
+    }
+}
+
+bitflags! {
+    /// Flags of the SVM control register.
+    pub struct VmCrFlags: u64 {
+        /// Disables external hardware debug port and certain internal debug features.
+        const DEBUG_PORT_DISABLE = 1;
+        /// Makes INIT signals cause an `#SX` exception instead of a reset while GIF is set.
+        const REDIRECT_INIT = 1 << 1;
+        /// Disables the A20 masking in SVM operation.
+        const DISABLE_A20M = 1 << 2;
+        /// Makes `SVM_DISABLE` read-only until the next reset.
+        const LOCK = 1 << 3;
+        /// Prevents setting EFER.SVME.
+        const SVM_DISABLE = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Flags of the SEV status register.
+    pub struct SevStatusFlags: u64 {
+        /// The guest runs with encrypted memory.
+        const SEV_ENABLED = 1;
+        /// The register state of the guest is encrypted as well.
+        const SEV_ES_ENABLED = 1 << 1;
+        /// The guest memory is integrity protected by SEV-SNP.
+        const SEV_SNP_ENABLED = 1 << 2;
+        /// Guest-physical addresses below the virtual top of memory are private.
+        const VTOM = 1 << 3;
+        /// Causes `#VC` exceptions to be reflected to the hypervisor.
+        const REFLECT_VC = 1 << 4;
+        /// Restricts the event injection into the guest to `#HV` exceptions.
+        const RESTRICTED_INJECTION = 1 << 5;
+        /// Events are injected through the VMSA of another VMPL.
+        const ALTERNATE_INJECTION = 1 << 6;
+        /// The debug registers are swapped on guest entry and exit.
+        const DEBUG_SWAP = 1 << 7;
+        /// Prevents the host from using IBS on the guest.
+        const PREVENT_HOST_IBS = 1 << 8;
+        /// Isolates the branch target buffer of the guest.
+        const BTB_ISOLATION = 1 << 9;
+        /// The VMSA supervisor shadow stack registers are checked per VMPL.
+        const VMPL_SSS = 1 << 10;
+        /// The TSC of the guest can't be changed by the hypervisor.
+        const SECURE_TSC = 1 << 11;
+    }
+}
+
+bitflags! {
+    /// Flags of the Intel Processor Trace control register.
+    ///
+    /// The multi-bit fields, e.g. the MTC and PSB frequencies and the address filter
+    /// configuration, aren't part of these flags. They are preserved by [`RtitCtl::write`].
+    pub struct RtitCtlFlags: u64 {
+        /// Enables tracing.
+        const TRACE_ENABLE = 1;
+        /// Generates cycle-accurate mode (CYC) packets.
+        const CYCLE_PACKETS = 1 << 1;
+        /// Traces while the processor is running at privilege level 0.
+        const OS_MODE = 1 << 2;
+        /// Traces while the processor is running at privilege level 3.
+        const USER_MODE = 1 << 3;
+        /// Generates power event trace packets.
+        const POWER_EVENT_TRACE = 1 << 4;
+        /// Generates FUP packets for `ptwrite`.
+        const FUP_ON_PTWRITE = 1 << 5;
+        /// Sends the trace output to the trace transport subsystem instead of memory.
+        const FABRIC_ENABLE = 1 << 6;
+        /// Only traces while CR3 matches [`RtitCr3Match`].
+        const CR3_FILTER = 1 << 7;
+        /// Uses a table of physical addresses (ToPA) for the output instead of a single
+        /// region.
+        const TOPA = 1 << 8;
+        /// Generates mini time counter (MTC) packets.
+        const MTC_PACKETS = 1 << 9;
+        /// Generates TSC packets.
+        const TSC_PACKETS = 1 << 10;
+        /// Disables the compression of return addresses.
+        const DISABLE_RETURN_COMPRESSION = 1 << 11;
+        /// Generates packets for `ptwrite`.
+        const PTWRITE = 1 << 12;
+        /// Generates packets for control flow changes.
+        const BRANCH_ENABLE = 1 << 13;
+        /// Generates a PSB packet and a PMI when tracing is enabled.
+        const INJECT_PSB_PMI_ON_ENABLE = 1 << 56;
+    }
+}
+
+impl_flags_display!(
+    EferFlags,
+    ApicBaseFlags,
+    SpecCtrlFlags,
+    ArchCapabilitiesFlags,
+    McgCapFlags,
+    McgStatusFlags,
+    PerfEvtSelFlags,
+    FeatureControlFlags,
+    RtitCtlFlags
+);
+
+/// The basic VMX capabilities, read from [`VmxBasic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmxBasicInfo {
+    /// The VMCS revision identifier, which must be written to the start of the VMXON region
+    /// and of each VMCS region.
+    pub revision_id: u32,
+    /// The size of the VMXON region and of the VMCS regions in bytes, at most 4096.
+    pub region_size: u16,
+    /// Whether the addresses of the VMXON region, VMCS regions and related data structures are
+    /// limited to 32 bits.
+    pub physical_address_width_32: bool,
+    /// Whether the dual-monitor treatment of SMIs and SMM is supported.
+    pub dual_monitor: bool,
+    /// The memory type that the processor uses to access the VMCS, 0 for uncacheable and 6 for
+    /// write-back.
+    pub memory_type: u8,
+    /// Whether VM exits caused by INS and OUTS report instruction information.
+    pub ins_outs_reporting: bool,
+    /// Whether the `IA32_VMX_TRUE_*` capability registers are supported, which allow clearing
+    /// default1 controls.
+    pub true_controls: bool,
+    /// Whether VM entry can deliver hardware exceptions without an error code, regardless of
+    /// the exception vector.
+    pub no_error_code_requirement: bool,
+}
+
+impl VmxBasicInfo {
+    /// Decodes the value of `IA32_VMX_BASIC`.
+    #[inline]
+    pub const fn from_raw(value: u64) -> Self {
+        VmxBasicInfo {
+            revision_id: value as u32 & 0x7fff_ffff,
+            region_size: (value >> 32) as u16 & 0x1fff,
+            physical_address_width_32: value & (1 << 48) != 0,
+            dual_monitor: value & (1 << 49) != 0,
+            memory_type: (value >> 50) as u8 & 0xf,
+            ins_outs_reporting: value & (1 << 54) != 0,
+            true_controls: value & (1 << 55) != 0,
+            no_error_code_requirement: value & (1 << 56) != 0,
+        }
+    }
+}
+
+/// The allowed settings of a 32-bit VMX control field, read from its capability register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmxControlCapability {
+    /// The allowed 0-settings: the controls whose bits are set here must be 1.
+    pub allowed_0: u32,
+    /// The allowed 1-settings: only the controls whose bits are set here may be 1.
+    pub allowed_1: u32,
+}
+
+impl VmxControlCapability {
+    /// Decodes the value of a control capability register, which holds the allowed 0-settings
+    /// in the low and the allowed 1-settings in the high 32 bits.
+    #[inline]
+    pub const fn from_raw(value: u64) -> Self {
+        VmxControlCapability {
+            allowed_0: value as u32,
+            allowed_1: (value >> 32) as u32,
+        }
+    }
+
+    /// Returns whether the given controls can be set, i.e. whether all required controls are
+    /// set and only allowed controls are set.
+    #[inline]
+    pub const fn is_allowed(&self, controls: u32) -> bool {
+        controls & self.allowed_0 == self.allowed_0 && controls & !self.allowed_1 == 0
+    }
+
+    /// Sets the required controls and clears the controls that aren't allowed.
+    #[inline]
+    pub const fn adjust(&self, controls: u32) -> u32 {
+        (controls | self.allowed_0) & self.allowed_1
+    }
+
+    /// Sets the required controls, or returns the requested controls that aren't allowed.
+    #[inline]
+    pub fn try_adjust(&self, controls: u32) -> Result<u32, u32> {
+        match controls & !self.allowed_1 {
+            0 => Ok(controls | self.allowed_0),
+            unsupported => Err(unsupported),
+        }
+    }
+}
+
+/// The 32-bit VMX control fields, whose allowed settings are reported by capability
+/// registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmxControl {
+    /// The pin-based VM-execution controls.
+    PinBased,
+    /// The primary processor-based VM-execution controls.
+    PrimaryProcBased,
+    /// The secondary processor-based VM-execution controls.
+    SecondaryProcBased,
+    /// The VM-exit controls.
+    Exit,
+    /// The VM-entry controls.
+    Entry,
+}
+
+/// The error returned when requested VMX controls aren't supported by the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedControls {
+    /// The control field of the controls.
+    pub control: VmxControl,
+    /// The requested controls that can't be set.
+    pub bits: u32,
+}
+
+impl fmt::Display for UnsupportedControls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the {:?} controls {:#x} are not supported by the processor",
+            self.control, self.bits
+        )
+    }
+}
+
+/// The miscellaneous VMX capabilities, read from [`VmxMisc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmxMiscInfo {
+    /// The VMX-preemption timer counts down by 1 every time bit X of the TSC changes, where X
+    /// is this value.
+    pub preemption_timer_rate: u8,
+    /// Whether VM exits store the value of `EFER.LMA` in the "IA-32e mode guest" VM-entry
+    /// control.
+    pub stores_lma: bool,
+    /// The supported activity states besides the active state, as a bitmap of HLT (bit 0),
+    /// shutdown (bit 1) and wait-for-SIPI (bit 2).
+    pub activity_states: u8,
+    /// Whether Intel Processor Trace can be used in VMX operation.
+    pub processor_trace: bool,
+    /// Whether `rdmsr` can read `IA32_SMBASE` in SMM.
+    pub smbase_readable: bool,
+    /// The number of CR3-target values supported.
+    pub cr3_targets: u16,
+    /// The recommended maximum number of entries in each MSR list.
+    pub max_msr_list_entries: u32,
+    /// Whether `vmwrite` can write all VMCS fields, including the read-only data fields.
+    pub vmwrite_all_fields: bool,
+    /// Whether VM entry can inject software interrupts and exceptions with an instruction
+    /// length of 0.
+    pub zero_length_injection: bool,
+    /// The MSEG revision identifier.
+    pub mseg_revision_id: u32,
+}
+
+impl VmxMiscInfo {
+    /// Decodes the value of `IA32_VMX_MISC`.
+    #[inline]
+    pub const fn from_raw(value: u64) -> Self {
+        VmxMiscInfo {
+            preemption_timer_rate: value as u8 & 0x1f,
+            stores_lma: value & (1 << 5) != 0,
+            activity_states: (value >> 6) as u8 & 0b111,
+            processor_trace: value & (1 << 14) != 0,
+            smbase_readable: value & (1 << 15) != 0,
+            cr3_targets: (value >> 16) as u16 & 0x1ff,
+            max_msr_list_entries: 512 * (((value >> 25) as u32 & 0b111) + 1),
+            vmwrite_all_fields: value & (1 << 29) != 0,
+            zero_length_injection: value & (1 << 30) != 0,
+            mseg_revision_id: (value >> 32) as u32,
+        }
+    }
+
+    /// Converts a number of TSC cycles to VMX-preemption timer ticks, rounding down and
+    /// saturating at the largest timer value.
+    #[inline]
+    pub const fn tsc_to_preemption_ticks(&self, tsc_cycles: u64) -> u32 {
+        let ticks = tsc_cycles >> self.preemption_timer_rate;
+        if ticks > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            ticks as u32
+        }
+    }
+
+    /// Converts a number of VMX-preemption timer ticks to TSC cycles.
+    #[inline]
+    pub const fn preemption_ticks_to_tsc(&self, ticks: u32) -> u64 {
+        (ticks as u64) << self.preemption_timer_rate
+    }
+}
+
+/// The bits of a control register that are fixed in VMX operation, read from its
+/// `IA32_VMX_CRx_FIXED0` and `IA32_VMX_CRx_FIXED1` registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmxFixedBits {
+    /// The bits that must be 1.
+    pub fixed_0: u64,
+    /// The bits that may be 1; all other bits must be 0.
+    pub fixed_1: u64,
+}
+
+impl VmxFixedBits {
+    /// Returns whether the given control register value is allowed in VMX operation.
+    #[inline]
+    pub const fn is_allowed(&self, value: u64) -> bool {
+        value & self.fixed_0 == self.fixed_0 && value & !self.fixed_1 == 0
+    }
+
+    /// Sets the bits that must be 1 and clears the bits that must be 0.
+    #[inline]
+    pub const fn adjust(&self, value: u64) -> u64 {
+        (value | self.fixed_0) & self.fixed_1
+    }
+
+    /// Returns the fixed bits of CR0 for an unrestricted guest, which may clear the PE and PG
+    /// bits.
+    #[inline]
+    pub const fn unrestricted_guest(self) -> Self {
+        VmxFixedBits {
+            fixed_0: self.fixed_0
+                & !(Cr0Flags::PROTECTED_MODE_ENABLE.bits() | Cr0Flags::PAGING.bits()),
+            fixed_1: self.fixed_1,
+        }
+    }
+}
+
+/// The supported EPT and VPID features, read from [`VmxEptVpidCap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EptVpidCapabilities {
+    /// Whether EPT entries may allow instruction fetches without allowing reads.
+    pub execute_only: bool,
+    /// Whether a page-walk length of 4 is supported.
+    pub page_walk_length_4: bool,
+    /// Whether a page-walk length of 5 is supported.
+    pub page_walk_length_5: bool,
+    /// Whether the EPT tables may be accessed with the uncacheable memory type.
+    pub uncacheable: bool,
+    /// Whether the EPT tables may be accessed with the write-back memory type.
+    pub write_back: bool,
+    /// Whether EPT PD entries may map 2MiB frames.
+    pub pages_2mib: bool,
+    /// Whether EPT PDPT entries may map 1GiB frames.
+    pub pages_1gib: bool,
+    /// Whether the `invept` instruction is supported.
+    pub invept: bool,
+    /// Whether the accessed and dirty flags of EPT entries are supported.
+    pub accessed_dirty: bool,
+    /// Whether EPT violations report advanced exit information.
+    pub advanced_exit_information: bool,
+    /// Whether supervisor shadow-stack control is supported.
+    pub supervisor_shadow_stack: bool,
+    /// Whether the single-context INVEPT type is supported.
+    pub invept_single_context: bool,
+    /// Whether the all-context INVEPT type is supported.
+    pub invept_all_context: bool,
+    /// Whether the `invvpid` instruction is supported.
+    pub invvpid: bool,
+    /// Whether the individual-address INVVPID type is supported.
+    pub invvpid_individual_address: bool,
+    /// Whether the single-context INVVPID type is supported.
+    pub invvpid_single_context: bool,
+    /// Whether the all-context INVVPID type is supported.
+    pub invvpid_all_context: bool,
+    /// Whether the single-context-retaining-globals INVVPID type is supported.
+    pub invvpid_single_context_retaining_globals: bool,
+}
+
+impl EptVpidCapabilities {
+    /// Decodes the value of `IA32_VMX_EPT_VPID_CAP`.
+    #[inline]
+    pub const fn from_raw(value: u64) -> Self {
+        EptVpidCapabilities {
+            execute_only: value & 1 != 0,
+            page_walk_length_4: value & (1 << 6) != 0,
+            page_walk_length_5: value & (1 << 7) != 0,
+            uncacheable: value & (1 << 8) != 0,
+            write_back: value & (1 << 14) != 0,
+            pages_2mib: value & (1 << 16) != 0,
+            pages_1gib: value & (1 << 17) != 0,
+            invept: value & (1 << 20) != 0,
+            accessed_dirty: value & (1 << 21) != 0,
+            advanced_exit_information: value & (1 << 22) != 0,
+            supervisor_shadow_stack: value & (1 << 23) != 0,
+            invept_single_context: value & (1 << 25) != 0,
+            invept_all_context: value & (1 << 26) != 0,
+            invvpid: value & (1 << 32) != 0,
+            invvpid_individual_address: value & (1 << 40) != 0,
+            invvpid_single_context: value & (1 << 41) != 0,
+            invvpid_all_context: value & (1 << 42) != 0,
+            invvpid_single_context_retaining_globals: value & (1 << 43) != 0,
+        }
+    }
+
+    /// Returns whether the EPT tables may be accessed with the given memory type.
+    ///
+    /// Only the uncacheable and write-back memory types can be supported.
+    #[inline]
+    pub const fn supports_memory_type(&self, memory_type: EptMemoryType) -> bool {
+        match memory_type {
+            EptMemoryType::Uncacheable => self.uncacheable,
+            EptMemoryType::WriteBack => self.write_back,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the given number of EPT table levels is supported.
+    #[inline]
+    pub const fn supports_page_walk_length(&self, length: u8) -> bool {
+        match length {
+            4 => self.page_walk_length_4,
+            5 => self.page_walk_length_5,
+            _ => false,
+        }
+    }
+
+    /// Returns whether EPT entries may map frames of size `S`.
+    #[inline]
+    pub fn supports_page_size<S: PageSize>(&self) -> bool {
+        match S::SIZE {
+            Size4KiB::SIZE => true,
+            Size2MiB::SIZE => self.pages_2mib,
+            Size1GiB::SIZE => self.pages_1gib,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+mod x86_64 {
+    use super::*;
+    use crate::addr::{PhysAddr, VirtAddr};
+    use crate::structures::paging::PhysFrame;
+    #[cfg(not(feature = "external_asm"))]
+    use core::arch::asm;
+
+    impl Msr {
+        /// Read 64 bits msr register.
+        ///
+        /// ## Safety
+        ///
+        /// The caller must ensure that this read operation has no unsafe side
+        /// effects.
+        #[inline]
+        pub unsafe fn read(&self) -> u64 {
+            #[cfg(not(feature = "external_asm"))]
+            {
+                let (high, low): (u32, u32);
+                asm!("rdmsr", in("ecx") self.0, out("eax") low, out("edx") high, options(nostack, preserves_flags));
+                ((high as u64) << 32) | (low as u64)
+            }
+
+            #[cfg(feature = "external_asm")]
+            crate::asm::x86_64_asm_rdmsr(self.0)
+        }
+
+        /// Write 64 bits to msr register.
+        ///
+        /// ## Safety
+        ///
+        /// The caller must ensure that this write operation has no unsafe side
+        /// effects.
+        #[inline]
+        pub unsafe fn write(&mut self, value: u64) {
+            #[cfg(not(feature = "external_asm"))]
+            {
+                let low = value as u32;
+                let high = (value >> 32) as u32;
+                asm!("wrmsr", in("ecx") self.0, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+            }
+
+            #[cfg(feature = "external_asm")]
+            crate::asm::x86_64_asm_wrmsr(self.0, value);
+        }
+    }
+
+    impl Efer {
+        /// Read the current EFER flags.
+        #[inline]
+        pub fn read() -> EferFlags {
+            EferFlags::from_bits_truncate(Self::read_raw())
+        }
+
+        /// Read the current raw EFER flags.
+        #[inline]
+        pub fn read_raw() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+
+        /// Write the EFER flags, preserving reserved values.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because it's possible to break memory
+        /// safety with wrong flags, e.g. by disabling long mode.
+        #[inline]
+        pub unsafe fn write(flags: EferFlags) {
+            let old_value = Self::read_raw();
+            let reserved = old_value & !(EferFlags::all().bits());
+            let new_value = reserved | flags.bits();
+
+            Self::write_raw(new_value);
+        }
+
+        /// Write the EFER flags.
+        ///
+        /// Does not preserve any bits, including reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because it's possible to
+        /// break memory safety with wrong flags, e.g. by disabling long mode.
+        #[inline]
+        pub unsafe fn write_raw(flags: u64) {
+            let mut msr = Self::MSR;
+            msr.write(flags);
+        }
+
+        /// Update EFER flags.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because it's possible to break memory
+        /// safety with wrong flags, e.g. by disabling long mode.
+        #[inline]
+        pub unsafe fn update<F>(f: F)
+        where
+            F: FnOnce(&mut EferFlags),
+        {
+            let mut flags = Self::read();
+            f(&mut flags);
+            Self::write(flags);
+        }
+    }
+
+    impl FsBase {
+        /// Read the current FsBase register.
+        #[inline]
+        pub fn read() -> VirtAddr {
+            VirtAddr::new(unsafe { Self::MSR.read() })
+        }
+
+        /// Write a given virtual address to the FS.Base register.
+        #[inline]
+        pub fn write(address: VirtAddr) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(address.as_u64()) };
+        }
+    }
+
+    impl GsBase {
+        /// Read the current GsBase register.
+        #[inline]
+        pub fn read() -> VirtAddr {
+            VirtAddr::new(unsafe { Self::MSR.read() })
+        }
+
+        /// Write a given virtual address to the GS.Base register.
+        #[inline]
+        pub fn write(address: VirtAddr) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(address.as_u64()) };
+        }
+    }
+
+    impl KernelGsBase {
+        /// Read the current KernelGsBase register.
+        #[inline]
+        pub fn read() -> VirtAddr {
+            VirtAddr::new(unsafe { Self::MSR.read() })
+        }
+
+        /// Write a given virtual address to the KernelGsBase register.
+        #[inline]
+        pub fn write(address: VirtAddr) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(address.as_u64()) };
+        }
+    }
+
+    impl Star {
+        /// Read the Ring 0 and Ring 3 segment bases.
+        /// The remaining fields are ignored because they are
+        /// not valid for long mode.
+        ///
+        /// # Returns
+        /// - Field 1 (SYSRET): The CS selector is set to this field + 16. SS.Sel is set to
+        /// this field + 8. Because SYSRET always returns to CPL 3, the
+        /// RPL bits 1:0 should be initialized to 11b.
+        /// - Field 2 (SYSCALL): This field is copied directly into CS.Sel. SS.Sel is set to
+        ///  this field + 8. Because SYSCALL always switches to CPL 0, the RPL bits
+        /// 33:32 should be initialized to 00b.
+        #[inline]
+        pub fn read_raw() -> (u16, u16) {
+            let msr_value = unsafe { Self::MSR.read() };
+            let sysret = msr_value.get_bits(48..64);
+            let syscall = msr_value.get_bits(32..48);
+            (sysret.try_into().unwrap(), syscall.try_into().unwrap())
+        }
+
+        /// Read the Ring 0 and Ring 3 segment bases.
+        /// Returns
+        /// - CS Selector SYSRET
+        /// - SS Selector SYSRET
+        /// - CS Selector SYSCALL
+        /// - SS Selector SYSCALL
+        #[inline]
+        pub fn read() -> (
+            SegmentSelector,
+            SegmentSelector,
+            SegmentSelector,
+            SegmentSelector,
+        ) {
+            let raw = Self::read_raw();
+            (
+                SegmentSelector((raw.0 + 16).try_into().unwrap()),
+                SegmentSelector((raw.0 + 8).try_into().unwrap()),
+                SegmentSelector((raw.1).try_into().unwrap()),
+                SegmentSelector((raw.1 + 8).try_into().unwrap()),
+            )
+        }
+
+        /// Write the Ring 0 and Ring 3 segment bases.
+        /// The remaining fields are ignored because they are
+        /// not valid for long mode.
+        ///
+        /// # Parameters
+        /// - sysret: The CS selector is set to this field + 16. SS.Sel is set to
+        /// this field + 8. Because SYSRET always returns to CPL 3, the
+        /// RPL bits 1:0 should be initialized to 11b.
+        /// - syscall: This field is copied directly into CS.Sel. SS.Sel is set to
+        ///  this field + 8. Because SYSCALL always switches to CPL 0, the RPL bits
+        /// 33:32 should be initialized to 00b.
+        ///
+        /// # Safety
+        ///
+        /// Unsafe because this can cause system instability if passed in the
+        /// wrong values for the fields.
+        #[inline]
+        pub unsafe fn write_raw(sysret: u16, syscall: u16) {
+            let mut msr_value = 0u64;
+            msr_value.set_bits(48..64, sysret.into());
+            msr_value.set_bits(32..48, syscall.into());
+            let mut msr = Self::MSR;
+            msr.write(msr_value);
+        }
+
+        /// Write the Ring 0 and Ring 3 segment bases.
+        /// The remaining fields are ignored because they are
+        /// not valid for long mode.
+        /// This function will fail if the segment selectors are
+        /// not in the correct offset of each other or if the
+        /// segment selectors do not have correct privileges.
+        #[inline]
+        pub fn write(
+            cs_sysret: SegmentSelector,
+            ss_sysret: SegmentSelector,
+            cs_syscall: SegmentSelector,
+            ss_syscall: SegmentSelector,
+        ) -> Result<(), &'static str> {
+            if cs_sysret.0 - 16 != ss_sysret.0 - 8 {
+                return Err("Sysret CS and SS is not offset by 8.");
+            }
+
+            if cs_syscall.0 != ss_syscall.0 - 8 {
+                return Err("Syscall CS and SS is not offset by 8.");
+            }
+
+            if ss_sysret.rpl() != PrivilegeLevel::Ring3 {
+                return Err("Sysret's segment must be a Ring3 segment.");
+            }
+
+            if ss_syscall.rpl() != PrivilegeLevel::Ring0 {
+                return Err("Syscall's segment must be a Ring0 segment.");
+            }
+
+            unsafe { Self::write_raw(ss_sysret.0 - 8, cs_syscall.0) };
+
+            Ok(())
+        }
+    }
+
+    impl LStar {
+        /// Read the current LStar register.
+        /// This holds the target RIP of a syscall.
+        #[inline]
+        pub fn read() -> VirtAddr {
+            VirtAddr::new(unsafe { Self::MSR.read() })
+        }
+
+        /// Write a given virtual address to the LStar register.
+        /// This holds the target RIP of a syscall.
+        #[inline]
+        pub fn write(address: VirtAddr) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(address.as_u64()) };
+        }
+    }
+
+    impl SFMask {
+        /// Read to the SFMask register.
+        /// The SFMASK register is used to specify which RFLAGS bits
+        /// are cleared during a SYSCALL. In long mode, SFMASK is used
+        /// to specify which RFLAGS bits are cleared when SYSCALL is
+        /// executed. If a bit in SFMASK is set to 1, the corresponding
+        /// bit in RFLAGS is cleared to 0. If a bit in SFMASK is cleared
+        /// to 0, the corresponding rFLAGS bit is not modified.
+        #[inline]
+        pub fn read() -> RFlags {
+            RFlags::from_bits(unsafe { Self::MSR.read() }).unwrap()
+        }
+
+        /// Write to the SFMask register.
+        /// The SFMASK register is used to specify which RFLAGS bits
+        /// are cleared during a SYSCALL. In long mode, SFMASK is used
+        /// to specify which RFLAGS bits are cleared when SYSCALL is
+        /// executed. If a bit in SFMASK is set to 1, the corresponding
+        /// bit in RFLAGS is cleared to 0. If a bit in SFMASK is cleared
+        /// to 0, the corresponding rFLAGS bit is not modified.
+        #[inline]
+        pub fn write(value: RFlags) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(value.bits()) };
+        }
+    }
+
+    impl ApicBase {
+        /// Read the physical frame of the local APIC registers and the current flags.
+        #[inline]
+        pub fn read() -> (PhysFrame, ApicBaseFlags) {
+            let value = unsafe { Self::MSR.read() };
+            let flags = ApicBaseFlags::from_bits_truncate(value);
+            let frame =
+                PhysFrame::containing_address(PhysAddr::new(value & 0x_000f_ffff_ffff_f000));
+            (frame, flags)
+        }
+
+        /// Write the physical frame of the local APIC registers and the flags.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because relocating or disabling the local APIC can break memory
+        /// safety, e.g. when the old register frame is still accessed.
+        #[inline]
+        pub unsafe fn write(frame: PhysFrame, flags: ApicBaseFlags) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & !(0x_000f_ffff_ffff_f000 | ApicBaseFlags::all().bits());
+            let new_value = reserved | frame.start_address().as_u64() | flags.bits();
+
+            let mut msr = Self::MSR;
+            msr.write(new_value);
+        }
+    }
+
+    impl TscDeadline {
+        /// Read the current TSC deadline of the local APIC timer.
+        ///
+        /// Returns zero if the timer is disarmed or the deadline has already passed.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+
+        /// Arm the local APIC timer for the given TSC value, or disarm it by writing zero.
+        ///
+        /// Only has an effect if the local APIC timer is in TSC-deadline mode.
+        #[inline]
+        pub fn write(deadline: u64) {
+            let mut msr = Self::MSR;
+            unsafe { msr.write(deadline) };
+        }
+    }
+    impl SpecCtrl {
+        /// Read the current speculation control flags.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if CPUID reports support for IBRS, STIBP, or SSBD; see the
+        /// [`mitigations`](crate::mitigations) module. Otherwise, reading it causes a general
+        /// protection fault.
+        #[inline]
+        pub unsafe fn read() -> SpecCtrlFlags {
+            SpecCtrlFlags::from_bits_truncate(Self::MSR.read())
+        }
+
+        /// Write the speculation control flags.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if CPUID reports support for IBRS, STIBP, or SSBD; see the
+        /// [`mitigations`](crate::mitigations) module. Otherwise, writing it causes a general
+        /// protection fault. The caller must also ensure that each written flag is supported.
+        #[inline]
+        pub unsafe fn write(flags: SpecCtrlFlags) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & !SpecCtrlFlags::all().bits();
+            let mut msr = Self::MSR;
+            msr.write(reserved | flags.bits());
+        }
+
+        /// Update the speculation control flags.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// The same requirements as for [`SpecCtrl::write`] apply.
+        #[inline]
+        pub unsafe fn update<F>(f: F)
+        where
+            F: FnOnce(&mut SpecCtrlFlags),
+        {
+            let mut flags = Self::read();
+            f(&mut flags);
+            Self::write(flags);
+        }
+    }
+
+    impl PredCmd {
+        /// Issue an indirect branch prediction barrier (IBPB).
+        ///
+        /// Prevents indirect branch targets trained before the barrier from controlling the
+        /// predictions of indirect branches executed after it.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if CPUID reports support for IBPB; see the
+        /// [`mitigations`](crate::mitigations) module. Otherwise, writing it causes a general
+        /// protection fault.
+        #[inline]
+        pub unsafe fn indirect_branch_prediction_barrier() {
+            let mut msr = Self::MSR;
+            msr.write(1);
+        }
+    }
+
+    impl ArchCapabilities {
+        /// Read the architectural capabilities.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if CPUID reports support for it; see the
+        /// [`mitigations`](crate::mitigations) module. Otherwise, reading it causes a general
+        /// protection fault.
+        #[inline]
+        pub unsafe fn read() -> ArchCapabilitiesFlags {
+            ArchCapabilitiesFlags::from_bits_truncate(Self::MSR.read())
+        }
+    }
+
+    impl FlushCmd {
+        /// Write back and invalidate the L1 data cache.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if CPUID reports support for the L1D flush; see the
+        /// [`mitigations`](crate::mitigations) module. Otherwise, writing it causes a general
+        /// protection fault.
+        #[inline]
+        pub unsafe fn flush_l1d() {
+            let mut msr = Self::MSR;
+            msr.write(1);
+        }
+    }
+
+    impl McgCap {
+        /// Read the number of machine check banks and the capability flags.
+        #[inline]
+        pub fn read() -> (u8, McgCapFlags) {
+            let value = unsafe { Self::MSR.read() };
+            (value as u8, McgCapFlags::from_bits_truncate(value))
+        }
+    }
+
+    impl McgStatus {
+        /// Read the machine check global status flags.
+        #[inline]
+        pub fn read() -> McgStatusFlags {
+            McgStatusFlags::from_bits_truncate(unsafe { Self::MSR.read() })
+        }
+
+        /// Write the machine check global status flags.
+        ///
+        /// Preserves the value of reserved fields. A machine check handler clears
+        /// `IN_PROGRESS` before returning, after it has read the state of the banks.
+        #[inline]
+        pub fn write(flags: McgStatusFlags) {
+            let old_value = unsafe { Self::MSR.read() };
+            let reserved = old_value & !McgStatusFlags::all().bits();
+            let mut msr = Self::MSR;
+            unsafe { msr.write(reserved | flags.bits()) };
+        }
+    }
+
+    impl PerfEvtSel {
+        /// Write the event select register of the given counter.
+        ///
+        /// The counter mask, bits 24..32, is set to zero, so the counter increments by the
+        /// number of events in each cycle.
+        ///
+        /// ## Safety
+        ///
+        /// The counter must be below the number of counters reported by
+        /// [`PerfmonInfo`](crate::cpuid::perfmon::PerfmonInfo), otherwise the write causes a
+        /// general protection fault. Also, the caller must ensure that the counter isn't used
+        /// by other code and, if `APIC_INTERRUPT` is set, that the overflow interrupt is
+        /// handled.
+        #[inline]
+        pub unsafe fn write(counter: u8, event: u8, unit_mask: u8, flags: PerfEvtSelFlags) {
+            let value = u64::from(event) | u64::from(unit_mask) << 8 | flags.bits();
+            Self::msr(counter).write(value);
+        }
+
+        /// Read the flags of the event select register of the given counter.
+        #[inline]
+        pub fn read_flags(counter: u8) -> PerfEvtSelFlags {
+            PerfEvtSelFlags::from_bits_truncate(unsafe { Self::msr(counter).read() })
+        }
+    }
+
+    impl Pmc {
+        /// Read the value of the given counter.
+        #[inline]
+        pub fn read(counter: u8) -> u64 {
+            unsafe { Self::msr(counter).read() }
+        }
+
+        /// Write the value of the given counter.
+        ///
+        /// Only bits 0..32 of the value are written, bit 31 is sign extended to the full
+        /// width of the counter.
+        ///
+        /// ## Safety
+        ///
+        /// The counter must be below the number of counters reported by
+        /// [`PerfmonInfo`](crate::cpuid::perfmon::PerfmonInfo), otherwise the write causes a
+        /// general protection fault. Also, the caller must ensure that the counter isn't used
+        /// by other code, since the write changes when its next overflow interrupt occurs.
+        #[inline]
+        pub unsafe fn write(counter: u8, value: u64) {
+            Self::msr(counter).write(value);
+        }
+    }
+
+    impl PerfGlobalStatus {
+        /// Read the overflow status. Bit `n` is set if general-purpose counter `n` overflowed.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+    }
+
+    impl PerfGlobalCtrl {
+        /// Read the global enable bits. Bit `n` enables general-purpose counter `n`.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+
+        /// Write the global enable bits. Bit `n` enables general-purpose counter `n`.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if [`PerfmonInfo`](crate::cpuid::perfmon::PerfmonInfo)
+        /// reports version 2 or later, and only the bits of existing counters may be set.
+        /// Otherwise, the write causes a general protection fault. Also, the caller must
+        /// ensure that the changed counters aren't used by other code.
+        #[inline]
+        pub unsafe fn write(value: u64) {
+            let mut msr = Self::MSR;
+            msr.write(value);
+        }
+    }
+
+    impl PerfGlobalOvfCtrl {
+        /// Clear the overflow status of the counters whose bits are set in `value`.
+        ///
+        /// ## Safety
+        ///
+        /// The register only exists if [`PerfmonInfo`](crate::cpuid::perfmon::PerfmonInfo)
+        /// reports version 2 or later, and only the bits of existing counters may be set.
+        /// Otherwise, the write causes a general protection fault.
+        #[inline]
+        pub unsafe fn clear(value: u64) {
+            let mut msr = Self::MSR;
+            msr.write(value);
+        }
+    }
+
+    impl FeatureControl {
+        /// Read the current feature control flags.
+        #[inline]
+        pub fn read() -> FeatureControlFlags {
+            FeatureControlFlags::from_bits_truncate(unsafe { Self::MSR.read() })
+        }
+
+        /// Write the feature control flags.
+        ///
+        /// Preserves the value of reserved fields. The register can't be written while it is
+        /// locked.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because writing the register while it is locked causes a general protection
+        /// fault.
+        #[inline]
+        pub unsafe fn write(flags: FeatureControlFlags) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & !FeatureControlFlags::all().bits();
+            let mut msr = Self::MSR;
+            msr.write(reserved | flags.bits());
+        }
+    }
+
+    impl RtitCtl {
+        /// Read the current Intel Processor Trace control flags.
+        #[inline]
+        pub fn read() -> RtitCtlFlags {
+            RtitCtlFlags::from_bits_truncate(Self::read_raw())
+        }
+
+        /// Read the current raw value of the register, including the multi-bit fields.
+        #[inline]
+        pub fn read_raw() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+
+        /// Write the Intel Processor Trace control flags.
+        ///
+        /// Preserves the value of the multi-bit fields. Most fields may only be changed while
+        /// tracing is disabled.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because enabling tracing makes the processor write to the configured
+        /// output region.
+        #[inline]
+        pub unsafe fn write(flags: RtitCtlFlags) {
+            let fields = Self::read_raw() & !RtitCtlFlags::all().bits();
+            Self::write_raw(fields | flags.bits());
+        }
+
+        /// Write the raw value of the register.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because enabling tracing makes the processor write to the configured
+        /// output region.
+        #[inline]
+        pub unsafe fn write_raw(value: u64) {
+            let mut msr = Self::MSR;
+            msr.write(value);
+        }
+    }
+
+    impl VmxBasic {
+        /// Read the basic VMX capabilities.
+        #[inline]
+        pub fn read() -> VmxBasicInfo {
+            VmxBasicInfo::from_raw(unsafe { Self::MSR.read() })
+        }
+
+        /// Read the VMCS revision identifier, which must be written to the start of the VMXON
+        /// region and of each VMCS region.
+        #[inline]
+        pub fn revision_id() -> u32 {
+            Self::read().revision_id
+        }
+    }
+
+    impl VmxPinbasedCtls {
+        /// Read the allowed settings of the pin-based VM-execution controls.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxProcbasedCtls {
+        /// Read the allowed settings of the primary processor-based VM-execution controls.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxExitCtls {
+        /// Read the allowed settings of the VM-exit controls.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxEntryCtls {
+        /// Read the allowed settings of the VM-entry controls.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxProcbasedCtls2 {
+        /// Read the allowed settings of the secondary processor-based VM-execution controls.
+        ///
+        /// The register only exists if the "activate secondary controls" control (bit 31) of
+        /// the primary processor-based controls may be set.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxTruePinbasedCtls {
+        /// Read the allowed settings of the pin-based VM-execution controls, including the default1 controls.
+        ///
+        /// The register only exists if [`VmxBasicInfo::true_controls`] is set.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxTrueProcbasedCtls {
+        /// Read the allowed settings of the primary processor-based VM-execution controls, including the
+        /// default1 controls.
+        ///
+        /// The register only exists if [`VmxBasicInfo::true_controls`] is set.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxTrueExitCtls {
+        /// Read the allowed settings of the VM-exit controls, including the default1 controls.
+        ///
+        /// The register only exists if [`VmxBasicInfo::true_controls`] is set.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxTrueEntryCtls {
+        /// Read the allowed settings of the VM-entry controls, including the default1 controls.
+        ///
+        /// The register only exists if [`VmxBasicInfo::true_controls`] is set.
+        #[inline]
+        pub fn read() -> VmxControlCapability {
+            VmxControlCapability::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl VmxControl {
+        /// Read the allowed settings of the control field.
+        ///
+        /// The `IA32_VMX_TRUE_*` registers are used if they are supported, so that default1
+        /// controls can be cleared. The secondary processor-based controls allow no settings if
+        /// they can't be activated.
+        pub fn capability(self) -> VmxControlCapability {
+            let true_controls = VmxBasic::read().true_controls;
+            match self {
+                VmxControl::PinBased if true_controls => VmxTruePinbasedCtls::read(),
+                VmxControl::PinBased => VmxPinbasedCtls::read(),
+                VmxControl::PrimaryProcBased if true_controls => VmxTrueProcbasedCtls::read(),
+                VmxControl::PrimaryProcBased => VmxProcbasedCtls::read(),
+                VmxControl::SecondaryProcBased => {
+                    if VmxProcbasedCtls::read().allowed_1 & (1 << 31) != 0 {
+                        VmxProcbasedCtls2::read()
+                    } else {
+                        VmxControlCapability::from_raw(0)
+                    }
+                }
+                VmxControl::Exit if true_controls => VmxTrueExitCtls::read(),
+                VmxControl::Exit => VmxExitCtls::read(),
+                VmxControl::Entry if true_controls => VmxTrueEntryCtls::read(),
+                VmxControl::Entry => VmxEntryCtls::read(),
+            }
+        }
+
+        /// Returns the value of the control field for the given controls, with the controls
+        /// that must be 1 set as well.
+        ///
+        /// Returns an error if one of the given controls isn't supported.
+        #[inline]
+        pub fn adjust(self, controls: u32) -> Result<u32, UnsupportedControls> {
+            self.capability()
+                .try_adjust(controls)
+                .map_err(|bits| UnsupportedControls {
+                    control: self,
+                    bits,
+                })
+        }
+    }
+
+    impl VmxMisc {
+        /// Read the miscellaneous VMX capabilities.
+        #[inline]
+        pub fn read() -> VmxMiscInfo {
+            VmxMiscInfo::from_raw(unsafe { Self::MSR.read() })
+        }
+
+        /// Read the rate of the VMX-preemption timer: it counts down by 1 every time bit X of
+        /// the TSC changes, where X is the returned value.
+        #[inline]
+        pub fn preemption_timer_rate() -> u8 {
+            Self::read().preemption_timer_rate
+        }
+    }
+
+    impl VmxCr0Fixed0 {
+        /// Read the CR0 bits that must be 1 in VMX operation.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+    }
 
-#[cfg(target_arch = "x86_64")]
-mod x86_64 {
-    use super::*;
-    use crate::addr::VirtAddr;
+    impl VmxCr0Fixed1 {
+        /// Read the CR0 bits that may be 1 in VMX operation.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+    }
 
-    impl Msr {
-        /// Read 64 bits msr register.
-        ///
-        /// ## Safety
-        ///
-        /// The caller must ensure that this read operation has no unsafe side
-        /// effects.
+    impl VmxCr4Fixed0 {
+        /// Read the CR4 bits that must be 1 in VMX operation.
         #[inline]
-        pub unsafe fn read(&self) -> u64 {
-            #[cfg(feature = "inline_asm")]
-            {
-                let (high, low): (u32, u32);
-                asm!("rdmsr" : "={eax}" (low), "={edx}" (high) : "{ecx}" (self.0) : "memory" : "volatile");
-                ((high as u64) << 32) | (low as u64)
-            }
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+    }
 
-            #[cfg(not(feature = "inline_asm"))]
-            crate::asm::x86_64_asm_rdmsr(self.0)
+    impl VmxCr4Fixed1 {
+        /// Read the CR4 bits that may be 1 in VMX operation.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
         }
+    }
 
-        /// Write 64 bits to msr register.
-        ///
-        /// ## Safety
-        ///
-        /// The caller must ensure that this write operation has no unsafe side
-        /// effects.
+    impl VmxFixedBits {
+        /// Read the fixed bits of CR0.
         #[inline]
-        pub unsafe fn write(&mut self, value: u64) {
-            #[cfg(feature = "inline_asm")]
-            {
-                let low = value as u32;
-                let high = (value >> 32) as u32;
-                asm!("wrmsr" :: "{ecx}" (self.0), "{eax}" (low), "{edx}" (high) : "memory" : "volatile" );
+        pub fn cr0() -> Self {
+            VmxFixedBits {
+                fixed_0: VmxCr0Fixed0::read(),
+                fixed_1: VmxCr0Fixed1::read(),
             }
+        }
 
-            #[cfg(not(feature = "inline_asm"))]
-            crate::asm::x86_64_asm_wrmsr(self.0, value);
+        /// Read the fixed bits of CR4.
+        #[inline]
+        pub fn cr4() -> Self {
+            VmxFixedBits {
+                fixed_0: VmxCr4Fixed0::read(),
+                fixed_1: VmxCr4Fixed1::read(),
+            }
         }
     }
 
-    impl Efer {
-        /// Read the current EFER flags.
+    impl VmxEptVpidCap {
+        /// Read the supported EPT and VPID features.
         #[inline]
-        pub fn read() -> EferFlags {
-            EferFlags::from_bits_truncate(Self::read_raw())
+        pub fn read() -> EptVpidCapabilities {
+            EptVpidCapabilities::from_raw(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl KvmWallClockNew {
+        /// Make the hypervisor write the wall clock time of boot to the structure at the given
+        /// guest-physical address.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because the hypervisor writes a
+        /// [`PvclockWallClock`](crate::kvm::PvclockWallClock) to the address, and because the
+        /// register only exists if KVM reports the `CLOCKSOURCE2` feature.
+        #[inline]
+        pub unsafe fn write(addr: PhysAddr) {
+            let mut msr = Self::MSR;
+            msr.write(addr.as_u64());
         }
+    }
 
-        /// Read the current raw EFER flags.
+    impl KvmSystemTimeNew {
+        /// Read the guest-physical address of the kvmclock structure and whether it is enabled.
         #[inline]
-        pub fn read_raw() -> u64 {
-            unsafe { Self::MSR.read() }
+        pub fn read() -> (PhysAddr, bool) {
+            let value = unsafe { Self::MSR.read() };
+            (PhysAddr::new(value & !1), value & 1 != 0)
         }
 
-        /// Write the EFER flags, preserving reserved values.
-        ///
-        /// Preserves the value of reserved fields.
+        /// Write the guest-physical address of the kvmclock structure of the current processor
+        /// and whether the hypervisor updates it.
         ///
         /// ## Safety
         ///
-        /// Unsafe because it's possible to break memory
-        /// safety with wrong flags, e.g. by disabling long mode.
+        /// Unsafe because the hypervisor keeps writing a
+        /// [`PvclockVcpuTimeInfo`](crate::kvm::PvclockVcpuTimeInfo) to the address while it is
+        /// enabled, and because the register only exists if KVM reports the `CLOCKSOURCE2`
+        /// feature.
         #[inline]
-        pub unsafe fn write(flags: EferFlags) {
-            let old_value = Self::read_raw();
-            let reserved = old_value & !(EferFlags::all().bits());
-            let new_value = reserved | flags.bits();
+        pub unsafe fn write(addr: PhysAddr, enable: bool) {
+            let mut msr = Self::MSR;
+            msr.write(addr.as_u64() | u64::from(enable));
+        }
+    }
 
-            Self::write_raw(new_value);
+    impl HvGuestOsId {
+        /// Read the guest operating system identity.
+        #[inline]
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
         }
 
-        /// Write the EFER flags.
-        ///
-        /// Does not preserve any bits, including reserved fields.
+        /// Write the guest operating system identity, which must be nonzero before the
+        /// hypercall page can be enabled.
         ///
         /// ## Safety
         ///
-        /// Unsafe because it's possible to
-        /// break memory safety with wrong flags, e.g. by disabling long mode.
+        /// Unsafe because writing zero disables the hypercall page, and because the register
+        /// only exists if Hyper-V reports access to the hypercall registers.
         #[inline]
-        pub unsafe fn write_raw(flags: u64) {
-            Self::MSR.write(flags);
+        pub unsafe fn write(id: u64) {
+            let mut msr = Self::MSR;
+            msr.write(id);
         }
+    }
 
-        /// Update EFER flags.
+    impl HvHypercall {
+        /// Read the frame of the hypercall page, whether the page is enabled and whether the
+        /// register is locked.
+        #[inline]
+        pub fn read() -> (PhysFrame, bool, bool) {
+            let value = unsafe { Self::MSR.read() };
+            let frame =
+                PhysFrame::containing_address(PhysAddr::new(value & 0x_000f_ffff_ffff_f000));
+            (frame, value & 1 != 0, value & (1 << 1) != 0)
+        }
+
+        /// Write the frame of the hypercall page and whether the page is enabled.
         ///
         /// Preserves the value of reserved fields.
         ///
         /// ## Safety
         ///
-        /// Unsafe because it's possible to break memory
-        /// safety with wrong flags, e.g. by disabling long mode.
+        /// Unsafe because the hypervisor overwrites the frame with the hypercall code while
+        /// the page is enabled, and because the register only exists if Hyper-V reports
+        /// access to the hypercall registers.
         #[inline]
-        pub unsafe fn update<F>(f: F)
-        where
-            F: FnOnce(&mut EferFlags),
-        {
-            let mut flags = Self::read();
-            f(&mut flags);
-            Self::write(flags);
+        pub unsafe fn write(frame: PhysFrame, enable: bool) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & 0xffc;
+            let mut msr = Self::MSR;
+            msr.write(reserved | frame.start_address().as_u64() | u64::from(enable));
         }
     }
 
-    impl FsBase {
-        /// Read the current FsBase register.
+    impl HvVpIndex {
+        /// Read the virtual processor index of the current processor, which is used to
+        /// address processors in hypercalls.
         #[inline]
-        pub fn read() -> VirtAddr {
-            VirtAddr::new(unsafe { Self::MSR.read() })
+        pub fn read() -> u32 {
+            unsafe { Self::MSR.read() as u32 }
         }
+    }
 
-        /// Write a given virtual address to the FS.Base register.
+    impl HvTimeRefCount {
+        /// Read the partition reference counter in units of 100 nanoseconds.
         #[inline]
-        pub fn write(address: VirtAddr) {
-            unsafe { Self::MSR.write(address.as_u64()) };
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
         }
     }
 
-    impl GsBase {
-        /// Read the current GsBase register.
+    impl HvReferenceTsc {
+        /// Read the frame of the reference TSC page and whether the page is enabled.
         #[inline]
-        pub fn read() -> VirtAddr {
-            VirtAddr::new(unsafe { Self::MSR.read() })
+        pub fn read() -> (PhysFrame, bool) {
+            let value = unsafe { Self::MSR.read() };
+            let frame =
+                PhysFrame::containing_address(PhysAddr::new(value & 0x_000f_ffff_ffff_f000));
+            (frame, value & 1 != 0)
         }
 
-        /// Write a given virtual address to the GS.Base register.
+        /// Write the frame of the reference TSC page and whether the page is enabled.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because the hypervisor writes a
+        /// [`HvReferenceTscPage`](crate::hyperv::HvReferenceTscPage) to the frame while the
+        /// page is enabled, and because the register only exists if Hyper-V reports access to
+        /// the reference TSC page.
         #[inline]
-        pub fn write(address: VirtAddr) {
-            unsafe { Self::MSR.write(address.as_u64()) };
+        pub unsafe fn write(frame: PhysFrame, enable: bool) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & 0xffe;
+            let mut msr = Self::MSR;
+            msr.write(reserved | frame.start_address().as_u64() | u64::from(enable));
         }
     }
 
-    impl KernelGsBase {
-        /// Read the current KernelGsBase register.
+    impl VmCr {
+        /// Read the current SVM control flags.
         #[inline]
-        pub fn read() -> VirtAddr {
-            VirtAddr::new(unsafe { Self::MSR.read() })
+        pub fn read() -> VmCrFlags {
+            VmCrFlags::from_bits_truncate(unsafe { Self::MSR.read() })
         }
 
-        /// Write a given virtual address to the KernelGsBase register.
+        /// Write the SVM control flags.
+        ///
+        /// Preserves the value of reserved fields.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because the register only exists on processors with SVM, and because
+        /// changing `SVM_DISABLE` while the register is locked causes a general protection
+        /// fault.
         #[inline]
-        pub fn write(address: VirtAddr) {
-            unsafe { Self::MSR.write(address.as_u64()) };
+        pub unsafe fn write(flags: VmCrFlags) {
+            let old_value = Self::MSR.read();
+            let reserved = old_value & !VmCrFlags::all().bits();
+            let mut msr = Self::MSR;
+            msr.write(reserved | flags.bits());
         }
     }
 
-    impl Star {
-        /// Read the Ring 0 and Ring 3 segment bases.
-        /// The remaining fields are ignored because they are
-        /// not valid for long mode.
-        ///
-        /// # Returns
-        /// - Field 1 (SYSRET): The CS selector is set to this field + 16. SS.Sel is set to
-        /// this field + 8. Because SYSRET always returns to CPL 3, the
-        /// RPL bits 1:0 should be initialized to 11b.
-        /// - Field 2 (SYSCALL): This field is copied directly into CS.Sel. SS.Sel is set to
-        ///  this field + 8. Because SYSCALL always switches to CPL 0, the RPL bits
-        /// 33:32 should be initialized to 00b.
+    impl VmHsavePa {
+        /// Read the physical address of the host save area.
         #[inline]
-        pub fn read_raw() -> (u16, u16) {
-            let msr_value = unsafe { Self::MSR.read() };
-            let sysret = msr_value.get_bits(48..64);
-            let syscall = msr_value.get_bits(32..48);
-            (sysret.try_into().unwrap(), syscall.try_into().unwrap())
+        pub fn read() -> PhysAddr {
+            PhysAddr::new(unsafe { Self::MSR.read() })
         }
 
-        /// Read the Ring 0 and Ring 3 segment bases.
-        /// Returns
-        /// - CS Selector SYSRET
-        /// - SS Selector SYSRET
-        /// - CS Selector SYSCALL
-        /// - SS Selector SYSCALL
+        /// Write the frame of the host save area.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because the processor writes the host state to the frame on every `vmrun`,
+        /// so the frame must not be used for anything else while SVM is enabled.
         #[inline]
-        pub fn read() -> (
-            SegmentSelector,
-            SegmentSelector,
-            SegmentSelector,
-            SegmentSelector,
-        ) {
-            let raw = Self::read_raw();
-            (
-                SegmentSelector((raw.0 + 16).try_into().unwrap()),
-                SegmentSelector((raw.0 + 8).try_into().unwrap()),
-                SegmentSelector((raw.1).try_into().unwrap()),
-                SegmentSelector((raw.1 + 8).try_into().unwrap()),
-            )
+        pub unsafe fn write(frame: PhysFrame) {
+            let mut msr = Self::MSR;
+            msr.write(frame.start_address().as_u64());
         }
+    }
 
-        /// Write the Ring 0 and Ring 3 segment bases.
-        /// The remaining fields are ignored because they are
-        /// not valid for long mode.
-        ///
-        /// # Parameters
-        /// - sysret: The CS selector is set to this field + 16. SS.Sel is set to
-        /// this field + 8. Because SYSRET always returns to CPL 3, the
-        /// RPL bits 1:0 should be initialized to 11b.
-        /// - syscall: This field is copied directly into CS.Sel. SS.Sel is set to
-        ///  this field + 8. Because SYSCALL always switches to CPL 0, the RPL bits
-        /// 33:32 should be initialized to 00b.
+    impl SevStatus {
+        /// Read the active memory encryption features of the guest.
         ///
-        /// # Safety
+        /// The register only exists if CPUID reports SEV, see
+        /// [`sev::status`](crate::sev::status).
+        #[inline]
+        pub fn read() -> SevStatusFlags {
+            SevStatusFlags::from_bits_truncate(unsafe { Self::MSR.read() })
+        }
+    }
+
+    impl SevEsGhcb {
+        /// Read the raw value of the register, a GHCB address or a GHCB MSR protocol value.
         ///
-        /// Unsafe because this can cause system instability if passed in the
-        /// wrong values for the fields.
+        /// The register is accessed without causing a `#VC` exception.
         #[inline]
-        pub unsafe fn write_raw(sysret: u16, syscall: u16) {
-            let mut msr_value = 0u64;
-            msr_value.set_bits(48..64, sysret.into());
-            msr_value.set_bits(32..48, syscall.into());
-            Self::MSR.write(msr_value);
+        pub fn read() -> u64 {
+            unsafe { Self::MSR.read() }
         }
 
-        /// Write the Ring 0 and Ring 3 segment bases.
-        /// The remaining fields are ignored because they are
-        /// not valid for long mode.
-        /// This function will fail if the segment selectors are
-        /// not in the correct offset of each other or if the
-        /// segment selectors do not have correct privileges.
+        /// Write the raw value of the register.
+        ///
+        /// ## Safety
+        ///
+        /// Unsafe because the register only exists in SEV-ES guests, and because the
+        /// hypervisor writes to the GHCB at the given address on the next `vmgexit`.
         #[inline]
-        pub fn write(
-            cs_sysret: SegmentSelector,
-            ss_sysret: SegmentSelector,
-            cs_syscall: SegmentSelector,
-            ss_syscall: SegmentSelector,
-        ) -> Result<(), &'static str> {
-            if cs_sysret.0 - 16 != ss_sysret.0 - 8 {
-                return Err("Sysret CS and SS is not offset by 8.");
-            }
+        pub unsafe fn write(value: u64) {
+            let mut msr = Self::MSR;
+            msr.write(value);
+        }
+    }
+}
 
-            if cs_syscall.0 != ss_syscall.0 - 8 {
-                return Err("Syscall CS and SS is not offset by 8.");
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            if ss_sysret.rpl() != PrivilegeLevel::Ring3 {
-                return Err("Sysret's segment must be a Ring3 segment.");
+    #[test]
+    fn vmx_basic_info() {
+        assert_eq!(
+            VmxBasicInfo::from_raw(0x00da_0400_8000_0004),
+            VmxBasicInfo {
+                revision_id: 4,
+                region_size: 0x400,
+                physical_address_width_32: false,
+                dual_monitor: true,
+                memory_type: 6,
+                ins_outs_reporting: true,
+                true_controls: true,
+                no_error_code_requirement: false,
             }
+        );
+    }
 
-            if ss_syscall.rpl() != PrivilegeLevel::Ring0 {
-                return Err("Syscall's segment must be a Ring0 segment.");
+    #[test]
+    fn vmx_misc_info() {
+        assert_eq!(
+            VmxMiscInfo::from_raw(0x1_7404_c1e7),
+            VmxMiscInfo {
+                preemption_timer_rate: 7,
+                stores_lma: true,
+                activity_states: 0b111,
+                processor_trace: true,
+                smbase_readable: true,
+                cr3_targets: 4,
+                max_msr_list_entries: 1536,
+                vmwrite_all_fields: true,
+                zero_length_injection: true,
+                mseg_revision_id: 1,
             }
+        );
+    }
 
-            unsafe { Self::write_raw(ss_sysret.0 - 8, cs_syscall.0) };
-
-            Ok(())
-        }
+    #[test]
+    fn rtit_ctl_flags() {
+        // the MTC, cycle threshold and PSB frequencies and the address filter configuration
+        let fields = 0x0000_ffff_0f7b_c000;
+        assert_eq!(RtitCtlFlags::all().bits() & fields, 0);
+        let flags = RtitCtlFlags::TRACE_ENABLE
+            | RtitCtlFlags::USER_MODE
+            | RtitCtlFlags::TOPA
+            | RtitCtlFlags::BRANCH_ENABLE
+            | RtitCtlFlags::INJECT_PSB_PMI_ON_ENABLE;
+        assert_eq!(flags.bits(), 0x0100_0000_0000_2109);
+        assert_eq!(
+            RtitCtlFlags::from_bits_truncate(flags.bits() | fields),
+            flags
+        );
     }
 
-    impl LStar {
-        /// Read the current LStar register.
-        /// This holds the target RIP of a syscall.
-        #[inline]
-        pub fn read() -> VirtAddr {
-            VirtAddr::new(unsafe { Self::MSR.read() })
-        }
+    #[test]
+    fn vmx_control_capability() {
+        // bits 1, 2 and 4 must be 1, bits 0 to 7 may be 1
+        let capability = VmxControlCapability::from_raw(0xff_0000_0016);
+        assert_eq!(capability.try_adjust(1), Ok(0x17));
+        assert_eq!(capability.try_adjust(0x301), Err(0x300));
+        assert!(capability.is_allowed(0x17));
+        assert!(!capability.is_allowed(1));
+        assert_eq!(capability.adjust(0x301), 0x17);
+    }
 
-        /// Write a given virtual address to the LStar register.
-        /// This holds the target RIP of a syscall.
-        #[inline]
-        pub fn write(address: VirtAddr) {
-            unsafe { Self::MSR.write(address.as_u64()) };
-        }
+    #[test]
+    fn vmx_fixed_bits() {
+        // PE, NE and PG must be 1, CD and NW may be 1
+        let fixed = VmxFixedBits {
+            fixed_0: 0x8000_0021,
+            fixed_1: 0xffff_ffff,
+        };
+        assert!(!fixed.is_allowed(0x6000_0010));
+        let fixed = fixed.unrestricted_guest();
+        assert_eq!(fixed.adjust(0x6000_0010), 0x6000_0030);
+        assert!(fixed.is_allowed(0x6000_0030));
     }
 
-    impl SFMask {
-        /// Read to the SFMask register.
-        /// The SFMASK register is used to specify which RFLAGS bits
-        /// are cleared during a SYSCALL. In long mode, SFMASK is used
-        /// to specify which RFLAGS bits are cleared when SYSCALL is
-        /// executed. If a bit in SFMASK is set to 1, the corresponding
-        /// bit in RFLAGS is cleared to 0. If a bit in SFMASK is cleared
-        /// to 0, the corresponding rFLAGS bit is not modified.
-        #[inline]
-        pub fn read() -> RFlags {
-            RFlags::from_bits(unsafe { Self::MSR.read() }).unwrap()
-        }
+    #[test]
+    fn preemption_timer_ticks() {
+        let misc = VmxMiscInfo::from_raw(5);
+        assert_eq!(misc.tsc_to_preemption_ticks(1000), 31);
+        assert_eq!(misc.preemption_ticks_to_tsc(31), 992);
+        assert_eq!(misc.tsc_to_preemption_ticks(u64::MAX), u32::MAX);
+    }
 
-        /// Write to the SFMask register.
-        /// The SFMASK register is used to specify which RFLAGS bits
-        /// are cleared during a SYSCALL. In long mode, SFMASK is used
-        /// to specify which RFLAGS bits are cleared when SYSCALL is
-        /// executed. If a bit in SFMASK is set to 1, the corresponding
-        /// bit in RFLAGS is cleared to 0. If a bit in SFMASK is cleared
-        /// to 0, the corresponding rFLAGS bit is not modified.
-        #[inline]
-        pub fn write(value: RFlags) {
-            unsafe { Self::MSR.write(value.bits()) };
-        }
+    #[test]
+    fn ept_vpid_capabilities() {
+        let capabilities = EptVpidCapabilities::from_raw(0x700_0221_4141);
+        assert!(capabilities.execute_only);
+        assert!(capabilities.supports_page_walk_length(4));
+        assert!(!capabilities.supports_page_walk_length(5));
+        assert!(capabilities.supports_memory_type(EptMemoryType::WriteBack));
+        assert!(!capabilities.supports_memory_type(EptMemoryType::WriteThrough));
+        assert!(capabilities.supports_page_size::<Size2MiB>());
+        assert!(!capabilities.supports_page_size::<Size1GiB>());
+        assert!(capabilities.accessed_dirty && capabilities.invept_single_context);
+        assert!(!capabilities.invvpid && capabilities.invvpid_all_context);
     }
 }
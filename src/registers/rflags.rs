@@ -1,12 +1,13 @@
 //! Processor state stored in the RFLAGS register.
 
-#[cfg(target_arch = "x86_64")]
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 pub use self::x86_64::*;
 
 use bitflags::bitflags;
 
 bitflags! {
     /// The RFLAGS register.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct RFlags: u64 {
         /// Processor feature identification flag.
         ///
@@ -62,9 +63,13 @@ bitflags! {
     }
 }
 
-#[cfg(target_arch = "x86_64")]
+impl_flags_display!(RFlags);
+
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
 mod x86_64 {
     use super::*;
+    #[cfg(not(feature = "external_asm"))]
+    use core::arch::asm;
 
     /// Returns the current value of the RFLAGS register.
     ///
@@ -78,12 +83,19 @@ mod x86_64 {
     #[inline]
     pub fn read_raw() -> u64 {
         let r: u64;
-        #[cfg(feature = "inline_asm")]
+        #[cfg(all(target_arch = "x86_64", not(feature = "external_asm")))]
         unsafe {
-            asm!("pushfq; popq $0" : "=r"(r) :: "memory")
+            asm!("pushfq; pop {}", out(reg) r, options(nomem, preserves_flags));
         };
 
-        #[cfg(not(feature = "inline_asm"))]
+        #[cfg(target_arch = "x86")]
+        unsafe {
+            let raw: u32;
+            asm!("pushfd; pop {}", out(reg) raw, options(nomem, preserves_flags));
+            r = u64::from(raw);
+        };
+
+        #[cfg(feature = "external_asm")]
         unsafe {
             r = crate::asm::x86_64_asm_read_rflags();
         };
@@ -94,11 +106,14 @@ mod x86_64 {
     /// Writes the RFLAGS register, preserves reserved bits.
     #[inline]
     pub fn write(flags: RFlags) {
-        let old_value = read_raw();
-        let reserved = old_value & !(RFlags::all().bits());
-        let new_value = reserved | flags.bits();
+        write_raw(with_reserved(read_raw(), flags));
+    }
 
-        write_raw(new_value);
+    /// Returns the value of `flags` with the reserved bits of `old_value`.
+    #[inline]
+    fn with_reserved(old_value: u64, flags: RFlags) -> u64 {
+        let reserved = old_value & !(RFlags::all().bits());
+        reserved | flags.bits()
     }
 
     /// Writes the RFLAGS register.
@@ -106,12 +121,17 @@ mod x86_64 {
     /// Does not preserve any bits, including reserved bits.
     #[inline]
     pub fn write_raw(val: u64) {
-        #[cfg(feature = "inline_asm")]
+        #[cfg(all(target_arch = "x86_64", not(feature = "external_asm")))]
         unsafe {
-            asm!("pushq $0; popfq" :: "r"(val) : "memory" "flags")
+            asm!("push {}; popfq", in(reg) val, options(nomem))
         };
 
-        #[cfg(not(feature = "inline_asm"))]
+        #[cfg(target_arch = "x86")]
+        unsafe {
+            asm!("push {}; popfd", in(reg) val as u32, options(nomem))
+        };
+
+        #[cfg(feature = "external_asm")]
         unsafe {
             crate::asm::x86_64_asm_write_rflags(val)
         }
@@ -119,12 +139,33 @@ mod x86_64 {
 
     #[cfg(test)]
     mod test {
-        use crate::registers::rflags::read;
+        use super::with_reserved;
+        use crate::registers::rflags::{read, read_raw, write, RFlags};
 
         #[test]
         fn rflags_read() {
             let rflags = read();
             println!("{:#?}", rflags);
         }
+
+        #[test]
+        fn rflags_write() {
+            // bit 1 is reserved and always set, interrupts are enabled in user mode
+            write(read());
+            assert_eq!(read_raw() & 0b10, 0b10);
+            assert!(read().contains(RFlags::INTERRUPT_FLAG));
+        }
+
+        #[test]
+        fn rflags_reserved_bits() {
+            // the direction flag is only set on plain values, it must be clear across calls
+            let old_value = 1 << 40 | 1 << 10 | 1 << 9 | 1 << 1 | 1;
+            let flags = RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::ZERO_FLAG;
+            assert_eq!(
+                with_reserved(old_value, flags),
+                1 << 40 | 1 << 10 | 1 << 9 | 1 << 6 | 1 << 1
+            );
+            assert_eq!(with_reserved(old_value, RFlags::empty()), 1 << 40 | 1 << 1);
+        }
     }
 }
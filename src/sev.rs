@@ -0,0 +1,419 @@
+//! AMD memory encryption (SME and SEV) from the view of the kernel whose memory is encrypted.
+//!
+//! A page is encrypted if the C-bit is set in the physical address of the page table entry
+//! that maps it. The position of the C-bit is reported by CPUID leaf 0x8000_001F, see
+//! [`CBit::detect`]. In an SEV guest, memory that is shared with the hypervisor, e.g. buffers
+//! of emulated devices, has to be mapped with the C-bit clear, all other memory with the C-bit
+//! set. Whether the kernel runs in an SEV guest is reported by [`status`].
+//!
+//! The C-bit is part of the address field of an entry, which [`PageTableFlags`] doesn't cover,
+//! so it is set and cleared through [`CBit`] instead.
+//!
+//! An SEV-ES guest requests services of the hypervisor with `vmgexit`, either through a
+//! [`Ghcb`] page with [`ghcb_call`] or, before a GHCB is set up, with the GHCB MSR protocol
+//! through [`msr_protocol`].
+
+use crate::cpuid::memory_encryption::{MemoryEncryptionFeatures, MemoryEncryptionInfo};
+use crate::instructions::svm::vmgexit;
+use crate::registers::model_specific::{SevEsGhcb, SevStatus, SevStatusFlags};
+use crate::structures::ghcb::{Ghcb, GhcbField};
+use crate::structures::paging::page_table::{PageTableEntry, PageTableFlags};
+use crate::structures::paging::PhysFrame;
+use crate::structures::vmcb::SvmEvent;
+use crate::PhysAddr;
+use core::fmt;
+
+/// Returns the decoded SEV_STATUS MSR flags, i.e. the memory encryption features that are
+/// active for the current guest.
+///
+/// Returns empty flags without reading SEV_STATUS if CPUID doesn't report SEV, e.g. on the
+/// host or in a guest of another hypervisor.
+pub fn status() -> SevStatusFlags {
+    match MemoryEncryptionInfo::read() {
+        Some(info) if info.features.contains(MemoryEncryptionFeatures::SEV) => SevStatus::read(),
+        _ => SevStatusFlags::empty(),
+    }
+}
+
+/// The position of the C-bit, which marks encrypted pages in physical addresses and page
+/// table entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CBit(u8);
+
+impl CBit {
+    /// Returns the C-bit of the current processor, or `None` if it supports neither SME nor
+    /// SEV.
+    ///
+    /// Note that the C-bit is also reported if memory encryption isn't enabled, which can be
+    /// checked with [`status`] for guests and the SYSCFG register for SME.
+    #[inline]
+    pub fn detect() -> Option<CBit> {
+        MemoryEncryptionInfo::read().map(|info| CBit::new(info.c_bit_position))
+    }
+
+    /// Creates the C-bit at the given position.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the position isn't in the address bits 12 to 51 of a page table entry.
+    #[inline]
+    pub fn new(position: u8) -> Self {
+        assert!(
+            (12..52).contains(&position),
+            "C-bit position {} is outside the address bits",
+            position
+        );
+        CBit(position)
+    }
+
+    /// Returns the position of the C-bit.
+    #[inline]
+    pub const fn position(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the mask of the C-bit.
+    #[inline]
+    pub const fn mask(self) -> u64 {
+        1 << self.0
+    }
+
+    /// Returns the given physical address with the C-bit set.
+    #[inline]
+    pub fn encrypted(self, addr: PhysAddr) -> PhysAddr {
+        PhysAddr::new(addr.as_u64() | self.mask())
+    }
+
+    /// Returns the given physical address with the C-bit cleared.
+    #[inline]
+    pub fn decrypted(self, addr: PhysAddr) -> PhysAddr {
+        PhysAddr::new(addr.as_u64() & !self.mask())
+    }
+
+    /// Maps the entry to the given frame with the given flags, with the C-bit set if the
+    /// frame is encrypted.
+    #[inline]
+    pub fn set_frame(
+        self,
+        entry: &mut PageTableEntry,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+        encrypted: bool,
+    ) {
+        entry.set_frame(frame, flags);
+        self.set_encrypted(entry, encrypted);
+    }
+
+    /// Returns whether the C-bit is set in the entry.
+    #[inline]
+    pub fn is_encrypted(self, entry: &PageTableEntry) -> bool {
+        entry.addr().as_u64() & self.mask() != 0
+    }
+
+    /// Sets or clears the C-bit in the entry, keeping its address and flags.
+    #[inline]
+    pub fn set_encrypted(self, entry: &mut PageTableEntry, encrypted: bool) {
+        let addr = if encrypted {
+            self.encrypted(entry.addr())
+        } else {
+            self.decrypted(entry.addr())
+        };
+        // all bits outside the address field are flags, so they are kept
+        entry.set_addr(addr, entry.flags());
+    }
+
+    /// Returns the physical address mapped by the entry without the C-bit.
+    #[inline]
+    pub fn addr(self, entry: &PageTableEntry) -> PhysAddr {
+        self.decrypted(entry.addr())
+    }
+}
+
+/// The register of a CPUID result that is requested through the GHCB MSR protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CpuidRegister {
+    /// The eax register.
+    Eax = 0,
+    /// The ebx register.
+    Ebx = 1,
+    /// The ecx register.
+    Ecx = 2,
+    /// The edx register.
+    Edx = 3,
+}
+
+/// A request of the GHCB MSR protocol, written to the GHCB MSR before `vmgexit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhcbMsrRequest {
+    /// Requests the supported protocol versions and the C-bit position.
+    SevInfo,
+    /// Requests one register of the given CPUID leaf, with sub-leaf 0.
+    Cpuid {
+        /// The CPUID leaf.
+        leaf: u32,
+        /// The requested register.
+        register: CpuidRegister,
+    },
+    /// Parks the processor until the hypervisor wakes it up, e.g. for an AP that is
+    /// offlined.
+    ApResetHold,
+    /// Requests the guest-physical frame that the hypervisor prefers for the GHCB.
+    PreferredGhcbFrame,
+    /// Registers the guest-physical frame of the GHCB, which SEV-SNP guests must do before using
+    /// it.
+    RegisterGhcbFrame(PhysFrame),
+    /// Changes the state of a frame of an SEV-SNP guest to private or shared.
+    PageStateChange {
+        /// The guest-physical frame.
+        frame: PhysFrame,
+        /// Whether the frame becomes private, i.e. encrypted, or shared.
+        private: bool,
+    },
+    /// Requests the features of the hypervisor.
+    HypervisorFeatures,
+    /// Asks the hypervisor to terminate the guest with the given reason set and code.
+    Terminate {
+        /// The reason set, 0 for the general reasons of the specification.
+        reason_set: u8,
+        /// The reason code within the set.
+        reason_code: u8,
+    },
+}
+
+impl GhcbMsrRequest {
+    /// Returns the value written to the GHCB MSR.
+    pub fn as_raw(self) -> u64 {
+        match self {
+            GhcbMsrRequest::SevInfo => 0x002,
+            GhcbMsrRequest::Cpuid { leaf, register } => {
+                0x004 | (register as u64) << 30 | u64::from(leaf) << 32
+            }
+            GhcbMsrRequest::ApResetHold => 0x006,
+            GhcbMsrRequest::PreferredGhcbFrame => 0x010,
+            GhcbMsrRequest::RegisterGhcbFrame(frame) => 0x012 | frame.start_address().as_u64(),
+            GhcbMsrRequest::PageStateChange { frame, private } => {
+                let operation: u64 = if private { 1 } else { 2 };
+                0x014 | frame.start_address().as_u64() | operation << 52
+            }
+            GhcbMsrRequest::HypervisorFeatures => 0x080,
+            GhcbMsrRequest::Terminate {
+                reason_set,
+                reason_code,
+            } => 0x100 | u64::from(reason_set & 0xf) << 12 | u64::from(reason_code) << 16,
+        }
+    }
+}
+
+/// A response of the GHCB MSR protocol, read from the GHCB MSR after `vmgexit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhcbMsrResponse {
+    /// The supported protocol versions and the C-bit position.
+    SevInfo {
+        /// The highest supported protocol version.
+        max_version: u16,
+        /// The lowest supported protocol version.
+        min_version: u16,
+        /// The position of the C-bit.
+        c_bit_position: u8,
+    },
+    /// The value of the requested CPUID register.
+    Cpuid(u32),
+    /// The processor was woken up, the value is 0 if the hold request wasn't accepted.
+    ApResetHold(u64),
+    /// The preferred frame of the GHCB, or `None` if the hypervisor has no preference.
+    PreferredGhcbFrame(Option<PhysFrame>),
+    /// The registered frame of the GHCB, or `None` if the registration failed.
+    RegisterGhcbFrame(Option<PhysFrame>),
+    /// The result of a page state change, 0 on success.
+    PageStateChange(u32),
+    /// The features of the hypervisor.
+    HypervisorFeatures(u64),
+    /// A response that isn't known to this crate.
+    Unknown(u64),
+}
+
+impl GhcbMsrResponse {
+    /// Decodes the value read from the GHCB MSR.
+    pub fn from_raw(raw: u64) -> Self {
+        // a frame number of all ones means "none"
+        let frame = |raw: u64| match raw >> 12 {
+            0x000f_ffff_ffff_ffff => None,
+            number => Some(PhysFrame::containing_address(PhysAddr::new_truncate(
+                number << 12,
+            ))),
+        };
+        match raw & 0xfff {
+            0x001 => GhcbMsrResponse::SevInfo {
+                max_version: (raw >> 48) as u16,
+                min_version: (raw >> 32) as u16,
+                c_bit_position: (raw >> 24) as u8,
+            },
+            0x005 => GhcbMsrResponse::Cpuid((raw >> 32) as u32),
+            0x007 => GhcbMsrResponse::ApResetHold(raw >> 12),
+            0x011 => GhcbMsrResponse::PreferredGhcbFrame(frame(raw)),
+            0x013 => GhcbMsrResponse::RegisterGhcbFrame(frame(raw)),
+            0x015 => GhcbMsrResponse::PageStateChange((raw >> 32) as u32),
+            0x081 => GhcbMsrResponse::HypervisorFeatures(raw >> 12),
+            _ => GhcbMsrResponse::Unknown(raw),
+        }
+    }
+}
+
+/// Performs a request of the GHCB MSR protocol and returns the response of the hypervisor.
+///
+/// The previous value of the GHCB MSR, e.g. the address of the GHCB, is restored afterwards,
+/// so that this function can be used in a `#VC` handler.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in an SEV-ES guest, and because the
+/// effects of requests like [`PageStateChange`](GhcbMsrRequest::PageStateChange) and
+/// [`Terminate`](GhcbMsrRequest::Terminate) are the caller's responsibility.
+pub unsafe fn msr_protocol(request: GhcbMsrRequest) -> GhcbMsrResponse {
+    let previous = SevEsGhcb::read();
+    SevEsGhcb::write(request.as_raw());
+    vmgexit();
+    let response = SevEsGhcb::read();
+    SevEsGhcb::write(previous);
+    GhcbMsrResponse::from_raw(response)
+}
+
+/// The error of a request through the GHCB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhcbError {
+    /// The hypervisor asks the guest to raise the given exception, e.g. `#GP` for an access
+    /// to an MSR that doesn't exist.
+    Exception(SvmEvent),
+    /// The hypervisor rejected the request, with the exit information it returned.
+    Failed {
+        /// The exit information 1, whose low 32 bits are the error code.
+        exit_info_1: u64,
+        /// The exit information 2.
+        exit_info_2: u64,
+    },
+}
+
+impl fmt::Display for GhcbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GhcbError::Exception(event) => write!(
+                f,
+                "the hypervisor requested the injection of exception {}",
+                event.vector()
+            ),
+            GhcbError::Failed {
+                exit_info_1,
+                exit_info_2,
+            } => write!(
+                f,
+                "the GHCB request failed with exit information {:#x}, {:#x}",
+                exit_info_1, exit_info_2
+            ),
+        }
+    }
+}
+
+/// Performs the request that was prepared in the GHCB, e.g. with
+/// [`set_request`](Ghcb::set_request), and checks the result that the hypervisor stored in
+/// the exit information.
+///
+/// `frame` is the guest-physical frame of the GHCB, which is written to the GHCB MSR. The
+/// registers returned by the hypervisor, e.g. RAX to RDX for `cpuid`, are read from the GHCB
+/// afterwards.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in an SEV-ES guest with the GHCB mapped
+/// as shared at the given frame, and because the effects of the request are the caller's
+/// responsibility.
+pub unsafe fn ghcb_call(ghcb: &mut Ghcb, frame: PhysFrame) -> Result<(), GhcbError> {
+    SevEsGhcb::write(frame.start_address().as_u64());
+    vmgexit();
+    ghcb_result(ghcb)
+}
+
+/// Decodes the result of a GHCB request from the exit information.
+fn ghcb_result(ghcb: &Ghcb) -> Result<(), GhcbError> {
+    let exit_info_1 = ghcb.get(GhcbField::SwExitInfo1).unwrap_or(0);
+    let exit_info_2 = ghcb.get(GhcbField::SwExitInfo2).unwrap_or(0);
+    match exit_info_1 as u32 {
+        0 => Ok(()),
+        1 if SvmEvent::from_raw(exit_info_2).is_valid() => {
+            Err(GhcbError::Exception(SvmEvent::from_raw(exit_info_2)))
+        }
+        _ => Err(GhcbError::Failed {
+            exit_info_1,
+            exit_info_2,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_bit_entries() {
+        let c_bit = CBit::new(47);
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x1234_5000));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let mut entry = PageTableEntry::new();
+        c_bit.set_frame(&mut entry, frame, flags, true);
+        assert!(c_bit.is_encrypted(&entry));
+        assert_eq!(entry.addr(), PhysAddr::new(0x8000_1234_5000));
+        assert_eq!(c_bit.addr(&entry), frame.start_address());
+        assert_eq!(entry.flags(), flags);
+
+        c_bit.set_encrypted(&mut entry, false);
+        assert!(!c_bit.is_encrypted(&entry));
+        assert_eq!(entry.addr(), frame.start_address());
+        assert_eq!(entry.flags(), flags);
+    }
+
+    #[test]
+    fn ghcb_msr_protocol() {
+        let request = GhcbMsrRequest::Cpuid {
+            leaf: 0x8000_001f,
+            register: CpuidRegister::Ebx,
+        };
+        assert_eq!(request.as_raw(), 0x8000_001f_4000_0004);
+        let terminate = GhcbMsrRequest::Terminate {
+            reason_set: 0,
+            reason_code: 1,
+        };
+        assert_eq!(terminate.as_raw(), 0x0001_0100);
+
+        assert_eq!(
+            GhcbMsrResponse::from_raw(0x0002_0001_3300_0001),
+            GhcbMsrResponse::SevInfo {
+                max_version: 2,
+                min_version: 1,
+                c_bit_position: 0x33,
+            }
+        );
+        assert_eq!(
+            GhcbMsrResponse::from_raw(0x1234_5000 | 0x013),
+            GhcbMsrResponse::RegisterGhcbFrame(Some(PhysFrame::containing_address(PhysAddr::new(
+                0x1234_5000
+            ))))
+        );
+        assert_eq!(
+            GhcbMsrResponse::from_raw(!0xfff | 0x013),
+            GhcbMsrResponse::RegisterGhcbFrame(None)
+        );
+    }
+
+    #[test]
+    fn ghcb_results() {
+        let mut ghcb = Ghcb::new();
+        ghcb.set_request(0x7c, 0, 0);
+        assert_eq!(ghcb_result(&ghcb), Ok(()));
+
+        // the hypervisor asks for a #GP with error code 0
+        let gp = SvmEvent::new(crate::structures::vmcb::SvmEventType::Exception, 13);
+        ghcb.set(GhcbField::SwExitInfo1, 1);
+        ghcb.set(GhcbField::SwExitInfo2, gp.as_raw());
+        assert_eq!(ghcb_result(&ghcb), Err(GhcbError::Exception(gp)));
+    }
+}
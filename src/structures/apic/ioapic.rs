@@ -0,0 +1,454 @@
+//! Provides a type for the memory mapped I/O APIC.
+
+use super::DeliveryMode;
+use crate::structures::mmio::Mmio;
+use crate::VirtAddr;
+use bit_field::BitField;
+use core::fmt;
+
+/// The memory mapped register window of an I/O APIC.
+///
+/// An I/O APIC routes external interrupts, identified by their global system interrupt
+/// (GSI) number, to the local APICs through its redirection table. The physical address of
+/// the register window and the first GSI handled by the I/O APIC are usually taken from the
+/// ACPI MADT table.
+#[derive(Debug)]
+pub struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    /// The offset of the register select register.
+    const IOREGSEL: u64 = 0x00;
+    /// The offset of the register data window.
+    const IOWIN: u64 = 0x10;
+
+    /// The register index of the ID register.
+    const ID: u32 = 0x00;
+    /// The register index of the version register.
+    const VERSION: u32 = 0x01;
+    /// The register index of the low half of the first redirection table entry.
+    const REDIRECTION_TABLE: u32 = 0x10;
+
+    /// Creates a new `IoApic` for the register window mapped at the given virtual address,
+    /// which handles the global system interrupts starting at `gsi_base`.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the register window of
+    /// the I/O APIC is mapped at `base` with caching disabled. Also, there must be no other
+    /// `IoApic` instance for the same I/O APIC that is used concurrently.
+    #[inline]
+    pub const unsafe fn new(base: VirtAddr, gsi_base: u32) -> IoApic {
+        IoApic { base, gsi_base }
+    }
+
+    /// Returns the virtual address the register window is mapped at.
+    #[inline]
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns the first global system interrupt handled by this I/O APIC.
+    #[inline]
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// Reads the register with the given index.
+    ///
+    /// This takes `&mut self` because the access selects the register through the register
+    /// select register first, so concurrent reads would interfere.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the index must be a valid register index.
+    #[inline]
+    pub unsafe fn read(&mut self, index: u32) -> u32 {
+        Mmio::from_addr(self.base + Self::IOREGSEL).write(index);
+        Mmio::from_addr(self.base + Self::IOWIN).read()
+    }
+
+    /// Writes the given value to the register with the given index.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the index must be a valid register index and writing
+    /// to the redirection table can cause interrupts to be delivered to arbitrary vectors.
+    #[inline]
+    pub unsafe fn write(&mut self, index: u32, value: u32) {
+        Mmio::from_addr(self.base + Self::IOREGSEL).write(index);
+        Mmio::from_addr(self.base + Self::IOWIN).write(value);
+    }
+
+    /// Returns the 4-bit ID of the I/O APIC.
+    #[inline]
+    pub fn id(&mut self) -> u8 {
+        unsafe { self.read(Self::ID) }.get_bits(24..28) as u8
+    }
+
+    /// Returns the version of the I/O APIC.
+    #[inline]
+    pub fn version(&mut self) -> u8 {
+        unsafe { self.read(Self::VERSION) }.get_bits(0..8) as u8
+    }
+
+    /// Returns the number of entries of the redirection table, i.e. the number of
+    /// interrupt inputs of the I/O APIC.
+    #[inline]
+    pub fn redirection_entry_count(&mut self) -> u8 {
+        unsafe { self.read(Self::VERSION) }.get_bits(16..24) as u8 + 1
+    }
+
+    /// Returns whether the given global system interrupt is handled by this I/O APIC.
+    #[inline]
+    pub fn handles_gsi(&mut self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi - self.gsi_base < u32::from(self.redirection_entry_count())
+    }
+
+    /// Reads the redirection table entry with the given index.
+    ///
+    /// Panics if the index is not smaller than the number of redirection entries.
+    #[inline]
+    pub fn redirection_entry(&mut self, index: u8) -> RedirectionEntry {
+        assert!(
+            index < self.redirection_entry_count(),
+            "invalid redirection entry"
+        );
+        let register = Self::REDIRECTION_TABLE + 2 * u32::from(index);
+        let low = unsafe { self.read(register) };
+        let high = unsafe { self.read(register + 1) };
+        RedirectionEntry((u64::from(high) << 32) | u64::from(low))
+    }
+
+    /// Writes the redirection table entry with the given index.
+    ///
+    /// The entry is masked while it is updated, so that no interrupt is delivered with a
+    /// partially written entry.
+    ///
+    /// Panics if the index is not smaller than the number of redirection entries.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the configured vector on the target processors.
+    #[inline]
+    pub unsafe fn set_redirection_entry(&mut self, index: u8, entry: RedirectionEntry) {
+        assert!(
+            index < self.redirection_entry_count(),
+            "invalid redirection entry"
+        );
+        let register = Self::REDIRECTION_TABLE + 2 * u32::from(index);
+        let low = entry.0 as u32;
+        self.write(register, low | (1 << 16));
+        self.write(register + 1, (entry.0 >> 32) as u32);
+        self.write(register, low);
+    }
+
+    /// Returns the redirection table index for the given global system interrupt.
+    ///
+    /// Panics if the interrupt is not handled by this I/O APIC.
+    #[inline]
+    fn gsi_index(&mut self, gsi: u32) -> u8 {
+        assert!(self.handles_gsi(gsi), "GSI not handled by this I/O APIC");
+        (gsi - self.gsi_base) as u8
+    }
+
+    /// Masks the given global system interrupt.
+    ///
+    /// Panics if the interrupt is not handled by this I/O APIC.
+    #[inline]
+    pub fn mask(&mut self, gsi: u32) {
+        let index = self.gsi_index(gsi);
+        let mut entry = self.redirection_entry(index);
+        entry.set_masked(true);
+        unsafe { self.set_redirection_entry(index, entry) };
+    }
+
+    /// Unmasks the given global system interrupt.
+    ///
+    /// Panics if the interrupt is not handled by this I/O APIC.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the configured vector on the target processors.
+    #[inline]
+    pub unsafe fn unmask(&mut self, gsi: u32) {
+        let index = self.gsi_index(gsi);
+        let mut entry = self.redirection_entry(index);
+        entry.set_masked(false);
+        self.set_redirection_entry(index, entry);
+    }
+
+    /// Routes the given global system interrupt to the given vector on the processor with
+    /// the given local APIC ID, using fixed delivery and unmasking the interrupt.
+    ///
+    /// Panics if the interrupt is not handled by this I/O APIC.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the vector on the target processor.
+    #[inline]
+    pub unsafe fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        apic_id: u8,
+        active_low: bool,
+        level_triggered: bool,
+    ) {
+        let index = self.gsi_index(gsi);
+        let mut entry = RedirectionEntry::new(vector);
+        entry
+            .set_destination(apic_id)
+            .set_active_low(active_low)
+            .set_level_triggered(level_triggered);
+        self.set_redirection_entry(index, entry);
+    }
+
+    /// Routes the given ISA IRQ to the given vector on the processor with the given local
+    /// APIC ID.
+    ///
+    /// ISA IRQs are identity mapped to global system interrupts and are edge triggered and
+    /// active high, unless an interrupt source override for the IRQ exists in `overrides`.
+    /// The overrides are usually taken from the ACPI MADT table.
+    ///
+    /// Panics if the resulting interrupt is not handled by this I/O APIC.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the vector on the target processor.
+    pub unsafe fn route_isa_irq(
+        &mut self,
+        irq: u8,
+        vector: u8,
+        apic_id: u8,
+        overrides: &[InterruptSourceOverride],
+    ) {
+        let source = overrides
+            .iter()
+            .find(|o| o.isa_irq == irq)
+            .copied()
+            .unwrap_or(InterruptSourceOverride {
+                isa_irq: irq,
+                gsi: u32::from(irq),
+                active_low: false,
+                level_triggered: false,
+            });
+        self.route(
+            source.gsi,
+            vector,
+            apic_id,
+            source.active_low,
+            source.level_triggered,
+        );
+    }
+}
+
+/// Describes how an ISA IRQ is connected to the I/O APICs if it differs from the default
+/// identity mapping, as reported by the ACPI MADT table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    /// The ISA IRQ number.
+    pub isa_irq: u8,
+    /// The global system interrupt the IRQ is connected to.
+    pub gsi: u32,
+    /// Whether the interrupt is active low.
+    pub active_low: bool,
+    /// Whether the interrupt is level triggered.
+    pub level_triggered: bool,
+}
+
+/// An entry of the redirection table of an I/O APIC.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct RedirectionEntry(pub u64);
+
+impl RedirectionEntry {
+    /// Creates an unmasked, edge triggered, active high entry that delivers the given vector
+    /// with fixed delivery mode to the processor with local APIC ID 0.
+    #[inline]
+    pub const fn new(vector: u8) -> RedirectionEntry {
+        RedirectionEntry(vector as u64)
+    }
+
+    /// Returns the interrupt vector number.
+    #[inline]
+    pub fn vector(self) -> u8 {
+        self.0.get_bits(0..8) as u8
+    }
+
+    /// Sets the interrupt vector number.
+    #[inline]
+    pub fn set_vector(&mut self, vector: u8) -> &mut Self {
+        self.0.set_bits(0..8, u64::from(vector));
+        self
+    }
+
+    /// Returns the delivery mode, or `None` if the field contains a reserved value.
+    #[inline]
+    pub fn delivery_mode(self) -> Option<DeliveryMode> {
+        DeliveryMode::from_bits(self.0.get_bits(8..11) as u8)
+    }
+
+    /// Sets the delivery mode.
+    #[inline]
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) -> &mut Self {
+        self.0.set_bits(8..11, mode as u64);
+        self
+    }
+
+    /// Returns whether the destination is interpreted as a logical destination.
+    #[inline]
+    pub fn logical_destination(self) -> bool {
+        self.0.get_bit(11)
+    }
+
+    /// Sets whether the destination is interpreted as a logical destination instead of a
+    /// local APIC ID.
+    #[inline]
+    pub fn set_logical_destination(&mut self, logical: bool) -> &mut Self {
+        self.0.set_bit(11, logical);
+        self
+    }
+
+    /// Returns whether the interrupt is waiting to be delivered to the local APIC.
+    #[inline]
+    pub fn delivery_pending(self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    /// Returns whether the interrupt input pin is active low.
+    #[inline]
+    pub fn active_low(self) -> bool {
+        self.0.get_bit(13)
+    }
+
+    /// Sets the polarity of the interrupt input pin.
+    #[inline]
+    pub fn set_active_low(&mut self, active_low: bool) -> &mut Self {
+        self.0.set_bit(13, active_low);
+        self
+    }
+
+    /// Returns whether a level triggered interrupt was accepted by a local APIC, but no
+    /// EOI was received yet.
+    #[inline]
+    pub fn remote_irr(self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    /// Returns whether the interrupt input pin is level triggered.
+    #[inline]
+    pub fn level_triggered(self) -> bool {
+        self.0.get_bit(15)
+    }
+
+    /// Sets the trigger mode of the interrupt input pin.
+    #[inline]
+    pub fn set_level_triggered(&mut self, level_triggered: bool) -> &mut Self {
+        self.0.set_bit(15, level_triggered);
+        self
+    }
+
+    /// Returns whether the interrupt is masked.
+    #[inline]
+    pub fn masked(self) -> bool {
+        self.0.get_bit(16)
+    }
+
+    /// Masks or unmasks the interrupt.
+    #[inline]
+    pub fn set_masked(&mut self, masked: bool) -> &mut Self {
+        self.0.set_bit(16, masked);
+        self
+    }
+
+    /// Returns the destination local APIC ID or logical destination.
+    #[inline]
+    pub fn destination(self) -> u8 {
+        self.0.get_bits(56..64) as u8
+    }
+
+    /// Sets the destination local APIC ID or logical destination.
+    #[inline]
+    pub fn set_destination(&mut self, destination: u8) -> &mut Self {
+        self.0.set_bits(56..64, u64::from(destination));
+        self
+    }
+}
+
+impl fmt::Debug for RedirectionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("RedirectionEntry");
+        s.field("vector", &self.vector());
+        s.field("delivery_mode", &self.delivery_mode());
+        s.field("active_low", &self.active_low());
+        s.field("level_triggered", &self.level_triggered());
+        s.field("masked", &self.masked());
+        s.field("destination", &self.destination());
+        s.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A register window in memory, which returns the value last written to the data window
+    /// for every register.
+    #[repr(C, align(16))]
+    struct FakeWindow([u32; 8]);
+
+    impl FakeWindow {
+        fn io_apic(&mut self) -> IoApic {
+            unsafe { IoApic::new(VirtAddr::from_ptr(self.0.as_mut_ptr()), 16) }
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let mut window = FakeWindow([0; 8]);
+        window.0[4] = 0x0517_0020;
+        let mut io_apic = window.io_apic();
+        assert_eq!(io_apic.version(), 0x20);
+        assert_eq!(io_apic.redirection_entry_count(), 24);
+        assert!(io_apic.handles_gsi(16) && io_apic.handles_gsi(39));
+        assert!(!io_apic.handles_gsi(15) && !io_apic.handles_gsi(40));
+        assert_eq!(io_apic.id(), 5);
+        assert_eq!(window.0[0], IoApic::ID);
+
+        let mut io_apic = window.io_apic();
+        let entry = RedirectionEntry(0x0300_0000_0000_a031);
+        unsafe { io_apic.set_redirection_entry(3, entry) };
+        // the low half is written last, after the masked low half and the high half
+        assert_eq!(window.0[0], IoApic::REDIRECTION_TABLE + 6);
+        assert_eq!(window.0[4], 0x0000_a031);
+    }
+
+    #[test]
+    fn redirection_entry_bits() {
+        let mut entry = RedirectionEntry::new(0x31);
+        entry
+            .set_delivery_mode(DeliveryMode::LowestPriority)
+            .set_logical_destination(true)
+            .set_active_low(true)
+            .set_level_triggered(true)
+            .set_masked(true)
+            .set_destination(0xa5);
+        assert_eq!(entry.0, 0xa500_0000_0001_a931);
+        assert_eq!(entry.vector(), 0x31);
+        assert_eq!(entry.delivery_mode(), Some(DeliveryMode::LowestPriority));
+        assert!(entry.logical_destination() && entry.active_low());
+        assert!(entry.level_triggered() && entry.masked());
+        assert_eq!(entry.destination(), 0xa5);
+
+        let status = RedirectionEntry(1 << 12 | 1 << 14);
+        assert!(status.delivery_pending() && status.remote_irr());
+        assert_eq!(RedirectionEntry(0b011 << 8).delivery_mode(), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Provides types for sending inter-processor interrupts (IPIs).
+
+use super::{Apic, DeliveryMode};
+use crate::structures::paging::PhysFrame;
+use bit_field::BitField;
+
+/// The destination of an inter-processor interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpiDestination {
+    /// The processor with the given local APIC ID.
+    Physical(u32),
+    /// The processors whose logical destination register matches the given value.
+    Logical(u32),
+    /// Only the sending processor.
+    SelfOnly,
+    /// All processors, including the sending processor.
+    AllIncludingSelf,
+    /// All processors except the sending processor.
+    AllExcludingSelf,
+}
+
+/// The trigger mode of an inter-processor interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// The interrupt is edge triggered.
+    Edge,
+    /// The interrupt is level triggered. Only used for the INIT level de-assert IPI.
+    Level,
+}
+
+/// An inter-processor interrupt that can be sent through the interrupt command register.
+///
+/// The interrupt is constructed through builder methods, e.g.:
+///
+/// ```ignore
+/// let ipi = InterprocessorInterrupt::new(0x40).destination(IpiDestination::Physical(1));
+/// unsafe { apic.send_ipi(ipi) };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterprocessorInterrupt {
+    vector: u8,
+    delivery_mode: DeliveryMode,
+    destination: IpiDestination,
+    assert: bool,
+    trigger_mode: TriggerMode,
+}
+
+impl InterprocessorInterrupt {
+    /// Creates a fixed, edge triggered interrupt with the given vector that is sent to the
+    /// current processor.
+    #[inline]
+    pub const fn new(vector: u8) -> InterprocessorInterrupt {
+        InterprocessorInterrupt {
+            vector,
+            delivery_mode: DeliveryMode::Fixed,
+            destination: IpiDestination::SelfOnly,
+            assert: true,
+            trigger_mode: TriggerMode::Edge,
+        }
+    }
+
+    /// Sets the interrupt vector.
+    #[inline]
+    pub const fn vector(mut self, vector: u8) -> Self {
+        self.vector = vector;
+        self
+    }
+
+    /// Sets the delivery mode.
+    #[inline]
+    pub const fn delivery_mode(mut self, delivery_mode: DeliveryMode) -> Self {
+        self.delivery_mode = delivery_mode;
+        self
+    }
+
+    /// Sets the destination.
+    #[inline]
+    pub const fn destination(mut self, destination: IpiDestination) -> Self {
+        self.destination = destination;
+        self
+    }
+
+    /// Sets the level of the interrupt. It must be `false` only for the INIT level
+    /// de-assert IPI.
+    #[inline]
+    pub const fn assert(mut self, assert: bool) -> Self {
+        self.assert = assert;
+        self
+    }
+
+    /// Sets the trigger mode.
+    #[inline]
+    pub const fn trigger_mode(mut self, trigger_mode: TriggerMode) -> Self {
+        self.trigger_mode = trigger_mode;
+        self
+    }
+
+    /// Returns the value of the interrupt command register for this interrupt.
+    ///
+    /// In xAPIC mode, the destination is stored in bits 56 to 63, so only the low 8 bits of
+    /// the destination ID are used. In x2APIC mode, the destination is stored in bits 32
+    /// to 63.
+    pub fn icr_value(self, x2apic: bool) -> u64 {
+        let mut value = 0u64;
+        value.set_bits(0..8, u64::from(self.vector));
+        value.set_bits(8..11, self.delivery_mode as u64);
+        value.set_bit(14, self.assert);
+        value.set_bit(15, self.trigger_mode == TriggerMode::Level);
+
+        let (logical, shorthand, target) = match self.destination {
+            IpiDestination::Physical(id) => (false, 0b00, id),
+            IpiDestination::Logical(id) => (true, 0b00, id),
+            IpiDestination::SelfOnly => (false, 0b01, 0),
+            IpiDestination::AllIncludingSelf => (false, 0b10, 0),
+            IpiDestination::AllExcludingSelf => (false, 0b11, 0),
+        };
+        value.set_bit(11, logical);
+        value.set_bits(18..20, shorthand);
+        if x2apic {
+            value.set_bits(32..64, u64::from(target));
+        } else {
+            value.set_bits(56..64, u64::from(target as u8));
+        }
+        value
+    }
+}
+
+/// The delays of the INIT-SIPI-SIPI start-up sequence, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartupDelays {
+    /// The delay after the INIT IPI, before the first start-up IPI is sent.
+    pub init: u64,
+    /// The delay after each start-up IPI.
+    pub startup: u64,
+}
+
+impl Default for StartupDelays {
+    /// Returns the delays recommended by the Intel Software Developer's Manual: 10ms after
+    /// the INIT IPI and 200µs after each start-up IPI.
+    #[inline]
+    fn default() -> Self {
+        StartupDelays {
+            init: 10_000,
+            startup: 200,
+        }
+    }
+}
+
+/// Starts the application processor with the given local APIC ID through the INIT-SIPI-SIPI
+/// sequence.
+///
+/// The application processor starts executing in real mode at the beginning of
+/// `start_page`, which must be located below 1MiB. The `delay` closure is called with the
+/// number of microseconds to wait between the steps of the sequence.
+///
+/// Panics if `start_page` is not located below 1MiB.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that valid real mode start-up code
+/// is located at `start_page` and that the target processor is not running already.
+pub unsafe fn start_application_processor<A, F>(
+    apic: &mut A,
+    apic_id: u32,
+    start_page: PhysFrame,
+    delays: StartupDelays,
+    mut delay: F,
+) where
+    A: Apic + ?Sized,
+    F: FnMut(u64),
+{
+    let page_number = start_page.start_address().as_u64() >> 12;
+    assert!(page_number < 0x100, "start page must be located below 1MiB");
+    let destination = IpiDestination::Physical(apic_id);
+
+    let init = InterprocessorInterrupt::new(0)
+        .delivery_mode(DeliveryMode::Init)
+        .destination(destination);
+    apic.send_ipi(init);
+    apic.wait_for_ipi_delivery();
+    delay(delays.init);
+
+    let startup = InterprocessorInterrupt::new(page_number as u8)
+        .delivery_mode(DeliveryMode::StartUp)
+        .destination(destination);
+    for _ in 0..2 {
+        apic.send_ipi(startup);
+        apic.wait_for_ipi_delivery();
+        delay(delays.startup);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn icr_value() {
+        let init = InterprocessorInterrupt::new(0)
+            .delivery_mode(DeliveryMode::Init)
+            .destination(IpiDestination::Physical(3));
+        assert_eq!(init.icr_value(false), 0x0300_0000_0000_4500);
+        assert_eq!(init.icr_value(true), 0x0000_0003_0000_4500);
+
+        let broadcast =
+            InterprocessorInterrupt::new(0x40).destination(IpiDestination::AllExcludingSelf);
+        assert_eq!(broadcast.icr_value(false), 0x000c_4040);
+    }
+}
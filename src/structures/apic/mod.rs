@@ -0,0 +1,820 @@
+//! Abstractions for the Advanced Programmable Interrupt Controller (APIC).
+//!
+//! Every processor core has a local APIC that receives interrupts and sends
+//! inter-processor interrupts. In xAPIC mode, the registers of the local APIC are
+//! memory mapped at the physical address stored in the
+//! [`ApicBase`](crate::registers::model_specific::ApicBase) model specific register.
+//! In x2APIC mode, the registers are accessed through model specific registers instead.
+//!
+//! Both modes are supported through the [`Apic`] trait, which is implemented by
+//! [`LocalApic`] (xAPIC mode) and [`X2Apic`] (x2APIC mode).
+//!
+//! External interrupts are routed to the local APICs by one or more [`IoApic`]s.
+
+pub use self::ioapic::{InterruptSourceOverride, IoApic, RedirectionEntry};
+pub use self::ipi::{
+    start_application_processor, InterprocessorInterrupt, IpiDestination, StartupDelays,
+    TriggerMode,
+};
+pub use self::timer::{calibrate_timer, TimerDivide, TimerMode};
+pub use self::x2apic::X2Apic;
+pub use self::xapic::LocalApic;
+
+use bit_field::BitField;
+use bitflags::bitflags;
+use core::fmt;
+
+mod ioapic;
+mod ipi;
+mod timer;
+mod x2apic;
+mod xapic;
+
+/// A trait for accessing the local APIC of the current processor.
+///
+/// This trait abstracts over the xAPIC and x2APIC register interfaces, so that kernels can
+/// choose the mode at runtime. Apart from the raw register accessors, all methods are
+/// provided on top of [`read`](Apic::read) and [`write`](Apic::write).
+pub trait Apic {
+    /// Reads the given register.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because reading some registers might have side effects.
+    unsafe fn read(&self, register: ApicRegister) -> u32;
+
+    /// Writes the given value to the given register.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because writing to the local APIC can have arbitrary side
+    /// effects, e.g. sending interrupts to other processors or resetting them.
+    unsafe fn write(&mut self, register: ApicRegister, value: u32);
+
+    /// Returns the ID of the local APIC.
+    ///
+    /// The xAPIC ID is 8 bits wide, the x2APIC ID is 32 bits wide.
+    fn id(&self) -> u32;
+
+    /// Reads the raw value of the interrupt command register (ICR).
+    fn icr(&self) -> u64;
+
+    /// Writes the raw value of the interrupt command register (ICR), which sends an
+    /// inter-processor interrupt.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because inter-processor interrupts can have arbitrary effects
+    /// on the target processors, e.g. resetting them.
+    unsafe fn set_icr(&mut self, value: u64);
+
+    /// Returns whether the last inter-processor interrupt was not accepted by the target yet.
+    ///
+    /// Always returns `false` in x2APIC mode, which has no delivery status bit.
+    fn icr_delivery_pending(&self) -> bool;
+
+    /// Returns whether this local APIC is accessed in x2APIC mode.
+    ///
+    /// This determines the layout of the destination field of the interrupt command register.
+    fn is_x2apic(&self) -> bool;
+
+    /// Sends the given inter-processor interrupt.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because inter-processor interrupts can have arbitrary effects
+    /// on the target processors, e.g. resetting them.
+    #[inline]
+    unsafe fn send_ipi(&mut self, ipi: InterprocessorInterrupt) {
+        let value = ipi.icr_value(self.is_x2apic());
+        self.set_icr(value);
+    }
+
+    /// Spins until the last inter-processor interrupt was accepted by its target.
+    #[inline]
+    fn wait_for_ipi_delivery(&self) {
+        while self.icr_delivery_pending() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the content of the version register.
+    #[inline]
+    fn version(&self) -> ApicVersion {
+        ApicVersion::from_raw(unsafe { self.read(ApicRegister::VERSION) })
+    }
+
+    /// Returns the task priority. Interrupts with a priority class (vector / 16) less or equal
+    /// the priority class of the task priority are not delivered to the processor.
+    #[inline]
+    fn task_priority(&self) -> u8 {
+        unsafe { self.read(ApicRegister::TASK_PRIORITY) }.get_bits(0..8) as u8
+    }
+
+    /// Sets the task priority.
+    #[inline]
+    fn set_task_priority(&mut self, priority: u8) {
+        unsafe { self.write(ApicRegister::TASK_PRIORITY, u32::from(priority)) };
+    }
+
+    /// Signals the end of the current interrupt handler to the local APIC.
+    ///
+    /// This must be called at the end of every interrupt handler for interrupts that are
+    /// delivered through the local APIC, except for NMI, SMI, INIT, and start-up interrupts.
+    #[inline]
+    fn eoi(&mut self) {
+        unsafe { self.write(ApicRegister::END_OF_INTERRUPT, 0) };
+    }
+
+    /// Returns whether the local APIC is software enabled.
+    #[inline]
+    fn is_enabled(&self) -> bool {
+        unsafe { self.read(ApicRegister::SPURIOUS_INTERRUPT_VECTOR) }.get_bit(8)
+    }
+
+    /// Software enables the local APIC by setting the enable bit of the spurious interrupt
+    /// vector register.
+    #[inline]
+    fn enable(&mut self) {
+        let mut value = unsafe { self.read(ApicRegister::SPURIOUS_INTERRUPT_VECTOR) };
+        value.set_bit(8, true);
+        unsafe { self.write(ApicRegister::SPURIOUS_INTERRUPT_VECTOR, value) };
+    }
+
+    /// Software disables the local APIC. All LVT entries are masked until the APIC is
+    /// enabled again.
+    #[inline]
+    fn disable(&mut self) {
+        let mut value = unsafe { self.read(ApicRegister::SPURIOUS_INTERRUPT_VECTOR) };
+        value.set_bit(8, false);
+        unsafe { self.write(ApicRegister::SPURIOUS_INTERRUPT_VECTOR, value) };
+    }
+
+    /// Returns the vector that is delivered on spurious interrupts.
+    #[inline]
+    fn spurious_vector(&self) -> u8 {
+        unsafe { self.read(ApicRegister::SPURIOUS_INTERRUPT_VECTOR) }.get_bits(0..8) as u8
+    }
+
+    /// Sets the vector that is delivered on spurious interrupts.
+    ///
+    /// The handler for this vector must not send an EOI.
+    #[inline]
+    fn set_spurious_vector(&mut self, vector: u8) {
+        let mut value = unsafe { self.read(ApicRegister::SPURIOUS_INTERRUPT_VECTOR) };
+        value.set_bits(0..8, u32::from(vector));
+        unsafe { self.write(ApicRegister::SPURIOUS_INTERRUPT_VECTOR, value) };
+    }
+
+    /// Reads the given LVT entry.
+    #[inline]
+    fn lvt(&self, lvt: Lvt) -> LvtEntry {
+        LvtEntry(unsafe { self.read(lvt.register()) })
+    }
+
+    /// Writes the given LVT entry.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the configured vector and delivery mode.
+    #[inline]
+    unsafe fn set_lvt(&mut self, lvt: Lvt, entry: LvtEntry) {
+        self.write(lvt.register(), entry.0);
+    }
+
+    /// Masks the given LVT entry, so that its interrupt source no longer delivers interrupts.
+    #[inline]
+    fn mask_lvt(&mut self, lvt: Lvt) {
+        let mut entry = self.lvt(lvt);
+        entry.set_masked(true);
+        unsafe { self.set_lvt(lvt, entry) };
+    }
+
+    /// Unmasks the given LVT entry.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the vector and delivery mode of the entry.
+    #[inline]
+    unsafe fn unmask_lvt(&mut self, lvt: Lvt) {
+        let mut entry = self.lvt(lvt);
+        entry.set_masked(false);
+        self.set_lvt(lvt, entry);
+    }
+
+    /// Returns whether the interrupt with the given vector is currently in service.
+    #[inline]
+    fn is_in_service(&self, vector: u8) -> bool {
+        let register = ApicRegister::in_service(vector / 32);
+        unsafe { self.read(register) }.get_bit(usize::from(vector % 32))
+    }
+
+    /// Returns whether the interrupt with the given vector is pending.
+    #[inline]
+    fn is_requested(&self, vector: u8) -> bool {
+        let register = ApicRegister::interrupt_request(vector / 32);
+        unsafe { self.read(register) }.get_bit(usize::from(vector % 32))
+    }
+
+    /// Returns the initial count of the local APIC timer.
+    #[inline]
+    fn timer_initial_count(&self) -> u32 {
+        unsafe { self.read(ApicRegister::TIMER_INITIAL_COUNT) }
+    }
+
+    /// Sets the initial count of the local APIC timer, which (re)starts the timer.
+    ///
+    /// Setting the initial count to zero stops the timer.
+    #[inline]
+    fn set_timer_initial_count(&mut self, count: u32) {
+        unsafe { self.write(ApicRegister::TIMER_INITIAL_COUNT, count) };
+    }
+
+    /// Returns the errors detected by the local APIC since the last call and clears them.
+    ///
+    /// The error status register is updated by writing to it before it is read, which
+    /// also clears the errors recorded so far.
+    #[inline]
+    fn error_status(&mut self) -> ErrorStatus {
+        unsafe {
+            self.write(ApicRegister::ERROR_STATUS, 0);
+            ErrorStatus::from_bits_truncate(self.read(ApicRegister::ERROR_STATUS))
+        }
+    }
+
+    /// Returns the current count of the local APIC timer.
+    #[inline]
+    fn timer_current_count(&self) -> u32 {
+        unsafe { self.read(ApicRegister::TIMER_CURRENT_COUNT) }
+    }
+
+    /// Returns the divider applied to the bus clock for the local APIC timer.
+    #[inline]
+    fn timer_divide(&self) -> TimerDivide {
+        TimerDivide::from_bits(unsafe { self.read(ApicRegister::TIMER_DIVIDE_CONFIGURATION) })
+    }
+
+    /// Sets the divider applied to the bus clock for the local APIC timer.
+    #[inline]
+    fn set_timer_divide(&mut self, divide: TimerDivide) {
+        unsafe { self.write(ApicRegister::TIMER_DIVIDE_CONFIGURATION, divide as u32) };
+    }
+
+    /// Configures the LVT timer entry to deliver the given vector in the given mode and
+    /// unmasks it.
+    ///
+    /// The timer is started by
+    /// [`set_timer_initial_count`](Apic::set_timer_initial_count) in the one-shot and
+    /// periodic modes and by writing the
+    /// [`TscDeadline`](crate::registers::model_specific::TscDeadline) register in the
+    /// TSC-deadline mode.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler
+    /// is installed for the given vector. For the TSC-deadline mode, the caller must ensure
+    /// that the mode is supported (see [`TimerMode::is_tsc_deadline_supported`]).
+    #[inline]
+    unsafe fn configure_timer(&mut self, vector: u8, mode: TimerMode) {
+        let mut entry = LvtEntry::new(vector);
+        entry.set_timer_mode(mode);
+        self.set_lvt(Lvt::Timer, entry);
+    }
+
+    /// Stops the local APIC timer and masks its LVT entry.
+    #[inline]
+    fn stop_timer(&mut self) {
+        self.mask_lvt(Lvt::Timer);
+        self.set_timer_initial_count(0);
+    }
+}
+
+/// A register of the local APIC.
+///
+/// The value is the offset of the register in the memory mapped xAPIC register page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApicRegister(u16);
+
+impl ApicRegister {
+    /// Local APIC ID Register.
+    pub const ID: ApicRegister = ApicRegister(0x020);
+    /// Local APIC Version Register.
+    pub const VERSION: ApicRegister = ApicRegister(0x030);
+    /// Task Priority Register (TPR).
+    pub const TASK_PRIORITY: ApicRegister = ApicRegister(0x080);
+    /// Arbitration Priority Register (APR).
+    pub const ARBITRATION_PRIORITY: ApicRegister = ApicRegister(0x090);
+    /// Processor Priority Register (PPR).
+    pub const PROCESSOR_PRIORITY: ApicRegister = ApicRegister(0x0A0);
+    /// End Of Interrupt Register (EOI).
+    pub const END_OF_INTERRUPT: ApicRegister = ApicRegister(0x0B0);
+    /// Logical Destination Register (LDR).
+    pub const LOGICAL_DESTINATION: ApicRegister = ApicRegister(0x0D0);
+    /// Destination Format Register (DFR).
+    pub const DESTINATION_FORMAT: ApicRegister = ApicRegister(0x0E0);
+    /// Spurious Interrupt Vector Register (SVR).
+    pub const SPURIOUS_INTERRUPT_VECTOR: ApicRegister = ApicRegister(0x0F0);
+    /// Error Status Register (ESR).
+    pub const ERROR_STATUS: ApicRegister = ApicRegister(0x280);
+    /// LVT Corrected Machine Check Interrupt (CMCI) Register.
+    pub const LVT_CMCI: ApicRegister = ApicRegister(0x2F0);
+    /// Interrupt Command Register (ICR), bits 0-31.
+    pub const INTERRUPT_COMMAND_LOW: ApicRegister = ApicRegister(0x300);
+    /// Interrupt Command Register (ICR), bits 32-63.
+    pub const INTERRUPT_COMMAND_HIGH: ApicRegister = ApicRegister(0x310);
+    /// LVT Timer Register.
+    pub const LVT_TIMER: ApicRegister = ApicRegister(0x320);
+    /// LVT Thermal Sensor Register.
+    pub const LVT_THERMAL_SENSOR: ApicRegister = ApicRegister(0x330);
+    /// LVT Performance Monitoring Counters Register.
+    pub const LVT_PERFORMANCE_COUNTER: ApicRegister = ApicRegister(0x340);
+    /// LVT LINT0 Register.
+    pub const LVT_LINT0: ApicRegister = ApicRegister(0x350);
+    /// LVT LINT1 Register.
+    pub const LVT_LINT1: ApicRegister = ApicRegister(0x360);
+    /// LVT Error Register.
+    pub const LVT_ERROR: ApicRegister = ApicRegister(0x370);
+    /// Initial Count Register (for the timer).
+    pub const TIMER_INITIAL_COUNT: ApicRegister = ApicRegister(0x380);
+    /// Current Count Register (for the timer).
+    pub const TIMER_CURRENT_COUNT: ApicRegister = ApicRegister(0x390);
+    /// Divide Configuration Register (for the timer).
+    pub const TIMER_DIVIDE_CONFIGURATION: ApicRegister = ApicRegister(0x3E0);
+
+    /// Returns the In-Service Register (ISR) that contains bits `32 * index..32 * (index + 1)`.
+    ///
+    /// Panics if the index is not in the range 0..8.
+    #[inline]
+    pub fn in_service(index: u8) -> ApicRegister {
+        assert!(index < 8, "there are only 8 in-service registers");
+        ApicRegister(0x100 + u16::from(index) * 0x10)
+    }
+
+    /// Returns the Trigger Mode Register (TMR) that contains bits `32 * index..32 * (index + 1)`.
+    ///
+    /// Panics if the index is not in the range 0..8.
+    #[inline]
+    pub fn trigger_mode(index: u8) -> ApicRegister {
+        assert!(index < 8, "there are only 8 trigger mode registers");
+        ApicRegister(0x180 + u16::from(index) * 0x10)
+    }
+
+    /// Returns the Interrupt Request Register (IRR) that contains bits
+    /// `32 * index..32 * (index + 1)`.
+    ///
+    /// Panics if the index is not in the range 0..8.
+    #[inline]
+    pub fn interrupt_request(index: u8) -> ApicRegister {
+        assert!(index < 8, "there are only 8 interrupt request registers");
+        ApicRegister(0x200 + u16::from(index) * 0x10)
+    }
+
+    /// Returns the offset of the register in the memory mapped xAPIC register page.
+    #[inline]
+    pub const fn offset(self) -> u16 {
+        self.0
+    }
+
+    /// Returns the model specific register that is used for accessing this register in
+    /// x2APIC mode.
+    ///
+    /// Note that the interrupt command register is a single 64-bit register at
+    /// `INTERRUPT_COMMAND_LOW` in x2APIC mode.
+    #[inline]
+    pub const fn x2apic_msr(self) -> u32 {
+        0x800 + (self.0 >> 4) as u32
+    }
+}
+
+/// An entry of the local vector table (LVT).
+///
+/// The local vector table specifies how interrupts that originate in the local APIC are
+/// delivered to the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lvt {
+    /// Corrected machine check error interrupts.
+    Cmci,
+    /// Interrupts of the local APIC timer.
+    Timer,
+    /// Interrupts of the thermal sensor.
+    ThermalSensor,
+    /// Interrupts on performance counter overflow.
+    PerformanceCounter,
+    /// Interrupts of the LINT0 pin.
+    Lint0,
+    /// Interrupts of the LINT1 pin.
+    Lint1,
+    /// Interrupts that signal an internal APIC error.
+    Error,
+}
+
+impl Lvt {
+    /// Returns the register that holds this LVT entry.
+    #[inline]
+    pub const fn register(self) -> ApicRegister {
+        match self {
+            Lvt::Cmci => ApicRegister::LVT_CMCI,
+            Lvt::Timer => ApicRegister::LVT_TIMER,
+            Lvt::ThermalSensor => ApicRegister::LVT_THERMAL_SENSOR,
+            Lvt::PerformanceCounter => ApicRegister::LVT_PERFORMANCE_COUNTER,
+            Lvt::Lint0 => ApicRegister::LVT_LINT0,
+            Lvt::Lint1 => ApicRegister::LVT_LINT1,
+            Lvt::Error => ApicRegister::LVT_ERROR,
+        }
+    }
+}
+
+/// Specifies how the APIC delivers an interrupt to the target processor(s).
+///
+/// Not all delivery modes are valid for all sources: for example, `StartUp` is only
+/// valid for inter-processor interrupts and `ExtInt` is not valid for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    /// Delivers the interrupt specified in the vector field.
+    Fixed = 0b000,
+    /// Delivers the interrupt to the processor with the lowest priority.
+    LowestPriority = 0b001,
+    /// Delivers a system management interrupt (the vector should be zero).
+    Smi = 0b010,
+    /// Delivers a non-maskable interrupt (the vector is ignored).
+    Nmi = 0b100,
+    /// Delivers an INIT request, which causes the processor to perform an INIT.
+    Init = 0b101,
+    /// Sends a start-up IPI (SIPI) to the target processor(s).
+    StartUp = 0b110,
+    /// Causes the processor to respond as if the interrupt came from an external 8259A
+    /// compatible interrupt controller.
+    ExtInt = 0b111,
+}
+
+impl DeliveryMode {
+    /// Converts the 3-bit delivery mode field to a `DeliveryMode`.
+    ///
+    /// Returns `None` for the reserved value `0b011`.
+    #[inline]
+    pub fn from_bits(bits: u8) -> Option<DeliveryMode> {
+        match bits {
+            0b000 => Some(DeliveryMode::Fixed),
+            0b001 => Some(DeliveryMode::LowestPriority),
+            0b010 => Some(DeliveryMode::Smi),
+            0b100 => Some(DeliveryMode::Nmi),
+            0b101 => Some(DeliveryMode::Init),
+            0b110 => Some(DeliveryMode::StartUp),
+            0b111 => Some(DeliveryMode::ExtInt),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// The errors reported by the error status register of the local APIC.
+    pub struct ErrorStatus: u32 {
+        /// A checksum error was detected for a message sent on the APIC bus (P6 and Pentium
+        /// only).
+        const SEND_CHECKSUM = 1 << 0;
+        /// A checksum error was detected for a message received on the APIC bus (P6 and
+        /// Pentium only).
+        const RECEIVE_CHECKSUM = 1 << 1;
+        /// A sent message was not accepted by any APIC (P6 and Pentium only).
+        const SEND_ACCEPT = 1 << 2;
+        /// A received message was not accepted by any APIC (P6 and Pentium only).
+        const RECEIVE_ACCEPT = 1 << 3;
+        /// The local APIC attempted to send a lowest priority IPI, which is not supported.
+        const REDIRECTABLE_IPI = 1 << 4;
+        /// The local APIC attempted to send a message with an illegal vector (0 to 15).
+        const SEND_ILLEGAL_VECTOR = 1 << 5;
+        /// The local APIC received a message with an illegal vector or an LVT entry
+        /// contains an illegal vector.
+        const RECEIVE_ILLEGAL_VECTOR = 1 << 6;
+        /// Software accessed a reserved register of the local APIC (xAPIC mode only).
+        const ILLEGAL_REGISTER_ADDRESS = 1 << 7;
+    }
+}
+
+/// The value of a local vector table register.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct LvtEntry(pub u32);
+
+impl LvtEntry {
+    /// Creates an unmasked entry that delivers the given vector with fixed delivery mode.
+    #[inline]
+    pub const fn new(vector: u8) -> LvtEntry {
+        LvtEntry(vector as u32)
+    }
+
+    /// Returns the interrupt vector number.
+    #[inline]
+    pub fn vector(self) -> u8 {
+        self.0.get_bits(0..8) as u8
+    }
+
+    /// Sets the interrupt vector number.
+    #[inline]
+    pub fn set_vector(&mut self, vector: u8) -> &mut Self {
+        self.0.set_bits(0..8, u32::from(vector));
+        self
+    }
+
+    /// Returns the delivery mode, or `None` if the field contains a reserved value.
+    ///
+    /// This field is not present in the timer and error entries.
+    #[inline]
+    pub fn delivery_mode(self) -> Option<DeliveryMode> {
+        DeliveryMode::from_bits(self.0.get_bits(8..11) as u8)
+    }
+
+    /// Sets the delivery mode.
+    #[inline]
+    pub fn set_delivery_mode(&mut self, mode: DeliveryMode) -> &mut Self {
+        self.0.set_bits(8..11, mode as u32);
+        self
+    }
+
+    /// Returns whether an interrupt from this source was sent to the processor core, but was
+    /// not accepted yet (send pending).
+    #[inline]
+    pub fn delivery_pending(self) -> bool {
+        self.0.get_bit(12)
+    }
+
+    /// Returns whether the interrupt input pin is active low (only for LINT0 and LINT1).
+    #[inline]
+    pub fn active_low(self) -> bool {
+        self.0.get_bit(13)
+    }
+
+    /// Sets the polarity of the interrupt input pin (only for LINT0 and LINT1).
+    #[inline]
+    pub fn set_active_low(&mut self, active_low: bool) -> &mut Self {
+        self.0.set_bit(13, active_low);
+        self
+    }
+
+    /// Returns whether the interrupt was accepted by the local APIC, but no EOI was
+    /// received yet (only for level triggered LINT0 and LINT1 interrupts).
+    #[inline]
+    pub fn remote_irr(self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    /// Returns whether the interrupt input pin is level triggered (only for LINT0 and LINT1).
+    #[inline]
+    pub fn level_triggered(self) -> bool {
+        self.0.get_bit(15)
+    }
+
+    /// Sets the trigger mode of the interrupt input pin (only for LINT0 and LINT1).
+    #[inline]
+    pub fn set_level_triggered(&mut self, level_triggered: bool) -> &mut Self {
+        self.0.set_bit(15, level_triggered);
+        self
+    }
+
+    /// Returns whether the interrupt is masked.
+    #[inline]
+    pub fn masked(self) -> bool {
+        self.0.get_bit(16)
+    }
+
+    /// Masks or unmasks the interrupt.
+    #[inline]
+    pub fn set_masked(&mut self, masked: bool) -> &mut Self {
+        self.0.set_bit(16, masked);
+        self
+    }
+
+    /// Returns the raw value of the timer mode field (only for the timer entry).
+    #[inline]
+    pub fn timer_mode_bits(self) -> u8 {
+        self.0.get_bits(17..19) as u8
+    }
+
+    /// Sets the raw value of the timer mode field (only for the timer entry).
+    #[inline]
+    pub fn set_timer_mode_bits(&mut self, mode: u8) -> &mut Self {
+        self.0.set_bits(17..19, u32::from(mode));
+        self
+    }
+
+    /// Returns the timer mode (only for the timer entry).
+    ///
+    /// Returns `None` for the reserved value `0b11`.
+    #[inline]
+    pub fn timer_mode(self) -> Option<TimerMode> {
+        TimerMode::from_bits(self.timer_mode_bits())
+    }
+
+    /// Sets the timer mode (only for the timer entry).
+    #[inline]
+    pub fn set_timer_mode(&mut self, mode: TimerMode) -> &mut Self {
+        self.set_timer_mode_bits(mode as u8)
+    }
+}
+
+impl fmt::Debug for LvtEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("LvtEntry");
+        s.field("vector", &self.vector());
+        s.field("delivery_mode", &self.delivery_mode());
+        s.field("delivery_pending", &self.delivery_pending());
+        s.field("masked", &self.masked());
+        s.finish()
+    }
+}
+
+/// The content of the local APIC version register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApicVersion {
+    /// The version number of the local APIC (0x10 to 0x15 for integrated APICs).
+    pub version: u8,
+    /// The number of LVT entries minus 1.
+    pub max_lvt_entry: u8,
+    /// Whether software can suppress the broadcast of EOI messages to I/O APICs.
+    pub eoi_broadcast_suppression: bool,
+}
+
+impl ApicVersion {
+    /// Decodes the raw value of the version register.
+    #[inline]
+    pub fn from_raw(value: u32) -> ApicVersion {
+        ApicVersion {
+            version: value.get_bits(0..8) as u8,
+            max_lvt_entry: value.get_bits(16..24) as u8,
+            eoi_broadcast_suppression: value.get_bit(24),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A local APIC whose registers are plain memory, with a write-then-read error status
+    /// register like the hardware.
+    #[derive(Debug)]
+    pub(super) struct FakeApic {
+        pub(super) registers: [u32; 64],
+        pub(super) errors: u32,
+    }
+
+    impl FakeApic {
+        pub(super) fn new() -> FakeApic {
+            FakeApic {
+                registers: [0; 64],
+                errors: 0,
+            }
+        }
+
+        pub(super) fn register(&self, register: ApicRegister) -> u32 {
+            self.registers[usize::from(register.0 >> 4)]
+        }
+    }
+
+    impl Apic for FakeApic {
+        unsafe fn read(&self, register: ApicRegister) -> u32 {
+            self.register(register)
+        }
+
+        unsafe fn write(&mut self, register: ApicRegister, value: u32) {
+            let value = if register == ApicRegister::ERROR_STATUS {
+                core::mem::take(&mut self.errors)
+            } else {
+                value
+            };
+            self.registers[usize::from(register.0 >> 4)] = value;
+        }
+
+        fn id(&self) -> u32 {
+            self.register(ApicRegister::ID) >> 24
+        }
+
+        fn icr(&self) -> u64 {
+            u64::from(self.register(ApicRegister::INTERRUPT_COMMAND_HIGH)) << 32
+                | u64::from(self.register(ApicRegister::INTERRUPT_COMMAND_LOW))
+        }
+
+        unsafe fn set_icr(&mut self, value: u64) {
+            self.write(ApicRegister::INTERRUPT_COMMAND_HIGH, (value >> 32) as u32);
+            self.write(ApicRegister::INTERRUPT_COMMAND_LOW, value as u32);
+        }
+
+        fn icr_delivery_pending(&self) -> bool {
+            false
+        }
+
+        fn is_x2apic(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn register_offsets() {
+        assert_eq!(ApicRegister::in_service(0).offset(), 0x100);
+        assert_eq!(ApicRegister::in_service(7).offset(), 0x170);
+        assert_eq!(ApicRegister::trigger_mode(1).offset(), 0x190);
+        assert_eq!(ApicRegister::interrupt_request(7).offset(), 0x270);
+        assert_eq!(Lvt::Cmci.register(), ApicRegister::LVT_CMCI);
+        assert_eq!(Lvt::Lint1.register().offset(), 0x360);
+        assert_eq!(Lvt::Error.register().offset(), 0x370);
+    }
+
+    #[test]
+    #[should_panic]
+    fn in_service_out_of_range() {
+        ApicRegister::in_service(8);
+    }
+
+    #[test]
+    fn lvt_entry_bits() {
+        let mut entry = LvtEntry::new(0x31);
+        entry
+            .set_delivery_mode(DeliveryMode::ExtInt)
+            .set_active_low(true)
+            .set_level_triggered(true)
+            .set_masked(true);
+        assert_eq!(entry.0, 1 << 16 | 1 << 15 | 1 << 13 | 0b111 << 8 | 0x31);
+        assert_eq!(entry.vector(), 0x31);
+        assert_eq!(entry.delivery_mode(), Some(DeliveryMode::ExtInt));
+        assert!(entry.active_low() && entry.level_triggered() && entry.masked());
+
+        entry.set_vector(0xfe).set_masked(false);
+        assert_eq!(entry.0, 1 << 15 | 1 << 13 | 0b111 << 8 | 0xfe);
+
+        let status = LvtEntry(1 << 14 | 1 << 12 | 0b011 << 8);
+        assert!(status.remote_irr() && status.delivery_pending());
+        assert_eq!(status.delivery_mode(), None);
+    }
+
+    #[test]
+    fn delivery_mode_bits() {
+        for &mode in &[
+            DeliveryMode::Fixed,
+            DeliveryMode::LowestPriority,
+            DeliveryMode::Smi,
+            DeliveryMode::Nmi,
+            DeliveryMode::Init,
+            DeliveryMode::StartUp,
+            DeliveryMode::ExtInt,
+        ] {
+            assert_eq!(DeliveryMode::from_bits(mode as u8), Some(mode));
+        }
+        assert_eq!(DeliveryMode::from_bits(0b011), None);
+    }
+
+    #[test]
+    fn version() {
+        let version = ApicVersion::from_raw(1 << 24 | 6 << 16 | 0x15);
+        assert_eq!(version.version, 0x15);
+        assert_eq!(version.max_lvt_entry, 6);
+        assert!(version.eoi_broadcast_suppression);
+    }
+
+    #[test]
+    fn provided_methods() {
+        let mut apic = FakeApic::new();
+        apic.set_spurious_vector(0xff);
+        apic.enable();
+        assert_eq!(
+            apic.register(ApicRegister::SPURIOUS_INTERRUPT_VECTOR),
+            0x1ff
+        );
+        assert!(apic.is_enabled());
+        apic.disable();
+        assert_eq!(apic.spurious_vector(), 0xff);
+        assert!(!apic.is_enabled());
+
+        apic.registers[usize::from(ApicRegister::in_service(1).offset() >> 4)] = 1 << 3;
+        assert!(apic.is_in_service(35));
+        assert!(!apic.is_in_service(3) && !apic.is_requested(35));
+
+        unsafe { apic.configure_timer(0x40, TimerMode::Periodic) };
+        assert_eq!(apic.register(ApicRegister::LVT_TIMER), 0b01 << 17 | 0x40);
+        apic.set_timer_initial_count(1000);
+        apic.stop_timer();
+        assert!(apic.lvt(Lvt::Timer).masked());
+        assert_eq!(apic.timer_initial_count(), 0);
+    }
+
+    #[test]
+    fn error_status() {
+        let mut apic = FakeApic::new();
+        // bit 8 is reserved
+        apic.errors = 1 << 8 | 1 << 7 | 1 << 5;
+        assert_eq!(
+            apic.error_status(),
+            ErrorStatus::ILLEGAL_REGISTER_ADDRESS | ErrorStatus::SEND_ILLEGAL_VECTOR
+        );
+        assert_eq!(apic.error_status(), ErrorStatus::empty());
+
+        apic.errors = 0xff;
+        assert_eq!(apic.error_status(), ErrorStatus::all());
+        assert_eq!(ErrorStatus::RECEIVE_CHECKSUM.bits(), 1 << 1);
+        assert_eq!(ErrorStatus::RECEIVE_ACCEPT.bits(), 1 << 3);
+        assert_eq!(ErrorStatus::REDIRECTABLE_IPI.bits(), 1 << 4);
+        assert_eq!(ErrorStatus::RECEIVE_ILLEGAL_VECTOR.bits(), 1 << 6);
+    }
+}
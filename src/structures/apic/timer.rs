@@ -0,0 +1,194 @@
+//! Provides types for configuring the local APIC timer.
+
+use super::{Apic, Lvt};
+use crate::cpuid::{cpuid, CpuidResult};
+
+/// The operating mode of the local APIC timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimerMode {
+    /// The timer counts down once from the initial count and then stops.
+    OneShot = 0b00,
+    /// The timer counts down from the initial count and reloads it when reaching zero.
+    Periodic = 0b01,
+    /// The timer fires when the time stamp counter reaches the value written to the
+    /// [`TscDeadline`](crate::registers::model_specific::TscDeadline) register.
+    TscDeadline = 0b10,
+}
+
+impl TimerMode {
+    /// Converts the 2-bit timer mode field to a `TimerMode`.
+    ///
+    /// Returns `None` for the reserved value `0b11`.
+    #[inline]
+    pub fn from_bits(bits: u8) -> Option<TimerMode> {
+        match bits {
+            0b00 => Some(TimerMode::OneShot),
+            0b01 => Some(TimerMode::Periodic),
+            0b10 => Some(TimerMode::TscDeadline),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the processor supports the TSC-deadline mode.
+    ///
+    /// The support is indicated by CPUID leaf 01h, ecx bit 24.
+    #[inline]
+    pub fn is_tsc_deadline_supported() -> bool {
+        Self::is_tsc_deadline_supported_with(cpuid)
+    }
+
+    /// Returns whether the TSC-deadline mode is supported, using `query` instead of the
+    /// `cpuid` instruction.
+    fn is_tsc_deadline_supported_with<F>(mut query: F) -> bool
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        query(1, 0).ecx & (1 << 24) != 0
+    }
+}
+
+/// The divider applied to the bus clock for the one-shot and periodic timer modes.
+///
+/// The values correspond to the encoding of the divide configuration register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TimerDivide {
+    /// Divide by 1.
+    By1 = 0b1011,
+    /// Divide by 2.
+    By2 = 0b0000,
+    /// Divide by 4.
+    By4 = 0b0001,
+    /// Divide by 8.
+    By8 = 0b0010,
+    /// Divide by 16.
+    By16 = 0b0011,
+    /// Divide by 32.
+    By32 = 0b1000,
+    /// Divide by 64.
+    By64 = 0b1001,
+    /// Divide by 128.
+    By128 = 0b1010,
+}
+
+impl TimerDivide {
+    /// Converts the value of the divide configuration register to a `TimerDivide`.
+    ///
+    /// Only bits 0, 1, and 3 are considered, all other bits are reserved.
+    #[inline]
+    pub fn from_bits(bits: u32) -> TimerDivide {
+        match bits & 0b1011 {
+            0b0000 => TimerDivide::By2,
+            0b0001 => TimerDivide::By4,
+            0b0010 => TimerDivide::By8,
+            0b0011 => TimerDivide::By16,
+            0b1000 => TimerDivide::By32,
+            0b1001 => TimerDivide::By64,
+            0b1010 => TimerDivide::By128,
+            _ => TimerDivide::By1,
+        }
+    }
+
+    /// Returns the divisor as a number.
+    #[inline]
+    pub fn divisor(self) -> u32 {
+        match self {
+            TimerDivide::By1 => 1,
+            TimerDivide::By2 => 2,
+            TimerDivide::By4 => 4,
+            TimerDivide::By8 => 8,
+            TimerDivide::By16 => 16,
+            TimerDivide::By32 => 32,
+            TimerDivide::By64 => 64,
+            TimerDivide::By128 => 128,
+        }
+    }
+}
+
+/// Measures the number of local APIC timer ticks that elapse during a reference interval.
+///
+/// The timer is started in masked one-shot mode with the given divider and the maximum
+/// initial count, then `wait` is called, which should busy wait for a known interval
+/// using a reference clock (e.g. the PIT or the time stamp counter). Afterwards, the timer
+/// is stopped and the number of elapsed ticks is returned. The result can be used to
+/// compute the initial count for a desired timer period.
+///
+/// Note that the LVT timer entry is left masked after the calibration.
+pub fn calibrate_timer<A, F>(apic: &mut A, divide: TimerDivide, wait: F) -> u32
+where
+    A: Apic + ?Sized,
+    F: FnOnce(),
+{
+    let mut entry = apic.lvt(Lvt::Timer);
+    entry.set_timer_mode(TimerMode::OneShot).set_masked(true);
+    unsafe { apic.set_lvt(Lvt::Timer, entry) };
+    apic.set_timer_divide(divide);
+
+    apic.set_timer_initial_count(u32::MAX);
+    wait();
+    let remaining = apic.timer_current_count();
+    apic.set_timer_initial_count(0);
+
+    u32::MAX - remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::LvtEntry;
+    use super::*;
+    use crate::cpuid::fake;
+
+    const DIVIDES: [(TimerDivide, u32, u32); 8] = [
+        (TimerDivide::By1, 0b1011, 1),
+        (TimerDivide::By2, 0b0000, 2),
+        (TimerDivide::By4, 0b0001, 4),
+        (TimerDivide::By8, 0b0010, 8),
+        (TimerDivide::By16, 0b0011, 16),
+        (TimerDivide::By32, 0b1000, 32),
+        (TimerDivide::By64, 0b1001, 64),
+        (TimerDivide::By128, 0b1010, 128),
+    ];
+
+    #[test]
+    fn tsc_deadline_support() {
+        assert!(TimerMode::is_tsc_deadline_supported_with(fake(&[(
+            1,
+            [0, 0, 1 << 24, 0]
+        )])));
+        assert!(!TimerMode::is_tsc_deadline_supported_with(fake(&[(
+            1,
+            [0, 0, !(1 << 24), !0]
+        )])));
+    }
+
+    #[test]
+    fn timer_mode_bits() {
+        for &mode in &[
+            TimerMode::OneShot,
+            TimerMode::Periodic,
+            TimerMode::TscDeadline,
+        ] {
+            assert_eq!(TimerMode::from_bits(mode as u8), Some(mode));
+
+            let mut entry = LvtEntry::new(0x20);
+            entry.set_masked(true).set_timer_mode(mode);
+            assert_eq!(entry.0, 1 << 16 | (mode as u32) << 17 | 0x20);
+            assert_eq!(entry.timer_mode(), Some(mode));
+        }
+        assert_eq!(TimerMode::from_bits(0b11), None);
+        assert_eq!(LvtEntry(0b11 << 17).timer_mode(), None);
+    }
+
+    #[test]
+    fn divide_bits() {
+        for &(divide, bits, divisor) in &DIVIDES {
+            assert_eq!(divide as u32, bits);
+            assert_eq!(TimerDivide::from_bits(bits), divide);
+            assert_eq!(divide.divisor(), divisor);
+        }
+        // bit 2 and the bits above 3 are reserved
+        assert_eq!(TimerDivide::from_bits(0xffff_fff4), TimerDivide::By2);
+        assert_eq!(TimerDivide::from_bits(0b0111), TimerDivide::By16);
+    }
+}
@@ -0,0 +1,167 @@
+//! Provides a type for the local APIC in x2APIC mode.
+
+use super::{Apic, ApicRegister};
+use crate::cpuid::{cpuid, CpuidResult};
+use crate::registers::model_specific::{ApicBase, ApicBaseFlags, Msr};
+
+/// The local APIC of the current processor in x2APIC mode.
+///
+/// In x2APIC mode, the local APIC registers are accessed through the model specific
+/// registers `0x800` to `0x8FF` instead of a memory mapped register page. The registers
+/// are accessed through the methods of the [`Apic`] trait.
+#[derive(Debug)]
+pub struct X2Apic {
+    _private: (),
+}
+
+impl X2Apic {
+    /// Returns whether the processor supports the x2APIC mode.
+    ///
+    /// The support is indicated by CPUID leaf 01h, ecx bit 21.
+    #[inline]
+    pub fn is_supported() -> bool {
+        Self::is_supported_with(cpuid)
+    }
+
+    /// Returns whether x2APIC mode is supported, using `query` instead of the `cpuid`
+    /// instruction.
+    fn is_supported_with<F>(mut query: F) -> bool
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        query(1, 0).ecx & (1 << 21) != 0
+    }
+
+    /// Returns whether the local APIC of the current processor is in x2APIC mode.
+    #[inline]
+    pub fn is_active() -> bool {
+        let (_, flags) = ApicBase::read();
+        flags.contains(ApicBaseFlags::GLOBAL_ENABLE | ApicBaseFlags::X2APIC_ENABLE)
+    }
+
+    /// Creates a new `X2Apic` for a local APIC that is already in x2APIC mode.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the local APIC of the
+    /// current processor is in x2APIC mode. Also, there must be no other `X2Apic` instance for
+    /// the same processor that is used concurrently.
+    #[inline]
+    pub const unsafe fn new() -> X2Apic {
+        X2Apic { _private: () }
+    }
+
+    /// Switches the local APIC of the current processor to x2APIC mode.
+    ///
+    /// If the local APIC is globally disabled, it is enabled first. The state of the local
+    /// APIC is preserved on the transition from xAPIC to x2APIC mode.
+    ///
+    /// Panics if x2APIC mode is not supported by the processor.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that no code accesses the
+    /// memory mapped xAPIC registers after the switch. Also, there must be no other `X2Apic`
+    /// instance for the same processor that is used concurrently.
+    #[inline]
+    pub unsafe fn enable() -> X2Apic {
+        assert!(Self::is_supported(), "x2APIC mode is not supported");
+
+        let (frame, mut flags) = ApicBase::read();
+        if !flags.contains(ApicBaseFlags::GLOBAL_ENABLE) {
+            flags.insert(ApicBaseFlags::GLOBAL_ENABLE);
+            ApicBase::write(frame, flags);
+        }
+        if !flags.contains(ApicBaseFlags::X2APIC_ENABLE) {
+            flags.insert(ApicBaseFlags::X2APIC_ENABLE);
+            ApicBase::write(frame, flags);
+        }
+        X2Apic::new()
+    }
+
+    /// Switches the local APIC of the current processor back to xAPIC mode.
+    ///
+    /// A direct transition from x2APIC to xAPIC mode is not allowed by the architecture, so
+    /// the local APIC is globally disabled first and then enabled in xAPIC mode. This resets
+    /// the local APIC to its power-up state, i.e. all LVT entries are masked and the APIC is
+    /// software disabled afterwards.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because resetting the local APIC can break assumptions of the
+    /// interrupt handling code.
+    #[inline]
+    pub unsafe fn disable(self) {
+        let (frame, mut flags) = ApicBase::read();
+        flags.remove(ApicBaseFlags::GLOBAL_ENABLE | ApicBaseFlags::X2APIC_ENABLE);
+        ApicBase::write(frame, flags);
+        flags.insert(ApicBaseFlags::GLOBAL_ENABLE);
+        ApicBase::write(frame, flags);
+    }
+}
+
+impl Apic for X2Apic {
+    /// Reads the given register through its model specific register.
+    ///
+    /// Note that some xAPIC registers, e.g. `INTERRUPT_COMMAND_HIGH` and
+    /// `DESTINATION_FORMAT`, do not exist in x2APIC mode. Reading them causes a general
+    /// protection fault.
+    #[inline]
+    unsafe fn read(&self, register: ApicRegister) -> u32 {
+        Msr::new(register.x2apic_msr()).read() as u32
+    }
+
+    #[inline]
+    unsafe fn write(&mut self, register: ApicRegister, value: u32) {
+        Msr::new(register.x2apic_msr()).write(u64::from(value));
+    }
+
+    #[inline]
+    fn id(&self) -> u32 {
+        unsafe { self.read(ApicRegister::ID) }
+    }
+
+    #[inline]
+    fn icr(&self) -> u64 {
+        unsafe { Msr::new(ApicRegister::INTERRUPT_COMMAND_LOW.x2apic_msr()).read() }
+    }
+
+    #[inline]
+    unsafe fn set_icr(&mut self, value: u64) {
+        Msr::new(ApicRegister::INTERRUPT_COMMAND_LOW.x2apic_msr()).write(value);
+    }
+
+    #[inline]
+    fn icr_delivery_pending(&self) -> bool {
+        false
+    }
+
+    #[inline]
+    fn is_x2apic(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake;
+
+    #[test]
+    fn support() {
+        assert!(X2Apic::is_supported_with(fake(&[(1, [0, 0, 1 << 21, 0])])));
+        assert!(!X2Apic::is_supported_with(fake(&[(
+            1,
+            [0, 0, !(1 << 21), !0]
+        )])));
+    }
+
+    #[test]
+    fn register_msrs() {
+        assert_eq!(ApicRegister::ID.x2apic_msr(), 0x802);
+        assert_eq!(ApicRegister::TASK_PRIORITY.x2apic_msr(), 0x808);
+        assert_eq!(ApicRegister::END_OF_INTERRUPT.x2apic_msr(), 0x80B);
+        assert_eq!(ApicRegister::INTERRUPT_COMMAND_LOW.x2apic_msr(), 0x830);
+        assert_eq!(ApicRegister::TIMER_DIVIDE_CONFIGURATION.x2apic_msr(), 0x83E);
+    }
+}
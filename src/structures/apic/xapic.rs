@@ -0,0 +1,140 @@
+//! Provides a type for the memory mapped local APIC (xAPIC mode).
+
+use super::{Apic, ApicRegister};
+use crate::structures::mmio::Mmio;
+use crate::VirtAddr;
+use bit_field::BitField;
+
+/// The memory mapped registers of the local APIC of the current processor in xAPIC mode.
+///
+/// The register page is located at the physical address stored in the
+/// [`ApicBase`](crate::registers::model_specific::ApicBase) register and needs to be
+/// mapped as uncacheable memory before this type can be used. Since the register page is
+/// per-processor, every processor accesses its own local APIC through the same address.
+///
+/// The registers are accessed through the methods of the [`Apic`] trait.
+#[derive(Debug)]
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    /// Creates a new `LocalApic` for the register page mapped at the given virtual address.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the complete 4KiB
+    /// register page of the local APIC is mapped at `base` with caching disabled. Also, there
+    /// must be no other `LocalApic` instance for the same processor that is used concurrently.
+    #[inline]
+    pub const unsafe fn new(base: VirtAddr) -> LocalApic {
+        LocalApic { base }
+    }
+
+    /// Returns the virtual address the register page is mapped at.
+    #[inline]
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns a handle for the given memory mapped register.
+    #[inline]
+    fn register(&self, register: ApicRegister) -> Mmio<u32> {
+        unsafe { Mmio::from_addr(self.base + u64::from(register.offset())) }
+    }
+}
+
+impl Apic for LocalApic {
+    #[inline]
+    unsafe fn read(&self, register: ApicRegister) -> u32 {
+        self.register(register).read()
+    }
+
+    #[inline]
+    unsafe fn write(&mut self, register: ApicRegister, value: u32) {
+        self.register(register).write(value);
+    }
+
+    #[inline]
+    fn id(&self) -> u32 {
+        unsafe { self.read(ApicRegister::ID) }.get_bits(24..32)
+    }
+
+    #[inline]
+    fn icr(&self) -> u64 {
+        let low = unsafe { self.read(ApicRegister::INTERRUPT_COMMAND_LOW) };
+        let high = unsafe { self.read(ApicRegister::INTERRUPT_COMMAND_HIGH) };
+        (u64::from(high) << 32) | u64::from(low)
+    }
+
+    /// Writes the raw value of the interrupt command register (ICR).
+    ///
+    /// The high half is written first, since writing the low half sends the IPI.
+    #[inline]
+    unsafe fn set_icr(&mut self, value: u64) {
+        self.write(ApicRegister::INTERRUPT_COMMAND_HIGH, (value >> 32) as u32);
+        self.write(ApicRegister::INTERRUPT_COMMAND_LOW, value as u32);
+    }
+
+    #[inline]
+    fn icr_delivery_pending(&self) -> bool {
+        unsafe { self.read(ApicRegister::INTERRUPT_COMMAND_LOW) }.get_bit(12)
+    }
+
+    #[inline]
+    fn is_x2apic(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A register page in memory. Each register occupies the first 4 bytes of its 16-byte
+    /// slot.
+    #[repr(C, align(4096))]
+    struct FakePage([u32; 1024]);
+
+    impl FakePage {
+        fn local_apic(&mut self) -> LocalApic {
+            unsafe { LocalApic::new(VirtAddr::from_ptr(self.0.as_mut_ptr())) }
+        }
+
+        fn register(&self, register: ApicRegister) -> u32 {
+            self.0[usize::from(register.offset() / 4)]
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let mut page = FakePage([0; 1024]);
+        page.0[0x20 / 4] = 0x0300_0000;
+        page.0[0x30 / 4] = 0x0105_0014;
+        let apic = page.local_apic();
+        assert_eq!(apic.id(), 3);
+        assert_eq!(apic.version().max_lvt_entry, 5);
+        assert!(!apic.is_x2apic());
+
+        let mut apic = page.local_apic();
+        apic.set_task_priority(0x20);
+        apic.set_spurious_vector(0xff);
+        apic.enable();
+        unsafe { apic.set_icr(0x0500_0000_0000_4031) };
+        assert_eq!(page.register(ApicRegister::TASK_PRIORITY), 0x20);
+        assert_eq!(
+            page.register(ApicRegister::SPURIOUS_INTERRUPT_VECTOR),
+            0x1ff
+        );
+        assert_eq!(
+            page.register(ApicRegister::INTERRUPT_COMMAND_HIGH),
+            0x0500_0000
+        );
+        assert_eq!(page.register(ApicRegister::INTERRUPT_COMMAND_LOW), 0x4031);
+
+        page.0[0x300 / 4] |= 1 << 12;
+        let apic = page.local_apic();
+        assert!(apic.icr_delivery_pending());
+        assert_eq!(apic.icr(), 0x0500_0000_0000_5031);
+    }
+}
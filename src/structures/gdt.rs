@@ -1,7 +1,8 @@
 //! Types for the Global Descriptor Table and segment selectors.
 
 use crate::structures::tss::TaskStateSegment;
-use crate::PrivilegeLevel;
+use crate::structures::DescriptorTablePointer;
+use crate::{PrivilegeLevel, VirtAddr};
 use bit_field::BitField;
 use bitflags::bitflags;
 use core::fmt;
@@ -11,6 +12,7 @@ use core::fmt;
 /// with some additional flags).
 ///
 /// See Intel 3a, Section 3.4.2 "Segment Selectors"
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 pub struct SegmentSelector(pub u16);
@@ -28,7 +30,7 @@ impl SegmentSelector {
 
     /// Returns the GDT index.
     #[inline]
-    pub fn index(self) -> u16 {
+    pub const fn index(self) -> u16 {
         self.0 >> 3
     }
 
@@ -38,6 +40,22 @@ impl SegmentSelector {
         PrivilegeLevel::from_u16(self.0.get_bits(0..2))
     }
 
+    /// Returns whether the selector references the local descriptor table (LDT) instead of
+    /// the global descriptor table (GDT).
+    #[inline]
+    pub const fn is_ldt(self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    #[inline]
+    fn table_name(self) -> &'static str {
+        if self.is_ldt() {
+            "LDT"
+        } else {
+            "GDT"
+        }
+    }
+
     /// Set the privilege level for this Segment selector.
     #[inline]
     pub fn set_rpl(&mut self, rpl: PrivilegeLevel) {
@@ -49,11 +67,27 @@ impl fmt::Debug for SegmentSelector {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = f.debug_struct("SegmentSelector");
         s.field("index", &self.index());
+        s.field("table", &format_args!("{}", self.table_name()));
         s.field("rpl", &self.rpl());
         s.finish()
     }
 }
 
+/// Formats the selector as its raw value followed by the decoded fields,
+/// e.g. `0x0008 (GDT index 1, Ring0)`.
+impl fmt::Display for SegmentSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#06x} ({} index {}, {:?})",
+            self.0,
+            self.table_name(),
+            self.index(),
+            self.rpl()
+        )
+    }
+}
+
 /// A 64-bit mode global descriptor table (GDT).
 ///
 /// In 64-bit mode, segmentation is not supported. The GDT is used nonetheless, for example for
@@ -105,20 +139,25 @@ impl GlobalDescriptorTable {
         }
     }
 
-    /// Adds the given segment descriptor to the GDT, returning the segment selector.
-    ///
-    /// Panics if the GDT has no free entries left.
-    #[inline]
-    pub fn add_entry(&mut self, entry: Descriptor) -> SegmentSelector {
-        let index = match entry {
-            Descriptor::UserSegment(value) => self.push(value),
-            Descriptor::SystemSegment(value_low, value_high) => {
-                let index = self.push(value_low);
-                self.push(value_high);
-                index
-            }
-        };
-        SegmentSelector::new(index as u16, PrivilegeLevel::Ring0)
+    const_fn! {
+        /// Adds the given segment descriptor to the GDT, returning the segment selector.
+        ///
+        /// With the `const_fn` feature, this can be used to build a GDT in a constant
+        /// initializer, so that the table can be put into a `static` directly.
+        ///
+        /// Panics if the GDT has no free entries left.
+        #[inline]
+        pub fn add_entry(&mut self, entry: Descriptor) -> SegmentSelector {
+            let index = match entry {
+                Descriptor::UserSegment(value) => self.push(value),
+                Descriptor::SystemSegment(value_low, value_high) => {
+                    let index = self.push(value_low);
+                    self.push(value_high);
+                    index
+                }
+            };
+            SegmentSelector::new(index as u16, PrivilegeLevel::Ring0)
+        }
     }
 
     /// Loads the GDT in the CPU using the `lgdt` instruction. This does **not** alter any of the
@@ -126,29 +165,139 @@ impl GlobalDescriptorTable {
     /// functions](crate::instructions::segmentation):
     /// [load_ss](crate::instructions::segmentation::load_ss),
     /// [set_cs](crate::instructions::segmentation::set_cs).
-    #[cfg(target_arch = "x86_64")]
+    ///
+    /// The `'static` bound ensures that the GDT outlives its use by the CPU. Use
+    /// [`load_unsafe`](GlobalDescriptorTable::load_unsafe) for GDTs with a shorter lifetime.
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
     #[inline]
     pub fn load(&'static self) {
-        use crate::instructions::tables::{lgdt, DescriptorTablePointer};
+        unsafe { self.load_unsafe() }
+    }
+
+    /// Loads the GDT in the CPU using the `lgdt` instruction. This does **not** alter any of the
+    /// segment registers; you **must** (re)load them yourself.
+    ///
+    /// # Safety
+    ///
+    /// As long as it is the active GDT, you must ensure that:
+    ///
+    /// - `self` is never destroyed.
+    /// - `self` always stays at the same memory location. It is recommended to wrap it in
+    ///   a `Box`.
+    ///
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    #[inline]
+    pub unsafe fn load_unsafe(&self) {
+        use crate::instructions::tables::lgdt;
+
+        lgdt(&self.pointer());
+    }
+
+    /// Returns a pointer to this GDT in the format expected by the `lgdt` instruction.
+    ///
+    /// The pointer is only valid as long as `self` is neither destroyed nor moved.
+    #[inline]
+    pub fn pointer(&self) -> DescriptorTablePointer {
         use core::mem::size_of;
 
-        let ptr = DescriptorTablePointer {
+        DescriptorTablePointer {
             base: self.table.as_ptr() as u64,
             limit: (self.table.len() * size_of::<u64>() - 1) as u16,
-        };
+        }
+    }
 
-        unsafe { lgdt(&ptr) };
+    /// Replaces the TSS descriptor referenced by `selector` with a descriptor for `tss`.
+    ///
+    /// The new descriptor is marked as available (not busy), so it can be loaded with
+    /// [`switch_tss`](GlobalDescriptorTable::switch_tss) afterwards.
+    ///
+    /// Panics if `selector` does not reference a TSS descriptor in this GDT.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that the old TSS is no longer
+    /// the active TSS, or becomes inactive before it is destroyed.
+    #[inline]
+    pub unsafe fn replace_tss(
+        &mut self,
+        selector: SegmentSelector,
+        tss: &'static TaskStateSegment,
+    ) {
+        let index = self.tss_index(selector);
+        if let Descriptor::SystemSegment(low, high) = Descriptor::tss_segment(tss) {
+            self.table[index] = low;
+            self.table[index + 1] = high;
+        }
     }
 
+    /// Clears the busy bit of the TSS descriptor referenced by `selector`.
+    ///
+    /// The CPU sets the busy bit when a TSS is loaded with `ltr` and refuses to load a TSS
+    /// whose busy bit is set, so the bit must be cleared before a TSS descriptor is reloaded.
+    ///
+    /// Panics if `selector` does not reference a TSS descriptor in this GDT.
     #[inline]
-    fn push(&mut self, value: u64) -> usize {
-        if self.next_free < self.table.len() {
-            let index = self.next_free;
-            self.table[index] = value;
-            self.next_free += 1;
-            index
-        } else {
-            panic!("GDT full");
+    pub fn clear_tss_busy(&mut self, selector: SegmentSelector) {
+        let index = self.tss_index(selector);
+        self.table[index].set_bit(41, false);
+    }
+
+    /// Loads the TSS descriptor referenced by `selector` into the task register.
+    ///
+    /// The busy bits of the currently loaded TSS descriptor (if it is in this GDT) and
+    /// of the new descriptor are cleared before the new TSS is loaded with `ltr`, so this
+    /// function can be used to switch between TSSs at runtime and to reload the current one.
+    ///
+    /// Panics if `selector` does not reference a TSS descriptor in this GDT.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that this GDT is the currently
+    /// loaded GDT and that the TSS referenced by `selector` is valid as long as it is loaded.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub unsafe fn switch_tss(&mut self, selector: SegmentSelector) {
+        use crate::instructions::tables::{load_tss, tr};
+
+        let old = tr();
+        if old.index() != 0 && self.is_tss(old) {
+            self.clear_tss_busy(old);
+        }
+        self.clear_tss_busy(selector);
+        load_tss(selector);
+    }
+
+    /// Returns whether `selector` references a TSS descriptor in this GDT.
+    #[inline]
+    fn is_tss(&self, selector: SegmentSelector) -> bool {
+        let index = usize::from(selector.index());
+        // type 0b1001 = available 64-bit tss, 0b1011 = busy 64-bit tss, both system segments
+        // (S = 0), since accessed code segments have the same type bits
+        index + 1 < self.next_free
+            && !self.table[index].get_bit(44)
+            && self.table[index].get_bits(40..44) & 0b1101 == 0b1001
+    }
+
+    /// Returns the table index of the TSS descriptor referenced by `selector`.
+    ///
+    /// Panics if `selector` does not reference a TSS descriptor in this GDT.
+    #[inline]
+    fn tss_index(&self, selector: SegmentSelector) -> usize {
+        assert!(self.is_tss(selector), "selector does not reference a TSS");
+        usize::from(selector.index())
+    }
+
+    const_fn! {
+        #[inline]
+        fn push(&mut self, value: u64) -> usize {
+            if self.next_free < self.table.len() {
+                let index = self.next_free;
+                self.table[index] = value;
+                self.next_free += 1;
+                index
+            } else {
+                panic!("GDT full");
+            }
         }
     }
 }
@@ -184,6 +333,16 @@ bitflags! {
         const PRESENT           = 1 << 47;
         /// Must be set for long mode code segments.
         const LONG_MODE         = 1 << 53;
+        /// Selects 32-bit operands and addresses for protected mode segments. Must be cleared
+        /// for long mode code segments.
+        const DEFAULT_SIZE      = 1 << 54;
+        /// Scales the limit of protected mode segments by 4096, so that a segment with the
+        /// maximum limit covers the whole 4GiB address space.
+        const GRANULARITY       = 1 << 55;
+        /// Bits 0..16 of the segment limit, which is ignored in 64-bit mode.
+        const LIMIT_0_15        = 0xffff;
+        /// Bits 16..20 of the segment limit, which is ignored in 64-bit mode.
+        const LIMIT_16_19       = 0xf << 48;
 
         /// The DPL for this descriptor is Ring 3
         const DPL_RING_3        = 3 << 45;
@@ -193,33 +352,48 @@ bitflags! {
 impl Descriptor {
     /// Creates a segment descriptor for a long mode kernel code segment.
     #[inline]
-    pub fn kernel_code_segment() -> Descriptor {
+    pub const fn kernel_code_segment() -> Descriptor {
         use self::DescriptorFlags as Flags;
 
-        let flags = Flags::USER_SEGMENT | Flags::PRESENT | Flags::EXECUTABLE | Flags::LONG_MODE;
-        Descriptor::UserSegment(flags.bits())
+        let flags = Flags::USER_SEGMENT.bits()
+            | Flags::PRESENT.bits()
+            | Flags::EXECUTABLE.bits()
+            | Flags::LONG_MODE.bits();
+        Descriptor::UserSegment(flags)
+    }
+
+    /// Creates a segment descriptor for a long mode kernel data segment.
+    #[inline]
+    pub const fn kernel_data_segment() -> Descriptor {
+        use self::DescriptorFlags as Flags;
+
+        let flags = Flags::USER_SEGMENT.bits() | Flags::PRESENT.bits() | Flags::WRITABLE.bits();
+        Descriptor::UserSegment(flags)
     }
 
     /// Creates a segment descriptor for a long mode ring 3 data segment.
     #[inline]
-    pub fn user_data_segment() -> Descriptor {
+    pub const fn user_data_segment() -> Descriptor {
         use self::DescriptorFlags as Flags;
 
-        let flags = Flags::USER_SEGMENT | Flags::PRESENT | Flags::WRITABLE | Flags::DPL_RING_3;
-        Descriptor::UserSegment(flags.bits())
+        let flags = Flags::USER_SEGMENT.bits()
+            | Flags::PRESENT.bits()
+            | Flags::WRITABLE.bits()
+            | Flags::DPL_RING_3.bits();
+        Descriptor::UserSegment(flags)
     }
 
     /// Creates a segment descriptor for a long mode ring 3 code segment.
     #[inline]
-    pub fn user_code_segment() -> Descriptor {
+    pub const fn user_code_segment() -> Descriptor {
         use self::DescriptorFlags as Flags;
 
-        let flags = Flags::USER_SEGMENT
-            | Flags::PRESENT
-            | Flags::EXECUTABLE
-            | Flags::LONG_MODE
-            | Flags::DPL_RING_3;
-        Descriptor::UserSegment(flags.bits())
+        let flags = Flags::USER_SEGMENT.bits()
+            | Flags::PRESENT.bits()
+            | Flags::EXECUTABLE.bits()
+            | Flags::LONG_MODE.bits()
+            | Flags::DPL_RING_3.bits();
+        Descriptor::UserSegment(flags)
     }
 
     /// Creates a TSS system descriptor for the given TSS.
@@ -245,3 +419,234 @@ impl Descriptor {
         Descriptor::SystemSegment(low, high)
     }
 }
+
+/// The segment selectors of the GDT created by [`CpuInit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selectors {
+    /// The kernel code segment selector.
+    pub kernel_code: SegmentSelector,
+    /// The kernel data segment selector.
+    pub kernel_data: SegmentSelector,
+    /// The user data segment selector (with RPL 3).
+    pub user_data: SegmentSelector,
+    /// The user code segment selector (with RPL 3).
+    pub user_code: SegmentSelector,
+    /// The TSS segment selector.
+    pub tss: SegmentSelector,
+}
+
+/// A builder for the GDT and TSS of a processor running a 64-bit kernel.
+///
+/// The created GDT contains a kernel code and data segment, a user data and code segment,
+/// and a TSS segment, in this order. This layout is compatible with the `syscall` and
+/// `sysret` instructions, i.e. the [`Star`](crate::registers::model_specific::Star) register
+/// can be initialized from the returned [`Selectors`].
+///
+/// # Example
+///
+/// ```ignore
+/// use x86_64::structures::gdt::{CpuInit, GlobalDescriptorTable};
+/// use x86_64::structures::tss::TaskStateSegment;
+///
+/// static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+/// static mut TSS: TaskStateSegment = TaskStateSegment::new();
+///
+/// let selectors = unsafe {
+///     CpuInit::new()
+///         .interrupt_stack(0, double_fault_stack_end)
+///         .privilege_stack(0, kernel_stack_end)
+///         .load(&mut GDT, &mut TSS)
+/// };
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInit {
+    tss: TaskStateSegment,
+}
+
+impl CpuInit {
+    /// Creates a new builder with an empty TSS.
+    #[inline]
+    pub const fn new() -> CpuInit {
+        CpuInit {
+            tss: TaskStateSegment::new(),
+        }
+    }
+
+    /// Sets the given interrupt stack table entry (0 to 6) to the given stack end address.
+    ///
+    /// The index corresponds to the index passed to `set_stack_index` in the options of an
+    /// IDT entry.
+    ///
+    /// Panics if the index is greater than 6.
+    #[inline]
+    pub fn interrupt_stack(mut self, index: usize, stack_end: VirtAddr) -> CpuInit {
+        let mut table = self.tss.interrupt_stack_table;
+        table[index] = stack_end;
+        self.tss.interrupt_stack_table = table;
+        self
+    }
+
+    /// Sets the stack that is loaded on a switch to the given privilege level (0 to 2) to
+    /// the given stack end address.
+    ///
+    /// Panics if the privilege level is greater than 2.
+    #[inline]
+    pub fn privilege_stack(mut self, privilege_level: usize, stack_end: VirtAddr) -> CpuInit {
+        let mut table = self.tss.privilege_stack_table;
+        table[privilege_level] = stack_end;
+        self.tss.privilege_stack_table = table;
+        self
+    }
+
+    /// Writes the GDT and the TSS to the given locations, loads them, and reloads the `cs`,
+    /// `ss`, `ds`, and `es` segment registers and the task register.
+    ///
+    /// Any previous content of `gdt` and `tss` is overwritten.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that no code relies on the
+    /// previous GDT or segment selectors and that the configured stacks are valid.
+    #[cfg(target_arch = "x86_64")]
+    pub unsafe fn load(
+        self,
+        gdt: &'static mut GlobalDescriptorTable,
+        tss: &'static mut TaskStateSegment,
+    ) -> Selectors {
+        use crate::instructions::segmentation::load_kernel_segments;
+
+        *tss = self.tss;
+        let tss: &'static TaskStateSegment = tss;
+
+        *gdt = GlobalDescriptorTable::new();
+        let selectors = Self::add_entries(gdt, tss);
+
+        let gdt: &'static GlobalDescriptorTable = gdt;
+        gdt.load();
+        load_kernel_segments(selectors.kernel_code, selectors.kernel_data, selectors.tss);
+        selectors
+    }
+
+    /// Adds the segments and the TSS descriptor to the empty `gdt`.
+    #[cfg(target_arch = "x86_64")]
+    fn add_entries(gdt: &mut GlobalDescriptorTable, tss: &'static TaskStateSegment) -> Selectors {
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let mut user_data = gdt.add_entry(Descriptor::user_data_segment());
+        user_data.set_rpl(PrivilegeLevel::Ring3);
+        let mut user_code = gdt.add_entry(Descriptor::user_code_segment());
+        user_code.set_rpl(PrivilegeLevel::Ring3);
+        let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+
+        Selectors {
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss,
+        }
+    }
+}
+
+impl Default for CpuInit {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selector_format() {
+        let selector = SegmentSelector::new(2, PrivilegeLevel::Ring3);
+        assert_eq!(format!("{}", selector), "0x0013 (GDT index 2, Ring3)");
+        assert_eq!(
+            format!("{:?}", selector),
+            "SegmentSelector { index: 2, table: GDT, rpl: Ring3 }"
+        );
+        assert!(SegmentSelector(0x0f).is_ldt());
+    }
+
+    #[test]
+    fn accessed_code_segment_is_not_tss() {
+        let mut gdt = GlobalDescriptorTable::new();
+        let code = Descriptor::kernel_code_segment();
+        let accessed = match code {
+            Descriptor::UserSegment(value) => Descriptor::UserSegment(value | 1 << 40),
+            Descriptor::SystemSegment(..) => unreachable!(),
+        };
+        let selector = gdt.add_entry(accessed);
+        gdt.add_entry(Descriptor::kernel_data_segment());
+        assert!(!gdt.is_tss(selector));
+    }
+
+    #[test]
+    fn pointer() {
+        let gdt = GlobalDescriptorTable::new();
+        let pointer = gdt.pointer();
+        // the limit is the offset of the last byte of the table
+        let (base, limit) = (pointer.base, pointer.limit);
+        assert_eq!(base, gdt.table.as_ptr() as u64);
+        assert_eq!(limit, 8 * 8 - 1);
+        assert_eq!(core::mem::size_of::<DescriptorTablePointer>(), 10);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn cpu_init_entries() {
+        static TSS: TaskStateSegment = TaskStateSegment::new();
+
+        let mut gdt = GlobalDescriptorTable::new();
+        let selectors = CpuInit::add_entries(&mut gdt, &TSS);
+        assert_eq!(selectors.kernel_code.0, 0x08);
+        assert_eq!(selectors.kernel_data.0, 0x10);
+        assert_eq!(selectors.user_data.0, 0x1b);
+        assert_eq!(selectors.user_code.0, 0x23);
+        assert_eq!(selectors.tss.0, 0x28);
+        // `sysret` loads SS from the selector 8 bytes before the user code segment
+        assert_eq!(selectors.user_data.index() + 1, selectors.user_code.index());
+
+        assert_eq!(gdt.table[2], 0x0000_9200_0000_0000);
+        assert_eq!(gdt.table[4] >> 40 & 0xff, 0xf8);
+        let tss_base = &TSS as *const _ as u64;
+        assert_eq!(gdt.table[5] >> 16 & 0xff_ffff, tss_base & 0xff_ffff);
+        assert_eq!(gdt.table[6], tss_base >> 32);
+        assert_eq!(gdt.next_free, 7);
+    }
+
+    #[test]
+    fn cpu_init_stacks() {
+        let init = CpuInit::new()
+            .interrupt_stack(6, VirtAddr::new(0x1000))
+            .privilege_stack(0, VirtAddr::new(0x2000));
+        let (interrupt, privilege) = (
+            init.tss.interrupt_stack_table,
+            init.tss.privilege_stack_table,
+        );
+        assert_eq!(interrupt[6], VirtAddr::new(0x1000));
+        assert_eq!(privilege[0], VirtAddr::new(0x2000));
+        assert_eq!(interrupt[0], VirtAddr::new(0));
+    }
+
+    #[cfg(feature = "const_fn")]
+    #[test]
+    fn const_gdt() {
+        static GDT: GlobalDescriptorTable = {
+            let mut gdt = GlobalDescriptorTable::new();
+            gdt.add_entry(Descriptor::kernel_code_segment());
+            gdt.add_entry(Descriptor::kernel_data_segment());
+            gdt
+        };
+        assert_eq!(GDT.next_free, 3);
+        assert_eq!(
+            GDT.table[1],
+            DescriptorFlags::USER_SEGMENT.bits()
+                | DescriptorFlags::PRESENT.bits()
+                | DescriptorFlags::EXECUTABLE.bits()
+                | DescriptorFlags::LONG_MODE.bits()
+        );
+    }
+}
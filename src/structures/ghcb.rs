@@ -0,0 +1,254 @@
+//! The guest-host communication block (GHCB) of SEV-ES guests.
+//!
+//! The register state of an SEV-ES guest is encrypted, so the guest copies the registers that
+//! the hypervisor needs to handle a request, e.g. the emulation of `cpuid` in a `#VC` handler,
+//! into a shared GHCB page and exits with [`vmgexit`](crate::instructions::svm::vmgexit). The
+//! hypervisor only reads registers whose bit in the valid bitmap is set. See the "SEV-ES
+//! Guest-Hypervisor Communication Block Standardization" specification of AMD.
+
+use core::fmt;
+
+/// A 64-bit field of the save area of the GHCB, identified by its offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum GhcbField {
+    /// The XSS register.
+    Xss = 0x140,
+    /// The DR7 register.
+    Dr7 = 0x160,
+    /// The RIP register.
+    Rip = 0x178,
+    /// The RSP register.
+    Rsp = 0x1d8,
+    /// The RAX register.
+    Rax = 0x1f8,
+    /// The RCX register.
+    Rcx = 0x308,
+    /// The RDX register.
+    Rdx = 0x310,
+    /// The RBX register.
+    Rbx = 0x318,
+    /// The RBP register.
+    Rbp = 0x328,
+    /// The RSI register.
+    Rsi = 0x330,
+    /// The RDI register.
+    Rdi = 0x338,
+    /// The R8 register.
+    R8 = 0x340,
+    /// The R9 register.
+    R9 = 0x348,
+    /// The R10 register.
+    R10 = 0x350,
+    /// The R11 register.
+    R11 = 0x358,
+    /// The R12 register.
+    R12 = 0x360,
+    /// The R13 register.
+    R13 = 0x368,
+    /// The R14 register.
+    R14 = 0x370,
+    /// The R15 register.
+    R15 = 0x378,
+    /// The exit code of the request, e.g. 0x72 for `cpuid` or 0x7b for I/O.
+    SwExitCode = 0x390,
+    /// The first exit information of the request, and the result on return.
+    SwExitInfo1 = 0x398,
+    /// The second exit information of the request, and the result on return.
+    SwExitInfo2 = 0x3a0,
+    /// The guest-physical address of a scratch buffer, e.g. in the shared buffer of the GHCB.
+    SwScratch = 0x3a8,
+    /// The XCR0 register.
+    Xcr0 = 0x3e8,
+}
+
+impl GhcbField {
+    /// Returns the offset of the field in the GHCB.
+    #[inline]
+    pub const fn offset(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the index of the field in the save area and the valid bitmap.
+    #[inline]
+    const fn index(self) -> usize {
+        self.offset() / 8
+    }
+}
+
+/// The offset of the CPL in the GHCB.
+const CPL_OFFSET: usize = 0xcb;
+
+/// The index of the first quadword of the valid bitmap in the save area.
+const VALID_BITMAP_INDEX: usize = 0x3f0 / 8;
+
+/// The size of the shared buffer of the GHCB.
+pub const SHARED_BUFFER_SIZE: usize = 2032;
+
+/// The guest-host communication block, a 4KiB page that is shared with the hypervisor, i.e.
+/// mapped with the C-bit clear.
+///
+/// The protocol version and usage must be set before the first request.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct Ghcb {
+    save: [u64; 0x100],
+    shared_buffer: [u8; SHARED_BUFFER_SIZE],
+    reserved: [u8; 10],
+    /// The negotiated version of the GHCB protocol.
+    pub protocol_version: u16,
+    /// The usage of the GHCB, 0 for the standard format.
+    pub usage: u32,
+}
+
+impl Ghcb {
+    /// Creates a GHCB with all fields cleared.
+    #[inline]
+    pub const fn new() -> Self {
+        Ghcb {
+            save: [0; 0x100],
+            shared_buffer: [0; SHARED_BUFFER_SIZE],
+            reserved: [0; 10],
+            protocol_version: 0,
+            usage: 0,
+        }
+    }
+
+    /// Returns the value of the given field, or `None` if it isn't marked as valid.
+    #[inline]
+    pub fn get(&self, field: GhcbField) -> Option<u64> {
+        if self.is_valid(field.index()) {
+            Some(self.save[field.index()])
+        } else {
+            None
+        }
+    }
+
+    /// Sets the value of the given field and marks it as valid.
+    #[inline]
+    pub fn set(&mut self, field: GhcbField, value: u64) {
+        self.save[field.index()] = value;
+        self.set_valid(field.index());
+    }
+
+    /// Returns the CPL, or `None` if it isn't marked as valid.
+    #[inline]
+    pub fn cpl(&self) -> Option<u8> {
+        let index = CPL_OFFSET / 8;
+        if self.is_valid(index) {
+            Some((self.save[index] >> ((CPL_OFFSET % 8) * 8)) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the CPL and marks it as valid.
+    #[inline]
+    pub fn set_cpl(&mut self, cpl: u8) {
+        let index = CPL_OFFSET / 8;
+        let shift = (CPL_OFFSET % 8) * 8;
+        self.save[index] = self.save[index] & !(0xff << shift) | u64::from(cpl) << shift;
+        self.set_valid(index);
+    }
+
+    /// Marks all fields as invalid, which should be done before each request.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.save[VALID_BITMAP_INDEX] = 0;
+        self.save[VALID_BITMAP_INDEX + 1] = 0;
+    }
+
+    /// Prepares a request with the given exit code and exit information, invalidating all
+    /// other fields.
+    #[inline]
+    pub fn set_request(&mut self, exit_code: u64, exit_info_1: u64, exit_info_2: u64) {
+        self.invalidate();
+        self.set(GhcbField::SwExitCode, exit_code);
+        self.set(GhcbField::SwExitInfo1, exit_info_1);
+        self.set(GhcbField::SwExitInfo2, exit_info_2);
+    }
+
+    /// Returns the shared buffer, which holds the data of requests like string I/O.
+    #[inline]
+    pub fn shared_buffer(&self) -> &[u8; SHARED_BUFFER_SIZE] {
+        &self.shared_buffer
+    }
+
+    /// Returns the shared buffer mutably.
+    #[inline]
+    pub fn shared_buffer_mut(&mut self) -> &mut [u8; SHARED_BUFFER_SIZE] {
+        &mut self.shared_buffer
+    }
+
+    /// Returns the offset of the shared buffer in the GHCB, to compute its guest-physical
+    /// address for [`SwScratch`](GhcbField::SwScratch).
+    #[inline]
+    pub const fn shared_buffer_offset() -> usize {
+        0x800
+    }
+
+    #[inline]
+    fn is_valid(&self, index: usize) -> bool {
+        self.save[VALID_BITMAP_INDEX + index / 64] & (1 << (index % 64)) != 0
+    }
+
+    #[inline]
+    fn set_valid(&mut self, index: usize) {
+        self.save[VALID_BITMAP_INDEX + index / 64] |= 1 << (index % 64);
+    }
+}
+
+impl Default for Ghcb {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Ghcb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ghcb")
+            .field("sw_exit_code", &self.get(GhcbField::SwExitCode))
+            .field("sw_exit_info_1", &self.get(GhcbField::SwExitInfo1))
+            .field("sw_exit_info_2", &self.get(GhcbField::SwExitInfo2))
+            .field("protocol_version", &self.protocol_version)
+            .field("usage", &self.usage)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::size_of;
+
+    #[test]
+    fn ghcb_layout() {
+        assert_eq!(size_of::<Ghcb>(), 4096);
+        let ghcb = Ghcb::new();
+        let base = &ghcb as *const Ghcb as usize;
+        assert_eq!(
+            ghcb.shared_buffer.as_ptr() as usize - base,
+            Ghcb::shared_buffer_offset()
+        );
+        assert_eq!(&ghcb.protocol_version as *const _ as usize - base, 0xffa);
+    }
+
+    #[test]
+    fn valid_bitmap() {
+        let mut ghcb = Ghcb::new();
+        assert_eq!(ghcb.get(GhcbField::Rax), None);
+        ghcb.set(GhcbField::Rax, 0x8000_0001);
+        ghcb.set_cpl(3);
+        assert_eq!(ghcb.get(GhcbField::Rax), Some(0x8000_0001));
+        assert_eq!(ghcb.cpl(), Some(3));
+        // RAX is quadword 0x3f, CPL is in quadword 0x19
+        assert_eq!(ghcb.save[VALID_BITMAP_INDEX], 1 << 0x3f | 1 << 0x19);
+
+        ghcb.set_request(0x72, 0, 0);
+        assert_eq!(ghcb.get(GhcbField::Rax), None);
+        assert_eq!(ghcb.get(GhcbField::SwExitCode), Some(0x72));
+        // SW_EXITCODE is quadword 0x72, in the second quadword of the bitmap
+        assert_eq!(ghcb.save[VALID_BITMAP_INDEX + 1], 0b111 << (0x72 - 64));
+    }
+}
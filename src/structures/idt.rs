@@ -9,13 +9,22 @@
 
 //! Provides types for the Interrupt Descriptor Table and its entries.
 
+use crate::registers::rflags::RFlags;
+use crate::structures::gdt::SegmentSelector;
+use crate::structures::DescriptorTablePointer;
 use crate::{PrivilegeLevel, VirtAddr};
 use bit_field::BitField;
 use bitflags::bitflags;
+use core::convert::TryFrom;
 use core::fmt;
 use core::marker::PhantomData;
 use core::ops::Bound::{Excluded, Included, Unbounded};
-use core::ops::{Deref, Index, IndexMut, RangeBounds};
+use core::ops::{
+    Deref, Index, IndexMut, Range, RangeBounds, RangeFrom, RangeInclusive, RangeTo,
+    RangeToInclusive,
+};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 /// An Interrupt Descriptor Table with 256 entries.
 ///
@@ -370,37 +379,35 @@ pub struct InterruptDescriptorTable {
 }
 
 impl InterruptDescriptorTable {
-    const_fn! {
-        /// Creates a new IDT filled with non-present entries.
-        #[inline]
-        pub fn new() -> InterruptDescriptorTable {
-            InterruptDescriptorTable {
-                divide_error: Entry::missing(),
-                debug: Entry::missing(),
-                non_maskable_interrupt: Entry::missing(),
-                breakpoint: Entry::missing(),
-                overflow: Entry::missing(),
-                bound_range_exceeded: Entry::missing(),
-                invalid_opcode: Entry::missing(),
-                device_not_available: Entry::missing(),
-                double_fault: Entry::missing(),
-                coprocessor_segment_overrun: Entry::missing(),
-                invalid_tss: Entry::missing(),
-                segment_not_present: Entry::missing(),
-                stack_segment_fault: Entry::missing(),
-                general_protection_fault: Entry::missing(),
-                page_fault: Entry::missing(),
-                reserved_1: Entry::missing(),
-                x87_floating_point: Entry::missing(),
-                alignment_check: Entry::missing(),
-                machine_check: Entry::missing(),
-                simd_floating_point: Entry::missing(),
-                virtualization: Entry::missing(),
-                reserved_2: [Entry::missing(); 9],
-                security_exception: Entry::missing(),
-                reserved_3: Entry::missing(),
-                interrupts: [Entry::missing(); 256 - 32],
-            }
+    /// Creates a new IDT filled with non-present entries.
+    #[inline]
+    pub const fn new() -> InterruptDescriptorTable {
+        InterruptDescriptorTable {
+            divide_error: Entry::missing(),
+            debug: Entry::missing(),
+            non_maskable_interrupt: Entry::missing(),
+            breakpoint: Entry::missing(),
+            overflow: Entry::missing(),
+            bound_range_exceeded: Entry::missing(),
+            invalid_opcode: Entry::missing(),
+            device_not_available: Entry::missing(),
+            double_fault: Entry::missing(),
+            coprocessor_segment_overrun: Entry::missing(),
+            invalid_tss: Entry::missing(),
+            segment_not_present: Entry::missing(),
+            stack_segment_fault: Entry::missing(),
+            general_protection_fault: Entry::missing(),
+            page_fault: Entry::missing(),
+            reserved_1: Entry::missing(),
+            x87_floating_point: Entry::missing(),
+            alignment_check: Entry::missing(),
+            machine_check: Entry::missing(),
+            simd_floating_point: Entry::missing(),
+            virtualization: Entry::missing(),
+            reserved_2: [Entry::missing(); 9],
+            security_exception: Entry::missing(),
+            reserved_3: Entry::missing(),
+            interrupts: [Entry::missing(); 256 - 32],
         }
     }
 
@@ -411,6 +418,9 @@ impl InterruptDescriptorTable {
     }
 
     /// Loads the IDT in the CPU using the `lidt` command.
+    ///
+    /// The `'static` bound ensures that the IDT outlives its use by the CPU. Use
+    /// [`load_unsafe`](InterruptDescriptorTable::load_unsafe) for IDTs with a shorter lifetime.
     #[cfg(target_arch = "x86_64")]
     #[inline]
     pub fn load(&'static self) {
@@ -430,15 +440,22 @@ impl InterruptDescriptorTable {
     #[cfg(target_arch = "x86_64")]
     #[inline]
     pub unsafe fn load_unsafe(&self) {
-        use crate::instructions::tables::{lidt, DescriptorTablePointer};
+        use crate::instructions::tables::lidt;
+
+        lidt(&self.pointer());
+    }
+
+    /// Returns a pointer to this IDT in the format expected by the `lidt` instruction.
+    ///
+    /// The pointer is only valid as long as `self` is neither destroyed nor moved.
+    #[inline]
+    pub fn pointer(&self) -> DescriptorTablePointer {
         use core::mem::size_of;
 
-        let ptr = DescriptorTablePointer {
+        DescriptorTablePointer {
             base: self as *const _ as u64,
             limit: (size_of::<Self>() - 1) as u16,
-        };
-
-        lidt(&ptr);
+        }
     }
 
     /// Returns a normalized and ranged check slice range from a RangeBounds trait object
@@ -460,6 +477,12 @@ impl InterruptDescriptorTable {
         if lower_idx > 256 || upper_idx > 256 {
             panic!("Index out of range [{}..{}]", lower_idx, upper_idx);
         }
+        if lower_idx > upper_idx {
+            panic!(
+                "Slice index starts at {} but ends at {}",
+                lower_idx, upper_idx
+            );
+        }
         if lower_idx < 32 {
             panic!("Cannot return slice from traps, faults, and exception handlers");
         }
@@ -485,6 +508,26 @@ impl InterruptDescriptorTable {
         let (lower_idx, upper_idx) = self.condition_slice_bounds(bounds);
         &mut self.interrupts[(lower_idx - 32)..(upper_idx - 32)]
     }
+
+    /// Returns the IDT entry with the specified index, or `None` if the index is outside the
+    /// IDT or the entry can't be accessed through indexing (e.g. exceptions with error code).
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&Entry<HandlerFunc>> {
+        match index {
+            0..=7 | 9 | 16 | 19 | 20 | 32..=255 => Some(&self[index]),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the IDT entry with the specified index, or `None` if the
+    /// index is outside the IDT or the entry can't be accessed through indexing.
+    #[inline]
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Entry<HandlerFunc>> {
+        match index {
+            0..=7 | 9 | 16 | 19 | 20 | 32..=255 => Some(&mut self[index]),
+            _ => None,
+        }
+    }
 }
 
 impl Index<usize> for InterruptDescriptorTable {
@@ -551,6 +594,38 @@ impl IndexMut<usize> for InterruptDescriptorTable {
     }
 }
 
+macro_rules! impl_index_for_idt {
+    ($ty:ty) => {
+        impl Index<$ty> for InterruptDescriptorTable {
+            type Output = [Entry<HandlerFunc>];
+
+            /// Returns the IDT entries in the specified range, see
+            /// [`slice`](InterruptDescriptorTable::slice).
+            #[inline]
+            fn index(&self, index: $ty) -> &Self::Output {
+                self.slice(index)
+            }
+        }
+
+        impl IndexMut<$ty> for InterruptDescriptorTable {
+            /// Returns the IDT entries in the specified range, see
+            /// [`slice_mut`](InterruptDescriptorTable::slice_mut).
+            #[inline]
+            fn index_mut(&mut self, index: $ty) -> &mut Self::Output {
+                self.slice_mut(index)
+            }
+        }
+    };
+}
+
+// this cannot be a blanket impl for `RangeBounds<usize>` because it would conflict with
+// `Index<usize>`
+impl_index_for_idt!(Range<usize>);
+impl_index_for_idt!(RangeFrom<usize>);
+impl_index_for_idt!(RangeInclusive<usize>);
+impl_index_for_idt!(RangeTo<usize>);
+impl_index_for_idt!(RangeToInclusive<usize>);
+
 /// An Interrupt Descriptor Table entry.
 ///
 /// The generic parameter can either be `HandlerFunc` or `HandlerFuncWithErrCode`, depending
@@ -568,18 +643,57 @@ pub struct Entry<F> {
 }
 
 /// A handler function for an interrupt or an exception without error code.
+///
+/// This type alias is only usable with the `abi_x86_interrupt` feature enabled.
+#[cfg(feature = "abi_x86_interrupt")]
 pub type HandlerFunc = extern "x86-interrupt" fn(&mut InterruptStackFrame);
+/// This type is not usable without the `abi_x86_interrupt` feature.
+#[cfg(not(feature = "abi_x86_interrupt"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HandlerFunc(());
+
 /// A handler function for an exception that pushes an error code.
+///
+/// This type alias is only usable with the `abi_x86_interrupt` feature enabled.
+#[cfg(feature = "abi_x86_interrupt")]
 pub type HandlerFuncWithErrCode =
     extern "x86-interrupt" fn(&mut InterruptStackFrame, error_code: u64);
+/// This type is not usable without the `abi_x86_interrupt` feature.
+#[cfg(not(feature = "abi_x86_interrupt"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HandlerFuncWithErrCode(());
+
 /// A page fault handler function that pushes a page fault error code.
+///
+/// This type alias is only usable with the `abi_x86_interrupt` feature enabled.
+#[cfg(feature = "abi_x86_interrupt")]
 pub type PageFaultHandlerFunc =
     extern "x86-interrupt" fn(&mut InterruptStackFrame, error_code: PageFaultErrorCode);
+/// This type is not usable without the `abi_x86_interrupt` feature.
+#[cfg(not(feature = "abi_x86_interrupt"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PageFaultHandlerFunc(());
+
 /// A handler function that must not return, e.g. for a machine check exception.
+///
+/// This type alias is only usable with the `abi_x86_interrupt` feature enabled.
+#[cfg(feature = "abi_x86_interrupt")]
 pub type DivergingHandlerFunc = extern "x86-interrupt" fn(&mut InterruptStackFrame) -> !;
+/// This type is not usable without the `abi_x86_interrupt` feature.
+#[cfg(not(feature = "abi_x86_interrupt"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DivergingHandlerFunc(());
+
 /// A handler function with an error code that must not return, e.g. for a double fault exception.
+///
+/// This type alias is only usable with the `abi_x86_interrupt` feature enabled.
+#[cfg(feature = "abi_x86_interrupt")]
 pub type DivergingHandlerFuncWithErrCode =
     extern "x86-interrupt" fn(&mut InterruptStackFrame, error_code: u64) -> !;
+/// This type is not usable without the `abi_x86_interrupt` feature.
+#[cfg(not(feature = "abi_x86_interrupt"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DivergingHandlerFuncWithErrCode(());
 
 impl<F> Entry<F> {
     /// Creates a non-present IDT entry (but sets the must-be-one bits).
@@ -603,11 +717,20 @@ impl<F> Entry<F> {
     ///
     /// The function returns a mutable reference to the entry's options that allows
     /// further customization.
+    ///
+    /// This function can be used to register handlers that are not expressible as a
+    /// [`HandlerFuncType`], e.g. naked assembly stubs.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must ensure that `addr` is the address of a valid interrupt handler function,
+    /// and the signature of such a function is correct for the entry type.
     #[cfg(target_arch = "x86_64")]
     #[inline]
-    fn set_handler_addr(&mut self, addr: u64) -> &mut EntryOptions {
+    pub unsafe fn set_handler_addr(&mut self, addr: VirtAddr) -> &mut EntryOptions {
         use crate::instructions::segmentation;
 
+        let addr = addr.as_u64();
         self.pointer_low = addr as u16;
         self.pointer_middle = (addr >> 16) as u16;
         self.pointer_high = (addr >> 32) as u32;
@@ -617,32 +740,372 @@ impl<F> Entry<F> {
         self.options.set_present(true);
         &mut self.options
     }
+
+    /// Returns the virtual address of this IDT entry's handler function.
+    #[inline]
+    pub fn handler_addr(&self) -> VirtAddr {
+        let addr = u64::from(self.pointer_low)
+            | (u64::from(self.pointer_middle) << 16)
+            | (u64::from(self.pointer_high) << 32);
+        VirtAddr::new_truncate(addr)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl<F: HandlerFuncType> Entry<F> {
+    /// Set the handler function for the IDT entry and sets the present bit.
+    ///
+    /// For the code selector field, this function uses the code segment selector currently
+    /// active in the CPU.
+    ///
+    /// The function returns a mutable reference to the entry's options that allows
+    /// further customization.
+    #[inline]
+    pub fn set_handler_fn(&mut self, handler: F) -> &mut EntryOptions {
+        unsafe { self.set_handler_addr(handler.to_virt_addr()) }
+    }
+}
+
+/// A common trait for all handler functions usable in [`Entry`].
+///
+/// ## Safety
+///
+/// Implementors have to ensure that `to_virt_addr` returns a valid address of a function
+/// that can be called by the CPU as handler for the corresponding entry type.
+pub unsafe trait HandlerFuncType {
+    /// Get the virtual address of the handler function.
+    fn to_virt_addr(self) -> VirtAddr;
 }
 
-macro_rules! impl_set_handler_fn {
-    ($h:ty) => {
-        #[cfg(target_arch = "x86_64")]
-        impl Entry<$h> {
-            /// Set the handler function for the IDT entry and sets the present bit.
-            ///
-            /// For the code selector field, this function uses the code segment selector currently
-            /// active in the CPU.
-            ///
-            /// The function returns a mutable reference to the entry's options that allows
-            /// further customization.
+macro_rules! impl_handler_func_type {
+    ($f:ty) => {
+        #[cfg(feature = "abi_x86_interrupt")]
+        unsafe impl HandlerFuncType for $f {
             #[inline]
-            pub fn set_handler_fn(&mut self, handler: $h) -> &mut EntryOptions {
-                self.set_handler_addr(handler as u64)
+            fn to_virt_addr(self) -> VirtAddr {
+                VirtAddr::new(self as usize as u64)
             }
         }
     };
 }
 
-impl_set_handler_fn!(HandlerFunc);
-impl_set_handler_fn!(HandlerFuncWithErrCode);
-impl_set_handler_fn!(PageFaultHandlerFunc);
-impl_set_handler_fn!(DivergingHandlerFunc);
-impl_set_handler_fn!(DivergingHandlerFuncWithErrCode);
+impl_handler_func_type!(HandlerFunc);
+impl_handler_func_type!(HandlerFuncWithErrCode);
+impl_handler_func_type!(PageFaultHandlerFunc);
+impl_handler_func_type!(DivergingHandlerFunc);
+impl_handler_func_type!(DivergingHandlerFuncWithErrCode);
+
+/// A slot for the context of an interrupt handler generated by the
+/// [`context_handler!`](crate::context_handler!) macro.
+///
+/// Interrupt handlers can't take additional arguments, so the context that should be passed to
+/// the handler is stored in a static `HandlerContext` instead. The context can be replaced at
+/// any time, e.g. when a driver is initialized or removed.
+#[derive(Debug)]
+pub struct HandlerContext<C> {
+    context: AtomicPtr<C>,
+}
+
+impl<C> HandlerContext<C> {
+    /// Creates an empty context slot.
+    #[inline]
+    pub const fn new() -> Self {
+        HandlerContext {
+            context: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+impl<C> Default for HandlerContext<C> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Sync> HandlerContext<C> {
+    /// Sets the context that is passed to the handler.
+    #[inline]
+    pub fn set(&self, context: &'static C) {
+        self.context
+            .store(context as *const C as *mut C, Ordering::Release);
+    }
+
+    /// Removes the context, so that the handler returns without calling its target.
+    #[inline]
+    pub fn clear(&self) {
+        self.context.store(ptr::null_mut(), Ordering::Release);
+    }
+
+    /// Returns the current context, or `None` if no context is set.
+    #[inline]
+    pub fn get(&self) -> Option<&'static C> {
+        unsafe { self.context.load(Ordering::Acquire).as_ref() }
+    }
+}
+
+/// Generates an interrupt handler that passes the context stored in a [`HandlerContext`] to the
+/// given target function.
+///
+/// The generated handler can be registered through `set_handler_fn` like any other handler. If
+/// no context is set when the interrupt occurs, the handler returns immediately.
+///
+/// For handlers without error code, the target is called with the context and the interrupt
+/// stack frame. For handlers with error code (`fn name(error_code) ...`), the error code is
+/// passed as additional third argument.
+///
+/// ## Example
+///
+/// ```ignore
+/// use x86_64::context_handler;
+/// use x86_64::structures::idt::{HandlerContext, InterruptStackFrame};
+///
+/// static SERIAL_CONTEXT: HandlerContext<SerialPort> = HandlerContext::new();
+///
+/// fn serial_interrupt(port: &SerialPort, _frame: &mut InterruptStackFrame) {
+///     port.receive();
+/// }
+///
+/// context_handler!(fn serial_handler = SERIAL_CONTEXT => serial_interrupt);
+///
+/// SERIAL_CONTEXT.set(&SERIAL_PORT);
+/// idt[36].set_handler_fn(serial_handler);
+/// ```
+#[macro_export]
+macro_rules! context_handler {
+    ($vis:vis fn $name:ident = $slot:path => $target:expr) => {
+        $vis extern "x86-interrupt" fn $name(
+            stack_frame: &mut $crate::structures::idt::InterruptStackFrame,
+        ) {
+            if let Some(context) = $slot.get() {
+                ($target)(context, stack_frame);
+            }
+        }
+    };
+    ($vis:vis fn $name:ident(error_code) = $slot:path => $target:expr) => {
+        $vis extern "x86-interrupt" fn $name(
+            stack_frame: &mut $crate::structures::idt::InterruptStackFrame,
+            error_code: u64,
+        ) {
+            if let Some(context) = $slot.get() {
+                ($target)(context, stack_frame, error_code);
+            }
+        }
+    };
+}
+
+/// The general purpose registers saved by a handler generated with the
+/// [`full_context_handler!`](crate::full_context_handler!) macro.
+///
+/// The registers are stored in the reverse order of the push instructions of the handler.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+#[repr(C)]
+pub struct GeneralPurposeRegisters {
+    #[allow(missing_docs)]
+    pub r15: u64,
+    #[allow(missing_docs)]
+    pub r14: u64,
+    #[allow(missing_docs)]
+    pub r13: u64,
+    #[allow(missing_docs)]
+    pub r12: u64,
+    #[allow(missing_docs)]
+    pub r11: u64,
+    #[allow(missing_docs)]
+    pub r10: u64,
+    #[allow(missing_docs)]
+    pub r9: u64,
+    #[allow(missing_docs)]
+    pub r8: u64,
+    #[allow(missing_docs)]
+    pub rbp: u64,
+    #[allow(missing_docs)]
+    pub rdi: u64,
+    #[allow(missing_docs)]
+    pub rsi: u64,
+    #[allow(missing_docs)]
+    pub rdx: u64,
+    #[allow(missing_docs)]
+    pub rcx: u64,
+    #[allow(missing_docs)]
+    pub rbx: u64,
+    #[allow(missing_docs)]
+    pub rax: u64,
+}
+
+/// Overwrites all saved registers with zeros, e.g. before a saved thread context is freed.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for GeneralPurposeRegisters {
+    fn zeroize(&mut self) {
+        let GeneralPurposeRegisters {
+            r15,
+            r14,
+            r13,
+            r12,
+            r11,
+            r10,
+            r9,
+            r8,
+            rbp,
+            rdi,
+            rsi,
+            rdx,
+            rcx,
+            rbx,
+            rax,
+        } = self;
+        for register in [
+            r15, r14, r13, r12, r11, r10, r9, r8, rbp, rdi, rsi, rdx, rcx, rbx, rax,
+        ] {
+            register.zeroize();
+        }
+    }
+}
+
+/// The complete register state of the interrupted code, passed to handlers generated with the
+/// [`full_context_handler!`](crate::full_context_handler!) macro.
+///
+/// All values are restored when the handler returns, so modifying them changes the state of
+/// the interrupted code, e.g. for switching to another thread.
+#[derive(Debug)]
+#[repr(C)]
+pub struct FullInterruptContext {
+    /// The general purpose registers of the interrupted code.
+    pub registers: GeneralPurposeRegisters,
+    /// The error code pushed by the CPU, or zero for interrupts without error code.
+    pub error_code: u64,
+    /// The interrupt stack frame pushed by the CPU.
+    pub stack_frame: InterruptStackFrame,
+}
+
+/// Generates an interrupt handler stub that saves all general purpose registers and passes
+/// them to a Rust function.
+///
+/// The macro generates a function `fn $name() -> VirtAddr` that returns the address of the
+/// stub, which must be registered through [`Entry::set_handler_addr`]. The stub pushes all
+/// general purpose registers, calls the target `fn(&mut FullInterruptContext)`, restores all
+/// registers from the (possibly modified) context, and returns using `iretq`.
+///
+/// The following variants are supported:
+///
+/// - `fn name => target`: for interrupts and exceptions without an error code.
+/// - `fn name(error_code) => target`: for exceptions that push an error code.
+/// - `fn name [swapgs] => target` and `fn name(error_code) [swapgs] => target`: executes
+///   `swapgs` on entry and exit if the interrupt occurred in user mode (i.e. the RPL of the
+///   saved code segment is not 0), so that the target runs with the kernel GS base.
+///
+/// The stub is emitted as a global assembly symbol whose name is derived from `$name`, so the
+/// names of the generated functions must be unique within the final binary.
+///
+/// Only the general purpose registers are saved, not the x87, SSE and AVX state of the
+/// interrupted code. So the target and all code it calls must not use these registers, i.e.
+/// the kernel must be built without SSE (like the `x86_64-unknown-none` target, which uses
+/// soft-float), or the target must save and restore the extended state itself, e.g. with
+/// `fxsave` and `fxrstor`. This also means that switching threads through the context
+/// doesn't switch their extended state.
+///
+/// ## Example
+///
+/// ```ignore
+/// use x86_64::full_context_handler;
+/// use x86_64::structures::idt::FullInterruptContext;
+///
+/// fn timer(context: &mut FullInterruptContext) {
+///     scheduler::switch(&mut context.registers);
+/// }
+///
+/// full_context_handler!(fn timer_stub [swapgs] => timer);
+///
+/// unsafe { idt[32].set_handler_addr(timer_stub()) };
+/// ```
+#[macro_export]
+macro_rules! full_context_handler {
+    ($vis:vis fn $name:ident => $target:expr) => {
+        $crate::full_context_handler!(@stub $vis $name, $target, "pushq $0", "");
+    };
+    ($vis:vis fn $name:ident(error_code) => $target:expr) => {
+        $crate::full_context_handler!(@stub $vis $name, $target, "", "");
+    };
+    ($vis:vis fn $name:ident [swapgs] => $target:expr) => {
+        $crate::full_context_handler!(
+            @stub $vis $name, $target, "pushq $0",
+            "testb $3, 16(%rsp)\njz 1f\nswapgs\n1:"
+        );
+    };
+    ($vis:vis fn $name:ident(error_code) [swapgs] => $target:expr) => {
+        $crate::full_context_handler!(
+            @stub $vis $name, $target, "",
+            "testb $3, 16(%rsp)\njz 1f\nswapgs\n1:"
+        );
+    };
+    (@stub $vis:vis $name:ident, $target:expr, $push_error_code:literal, $swapgs:literal) => {
+        $vis fn $name() -> $crate::VirtAddr {
+            extern "C" fn target(context: &mut $crate::structures::idt::FullInterruptContext) {
+                ($target)(context)
+            }
+
+            ::core::arch::global_asm!(
+                concat!(
+                    ".global __x86_64_full_context_", stringify!($name), "\n",
+                    ".p2align 4\n",
+                    "__x86_64_full_context_", stringify!($name), ":\n",
+                    $push_error_code, "\n",
+                    $swapgs, "\n",
+                    "pushq %rax\n",
+                    "pushq %rbx\n",
+                    "pushq %rcx\n",
+                    "pushq %rdx\n",
+                    "pushq %rsi\n",
+                    "pushq %rdi\n",
+                    "pushq %rbp\n",
+                    "pushq %r8\n",
+                    "pushq %r9\n",
+                    "pushq %r10\n",
+                    "pushq %r11\n",
+                    "pushq %r12\n",
+                    "pushq %r13\n",
+                    "pushq %r14\n",
+                    "pushq %r15\n",
+                    "movq %rsp, %rdi\n",
+                    // the CPU aligns the stack to 16 bytes before pushing the 5 qword stack
+                    // frame, so 21 qwords are pushed at this point
+                    "subq $8, %rsp\n",
+                    "cld\n",
+                    "call {target}\n",
+                    "addq $8, %rsp\n",
+                    "popq %r15\n",
+                    "popq %r14\n",
+                    "popq %r13\n",
+                    "popq %r12\n",
+                    "popq %r11\n",
+                    "popq %r10\n",
+                    "popq %r9\n",
+                    "popq %r8\n",
+                    "popq %rbp\n",
+                    "popq %rdi\n",
+                    "popq %rsi\n",
+                    "popq %rdx\n",
+                    "popq %rcx\n",
+                    "popq %rbx\n",
+                    "popq %rax\n",
+                    $swapgs, "\n",
+                    "addq $8, %rsp\n",
+                    "iretq\n",
+                ),
+                target = sym target,
+                options(att_syntax)
+            );
+
+            extern "C" {
+                #[link_name = concat!("__x86_64_full_context_", stringify!($name))]
+                fn stub();
+            }
+
+            $crate::VirtAddr::new(stub as usize as u64)
+        }
+    };
+}
 
 /// Represents the options field of an IDT entry.
 #[repr(transparent)]
@@ -721,6 +1184,28 @@ pub struct InterruptStackFrame {
 }
 
 impl InterruptStackFrame {
+    /// Creates a new interrupt stack frame with the given values.
+    ///
+    /// See [`InterruptStackFrameValue::new`] for more information.
+    #[inline]
+    pub fn new(
+        instruction_pointer: VirtAddr,
+        code_segment: SegmentSelector,
+        cpu_flags: RFlags,
+        stack_pointer: VirtAddr,
+        stack_segment: SegmentSelector,
+    ) -> Self {
+        InterruptStackFrame {
+            value: InterruptStackFrameValue::new(
+                instruction_pointer,
+                code_segment,
+                cpu_flags,
+                stack_pointer,
+                stack_segment,
+            ),
+        }
+    }
+
     /// Gives mutable access to the contents of the interrupt stack frame.
     ///
     /// ## Safety
@@ -753,6 +1238,7 @@ impl fmt::Debug for InterruptStackFrame {
 }
 
 /// Represents the interrupt stack frame pushed by the CPU on interrupt or exception entry.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone)]
 #[repr(C)]
 pub struct InterruptStackFrameValue {
@@ -772,21 +1258,38 @@ pub struct InterruptStackFrameValue {
     pub stack_segment: u64,
 }
 
-impl fmt::Debug for InterruptStackFrameValue {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        struct Hex(u64);
-        impl fmt::Debug for Hex {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                write!(f, "{:#x}", self.0)
-            }
+impl InterruptStackFrameValue {
+    /// Creates a new interrupt stack frame value with the given values.
+    ///
+    /// This is useful for fabricating a stack frame that is used to enter a new thread
+    /// through `iretq`, or for calling exception handlers in tests. The fields are laid out
+    /// in the order expected by `iretq`.
+    #[inline]
+    pub fn new(
+        instruction_pointer: VirtAddr,
+        code_segment: SegmentSelector,
+        cpu_flags: RFlags,
+        stack_pointer: VirtAddr,
+        stack_segment: SegmentSelector,
+    ) -> Self {
+        InterruptStackFrameValue {
+            instruction_pointer,
+            code_segment: u64::from(code_segment.0),
+            cpu_flags: cpu_flags.bits(),
+            stack_pointer,
+            stack_segment: u64::from(stack_segment.0),
         }
+    }
+}
 
+impl fmt::Debug for InterruptStackFrameValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut s = f.debug_struct("InterruptStackFrame");
         s.field("instruction_pointer", &self.instruction_pointer);
-        s.field("code_segment", &self.code_segment);
-        s.field("cpu_flags", &Hex(self.cpu_flags));
+        s.field("code_segment", &SegmentSelector(self.code_segment as u16));
+        s.field("cpu_flags", &RFlags::from_bits_truncate(self.cpu_flags));
         s.field("stack_pointer", &self.stack_pointer);
-        s.field("stack_segment", &self.stack_segment);
+        s.field("stack_segment", &SegmentSelector(self.stack_segment as u16));
         s.finish()
     }
 }
@@ -819,9 +1322,319 @@ bitflags! {
     }
 }
 
+impl_flags_display!(PageFaultErrorCode);
+
+/// The vector numbers of the CPU exceptions.
+///
+/// Reserved vectors below 32, including the legacy coprocessor segment overrun (vector 9),
+/// have no variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum ExceptionVector {
+    /// Divide error (`#DE`).
+    Division = 0x00,
+    /// Debug exception (`#DB`).
+    Debug = 0x01,
+    /// Non-maskable interrupt (NMI).
+    NonMaskableInterrupt = 0x02,
+    /// Breakpoint (`#BP`).
+    Breakpoint = 0x03,
+    /// Overflow (`#OF`).
+    Overflow = 0x04,
+    /// Bound range exceeded (`#BR`).
+    BoundRange = 0x05,
+    /// Invalid opcode (`#UD`).
+    InvalidOpcode = 0x06,
+    /// Device not available (`#NM`).
+    DeviceNotAvailable = 0x07,
+    /// Double fault (`#DF`).
+    Double = 0x08,
+    /// Invalid TSS (`#TS`).
+    InvalidTss = 0x0A,
+    /// Segment not present (`#NP`).
+    SegmentNotPresent = 0x0B,
+    /// Stack segment fault (`#SS`).
+    Stack = 0x0C,
+    /// General protection fault (`#GP`).
+    GeneralProtection = 0x0D,
+    /// Page fault (`#PF`).
+    Page = 0x0E,
+    /// x87 floating-point exception (`#MF`).
+    X87FloatingPoint = 0x10,
+    /// Alignment check (`#AC`).
+    AlignmentCheck = 0x11,
+    /// Machine check (`#MC`).
+    MachineCheck = 0x12,
+    /// SIMD floating-point exception (`#XM`).
+    SimdFloatingPoint = 0x13,
+    /// Virtualization exception (`#VE`).
+    Virtualization = 0x14,
+    /// Control protection exception (`#CP`).
+    ControlProtection = 0x15,
+    /// Hypervisor injection exception (`#HV`).
+    HypervisorInjection = 0x1C,
+    /// VMM communication exception (`#VC`).
+    VmmCommunication = 0x1D,
+    /// Security exception (`#SX`).
+    Security = 0x1E,
+}
+
+impl ExceptionVector {
+    /// Returns the vector number of the exception.
+    #[inline]
+    pub const fn vector(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the mnemonic of the exception, e.g. `#PF` for the page fault.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ExceptionVector::Division => "#DE",
+            ExceptionVector::Debug => "#DB",
+            ExceptionVector::NonMaskableInterrupt => "NMI",
+            ExceptionVector::Breakpoint => "#BP",
+            ExceptionVector::Overflow => "#OF",
+            ExceptionVector::BoundRange => "#BR",
+            ExceptionVector::InvalidOpcode => "#UD",
+            ExceptionVector::DeviceNotAvailable => "#NM",
+            ExceptionVector::Double => "#DF",
+            ExceptionVector::InvalidTss => "#TS",
+            ExceptionVector::SegmentNotPresent => "#NP",
+            ExceptionVector::Stack => "#SS",
+            ExceptionVector::GeneralProtection => "#GP",
+            ExceptionVector::Page => "#PF",
+            ExceptionVector::X87FloatingPoint => "#MF",
+            ExceptionVector::AlignmentCheck => "#AC",
+            ExceptionVector::MachineCheck => "#MC",
+            ExceptionVector::SimdFloatingPoint => "#XM",
+            ExceptionVector::Virtualization => "#VE",
+            ExceptionVector::ControlProtection => "#CP",
+            ExceptionVector::HypervisorInjection => "#HV",
+            ExceptionVector::VmmCommunication => "#VC",
+            ExceptionVector::Security => "#SX",
+        }
+    }
+
+    /// Returns the name of the exception, e.g. `Page Fault`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExceptionVector::Division => "Divide Error",
+            ExceptionVector::Debug => "Debug",
+            ExceptionVector::NonMaskableInterrupt => "Non-maskable Interrupt",
+            ExceptionVector::Breakpoint => "Breakpoint",
+            ExceptionVector::Overflow => "Overflow",
+            ExceptionVector::BoundRange => "Bound Range Exceeded",
+            ExceptionVector::InvalidOpcode => "Invalid Opcode",
+            ExceptionVector::DeviceNotAvailable => "Device Not Available",
+            ExceptionVector::Double => "Double Fault",
+            ExceptionVector::InvalidTss => "Invalid TSS",
+            ExceptionVector::SegmentNotPresent => "Segment Not Present",
+            ExceptionVector::Stack => "Stack-Segment Fault",
+            ExceptionVector::GeneralProtection => "General Protection",
+            ExceptionVector::Page => "Page Fault",
+            ExceptionVector::X87FloatingPoint => "x87 Floating-Point Exception",
+            ExceptionVector::AlignmentCheck => "Alignment Check",
+            ExceptionVector::MachineCheck => "Machine Check",
+            ExceptionVector::SimdFloatingPoint => "SIMD Floating-Point Exception",
+            ExceptionVector::Virtualization => "Virtualization Exception",
+            ExceptionVector::ControlProtection => "Control Protection Exception",
+            ExceptionVector::HypervisorInjection => "Hypervisor Injection Exception",
+            ExceptionVector::VmmCommunication => "VMM Communication Exception",
+            ExceptionVector::Security => "Security Exception",
+        }
+    }
+
+    /// Returns whether the CPU pushes an error code for this exception.
+    pub fn has_error_code(self) -> bool {
+        matches!(
+            self,
+            ExceptionVector::Double
+                | ExceptionVector::InvalidTss
+                | ExceptionVector::SegmentNotPresent
+                | ExceptionVector::Stack
+                | ExceptionVector::GeneralProtection
+                | ExceptionVector::Page
+                | ExceptionVector::AlignmentCheck
+                | ExceptionVector::ControlProtection
+                | ExceptionVector::VmmCommunication
+                | ExceptionVector::Security
+        )
+    }
+}
+
+impl fmt::Display for ExceptionVector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} \u{2014} {}", self.mnemonic(), self.name())
+    }
+}
+
+impl From<ExceptionVector> for u8 {
+    #[inline]
+    fn from(vector: ExceptionVector) -> u8 {
+        vector as u8
+    }
+}
+
+/// The error returned by the `TryFrom<u8>` implementation of [`ExceptionVector`] for vector
+/// numbers that are not (or no longer) assigned to an exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidExceptionVector(u8);
+
+impl fmt::Display for InvalidExceptionVector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x} is not an exception vector", self.0)
+    }
+}
+
+impl TryFrom<u8> for ExceptionVector {
+    type Error = InvalidExceptionVector;
+
+    fn try_from(vector: u8) -> Result<ExceptionVector, InvalidExceptionVector> {
+        Ok(match vector {
+            0x00 => ExceptionVector::Division,
+            0x01 => ExceptionVector::Debug,
+            0x02 => ExceptionVector::NonMaskableInterrupt,
+            0x03 => ExceptionVector::Breakpoint,
+            0x04 => ExceptionVector::Overflow,
+            0x05 => ExceptionVector::BoundRange,
+            0x06 => ExceptionVector::InvalidOpcode,
+            0x07 => ExceptionVector::DeviceNotAvailable,
+            0x08 => ExceptionVector::Double,
+            0x0A => ExceptionVector::InvalidTss,
+            0x0B => ExceptionVector::SegmentNotPresent,
+            0x0C => ExceptionVector::Stack,
+            0x0D => ExceptionVector::GeneralProtection,
+            0x0E => ExceptionVector::Page,
+            0x10 => ExceptionVector::X87FloatingPoint,
+            0x11 => ExceptionVector::AlignmentCheck,
+            0x12 => ExceptionVector::MachineCheck,
+            0x13 => ExceptionVector::SimdFloatingPoint,
+            0x14 => ExceptionVector::Virtualization,
+            0x15 => ExceptionVector::ControlProtection,
+            0x1C => ExceptionVector::HypervisorInjection,
+            0x1D => ExceptionVector::VmmCommunication,
+            0x1E => ExceptionVector::Security,
+            _ => return Err(InvalidExceptionVector(vector)),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use core::sync::atomic::AtomicU32;
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_registers() {
+        use zeroize::Zeroize;
+
+        let mut registers = GeneralPurposeRegisters {
+            rax: 1,
+            r15: 2,
+            ..Default::default()
+        };
+        registers.zeroize();
+        assert_eq!((registers.rax, registers.r15), (0, 0));
+    }
+
+    static COUNTER_CONTEXT: HandlerContext<AtomicU32> = HandlerContext::new();
+
+    #[cfg(all(feature = "abi_x86_interrupt", target_arch = "x86_64"))]
+    fn count(counter: &AtomicU32, _frame: &mut InterruptStackFrame) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(all(feature = "abi_x86_interrupt", target_arch = "x86_64"))]
+    crate::context_handler!(fn counter_handler = COUNTER_CONTEXT => count);
+
+    #[test]
+    fn handler_context() {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        assert!(COUNTER_CONTEXT.get().is_none());
+        COUNTER_CONTEXT.set(&COUNTER);
+        assert!(ptr::eq(COUNTER_CONTEXT.get().unwrap(), &COUNTER));
+        COUNTER_CONTEXT.clear();
+        assert!(COUNTER_CONTEXT.get().is_none());
+    }
+
+    #[cfg(all(feature = "abi_x86_interrupt", target_arch = "x86_64"))]
+    #[test]
+    fn context_handler() {
+        let mut idt = InterruptDescriptorTable::new();
+        idt[36].set_handler_fn(counter_handler);
+        let addr = counter_handler as HandlerFunc as usize as u64;
+        let entry = &idt[36];
+        assert_eq!(entry.handler_addr(), VirtAddr::new(addr));
+        // the address is split into three fields
+        assert_eq!(
+            (entry.pointer_low, entry.pointer_middle, entry.pointer_high),
+            (addr as u16, (addr >> 16) as u16, (addr >> 32) as u32)
+        );
+        assert_eq!(
+            entry.gdt_selector,
+            crate::instructions::segmentation::cs().0
+        );
+        assert_eq!(entry.options.0, 0b1000_1110_0000_0000);
+    }
+
+    #[test]
+    fn fabricated_stack_frame() {
+        let frame = InterruptStackFrame::new(
+            VirtAddr::new(0x40_1000),
+            SegmentSelector::new(4, PrivilegeLevel::Ring3),
+            RFlags::INTERRUPT_FLAG,
+            VirtAddr::new(0x7fff_f000),
+            SegmentSelector::new(3, PrivilegeLevel::Ring3),
+        );
+        // the qwords are in the order popped by `iretq`
+        let raw = unsafe { *(&frame as *const InterruptStackFrame as *const [u64; 5]) };
+        assert_eq!(raw, [0x40_1000, 0x23, 0x200, 0x7fff_f000, 0x1b]);
+        assert_eq!(frame.instruction_pointer, VirtAddr::new(0x40_1000));
+        assert_eq!(frame.stack_segment, 0x1b);
+    }
+
+    #[test]
+    fn pointer() {
+        let idt = InterruptDescriptorTable::new();
+        let pointer = idt.pointer();
+        // 256 entries of 16 bytes, the limit is the offset of the last byte
+        let (base, limit) = (pointer.base, pointer.limit);
+        assert_eq!(base, &idt as *const _ as u64);
+        assert_eq!(limit, 256 * 16 - 1);
+    }
+
+    #[test]
+    fn full_interrupt_context_layout() {
+        use core::mem::{size_of, MaybeUninit};
+        use core::ptr::addr_of;
+
+        // the handler stub builds the context on the stack and passes its address
+        let context = MaybeUninit::<FullInterruptContext>::uninit();
+        let base = context.as_ptr();
+        let offset = |field: *const u64| field as usize - base as usize;
+        unsafe {
+            assert_eq!(offset(addr_of!((*base).registers.r15)), 0);
+            assert_eq!(offset(addr_of!((*base).registers.r8)), 7 * 8);
+            assert_eq!(offset(addr_of!((*base).registers.rax)), 14 * 8);
+            assert_eq!(offset(addr_of!((*base).error_code)), 15 * 8);
+            assert_eq!(
+                addr_of!((*base).stack_frame) as usize - base as usize,
+                16 * 8
+            );
+        }
+        assert_eq!(size_of::<GeneralPurposeRegisters>(), 15 * 8);
+        assert_eq!(size_of::<InterruptStackFrame>(), 5 * 8);
+        // the stub aligns the stack for the call based on these 21 qwords
+        assert_eq!(size_of::<FullInterruptContext>(), 21 * 8);
+    }
+
+    #[test]
+    fn const_new() {
+        static IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
+        assert!(IDT.divide_error == Entry::missing());
+        assert!(IDT[32] == Entry::missing());
+    }
 
     #[test]
     fn size_test() {
@@ -829,4 +1642,32 @@ mod test {
         assert_eq!(size_of::<Entry<HandlerFunc>>(), 16);
         assert_eq!(size_of::<InterruptDescriptorTable>(), 256 * 16);
     }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn range_index() {
+        let mut idt = InterruptDescriptorTable::new();
+        assert_eq!(idt[32..48].len(), 16);
+        assert_eq!(idt[250..].len(), 6);
+        unsafe { idt[240..=255][0].set_handler_addr(VirtAddr::new(0x1000)) };
+        assert!(idt[240] != Entry::missing());
+        assert!(idt.get(14).is_none());
+        assert!(idt.get(256).is_none());
+        assert!(idt.get_mut(100).is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn range_index_exceptions() {
+        let idt = InterruptDescriptorTable::new();
+        let _ = &idt[..40];
+    }
+
+    #[test]
+    fn exception_vector() {
+        assert_eq!(ExceptionVector::try_from(14), Ok(ExceptionVector::Page));
+        assert!(ExceptionVector::try_from(9).is_err());
+        assert!(ExceptionVector::Page.has_error_code());
+        assert!(!ExceptionVector::Breakpoint.has_error_code());
+    }
 }
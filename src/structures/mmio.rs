@@ -0,0 +1,140 @@
+//! Provides a type for volatile access to memory mapped device registers.
+
+#[cfg(target_pointer_width = "64")]
+use crate::VirtAddr;
+use bit_field::BitField;
+use core::ops::Range;
+use core::ptr;
+
+/// A memory mapped device register of type `T`.
+///
+/// All accesses are performed through volatile reads and writes of the full register, so the
+/// compiler neither elides, merges, nor reorders them.
+///
+/// In contrast to a cell type that is placed at the register address, `Mmio` only stores a
+/// pointer to the register. Therefore no Rust reference to the device memory is ever created,
+/// which would allow the compiler to insert arbitrary reads.
+#[derive(Debug)]
+pub struct Mmio<T> {
+    ptr: *mut T,
+}
+
+impl<T: Copy> Mmio<T> {
+    /// Creates a new handle for the register at the given pointer.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that `ptr` is a valid and
+    /// properly aligned pointer to a memory mapped register of type `T` and that the register
+    /// stays mapped as long as this handle is used.
+    #[inline]
+    pub const unsafe fn new(ptr: *mut T) -> Mmio<T> {
+        Mmio { ptr }
+    }
+
+    /// Creates a new handle for the register at the given virtual address.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe for the same reasons as [`new`](Mmio::new).
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    pub unsafe fn from_addr(addr: VirtAddr) -> Mmio<T> {
+        Mmio::new(addr.as_mut_ptr())
+    }
+
+    /// Returns the pointer to the register.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// Reads the value of the register.
+    #[inline]
+    pub fn read(&self) -> T {
+        unsafe { ptr::read_volatile(self.ptr) }
+    }
+
+    /// Writes the given value to the register.
+    #[inline]
+    pub fn write(&mut self, value: T) {
+        unsafe { ptr::write_volatile(self.ptr, value) }
+    }
+
+    /// Reads the register, passes the value to the given closure, and writes the result back.
+    ///
+    /// Note that the read and the write are separate accesses, so the update is not atomic
+    /// with respect to the device.
+    #[inline]
+    pub fn update<F>(&mut self, f: F)
+    where
+        F: FnOnce(T) -> T,
+    {
+        let value = self.read();
+        self.write(f(value));
+    }
+}
+
+impl<T: Copy + BitField> Mmio<T> {
+    /// Reads the register and returns the given bit.
+    #[inline]
+    pub fn read_bit(&self, bit: usize) -> bool {
+        self.read().get_bit(bit)
+    }
+
+    /// Reads the register and returns the given range of bits, shifted to the least
+    /// significant bits.
+    #[inline]
+    pub fn read_bits(&self, range: Range<usize>) -> T {
+        self.read().get_bits(range)
+    }
+
+    /// Sets the given bit of the register to `value`, preserving all other bits.
+    #[inline]
+    pub fn write_bit(&mut self, bit: usize, value: bool) {
+        self.update(|mut reg| {
+            reg.set_bit(bit, value);
+            reg
+        });
+    }
+
+    /// Sets the given range of bits of the register to `value`, preserving all other bits.
+    ///
+    /// Panics if `value` does not fit into the range.
+    #[inline]
+    pub fn write_bits(&mut self, range: Range<usize>, value: T) {
+        self.update(|mut reg| {
+            reg.set_bits(range, value);
+            reg
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_bits() {
+        let mut value: u32 = 0x8000_00ff;
+        let mut register = unsafe { Mmio::new(&mut value as *mut u32) };
+        assert!(register.read_bit(31) && !register.read_bit(30));
+        assert_eq!(register.read_bits(4..12), 0x0f);
+
+        register.write_bit(0, false);
+        register.write_bits(8..16, 0xa5);
+        register.update(|reg| reg | 1 << 16);
+        assert_eq!(register.read(), 0x8001_a5fe);
+        register.write(0x1234);
+        assert_eq!(register.as_ptr(), &mut value as *mut u32);
+        assert_eq!(value, 0x1234);
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_bits_out_of_range() {
+        let mut value: u8 = 0;
+        let mut register = unsafe { Mmio::new(&mut value as *mut u8) };
+        register.write_bits(4..8, 0x10);
+    }
+}
@@ -1,18 +1,28 @@
 //! Representations of various x86 specific structures and descriptor tables.
 
-pub mod gdt;
+use core::fmt;
 
-// idt needs `feature(abi_x86_interrupt)`, which is not available on stable rust
-#[cfg(feature = "abi_x86_interrupt")]
+#[cfg(target_arch = "x86_64")]
+pub mod apic;
+pub mod gdt;
+pub mod ghcb;
 pub mod idt;
+pub mod mmio;
 
 pub mod paging;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+pub mod pic;
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+pub mod pit;
 pub mod port;
 pub mod tss;
+pub mod vmcb;
+pub mod vmcs;
+pub mod vmexit;
 
 /// A struct describing a pointer to a descriptor table (GDT / IDT).
 /// This is in a format suitable for giving to 'lgdt' or 'lidt'.
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone, Copy)]
 #[repr(C, packed)]
 pub struct DescriptorTablePointer {
     /// Size of the DT.
@@ -20,3 +30,14 @@ pub struct DescriptorTablePointer {
     /// Pointer to the memory region containing the DT.
     pub base: u64,
 }
+
+impl fmt::Debug for DescriptorTablePointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // copy the fields out of the packed struct to avoid unaligned references
+        let (limit, base) = (self.limit, self.base);
+        let mut s = f.debug_struct("DescriptorTablePointer");
+        s.field("limit", &format_args!("{:#x}", limit));
+        s.field("base", &format_args!("{:#x}", base));
+        s.finish()
+    }
+}
@@ -0,0 +1,510 @@
+//! Extended page tables (EPT), which translate guest-physical to host-physical addresses.
+//!
+//! EPT tables have the same 4-level structure as ordinary page tables, but their entries use
+//! a different flag layout. See section 29.3 "The Extended Page Table Mechanism (EPT)" of the
+//! Intel SDM, volume 3.
+
+use core::fmt;
+use core::ops::{Index, IndexMut};
+
+use super::page_table::{FrameError, PageTableIndex};
+use super::{PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB};
+use crate::addr::PhysAddr;
+use crate::registers::model_specific::EptVpidCapabilities;
+#[cfg(target_arch = "x86_64")]
+use crate::registers::model_specific::VmxEptVpidCap;
+
+use bitflags::bitflags;
+
+/// The memory type of the guest-physical memory mapped by an EPT entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EptMemoryType {
+    /// Uncacheable (UC).
+    Uncacheable = 0,
+    /// Write combining (WC).
+    WriteCombining = 1,
+    /// Write-through (WT).
+    WriteThrough = 4,
+    /// Write-protected (WP).
+    WriteProtected = 5,
+    /// Write-back (WB).
+    WriteBack = 6,
+}
+
+impl EptMemoryType {
+    /// Decodes a memory type, returning `None` for the reserved values 2, 3 and 7.
+    #[inline]
+    pub const fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(EptMemoryType::Uncacheable),
+            1 => Some(EptMemoryType::WriteCombining),
+            4 => Some(EptMemoryType::WriteThrough),
+            5 => Some(EptMemoryType::WriteProtected),
+            6 => Some(EptMemoryType::WriteBack),
+            _ => None,
+        }
+    }
+}
+
+/// A 64-bit EPT entry.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct EptEntry {
+    entry: u64,
+}
+
+impl EptEntry {
+    /// The bits of the memory type of a leaf entry.
+    const MEMORY_TYPE_MASK: u64 = 0b111 << 3;
+
+    /// Creates an unused EPT entry.
+    #[inline]
+    pub const fn new() -> Self {
+        EptEntry { entry: 0 }
+    }
+
+    /// Returns whether this entry is zero.
+    #[inline]
+    pub const fn is_unused(&self) -> bool {
+        self.entry == 0
+    }
+
+    /// Sets this entry to zero.
+    #[inline]
+    pub fn set_unused(&mut self) {
+        self.entry = 0;
+    }
+
+    /// Returns whether this entry is present, i.e. allows any kind of access.
+    #[inline]
+    pub const fn is_present(&self) -> bool {
+        self.flags().intersects(EptFlags::ACCESS)
+    }
+
+    /// Returns the flags of this entry.
+    #[inline]
+    pub const fn flags(&self) -> EptFlags {
+        EptFlags::from_bits_truncate(self.entry)
+    }
+
+    /// Returns the memory type of a leaf entry.
+    ///
+    /// Returns `None` for reserved values, which cause EPT misconfigurations.
+    #[inline]
+    pub const fn memory_type(&self) -> Option<EptMemoryType> {
+        EptMemoryType::from_raw(((self.entry & Self::MEMORY_TYPE_MASK) >> 3) as u8)
+    }
+
+    /// Returns the physical address mapped by this entry, might be zero.
+    #[inline]
+    pub fn addr(&self) -> PhysAddr {
+        PhysAddr::new(self.entry & 0x000fffff_fffff000)
+    }
+
+    /// Returns the physical frame mapped by this entry.
+    ///
+    /// Returns the following errors:
+    ///
+    /// - `FrameError::FrameNotPresent` if the entry doesn't allow any access.
+    /// - `FrameError::HugeFrame` if the entry has the `HUGE_PAGE` flag set (for huge pages the
+    ///   `addr` function must be used)
+    #[inline]
+    pub fn frame(&self) -> Result<PhysFrame, FrameError> {
+        if !self.is_present() {
+            Err(FrameError::FrameNotPresent)
+        } else if self.flags().contains(EptFlags::HUGE_PAGE) {
+            Err(FrameError::HugeFrame)
+        } else {
+            Ok(PhysFrame::containing_address(self.addr()))
+        }
+    }
+
+    /// Map the entry to the specified physical address with the specified flags.
+    ///
+    /// The memory type is set to uncacheable, which only matters for leaf entries.
+    #[inline]
+    pub fn set_addr(&mut self, addr: PhysAddr, flags: EptFlags) {
+        assert!(addr.is_aligned(Size4KiB::SIZE));
+        self.entry = addr.as_u64() | flags.bits();
+    }
+
+    /// Map the entry to the specified physical frame, which is either an EPT table or a 4KiB
+    /// frame, with the specified flags.
+    #[inline]
+    pub fn set_frame(&mut self, frame: PhysFrame, flags: EptFlags) {
+        assert!(!flags.contains(EptFlags::HUGE_PAGE));
+        self.set_addr(frame.start_address(), flags)
+    }
+
+    /// Map the entry to the specified 2MiB or 1GiB frame with the specified flags and memory
+    /// type.
+    ///
+    /// The `HUGE_PAGE` flag is set automatically. The entry must be in an EPT PD table for
+    /// 2MiB frames and in an EPT PDPT table for 1GiB frames.
+    #[inline]
+    pub fn set_huge_frame<S: PageSize>(
+        &mut self,
+        frame: PhysFrame<S>,
+        flags: EptFlags,
+        memory_type: EptMemoryType,
+    ) {
+        assert!(S::SIZE == Size2MiB::SIZE || S::SIZE == Size1GiB::SIZE);
+        self.entry = frame.start_address().as_u64() | (flags | EptFlags::HUGE_PAGE).bits();
+        self.set_memory_type(memory_type);
+    }
+
+    /// Sets the flags of this entry, keeping its address and memory type.
+    #[inline]
+    pub fn set_flags(&mut self, flags: EptFlags) {
+        self.entry = self.addr().as_u64() | (self.entry & Self::MEMORY_TYPE_MASK) | flags.bits();
+    }
+
+    /// Sets the memory type of a leaf entry.
+    #[inline]
+    pub fn set_memory_type(&mut self, memory_type: EptMemoryType) {
+        self.entry = (self.entry & !Self::MEMORY_TYPE_MASK) | (memory_type as u64) << 3;
+    }
+}
+
+impl Default for EptEntry {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for EptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("EptEntry");
+        f.field("addr", &self.addr());
+        f.field("flags", &self.flags());
+        f.field("memory_type", &self.memory_type());
+        f.finish()
+    }
+}
+
+bitflags! {
+    /// Possible flags for an EPT entry.
+    ///
+    /// The memory type in bits 3 to 5 is accessed through [`EptEntry::memory_type`] instead.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct EptFlags: u64 {
+        /// Allows reads from the mapped region.
+        const READ =                     1;
+        /// Allows writes to the mapped region.
+        const WRITE =                    1 << 1;
+        /// Allows instruction fetches from the mapped region, only from supervisor-mode linear
+        /// addresses if mode-based execute control is enabled.
+        const EXECUTE =                  1 << 2;
+        /// Ignores the PAT memory type of the guest, so that the EPT memory type is used.
+        /// Only allowed in leaf entries.
+        const IGNORE_PAT =               1 << 6;
+        /// Specifies that the entry maps a huge frame instead of an EPT table. Only allowed in
+        /// EPT PD or PDPT tables.
+        const HUGE_PAGE =                1 << 7;
+        /// Set by the CPU when the entry is used for a translation, if accessed and dirty flags
+        /// are enabled in the EPT pointer.
+        const ACCESSED =                 1 << 8;
+        /// Set by the CPU on a write to the mapped frame, if accessed and dirty flags are
+        /// enabled in the EPT pointer. Only used in leaf entries.
+        const DIRTY =                    1 << 9;
+        /// Allows instruction fetches from user-mode linear addresses, if mode-based execute
+        /// control is enabled.
+        const USER_EXECUTE =             1 << 10;
+        /// Available to the VMM, can be used to store additional data, e.g. custom flags.
+        const BIT_11 =                   1 << 11;
+        /// Verifies the guest paging structures on accesses to the mapped frame.
+        const VERIFY_GUEST_PAGING =      1 << 57;
+        /// Allows writes of the processor to guest paging structures in the mapped frame.
+        const PAGING_WRITE_ACCESS =      1 << 58;
+        /// Marks the mapped frame as a supervisor shadow-stack page.
+        const SUPERVISOR_SHADOW_STACK =  1 << 60;
+        /// Enables sub-page write permissions for the mapped frame.
+        const SUB_PAGE_WRITE =           1 << 61;
+        /// Suppresses virtualization exceptions for EPT violations caused by this entry.
+        const SUPPRESS_VE =              1 << 63;
+
+        /// Allows all kinds of accesses.
+        const ACCESS = Self::READ.bits | Self::WRITE.bits | Self::EXECUTE.bits;
+    }
+}
+
+impl_flags_display!(EptFlags);
+
+/// The number of entries in an EPT table.
+const ENTRY_COUNT: usize = 512;
+
+/// Represents an EPT table at any level.
+///
+/// Always page-sized.
+///
+/// This struct implements the `Index` and `IndexMut` traits, so the entries can be accessed
+/// through index operations.
+#[repr(align(4096))]
+#[repr(C)]
+pub struct EptPageTable {
+    entries: [EptEntry; ENTRY_COUNT],
+}
+
+impl EptPageTable {
+    /// Creates an empty EPT table.
+    #[inline]
+    pub const fn new() -> Self {
+        const EMPTY: EptEntry = EptEntry::new();
+        EptPageTable {
+            entries: [EMPTY; ENTRY_COUNT],
+        }
+    }
+
+    /// Clears all entries.
+    #[inline]
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+    }
+
+    /// Returns an iterator over the entries of the EPT table.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &EptEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns an iterator that allows modifying the entries of the EPT table.
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut EptEntry> {
+        self.entries.iter_mut()
+    }
+}
+
+impl Default for EptPageTable {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for EptPageTable {
+    type Output = EptEntry;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for EptPageTable {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}
+
+impl Index<PageTableIndex> for EptPageTable {
+    type Output = EptEntry;
+
+    #[inline]
+    fn index(&self, index: PageTableIndex) -> &Self::Output {
+        &self.entries[usize::from(index)]
+    }
+}
+
+impl IndexMut<PageTableIndex> for EptPageTable {
+    #[inline]
+    fn index_mut(&mut self, index: PageTableIndex) -> &mut Self::Output {
+        &mut self.entries[usize::from(index)]
+    }
+}
+
+impl fmt::Debug for EptPageTable {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.entries[..].fmt(f)
+    }
+}
+
+/// The EPT pointer, which references the level 4 EPT table and configures the translation.
+///
+/// The pointer is written to the [`Eptp`](crate::structures::vmcs::VmcsField64::Eptp) field
+/// of the VMCS. A pointer that isn't supported by the processor makes VM entry fail, so it
+/// should be checked with [`validate`](Eptp::validate) first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Eptp(u64);
+
+impl Eptp {
+    /// The bits of the memory type used for accesses to the EPT tables.
+    const MEMORY_TYPE_MASK: u64 = 0b111;
+    /// The bits of the page-walk length minus 1.
+    const PAGE_WALK_LENGTH_MASK: u64 = 0b111 << 3;
+    /// Enables the accessed and dirty flags of EPT entries.
+    const ACCESSED_DIRTY: u64 = 1 << 6;
+
+    /// Creates an EPT pointer to the given level 4 table with a page-walk length of 4 and the
+    /// accessed and dirty flags disabled.
+    ///
+    /// The memory type is used for accesses to the EPT tables, only
+    /// [`Uncacheable`](EptMemoryType::Uncacheable) and [`WriteBack`](EptMemoryType::WriteBack)
+    /// are allowed.
+    #[inline]
+    pub fn new(level_4_table: PhysFrame, memory_type: EptMemoryType) -> Self {
+        Eptp(level_4_table.start_address().as_u64() | memory_type as u64 | 3 << 3)
+    }
+
+    /// Creates an EPT pointer from its raw value.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        Eptp(raw)
+    }
+
+    /// Returns the raw value of the EPT pointer.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Sets the number of EPT table levels, which is 4, or 5 for a level 5 table.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the length isn't between 1 and 8.
+    #[inline]
+    pub fn with_page_walk_length(self, length: u8) -> Self {
+        assert!((1..=8).contains(&length), "invalid page-walk length");
+        Eptp((self.0 & !Self::PAGE_WALK_LENGTH_MASK) | u64::from(length - 1) << 3)
+    }
+
+    /// Enables or disables the accessed and dirty flags of EPT entries.
+    #[inline]
+    pub const fn with_accessed_dirty(self, enable: bool) -> Self {
+        if enable {
+            Eptp(self.0 | Self::ACCESSED_DIRTY)
+        } else {
+            Eptp(self.0 & !Self::ACCESSED_DIRTY)
+        }
+    }
+
+    /// Returns the frame of the top-level EPT table.
+    #[inline]
+    pub fn level_4_table(self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.0 & 0x000fffff_fffff000))
+    }
+
+    /// Returns the memory type used for accesses to the EPT tables.
+    ///
+    /// Returns `None` for reserved values.
+    #[inline]
+    pub const fn memory_type(self) -> Option<EptMemoryType> {
+        EptMemoryType::from_raw((self.0 & Self::MEMORY_TYPE_MASK) as u8)
+    }
+
+    /// Returns the number of EPT table levels.
+    #[inline]
+    pub const fn page_walk_length(self) -> u8 {
+        ((self.0 & Self::PAGE_WALK_LENGTH_MASK) >> 3) as u8 + 1
+    }
+
+    /// Returns whether the accessed and dirty flags of EPT entries are enabled.
+    #[inline]
+    pub const fn accessed_dirty(self) -> bool {
+        self.0 & Self::ACCESSED_DIRTY != 0
+    }
+
+    /// Checks the EPT pointer against the EPT capabilities of the processor.
+    ///
+    /// The capabilities are read from [`VmxEptVpidCap`], which must exist.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn validate(self) -> Result<Self, EptpError> {
+        self.validate_with(&VmxEptVpidCap::read())
+    }
+
+    /// Checks the EPT pointer against the given EPT capabilities.
+    pub fn validate_with(self, capabilities: &EptVpidCapabilities) -> Result<Self, EptpError> {
+        let memory_type = (self.0 & Self::MEMORY_TYPE_MASK) as u8;
+        match EptMemoryType::from_raw(memory_type) {
+            Some(memory_type) if capabilities.supports_memory_type(memory_type) => {}
+            _ => return Err(EptpError::UnsupportedMemoryType(memory_type)),
+        }
+
+        let length = self.page_walk_length();
+        if !capabilities.supports_page_walk_length(length) {
+            return Err(EptpError::UnsupportedPageWalkLength(length));
+        }
+
+        if self.accessed_dirty() && !capabilities.accessed_dirty {
+            return Err(EptpError::AccessedDirtyUnsupported);
+        }
+        Ok(self)
+    }
+}
+
+/// An EPT pointer setting that isn't supported by the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EptpError {
+    /// The memory type for accesses to the EPT tables isn't supported.
+    UnsupportedMemoryType(u8),
+    /// The number of EPT table levels isn't supported.
+    UnsupportedPageWalkLength(u8),
+    /// The accessed and dirty flags of EPT entries aren't supported.
+    AccessedDirtyUnsupported,
+}
+
+impl fmt::Display for EptpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EptpError::UnsupportedMemoryType(memory_type) => {
+                write!(f, "the EPT memory type {} is not supported", memory_type)
+            }
+            EptpError::UnsupportedPageWalkLength(length) => {
+                write!(f, "the EPT page-walk length {} is not supported", length)
+            }
+            EptpError::AccessedDirtyUnsupported => {
+                f.write_str("the EPT accessed and dirty flags are not supported")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ept_entry() {
+        let mut entry = EptEntry::new();
+        assert!(!entry.is_present());
+        let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(0x4020_0000));
+        entry.set_huge_frame(frame, EptFlags::READ, EptMemoryType::WriteBack);
+        assert_eq!(entry.addr(), PhysAddr::new(0x4020_0000));
+        assert_eq!(entry.memory_type(), Some(EptMemoryType::WriteBack));
+        assert_eq!(entry.frame(), Err(FrameError::HugeFrame));
+        entry.set_flags(EptFlags::ACCESS | EptFlags::HUGE_PAGE);
+        assert_eq!(entry.memory_type(), Some(EptMemoryType::WriteBack));
+        assert!(entry.flags().contains(EptFlags::WRITE));
+    }
+
+    #[test]
+    fn eptp() {
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x1234_5000));
+        let eptp = Eptp::new(frame, EptMemoryType::WriteBack).with_accessed_dirty(true);
+        assert_eq!(eptp.as_raw(), 0x1234_505e);
+        assert_eq!(eptp.level_4_table(), frame);
+        assert_eq!(eptp.page_walk_length(), 4);
+
+        // 4-level walks, UC and WB, no accessed and dirty flags
+        let capabilities = &EptVpidCapabilities::from_raw(1 << 6 | 1 << 8 | 1 << 14);
+        assert_eq!(
+            eptp.validate_with(capabilities),
+            Err(EptpError::AccessedDirtyUnsupported)
+        );
+        let eptp = eptp.with_accessed_dirty(false);
+        assert_eq!(eptp.validate_with(capabilities), Ok(eptp));
+        assert_eq!(
+            eptp.with_page_walk_length(5).validate_with(capabilities),
+            Err(EptpError::UnsupportedPageWalkLength(5))
+        );
+    }
+}
@@ -1,12 +1,18 @@
 //! Abstractions for default-sized and huge physical memory frames.
 
-use crate::structures::paging::page::{PageSize, Size4KiB};
+use crate::structures::paging::page::{AddressNotAligned, PageSize, Size4KiB};
 use crate::PhysAddr;
+use core::convert::TryFrom;
 use core::fmt;
 use core::marker::PhantomData;
 use core::ops::{Add, AddAssign, Sub, SubAssign};
 
 /// A physical memory frame.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "", try_from = "PhysAddr", into = "PhysAddr")
+)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(C)]
 pub struct PhysFrame<S: PageSize = Size4KiB> {
@@ -93,6 +99,22 @@ impl<S: PageSize> fmt::Debug for PhysFrame<S> {
     }
 }
 
+impl<S: PageSize> TryFrom<PhysAddr> for PhysFrame<S> {
+    type Error = AddressNotAligned;
+
+    #[inline]
+    fn try_from(address: PhysAddr) -> Result<Self, Self::Error> {
+        PhysFrame::from_start_address(address).map_err(|()| AddressNotAligned)
+    }
+}
+
+impl<S: PageSize> From<PhysFrame<S>> for PhysAddr {
+    #[inline]
+    fn from(frame: PhysFrame<S>) -> PhysAddr {
+        frame.start_address()
+    }
+}
+
 impl<S: PageSize> Add<u64> for PhysFrame<S> {
     type Output = Self;
     #[inline]
@@ -132,6 +154,11 @@ impl<S: PageSize> Sub<PhysFrame<S>> for PhysFrame<S> {
 }
 
 /// An range of physical memory frames, exclusive the upper bound.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct PhysFrameRange<S: PageSize = Size4KiB> {
@@ -174,6 +201,11 @@ impl<S: PageSize> fmt::Debug for PhysFrameRange<S> {
 }
 
 /// An range of physical memory frames, inclusive the upper bound.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct PhysFrameRangeInclusive<S: PageSize = Size4KiB> {
@@ -214,3 +246,37 @@ impl<S: PageSize> fmt::Debug for PhysFrameRangeInclusive<S> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::paging::Size2MiB;
+
+    #[test]
+    fn conversions() {
+        let frame = PhysFrame::<Size2MiB>::try_from(PhysAddr::new(0x20_0000)).unwrap();
+        assert_eq!(PhysAddr::from(frame), PhysAddr::new(0x20_0000));
+        assert_eq!(
+            PhysFrame::<Size2MiB>::try_from(PhysAddr::new(0x1000)),
+            Err(AddressNotAligned)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_rejects_unaligned_frames() {
+        use serde::de::{value::Error, Deserialize, IntoDeserializer};
+
+        let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(0x4020_0000));
+        let deserialized: Result<PhysFrame<Size2MiB>, Error> =
+            PhysFrame::deserialize(u64::from(PhysAddr::from(frame)).into_deserializer());
+        assert_eq!(deserialized.unwrap(), frame);
+
+        let unaligned: Result<PhysFrame<Size2MiB>, Error> =
+            PhysFrame::deserialize(0x4020_1000u64.into_deserializer());
+        assert!(unaligned.is_err());
+        let invalid: Result<PhysFrame, Error> =
+            PhysFrame::deserialize((1u64 << 52).into_deserializer());
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use crate::structures::paging::{
+    ept::{EptEntry, EptFlags, EptMemoryType, EptPageTable},
+    frame::PhysFrame,
+    frame_alloc::FrameAllocator,
+    mapper::*,
+    page::{Size1GiB, Size2MiB, Size4KiB},
+    page_table::{FrameError, PageTableIndex},
+};
+
+/// A trait for mapping guest-physical frames of size `S` in an EPT hierarchy.
+///
+/// This is the counterpart of [`Mapper`] for second-level address translation. Since EPT
+/// entries are cached by the processor, the cached translations have to be invalidated with
+/// `invept` after changing or removing an existing mapping.
+pub trait GuestMapper<S: PageSize> {
+    /// Maps the given guest-physical frame to the given host-physical frame.
+    ///
+    /// This function might need additional physical frames to create new EPT tables. These
+    /// frames are allocated from the `allocator` argument. At most three frames are required.
+    ///
+    /// ## Safety
+    ///
+    /// The guest gets access to the host frame with the given permissions, so the caller must
+    /// ensure that the frame isn't used by the host in a way that the guest can break.
+    unsafe fn map_to<A>(
+        &mut self,
+        guest: PhysFrame<S>,
+        host: PhysFrame<S>,
+        flags: EptFlags,
+        memory_type: EptMemoryType,
+        frame_allocator: &mut A,
+    ) -> Result<(), MapToError<S>>
+    where
+        Self: Sized,
+        A: FrameAllocator<Size4KiB>;
+
+    /// Removes a mapping and returns the host frame that used to be mapped.
+    ///
+    /// Note that no EPT tables or frames are deallocated.
+    fn unmap(&mut self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, UnmapError>;
+
+    /// Updates the flags of an existing mapping, keeping its memory type.
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because it can give the guest new permissions for the host frame,
+    /// see [`map_to`](GuestMapper::map_to).
+    unsafe fn update_flags(
+        &mut self,
+        guest: PhysFrame<S>,
+        flags: EptFlags,
+    ) -> Result<(), FlagUpdateError>;
+
+    /// Returns the host frame that the given guest frame is mapped to.
+    ///
+    /// This function assumes that the guest frame is mapped with size `S` and returns an error
+    /// otherwise.
+    fn translate_frame(&self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, TranslateError>;
+}
+
+/// An EPT mapper that requires that the complete host-physical memory is mapped at some
+/// offset in the virtual address space, like [`OffsetPageTable`](super::OffsetPageTable).
+#[derive(Debug)]
+pub struct EptMapper<'a> {
+    walker: EptWalker,
+    level_4_table: &'a mut EptPageTable,
+}
+
+impl<'a> EptMapper<'a> {
+    /// Creates a new `EptMapper` that uses the given offset for converting host-physical to
+    /// virtual addresses.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// `phys_offset` is correct. Also, the passed `level_4_table` must point to the level 4
+    /// table of a valid EPT hierarchy. Otherwise this function might break memory safety,
+    /// e.g. by writing to an illegal memory location.
+    #[inline]
+    pub unsafe fn new(level_4_table: &'a mut EptPageTable, phys_offset: VirtAddr) -> Self {
+        EptMapper {
+            walker: EptWalker { phys_offset },
+            level_4_table,
+        }
+    }
+
+    /// Returns a mutable reference to the wrapped level 4 EPT table.
+    #[inline]
+    pub fn level_4_table(&mut self) -> &mut EptPageTable {
+        self.level_4_table
+    }
+
+    /// Translates the given guest-physical address to the host-physical address that it maps
+    /// to.
+    ///
+    /// Returns `None` if there is no valid mapping for the given address.
+    pub fn translate_addr(&self, addr: PhysAddr) -> Option<PhysAddr> {
+        let mut table: &EptPageTable = self.level_4_table;
+        for level in (1..=4).rev() {
+            let entry = &table[index(addr, level)];
+            if !entry.is_present() {
+                return None;
+            }
+            if level == 1 || entry.flags().contains(EptFlags::HUGE_PAGE) {
+                let offset = addr.as_u64() & (level_size(level) - 1);
+                return Some(entry.addr() + offset);
+            }
+            table = self.walker.next_table(entry).ok()?;
+        }
+        unreachable!("level 1 entries are always leaves")
+    }
+
+    /// Returns the entry of the given level for the given guest-physical address.
+    fn entry(&self, addr: PhysAddr, level: u8) -> Result<&EptEntry, WalkError> {
+        let mut table: &EptPageTable = self.level_4_table;
+        for parent in (level + 1..=4).rev() {
+            table = self.walker.next_table(&table[index(addr, parent)])?;
+        }
+        Ok(&table[index(addr, level)])
+    }
+
+    /// Returns the entry of the given level for the given guest-physical address.
+    fn entry_mut(&mut self, addr: PhysAddr, level: u8) -> Result<&mut EptEntry, WalkError> {
+        let walker = &self.walker;
+        let mut table: &mut EptPageTable = self.level_4_table;
+        for parent in (level + 1..=4).rev() {
+            table = walker.next_table_mut(&mut table[index(addr, parent)])?;
+        }
+        Ok(&mut table[index(addr, level)])
+    }
+
+    /// Returns the entry of the given level for the given guest-physical address, creating
+    /// the EPT tables on the way if they don't exist.
+    fn create_entry<A>(
+        &mut self,
+        addr: PhysAddr,
+        level: u8,
+        allocator: &mut A,
+    ) -> Result<&mut EptEntry, CreateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let walker = &self.walker;
+        let mut table: &mut EptPageTable = self.level_4_table;
+        for parent in (level + 1..=4).rev() {
+            table = walker.create_next_table(&mut table[index(addr, parent)], allocator)?;
+        }
+        Ok(&mut table[index(addr, level)])
+    }
+}
+
+impl<'a, S: PageSize> GuestMapper<S> for EptMapper<'a> {
+    unsafe fn map_to<A>(
+        &mut self,
+        guest: PhysFrame<S>,
+        host: PhysFrame<S>,
+        flags: EptFlags,
+        memory_type: EptMemoryType,
+        allocator: &mut A,
+    ) -> Result<(), MapToError<S>>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let level = leaf_level::<S>();
+        let entry = self
+            .create_entry(guest.start_address(), level, allocator)
+            .map_err(|err| match err {
+                CreateError::MappedToHugePage => MapToError::ParentEntryHugePage,
+                CreateError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+            })?;
+
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(host));
+        }
+        let flags = if level > 1 {
+            flags | EptFlags::HUGE_PAGE
+        } else {
+            flags - EptFlags::HUGE_PAGE
+        };
+        entry.set_addr(host.start_address(), flags);
+        entry.set_memory_type(memory_type);
+        Ok(())
+    }
+
+    fn unmap(&mut self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, UnmapError> {
+        let level = leaf_level::<S>();
+        let entry = self.entry_mut(guest.start_address(), level)?;
+
+        if !entry.is_present() {
+            return Err(UnmapError::PageNotMapped);
+        }
+        if (level > 1) != entry.flags().contains(EptFlags::HUGE_PAGE) {
+            return Err(UnmapError::ParentEntryHugePage);
+        }
+
+        let frame = PhysFrame::from_start_address(entry.addr())
+            .map_err(|()| UnmapError::InvalidFrameAddress(entry.addr()))?;
+
+        entry.set_unused();
+        Ok(frame)
+    }
+
+    unsafe fn update_flags(
+        &mut self,
+        guest: PhysFrame<S>,
+        flags: EptFlags,
+    ) -> Result<(), FlagUpdateError> {
+        let level = leaf_level::<S>();
+        let entry = self.entry_mut(guest.start_address(), level)?;
+
+        if entry.is_unused() {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        if level > 1 {
+            entry.set_flags(flags | EptFlags::HUGE_PAGE);
+        } else {
+            entry.set_flags(flags - EptFlags::HUGE_PAGE);
+        }
+        Ok(())
+    }
+
+    fn translate_frame(&self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, TranslateError> {
+        let level = leaf_level::<S>();
+        let entry = self.entry(guest.start_address(), level)?;
+
+        if !entry.is_present() {
+            return Err(TranslateError::PageNotMapped);
+        }
+
+        PhysFrame::from_start_address(entry.addr())
+            .map_err(|()| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+/// Returns the level of the EPT tables whose entries map frames of size `S`.
+#[inline]
+fn leaf_level<S: PageSize>() -> u8 {
+    match S::SIZE {
+        Size4KiB::SIZE => 1,
+        Size2MiB::SIZE => 2,
+        Size1GiB::SIZE => 3,
+        _ => unreachable!("EPT entries only map 4KiB, 2MiB and 1GiB frames"),
+    }
+}
+
+/// Returns the size of the region mapped by an entry of the given level.
+#[inline]
+fn level_size(level: u8) -> u64 {
+    1 << (12 + 9 * u64::from(level - 1))
+}
+
+/// Returns the index into an EPT table of the given level for the given guest-physical
+/// address.
+#[inline]
+fn index(addr: PhysAddr, level: u8) -> PageTableIndex {
+    PageTableIndex::new_truncate((addr.as_u64() >> (12 + 9 * (level - 1))) as u16)
+}
+
+#[derive(Debug)]
+struct EptWalker {
+    phys_offset: VirtAddr,
+}
+
+impl EptWalker {
+    /// Returns the EPT table referenced by the given entry.
+    #[inline]
+    fn table_ptr(&self, frame: PhysFrame) -> *mut EptPageTable {
+        (self.phys_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
+
+    /// Internal helper function to get a reference to the EPT table of the next level.
+    ///
+    /// Returns `WalkError::NotMapped` if the entry is unused. Returns
+    /// `WalkError::MappedToHugePage` if the `HUGE_PAGE` flag is set in the passed entry.
+    #[inline]
+    fn next_table<'b>(&self, entry: &'b EptEntry) -> Result<&'b EptPageTable, WalkError> {
+        let table_ptr = self.table_ptr(entry.frame()?);
+        Ok(unsafe { &*table_ptr })
+    }
+
+    /// Internal helper function to get a mutable reference to the EPT table of the next
+    /// level.
+    ///
+    /// Returns `WalkError::NotMapped` if the entry is unused. Returns
+    /// `WalkError::MappedToHugePage` if the `HUGE_PAGE` flag is set in the passed entry.
+    #[inline]
+    fn next_table_mut<'b>(
+        &self,
+        entry: &'b mut EptEntry,
+    ) -> Result<&'b mut EptPageTable, WalkError> {
+        let table_ptr = self.table_ptr(entry.frame()?);
+        Ok(unsafe { &mut *table_ptr })
+    }
+
+    /// Internal helper function to create the EPT table of the next level if needed.
+    ///
+    /// If the passed entry is unused, a new frame is allocated from the given allocator, zeroed,
+    /// and the entry is updated to that address. The intermediate entries allow all accesses,
+    /// the permissions are controlled by the leaf entries.
+    fn create_next_table<'b, A>(
+        &self,
+        entry: &'b mut EptEntry,
+        allocator: &mut A,
+    ) -> Result<&'b mut EptPageTable, CreateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let created = if entry.is_unused() {
+            let frame = allocator
+                .allocate_frame()
+                .ok_or(CreateError::FrameAllocationFailed)?;
+            entry.set_frame(frame, EptFlags::ACCESS | EptFlags::USER_EXECUTE);
+            true
+        } else {
+            false
+        };
+
+        let table = match self.next_table_mut(entry) {
+            Err(WalkError::MappedToHugePage) => return Err(CreateError::MappedToHugePage),
+            Err(WalkError::NotMapped) => panic!("entry should be mapped at this point"),
+            Ok(table) => table,
+        };
+
+        if created {
+            table.zero();
+        }
+        Ok(table)
+    }
+}
+
+#[derive(Debug)]
+enum WalkError {
+    NotMapped,
+    MappedToHugePage,
+}
+
+#[derive(Debug)]
+enum CreateError {
+    MappedToHugePage,
+    FrameAllocationFailed,
+}
+
+impl From<FrameError> for WalkError {
+    #[inline]
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::HugeFrame => WalkError::MappedToHugePage,
+            FrameError::FrameNotPresent => WalkError::NotMapped,
+        }
+    }
+}
+
+impl From<WalkError> for CreateError {
+    #[inline]
+    fn from(err: WalkError) -> Self {
+        match err {
+            WalkError::MappedToHugePage => CreateError::MappedToHugePage,
+            WalkError::NotMapped => panic!("entry should be mapped at this point"),
+        }
+    }
+}
+
+impl From<WalkError> for UnmapError {
+    #[inline]
+    fn from(err: WalkError) -> Self {
+        match err {
+            WalkError::MappedToHugePage => UnmapError::ParentEntryHugePage,
+            WalkError::NotMapped => UnmapError::PageNotMapped,
+        }
+    }
+}
+
+impl From<WalkError> for FlagUpdateError {
+    #[inline]
+    fn from(err: WalkError) -> Self {
+        match err {
+            WalkError::MappedToHugePage => FlagUpdateError::ParentEntryHugePage,
+            WalkError::NotMapped => FlagUpdateError::PageNotMapped,
+        }
+    }
+}
+
+impl From<WalkError> for TranslateError {
+    #[inline]
+    fn from(err: WalkError) -> Self {
+        match err {
+            WalkError::MappedToHugePage => TranslateError::ParentEntryHugePage,
+            WalkError::NotMapped => TranslateError::PageNotMapped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Allocates the EPT tables from an array, using the virtual addresses of the test as
+    /// host-physical addresses.
+    struct TableAllocator<'a> {
+        tables: core::slice::IterMut<'a, EptPageTable>,
+    }
+
+    unsafe impl FrameAllocator<Size4KiB> for TableAllocator<'_> {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            let table = self.tables.next()?;
+            Some(PhysFrame::containing_address(PhysAddr::new(
+                table as *mut EptPageTable as u64,
+            )))
+        }
+    }
+
+    #[test]
+    fn ept_mapper() {
+        let mut tables: Vec<EptPageTable> = (0..5).map(|_| EptPageTable::new()).collect();
+        let (level_4_table, tables) = tables.split_first_mut().unwrap();
+        let mut allocator = TableAllocator {
+            tables: tables.iter_mut(),
+        };
+        let mut mapper = unsafe { EptMapper::new(level_4_table, VirtAddr::new(0)) };
+
+        let guest = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(0x1234_5000));
+        let host = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        unsafe {
+            mapper.map_to(
+                guest,
+                host,
+                EptFlags::READ | EptFlags::WRITE,
+                EptMemoryType::WriteBack,
+                &mut allocator,
+            )
+        }
+        .unwrap();
+        assert_eq!(mapper.translate_frame(guest).unwrap(), host);
+        assert_eq!(
+            mapper.translate_addr(PhysAddr::new(0x1234_5678)),
+            Some(PhysAddr::new(0x8000_0678))
+        );
+
+        // a 2MiB frame in the same EPT PDPT table, needing only a new EPT PD table
+        let guest = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(0x4000_0000));
+        let host = PhysFrame::containing_address(PhysAddr::new(0x1_0000_0000));
+        unsafe {
+            mapper.map_to(
+                guest,
+                host,
+                EptFlags::ACCESS,
+                EptMemoryType::WriteBack,
+                &mut allocator,
+            )
+        }
+        .unwrap();
+        assert_eq!(
+            mapper.translate_addr(PhysAddr::new(0x4012_3456)),
+            Some(PhysAddr::new(0x1_0012_3456))
+        );
+        assert_eq!(mapper.unmap(guest).unwrap(), host);
+        assert_eq!(mapper.translate_addr(PhysAddr::new(0x4012_3456)), None);
+    }
+}
@@ -1,9 +1,9 @@
 use crate::structures::paging::{
-    frame::PhysFrame,
+    frame::{PhysFrame, PhysFrameRange},
     frame_alloc::FrameAllocator,
     mapper::*,
-    page::{Page, Size1GiB, Size2MiB, Size4KiB},
-    page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags},
+    page::{Page, PageRange, Size1GiB, Size2MiB, Size4KiB},
+    page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags, PageTableLevel},
 };
 
 /// A Mapper implementation that relies on a PhysAddr to VirtAddr conversion function.
@@ -13,10 +13,13 @@ use crate::structures::paging::{
 /// the virtual address space at some offset. Other mappings between physical and virtual
 /// memory are possible too, as long as they can be calculated as an `PhysAddr` to
 /// `VirtAddr` closure.
+///
+/// The page table hierarchy has four levels, or five levels if it is created with
+/// [`new_5level`](MappedPageTable::new_5level) for 5-level paging (CR4.LA57).
 #[derive(Debug)]
 pub struct MappedPageTable<'a, P: PhysToVirt> {
     page_table_walker: PageTableWalker<P>,
-    level_4_table: &'a mut PageTable,
+    top_table: &'a mut PageTable,
 }
 
 impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
@@ -32,11 +35,41 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     #[inline]
     pub unsafe fn new(level_4_table: &'a mut PageTable, phys_to_virt: P) -> Self {
         Self {
-            level_4_table,
-            page_table_walker: PageTableWalker::new(phys_to_virt),
+            top_table: level_4_table,
+            page_table_walker: PageTableWalker::new(phys_to_virt, PageTableLevel::Four),
+        }
+    }
+
+    /// Creates a new `MappedPageTable` for 5-level paging that uses the passed closure for
+    /// converting virtual to physical addresses.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe for the same reasons as [`new`](MappedPageTable::new), with
+    /// the passed `level_5_table` pointing to the level 5 page table of a valid page table
+    /// hierarchy.
+    #[inline]
+    pub unsafe fn new_5level(level_5_table: &'a mut PageTable, phys_to_virt: P) -> Self {
+        Self {
+            top_table: level_5_table,
+            page_table_walker: PageTableWalker::new(phys_to_virt, PageTableLevel::Five),
         }
     }
 
+    /// Returns a mutable reference to the wrapped top-level `PageTable` instance, which is the
+    /// level 5 table with 5-level paging.
+    #[inline]
+    pub fn level_4_table(&mut self) -> &mut PageTable {
+        self.top_table
+    }
+
+    /// Returns the level of the top-level table, i.e. [`PageTableLevel::Five`] with 5-level
+    /// paging and [`PageTableLevel::Four`] otherwise.
+    #[inline]
+    pub fn top_level(&self) -> PageTableLevel {
+        self.page_table_walker.top_level
+    }
+
     /// Helper function for implementing Mapper. Safe to limit the scope of unsafe, see
     /// https://github.com/rust-lang/rfcs/pull/2585.
     fn map_to_1gib<A>(
@@ -49,7 +82,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     where
         A: FrameAllocator<Size4KiB>,
     {
-        let p4 = &mut self.level_4_table;
+        let p4 = self.page_table_walker.create_level_4_table(
+            self.top_table,
+            page.start_address(),
+            allocator,
+        )?;
         let p3 = self
             .page_table_walker
             .create_next_table(&mut p4[page.p4_index()], allocator)?;
@@ -74,7 +111,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     where
         A: FrameAllocator<Size4KiB>,
     {
-        let p4 = &mut self.level_4_table;
+        let p4 = self.page_table_walker.create_level_4_table(
+            self.top_table,
+            page.start_address(),
+            allocator,
+        )?;
         let p3 = self
             .page_table_walker
             .create_next_table(&mut p4[page.p4_index()], allocator)?;
@@ -102,7 +143,11 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     where
         A: FrameAllocator<Size4KiB>,
     {
-        let p4 = &mut self.level_4_table;
+        let p4 = self.page_table_walker.create_level_4_table(
+            self.top_table,
+            page.start_address(),
+            allocator,
+        )?;
         let p3 = self
             .page_table_walker
             .create_next_table(&mut p4[page.p4_index()], allocator)?;
@@ -122,6 +167,116 @@ impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
     }
 }
 
+impl<'a, P: PhysToVirt> MappedPageTable<'a, P> {
+    /// Helper function for implementing `Mapper::map_range`, which walks to the table of the
+    /// leaf entries once for all pages that share it.
+    fn map_range_inner<S, A>(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, (MapToError<S>, MapperFlushRange<S>)>
+    where
+        S: PageSize,
+        A: FrameAllocator<Size4KiB>,
+    {
+        assert_range_lengths(pages, frames);
+        let top_level = self.top_level();
+        let level = leaf_level::<S>();
+        let flags = match level {
+            PageTableLevel::One => flags,
+            _ => flags | PageTableFlags::HUGE_PAGE,
+        };
+
+        let (mut page, mut frame) = (pages.start, frames.start);
+        while page < pages.end {
+            let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+            let table = match self.page_table_walker.create_leaf_table(
+                self.top_table,
+                page.start_address(),
+                level,
+                allocator,
+            ) {
+                Ok(table) => table,
+                Err(PageTableCreateError::MappedToHugePage) => {
+                    return Err((MapToError::ParentEntryHugePage, done))
+                }
+                Err(PageTableCreateError::FrameAllocationFailed) => {
+                    return Err((MapToError::FrameAllocationFailed, done))
+                }
+            };
+
+            // map all pages of the range that are in this table
+            loop {
+                let entry = &mut table[page.start_address().page_table_index(level)];
+                if !entry.is_unused() {
+                    let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+                    return Err((MapToError::PageAlreadyMapped(frame), done));
+                }
+                entry.set_addr(frame.start_address(), flags);
+                page = next_page(page, top_level);
+                frame += 1;
+                if page == pages.end || u16::from(page.start_address().page_table_index(level)) == 0
+                {
+                    break;
+                }
+            }
+        }
+        Ok(MapperFlushRange::new(pages, top_level))
+    }
+
+    /// Helper function for implementing `Mapper::unmap_range`, see
+    /// [`map_range_inner`](MappedPageTable::map_range_inner).
+    fn unmap_range_inner<S: PageSize>(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, (UnmapError, MapperFlushRange<S>)> {
+        let top_level = self.top_level();
+        let level = leaf_level::<S>();
+
+        let mut page = pages.start;
+        while page < pages.end {
+            let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+            let table = match self.page_table_walker.leaf_table_mut(
+                self.top_table,
+                page.start_address(),
+                level,
+            ) {
+                Ok(table) => table,
+                Err(err) => return Err((err.into(), done)),
+            };
+
+            loop {
+                let entry = &mut table[page.start_address().page_table_index(level)];
+                let flags = entry.flags();
+                let error = if !flags.contains(PageTableFlags::PRESENT) {
+                    Some(UnmapError::PageNotMapped)
+                } else if flags.contains(PageTableFlags::HUGE_PAGE)
+                    != (level != PageTableLevel::One)
+                {
+                    Some(UnmapError::ParentEntryHugePage)
+                } else if PhysFrame::<S>::from_start_address(entry.addr()).is_err() {
+                    Some(UnmapError::InvalidFrameAddress(entry.addr()))
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+                    return Err((error, done));
+                }
+                entry.set_unused();
+                page = next_page(page, top_level);
+                if page == pages.end || u16::from(page.start_address().page_table_index(level)) == 0
+                {
+                    break;
+                }
+            }
+        }
+        Ok(MapperFlushRange::new(pages, top_level))
+    }
+}
+
 impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
     #[inline]
     unsafe fn map_to<A>(
@@ -137,11 +292,35 @@ impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
         self.map_to_1gib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+        frames: PhysFrameRange<Size1GiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size1GiB>, (MapToError<Size1GiB>, MapperFlushRange<Size1GiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.map_range_inner(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+    ) -> Result<MapperFlushRange<Size1GiB>, (UnmapError, MapperFlushRange<Size1GiB>)> {
+        self.unmap_range_inner(pages)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size1GiB>,
     ) -> Result<(PhysFrame<Size1GiB>, MapperFlush<Size1GiB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -168,7 +347,9 @@ impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
         page: Page<Size1GiB>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size1GiB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -182,7 +363,9 @@ impl<'a, P: PhysToVirt> Mapper<Size1GiB> for MappedPageTable<'a, P> {
     }
 
     fn translate_page(&self, page: Page<Size1GiB>) -> Result<PhysFrame<Size1GiB>, TranslateError> {
-        let p4 = &self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table(self.top_table, page.start_address())?;
         let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
 
         let p3_entry = &p3[page.p3_index()];
@@ -211,11 +394,35 @@ impl<'a, P: PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, P> {
         self.map_to_2mib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+        frames: PhysFrameRange<Size2MiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size2MiB>, (MapToError<Size2MiB>, MapperFlushRange<Size2MiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.map_range_inner(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+    ) -> Result<MapperFlushRange<Size2MiB>, (UnmapError, MapperFlushRange<Size2MiB>)> {
+        self.unmap_range_inner(pages)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size2MiB>,
     ) -> Result<(PhysFrame<Size2MiB>, MapperFlush<Size2MiB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -245,7 +452,9 @@ impl<'a, P: PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, P> {
         page: Page<Size2MiB>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size2MiB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -263,7 +472,9 @@ impl<'a, P: PhysToVirt> Mapper<Size2MiB> for MappedPageTable<'a, P> {
     }
 
     fn translate_page(&self, page: Page<Size2MiB>) -> Result<PhysFrame<Size2MiB>, TranslateError> {
-        let p4 = &self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table(self.top_table, page.start_address())?;
         let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
         let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
 
@@ -293,11 +504,35 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
         self.map_to_4kib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        frames: PhysFrameRange<Size4KiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size4KiB>, (MapToError<Size4KiB>, MapperFlushRange<Size4KiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.map_range_inner(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+    ) -> Result<MapperFlushRange<Size4KiB>, (UnmapError, MapperFlushRange<Size4KiB>)> {
+        self.unmap_range_inner(pages)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size4KiB>,
     ) -> Result<(PhysFrame<Size4KiB>, MapperFlush<Size4KiB>), UnmapError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -324,7 +559,9 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
         page: Page<Size4KiB>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size4KiB>, FlagUpdateError> {
-        let p4 = &mut self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table_mut(self.top_table, page.start_address())?;
         let p3 = self
             .page_table_walker
             .next_table_mut(&mut p4[page.p4_index()])?;
@@ -345,7 +582,9 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
     }
 
     fn translate_page(&self, page: Page<Size4KiB>) -> Result<PhysFrame<Size4KiB>, TranslateError> {
-        let p4 = &self.level_4_table;
+        let p4 = self
+            .page_table_walker
+            .level_4_table(self.top_table, page.start_address())?;
         let p3 = self.page_table_walker.next_table(&p4[page.p4_index()])?;
         let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
         let p1 = self.page_table_walker.next_table(&p2[page.p2_index()])?;
@@ -364,7 +603,13 @@ impl<'a, P: PhysToVirt> Mapper<Size4KiB> for MappedPageTable<'a, P> {
 impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
     #[allow(clippy::inconsistent_digit_grouping)]
     fn translate(&self, addr: VirtAddr) -> TranslateResult {
-        let p4 = &self.level_4_table;
+        let p4 = match self.page_table_walker.level_4_table(self.top_table, addr) {
+            Ok(page_table) => page_table,
+            Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
+            Err(PageTableWalkError::MappedToHugePage) => {
+                panic!("level 5 entry has huge page bit set")
+            }
+        };
         let p3 = match self.page_table_walker.next_table(&p4[addr.p4_index()]) {
             Ok(page_table) => page_table,
             Err(PageTableWalkError::NotMapped) => return TranslateResult::PageNotMapped,
@@ -409,12 +654,104 @@ impl<'a, P: PhysToVirt> MapperAllSizes for MappedPageTable<'a, P> {
 #[derive(Debug)]
 struct PageTableWalker<P: PhysToVirt> {
     phys_to_virt: P,
+    top_level: PageTableLevel,
 }
 
 impl<P: PhysToVirt> PageTableWalker<P> {
     #[inline]
-    pub unsafe fn new(phys_to_virt: P) -> Self {
-        Self { phys_to_virt }
+    pub unsafe fn new(phys_to_virt: P, top_level: PageTableLevel) -> Self {
+        Self {
+            phys_to_virt,
+            top_level,
+        }
+    }
+
+    /// Internal helper function to get a reference to the level 4 table of the given address,
+    /// which is the top-level table itself unless 5-level paging is used.
+    #[inline]
+    fn level_4_table<'b>(
+        &self,
+        top_table: &'b PageTable,
+        addr: VirtAddr,
+    ) -> Result<&'b PageTable, PageTableWalkError> {
+        match self.top_level {
+            PageTableLevel::Five => self.next_table(&top_table[addr.p5_index()]),
+            _ => Ok(top_table),
+        }
+    }
+
+    /// Internal helper function to get a mutable reference to the level 4 table of the given
+    /// address, see [`level_4_table`](PageTableWalker::level_4_table).
+    #[inline]
+    fn level_4_table_mut<'b>(
+        &self,
+        top_table: &'b mut PageTable,
+        addr: VirtAddr,
+    ) -> Result<&'b mut PageTable, PageTableWalkError> {
+        match self.top_level {
+            PageTableLevel::Five => self.next_table_mut(&mut top_table[addr.p5_index()]),
+            _ => Ok(top_table),
+        }
+    }
+
+    /// Internal helper function to get the level 4 table of the given address, creating it
+    /// with 5-level paging if needed, see [`create_next_table`](PageTableWalker::create_next_table).
+    #[inline]
+    fn create_level_4_table<'b, A>(
+        &self,
+        top_table: &'b mut PageTable,
+        addr: VirtAddr,
+        allocator: &mut A,
+    ) -> Result<&'b mut PageTable, PageTableCreateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        match self.top_level {
+            PageTableLevel::Five => {
+                self.create_next_table(&mut top_table[addr.p5_index()], allocator)
+            }
+            _ => Ok(top_table),
+        }
+    }
+
+    /// Internal helper function to get a mutable reference to the table of the given level
+    /// for the given address.
+    fn leaf_table_mut<'b>(
+        &self,
+        top_table: &'b mut PageTable,
+        addr: VirtAddr,
+        level: PageTableLevel,
+    ) -> Result<&'b mut PageTable, PageTableWalkError> {
+        let mut table = self.level_4_table_mut(top_table, addr)?;
+        let mut current = PageTableLevel::Four;
+        while current != level {
+            let entry = &mut table[addr.page_table_index(current)];
+            table = self.next_table_mut(entry)?;
+            current = current.next_lower_level().unwrap();
+        }
+        Ok(table)
+    }
+
+    /// Internal helper function to get the table of the given level for the given address,
+    /// creating the tables on the way if needed.
+    fn create_leaf_table<'b, A>(
+        &self,
+        top_table: &'b mut PageTable,
+        addr: VirtAddr,
+        level: PageTableLevel,
+        allocator: &mut A,
+    ) -> Result<&'b mut PageTable, PageTableCreateError>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let mut table = self.create_level_4_table(top_table, addr, allocator)?;
+        let mut current = PageTableLevel::Four;
+        while current != level {
+            let entry = &mut table[addr.page_table_index(current)];
+            table = self.create_next_table(entry, allocator)?;
+            current = current.next_lower_level().unwrap();
+        }
+        Ok(table)
     }
 
     /// Internal helper function to get a reference to the page table of the next level.
@@ -494,6 +831,16 @@ impl<P: PhysToVirt> PageTableWalker<P> {
     }
 }
 
+/// Returns the level of the page table entries that map pages of size `S`.
+#[inline]
+fn leaf_level<S: PageSize>() -> PageTableLevel {
+    match S::SIZE {
+        Size1GiB::SIZE => PageTableLevel::Three,
+        Size2MiB::SIZE => PageTableLevel::Two,
+        _ => PageTableLevel::One,
+    }
+}
+
 #[derive(Debug)]
 enum PageTableWalkError {
     NotMapped,
@@ -594,3 +941,130 @@ where
         self(phys_frame)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Allocates the page tables from an array, using the virtual addresses of the test as
+    /// physical addresses.
+    struct TableAllocator<'a> {
+        tables: core::slice::IterMut<'a, PageTable>,
+    }
+
+    unsafe impl FrameAllocator<Size4KiB> for TableAllocator<'_> {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            let table = self.tables.next()?;
+            Some(PhysFrame::containing_address(PhysAddr::new(
+                table as *mut PageTable as u64,
+            )))
+        }
+    }
+
+    #[test]
+    fn five_level_walk() {
+        let mut tables: Vec<PageTable> = (0..5).map(|_| PageTable::new()).collect();
+        let (level_5_table, tables) = tables.split_first_mut().unwrap();
+        let mut allocator = TableAllocator {
+            tables: tables.iter_mut(),
+        };
+        let phys_to_virt = |frame: PhysFrame| frame.start_address().as_u64() as *mut PageTable;
+        let mut mapper = unsafe { MappedPageTable::new_5level(level_5_table, phys_to_virt) };
+        assert_eq!(mapper.top_level(), PageTableLevel::Five);
+
+        // only canonical with 5-level paging
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new_la57(0x00ab_cdef_1234_5000));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, &mut allocator) }
+            .unwrap()
+            .ignore();
+        assert_eq!(mapper.translate_page(page).unwrap(), frame);
+        assert_eq!(
+            mapper.translate_addr(page.start_address().add_la57(0x678)),
+            Some(PhysAddr::new(0x8000_0678))
+        );
+        assert!(!mapper.level_4_table()[page.p5_index()].is_unused());
+
+        let (unmapped, flush) = mapper.unmap(page).unwrap();
+        flush.ignore();
+        assert_eq!(unmapped, frame);
+        assert_eq!(mapper.translate_addr(page.start_address()), None);
+    }
+
+    #[test]
+    fn map_range() {
+        let mut tables: Vec<PageTable> = (0..7).map(|_| PageTable::new()).collect();
+        let (level_4_table, tables) = tables.split_first_mut().unwrap();
+        let mut allocator = TableAllocator {
+            tables: tables.iter_mut(),
+        };
+        let phys_to_virt = |frame: PhysFrame| frame.start_address().as_u64() as *mut PageTable;
+        let mut mapper = unsafe { MappedPageTable::new(level_4_table, phys_to_virt) };
+
+        // 600 pages, which need two level 1 tables
+        let start = Page::<Size4KiB>::containing_address(VirtAddr::new(0x4000_0000));
+        let pages = Page::range(start, start + 600);
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        let frames = PhysFrame::range(frame, frame + 600);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let flush = unsafe { mapper.map_range(pages, frames, flags, &mut allocator) }.unwrap();
+        assert_eq!(flush.pages(), pages);
+        flush.ignore();
+        assert_eq!(mapper.translate_page(start + 599).unwrap(), frame + 599);
+
+        // the pages before the first mapped page are mapped and returned for flushing
+        let before = Page::range(start - 10, start + 1);
+        let frames = PhysFrame::range(frame - 10, frame + 1);
+        let (err, flush) = unsafe { mapper.map_range(before, frames, flags, &mut allocator) }
+            .err()
+            .unwrap();
+        assert!(matches!(err, MapToError::PageAlreadyMapped(f) if f == frame));
+        assert_eq!(flush.pages(), Page::range(start - 10, start));
+        flush.ignore();
+
+        mapper
+            .unmap_range(Page::range(start - 10, start + 600))
+            .unwrap()
+            .ignore();
+        assert!(mapper.translate_page(start + 300).is_err());
+        let (err, flush) = mapper.unmap_range(pages).err().unwrap();
+        assert!(matches!(err, UnmapError::PageNotMapped));
+        assert!(flush.pages().is_empty());
+        flush.ignore();
+    }
+
+    #[test]
+    fn five_level_map_range_crosses_bit_47() {
+        let mut tables: Vec<PageTable> = (0..8).map(|_| PageTable::new()).collect();
+        let (level_5_table, tables) = tables.split_first_mut().unwrap();
+        let mut allocator = TableAllocator {
+            tables: tables.iter_mut(),
+        };
+        let phys_to_virt = |frame: PhysFrame| frame.start_address().as_u64() as *mut PageTable;
+        let mut mapper = unsafe { MappedPageTable::new_5level(level_5_table, phys_to_virt) };
+
+        // two pages on each side of the end of the 4-level lower half
+        let start = Page::<Size4KiB>::containing_address(VirtAddr::new_la57(0x7fff_ffff_e000));
+        let pages = Page::range(start, start.add_la57(4));
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        let frames = PhysFrame::range(frame, frame + 4);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let flush = unsafe { mapper.map_range(pages, frames, flags, &mut allocator) }.unwrap();
+        assert_eq!(flush.pages(), pages);
+        assert_eq!(flush.top_level(), PageTableLevel::Five);
+        flush.ignore();
+        for (i, page) in pages.iter_la57().enumerate() {
+            assert_eq!(mapper.translate_page(page).unwrap(), frame + i as u64);
+        }
+        assert_eq!(
+            mapper.translate_addr(VirtAddr::new_la57(0x8000_0000_1234)),
+            Some(PhysAddr::new(0x8000_3234))
+        );
+
+        mapper.unmap_range(pages).unwrap().ignore();
+        assert!(pages
+            .iter_la57()
+            .all(|page| mapper.translate_page(page).is_err()));
+    }
+}
@@ -2,15 +2,27 @@
 
 pub use self::mapped_page_table::{MappedPageTable, PhysToVirt};
 #[cfg(target_arch = "x86_64")]
-pub use self::{offset_page_table::OffsetPageTable, recursive_page_table::RecursivePageTable};
+pub use self::{
+    ept_mapper::{EptMapper, GuestMapper},
+    npt_mapper::NptMapper,
+    offset_page_table::OffsetPageTable,
+    recursive_page_table::RecursivePageTable,
+};
 
 use crate::structures::paging::{
-    frame_alloc::FrameAllocator, page_table::PageTableFlags, Page, PageSize, PhysFrame, Size1GiB,
-    Size2MiB, Size4KiB,
+    frame::PhysFrameRange,
+    frame_alloc::FrameAllocator,
+    page::PageRange,
+    page_table::{PageTableFlags, PageTableLevel},
+    Page, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
 };
 use crate::{PhysAddr, VirtAddr};
 
+#[cfg(target_arch = "x86_64")]
+mod ept_mapper;
 mod mapped_page_table;
+#[cfg(target_arch = "x86_64")]
+mod npt_mapper;
 mod offset_page_table;
 mod recursive_page_table;
 
@@ -81,7 +93,8 @@ pub trait Mapper<S: PageSize> {
     /// Creates a new mapping in the page table.
     ///
     /// This function might need additional physical frames to create new page tables. These
-    /// frames are allocated from the `allocator` argument. At most three frames are required.
+    /// frames are allocated from the `allocator` argument. At most three frames are required, or
+    /// four with 5-level paging.
     ///
     /// ## Safety
     ///
@@ -148,6 +161,60 @@ pub trait Mapper<S: PageSize> {
     /// error otherwise.
     fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError>;
 
+    /// Maps the given range of pages to the given range of frames with the same flags.
+    ///
+    /// Returns a single flush for the whole range. Implementations walk the page tables once
+    /// per table instead of once per page where possible, the default implementation calls
+    /// [`map_to`](Mapper::map_to) for each page.
+    ///
+    /// If a page can't be mapped, the error is returned together with the flush of the pages
+    /// before it, which stay mapped.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the ranges don't have the same length.
+    ///
+    /// ## Safety
+    ///
+    /// This function invokes [`map_to`](Mapper::map_to) or does the same for each page, so all
+    /// safety requirements of it also apply for this function.
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<S>,
+        frames: PhysFrameRange<S>,
+        flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlushRange<S>, (MapToError<S>, MapperFlushRange<S>)>
+    where
+        Self: Sized,
+        A: FrameAllocator<Size4KiB>,
+    {
+        map_range_per_page(
+            self,
+            pages,
+            frames,
+            flags,
+            frame_allocator,
+            PageTableLevel::Four,
+        )
+    }
+
+    /// Removes the mappings of the given range of pages.
+    ///
+    /// Returns a single flush for the whole range. Implementations walk the page tables once
+    /// per table instead of once per page where possible, the default implementation calls
+    /// [`unmap`](Mapper::unmap) for each page. Note that no page tables or frames are
+    /// deallocated.
+    ///
+    /// If a page can't be unmapped, the error is returned together with the flush of the pages
+    /// before it, which are already unmapped.
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<S>,
+    ) -> Result<MapperFlushRange<S>, (UnmapError, MapperFlushRange<S>)> {
+        unmap_range_per_page(self, pages, PageTableLevel::Four)
+    }
+
     /// Maps the given frame to the virtual page with the same address.
     ///
     /// ## Safety
@@ -200,6 +267,147 @@ impl<S: PageSize> MapperFlush<S> {
     pub fn ignore(self) {}
 }
 
+/// This type represents a range of pages whose mappings have changed in the page table.
+///
+/// It is returned from [`Mapper::map_range`] and [`Mapper::unmap_range`] instead of one
+/// [`MapperFlush`] per page.
+#[derive(Debug)]
+#[must_use = "Page Table changes must be flushed or ignored."]
+pub struct MapperFlushRange<S: PageSize>(PageRange<S>, PageTableLevel);
+
+impl<S: PageSize> MapperFlushRange<S> {
+    /// Create a new flush promise for the given range in an address space with the given
+    /// top-level table
+    #[inline]
+    fn new(pages: PageRange<S>, top_level: PageTableLevel) -> Self {
+        MapperFlushRange(pages, top_level)
+    }
+
+    /// Returns the range of pages whose mappings have changed.
+    ///
+    /// With 5-level paging, the pages of the range have to be iterated with
+    /// [`PageRange::iter_la57`].
+    #[inline]
+    pub fn pages(&self) -> PageRange<S> {
+        self.0
+    }
+
+    /// Returns the level of the top-level table of the address space that contains the pages,
+    /// i.e. [`PageTableLevel::Five`] if the pages have to be iterated with
+    /// [`PageRange::iter_la57`].
+    #[inline]
+    pub fn top_level(&self) -> PageTableLevel {
+        self.1
+    }
+
+    /// Flush the pages from the TLB to ensure that the newest mappings are used.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn flush(self) {
+        let mut page = self.0.start;
+        while page < self.0.end {
+            crate::instructions::tlb::flush(page.start_address());
+            page = next_page(page, self.1);
+        }
+    }
+
+    /// Flush the whole TLB by reloading CR3, which is cheaper than flushing large ranges page
+    /// by page. Note that this doesn't flush global pages.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn flush_all(self) {
+        crate::instructions::tlb::flush_all();
+    }
+
+    /// Don't flush the TLB and silence the “must be used” warning.
+    #[inline]
+    pub fn ignore(self) {}
+}
+
+/// Returns the page after the given page in an address space with the given top-level table.
+#[inline]
+fn next_page<S: PageSize>(page: Page<S>, top_level: PageTableLevel) -> Page<S> {
+    match top_level {
+        PageTableLevel::Five => page.add_la57(1),
+        _ => page + 1,
+    }
+}
+
+/// Implements `Mapper::map_range` by calling `map_to` for each page.
+#[inline]
+unsafe fn map_range_per_page<M, S, A>(
+    mapper: &mut M,
+    pages: PageRange<S>,
+    frames: PhysFrameRange<S>,
+    flags: PageTableFlags,
+    frame_allocator: &mut A,
+    top_level: PageTableLevel,
+) -> Result<MapperFlushRange<S>, (MapToError<S>, MapperFlushRange<S>)>
+where
+    M: Mapper<S>,
+    S: PageSize,
+    A: FrameAllocator<Size4KiB>,
+{
+    assert_range_lengths(pages, frames);
+    let (mut page, mut frame) = (pages.start, frames.start);
+    while page < pages.end {
+        match mapper.map_to(page, frame, flags, frame_allocator) {
+            Ok(flush) => flush.ignore(),
+            Err(err) => {
+                let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+                return Err((err, done));
+            }
+        }
+        page = next_page(page, top_level);
+        frame += 1;
+    }
+    Ok(MapperFlushRange::new(pages, top_level))
+}
+
+/// Implements `Mapper::unmap_range` by calling `unmap` for each page.
+#[inline]
+fn unmap_range_per_page<M, S>(
+    mapper: &mut M,
+    pages: PageRange<S>,
+    top_level: PageTableLevel,
+) -> Result<MapperFlushRange<S>, (UnmapError, MapperFlushRange<S>)>
+where
+    M: Mapper<S> + ?Sized,
+    S: PageSize,
+{
+    let mut page = pages.start;
+    while page < pages.end {
+        match mapper.unmap(page) {
+            Ok((_, flush)) => flush.ignore(),
+            Err(err) => {
+                let done = MapperFlushRange::new(Page::range(pages.start, page), top_level);
+                return Err((err, done));
+            }
+        }
+        page = next_page(page, top_level);
+    }
+    Ok(MapperFlushRange::new(pages, top_level))
+}
+
+/// Panics if the given ranges don't have the same length.
+#[inline]
+fn assert_range_lengths<S: PageSize>(pages: PageRange<S>, frames: PhysFrameRange<S>) {
+    let page_count = if pages.is_empty() {
+        0
+    } else {
+        pages.end - pages.start
+    };
+    let frame_count = if frames.is_empty() {
+        0
+    } else {
+        frames.end - frames.start
+    };
+    assert_eq!(
+        page_count, frame_count,
+        "page and frame ranges must have the same length"
+    );
+}
+
 /// This error is returned from `map_to` and similar methods.
 #[derive(Debug)]
 pub enum MapToError<S: PageSize> {
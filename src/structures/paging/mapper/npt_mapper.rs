@@ -0,0 +1,230 @@
+use crate::structures::paging::{
+    frame::PhysFrame,
+    frame_alloc::FrameAllocator,
+    mapper::*,
+    page::{Page, Size1GiB, Size2MiB, Size4KiB},
+    page_table::{PageTable, PageTableFlags},
+};
+
+/// A mapper for the nested page tables of AMD SVM, which translate guest-physical to
+/// host-physical addresses.
+///
+/// Nested page tables use the format of ordinary page tables, so this type wraps an
+/// [`OffsetPageTable`] and maps guest-physical frames instead of pages. The processor treats
+/// all accesses through nested page tables as user accesses, so `USER_ACCESSIBLE` is set in
+/// the mapped entries and in the entries of the tables on the way.
+///
+/// The level 4 table is activated with
+/// [`VmcbControlArea::enable_nested_paging`](crate::structures::vmcb::VmcbControlArea::enable_nested_paging).
+/// After changing or removing an existing mapping, the cached translations of the guest have
+/// to be flushed, see [`svm::flush_guest_tlb`](crate::svm::flush_guest_tlb).
+#[derive(Debug)]
+pub struct NptMapper<'a> {
+    inner: OffsetPageTable<'a>,
+    phys_offset: VirtAddr,
+}
+
+impl<'a> NptMapper<'a> {
+    /// Creates a new `NptMapper` that uses the given offset for converting host-physical to
+    /// virtual addresses.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must guarantee that the passed
+    /// `phys_offset` is correct. Also, the passed `level_4_table` must point to the level 4
+    /// table of a valid nested page table hierarchy. Otherwise this function might break
+    /// memory safety, e.g. by writing to an illegal memory location.
+    #[inline]
+    pub unsafe fn new(level_4_table: &'a mut PageTable, phys_offset: VirtAddr) -> Self {
+        NptMapper {
+            inner: OffsetPageTable::new(level_4_table, phys_offset),
+            phys_offset,
+        }
+    }
+
+    /// Returns a mutable reference to the wrapped level 4 table.
+    #[inline]
+    pub fn level_4_table(&mut self) -> &mut PageTable {
+        self.inner.level_4_table()
+    }
+
+    /// Maps the given guest-physical frame to the given host-physical frame.
+    ///
+    /// This function might need additional physical frames to create new nested page tables.
+    /// These frames are allocated from the `allocator` argument. At most three frames are
+    /// required.
+    ///
+    /// ## Safety
+    ///
+    /// The guest gets access to the host frame with the given flags, so the caller must ensure
+    /// that the frame isn't used by the host in a way that the guest can break.
+    pub unsafe fn map_to<S, A>(
+        &mut self,
+        guest: PhysFrame<S>,
+        host: PhysFrame<S>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<(), MapToError<S>>
+    where
+        S: PageSize,
+        A: FrameAllocator<Size4KiB>,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        let flags = flags | PageTableFlags::USER_ACCESSIBLE;
+        self.inner
+            .map_to(guest_page(guest), host, flags, allocator)?
+            // the flush of a host TLB entry doesn't affect the guest
+            .ignore();
+        self.set_parents_user_accessible(guest.start_address(), leaf_level::<S>());
+        Ok(())
+    }
+
+    /// Removes a mapping and returns the host frame that used to be mapped.
+    ///
+    /// Note that no nested page tables or frames are deallocated.
+    pub fn unmap<S>(&mut self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, UnmapError>
+    where
+        S: PageSize,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        let (host, flush) = self.inner.unmap(guest_page(guest))?;
+        flush.ignore();
+        Ok(host)
+    }
+
+    /// Updates the flags of an existing mapping.
+    ///
+    /// ## Safety
+    ///
+    /// This method is unsafe because it can give the guest new permissions for the host frame,
+    /// see [`map_to`](NptMapper::map_to).
+    pub unsafe fn update_flags<S>(
+        &mut self,
+        guest: PhysFrame<S>,
+        flags: PageTableFlags,
+    ) -> Result<(), FlagUpdateError>
+    where
+        S: PageSize,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        let flags = flags | PageTableFlags::USER_ACCESSIBLE;
+        self.inner.update_flags(guest_page(guest), flags)?.ignore();
+        Ok(())
+    }
+
+    /// Returns the host frame that the given guest frame is mapped to.
+    ///
+    /// This function assumes that the guest frame is mapped with size `S` and returns an error
+    /// otherwise.
+    pub fn translate_frame<S>(&self, guest: PhysFrame<S>) -> Result<PhysFrame<S>, TranslateError>
+    where
+        S: PageSize,
+        OffsetPageTable<'a>: Mapper<S>,
+    {
+        self.inner.translate_page(guest_page(guest))
+    }
+
+    /// Translates the given guest-physical address to the host-physical address that it maps
+    /// to.
+    ///
+    /// Returns `None` if there is no valid mapping for the given address.
+    pub fn translate_addr(&self, addr: PhysAddr) -> Option<PhysAddr> {
+        if addr.as_u64() >= GUEST_ADDRESS_LIMIT {
+            return None;
+        }
+        self.inner
+            .translate_addr(VirtAddr::new_truncate(addr.as_u64()))
+    }
+
+    /// Sets `USER_ACCESSIBLE` in the entries that lead to the entry of the given level for the
+    /// given guest-physical address, which the `OffsetPageTable` creates without it.
+    fn set_parents_user_accessible(&mut self, addr: PhysAddr, leaf_level: u8) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new_truncate(addr.as_u64()));
+        let indices = [page.p4_index(), page.p3_index(), page.p2_index()];
+        let phys_offset = self.phys_offset;
+        let mut table = self.inner.level_4_table();
+        for &index in &indices[..4 - usize::from(leaf_level)] {
+            let entry = &mut table[index];
+            entry.set_flags(entry.flags() | PageTableFlags::USER_ACCESSIBLE);
+            let next = phys_offset + entry.addr().as_u64();
+            // the entry was just created or walked by the `OffsetPageTable`
+            table = unsafe { &mut *next.as_mut_ptr::<PageTable>() };
+        }
+    }
+}
+
+/// The first guest-physical address that isn't translated by 4-level nested page tables.
+const GUEST_ADDRESS_LIMIT: u64 = 1 << 48;
+
+/// Returns the page with the page table indices of the given guest-physical frame.
+///
+/// ## Panics
+///
+/// Panics if the frame isn't below 256TiB, which 4-level nested page tables can't translate.
+#[inline]
+fn guest_page<S: PageSize>(guest: PhysFrame<S>) -> Page<S> {
+    let addr = guest.start_address().as_u64();
+    assert!(
+        addr < GUEST_ADDRESS_LIMIT,
+        "guest-physical address {:#x} is not translated by 4-level page tables",
+        addr
+    );
+    Page::containing_address(VirtAddr::new_truncate(addr))
+}
+
+/// Returns the page table level of the entries that map frames of size `S`.
+#[inline]
+fn leaf_level<S: PageSize>() -> u8 {
+    match S::SIZE {
+        Size1GiB::SIZE => 3,
+        Size2MiB::SIZE => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Allocates the page tables from an array, using the virtual addresses of the test as
+    /// host-physical addresses.
+    struct TableAllocator<'a> {
+        tables: core::slice::IterMut<'a, PageTable>,
+    }
+
+    unsafe impl FrameAllocator<Size4KiB> for TableAllocator<'_> {
+        fn allocate_frame(&mut self) -> Option<PhysFrame> {
+            let table = self.tables.next()?;
+            Some(PhysFrame::containing_address(PhysAddr::new(
+                table as *mut PageTable as u64,
+            )))
+        }
+    }
+
+    #[test]
+    fn npt_mapper() {
+        let mut tables: Vec<PageTable> = (0..4).map(|_| PageTable::new()).collect();
+        let (level_4_table, tables) = tables.split_first_mut().unwrap();
+        let mut allocator = TableAllocator {
+            tables: tables.iter_mut(),
+        };
+        let mut mapper = unsafe { NptMapper::new(level_4_table, VirtAddr::new(0)) };
+
+        // above the canonical half of virtual addresses, but translated by nested paging
+        let guest = PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(0x8000_1234_5000));
+        let host = PhysFrame::containing_address(PhysAddr::new(0x8000_0000));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(guest, host, flags, &mut allocator) }.unwrap();
+        assert_eq!(mapper.translate_frame(guest).unwrap(), host);
+        assert_eq!(
+            mapper.translate_addr(PhysAddr::new(0x8000_1234_5678)),
+            Some(PhysAddr::new(0x8000_0678))
+        );
+        let entry = &mapper.level_4_table()[256];
+        assert!(entry.flags().contains(PageTableFlags::USER_ACCESSIBLE));
+
+        assert_eq!(mapper.unmap(guest).unwrap(), host);
+        assert_eq!(mapper.translate_addr(PhysAddr::new(0x8000_1234_5678)), None);
+        assert_eq!(mapper.translate_addr(PhysAddr::new(1 << 48)), None);
+    }
+}
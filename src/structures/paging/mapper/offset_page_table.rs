@@ -1,6 +1,11 @@
 #![cfg(target_arch = "x86_64")]
 
-use crate::structures::paging::{frame::PhysFrame, mapper::*, page_table::PageTable};
+use crate::structures::paging::{
+    frame::{PhysFrame, PhysFrameRange},
+    mapper::*,
+    page::PageRange,
+    page_table::{PageTable, PageTableLevel},
+};
 
 /// A Mapper implementation that requires that the complete physically memory is mapped at some
 /// offset in the virtual address space.
@@ -29,23 +34,61 @@ impl<'a> OffsetPageTable<'a> {
     pub unsafe fn new(level_4_table: &'a mut PageTable, phys_offset: VirtAddr) -> Self {
         let phys_offset = PhysOffset {
             offset: phys_offset,
+            top_level: PageTableLevel::Four,
         };
         Self {
             inner: MappedPageTable::new(level_4_table, phys_offset),
         }
     }
+
+    /// Creates a new `OffsetPageTable` for 5-level paging that uses the given offset for
+    /// converting virtual to physical addresses.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe for the same reasons as [`new`](OffsetPageTable::new), with
+    /// the passed `level_5_table` pointing to the level 5 page table of a valid page table
+    /// hierarchy.
+    #[inline]
+    pub unsafe fn new_5level(level_5_table: &'a mut PageTable, phys_offset: VirtAddr) -> Self {
+        let phys_offset = PhysOffset {
+            offset: phys_offset,
+            top_level: PageTableLevel::Five,
+        };
+        Self {
+            inner: MappedPageTable::new_5level(level_5_table, phys_offset),
+        }
+    }
+
+    /// Returns a mutable reference to the wrapped top-level `PageTable` instance, which is the
+    /// level 5 table with 5-level paging.
+    #[inline]
+    pub fn level_4_table(&mut self) -> &mut PageTable {
+        self.inner.level_4_table()
+    }
+
+    /// Returns the level of the top-level table, i.e. [`PageTableLevel::Five`] with 5-level
+    /// paging and [`PageTableLevel::Four`] otherwise.
+    #[inline]
+    pub fn top_level(&self) -> PageTableLevel {
+        self.inner.top_level()
+    }
 }
 
 #[derive(Debug)]
 struct PhysOffset {
     offset: VirtAddr,
+    top_level: PageTableLevel,
 }
 
 impl PhysToVirt for PhysOffset {
     #[inline]
     fn phys_to_virt(&self, frame: PhysFrame) -> *mut PageTable {
         let phys = frame.start_address().as_u64();
-        let virt = self.offset + phys;
+        let virt = match self.top_level {
+            PageTableLevel::Five => self.offset.add_la57(phys),
+            _ => self.offset + phys,
+        };
         virt.as_mut_ptr()
     }
 }
@@ -67,6 +110,28 @@ impl<'a> Mapper<Size1GiB> for OffsetPageTable<'a> {
         self.inner.map_to(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+        frames: PhysFrameRange<Size1GiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size1GiB>, (MapToError<Size1GiB>, MapperFlushRange<Size1GiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.inner.map_range(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+    ) -> Result<MapperFlushRange<Size1GiB>, (UnmapError, MapperFlushRange<Size1GiB>)> {
+        self.inner.unmap_range(pages)
+    }
+
     #[inline]
     fn unmap(
         &mut self,
@@ -105,6 +170,28 @@ impl<'a> Mapper<Size2MiB> for OffsetPageTable<'a> {
         self.inner.map_to(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+        frames: PhysFrameRange<Size2MiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size2MiB>, (MapToError<Size2MiB>, MapperFlushRange<Size2MiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.inner.map_range(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+    ) -> Result<MapperFlushRange<Size2MiB>, (UnmapError, MapperFlushRange<Size2MiB>)> {
+        self.inner.unmap_range(pages)
+    }
+
     #[inline]
     fn unmap(
         &mut self,
@@ -143,6 +230,28 @@ impl<'a> Mapper<Size4KiB> for OffsetPageTable<'a> {
         self.inner.map_to(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        frames: PhysFrameRange<Size4KiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size4KiB>, (MapToError<Size4KiB>, MapperFlushRange<Size4KiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        self.inner.map_range(pages, frames, flags, allocator)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+    ) -> Result<MapperFlushRange<Size4KiB>, (UnmapError, MapperFlushRange<Size4KiB>)> {
+        self.inner.unmap_range(pages)
+    }
+
     #[inline]
     fn unmap(
         &mut self,
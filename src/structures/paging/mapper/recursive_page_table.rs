@@ -3,12 +3,13 @@
 //! Access the page tables through a recursively mapped level 4 table.
 
 use super::*;
-use crate::registers::control::Cr3;
+use crate::registers::control::{Cr3, Cr4, Cr4Flags};
 use crate::structures::paging::PageTableIndex;
 use crate::structures::paging::{
+    frame::PhysFrameRange,
     frame_alloc::FrameAllocator,
-    page::NotGiantPageSize,
-    page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags},
+    page::{NotGiantPageSize, PageRange},
+    page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags, PageTableLevel},
     Page, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
 };
 use crate::VirtAddr;
@@ -25,31 +26,43 @@ use crate::VirtAddr;
 /// - To access a level 1 page table, we “loop” once, then use the level 4 index, then the
 ///   level 3 index, then the level 2 index.
 ///
+/// With 5-level paging (CR4.LA57), the recursive entry is in the level 5 table and all tables
+/// are reached by looping one more time.
+///
 /// This struct implements the `Mapper` trait.
 #[derive(Debug)]
 pub struct RecursivePageTable<'a> {
-    p4: &'a mut PageTable,
+    top_table: &'a mut PageTable,
     recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
 }
 
 impl<'a> RecursivePageTable<'a> {
-    /// Creates a new RecursivePageTable from the passed level 4 PageTable.
+    /// Creates a new RecursivePageTable from the passed level 4 PageTable, or from the level 5
+    /// PageTable if 5-level paging is enabled in CR4.
     ///
     /// The page table must be recursively mapped, that means:
     ///
     /// - The page table must have one recursive entry, i.e. an entry that points to the table
     ///   itself.
     ///     - The reference must use that “loop”, i.e. be of the form `0o_xxx_xxx_xxx_xxx_0000`
-    ///       where `xxx` is the recursive entry.
+    ///       where `xxx` is the recursive entry, with one more `xxx` for 5-level paging.
     /// - The page table must be active, i.e. the CR3 register must contain its physical address.
     ///
     /// Otherwise `Err(())` is returned.
     #[inline]
     pub fn new(table: &'a mut PageTable) -> Result<Self, ()> {
-        let page = Page::containing_address(VirtAddr::new(table as *const _ as u64));
-        let recursive_index = page.p4_index();
+        let addr = table as *const _ as u64;
+        let (page, recursive_index, top_level) = if Cr4::read().contains(Cr4Flags::L5_PAGING) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new_la57(addr));
+            (page, page.p5_index(), PageTableLevel::Five)
+        } else {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+            (page, page.p4_index(), PageTableLevel::Four)
+        };
 
-        if page.p3_index() != recursive_index
+        if page.p4_index() != recursive_index
+            || page.p3_index() != recursive_index
             || page.p2_index() != recursive_index
             || page.p1_index() != recursive_index
         {
@@ -60,8 +73,9 @@ impl<'a> RecursivePageTable<'a> {
         }
 
         Ok(RecursivePageTable {
-            p4: table,
+            top_table: table,
             recursive_index,
+            top_level,
         })
     }
 
@@ -73,8 +87,87 @@ impl<'a> RecursivePageTable<'a> {
     #[inline]
     pub unsafe fn new_unchecked(table: &'a mut PageTable, recursive_index: PageTableIndex) -> Self {
         RecursivePageTable {
-            p4: table,
+            top_table: table,
             recursive_index,
+            top_level: PageTableLevel::Four,
+        }
+    }
+
+    /// Creates a new RecursivePageTable from the passed level 5 PageTable without performing
+    /// any checks.
+    ///
+    /// ## Safety
+    ///
+    /// The `recursive_index` parameter must be the index of the recursively mapped entry and
+    /// 5-level paging must be enabled.
+    #[inline]
+    pub unsafe fn new_unchecked_5level(
+        table: &'a mut PageTable,
+        recursive_index: PageTableIndex,
+    ) -> Self {
+        RecursivePageTable {
+            top_table: table,
+            recursive_index,
+            top_level: PageTableLevel::Five,
+        }
+    }
+
+    /// Returns the level of the top-level table, i.e. [`PageTableLevel::Five`] with 5-level
+    /// paging and [`PageTableLevel::Four`] otherwise.
+    #[inline]
+    pub fn top_level(&self) -> PageTableLevel {
+        self.top_level
+    }
+
+    /// Internal helper function to get the level 4 table of the given page, which is the
+    /// top-level table itself unless 5-level paging is used.
+    ///
+    /// Returns `None` if the level 5 entry of the page is unused.
+    #[inline]
+    fn p4<S: PageSize>(&self, page: Page<S>) -> Option<&PageTable> {
+        match self.top_level {
+            PageTableLevel::Five => {
+                if self.top_table[page.p5_index()].is_unused() {
+                    return None;
+                }
+                Some(unsafe { &*(p4_ptr(page, self.recursive_index, self.top_level)) })
+            }
+            _ => Some(self.top_table),
+        }
+    }
+
+    /// Internal helper function to get the level 4 table of the given page mutably, see
+    /// [`p4`](RecursivePageTable::p4).
+    #[inline]
+    fn p4_mut<S: PageSize>(&mut self, page: Page<S>) -> Option<&mut PageTable> {
+        match self.top_level {
+            PageTableLevel::Five => {
+                if self.top_table[page.p5_index()].is_unused() {
+                    return None;
+                }
+                Some(unsafe { &mut *(p4_ptr(page, self.recursive_index, self.top_level)) })
+            }
+            _ => Some(self.top_table),
+        }
+    }
+
+    /// Internal helper function to get the level 4 table of the given page, creating it with
+    /// 5-level paging if needed, see [`create_next_table`](RecursivePageTable::create_next_table).
+    fn create_p4<A, S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut A,
+    ) -> Result<&mut PageTable, MapToError<S>>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        match self.top_level {
+            PageTableLevel::Five => {
+                let p4_page = p4_page(page, self.recursive_index, self.top_level);
+                let entry = &mut self.top_table[page.p5_index()];
+                unsafe { Self::create_next_table(entry, p4_page, allocator) }
+            }
+            _ => Ok(self.top_table),
         }
     }
 
@@ -150,9 +243,10 @@ impl<'a> RecursivePageTable<'a> {
         A: FrameAllocator<Size4KiB>,
     {
         use crate::structures::paging::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let (recursive_index, top_level) = (self.recursive_index, self.top_level);
+        let p4 = self.create_p4(page, allocator)?;
 
-        let p3_page = p3_page(page, self.recursive_index);
+        let p3_page = p3_page(page, recursive_index, top_level);
         let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
 
         if !p3[page.p3_index()].is_unused() {
@@ -176,12 +270,13 @@ impl<'a> RecursivePageTable<'a> {
         A: FrameAllocator<Size4KiB>,
     {
         use crate::structures::paging::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let (recursive_index, top_level) = (self.recursive_index, self.top_level);
+        let p4 = self.create_p4(page, allocator)?;
 
-        let p3_page = p3_page(page, self.recursive_index);
+        let p3_page = p3_page(page, recursive_index, top_level);
         let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
 
-        let p2_page = p2_page(page, self.recursive_index);
+        let p2_page = p2_page(page, recursive_index, top_level);
         let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)? };
 
         if !p2[page.p2_index()].is_unused() {
@@ -204,15 +299,16 @@ impl<'a> RecursivePageTable<'a> {
     where
         A: FrameAllocator<Size4KiB>,
     {
-        let p4 = &mut self.p4;
+        let (recursive_index, top_level) = (self.recursive_index, self.top_level);
+        let p4 = self.create_p4(page, allocator)?;
 
-        let p3_page = p3_page(page, self.recursive_index);
+        let p3_page = p3_page(page, recursive_index, top_level);
         let p3 = unsafe { Self::create_next_table(&mut p4[page.p4_index()], p3_page, allocator)? };
 
-        let p2_page = p2_page(page, self.recursive_index);
+        let p2_page = p2_page(page, recursive_index, top_level);
         let p2 = unsafe { Self::create_next_table(&mut p3[page.p3_index()], p2_page, allocator)? };
 
-        let p1_page = p1_page(page, self.recursive_index);
+        let p1_page = p1_page(page, recursive_index, top_level);
         let p1 = unsafe { Self::create_next_table(&mut p2[page.p2_index()], p1_page, allocator)? };
 
         if !p1[page.p1_index()].is_unused() {
@@ -239,11 +335,35 @@ impl<'a> Mapper<Size1GiB> for RecursivePageTable<'a> {
         self.map_to_1gib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+        frames: PhysFrameRange<Size1GiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size1GiB>, (MapToError<Size1GiB>, MapperFlushRange<Size1GiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let top_level = self.top_level;
+        map_range_per_page(self, pages, frames, flags, allocator, top_level)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size1GiB>,
+    ) -> Result<MapperFlushRange<Size1GiB>, (UnmapError, MapperFlushRange<Size1GiB>)> {
+        let top_level = self.top_level;
+        unmap_range_per_page(self, pages, top_level)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size1GiB>,
     ) -> Result<(PhysFrame<Size1GiB>, MapperFlush<Size1GiB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
 
         p4_entry.frame().map_err(|err| match err {
@@ -251,7 +371,7 @@ impl<'a> Mapper<Size1GiB> for RecursivePageTable<'a> {
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &mut p3[page.p3_index()];
         let flags = p3_entry.flags();
 
@@ -277,13 +397,13 @@ impl<'a> Mapper<Size1GiB> for RecursivePageTable<'a> {
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size1GiB>, FlagUpdateError> {
         use crate::structures::paging::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
 
         if p3[page.p3_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -294,13 +414,13 @@ impl<'a> Mapper<Size1GiB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&self, page: Page<Size1GiB>) -> Result<PhysFrame<Size1GiB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.p4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[page.p3_index()];
 
         if p3_entry.is_unused() {
@@ -327,25 +447,49 @@ impl<'a> Mapper<Size2MiB> for RecursivePageTable<'a> {
         self.map_to_2mib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+        frames: PhysFrameRange<Size2MiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size2MiB>, (MapToError<Size2MiB>, MapperFlushRange<Size2MiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let top_level = self.top_level;
+        map_range_per_page(self, pages, frames, flags, allocator, top_level)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size2MiB>,
+    ) -> Result<MapperFlushRange<Size2MiB>, (UnmapError, MapperFlushRange<Size2MiB>)> {
+        let top_level = self.top_level;
+        unmap_range_per_page(self, pages, top_level)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size2MiB>,
     ) -> Result<(PhysFrame<Size2MiB>, MapperFlush<Size2MiB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
         p4_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[page.p3_index()];
         p3_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index, self.top_level)) };
         let p2_entry = &mut p2[page.p2_index()];
         let flags = p2_entry.flags();
 
@@ -371,19 +515,19 @@ impl<'a> Mapper<Size2MiB> for RecursivePageTable<'a> {
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size2MiB>, FlagUpdateError> {
         use crate::structures::paging::PageTableFlags as Flags;
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
 
         if p3[page.p3_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index, self.top_level)) };
 
         if p2[page.p2_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -395,20 +539,20 @@ impl<'a> Mapper<Size2MiB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&self, page: Page<Size2MiB>) -> Result<PhysFrame<Size2MiB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.p4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[page.p3_index()];
 
         if p3_entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index, self.top_level)) };
         let p2_entry = &p2[page.p2_index()];
 
         if p2_entry.is_unused() {
@@ -435,32 +579,56 @@ impl<'a> Mapper<Size4KiB> for RecursivePageTable<'a> {
         self.map_to_4kib(page, frame, flags, allocator)
     }
 
+    #[inline]
+    unsafe fn map_range<A>(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+        frames: PhysFrameRange<Size4KiB>,
+        flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<MapperFlushRange<Size4KiB>, (MapToError<Size4KiB>, MapperFlushRange<Size4KiB>)>
+    where
+        A: FrameAllocator<Size4KiB>,
+    {
+        let top_level = self.top_level;
+        map_range_per_page(self, pages, frames, flags, allocator, top_level)
+    }
+
+    #[inline]
+    fn unmap_range(
+        &mut self,
+        pages: PageRange<Size4KiB>,
+    ) -> Result<MapperFlushRange<Size4KiB>, (UnmapError, MapperFlushRange<Size4KiB>)> {
+        let top_level = self.top_level;
+        unmap_range_per_page(self, pages, top_level)
+    }
+
     fn unmap(
         &mut self,
         page: Page<Size4KiB>,
     ) -> Result<(PhysFrame<Size4KiB>, MapperFlush<Size4KiB>), UnmapError> {
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(UnmapError::PageNotMapped)?;
         let p4_entry = &p4[page.p4_index()];
         p4_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[page.p3_index()];
         p3_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index, self.top_level)) };
         let p2_entry = &p2[page.p2_index()];
         p2_entry.frame().map_err(|err| match err {
             FrameError::FrameNotPresent => UnmapError::PageNotMapped,
             FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
         })?;
 
-        let p1 = unsafe { &mut *(p1_ptr(page, self.recursive_index)) };
+        let p1 = unsafe { &mut *(p1_ptr(page, self.recursive_index, self.top_level)) };
         let p1_entry = &mut p1[page.p1_index()];
 
         let frame = p1_entry.frame().map_err(|err| match err {
@@ -479,25 +647,25 @@ impl<'a> Mapper<Size4KiB> for RecursivePageTable<'a> {
         page: Page<Size4KiB>,
         flags: PageTableFlags,
     ) -> Result<MapperFlush<Size4KiB>, FlagUpdateError> {
-        let p4 = &mut self.p4;
+        let p4 = self.p4_mut(page).ok_or(FlagUpdateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &mut *(p3_ptr(page, self.recursive_index, self.top_level)) };
 
         if p3[page.p3_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &mut *(p2_ptr(page, self.recursive_index, self.top_level)) };
 
         if p2[page.p2_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
         }
 
-        let p1 = unsafe { &mut *(p1_ptr(page, self.recursive_index)) };
+        let p1 = unsafe { &mut *(p1_ptr(page, self.recursive_index, self.top_level)) };
 
         if p1[page.p1_index()].is_unused() {
             return Err(FlagUpdateError::PageNotMapped);
@@ -509,27 +677,27 @@ impl<'a> Mapper<Size4KiB> for RecursivePageTable<'a> {
     }
 
     fn translate_page(&self, page: Page<Size4KiB>) -> Result<PhysFrame<Size4KiB>, TranslateError> {
-        let p4 = &self.p4;
+        let p4 = self.p4(page).ok_or(TranslateError::PageNotMapped)?;
 
         if p4[page.p4_index()].is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[page.p3_index()];
 
         if p3_entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index, self.top_level)) };
         let p2_entry = &p2[page.p2_index()];
 
         if p2_entry.is_unused() {
             return Err(TranslateError::PageNotMapped);
         }
 
-        let p1 = unsafe { &*(p1_ptr(page, self.recursive_index)) };
+        let p1 = unsafe { &*(p1_ptr(page, self.recursive_index, self.top_level)) };
         let p1_entry = &p1[page.p1_index()];
 
         if p1_entry.is_unused() {
@@ -544,9 +712,12 @@ impl<'a> Mapper<Size4KiB> for RecursivePageTable<'a> {
 impl<'a> MapperAllSizes for RecursivePageTable<'a> {
     #[allow(clippy::inconsistent_digit_grouping)]
     fn translate(&self, addr: VirtAddr) -> TranslateResult {
-        let page = Page::containing_address(addr);
+        let page = Page::<Size4KiB>::containing_address(addr);
 
-        let p4 = &self.p4;
+        let p4 = match self.p4(page) {
+            Some(p4) => p4,
+            None => return TranslateResult::PageNotMapped,
+        };
         let p4_entry = &p4[addr.p4_index()];
         if p4_entry.is_unused() {
             return TranslateResult::PageNotMapped;
@@ -555,7 +726,7 @@ impl<'a> MapperAllSizes for RecursivePageTable<'a> {
             panic!("level 4 entry has huge page bit set")
         }
 
-        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index)) };
+        let p3 = unsafe { &*(p3_ptr(page, self.recursive_index, self.top_level)) };
         let p3_entry = &p3[addr.p3_index()];
         if p3_entry.is_unused() {
             return TranslateResult::PageNotMapped;
@@ -566,7 +737,7 @@ impl<'a> MapperAllSizes for RecursivePageTable<'a> {
             return TranslateResult::Frame1GiB { frame, offset };
         }
 
-        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index)) };
+        let p2 = unsafe { &*(p2_ptr(page, self.recursive_index, self.top_level)) };
         let p2_entry = &p2[addr.p2_index()];
         if p2_entry.is_unused() {
             return TranslateResult::PageNotMapped;
@@ -577,7 +748,7 @@ impl<'a> MapperAllSizes for RecursivePageTable<'a> {
             return TranslateResult::Frame2MiB { frame, offset };
         }
 
-        let p1 = unsafe { &*(p1_ptr(page, self.recursive_index)) };
+        let p1 = unsafe { &*(p1_ptr(page, self.recursive_index, self.top_level)) };
         let p1_entry = &p1[addr.p1_index()];
         if p1_entry.is_unused() {
             return TranslateResult::PageNotMapped;
@@ -595,47 +766,139 @@ impl<'a> MapperAllSizes for RecursivePageTable<'a> {
     }
 }
 
+/// Returns the page of the table of the given level that maps the given page.
+///
+/// The address of the table consists of the recursive index, repeated `level` times, followed
+/// by the indices of the entries that lead to the table.
 #[inline]
-fn p3_ptr<S: PageSize>(page: Page<S>, recursive_index: PageTableIndex) -> *mut PageTable {
-    p3_page(page, recursive_index).start_address().as_mut_ptr()
+fn table_page<S: PageSize>(
+    page: Page<S>,
+    level: PageTableLevel,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> Page {
+    let (level, top) = (level as u64, top_level as u64);
+    let mut addr = 0;
+    for _ in 0..level {
+        addr = addr << 9 | u64::from(recursive_index);
+    }
+    let index_bits = 9 * (top - level);
+    let indices = (page.start_address().as_u64() >> (12 + 9 * level)) & ((1 << index_bits) - 1);
+    let addr = (addr << index_bits | indices) << 12;
+    match top_level {
+        PageTableLevel::Five => Page::containing_address(VirtAddr::new_truncate_la57(addr)),
+        _ => Page::containing_address(VirtAddr::new_truncate(addr)),
+    }
 }
 
 #[inline]
-fn p3_page<S: PageSize>(page: Page<S>, recursive_index: PageTableIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        recursive_index,
-        recursive_index,
-        page.p4_index(),
-    )
+fn p4_ptr<S: PageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> *mut PageTable {
+    p4_page(page, recursive_index, top_level)
+        .start_address()
+        .as_mut_ptr()
 }
 
 #[inline]
-fn p2_ptr<S: NotGiantPageSize>(page: Page<S>, recursive_index: PageTableIndex) -> *mut PageTable {
-    p2_page(page, recursive_index).start_address().as_mut_ptr()
+fn p4_page<S: PageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> Page {
+    table_page(page, PageTableLevel::Four, recursive_index, top_level)
 }
 
 #[inline]
-fn p2_page<S: NotGiantPageSize>(page: Page<S>, recursive_index: PageTableIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        recursive_index,
-        page.p4_index(),
-        page.p3_index(),
-    )
+fn p3_ptr<S: PageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> *mut PageTable {
+    p3_page(page, recursive_index, top_level)
+        .start_address()
+        .as_mut_ptr()
+}
+
+#[inline]
+fn p3_page<S: PageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> Page {
+    table_page(page, PageTableLevel::Three, recursive_index, top_level)
 }
 
 #[inline]
-fn p1_ptr(page: Page<Size4KiB>, recursive_index: PageTableIndex) -> *mut PageTable {
-    p1_page(page, recursive_index).start_address().as_mut_ptr()
+fn p2_ptr<S: NotGiantPageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> *mut PageTable {
+    p2_page(page, recursive_index, top_level)
+        .start_address()
+        .as_mut_ptr()
 }
 
 #[inline]
-fn p1_page(page: Page<Size4KiB>, recursive_index: PageTableIndex) -> Page {
-    Page::from_page_table_indices(
-        recursive_index,
-        page.p4_index(),
-        page.p3_index(),
-        page.p2_index(),
-    )
+fn p2_page<S: NotGiantPageSize>(
+    page: Page<S>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> Page {
+    table_page(page, PageTableLevel::Two, recursive_index, top_level)
+}
+
+#[inline]
+fn p1_ptr(
+    page: Page<Size4KiB>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> *mut PageTable {
+    p1_page(page, recursive_index, top_level)
+        .start_address()
+        .as_mut_ptr()
+}
+
+#[inline]
+fn p1_page(
+    page: Page<Size4KiB>,
+    recursive_index: PageTableIndex,
+    top_level: PageTableLevel,
+) -> Page {
+    table_page(page, PageTableLevel::One, recursive_index, top_level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::unusual_byte_groupings)]
+    fn recursive_table_pages() {
+        let r = PageTableIndex::new(0o777);
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(0o_001_002_003_004_0000));
+        assert_eq!(
+            p1_page(page, r, PageTableLevel::Four),
+            Page::from_page_table_indices(r, page.p4_index(), page.p3_index(), page.p2_index())
+        );
+        assert_eq!(
+            p3_page(page, r, PageTableLevel::Four).start_address(),
+            VirtAddr::new(0o_177777_777_777_777_001_0000)
+        );
+
+        // with 5-level paging, the level 5 index follows the loops
+        let page =
+            Page::<Size4KiB>::containing_address(VirtAddr::new_la57(0o_005_001_002_003_004_0000));
+        assert_eq!(
+            p4_page(page, r, PageTableLevel::Five).start_address(),
+            VirtAddr::new_la57(0o_177_777_777_777_777_005_0000)
+        );
+        assert_eq!(
+            p1_page(page, r, PageTableLevel::Five).start_address(),
+            VirtAddr::new_la57(0o_177_777_005_001_002_003_0000)
+        );
+    }
 }
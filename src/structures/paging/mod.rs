@@ -12,11 +12,13 @@ pub use self::mapper::{Mapper, MapperAllSizes};
 #[cfg(target_arch = "x86_64")]
 #[doc(no_inline)]
 pub use self::mapper::{OffsetPageTable, RecursivePageTable};
-pub use self::page::{Page, PageSize, Size1GiB, Size2MiB, Size4KiB};
-pub use self::page_table::{PageOffset, PageTable, PageTableFlags, PageTableIndex};
+pub use self::page::{AddressNotAligned, Page, PageSize, Size1GiB, Size2MiB, Size4KiB};
+pub use self::page_table::{PageOffset, PageTable, PageTableFlags, PageTableIndex, PageTableLevel};
 
+pub mod ept;
 pub mod frame;
 mod frame_alloc;
 pub mod mapper;
 pub mod page;
 pub mod page_table;
+pub mod spp;
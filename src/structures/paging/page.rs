@@ -2,6 +2,7 @@
 
 use crate::structures::paging::PageTableIndex;
 use crate::VirtAddr;
+use core::convert::TryFrom;
 use core::fmt;
 use core::marker::PhantomData;
 use core::ops::{Add, AddAssign, Sub, SubAssign};
@@ -52,6 +53,11 @@ impl PageSize for Size1GiB {
 }
 
 /// A virtual memory page.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "", try_from = "VirtAddr", into = "VirtAddr")
+)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(C)]
 pub struct Page<S: PageSize = Size4KiB> {
@@ -114,6 +120,15 @@ impl<S: PageSize> Page<S> {
         }
     }
 
+    const_fn! {
+        /// Returns the level 5 page table index of this page, which is only used with 5-level
+        /// paging.
+        #[inline]
+        pub fn p5_index(self) -> PageTableIndex {
+            self.start_address().p5_index()
+        }
+    }
+
     const_fn! {
         /// Returns the level 4 page table index of this page.
         #[inline]
@@ -130,6 +145,22 @@ impl<S: PageSize> Page<S> {
         }
     }
 
+    /// Returns the page `rhs` pages after this page in a 5-level address space.
+    ///
+    /// Use the `+` operator for pages of a 4-level address space.
+    #[inline]
+    pub fn add_la57(self, rhs: u64) -> Self {
+        Page::containing_address(self.start_address().add_la57(rhs * S::SIZE))
+    }
+
+    /// Returns the page `rhs` pages before this page in a 5-level address space.
+    ///
+    /// Use the `-` operator for pages of a 4-level address space.
+    #[inline]
+    pub fn sub_la57(self, rhs: u64) -> Self {
+        Page::containing_address(self.start_address().sub_la57(rhs * S::SIZE))
+    }
+
     const_fn! {
         /// Returns a range of pages, exclusive `end`.
         #[inline]
@@ -164,12 +195,10 @@ impl Page<Size1GiB> {
         p4_index: PageTableIndex,
         p3_index: PageTableIndex,
     ) -> Self {
-        use bit_field::BitField;
-
-        let mut addr = 0;
-        addr.set_bits(39..48, u64::from(p4_index));
-        addr.set_bits(30..39, u64::from(p3_index));
-        Page::containing_address(VirtAddr::new(addr))
+        let addr = u64::from(p4_index) << 39 | u64::from(p3_index) << 30;
+        // the indices are at most 9 bits wide, so the address is aligned and only needs sign
+        // extension
+        unsafe { Page::from_start_address_unchecked(VirtAddr::new_truncate(addr)) }
     }
 }
 
@@ -181,13 +210,9 @@ impl Page<Size2MiB> {
         p3_index: PageTableIndex,
         p2_index: PageTableIndex,
     ) -> Self {
-        use bit_field::BitField;
-
-        let mut addr = 0;
-        addr.set_bits(39..48, u64::from(p4_index));
-        addr.set_bits(30..39, u64::from(p3_index));
-        addr.set_bits(21..30, u64::from(p2_index));
-        Page::containing_address(VirtAddr::new(addr))
+        let addr =
+            u64::from(p4_index) << 39 | u64::from(p3_index) << 30 | u64::from(p2_index) << 21;
+        unsafe { Page::from_start_address_unchecked(VirtAddr::new_truncate(addr)) }
     }
 }
 
@@ -200,14 +225,11 @@ impl Page<Size4KiB> {
         p2_index: PageTableIndex,
         p1_index: PageTableIndex,
     ) -> Self {
-        use bit_field::BitField;
-
-        let mut addr = 0;
-        addr.set_bits(39..48, u64::from(p4_index));
-        addr.set_bits(30..39, u64::from(p3_index));
-        addr.set_bits(21..30, u64::from(p2_index));
-        addr.set_bits(12..21, u64::from(p1_index));
-        Page::containing_address(VirtAddr::new(addr))
+        let addr = u64::from(p4_index) << 39
+            | u64::from(p3_index) << 30
+            | u64::from(p2_index) << 21
+            | u64::from(p1_index) << 12;
+        unsafe { Page::from_start_address_unchecked(VirtAddr::new_truncate(addr)) }
     }
 
     const_fn! {
@@ -229,6 +251,32 @@ impl<S: PageSize> fmt::Debug for Page<S> {
     }
 }
 
+impl<S: PageSize> TryFrom<VirtAddr> for Page<S> {
+    type Error = AddressNotAligned;
+
+    #[inline]
+    fn try_from(address: VirtAddr) -> Result<Self, Self::Error> {
+        Page::from_start_address(address).map_err(|()| AddressNotAligned)
+    }
+}
+
+impl<S: PageSize> From<Page<S>> for VirtAddr {
+    #[inline]
+    fn from(page: Page<S>) -> VirtAddr {
+        page.start_address()
+    }
+}
+
+/// The given address was not sufficiently aligned to be the start of a page or frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressNotAligned;
+
+impl fmt::Display for AddressNotAligned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the given address was not sufficiently aligned")
+    }
+}
+
 impl<S: PageSize> Add<u64> for Page<S> {
     type Output = Self;
     #[inline]
@@ -268,6 +316,11 @@ impl<S: PageSize> Sub<Self> for Page<S> {
 }
 
 /// A range of pages with exclusive upper bound.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct PageRange<S: PageSize = Size4KiB> {
@@ -283,6 +336,20 @@ impl<S: PageSize> PageRange<S> {
     pub fn is_empty(&self) -> bool {
         self.start >= self.end
     }
+
+    /// Returns an iterator over the pages of this range in a 5-level address space.
+    ///
+    /// Iterating the range itself steps with 4-level arithmetic, which jumps from the end of
+    /// the 48-bit lower half to the higher half.
+    #[inline]
+    pub fn iter_la57(self) -> impl Iterator<Item = Page<S>> {
+        let count = if self.is_empty() {
+            0
+        } else {
+            self.end - self.start
+        };
+        (0..count).map(move |i| self.start.add_la57(i))
+    }
 }
 
 impl<S: PageSize> Iterator for PageRange<S> {
@@ -321,6 +388,11 @@ impl<S: PageSize> fmt::Debug for PageRange<S> {
 }
 
 /// A range of pages with inclusive upper bound.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct PageRangeInclusive<S: PageSize = Size4KiB> {
@@ -393,4 +465,50 @@ mod tests {
         }
         assert_eq!(range_inclusive.next(), None);
     }
+
+    #[test]
+    pub fn test_page_range_la57() {
+        let start: Page = Page::containing_address(VirtAddr::new_la57(0x7fff_ffff_e000));
+        let end = start.add_la57(4);
+        assert_eq!(end.start_address(), VirtAddr::new_la57(0x8000_0000_2000));
+        assert_eq!(end.sub_la57(4), start);
+
+        let mut pages = Page::range(start, end).iter_la57();
+        for i in 0..4 {
+            assert_eq!(
+                pages.next().map(Page::start_address),
+                Some(VirtAddr::new_la57(0x7fff_ffff_e000 + i * Size4KiB::SIZE))
+            );
+        }
+        assert_eq!(pages.next(), None);
+        assert_eq!(Page::range(end, start).iter_la57().next(), None);
+    }
+
+    #[test]
+    pub fn test_conversions() {
+        let page = Page::<Size2MiB>::try_from(VirtAddr::new(0x20_0000)).unwrap();
+        assert_eq!(VirtAddr::from(page), VirtAddr::new(0x20_0000));
+        assert_eq!(
+            Page::<Size2MiB>::try_from(VirtAddr::new(0x1000)),
+            Err(AddressNotAligned)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn test_serde_rejects_unaligned_pages() {
+        use serde::de::{value::Error, Deserialize, IntoDeserializer};
+
+        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(0x4020_0000));
+        let deserialized: Result<Page<Size2MiB>, Error> =
+            Page::deserialize(u64::from(VirtAddr::from(page)).into_deserializer());
+        assert_eq!(deserialized.unwrap(), page);
+
+        let unaligned: Result<Page<Size2MiB>, Error> =
+            Page::deserialize(0x4020_1000u64.into_deserializer());
+        assert!(unaligned.is_err());
+        let non_canonical: Result<Page, Error> =
+            Page::deserialize(0x0100_0000_0000_0000u64.into_deserializer());
+        assert!(non_canonical.is_err());
+    }
 }
@@ -19,6 +19,7 @@ pub enum FrameError {
 }
 
 /// A 64-bit page table entry.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone)]
 #[repr(transparent)]
 pub struct PageTableEntry {
@@ -106,6 +107,7 @@ impl fmt::Debug for PageTableEntry {
 
 bitflags! {
     /// Possible flags for a page table entry.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct PageTableFlags: u64 {
         /// Specifies whether the mapped frame or page table is loaded in memory.
         const PRESENT =         1;
@@ -168,6 +170,8 @@ bitflags! {
     }
 }
 
+impl_flags_display!(PageTableFlags);
+
 /// The number of entries in a page table.
 const ENTRY_COUNT: usize = 512;
 
@@ -185,20 +189,11 @@ pub struct PageTable {
 
 impl PageTable {
     /// Creates an empty page table.
-    #[cfg(feature = "const_fn")]
     #[inline]
     pub const fn new() -> Self {
+        const EMPTY: PageTableEntry = PageTableEntry::new();
         PageTable {
-            entries: [PageTableEntry::new(); ENTRY_COUNT],
-        }
-    }
-
-    /// Creates an empty page table.
-    #[cfg(not(feature = "const_fn"))]
-    #[inline]
-    pub fn new() -> Self {
-        PageTable {
-            entries: array_init::array_init(|_| PageTableEntry::new()),
+            entries: [EMPTY; ENTRY_COUNT],
         }
     }
 
@@ -363,3 +358,52 @@ impl From<PageOffset> for usize {
         usize::from(offset.0)
     }
 }
+
+/// A level of the page table hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum PageTableLevel {
+    /// The level 1 table, whose entries map 4KiB pages.
+    One = 1,
+    /// The level 2 table, whose entries map 2MiB pages or level 1 tables.
+    Two,
+    /// The level 3 table, whose entries map 1GiB pages or level 2 tables.
+    Three,
+    /// The level 4 table, the top-level table of 4-level paging.
+    Four,
+    /// The level 5 table, the top-level table of 5-level paging (CR4.LA57).
+    Five,
+}
+
+impl PageTableLevel {
+    /// Returns the next lower level, or `None` for level 1.
+    #[inline]
+    pub const fn next_lower_level(self) -> Option<Self> {
+        match self {
+            PageTableLevel::One => None,
+            PageTableLevel::Two => Some(PageTableLevel::One),
+            PageTableLevel::Three => Some(PageTableLevel::Two),
+            PageTableLevel::Four => Some(PageTableLevel::Three),
+            PageTableLevel::Five => Some(PageTableLevel::Four),
+        }
+    }
+
+    /// Returns the next higher level, or `None` for level 5.
+    #[inline]
+    pub const fn next_higher_level(self) -> Option<Self> {
+        match self {
+            PageTableLevel::One => Some(PageTableLevel::Two),
+            PageTableLevel::Two => Some(PageTableLevel::Three),
+            PageTableLevel::Three => Some(PageTableLevel::Four),
+            PageTableLevel::Four => Some(PageTableLevel::Five),
+            PageTableLevel::Five => None,
+        }
+    }
+
+    /// Returns the size of the address space that an entry of this level maps, e.g. 2MiB for
+    /// level 2.
+    #[inline]
+    pub const fn entry_address_space_size(self) -> u64 {
+        1 << (12 + 9 * (self as u64 - 1))
+    }
+}
@@ -0,0 +1,345 @@
+//! Sub-page write permissions (SPP), which control writes to 128-byte sub-pages of 4KiB
+//! guest-physical pages.
+//!
+//! SPP applies to the pages whose leaf EPT entry has the
+//! [`SUB_PAGE_WRITE`](super::ept::EptFlags::SUB_PAGE_WRITE) flag set and don't allow writes
+//! otherwise. The write permissions of such a page are looked up in the SPP table hierarchy,
+//! which has the same 4-level structure as EPT and is referenced by the [`Spptp`]. The level 1
+//! entries hold a [`SppWritePermissions`] vector instead of a table address. See section
+//! 29.3.4 "Sub-Page Write Permissions" of the Intel SDM, volume 3.
+
+use core::fmt;
+use core::ops::{Index, IndexMut};
+
+use super::page_table::{FrameError, PageTableIndex};
+use super::PhysFrame;
+use crate::addr::PhysAddr;
+
+/// The number of sub-pages of a 4KiB page.
+pub const SUB_PAGE_COUNT: usize = 32;
+
+/// The size of a sub-page in bytes.
+pub const SUB_PAGE_SIZE: u64 = 128;
+
+/// The SPP table pointer, which references the level 4 SPP table.
+///
+/// The pointer is written to the
+/// [`SubPagePermissionTablePointer`](crate::structures::vmcs::VmcsField64::SubPagePermissionTablePointer)
+/// field of the VMCS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Spptp(u64);
+
+impl Spptp {
+    /// Creates an SPP table pointer to the given level 4 table.
+    #[inline]
+    pub fn new(level_4_table: PhysFrame) -> Self {
+        Spptp(level_4_table.start_address().as_u64())
+    }
+
+    /// Creates an SPP table pointer from the value of the VMCS field.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        Spptp(raw)
+    }
+
+    /// Returns the value of the VMCS field.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the frame of the level 4 SPP table.
+    #[inline]
+    pub fn level_4_table(self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.0 & 0x000f_ffff_ffff_f000))
+    }
+}
+
+/// The write permissions of the sub-pages of a 4KiB page, stored in a level 1 SPP entry.
+///
+/// The write permission of sub-page `i` is bit `2 * i`, the odd bits are reserved.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[repr(transparent)]
+pub struct SppWritePermissions(u64);
+
+impl SppWritePermissions {
+    /// The bits that hold write permissions.
+    const WRITE_MASK: u64 = 0x5555_5555_5555_5555;
+
+    /// Creates a vector in which no sub-page is writable.
+    #[inline]
+    pub const fn new() -> Self {
+        SppWritePermissions(0)
+    }
+
+    /// Creates a vector in which all sub-pages are writable.
+    #[inline]
+    pub const fn all_writable() -> Self {
+        SppWritePermissions(Self::WRITE_MASK)
+    }
+
+    /// Creates a vector from the value of a level 1 SPP entry, dropping the reserved bits.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        SppWritePermissions(raw & Self::WRITE_MASK)
+    }
+
+    /// Returns the value of the level 1 SPP entry.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the index of the sub-page containing the given offset in the page.
+    #[inline]
+    pub const fn sub_page(offset: u64) -> usize {
+        ((offset & 0xfff) / SUB_PAGE_SIZE) as usize
+    }
+
+    /// Returns whether the given sub-page is writable.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the sub-page index is 32 or larger.
+    #[inline]
+    pub fn is_writable(self, sub_page: usize) -> bool {
+        assert!(sub_page < SUB_PAGE_COUNT, "a page has 32 sub-pages");
+        self.0 & 1 << (2 * sub_page) != 0
+    }
+
+    /// Sets whether the given sub-page is writable.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the sub-page index is 32 or larger.
+    #[inline]
+    pub fn set_writable(&mut self, sub_page: usize, writable: bool) {
+        assert!(sub_page < SUB_PAGE_COUNT, "a page has 32 sub-pages");
+        if writable {
+            self.0 |= 1 << (2 * sub_page);
+        } else {
+            self.0 &= !(1 << (2 * sub_page));
+        }
+    }
+}
+
+impl fmt::Debug for SppWritePermissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set()
+            .entries((0..SUB_PAGE_COUNT).filter(|&sub_page| self.is_writable(sub_page)))
+            .finish()
+    }
+}
+
+/// A 64-bit SPP entry.
+///
+/// Entries of level 4 to 2 reference the next SPP table, entries of level 1 hold the write
+/// permissions of a 4KiB page.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone)]
+#[repr(transparent)]
+pub struct SppEntry {
+    entry: u64,
+}
+
+impl SppEntry {
+    /// The valid bit of a level 4 to 2 entry.
+    const VALID: u64 = 1;
+
+    /// Creates an unused SPP entry.
+    #[inline]
+    pub const fn new() -> Self {
+        SppEntry { entry: 0 }
+    }
+
+    /// Returns whether this entry is zero.
+    #[inline]
+    pub const fn is_unused(&self) -> bool {
+        self.entry == 0
+    }
+
+    /// Sets this entry to zero.
+    #[inline]
+    pub fn set_unused(&mut self) {
+        self.entry = 0;
+    }
+
+    /// Returns whether this level 4 to 2 entry references a table.
+    ///
+    /// A lookup through an entry that isn't valid causes an SPP miss.
+    #[inline]
+    pub const fn is_valid(&self) -> bool {
+        self.entry & Self::VALID != 0
+    }
+
+    /// Returns the physical address of the next table referenced by this level 4 to 2 entry,
+    /// might be zero.
+    #[inline]
+    pub fn addr(&self) -> PhysAddr {
+        PhysAddr::new(self.entry & 0x000f_ffff_ffff_f000)
+    }
+
+    /// Returns the frame of the next table referenced by this level 4 to 2 entry.
+    ///
+    /// Returns `FrameError::FrameNotPresent` if the entry isn't valid.
+    #[inline]
+    pub fn frame(&self) -> Result<PhysFrame, FrameError> {
+        if self.is_valid() {
+            Ok(PhysFrame::containing_address(self.addr()))
+        } else {
+            Err(FrameError::FrameNotPresent)
+        }
+    }
+
+    /// Makes this level 4 to 2 entry reference the given SPP table.
+    #[inline]
+    pub fn set_frame(&mut self, frame: PhysFrame) {
+        self.entry = frame.start_address().as_u64() | Self::VALID;
+    }
+
+    /// Returns the write permissions held by this level 1 entry.
+    #[inline]
+    pub const fn write_permissions(&self) -> SppWritePermissions {
+        SppWritePermissions::from_raw(self.entry)
+    }
+
+    /// Sets the write permissions held by this level 1 entry.
+    #[inline]
+    pub fn set_write_permissions(&mut self, permissions: SppWritePermissions) {
+        self.entry = permissions.as_raw();
+    }
+}
+
+impl Default for SppEntry {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SppEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SppEntry")
+            .field(&format_args!("{:#x}", self.entry))
+            .finish()
+    }
+}
+
+/// The number of entries in an SPP table.
+const ENTRY_COUNT: usize = 512;
+
+/// Represents an SPP table at any level.
+///
+/// The tables are indexed with the same bits of the guest-physical address as EPT tables.
+///
+/// This struct implements the `Index` and `IndexMut` traits, so the entries can be accessed
+/// through index operations.
+#[repr(align(4096))]
+#[repr(C)]
+pub struct SppTable {
+    entries: [SppEntry; ENTRY_COUNT],
+}
+
+impl SppTable {
+    /// Creates an empty SPP table.
+    #[inline]
+    pub const fn new() -> Self {
+        const EMPTY: SppEntry = SppEntry::new();
+        SppTable {
+            entries: [EMPTY; ENTRY_COUNT],
+        }
+    }
+
+    /// Clears all entries.
+    #[inline]
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+    }
+
+    /// Returns an iterator over the entries of the SPP table.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &SppEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns an iterator that allows modifying the entries of the SPP table.
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut SppEntry> {
+        self.entries.iter_mut()
+    }
+}
+
+impl Default for SppTable {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for SppTable {
+    type Output = SppEntry;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for SppTable {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}
+
+impl Index<PageTableIndex> for SppTable {
+    type Output = SppEntry;
+
+    #[inline]
+    fn index(&self, index: PageTableIndex) -> &Self::Output {
+        &self.entries[usize::from(index)]
+    }
+}
+
+impl IndexMut<PageTableIndex> for SppTable {
+    #[inline]
+    fn index_mut(&mut self, index: PageTableIndex) -> &mut Self::Output {
+        &mut self.entries[usize::from(index)]
+    }
+}
+
+impl fmt::Debug for SppTable {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.entries[..].fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_permissions() {
+        let mut permissions = SppWritePermissions::new();
+        permissions.set_writable(SppWritePermissions::sub_page(0x80), true);
+        permissions.set_writable(31, true);
+        assert_eq!(permissions.as_raw(), 1 << 2 | 1 << 62);
+        assert!(permissions.is_writable(1) && !permissions.is_writable(2));
+        assert_eq!(
+            SppWritePermissions::from_raw(u64::MAX),
+            SppWritePermissions::all_writable()
+        );
+
+        let mut entry = SppEntry::new();
+        entry.set_write_permissions(permissions);
+        assert_eq!(entry.write_permissions(), permissions);
+        let frame = PhysFrame::containing_address(PhysAddr::new(0x1234_5000));
+        entry.set_frame(frame);
+        assert_eq!(entry.frame(), Ok(frame));
+        assert_eq!(Spptp::new(frame).level_4_table(), frame);
+    }
+}
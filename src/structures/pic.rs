@@ -0,0 +1,226 @@
+//! Provides a driver for the legacy 8259 programmable interrupt controllers (PICs).
+//!
+//! Most PCs have two chained 8259 PICs: the primary PIC handles IRQs 0 to 7 and the
+//! secondary PIC, which is connected to IRQ 2 of the primary PIC, handles IRQs 8 to 15. By
+//! default, the PICs deliver their interrupts on vectors 0x08 to 0x0F and 0x70 to 0x77, which
+//! collide with the CPU exceptions. Therefore the PICs must be remapped to other vectors
+//! before interrupts are enabled, even if they are masked and the APIC is used instead.
+
+use crate::instructions::port::Port;
+
+/// The command that starts the initialization sequence (ICW1_INIT | ICW1_ICW4).
+const CMD_INIT: u8 = 0x11;
+/// The command that signals the end of an interrupt.
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+/// Puts the PIC into 8086/88 mode (ICW4).
+const MODE_8086: u8 = 0x01;
+
+/// A single 8259 PIC.
+#[derive(Debug)]
+struct Pic {
+    /// The vector of the first IRQ handled by this PIC.
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    /// Returns whether the given vector is handled by this PIC.
+    fn handles_interrupt(&self, vector: u8) -> bool {
+        vector.wrapping_sub(self.offset) < 8
+    }
+
+    /// Signals the end of an interrupt to this PIC.
+    unsafe fn end_of_interrupt(&mut self) {
+        self.command.write(CMD_END_OF_INTERRUPT);
+    }
+}
+
+/// The pair of chained 8259 PICs of a PC.
+#[derive(Debug)]
+pub struct ChainedPics {
+    pics: [Pic; 2],
+}
+
+impl ChainedPics {
+    const_fn! {
+        /// Creates a new driver for the chained PICs, which maps the IRQs of the primary PIC
+        /// to the vectors starting at `offset1` and the IRQs of the secondary PIC to the vectors
+        /// starting at `offset2`.
+        ///
+        /// The PICs are not reprogrammed until [`initialize`](ChainedPics::initialize) is called.
+        ///
+        /// ## Safety
+        ///
+        /// This function is unsafe because the caller must ensure that there is only one
+        /// `ChainedPics` instance and that the offsets do not overlap with the CPU exceptions
+        /// or other interrupt vectors.
+        #[inline]
+        pub unsafe fn new(offset1: u8, offset2: u8) -> ChainedPics {
+            ChainedPics {
+                pics: [
+                    Pic {
+                        offset: offset1,
+                        command: Port::new(0x20),
+                        data: Port::new(0x21),
+                    },
+                    Pic {
+                        offset: offset2,
+                        command: Port::new(0xA0),
+                        data: Port::new(0xA1),
+                    },
+                ],
+            }
+        }
+    }
+
+    /// Initializes both PICs with the offsets given to [`new`](ChainedPics::new) and
+    /// restores the previous masks afterwards.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because unmasked interrupts are delivered on the new vectors,
+    /// so the caller must ensure that appropriate handlers are installed for them.
+    pub unsafe fn initialize(&mut self) {
+        // Port 0x80 is unused, writing to it gives the PICs time to process the commands
+        // on older hardware.
+        let mut wait_port: Port<u8> = Port::new(0x80);
+        let mut wait = || wait_port.write(0);
+
+        let masks = self.read_masks();
+
+        self.pics[0].command.write(CMD_INIT);
+        wait();
+        self.pics[1].command.write(CMD_INIT);
+        wait();
+
+        self.pics[0].data.write(self.pics[0].offset);
+        wait();
+        self.pics[1].data.write(self.pics[1].offset);
+        wait();
+
+        // Tell the primary PIC that the secondary PIC is connected to IRQ 2 and the secondary
+        // PIC its cascade identity.
+        self.pics[0].data.write(1 << 2);
+        wait();
+        self.pics[1].data.write(2);
+        wait();
+
+        self.pics[0].data.write(MODE_8086);
+        wait();
+        self.pics[1].data.write(MODE_8086);
+        wait();
+
+        self.write_masks(masks);
+    }
+
+    /// Reads the interrupt masks of the primary and the secondary PIC.
+    ///
+    /// A set bit means that the corresponding IRQ is masked.
+    #[inline]
+    pub fn read_masks(&mut self) -> [u8; 2] {
+        unsafe { [self.pics[0].data.read(), self.pics[1].data.read()] }
+    }
+
+    /// Writes the interrupt masks of the primary and the secondary PIC.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because unmasked interrupts are delivered, so the caller must
+    /// ensure that appropriate handlers are installed for them.
+    #[inline]
+    pub unsafe fn write_masks(&mut self, masks: [u8; 2]) {
+        self.pics[0].data.write(masks[0]);
+        self.pics[1].data.write(masks[1]);
+    }
+
+    /// Masks all IRQs of both PICs, e.g. before switching to the APIC.
+    #[inline]
+    pub fn disable(&mut self) {
+        unsafe { self.write_masks([0xFF, 0xFF]) };
+    }
+
+    /// Masks the given IRQ (0 to 15).
+    ///
+    /// Panics if the IRQ is greater than 15.
+    #[inline]
+    pub fn mask(&mut self, irq: u8) {
+        assert!(irq < 16, "invalid IRQ");
+        let pic = &mut self.pics[usize::from(irq / 8)];
+        unsafe {
+            let mask = pic.data.read();
+            pic.data.write(mask | (1 << (irq % 8)));
+        }
+    }
+
+    /// Unmasks the given IRQ (0 to 15).
+    ///
+    /// Note that IRQs 8 to 15 are only delivered if IRQ 2 of the primary PIC is unmasked too.
+    ///
+    /// Panics if the IRQ is greater than 15.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that an appropriate handler is
+    /// installed for the corresponding vector.
+    #[inline]
+    pub unsafe fn unmask(&mut self, irq: u8) {
+        assert!(irq < 16, "invalid IRQ");
+        let pic = &mut self.pics[usize::from(irq / 8)];
+        let mask = pic.data.read();
+        pic.data.write(mask & !(1 << (irq % 8)));
+    }
+
+    /// Returns whether the given interrupt vector is handled by one of the PICs.
+    #[inline]
+    pub fn handles_interrupt(&self, vector: u8) -> bool {
+        self.pics.iter().any(|pic| pic.handles_interrupt(vector))
+    }
+
+    /// Signals the end of the interrupt with the given vector to the PICs.
+    ///
+    /// For vectors of the secondary PIC, both PICs are notified. Vectors that are not
+    /// handled by the PICs are ignored.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because signaling the end of an interrupt that is still
+    /// being handled can cause nested interrupts.
+    #[inline]
+    pub unsafe fn notify_end_of_interrupt(&mut self, vector: u8) {
+        if self.handles_interrupt(vector) {
+            if self.pics[1].handles_interrupt(vector) {
+                self.pics[1].end_of_interrupt();
+            }
+            self.pics[0].end_of_interrupt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports() {
+        let pics = unsafe { ChainedPics::new(0x20, 0x28) };
+        let ports: [(u16, u16); 2] = [
+            (pics.pics[0].command.port(), pics.pics[0].data.port()),
+            (pics.pics[1].command.port(), pics.pics[1].data.port()),
+        ];
+        assert_eq!(ports, [(0x20, 0x21), (0xA0, 0xA1)]);
+    }
+
+    #[test]
+    fn handles_interrupt() {
+        let pics = unsafe { ChainedPics::new(0x20, 0x28) };
+        assert!((0x20..0x30).all(|vector| pics.handles_interrupt(vector)));
+        assert!(!pics.handles_interrupt(0x1f) && !pics.handles_interrupt(0x30));
+        assert!(pics.pics[1].handles_interrupt(0x28) && !pics.pics[1].handles_interrupt(0x27));
+
+        // the vector range of a PIC can end at the last vector
+        let pics = unsafe { ChainedPics::new(0xf0, 0xf8) };
+        assert!(pics.handles_interrupt(0xff));
+        assert!(!pics.handles_interrupt(0x00) && !pics.handles_interrupt(0xef));
+    }
+}
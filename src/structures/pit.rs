@@ -0,0 +1,297 @@
+//! Provides helpers for programming the legacy 8254 programmable interval timer (PIT).
+//!
+//! The PIT has three channels that count down at a fixed frequency of
+//! [`FREQUENCY`] Hz: channel 0 is connected to IRQ 0 and is usually used as system timer,
+//! channel 1 is obsolete, and channel 2 is connected to the PC speaker. Since the output of
+//! channel 2 can be polled, it is well suited for busy waiting, e.g. for calibrating the APIC
+//! timer or the time stamp counter.
+
+use crate::instructions::port::Port;
+use bit_field::BitField;
+
+/// The frequency of the PIT input clock in Hz.
+pub const FREQUENCY: u32 = 1_193_182;
+
+/// A PIT channel that can be programmed through this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    /// Channel 0, which is connected to IRQ 0.
+    Channel0 = 0,
+    /// Channel 2, which is connected to the PC speaker and can be gated by software.
+    Channel2 = 2,
+}
+
+/// The operating mode of a PIT channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OperatingMode {
+    /// The output goes high once the counter reaches zero (mode 0).
+    InterruptOnTerminalCount = 0b000,
+    /// The output goes low on the rising edge of the gate input and goes high once the
+    /// counter reaches zero (mode 1).
+    HardwareOneShot = 0b001,
+    /// The output goes low for one cycle whenever the counter reaches one, the counter is
+    /// reloaded afterwards (mode 2).
+    RateGenerator = 0b010,
+    /// The output is a square wave with the frequency of the input clock divided by the
+    /// reload value (mode 3).
+    SquareWave = 0b011,
+    /// The output goes low for one cycle once the counter reaches zero (mode 4).
+    SoftwareStrobe = 0b100,
+    /// Like the software strobe, but the counter is started by the rising edge of the gate
+    /// input (mode 5).
+    HardwareStrobe = 0b101,
+}
+
+/// The status of a PIT channel, as returned by the read-back command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ChannelStatus(pub u8);
+
+impl ChannelStatus {
+    /// Returns the state of the output pin of the channel.
+    #[inline]
+    pub fn output(self) -> bool {
+        self.0.get_bit(7)
+    }
+
+    /// Returns whether the reload value was written but is not loaded into the counter yet.
+    #[inline]
+    pub fn null_count(self) -> bool {
+        self.0.get_bit(6)
+    }
+
+    /// Returns the operating mode of the channel.
+    ///
+    /// The modes 6 and 7 are aliases for the modes 2 and 3.
+    #[inline]
+    pub fn operating_mode(self) -> OperatingMode {
+        match self.0.get_bits(1..4) {
+            0b000 => OperatingMode::InterruptOnTerminalCount,
+            0b001 => OperatingMode::HardwareOneShot,
+            0b010 | 0b110 => OperatingMode::RateGenerator,
+            0b011 | 0b111 => OperatingMode::SquareWave,
+            0b100 => OperatingMode::SoftwareStrobe,
+            _ => OperatingMode::HardwareStrobe,
+        }
+    }
+}
+
+/// The I/O ports of the PIT.
+#[derive(Debug)]
+pub struct Pit {
+    channel0: Port<u8>,
+    channel2: Port<u8>,
+    command: Port<u8>,
+    /// The keyboard controller port B, which controls the gate of channel 2.
+    port_b: Port<u8>,
+}
+
+impl Pit {
+    const_fn! {
+        /// Creates a new driver for the PIT.
+        ///
+        /// ## Safety
+        ///
+        /// This function is unsafe because the caller must ensure that there is only one `Pit`
+        /// instance that is used at a time.
+        #[inline]
+        pub unsafe fn new() -> Pit {
+            Pit {
+                channel0: Port::new(0x40),
+                channel2: Port::new(0x42),
+                command: Port::new(0x43),
+                port_b: Port::new(0x61),
+            }
+        }
+    }
+
+    #[inline]
+    fn data_port(&mut self, channel: Channel) -> &mut Port<u8> {
+        match channel {
+            Channel::Channel0 => &mut self.channel0,
+            Channel::Channel2 => &mut self.channel2,
+        }
+    }
+
+    /// Programs the given channel with the given operating mode and reload value.
+    ///
+    /// A reload value of zero is interpreted as 65536. For the periodic modes, the output
+    /// frequency is [`FREQUENCY`] divided by the reload value.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because reprogramming channel 0 changes the rate of IRQ 0.
+    #[inline]
+    pub unsafe fn configure(&mut self, channel: Channel, mode: OperatingMode, reload: u16) {
+        self.command.write(mode_command(channel, mode));
+        let port = self.data_port(channel);
+        port.write(reload as u8);
+        port.write((reload >> 8) as u8);
+    }
+
+    /// Programs channel 0 as a periodic interrupt source with approximately the given
+    /// frequency in Hz.
+    ///
+    /// Frequencies that are too low or too high are clamped to the supported range of about
+    /// 19Hz to [`FREQUENCY`] / 2.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because it changes the rate of IRQ 0.
+    #[inline]
+    pub unsafe fn set_frequency(&mut self, frequency: u32) {
+        self.configure(
+            Channel::Channel0,
+            OperatingMode::RateGenerator,
+            divisor(frequency),
+        );
+    }
+
+    /// Returns the current count of the given channel.
+    #[inline]
+    pub fn read_count(&mut self, channel: Channel) -> u16 {
+        unsafe {
+            self.command.write(latch_command(channel));
+            let port = self.data_port(channel);
+            let low = port.read();
+            let high = port.read();
+            u16::from(low) | u16::from(high) << 8
+        }
+    }
+
+    /// Returns the status of the given channel, using the read-back command.
+    #[inline]
+    pub fn read_status(&mut self, channel: Channel) -> ChannelStatus {
+        unsafe {
+            self.command.write(read_back_status_command(channel));
+            ChannelStatus(self.data_port(channel).read())
+        }
+    }
+
+    /// Busy waits for the given number of microseconds, using channel 2.
+    ///
+    /// The PC speaker is disabled while waiting.
+    pub fn wait(&mut self, microseconds: u64) {
+        let mut ticks = ticks(microseconds);
+        while ticks > 0 {
+            let chunk = ticks.min(0xFFFF);
+            ticks -= chunk;
+            unsafe {
+                // disable the gate of channel 2 and the speaker
+                let port_b = self.port_b.read() & !0b11;
+                self.port_b.write(port_b);
+                self.configure(
+                    Channel::Channel2,
+                    OperatingMode::InterruptOnTerminalCount,
+                    chunk as u16,
+                );
+                // the counter starts counting when the gate goes high
+                self.port_b.write(port_b | 0b01);
+                while !self.port_b.read().get_bit(5) {
+                    core::hint::spin_loop();
+                }
+                self.port_b.write(port_b);
+            }
+        }
+    }
+}
+
+/// Returns the command that sets the operating mode of the given channel, with an access
+/// mode of low byte, then high byte and binary counting.
+#[inline]
+fn mode_command(channel: Channel, mode: OperatingMode) -> u8 {
+    (channel as u8) << 6 | 0b11 << 4 | (mode as u8) << 1
+}
+
+/// Returns the command that latches the count of the given channel.
+#[inline]
+fn latch_command(channel: Channel) -> u8 {
+    (channel as u8) << 6
+}
+
+/// Returns the read-back command that latches the status, but not the count, of the given
+/// channel.
+#[inline]
+fn read_back_status_command(channel: Channel) -> u8 {
+    0b11 << 6 | 1 << 5 | 1 << (channel as u8 + 1)
+}
+
+/// Returns the reload value for the given frequency in Hz, clamped to the valid range of the
+/// rate generator mode.
+#[inline]
+fn divisor(frequency: u32) -> u16 {
+    (FREQUENCY / frequency.max(1)).clamp(2, 0xFFFF) as u16
+}
+
+/// Returns the number of PIT ticks in the given number of microseconds.
+#[inline]
+fn ticks(microseconds: u64) -> u64 {
+    microseconds * u64::from(FREQUENCY) / 1_000_000
+}
+
+/// Busy waits for the given number of microseconds, using channel 2 of the PIT.
+///
+/// This is useful for calibrating other timers, e.g. by passing `|| pit_wait(10_000)` to
+/// [`calibrate_timer`](crate::structures::apic::calibrate_timer).
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that channel 2 of the PIT is not
+/// used concurrently.
+#[inline]
+pub unsafe fn pit_wait(microseconds: u64) {
+    Pit::new().wait(microseconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            mode_command(Channel::Channel0, OperatingMode::RateGenerator),
+            0b0011_0100
+        );
+        assert_eq!(
+            mode_command(Channel::Channel2, OperatingMode::InterruptOnTerminalCount),
+            0b1011_0000
+        );
+        assert_eq!(
+            mode_command(Channel::Channel0, OperatingMode::HardwareStrobe),
+            0b0011_1010
+        );
+        assert_eq!(latch_command(Channel::Channel2), 0b1000_0000);
+        assert_eq!(read_back_status_command(Channel::Channel0), 0b1110_0010);
+        assert_eq!(read_back_status_command(Channel::Channel2), 0b1110_1000);
+    }
+
+    #[test]
+    fn channel_status() {
+        let status = ChannelStatus(1 << 7 | 0b11 << 4 | (OperatingMode::SquareWave as u8) << 1);
+        assert!(status.output() && !status.null_count());
+        assert_eq!(status.operating_mode(), OperatingMode::SquareWave);
+        // modes 6 and 7 are aliases of modes 2 and 3
+        assert_eq!(
+            ChannelStatus(0b110 << 1).operating_mode(),
+            OperatingMode::RateGenerator
+        );
+        assert_eq!(
+            ChannelStatus(1 << 6 | 0b111 << 1).operating_mode(),
+            OperatingMode::SquareWave
+        );
+        assert!(ChannelStatus(1 << 6).null_count());
+    }
+
+    #[test]
+    fn divisors() {
+        assert_eq!(divisor(1000), 1193);
+        assert_eq!(divisor(0), 0xFFFF);
+        assert_eq!(divisor(1), 0xFFFF);
+        assert_eq!(divisor(u32::MAX), 2);
+        assert_eq!(ticks(1_000_000), u64::from(FREQUENCY));
+        assert_eq!(ticks(10_000), 11_931);
+    }
+}
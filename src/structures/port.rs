@@ -12,6 +12,23 @@ pub trait PortRead {
     /// This function is unsafe because the I/O port could have side effects that violate memory
     /// safety.
     unsafe fn read_from_port(port: u16) -> Self;
+
+    /// Reads a `Self` value from the port `N`, which is known at compile time.
+    ///
+    /// This allows implementations to use the immediate encoding of `in` for ports below 256.
+    /// The default implementation calls [`read_from_port`](PortRead::read_from_port).
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the I/O port could have side effects that violate memory
+    /// safety.
+    #[inline]
+    unsafe fn read_from_const_port<const N: u16>() -> Self
+    where
+        Self: Sized,
+    {
+        Self::read_from_port(N)
+    }
 }
 
 /// A helper trait that implements the write port operation.
@@ -26,6 +43,23 @@ pub trait PortWrite {
     /// This function is unsafe because the I/O port could have side effects that violate memory
     /// safety.
     unsafe fn write_to_port(port: u16, value: Self);
+
+    /// Writes a `Self` value to the port `N`, which is known at compile time.
+    ///
+    /// This allows implementations to use the immediate encoding of `out` for ports below
+    /// 256. The default implementation calls [`write_to_port`](PortWrite::write_to_port).
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the I/O port could have side effects that violate memory
+    /// safety.
+    #[inline]
+    unsafe fn write_to_const_port<const N: u16>(value: Self)
+    where
+        Self: Sized,
+    {
+        Self::write_to_port(N, value)
+    }
 }
 
 /// A helper trait that implements the read/write port operations.
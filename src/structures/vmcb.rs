@@ -0,0 +1,1718 @@
+//! The virtual machine control block (VMCB) of AMD SVM, which describes a guest to
+//! [`vmrun`](crate::instructions::svm::vmrun).
+//!
+//! Unlike the VMCS, the VMCB is an ordinary structure in memory: the control area selects the
+//! intercepts and reports the cause of a `#VMEXIT`, the state-save area holds the guest state.
+//! See appendix B "Layout of VMCB" of the AMD APM, volume 2.
+
+use crate::structures::paging::PhysFrame;
+use crate::structures::vmcs::{
+    ExceptionBitmap, GuestSegment, SegmentAccessRights, VirtualApicPage,
+};
+use crate::PhysAddr;
+use bitflags::bitflags;
+use core::fmt;
+
+bitflags! {
+    /// A set of control registers, e.g. the CR read and write intercepts at offset 0x00 of the
+    /// control area.
+    pub struct CrIntercepts: u16 {
+        /// The CR0 register.
+        const CR0 = 1;
+        /// The CR1 register.
+        const CR1 = 1 << 1;
+        /// The CR2 register.
+        const CR2 = 1 << 2;
+        /// The CR3 register.
+        const CR3 = 1 << 3;
+        /// The CR4 register.
+        const CR4 = 1 << 4;
+        /// The CR5 register.
+        const CR5 = 1 << 5;
+        /// The CR6 register.
+        const CR6 = 1 << 6;
+        /// The CR7 register.
+        const CR7 = 1 << 7;
+        /// The CR8 register.
+        const CR8 = 1 << 8;
+        /// The CR9 register.
+        const CR9 = 1 << 9;
+        /// The CR10 register.
+        const CR10 = 1 << 10;
+        /// The CR11 register.
+        const CR11 = 1 << 11;
+        /// The CR12 register.
+        const CR12 = 1 << 12;
+        /// The CR13 register.
+        const CR13 = 1 << 13;
+        /// The CR14 register.
+        const CR14 = 1 << 14;
+        /// The CR15 register.
+        const CR15 = 1 << 15;
+    }
+}
+
+impl_flags_display!(CrIntercepts);
+
+bitflags! {
+    /// A set of debug registers, e.g. the DR read and write intercepts at offset 0x04 of the
+    /// control area.
+    pub struct DrIntercepts: u16 {
+        /// The DR0 register.
+        const DR0 = 1;
+        /// The DR1 register.
+        const DR1 = 1 << 1;
+        /// The DR2 register.
+        const DR2 = 1 << 2;
+        /// The DR3 register.
+        const DR3 = 1 << 3;
+        /// The DR4 register.
+        const DR4 = 1 << 4;
+        /// The DR5 register.
+        const DR5 = 1 << 5;
+        /// The DR6 register.
+        const DR6 = 1 << 6;
+        /// The DR7 register.
+        const DR7 = 1 << 7;
+        /// The DR8 register.
+        const DR8 = 1 << 8;
+        /// The DR9 register.
+        const DR9 = 1 << 9;
+        /// The DR10 register.
+        const DR10 = 1 << 10;
+        /// The DR11 register.
+        const DR11 = 1 << 11;
+        /// The DR12 register.
+        const DR12 = 1 << 12;
+        /// The DR13 register.
+        const DR13 = 1 << 13;
+        /// The DR14 register.
+        const DR14 = 1 << 14;
+        /// The DR15 register.
+        const DR15 = 1 << 15;
+    }
+}
+
+impl_flags_display!(DrIntercepts);
+
+bitflags! {
+    /// The first vector of general intercepts, at offset 0x0C of the control area.
+    pub struct GeneralIntercepts1: u32 {
+        /// Intercepts physical external interrupts.
+        const INTR =               1 << 0;
+        /// Intercepts physical NMIs.
+        const NMI =                1 << 1;
+        /// Intercepts physical SMIs.
+        const SMI =                1 << 2;
+        /// Intercepts INIT signals.
+        const INIT =               1 << 3;
+        /// Intercepts the delivery of virtual interrupts.
+        const VINTR =              1 << 4;
+        /// Intercepts writes to CR0 that change bits other than TS and MP.
+        const CR0_SELECTIVE_WRITE = 1 << 5;
+        /// Intercepts `sidt`.
+        const IDTR_READ =          1 << 6;
+        /// Intercepts `sgdt`.
+        const GDTR_READ =          1 << 7;
+        /// Intercepts `sldt`.
+        const LDTR_READ =          1 << 8;
+        /// Intercepts `str`.
+        const TR_READ =            1 << 9;
+        /// Intercepts `lidt`.
+        const IDTR_WRITE =         1 << 10;
+        /// Intercepts `lgdt`.
+        const GDTR_WRITE =         1 << 11;
+        /// Intercepts `lldt`.
+        const LDTR_WRITE =         1 << 12;
+        /// Intercepts `ltr`.
+        const TR_WRITE =           1 << 13;
+        /// Intercepts `rdtsc`.
+        const RDTSC =              1 << 14;
+        /// Intercepts `rdpmc`.
+        const RDPMC =              1 << 15;
+        /// Intercepts `pushf`.
+        const PUSHF =              1 << 16;
+        /// Intercepts `popf`.
+        const POPF =               1 << 17;
+        /// Intercepts `cpuid`.
+        const CPUID =              1 << 18;
+        /// Intercepts `rsm`.
+        const RSM =                1 << 19;
+        /// Intercepts `iret`.
+        const IRET =               1 << 20;
+        /// Intercepts `int n`.
+        const INTN =               1 << 21;
+        /// Intercepts `invd`.
+        const INVD =               1 << 22;
+        /// Intercepts `pause`, subject to the pause filter.
+        const PAUSE =              1 << 23;
+        /// Intercepts `hlt`.
+        const HLT =                1 << 24;
+        /// Intercepts `invlpg`.
+        const INVLPG =             1 << 25;
+        /// Intercepts `invlpga`.
+        const INVLPGA =            1 << 26;
+        /// Intercepts the I/O ports selected by the I/O permission map.
+        const IOIO_PROT =          1 << 27;
+        /// Intercepts the MSR accesses selected by the MSR permission map.
+        const MSR_PROT =           1 << 28;
+        /// Intercepts task switches.
+        const TASK_SWITCH =        1 << 29;
+        /// Intercepts processor freezes during legacy FERR handling.
+        const FERR_FREEZE =        1 << 30;
+        /// Intercepts shutdown events, e.g. triple faults.
+        const SHUTDOWN =           1 << 31;
+    }
+}
+
+impl_flags_display!(GeneralIntercepts1);
+
+bitflags! {
+    /// The second vector of general intercepts, at offset 0x10 of the control area.
+    ///
+    /// Bits 16 to 31 intercept writes to CR0 to CR15 after they completed, they are available
+    /// through [`cr_write_traps`](VmcbControlArea::cr_write_traps).
+    pub struct GeneralIntercepts2: u32 {
+        /// Intercepts `vmrun`, which must always be set.
+        const VMRUN =              1 << 0;
+        /// Intercepts `vmmcall`.
+        const VMMCALL =            1 << 1;
+        /// Intercepts `vmload`.
+        const VMLOAD =             1 << 2;
+        /// Intercepts `vmsave`.
+        const VMSAVE =             1 << 3;
+        /// Intercepts `stgi`.
+        const STGI =               1 << 4;
+        /// Intercepts `clgi`.
+        const CLGI =               1 << 5;
+        /// Intercepts `skinit`.
+        const SKINIT =             1 << 6;
+        /// Intercepts `rdtscp`.
+        const RDTSCP =             1 << 7;
+        /// Intercepts `int1`.
+        const ICEBP =              1 << 8;
+        /// Intercepts `wbinvd` and `wbnoinvd`.
+        const WBINVD =             1 << 9;
+        /// Intercepts `monitor` and `monitorx`.
+        const MONITOR =            1 << 10;
+        /// Intercepts `mwait` and `mwaitx` unconditionally.
+        const MWAIT =              1 << 11;
+        /// Intercepts `mwait` and `mwaitx` if the monitor hardware is armed.
+        const MWAIT_CONDITIONAL =  1 << 12;
+        /// Intercepts `xsetbv`.
+        const XSETBV =             1 << 13;
+        /// Intercepts `rdpru`.
+        const RDPRU =              1 << 14;
+        /// Intercepts writes to EFER after they completed.
+        const EFER_WRITE_TRAP =    1 << 15;
+    }
+}
+
+impl_flags_display!(GeneralIntercepts2);
+
+bitflags! {
+    /// The third vector of general intercepts, at offset 0x14 of the control area.
+    pub struct GeneralIntercepts3: u32 {
+        /// Intercepts all `invlpgb` instructions.
+        const INVLPGB =            1 << 0;
+        /// Intercepts only `invlpgb` instructions with invalid operands.
+        const INVLPGB_ILLEGAL =    1 << 1;
+        /// Intercepts `invpcid`.
+        const INVPCID =            1 << 2;
+        /// Intercepts `mcommit`.
+        const MCOMMIT =            1 << 3;
+        /// Intercepts `tlbsync`.
+        const TLBSYNC =            1 << 4;
+    }
+}
+
+impl_flags_display!(GeneralIntercepts3);
+
+bitflags! {
+    /// The nested paging and SEV controls at offset 0x90 of the control area.
+    pub struct NestedControl: u64 {
+        /// Enables nested paging with the page table in
+        /// [`nested_cr3`](VmcbControlArea::nested_cr3).
+        const NESTED_PAGING =      1 << 0;
+        /// Enables SEV memory encryption of the guest.
+        const SEV =                1 << 1;
+        /// Enables SEV-ES, which encrypts the state-save area.
+        const SEV_ES =             1 << 2;
+        /// Enables guest mode execute trap for nested page tables.
+        const GMET =               1 << 3;
+        /// Enables supervisor shadow stack checks in nested page tables.
+        const SSS_CHECK =          1 << 4;
+        /// Enables virtual transparent encryption.
+        const VTE =                1 << 5;
+    }
+}
+
+impl_flags_display!(NestedControl);
+
+/// The TLB flush performed on `vmrun`, at offset 0x5C of the control area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TlbControl {
+    /// Doesn't flush the TLB.
+    DoNothing = 0,
+    /// Flushes the entire TLB, including the entries of the host and other guests.
+    FlushAll = 1,
+    /// Flushes the entries of the guest ASID.
+    FlushAsid = 3,
+    /// Flushes the non-global entries of the guest ASID.
+    FlushAsidNonGlobal = 7,
+}
+
+impl TlbControl {
+    /// Decodes the TLB control value, returning `None` for reserved values.
+    #[inline]
+    pub const fn from_raw(raw: u8) -> Option<TlbControl> {
+        match raw {
+            0 => Some(TlbControl::DoNothing),
+            1 => Some(TlbControl::FlushAll),
+            3 => Some(TlbControl::FlushAsid),
+            7 => Some(TlbControl::FlushAsidNonGlobal),
+            _ => None,
+        }
+    }
+}
+
+/// The cause of a `#VMEXIT`, stored in the `EXITCODE` field of the control area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvmExitCode {
+    /// A read of the given control register.
+    CrRead(u8),
+    /// A write of the given control register.
+    CrWrite(u8),
+    /// A read of the given debug register.
+    DrRead(u8),
+    /// A write of the given debug register.
+    DrWrite(u8),
+    /// An exception with the given vector.
+    Exception(u8),
+    /// A physical external interrupt.
+    Intr,
+    /// A physical NMI.
+    Nmi,
+    /// A physical SMI.
+    Smi,
+    /// An INIT signal.
+    Init,
+    /// A virtual interrupt.
+    Vintr,
+    /// A write to CR0 that changed bits other than TS and MP.
+    Cr0SelectiveWrite,
+    /// `sidt`.
+    IdtrRead,
+    /// `sgdt`.
+    GdtrRead,
+    /// `sldt`.
+    LdtrRead,
+    /// `str`.
+    TrRead,
+    /// `lidt`.
+    IdtrWrite,
+    /// `lgdt`.
+    GdtrWrite,
+    /// `lldt`.
+    LdtrWrite,
+    /// `ltr`.
+    TrWrite,
+    /// `rdtsc`.
+    Rdtsc,
+    /// `rdpmc`.
+    Rdpmc,
+    /// `pushf`.
+    Pushf,
+    /// `popf`.
+    Popf,
+    /// `cpuid`.
+    Cpuid,
+    /// `rsm`.
+    Rsm,
+    /// `iret`.
+    Iret,
+    /// `int n`.
+    Swint,
+    /// `invd`.
+    Invd,
+    /// `pause`.
+    Pause,
+    /// `hlt`.
+    Hlt,
+    /// `invlpg`.
+    Invlpg,
+    /// `invlpga`.
+    Invlpga,
+    /// An intercepted I/O port access, described by `EXITINFO1`.
+    Ioio,
+    /// An intercepted MSR access, `EXITINFO1` is 1 for writes.
+    Msr,
+    /// A task switch.
+    TaskSwitch,
+    /// A processor freeze during legacy FERR handling.
+    FerrFreeze,
+    /// A shutdown event, e.g. a triple fault.
+    Shutdown,
+    /// `vmrun`.
+    Vmrun,
+    /// `vmmcall`.
+    Vmmcall,
+    /// `vmload`.
+    Vmload,
+    /// `vmsave`.
+    Vmsave,
+    /// `stgi`.
+    Stgi,
+    /// `clgi`.
+    Clgi,
+    /// `skinit`.
+    Skinit,
+    /// `rdtscp`.
+    Rdtscp,
+    /// `int1`.
+    Icebp,
+    /// `wbinvd` or `wbnoinvd`.
+    Wbinvd,
+    /// `monitor` or `monitorx`.
+    Monitor,
+    /// `mwait` or `mwaitx`.
+    Mwait,
+    /// `mwait` or `mwaitx` with armed monitor hardware.
+    MwaitConditional,
+    /// `xsetbv`.
+    Xsetbv,
+    /// `rdpru`.
+    Rdpru,
+    /// A completed write to EFER, the new value is in `EXITINFO1`.
+    EferWriteTrap,
+    /// A completed write to the given control register, the new value is in `EXITINFO1`.
+    CrWriteTrap(u8),
+    /// A nested page fault, with the error code in `EXITINFO1` and the guest physical
+    /// address in `EXITINFO2`.
+    NestedPageFault,
+    /// An AVIC IPI that couldn't be delivered completely.
+    AvicIncompleteIpi,
+    /// An AVIC access that wasn't accelerated.
+    AvicNoAccel,
+    /// `vmgexit` of an SEV-ES guest.
+    Vmgexit,
+    /// `vmrun` failed because of an invalid guest state.
+    Invalid,
+    /// An exit code that isn't known to this crate.
+    Unknown(u64),
+}
+
+/// The exit codes from `Intr` to `EferWriteTrap`, which are consecutive starting at 0x60.
+const SEQUENTIAL_EXIT_CODES: [SvmExitCode; 48] = [
+    SvmExitCode::Intr,
+    SvmExitCode::Nmi,
+    SvmExitCode::Smi,
+    SvmExitCode::Init,
+    SvmExitCode::Vintr,
+    SvmExitCode::Cr0SelectiveWrite,
+    SvmExitCode::IdtrRead,
+    SvmExitCode::GdtrRead,
+    SvmExitCode::LdtrRead,
+    SvmExitCode::TrRead,
+    SvmExitCode::IdtrWrite,
+    SvmExitCode::GdtrWrite,
+    SvmExitCode::LdtrWrite,
+    SvmExitCode::TrWrite,
+    SvmExitCode::Rdtsc,
+    SvmExitCode::Rdpmc,
+    SvmExitCode::Pushf,
+    SvmExitCode::Popf,
+    SvmExitCode::Cpuid,
+    SvmExitCode::Rsm,
+    SvmExitCode::Iret,
+    SvmExitCode::Swint,
+    SvmExitCode::Invd,
+    SvmExitCode::Pause,
+    SvmExitCode::Hlt,
+    SvmExitCode::Invlpg,
+    SvmExitCode::Invlpga,
+    SvmExitCode::Ioio,
+    SvmExitCode::Msr,
+    SvmExitCode::TaskSwitch,
+    SvmExitCode::FerrFreeze,
+    SvmExitCode::Shutdown,
+    SvmExitCode::Vmrun,
+    SvmExitCode::Vmmcall,
+    SvmExitCode::Vmload,
+    SvmExitCode::Vmsave,
+    SvmExitCode::Stgi,
+    SvmExitCode::Clgi,
+    SvmExitCode::Skinit,
+    SvmExitCode::Rdtscp,
+    SvmExitCode::Icebp,
+    SvmExitCode::Wbinvd,
+    SvmExitCode::Monitor,
+    SvmExitCode::Mwait,
+    SvmExitCode::MwaitConditional,
+    SvmExitCode::Xsetbv,
+    SvmExitCode::Rdpru,
+    SvmExitCode::EferWriteTrap,
+];
+
+impl SvmExitCode {
+    /// Decodes the raw value of the `EXITCODE` field.
+    pub fn from_raw(raw: u64) -> SvmExitCode {
+        let index = raw as u8 & 0xf;
+        match raw {
+            0x00..=0x0f => SvmExitCode::CrRead(index),
+            0x10..=0x1f => SvmExitCode::CrWrite(index),
+            0x20..=0x2f => SvmExitCode::DrRead(index),
+            0x30..=0x3f => SvmExitCode::DrWrite(index),
+            0x40..=0x5f => SvmExitCode::Exception(raw as u8 - 0x40),
+            0x60..=0x8f => SEQUENTIAL_EXIT_CODES[raw as usize - 0x60],
+            0x90..=0x9f => SvmExitCode::CrWriteTrap(index),
+            0x400 => SvmExitCode::NestedPageFault,
+            0x401 => SvmExitCode::AvicIncompleteIpi,
+            0x402 => SvmExitCode::AvicNoAccel,
+            0x403 => SvmExitCode::Vmgexit,
+            u64::MAX => SvmExitCode::Invalid,
+            _ => SvmExitCode::Unknown(raw),
+        }
+    }
+
+    /// Returns the raw value of the `EXITCODE` field.
+    pub fn as_raw(self) -> u64 {
+        match self {
+            SvmExitCode::CrRead(n) => u64::from(n & 0xf),
+            SvmExitCode::CrWrite(n) => 0x10 + u64::from(n & 0xf),
+            SvmExitCode::DrRead(n) => 0x20 + u64::from(n & 0xf),
+            SvmExitCode::DrWrite(n) => 0x30 + u64::from(n & 0xf),
+            SvmExitCode::Exception(vector) => 0x40 + u64::from(vector & 0x1f),
+            SvmExitCode::CrWriteTrap(n) => 0x90 + u64::from(n & 0xf),
+            SvmExitCode::NestedPageFault => 0x400,
+            SvmExitCode::AvicIncompleteIpi => 0x401,
+            SvmExitCode::AvicNoAccel => 0x402,
+            SvmExitCode::Vmgexit => 0x403,
+            SvmExitCode::Invalid => u64::MAX,
+            SvmExitCode::Unknown(raw) => raw,
+            code => {
+                let index = SEQUENTIAL_EXIT_CODES
+                    .iter()
+                    .position(|&c| c == code)
+                    .expect("all other exit codes are sequential");
+                0x60 + index as u64
+            }
+        }
+    }
+}
+
+/// The type of an event in the `EVENTINJ` and `EXITINTINFO` fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SvmEventType {
+    /// An external or virtual interrupt.
+    Interrupt = 0,
+    /// A non-maskable interrupt.
+    Nmi = 2,
+    /// An exception, e.g. a page fault.
+    Exception = 3,
+    /// A software interrupt, caused by `int n`.
+    SoftwareInterrupt = 4,
+}
+
+/// An event in the format of the `EVENTINJ` field, which injects the event on `vmrun`, and the
+/// `EXITINTINFO` field, which reports an event whose delivery caused the `#VMEXIT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SvmEvent(u64);
+
+impl SvmEvent {
+    const VALID: u64 = 1 << 31;
+    const ERROR_CODE_VALID: u64 = 1 << 11;
+
+    /// Creates a valid event of the given type and vector without error code.
+    #[inline]
+    pub const fn new(event_type: SvmEventType, vector: u8) -> Self {
+        SvmEvent(Self::VALID | (event_type as u64) << 8 | vector as u64)
+    }
+
+    /// Returns the event with the given error code, which is pushed by exceptions like
+    /// `#GP` and `#PF`.
+    #[inline]
+    pub const fn with_error_code(self, error_code: u32) -> Self {
+        SvmEvent(self.0 & 0xffff_ffff | Self::ERROR_CODE_VALID | (error_code as u64) << 32)
+    }
+
+    /// Creates an event from the raw value of the field.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        SvmEvent(raw)
+    }
+
+    /// Returns the raw value of the field.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether the event is valid, i.e. whether an event is injected or was being
+    /// delivered.
+    #[inline]
+    pub const fn is_valid(self) -> bool {
+        self.0 & Self::VALID != 0
+    }
+
+    /// Returns the vector of the event.
+    #[inline]
+    pub const fn vector(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns the type of the event, or `None` for reserved types.
+    #[inline]
+    pub const fn event_type(self) -> Option<SvmEventType> {
+        match (self.0 >> 8) & 0b111 {
+            0 => Some(SvmEventType::Interrupt),
+            2 => Some(SvmEventType::Nmi),
+            3 => Some(SvmEventType::Exception),
+            4 => Some(SvmEventType::SoftwareInterrupt),
+            _ => None,
+        }
+    }
+
+    /// Returns the error code of the event, if it has one.
+    #[inline]
+    pub const fn error_code(self) -> Option<u32> {
+        if self.0 & Self::ERROR_CODE_VALID != 0 {
+            Some((self.0 >> 32) as u32)
+        } else {
+            None
+        }
+    }
+}
+
+/// The control area of the VMCB, the first 1KiB.
+#[derive(Clone)]
+#[repr(C)]
+pub struct VmcbControlArea {
+    cr_read_intercepts: u16,
+    cr_write_intercepts: u16,
+    dr_read_intercepts: u16,
+    dr_write_intercepts: u16,
+    exception_intercepts: u32,
+    intercepts_1: u32,
+    intercepts_2: u32,
+    intercepts_3: u32,
+    reserved_1: [u8; 0x24],
+    /// The number of `pause` instructions in a short interval that don't cause an exit.
+    pub pause_filter_threshold: u16,
+    /// The number of `pause` instructions that are ignored before an intercept.
+    pub pause_filter_count: u16,
+    /// The physical address of the 12KiB I/O permission map.
+    pub iopm_base_pa: u64,
+    /// The physical address of the 8KiB MSR permission map.
+    pub msrpm_base_pa: u64,
+    /// The value added to the TSC in the guest.
+    pub tsc_offset: u64,
+    /// The address space identifier of the guest, which must not be 0.
+    pub guest_asid: u32,
+    tlb_control: u8,
+    reserved_2: [u8; 3],
+    /// The virtual interrupt controls: V_TPR in bits 0 to 7, V_IRQ in bit 8, the priority in
+    /// bits 16 to 19, V_INTR_MASKING in bit 24, AVIC_ENABLE in bit 31 and the vector in bits 32
+    /// to 39. AVIC is enabled with [`enable_avic`](Self::enable_avic).
+    pub virtual_interrupt: u64,
+    /// The interrupt shadow of the guest in bit 0, and whether the guest has interrupts
+    /// masked in bit 1.
+    pub interrupt_shadow: u64,
+    exit_code: u64,
+    /// The first exit information field, whose meaning depends on the exit code.
+    pub exit_info_1: u64,
+    /// The second exit information field, whose meaning depends on the exit code.
+    pub exit_info_2: u64,
+    exit_int_info: u64,
+    nested_control: u64,
+    avic_apic_bar: u64,
+    /// The physical address of the guest-host communication block of an SEV-ES guest.
+    pub ghcb_pa: u64,
+    event_injection: u64,
+    /// The nested page table root of the guest, in the format of CR3.
+    pub nested_cr3: u64,
+    /// LBR virtualization in bit 0 and virtualized `vmload`/`vmsave` in bit 1.
+    pub virtualization_extensions: u64,
+    /// The clean bits, which tell the processor which parts of the VMCB weren't modified
+    /// since the last `vmrun` on the same processor.
+    pub clean_bits: u32,
+    reserved_3: u32,
+    /// The address of the next instruction, stored on intercepts of instructions if next RIP
+    /// saving is supported.
+    pub next_rip: u64,
+    /// The number of valid bytes in [`guest_instruction_bytes`](Self::guest_instruction_bytes).
+    pub bytes_fetched: u8,
+    /// The first bytes of the intercepted instruction, stored on nested page faults and
+    /// intercepted exceptions if decode assists are supported.
+    pub guest_instruction_bytes: [u8; 15],
+    avic_backing_page: u64,
+    reserved_4: u64,
+    avic_logical_table: u64,
+    avic_physical_table: u64,
+    reserved_5: u64,
+    /// The physical address of the encrypted state-save area of an SEV-ES guest.
+    pub vmsa_pa: u64,
+    reserved_6: [u8; 0x2f0],
+}
+
+impl VmcbControlArea {
+    const AVIC_ENABLE: u64 = 1 << 31;
+
+    /// Creates a control area with all fields cleared.
+    #[inline]
+    pub const fn new() -> Self {
+        VmcbControlArea {
+            cr_read_intercepts: 0,
+            cr_write_intercepts: 0,
+            dr_read_intercepts: 0,
+            dr_write_intercepts: 0,
+            exception_intercepts: 0,
+            intercepts_1: 0,
+            intercepts_2: 0,
+            intercepts_3: 0,
+            reserved_1: [0; 0x24],
+            pause_filter_threshold: 0,
+            pause_filter_count: 0,
+            iopm_base_pa: 0,
+            msrpm_base_pa: 0,
+            tsc_offset: 0,
+            guest_asid: 0,
+            tlb_control: 0,
+            reserved_2: [0; 3],
+            virtual_interrupt: 0,
+            interrupt_shadow: 0,
+            exit_code: 0,
+            exit_info_1: 0,
+            exit_info_2: 0,
+            exit_int_info: 0,
+            nested_control: 0,
+            avic_apic_bar: 0,
+            ghcb_pa: 0,
+            event_injection: 0,
+            nested_cr3: 0,
+            virtualization_extensions: 0,
+            clean_bits: 0,
+            reserved_3: 0,
+            next_rip: 0,
+            bytes_fetched: 0,
+            guest_instruction_bytes: [0; 15],
+            avic_backing_page: 0,
+            reserved_4: 0,
+            avic_logical_table: 0,
+            avic_physical_table: 0,
+            reserved_5: 0,
+            vmsa_pa: 0,
+            reserved_6: [0; 0x2f0],
+        }
+    }
+
+    /// Returns the control registers whose reads are intercepted.
+    #[inline]
+    pub const fn cr_read_intercepts(&self) -> CrIntercepts {
+        CrIntercepts::from_bits_truncate(self.cr_read_intercepts)
+    }
+
+    /// Sets the control registers whose reads are intercepted.
+    #[inline]
+    pub fn set_cr_read_intercepts(&mut self, registers: CrIntercepts) {
+        self.cr_read_intercepts = registers.bits();
+    }
+
+    /// Returns the control registers whose writes are intercepted before they complete.
+    #[inline]
+    pub const fn cr_write_intercepts(&self) -> CrIntercepts {
+        CrIntercepts::from_bits_truncate(self.cr_write_intercepts)
+    }
+
+    /// Sets the control registers whose writes are intercepted before they complete.
+    #[inline]
+    pub fn set_cr_write_intercepts(&mut self, registers: CrIntercepts) {
+        self.cr_write_intercepts = registers.bits();
+    }
+
+    /// Returns the debug registers whose reads are intercepted.
+    #[inline]
+    pub const fn dr_read_intercepts(&self) -> DrIntercepts {
+        DrIntercepts::from_bits_truncate(self.dr_read_intercepts)
+    }
+
+    /// Sets the debug registers whose reads are intercepted.
+    #[inline]
+    pub fn set_dr_read_intercepts(&mut self, registers: DrIntercepts) {
+        self.dr_read_intercepts = registers.bits();
+    }
+
+    /// Returns the debug registers whose writes are intercepted.
+    #[inline]
+    pub const fn dr_write_intercepts(&self) -> DrIntercepts {
+        DrIntercepts::from_bits_truncate(self.dr_write_intercepts)
+    }
+
+    /// Sets the debug registers whose writes are intercepted.
+    #[inline]
+    pub fn set_dr_write_intercepts(&mut self, registers: DrIntercepts) {
+        self.dr_write_intercepts = registers.bits();
+    }
+
+    /// Returns the intercepted exceptions.
+    #[inline]
+    pub const fn exception_intercepts(&self) -> ExceptionBitmap {
+        ExceptionBitmap::from_bits_truncate(self.exception_intercepts)
+    }
+
+    /// Sets the intercepted exceptions.
+    #[inline]
+    pub fn set_exception_intercepts(&mut self, exceptions: ExceptionBitmap) {
+        self.exception_intercepts = exceptions.bits();
+    }
+
+    /// Returns the first vector of general intercepts.
+    #[inline]
+    pub const fn intercepts_1(&self) -> GeneralIntercepts1 {
+        GeneralIntercepts1::from_bits_truncate(self.intercepts_1)
+    }
+
+    /// Sets the first vector of general intercepts.
+    #[inline]
+    pub fn set_intercepts_1(&mut self, intercepts: GeneralIntercepts1) {
+        self.intercepts_1 = intercepts.bits();
+    }
+
+    /// Returns the second vector of general intercepts, without the CR write traps.
+    #[inline]
+    pub const fn intercepts_2(&self) -> GeneralIntercepts2 {
+        GeneralIntercepts2::from_bits_truncate(self.intercepts_2)
+    }
+
+    /// Sets the second vector of general intercepts, keeping the CR write traps.
+    #[inline]
+    pub fn set_intercepts_2(&mut self, intercepts: GeneralIntercepts2) {
+        self.intercepts_2 = self.intercepts_2 & 0xffff_0000 | intercepts.bits();
+    }
+
+    /// Returns the control registers whose writes are trapped after they complete.
+    #[inline]
+    pub const fn cr_write_traps(&self) -> CrIntercepts {
+        CrIntercepts::from_bits_truncate((self.intercepts_2 >> 16) as u16)
+    }
+
+    /// Sets the control registers whose writes are trapped after they complete.
+    #[inline]
+    pub fn set_cr_write_traps(&mut self, registers: CrIntercepts) {
+        self.intercepts_2 = self.intercepts_2 & 0xffff | u32::from(registers.bits()) << 16;
+    }
+
+    /// Returns the third vector of general intercepts.
+    #[inline]
+    pub const fn intercepts_3(&self) -> GeneralIntercepts3 {
+        GeneralIntercepts3::from_bits_truncate(self.intercepts_3)
+    }
+
+    /// Sets the third vector of general intercepts.
+    #[inline]
+    pub fn set_intercepts_3(&mut self, intercepts: GeneralIntercepts3) {
+        self.intercepts_3 = intercepts.bits();
+    }
+
+    /// Returns the TLB flush performed on `vmrun`, or `None` if the field holds a reserved
+    /// value.
+    #[inline]
+    pub const fn tlb_control(&self) -> Option<TlbControl> {
+        TlbControl::from_raw(self.tlb_control)
+    }
+
+    /// Sets the TLB flush performed on `vmrun`.
+    #[inline]
+    pub fn set_tlb_control(&mut self, control: TlbControl) {
+        self.tlb_control = control as u8;
+    }
+
+    /// Returns the cause of the last `#VMEXIT`.
+    #[inline]
+    pub fn exit_code(&self) -> SvmExitCode {
+        SvmExitCode::from_raw(self.exit_code)
+    }
+
+    /// Sets the exit code, e.g. when emulating a `#VMEXIT` to a nested hypervisor.
+    #[inline]
+    pub fn set_exit_code(&mut self, code: SvmExitCode) {
+        self.exit_code = code.as_raw();
+    }
+
+    /// Returns the event whose delivery caused the last `#VMEXIT`, if any.
+    #[inline]
+    pub const fn exit_int_info(&self) -> Option<SvmEvent> {
+        let event = SvmEvent::from_raw(self.exit_int_info);
+        if event.is_valid() {
+            Some(event)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the nested paging and SEV controls.
+    #[inline]
+    pub const fn nested_control(&self) -> NestedControl {
+        NestedControl::from_bits_truncate(self.nested_control)
+    }
+
+    /// Sets the nested paging and SEV controls.
+    #[inline]
+    pub fn set_nested_control(&mut self, control: NestedControl) {
+        self.nested_control = control.bits();
+    }
+
+    /// Enables nested paging with the given level 4 table, which is written to
+    /// [`nested_cr3`](Self::nested_cr3).
+    ///
+    /// The tables can be set up with
+    /// [`NptMapper`](crate::structures::paging::mapper::NptMapper).
+    #[inline]
+    pub fn enable_nested_paging(&mut self, level_4_table: PhysFrame) {
+        self.nested_cr3 = level_4_table.start_address().as_u64();
+        self.nested_control |= NestedControl::NESTED_PAGING.bits();
+    }
+
+    /// Disables nested paging, so that guest-physical addresses are host-physical addresses.
+    #[inline]
+    pub fn disable_nested_paging(&mut self) {
+        self.nested_control &= !NestedControl::NESTED_PAGING.bits();
+    }
+
+    /// Returns the level 4 table of the nested page tables, or `None` if nested paging is
+    /// disabled.
+    #[inline]
+    pub fn nested_page_table(&self) -> Option<PhysFrame> {
+        if self.nested_control().contains(NestedControl::NESTED_PAGING) {
+            Some(PhysFrame::containing_address(PhysAddr::new(
+                self.nested_cr3 & 0x000f_ffff_ffff_f000,
+            )))
+        } else {
+            None
+        }
+    }
+
+    /// Enables AVIC for the guest, with the given guest-physical address of its local APIC,
+    /// the host-physical frames of its [`AvicBackingPage`], [`AvicLogicalIdTable`] and
+    /// [`AvicPhysicalIdTable`], and the highest valid index of the physical APIC ID table.
+    ///
+    /// The backing page must be the one that the physical APIC ID table entry of the guest
+    /// processor points to.
+    #[inline]
+    pub fn enable_avic(
+        &mut self,
+        apic_base: PhysFrame,
+        backing_page: PhysFrame,
+        logical_table: PhysFrame,
+        physical_table: PhysFrame,
+        max_physical_index: u8,
+    ) {
+        self.avic_apic_bar = apic_base.start_address().as_u64();
+        self.avic_backing_page = backing_page.start_address().as_u64();
+        self.avic_logical_table = logical_table.start_address().as_u64();
+        self.avic_physical_table =
+            physical_table.start_address().as_u64() | u64::from(max_physical_index);
+        self.virtual_interrupt |= Self::AVIC_ENABLE;
+    }
+
+    /// Disables AVIC, so that APIC accesses of the guest are handled by the hypervisor.
+    #[inline]
+    pub fn disable_avic(&mut self) {
+        self.virtual_interrupt &= !Self::AVIC_ENABLE;
+    }
+
+    /// Returns whether AVIC is enabled.
+    #[inline]
+    pub const fn avic_enabled(&self) -> bool {
+        self.virtual_interrupt & Self::AVIC_ENABLE != 0
+    }
+
+    /// Returns the guest-physical address of the local APIC of the guest.
+    #[inline]
+    pub fn avic_apic_base(&self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.avic_apic_bar & AVIC_ADDRESS_MASK))
+    }
+
+    /// Returns the frame of the AVIC backing page.
+    #[inline]
+    pub fn avic_backing_page(&self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.avic_backing_page & AVIC_ADDRESS_MASK))
+    }
+
+    /// Returns the frame of the logical APIC ID table.
+    #[inline]
+    pub fn avic_logical_table(&self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.avic_logical_table & AVIC_ADDRESS_MASK))
+    }
+
+    /// Returns the frame of the physical APIC ID table and its highest valid index.
+    #[inline]
+    pub fn avic_physical_table(&self) -> (PhysFrame, u8) {
+        let frame = PhysFrame::containing_address(PhysAddr::new(
+            self.avic_physical_table & AVIC_ADDRESS_MASK,
+        ));
+        (frame, self.avic_physical_table as u8)
+    }
+
+    /// Returns the event injected on the next `vmrun`, if any.
+    ///
+    /// The processor clears the field when the event was delivered.
+    #[inline]
+    pub const fn event_injection(&self) -> Option<SvmEvent> {
+        let event = SvmEvent::from_raw(self.event_injection);
+        if event.is_valid() {
+            Some(event)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the event injected on the next `vmrun`, or clears the injection.
+    #[inline]
+    pub fn set_event_injection(&mut self, event: Option<SvmEvent>) {
+        self.event_injection = event.map_or(0, SvmEvent::as_raw);
+    }
+}
+
+impl Default for VmcbControlArea {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for VmcbControlArea {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmcbControlArea")
+            .field("cr_read_intercepts", &self.cr_read_intercepts())
+            .field("cr_write_intercepts", &self.cr_write_intercepts())
+            .field("dr_read_intercepts", &self.dr_read_intercepts())
+            .field("dr_write_intercepts", &self.dr_write_intercepts())
+            .field("exception_intercepts", &self.exception_intercepts())
+            .field("intercepts_1", &self.intercepts_1())
+            .field("intercepts_2", &self.intercepts_2())
+            .field("intercepts_3", &self.intercepts_3())
+            .field("guest_asid", &self.guest_asid)
+            .field("tlb_control", &self.tlb_control)
+            .field("exit_code", &self.exit_code())
+            .field("exit_info_1", &self.exit_info_1)
+            .field("exit_info_2", &self.exit_info_2)
+            .field("exit_int_info", &self.exit_int_info())
+            .field("nested_control", &self.nested_control())
+            .field("event_injection", &self.event_injection())
+            .field("next_rip", &self.next_rip)
+            .finish()
+    }
+}
+
+/// A segment register in the state-save area of the VMCB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct VmcbSegment {
+    /// The segment selector.
+    pub selector: u16,
+    /// The access rights in the compressed format of the VMCB: bits 40 to 47 of the segment
+    /// descriptor in bits 0 to 7 and bits 52 to 55 in bits 8 to 11.
+    pub attrib: u16,
+    /// The segment limit in bytes, i.e. already scaled by the granularity.
+    pub limit: u32,
+    /// The base address.
+    pub base: u64,
+}
+
+impl VmcbSegment {
+    /// A segment with all fields cleared, e.g. a null segment.
+    pub const NULL: VmcbSegment = VmcbSegment {
+        selector: 0,
+        attrib: 0,
+        limit: 0,
+        base: 0,
+    };
+
+    /// Returns the access rights in the format of the VMCS.
+    ///
+    /// A segment that isn't present is reported as unusable.
+    #[inline]
+    pub const fn access_rights(&self) -> SegmentAccessRights {
+        let bits = (self.attrib & 0xff) as u32 | ((self.attrib as u32 & 0xf00) << 4);
+        let rights = SegmentAccessRights::from_bits_truncate(bits);
+        if rights.contains(SegmentAccessRights::PRESENT) {
+            rights
+        } else {
+            SegmentAccessRights::from_bits_truncate(bits | SegmentAccessRights::UNUSABLE.bits())
+        }
+    }
+
+    /// Sets the access rights from the format of the VMCS.
+    ///
+    /// Unusable segments are stored with all attributes cleared.
+    #[inline]
+    pub fn set_access_rights(&mut self, rights: SegmentAccessRights) {
+        self.attrib = if rights.contains(SegmentAccessRights::UNUSABLE) {
+            0
+        } else {
+            let bits = rights.bits();
+            (bits & 0xff | (bits >> 4) & 0xf00) as u16
+        };
+    }
+}
+
+impl From<GuestSegment> for VmcbSegment {
+    #[inline]
+    fn from(segment: GuestSegment) -> Self {
+        let mut vmcb_segment = VmcbSegment {
+            selector: segment.selector.0,
+            attrib: 0,
+            limit: segment.limit,
+            base: segment.base,
+        };
+        vmcb_segment.set_access_rights(segment.access_rights);
+        vmcb_segment
+    }
+}
+
+/// The state-save area of the VMCB, which holds the guest state and starts at offset 0x400.
+#[derive(Clone)]
+#[repr(C)]
+pub struct VmcbStateSaveArea {
+    /// The ES segment register.
+    pub es: VmcbSegment,
+    /// The CS segment register.
+    pub cs: VmcbSegment,
+    /// The SS segment register.
+    pub ss: VmcbSegment,
+    /// The DS segment register.
+    pub ds: VmcbSegment,
+    /// The FS segment register.
+    pub fs: VmcbSegment,
+    /// The GS segment register.
+    pub gs: VmcbSegment,
+    /// The GDTR, of which only the limit and base are used.
+    pub gdtr: VmcbSegment,
+    /// The LDTR.
+    pub ldtr: VmcbSegment,
+    /// The IDTR, of which only the limit and base are used.
+    pub idtr: VmcbSegment,
+    /// The task register.
+    pub tr: VmcbSegment,
+    reserved_1: [u8; 0x2b],
+    /// The current privilege level, which must match the DPL of SS.
+    pub cpl: u8,
+    reserved_2: u32,
+    /// The EFER register, in which SVME must be set.
+    pub efer: u64,
+    reserved_3: [u8; 0x70],
+    /// The CR4 register.
+    pub cr4: u64,
+    /// The CR3 register.
+    pub cr3: u64,
+    /// The CR0 register.
+    pub cr0: u64,
+    /// The DR7 register.
+    pub dr7: u64,
+    /// The DR6 register.
+    pub dr6: u64,
+    /// The RFLAGS register.
+    pub rflags: u64,
+    /// The RIP register.
+    pub rip: u64,
+    reserved_4: [u8; 0x58],
+    /// The RSP register.
+    pub rsp: u64,
+    /// The supervisor shadow stack controls (`IA32_S_CET`).
+    pub s_cet: u64,
+    /// The shadow stack pointer.
+    pub ssp: u64,
+    /// The address of the interrupt shadow stack table (`IA32_INTERRUPT_SSP_TABLE_ADDR`).
+    pub isst_addr: u64,
+    /// The RAX register, which isn't part of the registers passed to `vmrun`.
+    pub rax: u64,
+    /// The STAR register.
+    pub star: u64,
+    /// The LSTAR register.
+    pub lstar: u64,
+    /// The CSTAR register.
+    pub cstar: u64,
+    /// The SFMASK register.
+    pub sfmask: u64,
+    /// The KernelGsBase register.
+    pub kernel_gs_base: u64,
+    /// The SYSENTER_CS register.
+    pub sysenter_cs: u64,
+    /// The SYSENTER_ESP register.
+    pub sysenter_esp: u64,
+    /// The SYSENTER_EIP register.
+    pub sysenter_eip: u64,
+    /// The CR2 register.
+    pub cr2: u64,
+    reserved_5: [u8; 0x20],
+    /// The guest PAT, used for nested paging.
+    pub g_pat: u64,
+    /// The DBGCTL register, used with LBR virtualization.
+    pub dbgctl: u64,
+    /// The BR_FROM register, used with LBR virtualization.
+    pub br_from: u64,
+    /// The BR_TO register, used with LBR virtualization.
+    pub br_to: u64,
+    /// The LASTEXCPFROM register, used with LBR virtualization.
+    pub last_excp_from: u64,
+    /// The LASTEXCPTO register, used with LBR virtualization.
+    pub last_excp_to: u64,
+    reserved_6: [u8; 0x968],
+}
+
+impl VmcbStateSaveArea {
+    /// Creates a state-save area with all fields cleared.
+    #[inline]
+    pub const fn new() -> Self {
+        VmcbStateSaveArea {
+            es: VmcbSegment::NULL,
+            cs: VmcbSegment::NULL,
+            ss: VmcbSegment::NULL,
+            ds: VmcbSegment::NULL,
+            fs: VmcbSegment::NULL,
+            gs: VmcbSegment::NULL,
+            gdtr: VmcbSegment::NULL,
+            ldtr: VmcbSegment::NULL,
+            idtr: VmcbSegment::NULL,
+            tr: VmcbSegment::NULL,
+            reserved_1: [0; 0x2b],
+            cpl: 0,
+            reserved_2: 0,
+            efer: 0,
+            reserved_3: [0; 0x70],
+            cr4: 0,
+            cr3: 0,
+            cr0: 0,
+            dr7: 0,
+            dr6: 0,
+            rflags: 0,
+            rip: 0,
+            reserved_4: [0; 0x58],
+            rsp: 0,
+            s_cet: 0,
+            ssp: 0,
+            isst_addr: 0,
+            rax: 0,
+            star: 0,
+            lstar: 0,
+            cstar: 0,
+            sfmask: 0,
+            kernel_gs_base: 0,
+            sysenter_cs: 0,
+            sysenter_esp: 0,
+            sysenter_eip: 0,
+            cr2: 0,
+            reserved_5: [0; 0x20],
+            g_pat: 0,
+            dbgctl: 0,
+            br_from: 0,
+            br_to: 0,
+            last_excp_from: 0,
+            last_excp_to: 0,
+            reserved_6: [0; 0x968],
+        }
+    }
+}
+
+impl Default for VmcbStateSaveArea {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Overwrites the whole state-save area with zeros, e.g. before the VMCB of a destroyed guest
+/// is freed.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for VmcbStateSaveArea {
+    fn zeroize(&mut self) {
+        // the area only consists of integers, so writing a cleared area zeroes every byte
+        unsafe { core::ptr::write_volatile(self, Self::new()) };
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for VmcbStateSaveArea {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmcbStateSaveArea")
+            .field("es", &self.es)
+            .field("cs", &self.cs)
+            .field("ss", &self.ss)
+            .field("ds", &self.ds)
+            .field("fs", &self.fs)
+            .field("gs", &self.gs)
+            .field("gdtr", &self.gdtr)
+            .field("ldtr", &self.ldtr)
+            .field("idtr", &self.idtr)
+            .field("tr", &self.tr)
+            .field("cpl", &self.cpl)
+            .field("efer", &self.efer)
+            .field("cr0", &self.cr0)
+            .field("cr2", &self.cr2)
+            .field("cr3", &self.cr3)
+            .field("cr4", &self.cr4)
+            .field("dr6", &self.dr6)
+            .field("dr7", &self.dr7)
+            .field("rflags", &self.rflags)
+            .field("rip", &self.rip)
+            .field("rsp", &self.rsp)
+            .field("rax", &self.rax)
+            .finish()
+    }
+}
+
+/// The bits of the host-physical addresses in the AVIC fields and table entries.
+const AVIC_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The AVIC backing page of a guest processor, which holds its APIC registers.
+///
+/// The page has the layout of the xAPIC registers, like the virtual-APIC page of VMX. The
+/// processor reads and writes it while the guest runs.
+pub type AvicBackingPage = VirtualApicPage;
+
+/// An entry of the [`AvicPhysicalIdTable`], which locates the backing page of a guest
+/// processor and the host processor that it runs on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct AvicPhysicalIdEntry(u64);
+
+impl AvicPhysicalIdEntry {
+    const HOST_APIC_ID: u64 = 0xfff;
+    const IS_RUNNING: u64 = 1 << 62;
+    const VALID: u64 = 1 << 63;
+
+    /// An entry that isn't valid, so that IPIs to the guest processor cause a `#VMEXIT`.
+    pub const INVALID: AvicPhysicalIdEntry = AvicPhysicalIdEntry(0);
+
+    /// Creates a valid entry for a guest processor with the given backing page, which isn't
+    /// running.
+    #[inline]
+    pub fn new(backing_page: PhysFrame) -> Self {
+        AvicPhysicalIdEntry(Self::VALID | backing_page.start_address().as_u64())
+    }
+
+    /// Creates an entry from its raw value.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        AvicPhysicalIdEntry(raw)
+    }
+
+    /// Returns the raw value of the entry.
+    #[inline]
+    pub const fn as_raw(self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether the entry is valid.
+    #[inline]
+    pub const fn is_valid(self) -> bool {
+        self.0 & Self::VALID != 0
+    }
+
+    /// Returns the frame of the backing page of the guest processor.
+    #[inline]
+    pub fn backing_page(self) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(self.0 & AVIC_ADDRESS_MASK))
+    }
+
+    /// Returns the APIC ID of the host processor that runs the guest processor, or `None`
+    /// if it isn't running.
+    #[inline]
+    pub const fn running_on(self) -> Option<u32> {
+        if self.0 & Self::IS_RUNNING != 0 {
+            Some((self.0 & Self::HOST_APIC_ID) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the entry with the given APIC ID of the host processor that runs the guest
+    /// processor, or with the running flag cleared for `None`.
+    ///
+    /// IPIs to a running guest processor are delivered as doorbell interrupts to the host
+    /// processor, IPIs to a processor that isn't running cause a `#VMEXIT` so that the
+    /// hypervisor can wake it up. AVIC supports 8-bit and x2AVIC 12-bit APIC IDs.
+    #[inline]
+    pub const fn with_running_on(self, host_apic_id: Option<u32>) -> Self {
+        let entry = self.0 & !(Self::IS_RUNNING | Self::HOST_APIC_ID);
+        match host_apic_id {
+            Some(id) => {
+                AvicPhysicalIdEntry(entry | Self::IS_RUNNING | id as u64 & Self::HOST_APIC_ID)
+            }
+            None => AvicPhysicalIdEntry(entry),
+        }
+    }
+}
+
+/// The physical APIC ID table of AVIC, which is indexed by the APIC ID of a guest processor.
+///
+/// The entries are accessed by the processor while guests run, so all accesses are volatile.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct AvicPhysicalIdTable {
+    entries: [AvicPhysicalIdEntry; 512],
+}
+
+impl AvicPhysicalIdTable {
+    /// Creates a table with all entries invalid.
+    #[inline]
+    pub const fn new() -> Self {
+        AvicPhysicalIdTable {
+            entries: [AvicPhysicalIdEntry::INVALID; 512],
+        }
+    }
+
+    /// Returns the entry of the guest processor with the given APIC ID.
+    ///
+    /// Panics if the APIC ID is 512 or higher.
+    #[inline]
+    pub fn get(&self, apic_id: usize) -> AvicPhysicalIdEntry {
+        unsafe { core::ptr::read_volatile(&self.entries[apic_id]) }
+    }
+
+    /// Sets the entry of the guest processor with the given APIC ID.
+    ///
+    /// Panics if the APIC ID is 512 or higher.
+    #[inline]
+    pub fn set(&mut self, apic_id: usize, entry: AvicPhysicalIdEntry) {
+        unsafe { core::ptr::write_volatile(&mut self.entries[apic_id], entry) }
+    }
+}
+
+impl Default for AvicPhysicalIdTable {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AvicPhysicalIdTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                (0..self.entries.len())
+                    .map(|i| (i, self.get(i)))
+                    .filter(|(_, entry)| entry.is_valid()),
+            )
+            .finish()
+    }
+}
+
+/// An entry of the [`AvicLogicalIdTable`], which maps a logical APIC ID to the physical APIC
+/// ID of a guest processor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct AvicLogicalIdEntry(u32);
+
+impl AvicLogicalIdEntry {
+    const VALID: u32 = 1 << 31;
+
+    /// An entry that isn't valid, so that IPIs to the logical APIC ID cause a `#VMEXIT`.
+    pub const INVALID: AvicLogicalIdEntry = AvicLogicalIdEntry(0);
+
+    /// Creates a valid entry for the guest processor with the given physical APIC ID.
+    #[inline]
+    pub const fn new(physical_apic_id: u8) -> Self {
+        AvicLogicalIdEntry(Self::VALID | physical_apic_id as u32)
+    }
+
+    /// Creates an entry from its raw value.
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Self {
+        AvicLogicalIdEntry(raw)
+    }
+
+    /// Returns the raw value of the entry.
+    #[inline]
+    pub const fn as_raw(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the physical APIC ID of the guest processor, or `None` if the entry isn't
+    /// valid.
+    #[inline]
+    pub const fn physical_apic_id(self) -> Option<u8> {
+        if self.0 & Self::VALID != 0 {
+            Some(self.0 as u8)
+        } else {
+            None
+        }
+    }
+}
+
+/// The logical APIC ID table of AVIC, which is used for IPIs in logical destination mode.
+///
+/// The entries are accessed by the processor while guests run, so all accesses are volatile.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct AvicLogicalIdTable {
+    entries: [AvicLogicalIdEntry; 1024],
+}
+
+impl AvicLogicalIdTable {
+    /// Creates a table with all entries invalid.
+    #[inline]
+    pub const fn new() -> Self {
+        AvicLogicalIdTable {
+            entries: [AvicLogicalIdEntry::INVALID; 1024],
+        }
+    }
+
+    /// Returns the index of the entry for the given logical APIC ID, which must select a
+    /// single processor.
+    ///
+    /// In flat mode, the index is the number of the set bit. In cluster mode, the cluster in
+    /// bits 4 to 7 selects a group of four entries, of which the set bit in bits 0 to 3
+    /// selects one. Returns `None` if more or less than one bit is set or the cluster is the
+    /// broadcast cluster 15.
+    #[inline]
+    pub const fn index(logical_apic_id: u8, cluster_mode: bool) -> Option<usize> {
+        if cluster_mode {
+            let (cluster, bits) = (logical_apic_id >> 4, logical_apic_id & 0xf);
+            if cluster == 0xf || bits.count_ones() != 1 {
+                return None;
+            }
+            Some(cluster as usize * 4 + bits.trailing_zeros() as usize)
+        } else if logical_apic_id.count_ones() == 1 {
+            Some(logical_apic_id.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the entry at the given index, see [`index`](Self::index).
+    ///
+    /// Panics if the index is 1024 or higher.
+    #[inline]
+    pub fn get(&self, index: usize) -> AvicLogicalIdEntry {
+        unsafe { core::ptr::read_volatile(&self.entries[index]) }
+    }
+
+    /// Sets the entry at the given index, see [`index`](Self::index).
+    ///
+    /// Panics if the index is 1024 or higher.
+    #[inline]
+    pub fn set(&mut self, index: usize, entry: AvicLogicalIdEntry) {
+        unsafe { core::ptr::write_volatile(&mut self.entries[index], entry) }
+    }
+}
+
+impl Default for AvicLogicalIdTable {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AvicLogicalIdTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                (0..self.entries.len())
+                    .map(|i| (i, self.get(i)))
+                    .filter(|(_, entry)| entry.physical_apic_id().is_some()),
+            )
+            .finish()
+    }
+}
+
+/// The virtual machine control block, a 4KiB aligned page whose physical address is passed
+/// to `vmrun`, `vmload` and `vmsave`.
+#[derive(Debug, Clone, Default)]
+#[repr(C, align(4096))]
+pub struct Vmcb {
+    /// The control area.
+    pub control: VmcbControlArea,
+    /// The state-save area.
+    pub save: VmcbStateSaveArea,
+}
+
+impl Vmcb {
+    /// Creates a VMCB with all fields cleared.
+    #[inline]
+    pub const fn new() -> Self {
+        Vmcb {
+            control: VmcbControlArea::new(),
+            save: VmcbStateSaveArea::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::gdt::{Descriptor, SegmentSelector};
+    use crate::PrivilegeLevel;
+    use core::mem::size_of;
+
+    #[test]
+    fn layout() {
+        assert_eq!(size_of::<VmcbControlArea>(), 0x400);
+        assert_eq!(size_of::<VmcbStateSaveArea>(), 0xc00);
+        assert_eq!(size_of::<Vmcb>(), 4096);
+
+        let vmcb = Vmcb::new();
+        let base = &vmcb as *const Vmcb as usize;
+        let offset = |field: *const u8| field as usize - base;
+        assert_eq!(offset(&vmcb.control.intercepts_1 as *const _ as _), 0x0c);
+        assert_eq!(
+            offset(&vmcb.control.pause_filter_threshold as *const _ as _),
+            0x3c
+        );
+        assert_eq!(offset(&vmcb.control.tlb_control), 0x5c);
+        assert_eq!(offset(&vmcb.control.exit_code as *const _ as _), 0x70);
+        assert_eq!(offset(&vmcb.control.event_injection as *const _ as _), 0xa8);
+        assert_eq!(offset(&vmcb.control.bytes_fetched), 0xd0);
+        assert_eq!(offset(&vmcb.control.vmsa_pa as *const _ as _), 0x108);
+        assert_eq!(
+            offset(&vmcb.control.avic_physical_table as *const _ as _),
+            0xf8
+        );
+        assert_eq!(offset(&vmcb.save.cpl), 0x4cb);
+        assert_eq!(offset(&vmcb.save.efer as *const _ as _), 0x4d0);
+        assert_eq!(offset(&vmcb.save.cr4 as *const _ as _), 0x548);
+        assert_eq!(offset(&vmcb.save.rsp as *const _ as _), 0x5d8);
+        assert_eq!(offset(&vmcb.save.rax as *const _ as _), 0x5f8);
+        assert_eq!(offset(&vmcb.save.cr2 as *const _ as _), 0x640);
+        assert_eq!(offset(&vmcb.save.last_excp_to as *const _ as _), 0x690);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_state_save_area() {
+        use zeroize::Zeroize;
+
+        let mut save = VmcbStateSaveArea::new();
+        save.cs.base = 0x1000;
+        save.cpl = 3;
+        save.rax = 1;
+        save.last_excp_to = 2;
+        save.zeroize();
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &save as *const _ as *const u8,
+                size_of::<VmcbStateSaveArea>(),
+            )
+        };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn exit_codes_round_trip() {
+        for raw in (0..0xa0).chain(0x400..0x404).chain([0x1234, u64::MAX]) {
+            assert_eq!(SvmExitCode::from_raw(raw).as_raw(), raw);
+        }
+        assert_eq!(SvmExitCode::from_raw(0x4e), SvmExitCode::Exception(14));
+        assert_eq!(SvmExitCode::from_raw(0x72), SvmExitCode::Cpuid);
+        assert_eq!(SvmExitCode::from_raw(0x7b), SvmExitCode::Ioio);
+        assert_eq!(SvmExitCode::from_raw(0x81), SvmExitCode::Vmmcall);
+    }
+
+    #[test]
+    fn cr_dr_intercepts() {
+        let mut control = VmcbControlArea::new();
+        control.set_cr_read_intercepts(CrIntercepts::CR0 | CrIntercepts::CR3);
+        control.set_cr_write_intercepts(CrIntercepts::CR8 | CrIntercepts::CR15);
+        control.set_dr_read_intercepts(DrIntercepts::DR6);
+        control.set_dr_write_intercepts(DrIntercepts::DR0 | DrIntercepts::DR7);
+
+        // the four vectors are the first eight bytes of the control area
+        let bytes = unsafe { *(&control as *const _ as *const [u16; 4]) };
+        assert_eq!(bytes, [0x0009, 0x8100, 0x0040, 0x0081]);
+        assert_eq!(
+            control.cr_read_intercepts(),
+            CrIntercepts::CR0 | CrIntercepts::CR3
+        );
+        assert_eq!(
+            control.cr_write_intercepts(),
+            CrIntercepts::CR8 | CrIntercepts::CR15
+        );
+        assert_eq!(control.dr_read_intercepts(), DrIntercepts::DR6);
+        assert_eq!(
+            control.dr_write_intercepts(),
+            DrIntercepts::DR0 | DrIntercepts::DR7
+        );
+    }
+
+    #[test]
+    fn control_accessors() {
+        let mut control = VmcbControlArea::new();
+        control.set_intercepts_2(GeneralIntercepts2::VMRUN | GeneralIntercepts2::VMMCALL);
+        control.set_cr_write_traps(CrIntercepts::CR3);
+        assert_eq!(control.intercepts_2, 0x0008_0003);
+        control.set_intercepts_2(GeneralIntercepts2::VMRUN);
+        assert_eq!(control.cr_write_traps(), CrIntercepts::CR3);
+        control.set_cr_write_intercepts(CrIntercepts::CR0 | CrIntercepts::CR4);
+        control.set_dr_read_intercepts(DrIntercepts::DR7);
+        assert_eq!(control.cr_write_intercepts, 0x11);
+        assert_eq!(control.dr_read_intercepts, 0x80);
+
+        let event = SvmEvent::new(SvmEventType::Exception, 14).with_error_code(2);
+        control.set_event_injection(Some(event));
+        assert_eq!(control.event_injection, 0x0000_0002_8000_0b0e);
+        assert_eq!(control.event_injection(), Some(event));
+        assert_eq!(event.event_type(), Some(SvmEventType::Exception));
+        assert_eq!(event.error_code(), Some(2));
+        control.set_event_injection(None);
+        assert_eq!(control.event_injection(), None);
+
+        let table = PhysFrame::containing_address(PhysAddr::new(0x12_3000));
+        control.enable_nested_paging(table);
+        assert_eq!(control.nested_cr3, 0x12_3000);
+        assert_eq!(control.nested_page_table(), Some(table));
+        control.disable_nested_paging();
+        assert_eq!(control.nested_page_table(), None);
+    }
+
+    #[test]
+    fn segment_attributes() {
+        let descriptor = Descriptor::kernel_code_segment();
+        let selector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+        let segment = VmcbSegment::from(GuestSegment::from_descriptor(selector, &descriptor));
+        assert_eq!(segment.selector, 8);
+        // present, ring 0, accessed code segment, long mode
+        assert_eq!(segment.attrib, 0x299);
+        assert_eq!(
+            segment.access_rights(),
+            SegmentAccessRights::from_descriptor(&descriptor) | SegmentAccessRights::ACCESSED
+        );
+
+        assert!(VmcbSegment::NULL
+            .access_rights()
+            .contains(SegmentAccessRights::UNUSABLE));
+    }
+
+    #[test]
+    fn avic() {
+        let frame = |addr| PhysFrame::containing_address(PhysAddr::new(addr));
+        let mut control = VmcbControlArea::new();
+        control.enable_avic(
+            frame(0xfee0_0000),
+            frame(0x1000),
+            frame(0x2000),
+            frame(0x3000),
+            3,
+        );
+        assert!(control.avic_enabled());
+        assert_eq!(control.virtual_interrupt, 1 << 31);
+        assert_eq!(control.avic_physical_table(), (frame(0x3000), 3));
+        control.disable_avic();
+        assert!(!control.avic_enabled());
+
+        let entry = AvicPhysicalIdEntry::new(frame(0x1000)).with_running_on(Some(5));
+        assert_eq!(entry.as_raw(), 0xc000_0000_0000_1005);
+        assert_eq!(entry.running_on(), Some(5));
+        assert_eq!(entry.with_running_on(None).running_on(), None);
+        assert_eq!(entry.backing_page(), frame(0x1000));
+
+        assert_eq!(AvicLogicalIdTable::index(0x08, false), Some(3));
+        assert_eq!(AvicLogicalIdTable::index(0x24, true), Some(10));
+        assert_eq!(AvicLogicalIdTable::index(0x03, false), None);
+        assert_eq!(AvicLogicalIdTable::index(0xf1, true), None);
+        assert_eq!(AvicLogicalIdEntry::new(2).physical_apic_id(), Some(2));
+    }
+}
@@ -0,0 +1,4204 @@
+//! The virtual machine control structure (VMCS), the encodings of its fields and the types
+//! stored in them.
+//!
+//! The fields are read and written with [`vmread`](crate::instructions::vmx::vmread) and
+//! [`vmwrite`](crate::instructions::vmx::vmwrite). See appendix B "Field Encoding in VMCS" of
+//! the Intel SDM, volume 3.
+
+#[cfg(target_arch = "x86_64")]
+use crate::instructions::{
+    segmentation, tables,
+    vmx::{self, VmInstructionError, VmxError},
+};
+use crate::registers::model_specific::VmxFixedBits;
+#[cfg(target_arch = "x86_64")]
+use crate::registers::{
+    control::{Cr0, Cr3, Cr4},
+    model_specific::{
+        Efer, FsBase, GsBase, SysEnterCs, SysEnterEip, SysEnterEsp, UnsupportedControls, VmxBasic,
+        VmxControl, VmxMisc,
+    },
+};
+use crate::structures::gdt::{Descriptor, SegmentSelector};
+use crate::structures::idt::ExceptionVector;
+#[cfg(target_arch = "x86_64")]
+use crate::structures::idt::PageFaultErrorCode;
+#[cfg(target_arch = "x86_64")]
+use crate::structures::paging::{ept::Eptp, mapper::MapperAllSizes, spp::Spptp};
+#[cfg(target_arch = "x86_64")]
+use crate::structures::vmexit::{ExitReasonField, IdtVectoringInfo};
+use crate::PhysAddr;
+use crate::PrivilegeLevel;
+#[cfg(target_arch = "x86_64")]
+use crate::VirtAddr;
+use bit_field::BitField;
+use bitflags::bitflags;
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Defines the [`VmcsField`] enum with all fields, and an enum per width with the fields of
+/// that width, which converts into a `VmcsField`.
+macro_rules! vmcs_fields {
+    ($(
+        $(#[$width_attr:meta])*
+        $width:ident {
+            $(
+                $(#[$attr:meta])*
+                $field:ident = $encoding:literal,
+            )*
+        }
+    )*) => {
+        /// A field of the VMCS, identified by its encoding.
+        ///
+        /// 64-bit fields are given by the encoding of their full value. The encoding of the
+        /// high 32 bits, which is only needed outside of 64-bit mode, is the full encoding plus
+        /// one.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(u32)]
+        pub enum VmcsField {
+            $($(
+                $(#[$attr])*
+                $field = $encoding,
+            )*)*
+        }
+
+        impl VmcsField {
+            /// All fields, sorted by their encoding.
+            pub const ALL: &'static [VmcsField] = &[$($(VmcsField::$field,)*)*];
+
+            /// Returns the field with the given encoding, or `None` if the encoding isn't the
+            /// full encoding of a known field.
+            #[inline]
+            pub const fn from_encoding(encoding: u32) -> Option<VmcsField> {
+                match encoding {
+                    $($($encoding => Some(VmcsField::$field),)*)*
+                    _ => None,
+                }
+            }
+        }
+
+        $(
+            $(#[$width_attr])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            #[repr(u32)]
+            pub enum $width {
+                $(
+                    $(#[$attr])*
+                    $field = $encoding,
+                )*
+            }
+
+            impl $width {
+                /// Returns the encoding of the field, as passed to `vmread` and `vmwrite`.
+                #[inline]
+                pub const fn encoding(self) -> u32 {
+                    self as u32
+                }
+            }
+
+            impl From<$width> for VmcsField {
+                #[inline]
+                fn from(field: $width) -> VmcsField {
+                    match field {
+                        $($width::$field => VmcsField::$field,)*
+                    }
+                }
+            }
+        )*
+    };
+}
+
+vmcs_fields! {
+    /// A 16-bit field of the VMCS.
+    VmcsField16 {
+        // 16-bit control fields
+        /// Virtual-processor identifier (VPID).
+        VirtualProcessorId = 0x0000,
+        /// Posted-interrupt notification vector.
+        PostedInterruptNotificationVector = 0x0002,
+        /// EPTP index.
+        EptpIndex = 0x0004,
+
+        // 16-bit guest-state fields
+        /// Guest ES selector.
+        GuestEsSelector = 0x0800,
+        /// Guest CS selector.
+        GuestCsSelector = 0x0802,
+        /// Guest SS selector.
+        GuestSsSelector = 0x0804,
+        /// Guest DS selector.
+        GuestDsSelector = 0x0806,
+        /// Guest FS selector.
+        GuestFsSelector = 0x0808,
+        /// Guest GS selector.
+        GuestGsSelector = 0x080a,
+        /// Guest LDTR selector.
+        GuestLdtrSelector = 0x080c,
+        /// Guest TR selector.
+        GuestTrSelector = 0x080e,
+        /// Guest interrupt status.
+        GuestInterruptStatus = 0x0810,
+        /// PML index.
+        PmlIndex = 0x0812,
+
+        // 16-bit host-state fields
+        /// Host ES selector.
+        HostEsSelector = 0x0c00,
+        /// Host CS selector.
+        HostCsSelector = 0x0c02,
+        /// Host SS selector.
+        HostSsSelector = 0x0c04,
+        /// Host DS selector.
+        HostDsSelector = 0x0c06,
+        /// Host FS selector.
+        HostFsSelector = 0x0c08,
+        /// Host GS selector.
+        HostGsSelector = 0x0c0a,
+        /// Host TR selector.
+        HostTrSelector = 0x0c0c,
+    }
+
+    /// A 64-bit field of the VMCS.
+    VmcsField64 {
+        // 64-bit control fields
+        /// Address of I/O bitmap A.
+        IoBitmapA = 0x2000,
+        /// Address of I/O bitmap B.
+        IoBitmapB = 0x2002,
+        /// Address of MSR bitmaps.
+        MsrBitmaps = 0x2004,
+        /// VM-exit MSR-store address.
+        ExitMsrStoreAddress = 0x2006,
+        /// VM-exit MSR-load address.
+        ExitMsrLoadAddress = 0x2008,
+        /// VM-entry MSR-load address.
+        EntryMsrLoadAddress = 0x200a,
+        /// Executive-VMCS pointer.
+        ExecutiveVmcsPointer = 0x200c,
+        /// PML address.
+        PmlAddress = 0x200e,
+        /// TSC offset.
+        TscOffset = 0x2010,
+        /// Virtual-APIC address.
+        VirtualApicAddress = 0x2012,
+        /// APIC-access address.
+        ApicAccessAddress = 0x2014,
+        /// Posted-interrupt descriptor address.
+        PostedInterruptDescriptorAddress = 0x2016,
+        /// VM-function controls.
+        VmFunctionControls = 0x2018,
+        /// EPT pointer.
+        Eptp = 0x201a,
+        /// EOI-exit bitmap 0.
+        EoiExitBitmap0 = 0x201c,
+        /// EOI-exit bitmap 1.
+        EoiExitBitmap1 = 0x201e,
+        /// EOI-exit bitmap 2.
+        EoiExitBitmap2 = 0x2020,
+        /// EOI-exit bitmap 3.
+        EoiExitBitmap3 = 0x2022,
+        /// EPTP-list address.
+        EptpListAddress = 0x2024,
+        /// VMREAD-bitmap address.
+        VmreadBitmapAddress = 0x2026,
+        /// VMWRITE-bitmap address.
+        VmwriteBitmapAddress = 0x2028,
+        /// Virtualization-exception information address.
+        VirtualizationExceptionInformationAddress = 0x202a,
+        /// XSS-exiting bitmap.
+        XssExitingBitmap = 0x202c,
+        /// ENCLS-exiting bitmap.
+        EnclsExitingBitmap = 0x202e,
+        /// Sub-page-permission-table pointer.
+        SubPagePermissionTablePointer = 0x2030,
+        /// TSC multiplier.
+        TscMultiplier = 0x2032,
+        /// Tertiary processor-based VM-execution controls.
+        TertiaryProcessorBasedControls = 0x2034,
+        /// ENCLV-exiting bitmap.
+        EnclvExitingBitmap = 0x2036,
+
+        // 64-bit read-only data fields
+        /// Guest-physical address.
+        GuestPhysicalAddress = 0x2400,
+
+        // 64-bit guest-state fields
+        /// VMCS link pointer.
+        VmcsLinkPointer = 0x2800,
+        /// Guest IA32_DEBUGCTL.
+        GuestIa32Debugctl = 0x2802,
+        /// Guest IA32_PAT.
+        GuestIa32Pat = 0x2804,
+        /// Guest IA32_EFER.
+        GuestIa32Efer = 0x2806,
+        /// Guest IA32_PERF_GLOBAL_CTRL.
+        GuestIa32PerfGlobalCtrl = 0x2808,
+        /// Guest PDPTE0.
+        GuestPdpte0 = 0x280a,
+        /// Guest PDPTE1.
+        GuestPdpte1 = 0x280c,
+        /// Guest PDPTE2.
+        GuestPdpte2 = 0x280e,
+        /// Guest PDPTE3.
+        GuestPdpte3 = 0x2810,
+        /// Guest IA32_BNDCFGS.
+        GuestIa32Bndcfgs = 0x2812,
+        /// Guest IA32_RTIT_CTL.
+        GuestIa32RtitCtl = 0x2814,
+        /// Guest IA32_LBR_CTL.
+        GuestIa32LbrCtl = 0x2816,
+        /// Guest IA32_PKRS.
+        GuestIa32Pkrs = 0x2818,
+
+        // 64-bit host-state fields
+        /// Host IA32_PAT.
+        HostIa32Pat = 0x2c00,
+        /// Host IA32_EFER.
+        HostIa32Efer = 0x2c02,
+        /// Host IA32_PERF_GLOBAL_CTRL.
+        HostIa32PerfGlobalCtrl = 0x2c04,
+        /// Host IA32_PKRS.
+        HostIa32Pkrs = 0x2c06,
+    }
+
+    /// A 32-bit field of the VMCS.
+    VmcsField32 {
+        // 32-bit control fields
+        /// Pin-based VM-execution controls.
+        PinBasedControls = 0x4000,
+        /// Primary processor-based VM-execution controls.
+        PrimaryProcessorBasedControls = 0x4002,
+        /// Exception bitmap.
+        ExceptionBitmap = 0x4004,
+        /// Page-fault error-code mask.
+        PageFaultErrorCodeMask = 0x4006,
+        /// Page-fault error-code match.
+        PageFaultErrorCodeMatch = 0x4008,
+        /// CR3-target count.
+        Cr3TargetCount = 0x400a,
+        /// Primary VM-exit controls.
+        ExitControls = 0x400c,
+        /// VM-exit MSR-store count.
+        ExitMsrStoreCount = 0x400e,
+        /// VM-exit MSR-load count.
+        ExitMsrLoadCount = 0x4010,
+        /// VM-entry controls.
+        EntryControls = 0x4012,
+        /// VM-entry MSR-load count.
+        EntryMsrLoadCount = 0x4014,
+        /// VM-entry interruption-information field.
+        EntryInterruptionInformation = 0x4016,
+        /// VM-entry exception error code.
+        EntryExceptionErrorCode = 0x4018,
+        /// VM-entry instruction length.
+        EntryInstructionLength = 0x401a,
+        /// TPR threshold.
+        TprThreshold = 0x401c,
+        /// Secondary processor-based VM-execution controls.
+        SecondaryProcessorBasedControls = 0x401e,
+        /// PAUSE-loop exiting gap.
+        PleGap = 0x4020,
+        /// PAUSE-loop exiting window.
+        PleWindow = 0x4022,
+
+        // 32-bit read-only data fields
+        /// VM-instruction error.
+        VmInstructionError = 0x4400,
+        /// Exit reason.
+        ExitReason = 0x4402,
+        /// VM-exit interruption information.
+        ExitInterruptionInformation = 0x4404,
+        /// VM-exit interruption error code.
+        ExitInterruptionErrorCode = 0x4406,
+        /// IDT-vectoring information field.
+        IdtVectoringInformation = 0x4408,
+        /// IDT-vectoring error code.
+        IdtVectoringErrorCode = 0x440a,
+        /// VM-exit instruction length.
+        ExitInstructionLength = 0x440c,
+        /// VM-exit instruction information.
+        ExitInstructionInformation = 0x440e,
+
+        // 32-bit guest-state fields
+        /// Guest ES limit.
+        GuestEsLimit = 0x4800,
+        /// Guest CS limit.
+        GuestCsLimit = 0x4802,
+        /// Guest SS limit.
+        GuestSsLimit = 0x4804,
+        /// Guest DS limit.
+        GuestDsLimit = 0x4806,
+        /// Guest FS limit.
+        GuestFsLimit = 0x4808,
+        /// Guest GS limit.
+        GuestGsLimit = 0x480a,
+        /// Guest LDTR limit.
+        GuestLdtrLimit = 0x480c,
+        /// Guest TR limit.
+        GuestTrLimit = 0x480e,
+        /// Guest GDTR limit.
+        GuestGdtrLimit = 0x4810,
+        /// Guest IDTR limit.
+        GuestIdtrLimit = 0x4812,
+        /// Guest ES access rights.
+        GuestEsAccessRights = 0x4814,
+        /// Guest CS access rights.
+        GuestCsAccessRights = 0x4816,
+        /// Guest SS access rights.
+        GuestSsAccessRights = 0x4818,
+        /// Guest DS access rights.
+        GuestDsAccessRights = 0x481a,
+        /// Guest FS access rights.
+        GuestFsAccessRights = 0x481c,
+        /// Guest GS access rights.
+        GuestGsAccessRights = 0x481e,
+        /// Guest LDTR access rights.
+        GuestLdtrAccessRights = 0x4820,
+        /// Guest TR access rights.
+        GuestTrAccessRights = 0x4822,
+        /// Guest interruptibility state.
+        GuestInterruptibilityState = 0x4824,
+        /// Guest activity state.
+        GuestActivityState = 0x4826,
+        /// Guest SMBASE.
+        GuestSmbase = 0x4828,
+        /// Guest IA32_SYSENTER_CS.
+        GuestIa32SysenterCs = 0x482a,
+        /// VMX-preemption timer value.
+        PreemptionTimerValue = 0x482e,
+
+        // 32-bit host-state fields
+        /// Host IA32_SYSENTER_CS.
+        HostIa32SysenterCs = 0x4c00,
+    }
+
+    /// A natural-width field of the VMCS, which is 64 bits wide on processors supporting
+    /// long mode.
+    VmcsFieldNatural {
+        // natural-width control fields
+        /// CR0 guest/host mask.
+        Cr0GuestHostMask = 0x6000,
+        /// CR4 guest/host mask.
+        Cr4GuestHostMask = 0x6002,
+        /// CR0 read shadow.
+        Cr0ReadShadow = 0x6004,
+        /// CR4 read shadow.
+        Cr4ReadShadow = 0x6006,
+        /// CR3-target value 0.
+        Cr3TargetValue0 = 0x6008,
+        /// CR3-target value 1.
+        Cr3TargetValue1 = 0x600a,
+        /// CR3-target value 2.
+        Cr3TargetValue2 = 0x600c,
+        /// CR3-target value 3.
+        Cr3TargetValue3 = 0x600e,
+
+        // natural-width read-only data fields
+        /// Exit qualification.
+        ExitQualification = 0x6400,
+        /// I/O RCX.
+        IoRcx = 0x6402,
+        /// I/O RSI.
+        IoRsi = 0x6404,
+        /// I/O RDI.
+        IoRdi = 0x6406,
+        /// I/O RIP.
+        IoRip = 0x6408,
+        /// Guest-linear address.
+        GuestLinearAddress = 0x640a,
+
+        // natural-width guest-state fields
+        /// Guest CR0.
+        GuestCr0 = 0x6800,
+        /// Guest CR3.
+        GuestCr3 = 0x6802,
+        /// Guest CR4.
+        GuestCr4 = 0x6804,
+        /// Guest ES base.
+        GuestEsBase = 0x6806,
+        /// Guest CS base.
+        GuestCsBase = 0x6808,
+        /// Guest SS base.
+        GuestSsBase = 0x680a,
+        /// Guest DS base.
+        GuestDsBase = 0x680c,
+        /// Guest FS base.
+        GuestFsBase = 0x680e,
+        /// Guest GS base.
+        GuestGsBase = 0x6810,
+        /// Guest LDTR base.
+        GuestLdtrBase = 0x6812,
+        /// Guest TR base.
+        GuestTrBase = 0x6814,
+        /// Guest GDTR base.
+        GuestGdtrBase = 0x6816,
+        /// Guest IDTR base.
+        GuestIdtrBase = 0x6818,
+        /// Guest DR7.
+        GuestDr7 = 0x681a,
+        /// Guest RSP.
+        GuestRsp = 0x681c,
+        /// Guest RIP.
+        GuestRip = 0x681e,
+        /// Guest RFLAGS.
+        GuestRflags = 0x6820,
+        /// Guest pending debug exceptions.
+        GuestPendingDebugExceptions = 0x6822,
+        /// Guest IA32_SYSENTER_ESP.
+        GuestIa32SysenterEsp = 0x6824,
+        /// Guest IA32_SYSENTER_EIP.
+        GuestIa32SysenterEip = 0x6826,
+        /// Guest IA32_S_CET.
+        GuestIa32SCet = 0x6828,
+        /// Guest SSP.
+        GuestSsp = 0x682a,
+        /// Guest IA32_INTERRUPT_SSP_TABLE_ADDR.
+        GuestIa32InterruptSspTableAddr = 0x682c,
+
+        // natural-width host-state fields
+        /// Host CR0.
+        HostCr0 = 0x6c00,
+        /// Host CR3.
+        HostCr3 = 0x6c02,
+        /// Host CR4.
+        HostCr4 = 0x6c04,
+        /// Host FS base.
+        HostFsBase = 0x6c06,
+        /// Host GS base.
+        HostGsBase = 0x6c08,
+        /// Host TR base.
+        HostTrBase = 0x6c0a,
+        /// Host GDTR base.
+        HostGdtrBase = 0x6c0c,
+        /// Host IDTR base.
+        HostIdtrBase = 0x6c0e,
+        /// Host IA32_SYSENTER_ESP.
+        HostIa32SysenterEsp = 0x6c10,
+        /// Host IA32_SYSENTER_EIP.
+        HostIa32SysenterEip = 0x6c12,
+        /// Host RSP.
+        HostRsp = 0x6c14,
+        /// Host RIP.
+        HostRip = 0x6c16,
+        /// Host IA32_S_CET.
+        HostIa32SCet = 0x6c18,
+        /// Host SSP.
+        HostSsp = 0x6c1a,
+        /// Host IA32_INTERRUPT_SSP_TABLE_ADDR.
+        HostIa32InterruptSspTableAddr = 0x6c1c,
+    }
+}
+
+impl VmcsField {
+    /// Returns the encoding of the field, as passed to `vmread` and `vmwrite`.
+    #[inline]
+    pub const fn encoding(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the width of the field, encoded in bits 13 to 14.
+    #[inline]
+    pub const fn width(self) -> VmcsFieldWidth {
+        VmcsFieldEncoding(self.encoding()).width()
+    }
+
+    /// Returns the type of the field, encoded in bits 10 to 11.
+    #[inline]
+    pub const fn field_type(self) -> VmcsFieldType {
+        VmcsFieldEncoding(self.encoding()).field_type()
+    }
+
+    /// Returns the index of the field among the fields of the same width and type, encoded in
+    /// bits 1 to 9.
+    #[inline]
+    pub const fn index(self) -> u16 {
+        VmcsFieldEncoding(self.encoding()).index()
+    }
+
+    /// Returns whether the field is a read-only data field.
+    ///
+    /// Writing these fields fails unless the processor supports writing all VMCS fields, which
+    /// is reported by bit 29 of `IA32_VMX_MISC`.
+    #[inline]
+    pub const fn is_read_only(self) -> bool {
+        matches!(self.field_type(), VmcsFieldType::ReadOnly)
+    }
+}
+
+impl From<VmcsField> for u32 {
+    #[inline]
+    fn from(field: VmcsField) -> u32 {
+        field.encoding()
+    }
+}
+
+/// The width of a VMCS field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmcsFieldWidth {
+    /// A 16-bit field.
+    Bits16,
+    /// A 64-bit field.
+    Bits64,
+    /// A 32-bit field.
+    Bits32,
+    /// A field with the width of the native registers, 64 bits on processors supporting long
+    /// mode.
+    Natural,
+}
+
+/// The type of a VMCS field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmcsFieldType {
+    /// A VM-execution, VM-exit or VM-entry control field.
+    Control,
+    /// A read-only data field holding information about the last VM exit.
+    ReadOnly,
+    /// A guest-state field, loaded on VM entry and saved on VM exit.
+    GuestState,
+    /// A host-state field, loaded on VM exit.
+    HostState,
+}
+
+/// A raw VMCS field encoding, which may also encode the high 32 bits of a 64-bit field or a
+/// field that isn't known to this crate.
+///
+/// This is used e.g. to emulate `vmread` and `vmwrite` for a nested hypervisor, whose operands
+/// are arbitrary encodings. See appendix B "Field Encoding in VMCS" of the Intel SDM, volume 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct VmcsFieldEncoding(pub u32);
+
+impl VmcsFieldEncoding {
+    /// The bits of an encoding that must be zero.
+    const RESERVED: u32 = 0xffff_9000;
+
+    /// Creates the encoding of the field with the given width, type and index.
+    ///
+    /// `high` selects the high 32 bits of a 64-bit field.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the index is larger than 511, or if `high` is set for a field that isn't
+    /// 64 bits wide.
+    #[inline]
+    pub const fn from_parts(
+        width: VmcsFieldWidth,
+        field_type: VmcsFieldType,
+        index: u16,
+        high: bool,
+    ) -> Self {
+        assert!(index < 512, "VMCS field indices are 9 bits wide");
+        assert!(
+            !high || matches!(width, VmcsFieldWidth::Bits64),
+            "only 64-bit fields have a high half"
+        );
+        let width = match width {
+            VmcsFieldWidth::Bits16 => 0,
+            VmcsFieldWidth::Bits64 => 1,
+            VmcsFieldWidth::Bits32 => 2,
+            VmcsFieldWidth::Natural => 3,
+        };
+        let field_type = match field_type {
+            VmcsFieldType::Control => 0,
+            VmcsFieldType::ReadOnly => 1,
+            VmcsFieldType::GuestState => 2,
+            VmcsFieldType::HostState => 3,
+        };
+        VmcsFieldEncoding(width << 13 | field_type << 10 | (index as u32) << 1 | high as u32)
+    }
+
+    /// Returns whether no reserved bits are set, i.e. whether the encoding is well-formed.
+    #[inline]
+    pub const fn is_valid(self) -> bool {
+        self.0 & Self::RESERVED == 0
+            && !(self.is_high() && !matches!(self.width(), VmcsFieldWidth::Bits64))
+    }
+
+    /// Returns the width of the field, encoded in bits 13 to 14.
+    #[inline]
+    pub const fn width(self) -> VmcsFieldWidth {
+        match (self.0 >> 13) & 0b11 {
+            0 => VmcsFieldWidth::Bits16,
+            1 => VmcsFieldWidth::Bits64,
+            2 => VmcsFieldWidth::Bits32,
+            _ => VmcsFieldWidth::Natural,
+        }
+    }
+
+    /// Returns the type of the field, encoded in bits 10 to 11.
+    #[inline]
+    pub const fn field_type(self) -> VmcsFieldType {
+        match (self.0 >> 10) & 0b11 {
+            0 => VmcsFieldType::Control,
+            1 => VmcsFieldType::ReadOnly,
+            2 => VmcsFieldType::GuestState,
+            _ => VmcsFieldType::HostState,
+        }
+    }
+
+    /// Returns the index of the field, encoded in bits 1 to 9.
+    #[inline]
+    pub const fn index(self) -> u16 {
+        ((self.0 >> 1) & 0x1ff) as u16
+    }
+
+    /// Returns whether the encoding selects the high 32 bits of a 64-bit field, which is
+    /// encoded in bit 0.
+    #[inline]
+    pub const fn is_high(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// Returns the encoding of the full field, i.e. with bit 0 cleared.
+    #[inline]
+    pub const fn full(self) -> Self {
+        VmcsFieldEncoding(self.0 & !1)
+    }
+
+    /// Returns the known field with this encoding, or the field whose high 32 bits are
+    /// selected.
+    #[inline]
+    pub const fn field(self) -> Option<VmcsField> {
+        VmcsField::from_encoding(self.full().0)
+    }
+}
+
+impl From<VmcsField> for VmcsFieldEncoding {
+    #[inline]
+    fn from(field: VmcsField) -> Self {
+        VmcsFieldEncoding(field.encoding())
+    }
+}
+
+bitflags! {
+    /// The pin-based VM-execution controls, stored in [`VmcsField32::PinBasedControls`].
+    ///
+    /// The reserved default1 bits 1, 2 and 4 aren't part of these flags. They are set by
+    /// [`Vmcs::set_pin_based_controls`].
+    pub struct PinBasedControls: u32 {
+        /// External interrupts cause VM exits.
+        const EXTERNAL_INTERRUPT_EXITING = 1;
+        /// Non-maskable interrupts cause VM exits.
+        const NMI_EXITING = 1 << 3;
+        /// NMIs are never blocked and blocking of virtual NMIs is tracked, requires
+        /// `NMI_EXITING`.
+        const VIRTUAL_NMIS = 1 << 5;
+        /// Activates the VMX-preemption timer.
+        const ACTIVATE_PREEMPTION_TIMER = 1 << 6;
+        /// Processes posted interrupts, requires the "acknowledge interrupt on exit" VM-exit
+        /// control.
+        const PROCESS_POSTED_INTERRUPTS = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// The primary processor-based VM-execution controls, stored in
+    /// [`VmcsField32::PrimaryProcessorBasedControls`].
+    ///
+    /// The reserved default1 bits 1, 4 to 6, 8, 13, 14 and 26 aren't part of these flags. They
+    /// are set by [`Vmcs::set_primary_proc_based_controls`].
+    pub struct PrimaryProcBasedControls: u32 {
+        /// VM exits occur at the beginning of any instruction if RFLAGS.IF is set and interrupts
+        /// aren't blocked otherwise.
+        const INTERRUPT_WINDOW_EXITING = 1 << 2;
+        /// Adds the TSC offset field to the values of the TSC read by the guest.
+        const USE_TSC_OFFSETTING = 1 << 3;
+        /// `hlt` causes VM exits.
+        const HLT_EXITING = 1 << 7;
+        /// `invlpg` causes VM exits.
+        const INVLPG_EXITING = 1 << 9;
+        /// `mwait` causes VM exits.
+        const MWAIT_EXITING = 1 << 10;
+        /// `rdpmc` causes VM exits.
+        const RDPMC_EXITING = 1 << 11;
+        /// `rdtsc` and `rdtscp` cause VM exits.
+        const RDTSC_EXITING = 1 << 12;
+        /// Writes to CR3 cause VM exits, unless the value is one of the CR3-target values.
+        const CR3_LOAD_EXITING = 1 << 15;
+        /// Reads from CR3 cause VM exits.
+        const CR3_STORE_EXITING = 1 << 16;
+        /// Enables the tertiary processor-based VM-execution controls.
+        const ACTIVATE_TERTIARY_CONTROLS = 1 << 17;
+        /// Writes to CR8 cause VM exits.
+        const CR8_LOAD_EXITING = 1 << 19;
+        /// Reads from CR8 cause VM exits.
+        const CR8_STORE_EXITING = 1 << 20;
+        /// Enables TPR virtualization through the virtual-APIC page.
+        const USE_TPR_SHADOW = 1 << 21;
+        /// VM exits occur at the beginning of any instruction if there is no virtual-NMI blocking.
+        const NMI_WINDOW_EXITING = 1 << 22;
+        /// Moves to and from debug registers cause VM exits.
+        const MOV_DR_EXITING = 1 << 23;
+        /// All I/O instructions cause VM exits, unless the I/O bitmaps are used.
+        const UNCONDITIONAL_IO_EXITING = 1 << 24;
+        /// Uses the I/O bitmaps to decide which I/O instructions cause VM exits.
+        const USE_IO_BITMAPS = 1 << 25;
+        /// Enables the monitor trap flag, which causes a VM exit after each guest instruction.
+        const MONITOR_TRAP_FLAG = 1 << 27;
+        /// Uses the MSR bitmaps to decide which `rdmsr` and `wrmsr` cause VM exits.
+        const USE_MSR_BITMAPS = 1 << 28;
+        /// `monitor` causes VM exits.
+        const MONITOR_EXITING = 1 << 29;
+        /// `pause` causes VM exits.
+        const PAUSE_EXITING = 1 << 30;
+        /// Enables the secondary processor-based VM-execution controls.
+        const ACTIVATE_SECONDARY_CONTROLS = 1 << 31;
+    }
+}
+
+bitflags! {
+    /// The secondary processor-based VM-execution controls, stored in
+    /// [`VmcsField32::SecondaryProcessorBasedControls`].
+    ///
+    /// These controls are only used if `PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS`
+    /// is set.
+    pub struct SecondaryProcBasedControls: u32 {
+        /// Accesses to the APIC-access page are virtualized.
+        const VIRTUALIZE_APIC_ACCESSES = 1;
+        /// Enables extended page tables.
+        const ENABLE_EPT = 1 << 1;
+        /// Loads and stores of the descriptor table registers cause VM exits.
+        const DESCRIPTOR_TABLE_EXITING = 1 << 2;
+        /// Enables `rdtscp` in the guest, which otherwise causes an invalid opcode exception.
+        const ENABLE_RDTSCP = 1 << 3;
+        /// Accesses to the x2APIC MSRs are virtualized.
+        const VIRTUALIZE_X2APIC_MODE = 1 << 4;
+        /// Tags cached translations with the virtual-processor identifier.
+        const ENABLE_VPID = 1 << 5;
+        /// `wbinvd` and `wbnoinvd` cause VM exits.
+        const WBINVD_EXITING = 1 << 6;
+        /// Allows the guest to run in unpaged protected mode or in real-address mode, requires
+        /// `ENABLE_EPT`.
+        const UNRESTRICTED_GUEST = 1 << 7;
+        /// Enables virtualization of the APIC registers.
+        const APIC_REGISTER_VIRTUALIZATION = 1 << 8;
+        /// Enables evaluation and delivery of pending virtual interrupts.
+        const VIRTUAL_INTERRUPT_DELIVERY = 1 << 9;
+        /// Spinning loops of `pause` cause VM exits, see the PLE gap and window fields.
+        const PAUSE_LOOP_EXITING = 1 << 10;
+        /// `rdrand` causes VM exits.
+        const RDRAND_EXITING = 1 << 11;
+        /// Enables `invpcid` in the guest, which otherwise causes an invalid opcode exception.
+        const ENABLE_INVPCID = 1 << 12;
+        /// Enables `vmfunc` in the guest.
+        const ENABLE_VM_FUNCTIONS = 1 << 13;
+        /// `vmread` and `vmwrite` in the guest access the shadow VMCS.
+        const VMCS_SHADOWING = 1 << 14;
+        /// `encls` causes VM exits depending on the ENCLS-exiting bitmap.
+        const ENABLE_ENCLS_EXITING = 1 << 15;
+        /// `rdseed` causes VM exits.
+        const RDSEED_EXITING = 1 << 16;
+        /// Enables page-modification logging.
+        const ENABLE_PML = 1 << 17;
+        /// EPT violations may cause virtualization exceptions instead of VM exits.
+        const EPT_VIOLATION_VE = 1 << 18;
+        /// Hides VMX non-root operation from Intel Processor Trace.
+        const CONCEAL_VMX_FROM_PT = 1 << 19;
+        /// Enables `xsaves` and `xrstors` in the guest.
+        const ENABLE_XSAVES = 1 << 20;
+        /// Separates the EPT execute permissions of supervisor-mode and user-mode addresses.
+        const MODE_BASED_EPT_EXECUTE = 1 << 22;
+        /// Enables write permissions of EPT mappings at a granularity of 128 bytes.
+        const SUB_PAGE_WRITE_PERMISSIONS = 1 << 23;
+        /// Intel Processor Trace uses guest-physical addresses, translated through EPT.
+        const PT_USES_GUEST_PHYSICAL = 1 << 24;
+        /// Multiplies the values of the TSC read by the guest with the TSC multiplier.
+        const USE_TSC_SCALING = 1 << 25;
+        /// Enables `tpause`, `umonitor` and `umwait` in the guest.
+        const ENABLE_USER_WAIT_PAUSE = 1 << 26;
+        /// `enclv` causes VM exits depending on the ENCLV-exiting bitmap.
+        const ENABLE_ENCLV_EXITING = 1 << 28;
+    }
+}
+
+bitflags! {
+    /// The VM-exit controls, stored in [`VmcsField32::ExitControls`].
+    ///
+    /// The reserved default1 bits aren't part of these flags. They are set by
+    /// [`Vmcs::set_exit_controls`].
+    pub struct ExitControls: u32 {
+        /// Saves DR7 and `IA32_DEBUGCTL` on VM exit.
+        const SAVE_DEBUG_CONTROLS = 1 << 2;
+        /// Returns to a host in 64-bit mode.
+        const HOST_ADDRESS_SPACE_SIZE = 1 << 9;
+        /// Loads `IA32_PERF_GLOBAL_CTRL` on VM exit.
+        const LOAD_PERF_GLOBAL_CTRL = 1 << 12;
+        /// Acknowledges the external interrupt that caused the VM exit and stores its vector.
+        const ACK_INTERRUPT_ON_EXIT = 1 << 15;
+        /// Saves the guest `IA32_PAT` on VM exit.
+        const SAVE_PAT = 1 << 18;
+        /// Loads the host `IA32_PAT` on VM exit.
+        const LOAD_PAT = 1 << 19;
+        /// Saves the guest `IA32_EFER` on VM exit.
+        const SAVE_EFER = 1 << 20;
+        /// Loads the host `IA32_EFER` on VM exit.
+        const LOAD_EFER = 1 << 21;
+        /// Saves the value of the VMX-preemption timer on VM exit.
+        const SAVE_PREEMPTION_TIMER = 1 << 22;
+        /// Clears `IA32_BNDCFGS` on VM exit.
+        const CLEAR_BNDCFGS = 1 << 23;
+        /// Hides the VM exit from Intel Processor Trace.
+        const CONCEAL_VMX_FROM_PT = 1 << 24;
+        /// Clears `IA32_RTIT_CTL` on VM exit, which stops tracing of the host.
+        const CLEAR_RTIT_CTL = 1 << 25;
+        /// Clears `IA32_LBR_CTL` on VM exit.
+        const CLEAR_LBR_CTL = 1 << 26;
+        /// Loads the host CET state on VM exit.
+        const LOAD_CET_STATE = 1 << 28;
+        /// Loads the host `IA32_PKRS` on VM exit.
+        const LOAD_PKRS = 1 << 29;
+        /// Saves the guest `IA32_PERF_GLOBAL_CTRL` on VM exit.
+        const SAVE_PERF_GLOBAL_CTRL = 1 << 30;
+        /// Activates the secondary VM-exit controls.
+        const ACTIVATE_SECONDARY_CONTROLS = 1 << 31;
+    }
+}
+
+bitflags! {
+    /// The VM-entry controls, stored in [`VmcsField32::EntryControls`].
+    ///
+    /// The reserved default1 bits aren't part of these flags. They are set by
+    /// [`Vmcs::set_entry_controls`].
+    pub struct EntryControls: u32 {
+        /// Loads DR7 and `IA32_DEBUGCTL` on VM entry.
+        const LOAD_DEBUG_CONTROLS = 1 << 2;
+        /// Enters a guest in IA-32e mode, i.e. with `EFER.LMA` set.
+        const IA32E_MODE_GUEST = 1 << 9;
+        /// Enters system-management mode, for the dual-monitor treatment of SMM.
+        const ENTRY_TO_SMM = 1 << 10;
+        /// Deactivates the dual-monitor treatment of SMM.
+        const DEACTIVATE_DUAL_MONITOR = 1 << 11;
+        /// Loads the guest `IA32_PERF_GLOBAL_CTRL` on VM entry.
+        const LOAD_PERF_GLOBAL_CTRL = 1 << 13;
+        /// Loads the guest `IA32_PAT` on VM entry.
+        const LOAD_PAT = 1 << 14;
+        /// Loads the guest `IA32_EFER` on VM entry.
+        const LOAD_EFER = 1 << 15;
+        /// Loads the guest `IA32_BNDCFGS` on VM entry.
+        const LOAD_BNDCFGS = 1 << 16;
+        /// Hides the VM entry from Intel Processor Trace.
+        const CONCEAL_VMX_FROM_PT = 1 << 17;
+        /// Loads the guest `IA32_RTIT_CTL` on VM entry.
+        const LOAD_RTIT_CTL = 1 << 18;
+        /// Loads the guest CET state on VM entry.
+        const LOAD_CET_STATE = 1 << 20;
+        /// Loads the guest `IA32_LBR_CTL` on VM entry.
+        const LOAD_LBR_CTL = 1 << 21;
+        /// Loads the guest `IA32_PKRS` on VM entry.
+        const LOAD_PKRS = 1 << 22;
+    }
+}
+
+impl_flags_display!(
+    PinBasedControls,
+    PrimaryProcBasedControls,
+    SecondaryProcBasedControls,
+    ExitControls,
+    EntryControls
+);
+
+#[cfg(target_arch = "x86_64")]
+impl SecondaryProcBasedControls {
+    /// Returns the secondary controls that may be set on this processor.
+    ///
+    /// No controls may be set if the secondary controls can't be activated.
+    #[inline]
+    pub fn supported() -> Self {
+        Self::from_bits_truncate(VmxControl::SecondaryProcBased.capability().allowed_1)
+    }
+}
+
+/// The TSC multiplier that doesn't scale the TSC, i.e. 1.0 in the fixed-point format of the
+/// TSC multiplier field with 48 fractional bits.
+pub const TSC_MULTIPLIER_ONE: u64 = 1 << 48;
+
+/// Returns the TSC offset for which the guest reads `guest_tsc` while the TSC of the host is
+/// `host_tsc`.
+///
+/// The multiplier is the value of the TSC multiplier field, or [`TSC_MULTIPLIER_ONE`] if TSC
+/// scaling isn't enabled.
+#[inline]
+pub const fn tsc_offset_for(guest_tsc: u64, host_tsc: u64, multiplier: u64) -> u64 {
+    let scaled = ((host_tsc as u128 * multiplier as u128) >> 48) as u64;
+    guest_tsc.wrapping_sub(scaled)
+}
+
+/// Returns whether the "use TSC scaling" secondary control may be set on this processor.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub fn tsc_scaling_supported() -> bool {
+    SecondaryProcBasedControls::supported().contains(SecondaryProcBasedControls::USE_TSC_SCALING)
+}
+
+bitflags! {
+    /// The exception bitmap, which selects the exceptions that cause VM exits.
+    ///
+    /// Bit `n` corresponds to exception vector `n`. Whether a page fault causes a VM exit also
+    /// depends on the page-fault error-code mask and match fields, see
+    /// [`intercepts_page_fault`](Self::intercepts_page_fault).
+    pub struct ExceptionBitmap: u32 {
+        /// Divide error (`#DE`).
+        const DIVISION =               1 << 0x00;
+        /// Debug exception (`#DB`).
+        const DEBUG =                  1 << 0x01;
+        /// Non-maskable interrupt. NMIs are controlled by the "NMI exiting" pin-based control
+        /// instead, so this bit only matters for `int 2`.
+        const NON_MASKABLE_INTERRUPT = 1 << 0x02;
+        /// Breakpoint (`#BP`).
+        const BREAKPOINT =             1 << 0x03;
+        /// Overflow (`#OF`).
+        const OVERFLOW =               1 << 0x04;
+        /// Bound range exceeded (`#BR`).
+        const BOUND_RANGE =            1 << 0x05;
+        /// Invalid opcode (`#UD`).
+        const INVALID_OPCODE =         1 << 0x06;
+        /// Device not available (`#NM`).
+        const DEVICE_NOT_AVAILABLE =   1 << 0x07;
+        /// Double fault (`#DF`).
+        const DOUBLE =                 1 << 0x08;
+        /// Invalid TSS (`#TS`).
+        const INVALID_TSS =            1 << 0x0A;
+        /// Segment not present (`#NP`).
+        const SEGMENT_NOT_PRESENT =    1 << 0x0B;
+        /// Stack segment fault (`#SS`).
+        const STACK =                  1 << 0x0C;
+        /// General protection fault (`#GP`).
+        const GENERAL_PROTECTION =     1 << 0x0D;
+        /// Page fault (`#PF`).
+        const PAGE =                   1 << 0x0E;
+        /// x87 floating-point exception (`#MF`).
+        const X87_FLOATING_POINT =     1 << 0x10;
+        /// Alignment check (`#AC`).
+        const ALIGNMENT_CHECK =        1 << 0x11;
+        /// Machine check (`#MC`).
+        const MACHINE_CHECK =          1 << 0x12;
+        /// SIMD floating-point exception (`#XM`).
+        const SIMD_FLOATING_POINT =    1 << 0x13;
+        /// Virtualization exception (`#VE`).
+        const VIRTUALIZATION =         1 << 0x14;
+        /// Control protection exception (`#CP`).
+        const CONTROL_PROTECTION =     1 << 0x15;
+        /// Hypervisor injection exception (`#HV`).
+        const HYPERVISOR_INJECTION =   1 << 0x1C;
+        /// VMM communication exception (`#VC`).
+        const VMM_COMMUNICATION =      1 << 0x1D;
+        /// Security exception (`#SX`).
+        const SECURITY =               1 << 0x1E;
+    }
+}
+
+impl_flags_display!(ExceptionBitmap);
+
+impl ExceptionBitmap {
+    /// Returns the bitmap with only the bit of the given exception set.
+    #[inline]
+    pub const fn from_exception(exception: ExceptionVector) -> Self {
+        Self::from_vector(exception.vector())
+    }
+
+    /// Returns the bitmap with only the bit of the given vector set, including reserved
+    /// vectors.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the vector isn't below 32.
+    #[inline]
+    pub const fn from_vector(vector: u8) -> Self {
+        assert!(vector < 32, "exception vectors are below 32");
+        // reserved vectors have no flag, but the bits exist in the VMCS field
+        unsafe { Self::from_bits_unchecked(1 << vector) }
+    }
+
+    /// Returns whether the given vector causes VM exits.
+    ///
+    /// Page faults may be filtered further, see
+    /// [`intercepts_page_fault`](Self::intercepts_page_fault).
+    #[inline]
+    pub const fn intercepts(self, vector: u8) -> bool {
+        vector < 32 && self.bits() & (1 << vector) != 0
+    }
+
+    /// Returns whether a page fault with the given error code causes a VM exit, with the given
+    /// values of the page-fault error-code mask and match fields.
+    ///
+    /// If the masked error code equals the match value, the page fault causes a VM exit if
+    /// the `PAGE` bit is set. Otherwise, it causes a VM exit if the bit is clear. So with the
+    /// `PAGE` bit set, a mask and match of 0 intercept all page faults, and a mask of 0 with a
+    /// match of `u32::MAX` intercepts none.
+    #[inline]
+    pub const fn intercepts_page_fault(self, error_code: u32, mask: u32, matches: u32) -> bool {
+        let page = self.contains(ExceptionBitmap::PAGE);
+        page == ((error_code & mask) == matches)
+    }
+}
+
+bitflags! {
+    /// The access rights of a guest segment in the format of the VMCS, e.g. for
+    /// [`VmcsField32::GuestCsAccessRights`].
+    ///
+    /// The format matches bits 40 to 55 of a segment descriptor, with an additional
+    /// `UNUSABLE` flag.
+    pub struct SegmentAccessRights: u32 {
+        /// Set by the processor when a code or data segment is accessed.
+        const ACCESSED =      1;
+        /// Makes a data segment writable or a code segment readable. For TSS segments, marks
+        /// the TSS as busy.
+        const WRITABLE =      1 << 1;
+        /// Makes a code segment conforming or a data segment expand-down.
+        const CONFORMING =    1 << 2;
+        /// Marks a code segment.
+        const EXECUTABLE =    1 << 3;
+        /// Marks a code or data segment, in contrast to a system segment.
+        const USER_SEGMENT =  1 << 4;
+        /// The descriptor privilege level is ring 3.
+        const DPL_RING_3 =    3 << 5;
+        /// Marks the segment as present.
+        const PRESENT =       1 << 7;
+        /// Available for use by system software.
+        const AVAILABLE =     1 << 12;
+        /// Marks a long mode code segment.
+        const LONG_MODE =     1 << 13;
+        /// Selects 32-bit operands and addresses for protected mode segments.
+        const DEFAULT_SIZE =  1 << 14;
+        /// Scales the limit by 4096.
+        const GRANULARITY =   1 << 15;
+        /// Marks the segment as unusable, e.g. a null segment. The other access rights are
+        /// ignored then, except for the DPL of SS and the `LONG_MODE` flag of CS.
+        const UNUSABLE =      1 << 16;
+    }
+}
+
+impl_flags_display!(SegmentAccessRights);
+
+impl SegmentAccessRights {
+    /// The bits of the access rights that are taken from a segment descriptor.
+    const DESCRIPTOR_MASK: u64 = 0xf0ff;
+
+    /// Decodes the access rights from the low 64 bits of a segment descriptor.
+    #[inline]
+    pub const fn from_descriptor_bits(low: u64) -> Self {
+        Self::from_bits_truncate(((low >> 40) & Self::DESCRIPTOR_MASK) as u32)
+    }
+
+    /// Returns the access rights of the given segment descriptor.
+    #[inline]
+    pub const fn from_descriptor(descriptor: &Descriptor) -> Self {
+        match *descriptor {
+            Descriptor::UserSegment(low) | Descriptor::SystemSegment(low, _) => {
+                Self::from_descriptor_bits(low)
+            }
+        }
+    }
+
+    /// Encodes the access rights as bits 40 to 55 of a segment descriptor.
+    ///
+    /// The `UNUSABLE` flag has no descriptor equivalent and is dropped.
+    #[inline]
+    pub const fn to_descriptor_bits(self) -> u64 {
+        (self.bits() as u64 & Self::DESCRIPTOR_MASK) << 40
+    }
+
+    /// Returns the segment type in bits 0 to 3, e.g. 11 for a busy 64-bit TSS.
+    #[inline]
+    pub const fn segment_type(self) -> u8 {
+        (self.bits() & 0xf) as u8
+    }
+
+    /// Returns the descriptor privilege level.
+    #[inline]
+    pub fn dpl(self) -> PrivilegeLevel {
+        PrivilegeLevel::from_u16(((self.bits() >> 5) & 0b11) as u16)
+    }
+}
+
+/// The state of a guest segment register in the guest-state area of the VMCS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestSegment {
+    /// The segment selector.
+    pub selector: SegmentSelector,
+    /// The base address.
+    pub base: u64,
+    /// The segment limit in bytes, i.e. already scaled by the granularity.
+    pub limit: u32,
+    /// The access rights.
+    pub access_rights: SegmentAccessRights,
+}
+
+impl GuestSegment {
+    /// Returns the state of a segment register loaded with the given selector and descriptor.
+    ///
+    /// The `ACCESSED` flag of code and data segments and the busy flag of available TSS
+    /// segments are set, like the processor does when loading the segment.
+    pub fn from_descriptor(selector: SegmentSelector, descriptor: &Descriptor) -> Self {
+        let (low, high) = match *descriptor {
+            Descriptor::UserSegment(low) => (low, 0),
+            Descriptor::SystemSegment(low, high) => (low, high),
+        };
+        let mut base = low.get_bits(16..40) | low.get_bits(56..64) << 24;
+        if let Descriptor::SystemSegment(..) = descriptor {
+            base |= high.get_bits(0..32) << 32;
+        }
+        let mut access_rights = SegmentAccessRights::from_descriptor_bits(low);
+        if access_rights.contains(SegmentAccessRights::USER_SEGMENT) {
+            access_rights |= SegmentAccessRights::ACCESSED;
+        } else if access_rights.segment_type() == 0b1001 {
+            // the busy flag of a TSS segment is at the position of the writable flag
+            access_rights |= SegmentAccessRights::WRITABLE;
+        }
+        let mut limit = (low.get_bits(0..16) | low.get_bits(48..52) << 16) as u32;
+        if access_rights.contains(SegmentAccessRights::GRANULARITY) {
+            limit = limit << 12 | 0xfff;
+        }
+        GuestSegment {
+            selector,
+            base,
+            limit,
+            access_rights,
+        }
+    }
+
+    /// Returns the state of a segment register loaded with the given selector in
+    /// real-address mode, with a limit of 64KiB.
+    ///
+    /// The given flags are added to the access rights of a present and accessed code or data
+    /// segment, e.g. `EXECUTABLE` for a code segment.
+    #[inline]
+    pub const fn real_mode(selector: u16, flags: SegmentAccessRights) -> Self {
+        let access_rights = SegmentAccessRights::USER_SEGMENT.bits()
+            | SegmentAccessRights::PRESENT.bits()
+            | SegmentAccessRights::ACCESSED.bits()
+            | flags.bits();
+        GuestSegment {
+            selector: SegmentSelector(selector),
+            base: (selector as u64) << 4,
+            limit: 0xffff,
+            access_rights: SegmentAccessRights::from_bits_truncate(access_rights),
+        }
+    }
+
+    /// Returns the state of an unusable segment register, e.g. one loaded with a null
+    /// selector.
+    #[inline]
+    pub const fn unusable() -> Self {
+        GuestSegment {
+            selector: SegmentSelector(0),
+            base: 0,
+            limit: 0,
+            access_rights: SegmentAccessRights::UNUSABLE,
+        }
+    }
+}
+
+/// A segment register of the guest, selecting the VMCS fields of its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestSegmentRegister {
+    /// The ES register.
+    Es,
+    /// The CS register.
+    Cs,
+    /// The SS register.
+    Ss,
+    /// The DS register.
+    Ds,
+    /// The FS register.
+    Fs,
+    /// The GS register.
+    Gs,
+    /// The local descriptor table register.
+    Ldtr,
+    /// The task register.
+    Tr,
+}
+
+impl GuestSegmentRegister {
+    /// Returns the selector, limit, access-rights and base fields of the register.
+    pub const fn fields(self) -> (VmcsField16, VmcsField32, VmcsField32, VmcsFieldNatural) {
+        use GuestSegmentRegister::*;
+        match self {
+            Es => (
+                VmcsField16::GuestEsSelector,
+                VmcsField32::GuestEsLimit,
+                VmcsField32::GuestEsAccessRights,
+                VmcsFieldNatural::GuestEsBase,
+            ),
+            Cs => (
+                VmcsField16::GuestCsSelector,
+                VmcsField32::GuestCsLimit,
+                VmcsField32::GuestCsAccessRights,
+                VmcsFieldNatural::GuestCsBase,
+            ),
+            Ss => (
+                VmcsField16::GuestSsSelector,
+                VmcsField32::GuestSsLimit,
+                VmcsField32::GuestSsAccessRights,
+                VmcsFieldNatural::GuestSsBase,
+            ),
+            Ds => (
+                VmcsField16::GuestDsSelector,
+                VmcsField32::GuestDsLimit,
+                VmcsField32::GuestDsAccessRights,
+                VmcsFieldNatural::GuestDsBase,
+            ),
+            Fs => (
+                VmcsField16::GuestFsSelector,
+                VmcsField32::GuestFsLimit,
+                VmcsField32::GuestFsAccessRights,
+                VmcsFieldNatural::GuestFsBase,
+            ),
+            Gs => (
+                VmcsField16::GuestGsSelector,
+                VmcsField32::GuestGsLimit,
+                VmcsField32::GuestGsAccessRights,
+                VmcsFieldNatural::GuestGsBase,
+            ),
+            Ldtr => (
+                VmcsField16::GuestLdtrSelector,
+                VmcsField32::GuestLdtrLimit,
+                VmcsField32::GuestLdtrAccessRights,
+                VmcsFieldNatural::GuestLdtrBase,
+            ),
+            Tr => (
+                VmcsField16::GuestTrSelector,
+                VmcsField32::GuestTrLimit,
+                VmcsField32::GuestTrAccessRights,
+                VmcsFieldNatural::GuestTrBase,
+            ),
+        }
+    }
+}
+
+/// The type of an event injected on VM entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptionType {
+    /// An external interrupt.
+    ExternalInterrupt = 0,
+    /// A non-maskable interrupt.
+    Nmi = 2,
+    /// A hardware exception, e.g. a page fault.
+    HardwareException = 3,
+    /// A software interrupt, caused by `int n`.
+    SoftwareInterrupt = 4,
+    /// A privileged software exception, caused by `int1`.
+    PrivilegedSoftwareException = 5,
+    /// A software exception, caused by `int3` or `into`.
+    SoftwareException = 6,
+    /// Another event, e.g. a pending monitor trap flag VM exit.
+    OtherEvent = 7,
+}
+
+impl InterruptionType {
+    /// Decodes the interruption type in bits 8 to 10 of an interruption-information field.
+    ///
+    /// Returns `None` for the reserved type 1.
+    #[inline]
+    pub const fn from_raw(raw: u8) -> Option<InterruptionType> {
+        match raw & 0b111 {
+            0 => Some(InterruptionType::ExternalInterrupt),
+            2 => Some(InterruptionType::Nmi),
+            3 => Some(InterruptionType::HardwareException),
+            4 => Some(InterruptionType::SoftwareInterrupt),
+            5 => Some(InterruptionType::PrivilegedSoftwareException),
+            6 => Some(InterruptionType::SoftwareException),
+            7 => Some(InterruptionType::OtherEvent),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the event is caused by an instruction, so that VM entry needs the
+    /// instruction length to compute the return address.
+    #[inline]
+    pub const fn is_software(self) -> bool {
+        matches!(
+            self,
+            InterruptionType::SoftwareInterrupt
+                | InterruptionType::PrivilegedSoftwareException
+                | InterruptionType::SoftwareException
+        )
+    }
+}
+
+/// An event to inject on VM entry, written to the VM-entry interruption-information field and
+/// the related error code and instruction length fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInterruptInfo {
+    vector: u8,
+    kind: InterruptionType,
+    error_code: Option<u32>,
+    instruction_length: u32,
+}
+
+impl EntryInterruptInfo {
+    /// Creates an event of the given vector and type, without an error code.
+    #[inline]
+    pub const fn new(vector: u8, kind: InterruptionType) -> Self {
+        EntryInterruptInfo {
+            vector,
+            kind,
+            error_code: None,
+            instruction_length: 0,
+        }
+    }
+
+    /// Creates an external interrupt with the given vector.
+    #[inline]
+    pub const fn external_interrupt(vector: u8) -> Self {
+        Self::new(vector, InterruptionType::ExternalInterrupt)
+    }
+
+    /// Creates a non-maskable interrupt.
+    #[inline]
+    pub const fn nmi() -> Self {
+        Self::new(2, InterruptionType::Nmi)
+    }
+
+    /// Creates a hardware exception.
+    ///
+    /// The error code of exceptions that push one is 0 until set with
+    /// [`with_error_code`](Self::with_error_code).
+    #[inline]
+    pub fn exception(exception: ExceptionVector) -> Self {
+        let event = Self::new(exception.vector(), InterruptionType::HardwareException);
+        if exception.has_error_code() {
+            event.with_error_code(0)
+        } else {
+            event
+        }
+    }
+
+    /// Creates a software interrupt caused by an `int n` instruction of the given length.
+    #[inline]
+    pub const fn software_interrupt(vector: u8, instruction_length: u32) -> Self {
+        Self::new(vector, InterruptionType::SoftwareInterrupt)
+            .with_instruction_length(instruction_length)
+    }
+
+    /// Delivers the given error code with the event.
+    #[inline]
+    pub const fn with_error_code(mut self, error_code: u32) -> Self {
+        self.error_code = Some(error_code);
+        self
+    }
+
+    /// Sets the length of the instruction that caused a software interrupt or exception.
+    #[inline]
+    pub const fn with_instruction_length(mut self, instruction_length: u32) -> Self {
+        self.instruction_length = instruction_length;
+        self
+    }
+
+    /// Returns the vector of the event.
+    #[inline]
+    pub const fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Returns the type of the event.
+    #[inline]
+    pub const fn interruption_type(&self) -> InterruptionType {
+        self.kind
+    }
+
+    /// Returns the error code that is delivered with the event.
+    #[inline]
+    pub const fn error_code(&self) -> Option<u32> {
+        self.error_code
+    }
+
+    /// Returns the value of the VM-entry interruption-information field, with the valid bit
+    /// set.
+    #[inline]
+    pub const fn raw(&self) -> u32 {
+        let deliver_error_code = if self.error_code.is_some() {
+            1 << 11
+        } else {
+            0
+        };
+        1 << 31 | deliver_error_code | (self.kind as u32) << 8 | self.vector as u32
+    }
+}
+
+/// The fields of the current VMCS, accessed with width-checked accessors.
+///
+/// The field enums of each width only accept values of that width, so that e.g. writing a
+/// 64-bit value to a 16-bit field doesn't compile.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug)]
+pub struct Vmcs {
+    _private: (),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Vmcs {
+    /// Returns the accessors of the current VMCS.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that the processor is in VMX
+    /// root operation and that the current VMCS stays loaded while the returned value is used.
+    #[inline]
+    pub unsafe fn current() -> Vmcs {
+        Vmcs { _private: () }
+    }
+
+    /// Reads a 16-bit field.
+    #[inline]
+    pub fn read16(&self, field: VmcsField16) -> Result<u16, VmxError> {
+        unsafe { vmx::vmread(field.into()) }.map(|value| value as u16)
+    }
+
+    /// Reads a 32-bit field.
+    #[inline]
+    pub fn read32(&self, field: VmcsField32) -> Result<u32, VmxError> {
+        unsafe { vmx::vmread(field.into()) }.map(|value| value as u32)
+    }
+
+    /// Reads a 64-bit field.
+    #[inline]
+    pub fn read64(&self, field: VmcsField64) -> Result<u64, VmxError> {
+        unsafe { vmx::vmread(field.into()) }
+    }
+
+    /// Reads a natural-width field.
+    #[inline]
+    pub fn read_natural(&self, field: VmcsFieldNatural) -> Result<u64, VmxError> {
+        unsafe { vmx::vmread(field.into()) }
+    }
+
+    /// Returns whether the processor supports the given field.
+    ///
+    /// The field is probed with `vmread`, which fails with
+    /// [`UnsupportedVmcsComponent`](VmInstructionError::UnsupportedVmcsComponent) for fields
+    /// the processor doesn't know, e.g. fields of newer VMX features. Other failures are
+    /// returned as errors.
+    pub fn field_supported(&self, field: VmcsField) -> Result<bool, VmxError> {
+        Self::probe_result(unsafe { vmx::vmread(field) })
+    }
+
+    /// Converts the result of probing a field with `vmread` to whether the field is supported.
+    #[inline]
+    fn probe_result(result: Result<u64, VmxError>) -> Result<bool, VmxError> {
+        match result {
+            Ok(_) => Ok(true),
+            Err(VmxError::Valid(VmInstructionError::UnsupportedVmcsComponent)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes a 16-bit field.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that the written value doesn't
+    /// break memory safety of the host, e.g. through the host-state fields.
+    #[inline]
+    pub unsafe fn write16(&mut self, field: VmcsField16, value: u16) -> Result<(), VmxError> {
+        vmx::vmwrite(field.into(), value.into())
+    }
+
+    /// Writes a 32-bit field.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn write32(&mut self, field: VmcsField32, value: u32) -> Result<(), VmxError> {
+        vmx::vmwrite(field.into(), value.into())
+    }
+
+    /// Writes a 64-bit field.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn write64(&mut self, field: VmcsField64, value: u64) -> Result<(), VmxError> {
+        vmx::vmwrite(field.into(), value)
+    }
+
+    /// Writes a natural-width field.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn write_natural(
+        &mut self,
+        field: VmcsFieldNatural,
+        value: u64,
+    ) -> Result<(), VmxError> {
+        vmx::vmwrite(field.into(), value)
+    }
+
+    /// Reads the pin-based VM-execution controls.
+    #[inline]
+    pub fn pin_based_controls(&self) -> Result<PinBasedControls, VmxError> {
+        self.read32(VmcsField32::PinBasedControls)
+            .map(PinBasedControls::from_bits_truncate)
+    }
+
+    /// Writes the pin-based VM-execution controls.
+    ///
+    /// The controls that must be 1 according to the capability registers are set as well.
+    /// Setting a control that isn't supported makes the next VM entry fail.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_pin_based_controls(
+        &mut self,
+        controls: PinBasedControls,
+    ) -> Result<(), VmxError> {
+        let capability = VmxControl::PinBased.capability();
+        self.write32(
+            VmcsField32::PinBasedControls,
+            controls.bits() | capability.allowed_0,
+        )
+    }
+
+    /// Reads the exit reason of the last VM exit.
+    #[inline]
+    pub fn exit_reason(&self) -> Result<ExitReasonField, VmxError> {
+        self.read32(VmcsField32::ExitReason)
+            .map(ExitReasonField::from_raw)
+    }
+
+    /// Injects the given event on the next VM entry.
+    ///
+    /// The error code and instruction length fields are written if the event uses them.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn inject_event(&mut self, event: EntryInterruptInfo) -> Result<(), VmxError> {
+        if let Some(error_code) = event.error_code {
+            self.write32(VmcsField32::EntryExceptionErrorCode, error_code)?;
+        }
+        if event.kind.is_software() {
+            self.write32(
+                VmcsField32::EntryInstructionLength,
+                event.instruction_length,
+            )?;
+        }
+        self.write32(VmcsField32::EntryInterruptionInformation, event.raw())
+    }
+
+    /// Reads the event whose delivery was interrupted by the last VM exit, if any.
+    #[inline]
+    pub fn idt_vectoring_info(&self) -> Result<Option<IdtVectoringInfo>, VmxError> {
+        let info = self.read32(VmcsField32::IdtVectoringInformation)?;
+        if info & (1 << 31) == 0 {
+            return Ok(None);
+        }
+        let error_code = self.read32(VmcsField32::IdtVectoringErrorCode)?;
+        let instruction_length = self.read32(VmcsField32::ExitInstructionLength)?;
+        Ok(IdtVectoringInfo::from_raw(
+            info,
+            error_code,
+            instruction_length,
+        ))
+    }
+
+    /// Reads the primary processor-based VM-execution controls.
+    #[inline]
+    pub fn primary_proc_based_controls(&self) -> Result<PrimaryProcBasedControls, VmxError> {
+        self.read32(VmcsField32::PrimaryProcessorBasedControls)
+            .map(PrimaryProcBasedControls::from_bits_truncate)
+    }
+
+    /// Writes the primary processor-based VM-execution controls.
+    ///
+    /// The controls that must be 1 according to the capability registers are set as well.
+    /// Setting a control that isn't supported makes the next VM entry fail.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_primary_proc_based_controls(
+        &mut self,
+        controls: PrimaryProcBasedControls,
+    ) -> Result<(), VmxError> {
+        let capability = VmxControl::PrimaryProcBased.capability();
+        self.write32(
+            VmcsField32::PrimaryProcessorBasedControls,
+            controls.bits() | capability.allowed_0,
+        )
+    }
+
+    /// Requests a VM exit as soon as the guest can receive external interrupts, by setting the
+    /// "interrupt-window exiting" control.
+    ///
+    /// This is used to inject an interrupt that is pending while the guest blocks interrupts,
+    /// e.g. because RFLAGS.IF is clear. The control stays set until
+    /// [`cancel_interrupt_window`](Self::cancel_interrupt_window) is called.
+    #[inline]
+    pub fn request_interrupt_window(&mut self) -> Result<(), VmxError> {
+        self.update_primary_proc_based_controls(
+            PrimaryProcBasedControls::INTERRUPT_WINDOW_EXITING,
+            true,
+        )
+    }
+
+    /// Clears the "interrupt-window exiting" control, e.g. after the pending interrupt was
+    /// injected.
+    #[inline]
+    pub fn cancel_interrupt_window(&mut self) -> Result<(), VmxError> {
+        self.update_primary_proc_based_controls(
+            PrimaryProcBasedControls::INTERRUPT_WINDOW_EXITING,
+            false,
+        )
+    }
+
+    /// Requests a VM exit as soon as the guest doesn't block NMIs, by setting the "NMI-window
+    /// exiting" control.
+    ///
+    /// The control may only be set if the "virtual NMIs" pin-based control is set, otherwise
+    /// the next VM entry fails. The control stays set until
+    /// [`cancel_nmi_window`](Self::cancel_nmi_window) is called.
+    #[inline]
+    pub fn request_nmi_window(&mut self) -> Result<(), VmxError> {
+        self.update_primary_proc_based_controls(PrimaryProcBasedControls::NMI_WINDOW_EXITING, true)
+    }
+
+    /// Clears the "NMI-window exiting" control, e.g. after the pending NMI was injected.
+    #[inline]
+    pub fn cancel_nmi_window(&mut self) -> Result<(), VmxError> {
+        self.update_primary_proc_based_controls(PrimaryProcBasedControls::NMI_WINDOW_EXITING, false)
+    }
+
+    fn update_primary_proc_based_controls(
+        &mut self,
+        controls: PrimaryProcBasedControls,
+        set: bool,
+    ) -> Result<(), VmxError> {
+        let field = VmcsField32::PrimaryProcessorBasedControls;
+        let current = self.read32(field)?;
+        // the window-exiting controls only cause additional VM exits
+        unsafe { self.write32(field, Self::toggle_controls(current, controls, set)) }
+    }
+
+    /// Sets or clears the given controls in the raw value of the control field, keeping the
+    /// reserved default1 bits.
+    #[inline]
+    fn toggle_controls(raw: u32, controls: PrimaryProcBasedControls, set: bool) -> u32 {
+        if set {
+            raw | controls.bits()
+        } else {
+            raw & !controls.bits()
+        }
+    }
+
+    /// Reads the secondary processor-based VM-execution controls.
+    #[inline]
+    pub fn secondary_proc_based_controls(&self) -> Result<SecondaryProcBasedControls, VmxError> {
+        self.read32(VmcsField32::SecondaryProcessorBasedControls)
+            .map(SecondaryProcBasedControls::from_bits_truncate)
+    }
+
+    /// Writes the secondary processor-based VM-execution controls.
+    ///
+    /// Setting a control that isn't supported makes the next VM entry fail.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_secondary_proc_based_controls(
+        &mut self,
+        controls: SecondaryProcBasedControls,
+    ) -> Result<(), VmxError> {
+        self.write32(
+            VmcsField32::SecondaryProcessorBasedControls,
+            controls.bits(),
+        )
+    }
+
+    /// Reads the VM-exit controls.
+    #[inline]
+    pub fn exit_controls(&self) -> Result<ExitControls, VmxError> {
+        self.read32(VmcsField32::ExitControls)
+            .map(ExitControls::from_bits_truncate)
+    }
+
+    /// Writes the VM-exit controls.
+    ///
+    /// The controls that must be 1 according to the capability registers are set as well.
+    /// Setting a control that isn't supported makes the next VM entry fail.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_exit_controls(&mut self, controls: ExitControls) -> Result<(), VmxError> {
+        let capability = VmxControl::Exit.capability();
+        self.write32(
+            VmcsField32::ExitControls,
+            controls.bits() | capability.allowed_0,
+        )
+    }
+
+    /// Reads the VM-entry controls.
+    #[inline]
+    pub fn entry_controls(&self) -> Result<EntryControls, VmxError> {
+        self.read32(VmcsField32::EntryControls)
+            .map(EntryControls::from_bits_truncate)
+    }
+
+    /// Writes the VM-entry controls.
+    ///
+    /// The controls that must be 1 according to the capability registers are set as well.
+    /// Setting a control that isn't supported makes the next VM entry fail.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_entry_controls(&mut self, controls: EntryControls) -> Result<(), VmxError> {
+        let capability = VmxControl::Entry.capability();
+        self.write32(
+            VmcsField32::EntryControls,
+            controls.bits() | capability.allowed_0,
+        )
+    }
+
+    /// Reads the guest `IA32_RTIT_CTL`.
+    #[inline]
+    pub fn guest_rtit_ctl(&self) -> Result<u64, VmxError> {
+        self.read64(VmcsField64::GuestIa32RtitCtl)
+    }
+
+    /// Writes the guest `IA32_RTIT_CTL`, which is loaded on VM entry if the "load
+    /// IA32_RTIT_CTL" VM-entry control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_guest_rtit_ctl(&mut self, value: u64) -> Result<(), VmxError> {
+        self.write64(VmcsField64::GuestIa32RtitCtl, value)
+    }
+
+    /// Lets the guest use Intel Processor Trace, by switching `IA32_RTIT_CTL` on VM entry and
+    /// VM exit.
+    ///
+    /// The guest value is loaded on VM entry and stored in the guest `IA32_RTIT_CTL` field,
+    /// and the register is cleared on VM exit, so the host has to restore its own value
+    /// afterwards. VM entries and exits are concealed from the trace, and the guest trace uses
+    /// guest-physical addresses. The other RTIT MSRs aren't switched, which can be done with
+    /// the MSR load and store lists. The secondary controls are activated if needed.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, EPT must be enabled, since the trace
+    /// output addresses of the guest are translated through it.
+    pub unsafe fn enable_pt_passthrough(
+        &mut self,
+        guest_rtit_ctl: u64,
+    ) -> Result<(), VmcsSetupError> {
+        let secondary_required = SecondaryProcBasedControls::CONCEAL_VMX_FROM_PT
+            | SecondaryProcBasedControls::PT_USES_GUEST_PHYSICAL;
+        let exit_required = ExitControls::CONCEAL_VMX_FROM_PT | ExitControls::CLEAR_RTIT_CTL;
+        let entry_required = EntryControls::CONCEAL_VMX_FROM_PT | EntryControls::LOAD_RTIT_CTL;
+        for &(control, required) in &[
+            (
+                VmxControl::PrimaryProcBased,
+                PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS.bits(),
+            ),
+            (VmxControl::SecondaryProcBased, secondary_required.bits()),
+            (VmxControl::Exit, exit_required.bits()),
+            (VmxControl::Entry, entry_required.bits()),
+        ] {
+            let unsupported = required & !control.capability().allowed_1;
+            if unsupported != 0 {
+                return Err(UnsupportedControls {
+                    control,
+                    bits: unsupported,
+                }
+                .into());
+            }
+        }
+
+        self.set_guest_rtit_ctl(guest_rtit_ctl)?;
+        let primary = self.primary_proc_based_controls()?;
+        self.set_primary_proc_based_controls(
+            primary | PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS,
+        )?;
+        let secondary = self.secondary_proc_based_controls()?;
+        self.set_secondary_proc_based_controls(secondary | secondary_required)?;
+        let exit = self.exit_controls()?;
+        self.set_exit_controls(exit | exit_required)?;
+        let entry = self.entry_controls()?;
+        self.set_entry_controls(entry | entry_required)?;
+        Ok(())
+    }
+
+    /// Reads the EPT pointer.
+    #[inline]
+    pub fn eptp(&self) -> Result<Eptp, VmxError> {
+        self.read64(VmcsField64::Eptp).map(Eptp::from_raw)
+    }
+
+    /// Writes the EPT pointer, which is used if the "enable EPT" secondary control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the referenced EPT tables must not map
+    /// host memory that the guest must not access.
+    #[inline]
+    pub unsafe fn set_eptp(&mut self, eptp: Eptp) -> Result<(), VmxError> {
+        self.write64(VmcsField64::Eptp, eptp.as_raw())
+    }
+
+    /// Writes the SPP table pointer, which is used for pages with sub-page write permissions
+    /// if the "sub-page write permissions for EPT" secondary control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the referenced SPP tables must stay valid
+    /// while they are used.
+    #[inline]
+    pub unsafe fn set_spptp(&mut self, spptp: Spptp) -> Result<(), VmxError> {
+        self.write64(VmcsField64::SubPagePermissionTablePointer, spptp.as_raw())
+    }
+
+    /// Writes the physical address of the MSR bitmap, which is used if the "use MSR bitmaps"
+    /// control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the bitmap must stay valid while it is
+    /// used.
+    #[inline]
+    pub unsafe fn set_msr_bitmap(&mut self, addr: PhysAddr) -> Result<(), VmxError> {
+        self.write64(VmcsField64::MsrBitmaps, addr.as_u64())
+    }
+
+    /// Writes the physical addresses of the I/O bitmaps A and B, which are used if the "use
+    /// I/O bitmaps" control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the bitmaps must stay valid while they
+    /// are used.
+    #[inline]
+    pub unsafe fn set_io_bitmaps(&mut self, a: PhysAddr, b: PhysAddr) -> Result<(), VmxError> {
+        self.write64(VmcsField64::IoBitmapA, a.as_u64())?;
+        self.write64(VmcsField64::IoBitmapB, b.as_u64())
+    }
+
+    /// Writes the physical address of the virtual-APIC page, which is used if the "use TPR
+    /// shadow" control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the page must stay valid while it is
+    /// used, since the processor writes to it.
+    #[inline]
+    pub unsafe fn set_virtual_apic_address(&mut self, addr: PhysAddr) -> Result<(), VmxError> {
+        self.write64(VmcsField64::VirtualApicAddress, addr.as_u64())
+    }
+
+    /// Writes the TPR threshold: a VM exit occurs when the guest lowers bits 7:4 of its
+    /// virtual task priority below the threshold, unless virtual-interrupt delivery is
+    /// enabled.
+    ///
+    /// Panics if the threshold is 16 or larger.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_tpr_threshold(&mut self, threshold: u8) -> Result<(), VmxError> {
+        assert!(threshold < 16, "TPR threshold must be below 16");
+        self.write32(VmcsField32::TprThreshold, u32::from(threshold))
+    }
+
+    /// Writes the physical address of the APIC-access page, which is used if the "virtualize
+    /// APIC accesses" control is set.
+    ///
+    /// Guest accesses to the page cause VM exits, or are redirected to the virtual-APIC page if
+    /// APIC-register virtualization is enabled, so the page itself is never accessed.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_apic_access_address(&mut self, addr: PhysAddr) -> Result<(), VmxError> {
+        self.write64(VmcsField64::ApicAccessAddress, addr.as_u64())
+    }
+
+    /// Reads the EOI-exit bitmap.
+    pub fn eoi_exit_bitmap(&self) -> Result<EoiExitBitmap, VmxError> {
+        Ok(EoiExitBitmap::from_raw([
+            self.read64(VmcsField64::EoiExitBitmap0)?,
+            self.read64(VmcsField64::EoiExitBitmap1)?,
+            self.read64(VmcsField64::EoiExitBitmap2)?,
+            self.read64(VmcsField64::EoiExitBitmap3)?,
+        ]))
+    }
+
+    /// Writes the EOI-exit bitmap.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn set_eoi_exit_bitmap(&mut self, bitmap: &EoiExitBitmap) -> Result<(), VmxError> {
+        let [bits0, bits1, bits2, bits3] = bitmap.as_raw();
+        self.write64(VmcsField64::EoiExitBitmap0, bits0)?;
+        self.write64(VmcsField64::EoiExitBitmap1, bits1)?;
+        self.write64(VmcsField64::EoiExitBitmap2, bits2)?;
+        self.write64(VmcsField64::EoiExitBitmap3, bits3)
+    }
+
+    /// Reads the guest interrupt status.
+    #[inline]
+    pub fn guest_interrupt_status(&self) -> Result<GuestInterruptStatus, VmxError> {
+        self.read16(VmcsField16::GuestInterruptStatus)
+            .map(GuestInterruptStatus::from_raw)
+    }
+
+    /// Writes the guest interrupt status.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_guest_interrupt_status(
+        &mut self,
+        status: GuestInterruptStatus,
+    ) -> Result<(), VmxError> {
+        self.write16(VmcsField16::GuestInterruptStatus, status.as_raw())
+    }
+
+    /// Writes the posted-interrupt notification vector and the physical address of the
+    /// posted-interrupt descriptor, which are used if the "process posted interrupts" control
+    /// is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the descriptor must stay valid while it
+    /// is used, since the processor writes to it.
+    #[inline]
+    pub unsafe fn set_posted_interrupts(
+        &mut self,
+        notification_vector: u8,
+        descriptor: PhysAddr,
+    ) -> Result<(), VmxError> {
+        self.write16(
+            VmcsField16::PostedInterruptNotificationVector,
+            u16::from(notification_vector),
+        )?;
+        self.write64(
+            VmcsField64::PostedInterruptDescriptorAddress,
+            descriptor.as_u64(),
+        )
+    }
+
+    /// Reads the VMX-preemption timer value.
+    ///
+    /// On VM exits, the value is only saved if the "save VMX-preemption timer value" VM-exit
+    /// control is set.
+    #[inline]
+    pub fn preemption_timer_value(&self) -> Result<u32, VmxError> {
+        self.read32(VmcsField32::PreemptionTimerValue)
+    }
+
+    /// Writes the VMX-preemption timer value, which counts down while the guest runs if the
+    /// "activate VMX-preemption timer" pin-based control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_preemption_timer_value(&mut self, ticks: u32) -> Result<(), VmxError> {
+        self.write32(VmcsField32::PreemptionTimerValue, ticks)
+    }
+
+    /// Programs the VMX-preemption timer to cause a VM exit after the given number of TSC
+    /// cycles, rounded down to the timer rate read from `IA32_VMX_MISC`.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_preemption_timeout(&mut self, tsc_cycles: u64) -> Result<(), VmxError> {
+        let ticks = VmxMisc::read().tsc_to_preemption_ticks(tsc_cycles);
+        self.set_preemption_timer_value(ticks)
+    }
+
+    /// Reads the TSC offset.
+    #[inline]
+    pub fn tsc_offset(&self) -> Result<u64, VmxError> {
+        self.read64(VmcsField64::TscOffset)
+    }
+
+    /// Writes the TSC offset, which is added to the TSC values read by the guest if the "use
+    /// TSC offsetting" control is set. See [`tsc_offset_for`] for computing an offset.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_tsc_offset(&mut self, offset: u64) -> Result<(), VmxError> {
+        self.write64(VmcsField64::TscOffset, offset)
+    }
+
+    /// Reads the TSC multiplier.
+    #[inline]
+    pub fn tsc_multiplier(&self) -> Result<u64, VmxError> {
+        self.read64(VmcsField64::TscMultiplier)
+    }
+
+    /// Writes the TSC multiplier, a fixed-point number with 48 fractional bits that the TSC
+    /// values read by the guest are multiplied with if the "use TSC scaling" control is set.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_tsc_multiplier(&mut self, multiplier: u64) -> Result<(), VmxError> {
+        self.write64(VmcsField64::TscMultiplier, multiplier)
+    }
+
+    /// Reads the state of the given guest segment register.
+    pub fn guest_segment(&self, register: GuestSegmentRegister) -> Result<GuestSegment, VmxError> {
+        let (selector, limit, access_rights, base) = register.fields();
+        Ok(GuestSegment {
+            selector: SegmentSelector(self.read16(selector)?),
+            base: self.read_natural(base)?,
+            limit: self.read32(limit)?,
+            access_rights: SegmentAccessRights::from_bits_truncate(self.read32(access_rights)?),
+        })
+    }
+
+    /// Writes the state of the given guest segment register.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn set_guest_segment(
+        &mut self,
+        register: GuestSegmentRegister,
+        segment: &GuestSegment,
+    ) -> Result<(), VmxError> {
+        let (selector, limit, access_rights, base) = register.fields();
+        self.write16(selector, segment.selector.0)?;
+        self.write_natural(base, segment.base)?;
+        self.write32(limit, segment.limit)?;
+        self.write32(access_rights, segment.access_rights.bits())
+    }
+
+    /// Writes the address and number of entries of the MSR list that is loaded on VM entry.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the list must stay valid while it is
+    /// used and loading its values must not break memory safety.
+    #[inline]
+    pub unsafe fn set_entry_msr_load(
+        &mut self,
+        addr: PhysAddr,
+        count: u32,
+    ) -> Result<(), VmxError> {
+        self.write64(VmcsField64::EntryMsrLoadAddress, addr.as_u64())?;
+        self.write32(VmcsField32::EntryMsrLoadCount, count)
+    }
+
+    /// Writes the address and number of entries of the MSR list that is stored on VM exit.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the list must stay valid while it is
+    /// used, since the processor writes to it.
+    #[inline]
+    pub unsafe fn set_exit_msr_store(
+        &mut self,
+        addr: PhysAddr,
+        count: u32,
+    ) -> Result<(), VmxError> {
+        self.write64(VmcsField64::ExitMsrStoreAddress, addr.as_u64())?;
+        self.write32(VmcsField32::ExitMsrStoreCount, count)
+    }
+
+    /// Writes the address and number of entries of the MSR list that is loaded on VM exit.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the list must stay valid while it is
+    /// used and loading its values must not break memory safety.
+    #[inline]
+    pub unsafe fn set_exit_msr_load(&mut self, addr: PhysAddr, count: u32) -> Result<(), VmxError> {
+        self.write64(VmcsField64::ExitMsrLoadAddress, addr.as_u64())?;
+        self.write32(VmcsField32::ExitMsrLoadCount, count)
+    }
+
+    /// Enables VMCS shadowing with the given shadow VMCS and VMREAD and VMWRITE bitmaps.
+    ///
+    /// This writes the VMCS link pointer and the bitmap addresses and sets the "VMCS
+    /// shadowing" secondary control, activating the secondary controls if needed.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the shadow VMCS must be marked as shadow
+    /// VMCS and the shadow VMCS and bitmaps must stay valid while they are used.
+    pub unsafe fn enable_vmcs_shadowing(
+        &mut self,
+        shadow_vmcs: PhysAddr,
+        vmread_bitmap: PhysAddr,
+        vmwrite_bitmap: PhysAddr,
+    ) -> Result<(), VmxError> {
+        self.write64(VmcsField64::VmcsLinkPointer, shadow_vmcs.as_u64())?;
+        self.write64(VmcsField64::VmreadBitmapAddress, vmread_bitmap.as_u64())?;
+        self.write64(VmcsField64::VmwriteBitmapAddress, vmwrite_bitmap.as_u64())?;
+
+        let secondary = self.secondary_proc_based_controls()?;
+        self.set_secondary_proc_based_controls(
+            secondary | SecondaryProcBasedControls::VMCS_SHADOWING,
+        )?;
+        let primary = self.primary_proc_based_controls()?;
+        self.set_primary_proc_based_controls(
+            primary | PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS,
+        )
+    }
+
+    /// Disables VMCS shadowing by clearing the "VMCS shadowing" secondary control and the
+    /// VMCS link pointer.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn disable_vmcs_shadowing(&mut self) -> Result<(), VmxError> {
+        let secondary = self.secondary_proc_based_controls()?;
+        self.set_secondary_proc_based_controls(
+            secondary - SecondaryProcBasedControls::VMCS_SHADOWING,
+        )?;
+        self.write64(VmcsField64::VmcsLinkPointer, u64::MAX)
+    }
+
+    /// Configures the guest to start in real-address mode at the reset vector
+    /// `0xf000:0xfff0`, e.g. to run firmware.
+    ///
+    /// This enables EPT with the given EPT pointer and the "unrestricted guest" secondary
+    /// control, clears the "IA-32e mode guest" VM-entry control and writes the guest state
+    /// after a processor reset. CR0 and CR4 are adjusted to the bits that are fixed in VMX
+    /// operation, except for PE and PG. The VMCS link pointer is cleared.
+    ///
+    /// Returns an error if the processor doesn't support unrestricted guests.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the EPT tables must not map host memory
+    /// that the guest must not access.
+    pub unsafe fn setup_unrestricted_guest(&mut self, eptp: Eptp) -> Result<(), VmcsSetupError> {
+        let required =
+            SecondaryProcBasedControls::ENABLE_EPT | SecondaryProcBasedControls::UNRESTRICTED_GUEST;
+        let unsupported = required - SecondaryProcBasedControls::supported();
+        if !unsupported.is_empty() {
+            return Err(UnsupportedControls {
+                control: VmxControl::SecondaryProcBased,
+                bits: unsupported.bits(),
+            }
+            .into());
+        }
+
+        self.set_eptp(eptp)?;
+        let secondary = self.secondary_proc_based_controls()?;
+        self.set_secondary_proc_based_controls(secondary | required)?;
+        let primary = self.primary_proc_based_controls()?;
+        self.set_primary_proc_based_controls(
+            primary | PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS,
+        )?;
+        let entry_controls = self.entry_controls()?;
+        self.set_entry_controls(entry_controls - EntryControls::IA32E_MODE_GUEST)?;
+
+        // CD, NW and ET are set after reset
+        let fixed = VmxFixedBits::cr0().unrestricted_guest();
+        self.set_cr0_shadow(&ControlRegisterShadow::new(0x6000_0010, 0, 0, fixed))?;
+        self.write_natural(VmcsFieldNatural::GuestCr3, 0)?;
+        self.set_cr4_shadow(&ControlRegisterShadow::cr4(0, 0, 0))?;
+        self.write_natural(VmcsFieldNatural::GuestDr7, 0x400)?;
+        self.write_natural(VmcsFieldNatural::GuestRflags, 0x2)?;
+        self.write_natural(VmcsFieldNatural::GuestRip, 0xfff0)?;
+        self.write_natural(VmcsFieldNatural::GuestRsp, 0)?;
+        self.write64(VmcsField64::GuestIa32Efer, 0)?;
+
+        let data = GuestSegment::real_mode(0, SegmentAccessRights::WRITABLE);
+        for register in [
+            GuestSegmentRegister::Es,
+            GuestSegmentRegister::Ss,
+            GuestSegmentRegister::Ds,
+            GuestSegmentRegister::Fs,
+            GuestSegmentRegister::Gs,
+        ]
+        .iter()
+        {
+            self.set_guest_segment(*register, &data)?;
+        }
+        let code = GuestSegment {
+            base: 0xffff_0000,
+            // a readable code segment
+            ..GuestSegment::real_mode(
+                0xf000,
+                SegmentAccessRights::EXECUTABLE | SegmentAccessRights::WRITABLE,
+            )
+        };
+        self.set_guest_segment(GuestSegmentRegister::Cs, &code)?;
+        let system = |segment_type: u32| GuestSegment {
+            selector: SegmentSelector(0),
+            base: 0,
+            limit: 0xffff,
+            access_rights: SegmentAccessRights::from_bits_truncate(segment_type)
+                | SegmentAccessRights::PRESENT,
+        };
+        // an LDT and a busy 16-bit TSS
+        self.set_guest_segment(GuestSegmentRegister::Ldtr, &system(0b0010))?;
+        self.set_guest_segment(GuestSegmentRegister::Tr, &system(0b0011))?;
+        self.write_natural(VmcsFieldNatural::GuestGdtrBase, 0)?;
+        self.write32(VmcsField32::GuestGdtrLimit, 0xffff)?;
+        self.write_natural(VmcsFieldNatural::GuestIdtrBase, 0)?;
+        self.write32(VmcsField32::GuestIdtrLimit, 0xffff)?;
+
+        self.write32(VmcsField32::GuestActivityState, 0)?;
+        self.write32(VmcsField32::GuestInterruptibilityState, 0)?;
+        self.write64(VmcsField64::VmcsLinkPointer, u64::MAX)?;
+        Ok(())
+    }
+
+    /// Reads the exception bitmap.
+    #[inline]
+    pub fn exception_bitmap(&self) -> Result<ExceptionBitmap, VmxError> {
+        self.read32(VmcsField32::ExceptionBitmap)
+            .map(|bits| unsafe { ExceptionBitmap::from_bits_unchecked(bits) })
+    }
+
+    /// Writes the exception bitmap.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_exception_bitmap(&mut self, bitmap: ExceptionBitmap) -> Result<(), VmxError> {
+        self.write32(VmcsField32::ExceptionBitmap, bitmap.bits())
+    }
+
+    /// Writes the page-fault error-code mask and match fields, which filter the page faults
+    /// that cause VM exits.
+    ///
+    /// See [`ExceptionBitmap::intercepts_page_fault`] for how the fields are evaluated.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    #[inline]
+    pub unsafe fn set_page_fault_filter(
+        &mut self,
+        mask: PageFaultErrorCode,
+        matches: PageFaultErrorCode,
+    ) -> Result<(), VmxError> {
+        self.write32(VmcsField32::PageFaultErrorCodeMask, mask.bits() as u32)?;
+        self.write32(VmcsField32::PageFaultErrorCodeMatch, matches.bits() as u32)
+    }
+
+    /// Writes the guest CR0 together with its guest/host mask and read shadow.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn set_cr0_shadow(&mut self, cr0: &ControlRegisterShadow) -> Result<(), VmxError> {
+        self.write_natural(VmcsFieldNatural::Cr0GuestHostMask, cr0.mask)?;
+        self.write_natural(VmcsFieldNatural::Cr0ReadShadow, cr0.read_shadow)?;
+        self.write_natural(VmcsFieldNatural::GuestCr0, cr0.value)
+    }
+
+    /// Writes the guest CR4 together with its guest/host mask and read shadow.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16).
+    pub unsafe fn set_cr4_shadow(&mut self, cr4: &ControlRegisterShadow) -> Result<(), VmxError> {
+        self.write_natural(VmcsFieldNatural::Cr4GuestHostMask, cr4.mask)?;
+        self.write_natural(VmcsFieldNatural::Cr4ReadShadow, cr4.read_shadow)?;
+        self.write_natural(VmcsFieldNatural::GuestCr4, cr4.value)
+    }
+
+    /// Writes the physical address of the page-modification log and marks the log as empty.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). Additionally, the log must stay valid while the PML is
+    /// enabled, since the processor writes to it.
+    #[inline]
+    pub unsafe fn set_pml_address(&mut self, addr: PhysAddr) -> Result<(), VmxError> {
+        self.write64(VmcsField64::PmlAddress, addr.as_u64())?;
+        self.write16(VmcsField16::PmlIndex, PmlBuffer::EMPTY_INDEX)
+    }
+
+    /// Reads the PML index, which selects the next entry of the page-modification log.
+    #[inline]
+    pub fn pml_index(&self) -> Result<u16, VmxError> {
+        self.read16(VmcsField16::PmlIndex)
+    }
+
+    /// Returns the addresses logged in the given page-modification log and marks the log as
+    /// empty, e.g. on a VM exit because the log is full.
+    ///
+    /// The addresses must be consumed before the guest runs again, since the processor
+    /// overwrites them.
+    ///
+    /// ## Safety
+    ///
+    /// See [`write16`](Vmcs::write16). The given log must be the one whose address was
+    /// written with [`set_pml_address`](Vmcs::set_pml_address).
+    #[inline]
+    pub unsafe fn drain_pml<'a>(
+        &mut self,
+        buffer: &'a PmlBuffer,
+    ) -> Result<PmlEntries<'a>, VmxError> {
+        let index = self.pml_index()?;
+        self.write16(VmcsField16::PmlIndex, PmlBuffer::EMPTY_INDEX)?;
+        Ok(buffer.logged(index))
+    }
+}
+
+/// An error of a VMCS setup helper.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmcsSetupError {
+    /// The required VMX controls aren't supported by the processor.
+    UnsupportedControls(UnsupportedControls),
+    /// A VMX instruction failed.
+    Vmx(VmxError),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<UnsupportedControls> for VmcsSetupError {
+    #[inline]
+    fn from(err: UnsupportedControls) -> Self {
+        VmcsSetupError::UnsupportedControls(err)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<VmxError> for VmcsSetupError {
+    #[inline]
+    fn from(err: VmxError) -> Self {
+        VmcsSetupError::Vmx(err)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl fmt::Display for VmcsSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmcsSetupError::UnsupportedControls(err) => err.fmt(f),
+            VmcsSetupError::Vmx(err) => err.fmt(f),
+        }
+    }
+}
+
+/// Returns the base address of the TSS that `tr` selects in the given GDT.
+///
+/// ## Safety
+///
+/// The GDT must be valid and contain a TSS descriptor at the index of `tr`.
+#[cfg(target_arch = "x86_64")]
+unsafe fn tss_base(gdt: &tables::DescriptorTablePointer, tr: SegmentSelector) -> u64 {
+    // the TSS descriptor occupies two GDT entries
+    let descriptor = (gdt.base as *const u64).add(usize::from(tr.index()));
+    let descriptor = Descriptor::SystemSegment(*descriptor, *descriptor.add(1));
+    GuestSegment::from_descriptor(tr, &descriptor).base
+}
+
+/// Writes the current state of the processor to the host-state area of the current VMCS, so
+/// that VM exits return to the current address space and descriptor tables.
+///
+/// This writes the control registers, the segment selectors, the FS, GS, TR, GDTR and IDTR
+/// bases and the `IA32_SYSENTER_*` MSRs. `IA32_EFER` is written if the "load IA32_EFER" VM-exit
+/// control is supported. The host RSP and RIP, which depend on the VM exit handler, must be
+/// written separately.
+///
+/// ## Safety
+///
+/// See [`Vmcs::write16`]. Additionally, the current segment selectors must fulfill the
+/// requirements for host selectors, e.g. their RPL must be 0.
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn setup_host_state() -> Result<(), VmxError> {
+    let mut vmcs = Vmcs::current();
+
+    vmcs.write_natural(VmcsFieldNatural::HostCr0, Cr0::read_raw())?;
+    let (frame, flags) = Cr3::read();
+    vmcs.write_natural(
+        VmcsFieldNatural::HostCr3,
+        frame.start_address().as_u64() | flags.bits(),
+    )?;
+    vmcs.write_natural(VmcsFieldNatural::HostCr4, Cr4::read_raw())?;
+
+    let tr = tables::tr();
+    vmcs.write16(VmcsField16::HostEsSelector, segmentation::es().0)?;
+    vmcs.write16(VmcsField16::HostCsSelector, segmentation::cs().0)?;
+    vmcs.write16(VmcsField16::HostSsSelector, segmentation::ss().0)?;
+    vmcs.write16(VmcsField16::HostDsSelector, segmentation::ds().0)?;
+    vmcs.write16(VmcsField16::HostFsSelector, segmentation::fs().0)?;
+    vmcs.write16(VmcsField16::HostGsSelector, segmentation::gs().0)?;
+    vmcs.write16(VmcsField16::HostTrSelector, tr.0)?;
+
+    let gdt = tables::sgdt();
+    vmcs.write_natural(VmcsFieldNatural::HostFsBase, FsBase::read().as_u64())?;
+    vmcs.write_natural(VmcsFieldNatural::HostGsBase, GsBase::read().as_u64())?;
+    vmcs.write_natural(VmcsFieldNatural::HostTrBase, tss_base(&gdt, tr))?;
+    vmcs.write_natural(VmcsFieldNatural::HostGdtrBase, gdt.base)?;
+    vmcs.write_natural(VmcsFieldNatural::HostIdtrBase, tables::sidt().base)?;
+
+    vmcs.write32(
+        VmcsField32::HostIa32SysenterCs,
+        SysEnterCs::MSR.read() as u32,
+    )?;
+    vmcs.write_natural(
+        VmcsFieldNatural::HostIa32SysenterEsp,
+        SysEnterEsp::MSR.read(),
+    )?;
+    vmcs.write_natural(
+        VmcsFieldNatural::HostIa32SysenterEip,
+        SysEnterEip::MSR.read(),
+    )?;
+
+    if VmxControl::Exit.capability().allowed_1 & ExitControls::LOAD_EFER.bits() != 0 {
+        vmcs.write64(VmcsField64::HostIa32Efer, Efer::read_raw())?;
+    }
+    Ok(())
+}
+
+/// The VMXON region, which is used by the processor while in VMX operation.
+///
+/// The region is 4KiB aligned and starts with the VMCS revision identifier, as required by
+/// `vmxon`.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct VmxonRegion {
+    revision_id: u32,
+    data: [u8; 4092],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl VmxonRegion {
+    /// Creates a VMXON region with the revision identifier of the current processor, read from
+    /// `IA32_VMX_BASIC`.
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_revision_id(VmxBasic::revision_id())
+    }
+
+    /// Creates a VMXON region with the given revision identifier.
+    #[inline]
+    pub const fn with_revision_id(revision_id: u32) -> Self {
+        VmxonRegion {
+            revision_id: revision_id & 0x7fff_ffff,
+            data: [0; 4092],
+        }
+    }
+
+    /// Returns the revision identifier of the region.
+    #[inline]
+    pub const fn revision_id(&self) -> u32 {
+        self.revision_id
+    }
+
+    /// Returns the physical address of the region, as passed to `vmxon`.
+    ///
+    /// Returns `None` if the region isn't mapped by the given mapper.
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for VmxonRegion {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl fmt::Debug for VmxonRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmxonRegion")
+            .field("revision_id", &self.revision_id)
+            .finish()
+    }
+}
+
+/// The memory region of a VMCS, as loaded with `vmptrld`.
+///
+/// The region is 4KiB aligned and starts with the VMCS revision identifier. The rest of the
+/// region is in an implementation-specific format and must only be accessed through `vmread`
+/// and `vmwrite`.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct VmcsRegion {
+    revision_id: u32,
+    abort_indicator: u32,
+    data: [u8; 4088],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl VmcsRegion {
+    /// Creates a VMCS region with the revision identifier of the current processor, read from
+    /// `IA32_VMX_BASIC`.
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_revision_id(VmxBasic::revision_id())
+    }
+
+    /// Creates a VMCS region with the given revision identifier.
+    #[inline]
+    pub const fn with_revision_id(revision_id: u32) -> Self {
+        VmcsRegion {
+            revision_id: revision_id & 0x7fff_ffff,
+            abort_indicator: 0,
+            data: [0; 4088],
+        }
+    }
+
+    /// Creates a shadow VMCS region with the revision identifier of the current processor.
+    ///
+    /// A shadow VMCS is referenced by the VMCS link pointer and accessed by `vmread` and
+    /// `vmwrite` in the guest if VMCS shadowing is enabled.
+    #[inline]
+    pub fn new_shadow() -> Self {
+        let mut region = Self::new();
+        region.set_shadow(true);
+        region
+    }
+
+    /// Returns the revision identifier of the region.
+    #[inline]
+    pub const fn revision_id(&self) -> u32 {
+        self.revision_id & 0x7fff_ffff
+    }
+
+    /// Returns whether the region is marked as a shadow VMCS, i.e. whether the shadow-VMCS
+    /// indicator in bit 31 of the revision identifier is set.
+    #[inline]
+    pub const fn is_shadow(&self) -> bool {
+        self.revision_id & (1 << 31) != 0
+    }
+
+    /// Sets or clears the shadow-VMCS indicator.
+    ///
+    /// The indicator must only be changed while the VMCS isn't active, i.e. before it is
+    /// loaded with `vmptrld` or after it was cleared with `vmclear`.
+    #[inline]
+    pub fn set_shadow(&mut self, shadow: bool) {
+        if shadow {
+            self.revision_id |= 1 << 31;
+        } else {
+            self.revision_id &= !(1 << 31);
+        }
+    }
+
+    /// Returns the VMX-abort indicator, which the processor writes if a VM exit fails.
+    #[inline]
+    pub fn abort_indicator(&self) -> u32 {
+        // the processor writes the indicator behind our back
+        unsafe { core::ptr::read_volatile(&self.abort_indicator) }
+    }
+
+    /// Returns the physical address of the region, as passed to `vmptrld` and `vmclear`.
+    ///
+    /// Returns `None` if the region isn't mapped by the given mapper.
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for VmcsRegion {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl fmt::Debug for VmcsRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmcsRegion")
+            .field("revision_id", &self.revision_id)
+            .field("abort_indicator", &self.abort_indicator())
+            .finish()
+    }
+}
+
+/// The MSR bitmap, which selects the `rdmsr` and `wrmsr` instructions that cause VM exits if
+/// the "use MSR bitmaps" control is set.
+///
+/// Only the MSRs `0..=0x1fff` and `0xc000_0000..=0xc000_1fff` can be controlled, accesses to
+/// other MSRs always cause VM exits.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct MsrBitmap {
+    bits: [u8; 4096],
+}
+
+impl MsrBitmap {
+    /// The byte offset of the write bitmaps, behind the read bitmaps.
+    const WRITE_OFFSET: usize = 2048;
+
+    /// Creates an MSR bitmap that intercepts no accesses.
+    #[inline]
+    pub const fn new() -> Self {
+        MsrBitmap { bits: [0; 4096] }
+    }
+
+    /// Returns the bit of the given MSR in the read bitmaps, if the bitmap controls it.
+    #[inline]
+    fn bit(msr: u32) -> Option<usize> {
+        match msr {
+            0..=0x1fff => Some(msr as usize),
+            0xc000_0000..=0xc000_1fff => Some(0x2000 + (msr - 0xc000_0000) as usize),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, msr: u32, offset: usize, intercept: bool) {
+        let bit = Self::bit(msr)
+            .unwrap_or_else(|| panic!("MSR {:#x} is not controlled by the MSR bitmap", msr));
+        let byte = &mut self.bits[offset + bit / 8];
+        if intercept {
+            *byte |= 1 << (bit % 8);
+        } else {
+            *byte &= !(1 << (bit % 8));
+        }
+    }
+
+    #[inline]
+    fn get(&self, msr: u32, offset: usize) -> bool {
+        match Self::bit(msr) {
+            Some(bit) => self.bits[offset + bit / 8] & (1 << (bit % 8)) != 0,
+            None => true,
+        }
+    }
+
+    /// Sets whether `rdmsr` of the given MSR causes a VM exit.
+    ///
+    /// Panics if the MSR is outside the ranges controlled by the bitmap.
+    #[inline]
+    pub fn set_read_intercept(&mut self, msr: u32, intercept: bool) {
+        self.set(msr, 0, intercept);
+    }
+
+    /// Sets whether `wrmsr` of the given MSR causes a VM exit.
+    ///
+    /// Panics if the MSR is outside the ranges controlled by the bitmap.
+    #[inline]
+    pub fn set_write_intercept(&mut self, msr: u32, intercept: bool) {
+        self.set(msr, Self::WRITE_OFFSET, intercept);
+    }
+
+    /// Returns whether `rdmsr` of the given MSR causes a VM exit.
+    #[inline]
+    pub fn read_intercepted(&self, msr: u32) -> bool {
+        self.get(msr, 0)
+    }
+
+    /// Returns whether `wrmsr` of the given MSR causes a VM exit.
+    #[inline]
+    pub fn write_intercepted(&self, msr: u32) -> bool {
+        self.get(msr, Self::WRITE_OFFSET)
+    }
+
+    /// Returns the physical address of the bitmap, as written to the MSR-bitmap address field.
+    ///
+    /// Returns `None` if the bitmap isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for MsrBitmap {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MsrBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MsrBitmap").finish()
+    }
+}
+
+/// The I/O bitmaps, which select the I/O ports whose accesses cause VM exits if the "use I/O
+/// bitmaps" control is set.
+///
+/// The bitmap A for the ports `0..=0x7fff` and the bitmap B for the ports `0x8000..=0xffff`
+/// are two consecutive 4KiB pages, whose addresses are written to separate VMCS fields.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct IoBitmap {
+    bits: [u8; 8192],
+}
+
+impl IoBitmap {
+    /// Creates I/O bitmaps that intercept no accesses.
+    #[inline]
+    pub const fn new() -> Self {
+        IoBitmap { bits: [0; 8192] }
+    }
+
+    /// Sets whether accesses to the given port cause VM exits.
+    #[inline]
+    pub fn set_intercept(&mut self, port: u16, intercept: bool) {
+        let byte = &mut self.bits[usize::from(port / 8)];
+        if intercept {
+            *byte |= 1 << (port % 8);
+        } else {
+            *byte &= !(1 << (port % 8));
+        }
+    }
+
+    /// Sets whether accesses to the given ports cause VM exits.
+    #[inline]
+    pub fn set_range_intercept(&mut self, ports: RangeInclusive<u16>, intercept: bool) {
+        for port in ports {
+            self.set_intercept(port, intercept);
+        }
+    }
+
+    /// Returns whether accesses to the given port cause VM exits.
+    ///
+    /// An access of multiple bytes causes a VM exit if any of its ports is intercepted.
+    #[inline]
+    pub fn intercepted(&self, port: u16) -> bool {
+        self.bits[usize::from(port / 8)] & (1 << (port % 8)) != 0
+    }
+
+    /// Returns the physical addresses of the bitmaps A and B, as written to the I/O-bitmap
+    /// address fields.
+    ///
+    /// Returns `None` if a bitmap isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addrs(&self, mapper: &impl MapperAllSizes) -> Option<(PhysAddr, PhysAddr)> {
+        let a = mapper.translate_addr(VirtAddr::from_ptr(&self.bits[0]))?;
+        let b = mapper.translate_addr(VirtAddr::from_ptr(&self.bits[4096]))?;
+        Some((a, b))
+    }
+}
+
+impl Default for IoBitmap {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for IoBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IoBitmap").finish()
+    }
+}
+
+/// The virtual-APIC page, which holds the virtual APIC registers of the guest if the "use TPR
+/// shadow" control is set.
+///
+/// The registers are at the offsets of the corresponding registers of the local APIC. The
+/// processor accesses the page while the guest runs, so all accesses are volatile.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct VirtualApicPage {
+    registers: [u32; 1024],
+}
+
+impl VirtualApicPage {
+    /// The offset of the virtual task-priority register (VTPR).
+    pub const TPR: usize = 0x80;
+    /// The offset of the virtual processor-priority register (VPPR).
+    pub const PPR: usize = 0xa0;
+    /// The offset of the virtual EOI register (VEOI).
+    pub const EOI: usize = 0xb0;
+    /// The offset of the first register of the virtual in-service register (VISR).
+    pub const ISR: usize = 0x100;
+    /// The offset of the first register of the virtual trigger-mode register (VTMR).
+    pub const TMR: usize = 0x180;
+    /// The offset of the first register of the virtual interrupt-request register (VIRR).
+    pub const IRR: usize = 0x200;
+
+    /// Creates a virtual-APIC page with all registers zero.
+    #[inline]
+    pub const fn new() -> Self {
+        VirtualApicPage {
+            registers: [0; 1024],
+        }
+    }
+
+    /// Reads the 32-bit register at the given offset.
+    ///
+    /// Panics if the offset isn't 4-byte aligned or outside the page.
+    #[inline]
+    pub fn read(&self, offset: usize) -> u32 {
+        assert!(offset & 0b11 == 0, "unaligned virtual APIC register");
+        unsafe { core::ptr::read_volatile(&self.registers[offset / 4]) }
+    }
+
+    /// Writes the 32-bit register at the given offset.
+    ///
+    /// Panics if the offset isn't 4-byte aligned or outside the page.
+    #[inline]
+    pub fn write(&mut self, offset: usize, value: u32) {
+        assert!(offset & 0b11 == 0, "unaligned virtual APIC register");
+        unsafe { core::ptr::write_volatile(&mut self.registers[offset / 4], value) }
+    }
+
+    /// Returns the virtual task priority.
+    #[inline]
+    pub fn tpr(&self) -> u8 {
+        self.read(Self::TPR) as u8
+    }
+
+    /// Sets the virtual task priority.
+    #[inline]
+    pub fn set_tpr(&mut self, tpr: u8) {
+        self.write(Self::TPR, u32::from(tpr));
+    }
+
+    /// Returns the virtual processor priority.
+    #[inline]
+    pub fn ppr(&self) -> u8 {
+        self.read(Self::PPR) as u8
+    }
+
+    /// Returns the value of the virtual EOI register, which the guest writes to signal the end
+    /// of an interrupt.
+    #[inline]
+    pub fn eoi(&self) -> u32 {
+        self.read(Self::EOI)
+    }
+
+    /// Returns the offset and bit of the given vector in an interrupt array at `base`.
+    ///
+    /// Each of the 8 registers of an array holds 32 vectors and is aligned to 16 bytes.
+    #[inline]
+    fn vector_bit(base: usize, vector: u8) -> (usize, u32) {
+        (
+            base + usize::from(vector / 32) * 0x10,
+            u32::from(vector % 32),
+        )
+    }
+
+    #[inline]
+    fn vector_set(&self, base: usize, vector: u8) -> bool {
+        let (offset, bit) = Self::vector_bit(base, vector);
+        self.read(offset) & (1 << bit) != 0
+    }
+
+    #[inline]
+    fn set_vector(&mut self, base: usize, vector: u8, value: bool) {
+        let (offset, bit) = Self::vector_bit(base, vector);
+        let register = self.read(offset);
+        if value {
+            self.write(offset, register | 1 << bit);
+        } else {
+            self.write(offset, register & !(1 << bit));
+        }
+    }
+
+    /// Returns the highest vector set in an interrupt array at `base`.
+    #[inline]
+    fn highest_vector(&self, base: usize) -> Option<u8> {
+        (0..8u8).rev().find_map(|i| {
+            let register = self.read(base + usize::from(i) * 0x10);
+            match register {
+                0 => None,
+                _ => Some(i * 32 + (31 - register.leading_zeros() as u8)),
+            }
+        })
+    }
+
+    /// Returns whether the given vector is set in the VISR.
+    #[inline]
+    pub fn is_in_service(&self, vector: u8) -> bool {
+        self.vector_set(Self::ISR, vector)
+    }
+
+    /// Sets or clears the given vector in the VISR.
+    #[inline]
+    pub fn set_in_service(&mut self, vector: u8, in_service: bool) {
+        self.set_vector(Self::ISR, vector, in_service);
+    }
+
+    /// Returns the highest vector in the VISR, if any.
+    #[inline]
+    pub fn highest_in_service(&self) -> Option<u8> {
+        self.highest_vector(Self::ISR)
+    }
+
+    /// Returns whether the given vector is set in the VIRR.
+    #[inline]
+    pub fn is_requested(&self, vector: u8) -> bool {
+        self.vector_set(Self::IRR, vector)
+    }
+
+    /// Sets or clears the given vector in the VIRR.
+    #[inline]
+    pub fn set_requested(&mut self, vector: u8, requested: bool) {
+        self.set_vector(Self::IRR, vector, requested);
+    }
+
+    /// Returns the highest vector in the VIRR, if any.
+    #[inline]
+    pub fn highest_requested(&self) -> Option<u8> {
+        self.highest_vector(Self::IRR)
+    }
+
+    /// Returns the physical address of the page, as written to the virtual-APIC address field.
+    ///
+    /// Returns `None` if the page isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for VirtualApicPage {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for VirtualApicPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VirtualApicPage")
+            .field("tpr", &self.tpr())
+            .field("ppr", &self.ppr())
+            .finish()
+    }
+}
+
+/// The EOI-exit bitmap, which selects the vectors for which a guest EOI causes a VM exit if
+/// the "virtual-interrupt delivery" control is set.
+///
+/// Without a VM exit, the EOI is virtualized, so the bit has to be set for level-triggered
+/// interrupts whose EOI must be forwarded, e.g. to an emulated I/O APIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EoiExitBitmap {
+    bits: [u64; 4],
+}
+
+impl EoiExitBitmap {
+    /// Creates a bitmap in which no EOI causes a VM exit.
+    #[inline]
+    pub const fn new() -> Self {
+        EoiExitBitmap { bits: [0; 4] }
+    }
+
+    /// Creates a bitmap from the values of the four EOI-exit bitmap fields.
+    #[inline]
+    pub const fn from_raw(bits: [u64; 4]) -> Self {
+        EoiExitBitmap { bits }
+    }
+
+    /// Returns the values of the four EOI-exit bitmap fields.
+    #[inline]
+    pub const fn as_raw(&self) -> [u64; 4] {
+        self.bits
+    }
+
+    /// Sets whether an EOI for the given vector causes a VM exit.
+    #[inline]
+    pub fn set_exit(&mut self, vector: u8, exit: bool) {
+        let bit = 1 << (vector % 64);
+        let word = &mut self.bits[usize::from(vector / 64)];
+        if exit {
+            *word |= bit;
+        } else {
+            *word &= !bit;
+        }
+    }
+
+    /// Returns whether an EOI for the given vector causes a VM exit.
+    #[inline]
+    pub const fn exits(&self, vector: u8) -> bool {
+        self.bits[(vector / 64) as usize] & 1 << (vector % 64) != 0
+    }
+}
+
+/// The guest interrupt status, which holds the requesting virtual interrupt (RVI) and the
+/// servicing virtual interrupt (SVI) if the "virtual-interrupt delivery" control is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GuestInterruptStatus {
+    /// The vector of the highest-priority virtual interrupt that is requested, or 0.
+    pub requesting: u8,
+    /// The vector of the highest-priority virtual interrupt that is in service, or 0.
+    pub servicing: u8,
+}
+
+impl GuestInterruptStatus {
+    /// Decodes the value of the guest interrupt status field.
+    #[inline]
+    pub const fn from_raw(raw: u16) -> Self {
+        GuestInterruptStatus {
+            requesting: raw as u8,
+            servicing: (raw >> 8) as u8,
+        }
+    }
+
+    /// Encodes the guest interrupt status as the value of the VMCS field.
+    #[inline]
+    pub const fn as_raw(&self) -> u16 {
+        (self.servicing as u16) << 8 | self.requesting as u16
+    }
+
+    /// Returns the status matching the virtual IRR and ISR of the given virtual-APIC page.
+    #[inline]
+    pub fn from_virtual_apic(page: &VirtualApicPage) -> Self {
+        GuestInterruptStatus {
+            requesting: page.highest_requested().unwrap_or(0),
+            servicing: page.highest_in_service().unwrap_or(0),
+        }
+    }
+}
+
+/// The posted-interrupt descriptor, through which interrupts are posted to a guest if the
+/// "process posted interrupts" control is set.
+///
+/// Another processor posts an interrupt by setting its vector in the posted-interrupt requests
+/// (PIR), setting the outstanding-notification (ON) bit, and sending the notification vector
+/// to the processor running the guest if ON wasn't set before. The processor running the guest
+/// then moves the PIR into the virtual-APIC page. All operations are atomic, so that the
+/// descriptor can be shared between processors.
+#[derive(Debug)]
+#[repr(C, align(64))]
+pub struct PostedInterruptDescriptor {
+    pir: [AtomicU64; 4],
+    control: AtomicU64,
+    reserved: [u64; 3],
+}
+
+impl PostedInterruptDescriptor {
+    /// The outstanding-notification bit of the control word.
+    const ON: u64 = 1;
+    /// The suppress-notification bit of the control word.
+    const SN: u64 = 1 << 1;
+
+    /// Creates a descriptor without pending interrupts.
+    #[inline]
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU64 = AtomicU64::new(0);
+        PostedInterruptDescriptor {
+            pir: [EMPTY; 4],
+            control: AtomicU64::new(0),
+            reserved: [0; 3],
+        }
+    }
+
+    /// Sets the given vector in the PIR and returns whether it was set before.
+    #[inline]
+    pub fn set_pir(&self, vector: u8) -> bool {
+        let bit = 1 << (vector % 64);
+        self.pir[usize::from(vector / 64)].fetch_or(bit, Ordering::AcqRel) & bit != 0
+    }
+
+    /// Returns whether the given vector is set in the PIR.
+    #[inline]
+    pub fn is_pending(&self, vector: u8) -> bool {
+        self.pir[usize::from(vector / 64)].load(Ordering::Acquire) & 1 << (vector % 64) != 0
+    }
+
+    /// Sets the outstanding-notification bit and returns whether it was set before.
+    ///
+    /// The notification vector must be sent if the bit wasn't set before.
+    #[inline]
+    pub fn set_on(&self) -> bool {
+        self.control.fetch_or(Self::ON, Ordering::AcqRel) & Self::ON != 0
+    }
+
+    /// Clears the outstanding-notification bit and returns whether it was set before.
+    #[inline]
+    pub fn clear_on(&self) -> bool {
+        self.control.fetch_and(!Self::ON, Ordering::AcqRel) & Self::ON != 0
+    }
+
+    /// Returns whether the outstanding-notification bit is set.
+    #[inline]
+    pub fn is_on(&self) -> bool {
+        self.control.load(Ordering::Acquire) & Self::ON != 0
+    }
+
+    /// Sets or clears the suppress-notification bit, which suppresses notifications for
+    /// non-urgent interrupts, e.g. while the guest isn't running.
+    #[inline]
+    pub fn set_suppress_notification(&self, suppress: bool) {
+        if suppress {
+            self.control.fetch_or(Self::SN, Ordering::AcqRel);
+        } else {
+            self.control.fetch_and(!Self::SN, Ordering::AcqRel);
+        }
+    }
+
+    /// Returns whether the suppress-notification bit is set.
+    #[inline]
+    pub fn suppress_notification(&self) -> bool {
+        self.control.load(Ordering::Acquire) & Self::SN != 0
+    }
+
+    /// Returns the notification vector used by interrupt remapping hardware.
+    #[inline]
+    pub fn notification_vector(&self) -> u8 {
+        (self.control.load(Ordering::Acquire) >> 16) as u8
+    }
+
+    /// Returns the APIC ID of the processor that notifications are sent to by interrupt
+    /// remapping hardware.
+    #[inline]
+    pub fn notification_destination(&self) -> u32 {
+        (self.control.load(Ordering::Acquire) >> 32) as u32
+    }
+
+    /// Sets the notification vector and destination used by interrupt remapping hardware,
+    /// keeping the ON and SN bits.
+    #[inline]
+    pub fn set_notification(&self, vector: u8, destination: u32) {
+        let fields = u64::from(vector) << 16 | u64::from(destination) << 32;
+        let _ = self
+            .control
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |control| {
+                Some(control & (Self::ON | Self::SN) | fields)
+            });
+    }
+
+    /// Atomically takes the PIR, clearing all of its vectors.
+    #[inline]
+    pub fn take_pir(&self) -> [u64; 4] {
+        let mut pir = [0; 4];
+        for (bits, requests) in pir.iter_mut().zip(self.pir.iter()) {
+            *bits = requests.swap(0, Ordering::AcqRel);
+        }
+        pir
+    }
+
+    /// Clears the PIR and the outstanding-notification bit and returns the vectors that were
+    /// pending, in ascending order.
+    #[inline]
+    pub fn drain(&self) -> impl Iterator<Item = u8> {
+        self.clear_on();
+        let pir = self.take_pir();
+        (0..=255u8).filter(move |&vector| pir[usize::from(vector / 64)] & 1 << (vector % 64) != 0)
+    }
+
+    /// Returns the physical address of the descriptor, as written to the posted-interrupt
+    /// descriptor address field.
+    ///
+    /// Returns `None` if the descriptor isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for PostedInterruptDescriptor {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An entry of an [`MsrList`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub struct MsrEntry {
+    /// The index of the MSR.
+    pub index: u32,
+    reserved: u32,
+    /// The value that is loaded into the MSR or that the MSR was stored as.
+    pub data: u64,
+}
+
+impl MsrEntry {
+    /// Creates an entry for the given MSR and value.
+    #[inline]
+    pub const fn new(index: u32, data: u64) -> Self {
+        MsrEntry {
+            index,
+            reserved: 0,
+            data,
+        }
+    }
+}
+
+/// The number of entries an [`MsrList`] can hold.
+pub const MSR_LIST_ENTRIES: usize = 255;
+
+/// A list of MSRs that are loaded on VM entry, or stored or loaded on VM exit.
+///
+/// The list occupies a 4KiB page, its entries start at the beginning of the page and the
+/// number of entries is stored in the last 16 bytes. The address and number of entries are
+/// written to the VMCS, e.g. with [`Vmcs::set_entry_msr_load`]. The processor
+/// recommends at most [`VmxMiscInfo::max_msr_list_entries`] entries per list.
+///
+/// [`VmxMiscInfo::max_msr_list_entries`]: crate::registers::model_specific::VmxMiscInfo::max_msr_list_entries
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct MsrList {
+    entries: [MsrEntry; MSR_LIST_ENTRIES],
+    len: u32,
+}
+
+impl MsrList {
+    /// Creates an empty MSR list.
+    #[inline]
+    pub const fn new() -> Self {
+        MsrList {
+            entries: [MsrEntry::new(0, 0); MSR_LIST_ENTRIES],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of entries, as written to the count fields of the VMCS.
+    #[inline]
+    pub const fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns whether the list has no entries.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all entries.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends an entry, returning it as an error if the list is full.
+    #[inline]
+    pub fn push(&mut self, entry: MsrEntry) -> Result<(), MsrEntry> {
+        let slot = self.entries.get_mut(self.len as usize).ok_or(entry)?;
+        *slot = entry;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Sets the value of the entry for the given MSR, appending an entry if there is none.
+    ///
+    /// Returns an error if the list is full.
+    #[inline]
+    pub fn set(&mut self, index: u32, data: u64) -> Result<(), MsrEntry> {
+        if let Some(entry) = self.iter_mut().find(|entry| entry.index == index) {
+            entry.data = data;
+            return Ok(());
+        }
+        self.push(MsrEntry::new(index, data))
+    }
+
+    /// Returns the value of the first entry for the given MSR, e.g. the value stored on the
+    /// last VM exit.
+    #[inline]
+    pub fn get(&self, index: u32) -> Option<u64> {
+        self.iter()
+            .find(|entry| entry.index == index)
+            // the processor stores the values behind our back
+            .map(|entry| unsafe { core::ptr::read_volatile(&entry.data) })
+    }
+
+    /// Returns an iterator over the entries.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &MsrEntry> {
+        self.entries[..self.len as usize].iter()
+    }
+
+    /// Returns an iterator that allows modifying the entries.
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut MsrEntry> {
+        self.entries[..self.len as usize].iter_mut()
+    }
+
+    /// Returns the physical address of the list, as written to the MSR-area address fields.
+    ///
+    /// Returns `None` if the list isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for MsrList {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MsrList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// A VMREAD or VMWRITE bitmap, which selects the VMCS fields whose accesses by `vmread` or
+/// `vmwrite` in the guest cause VM exits if the "VMCS shadowing" control is set.
+///
+/// Accesses to the other fields access the shadow VMCS referenced by the VMCS link pointer
+/// instead. Accesses to fields whose encoding is 0x8000 or higher always cause VM exits.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct VmcsAccessBitmap {
+    bits: [u8; 4096],
+}
+
+impl VmcsAccessBitmap {
+    /// Creates a bitmap that intercepts no accesses.
+    #[inline]
+    pub const fn new() -> Self {
+        VmcsAccessBitmap { bits: [0; 4096] }
+    }
+
+    /// Creates a bitmap that intercepts all accesses.
+    #[inline]
+    pub const fn intercept_all() -> Self {
+        VmcsAccessBitmap { bits: [0xff; 4096] }
+    }
+
+    /// Sets whether accesses to the given field cause VM exits.
+    #[inline]
+    pub fn set_intercept(&mut self, field: VmcsField, intercept: bool) {
+        let bit = field.encoding() as usize;
+        let byte = &mut self.bits[bit / 8];
+        if intercept {
+            *byte |= 1 << (bit % 8);
+        } else {
+            *byte &= !(1 << (bit % 8));
+        }
+    }
+
+    /// Returns whether accesses to the given field cause VM exits.
+    #[inline]
+    pub fn intercepted(&self, field: VmcsField) -> bool {
+        let bit = field.encoding() as usize;
+        self.bits[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// Returns the physical address of the bitmap, as written to the VMREAD-bitmap or
+    /// VMWRITE-bitmap address field.
+    ///
+    /// Returns `None` if the bitmap isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for VmcsAccessBitmap {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for VmcsAccessBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VmcsAccessBitmap").finish()
+    }
+}
+
+/// The value, guest/host mask and read shadow of a guest CR0 or CR4.
+///
+/// The bits set in the guest/host mask are owned by the hypervisor: guest reads of them return
+/// the read shadow, and guest writes that change them relative to the read shadow cause VM
+/// exits. The bits that are fixed in VMX operation are always owned, so that the guest sees
+/// the value it wrote even if the register needs a different value, e.g. for CR4.VMXE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlRegisterShadow {
+    /// The value of the register while the guest runs.
+    pub value: u64,
+    /// The guest/host mask.
+    pub mask: u64,
+    /// The read shadow, i.e. the value of the owned bits as seen by the guest.
+    pub read_shadow: u64,
+}
+
+impl ControlRegisterShadow {
+    /// Creates the state for a register of which the guest sees `guest_value`.
+    ///
+    /// The bits in `owned` are set to their value in `owned_value` while the guest runs, and
+    /// the bits fixed by `fixed` are adjusted to the allowed values.
+    #[inline]
+    pub const fn new(guest_value: u64, owned: u64, owned_value: u64, fixed: VmxFixedBits) -> Self {
+        let value = fixed.adjust((guest_value & !owned) | (owned_value & owned));
+        ControlRegisterShadow {
+            value,
+            mask: owned | fixed.fixed_0 | !fixed.fixed_1,
+            read_shadow: guest_value,
+        }
+    }
+
+    /// Returns the value of the register as read by the guest.
+    #[inline]
+    pub const fn guest_value(&self) -> u64 {
+        (self.value & !self.mask) | (self.read_shadow & self.mask)
+    }
+
+    /// Returns whether a guest write of the given value causes a VM exit.
+    #[inline]
+    pub const fn write_causes_exit(&self, value: u64) -> bool {
+        (value ^ self.read_shadow) & self.mask != 0
+    }
+
+    /// Returns the state after the guest wrote the given value, e.g. when emulating a write
+    /// that caused a VM exit.
+    ///
+    /// The owned bits keep their current value.
+    #[inline]
+    pub const fn with_guest_value(self, guest_value: u64) -> Self {
+        ControlRegisterShadow {
+            value: (guest_value & !self.mask) | (self.value & self.mask),
+            mask: self.mask,
+            read_shadow: guest_value,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl ControlRegisterShadow {
+    /// Creates the state of CR0 with the fixed bits of the current processor.
+    ///
+    /// For an unrestricted guest, the fixed bits of
+    /// [`VmxFixedBits::unrestricted_guest`] have to be passed to [`new`](Self::new) instead.
+    #[inline]
+    pub fn cr0(guest_value: u64, owned: u64, owned_value: u64) -> Self {
+        Self::new(guest_value, owned, owned_value, VmxFixedBits::cr0())
+    }
+
+    /// Creates the state of CR4 with the fixed bits of the current processor.
+    #[inline]
+    pub fn cr4(guest_value: u64, owned: u64, owned_value: u64) -> Self {
+        Self::new(guest_value, owned, owned_value, VmxFixedBits::cr4())
+    }
+}
+
+/// An in-memory copy of the fields of a VMCS, e.g. the VMCS that a nested hypervisor uses for
+/// its guest.
+///
+/// Every field of [`VmcsField::ALL`] has a slot. Values are truncated to the width of their
+/// field when they are set. The fields are copied from and to the current VMCS with
+/// [`read_fields`](Self::read_fields) and [`write_fields`](Self::write_fields).
+#[derive(Clone)]
+pub struct SoftwareVmcs {
+    values: [u64; Self::FIELD_COUNT],
+}
+
+impl SoftwareVmcs {
+    const FIELD_COUNT: usize = VmcsField::ALL.len();
+
+    /// Creates a copy in which all fields are zero.
+    #[inline]
+    pub const fn new() -> Self {
+        SoftwareVmcs {
+            values: [0; Self::FIELD_COUNT],
+        }
+    }
+
+    #[inline]
+    fn slot(field: VmcsField) -> usize {
+        // the fields are sorted by encoding
+        VmcsField::ALL
+            .binary_search_by_key(&field.encoding(), |field| field.encoding())
+            .expect("all fields are in VmcsField::ALL")
+    }
+
+    /// Returns the value of the given field.
+    #[inline]
+    pub fn get(&self, field: VmcsField) -> u64 {
+        self.values[Self::slot(field)]
+    }
+
+    /// Sets the value of the given field, truncated to its width.
+    #[inline]
+    pub fn set(&mut self, field: VmcsField, value: u64) {
+        let value = match field.width() {
+            VmcsFieldWidth::Bits16 => value & 0xffff,
+            VmcsFieldWidth::Bits32 => value & 0xffff_ffff,
+            VmcsFieldWidth::Bits64 | VmcsFieldWidth::Natural => value,
+        };
+        self.values[Self::slot(field)] = value;
+    }
+
+    /// Returns the value of the field with the given encoding, including the high 32 bits of
+    /// 64-bit fields, as read by `vmread`.
+    ///
+    /// Returns `None` for unknown or malformed encodings.
+    #[inline]
+    pub fn read(&self, encoding: VmcsFieldEncoding) -> Option<u64> {
+        if !encoding.is_valid() {
+            return None;
+        }
+        let value = self.get(encoding.field()?);
+        Some(if encoding.is_high() {
+            value >> 32
+        } else {
+            value
+        })
+    }
+
+    /// Sets the value of the field with the given encoding, as written by `vmwrite`.
+    ///
+    /// Writing the high 32 bits of a 64-bit field keeps the low 32 bits. Returns `false` for
+    /// unknown or malformed encodings.
+    #[inline]
+    pub fn write(&mut self, encoding: VmcsFieldEncoding, value: u64) -> bool {
+        let field = match encoding.field() {
+            Some(field) if encoding.is_valid() => field,
+            _ => return false,
+        };
+        if encoding.is_high() {
+            let low = self.get(field) & 0xffff_ffff;
+            self.set(field, value << 32 | low);
+        } else {
+            self.set(field, value);
+        }
+        true
+    }
+
+    /// Returns an iterator over the fields and their values.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (VmcsField, u64)> + '_ {
+        VmcsField::ALL
+            .iter()
+            .copied()
+            .zip(self.values.iter().copied())
+    }
+}
+
+/// Overwrites all fields with zeros, e.g. before the copy of a destroyed guest is freed.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for SoftwareVmcs {
+    fn zeroize(&mut self) {
+        self.values[..].zeroize();
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl SoftwareVmcs {
+    /// Reads the given fields from the current VMCS.
+    ///
+    /// Fields that the processor doesn't support are set to zero.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that the processor is in VMX
+    /// root operation.
+    pub unsafe fn read_fields(&mut self, fields: &[VmcsField]) -> Result<(), VmxError> {
+        for &field in fields {
+            let value = match vmx::vmread(field) {
+                Ok(value) => value,
+                Err(VmxError::Valid(VmInstructionError::UnsupportedVmcsComponent)) => 0,
+                Err(err) => return Err(err),
+            };
+            self.set(field, value);
+        }
+        Ok(())
+    }
+
+    /// Writes the given fields to the current VMCS.
+    ///
+    /// Read-only fields are skipped unless the processor supports writing them, which is
+    /// reported by bit 29 of `IA32_VMX_MISC`. Fields that the processor doesn't support are
+    /// skipped as well.
+    ///
+    /// ## Safety
+    ///
+    /// See [`Vmcs::write16`].
+    pub unsafe fn write_fields(&self, fields: &[VmcsField]) -> Result<(), VmxError> {
+        let write_read_only = VmxMisc::read().vmwrite_all_fields;
+        for &field in fields {
+            if field.is_read_only() && !write_read_only {
+                continue;
+            }
+            match vmx::vmwrite(field, self.get(field)) {
+                Ok(()) | Err(VmxError::Valid(VmInstructionError::UnsupportedVmcsComponent)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for SoftwareVmcs {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SoftwareVmcs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().filter(|&(_, value)| value != 0))
+            .finish()
+    }
+}
+
+/// The number of entries in the page-modification log.
+pub const PML_ENTRIES: usize = 512;
+
+/// The page-modification log, to which the processor writes the guest-physical addresses of
+/// the pages that the guest writes to.
+///
+/// The processor logs a page if the "enable PML" secondary control is set and the write sets
+/// the dirty flag of an EPT entry. The log is filled from the last entry down to the first
+/// one, the next entry is selected by the PML index field of the VMCS. A VM exit occurs when
+/// the log is full.
+#[derive(Clone)]
+#[repr(C, align(4096))]
+pub struct PmlBuffer {
+    entries: [u64; PML_ENTRIES],
+}
+
+impl PmlBuffer {
+    /// The PML index of an empty log.
+    pub const EMPTY_INDEX: u16 = PML_ENTRIES as u16 - 1;
+
+    /// Creates an empty page-modification log.
+    #[inline]
+    pub const fn new() -> Self {
+        PmlBuffer {
+            entries: [0; PML_ENTRIES],
+        }
+    }
+
+    /// Returns an iterator over the guest-physical addresses logged before the given PML
+    /// index was reached.
+    ///
+    /// The index is the value of the PML index field of the VMCS, which underflows to
+    /// `0xffff` when the log is full.
+    #[inline]
+    pub fn logged(&self, index: u16) -> PmlEntries<'_> {
+        let next = match index {
+            index if index < Self::EMPTY_INDEX => usize::from(index) + 1,
+            Self::EMPTY_INDEX => PML_ENTRIES,
+            _ => 0,
+        };
+        PmlEntries { buffer: self, next }
+    }
+
+    /// Returns the physical address of the log, as written to the PML address field.
+    ///
+    /// Returns `None` if the log isn't mapped by the given mapper.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub fn as_phys_addr(&self, mapper: &impl MapperAllSizes) -> Option<PhysAddr> {
+        mapper.translate_addr(VirtAddr::from_ptr(self))
+    }
+}
+
+impl Default for PmlBuffer {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PmlBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PmlBuffer").finish()
+    }
+}
+
+/// An iterator over the guest-physical addresses in the page-modification log, created by
+/// [`PmlBuffer::logged`] or [`Vmcs::drain_pml`].
+#[derive(Debug, Clone)]
+pub struct PmlEntries<'a> {
+    buffer: &'a PmlBuffer,
+    next: usize,
+}
+
+impl Iterator for PmlEntries<'_> {
+    type Item = PhysAddr;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.buffer.entries.get(self.next)?;
+        self.next += 1;
+        // the processor writes the log behind our back
+        let entry = unsafe { core::ptr::read_volatile(entry) };
+        Some(PhysAddr::new_truncate(entry & !0xfff))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = PML_ENTRIES - self.next.min(PML_ENTRIES);
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for PmlEntries<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_encodings() {
+        assert_eq!(VmcsField::GuestRip.encoding(), 0x681e);
+        assert_eq!(VmcsField::GuestRip.width(), VmcsFieldWidth::Natural);
+        assert_eq!(VmcsField::GuestRip.field_type(), VmcsFieldType::GuestState);
+        assert_eq!(VmcsField::Eptp.width(), VmcsFieldWidth::Bits64);
+        assert_eq!(
+            VmcsField::HostCsSelector.field_type(),
+            VmcsFieldType::HostState
+        );
+        assert_eq!(VmcsField::ExitReason.width(), VmcsFieldWidth::Bits32);
+        assert!(VmcsField::ExitReason.is_read_only());
+        assert!(!VmcsField::PinBasedControls.is_read_only());
+    }
+
+    #[test]
+    fn field_encoding_parts() {
+        let encoding = VmcsFieldEncoding::from(VmcsField::Eptp);
+        assert_eq!(encoding.index(), 13);
+        assert_eq!(VmcsField::Eptp.index(), 13);
+        let high = VmcsFieldEncoding(0x201b);
+        assert!(high.is_valid() && high.is_high());
+        assert_eq!(high.field(), Some(VmcsField::Eptp));
+        assert_eq!(
+            VmcsFieldEncoding::from_parts(
+                VmcsFieldWidth::Natural,
+                VmcsFieldType::GuestState,
+                15,
+                false
+            ),
+            VmcsFieldEncoding::from(VmcsField::GuestRip)
+        );
+        // the high half of a natural-width field doesn't exist
+        assert!(!VmcsFieldEncoding(0x681f).is_valid());
+        assert_eq!(VmcsField::from_encoding(0x1234), None);
+        assert!(VmcsField::ALL
+            .windows(2)
+            .all(|pair| pair[0].encoding() < pair[1].encoding()));
+    }
+
+    #[test]
+    fn software_vmcs() {
+        let mut vmcs = SoftwareVmcs::new();
+        vmcs.set(VmcsField::GuestCsSelector, 0x1_0008);
+        assert_eq!(vmcs.get(VmcsField::GuestCsSelector), 0x8);
+        assert!(vmcs.write(VmcsFieldEncoding::from(VmcsField::Eptp), 0x1234_505e));
+        assert!(vmcs.write(VmcsFieldEncoding(0x201b), 0x1));
+        assert_eq!(vmcs.get(VmcsField::Eptp), 0x1_1234_505e);
+        assert_eq!(vmcs.read(VmcsFieldEncoding(0x201b)), Some(1));
+        assert_eq!(vmcs.read(VmcsFieldEncoding(0x0ffe)), None);
+        assert!(!vmcs.write(VmcsFieldEncoding(0x681f), 0));
+        assert_eq!(vmcs.iter().filter(|&(_, value)| value != 0).count(), 2);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_software_vmcs() {
+        use zeroize::Zeroize;
+
+        let mut vmcs = SoftwareVmcs::new();
+        vmcs.set(VmcsField::GuestRip, 0x1000);
+        vmcs.set(VmcsField::GuestCr3, 0x2000);
+        vmcs.zeroize();
+        assert!(vmcs.iter().all(|(_, value)| value == 0));
+    }
+
+    #[test]
+    fn width_enums() {
+        let field = VmcsField::from(VmcsField16::GuestTrSelector);
+        assert_eq!(field, VmcsField::GuestTrSelector);
+        assert_eq!(field.width(), VmcsFieldWidth::Bits16);
+        assert_eq!(
+            VmcsField::from(VmcsField64::GuestIa32Efer).width(),
+            VmcsFieldWidth::Bits64
+        );
+        assert_eq!(
+            VmcsField::from(VmcsField32::ExitReason).width(),
+            VmcsFieldWidth::Bits32
+        );
+        assert_eq!(
+            VmcsField::from(VmcsFieldNatural::HostRip).width(),
+            VmcsFieldWidth::Natural
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn host_tss_base() {
+        use crate::structures::gdt::GlobalDescriptorTable;
+        use crate::structures::tss::TaskStateSegment;
+
+        static TSS: TaskStateSegment = TaskStateSegment::new();
+        let mut gdt = GlobalDescriptorTable::new();
+        gdt.add_entry(Descriptor::kernel_code_segment());
+        let tr = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        assert_eq!(
+            unsafe { tss_base(&gdt.pointer(), tr) },
+            &TSS as *const _ as u64
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn window_exiting_controls() {
+        let default1 = 0x0400_6172;
+        let interrupt_window = PrimaryProcBasedControls::INTERRUPT_WINDOW_EXITING;
+        let nmi_window = PrimaryProcBasedControls::NMI_WINDOW_EXITING;
+        let raw = Vmcs::toggle_controls(default1, interrupt_window, true);
+        assert_eq!(raw, 0x0400_6176);
+        let raw = Vmcs::toggle_controls(raw, nmi_window, true);
+        assert_eq!(raw, 0x0440_6176);
+        assert_eq!(
+            PrimaryProcBasedControls::from_bits_truncate(raw),
+            interrupt_window | nmi_window
+        );
+        let raw = Vmcs::toggle_controls(raw, interrupt_window, false);
+        assert_eq!(Vmcs::toggle_controls(raw, nmi_window, false), default1);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn field_probing() {
+        assert_eq!(Vmcs::probe_result(Ok(0)), Ok(true));
+        assert_eq!(
+            Vmcs::probe_result(Err(VmxError::Valid(
+                VmInstructionError::UnsupportedVmcsComponent
+            ))),
+            Ok(false)
+        );
+        let no_vmcs = Err(VmxError::Invalid);
+        assert_eq!(Vmcs::probe_result(no_vmcs), Err(VmxError::Invalid));
+    }
+
+    #[test]
+    fn entry_exit_controls() {
+        // only the "save/load debug controls" bits of the default1 bits are controls
+        let exit_default1 = 0x0003_6dff;
+        assert_eq!(
+            ExitControls::from_bits_truncate(exit_default1),
+            ExitControls::SAVE_DEBUG_CONTROLS
+        );
+        let entry_default1 = 0x11ff;
+        assert_eq!(
+            EntryControls::from_bits_truncate(entry_default1),
+            EntryControls::LOAD_DEBUG_CONTROLS
+        );
+
+        let exit = ExitControls::HOST_ADDRESS_SPACE_SIZE
+            | ExitControls::LOAD_EFER
+            | ExitControls::CONCEAL_VMX_FROM_PT
+            | ExitControls::CLEAR_RTIT_CTL;
+        assert_eq!(exit.bits(), 0x0320_0200);
+        assert_eq!(ExitControls::from_bits(exit.bits() | 1 << 27), None);
+        let entry = EntryControls::IA32E_MODE_GUEST
+            | EntryControls::CONCEAL_VMX_FROM_PT
+            | EntryControls::LOAD_RTIT_CTL;
+        assert_eq!(entry.bits(), 0x6_0200);
+        assert_eq!(EntryControls::from_bits(entry.bits()), Some(entry));
+    }
+
+    #[test]
+    fn pin_based_controls() {
+        use crate::registers::model_specific::VmxControlCapability;
+
+        // the default1 bits 1, 2 and 4 must be 1, posted interrupts aren't supported
+        let capability = VmxControlCapability::from_raw(0x7f_0000_0016);
+        let controls = PinBasedControls::NMI_EXITING
+            | PinBasedControls::VIRTUAL_NMIS
+            | PinBasedControls::PROCESS_POSTED_INTERRUPTS;
+        assert_eq!(controls.bits(), 0xa8);
+        let adjusted = capability.adjust(controls.bits());
+        assert_eq!(adjusted, 0x3e);
+        assert_eq!(
+            PinBasedControls::from_bits_truncate(adjusted),
+            PinBasedControls::NMI_EXITING | PinBasedControls::VIRTUAL_NMIS
+        );
+    }
+
+    #[test]
+    fn proc_based_controls() {
+        // the default1 bits aren't part of the flags
+        let default1 = 0x0400_6172;
+        assert_eq!(PrimaryProcBasedControls::all().bits() & default1, 0);
+        let controls = PrimaryProcBasedControls::from_bits_truncate(default1 | 1 << 7 | 1 << 31);
+        assert_eq!(
+            controls,
+            PrimaryProcBasedControls::HLT_EXITING
+                | PrimaryProcBasedControls::ACTIVATE_SECONDARY_CONTROLS
+        );
+
+        let secondary =
+            SecondaryProcBasedControls::ENABLE_EPT | SecondaryProcBasedControls::UNRESTRICTED_GUEST;
+        assert_eq!(secondary.bits(), 0x82);
+        assert_eq!(SecondaryProcBasedControls::from_bits(0x82), Some(secondary));
+        assert_eq!(SecondaryProcBasedControls::from_bits(1 << 21), None);
+        assert_eq!(SecondaryProcBasedControls::from_bits(1 << 27), None);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn regions() {
+        assert_eq!(core::mem::size_of::<VmxonRegion>(), 4096);
+        assert_eq!(core::mem::align_of::<VmcsRegion>(), 4096);
+        // bit 31 is the shadow-VMCS indicator and not part of the identifier
+        let mut region = VmcsRegion::with_revision_id(0x8000_0004);
+        assert_eq!(region.revision_id(), 4);
+        assert_eq!(region.abort_indicator(), 0);
+        assert!(!region.is_shadow());
+        region.set_shadow(true);
+        assert!(region.is_shadow());
+        assert_eq!(region.revision_id(), 4);
+    }
+
+    #[test]
+    fn entry_interrupt_info() {
+        let event = EntryInterruptInfo::exception(ExceptionVector::Page).with_error_code(2);
+        assert_eq!(event.raw(), 0x8000_0b0e);
+        assert_eq!(event.error_code(), Some(2));
+        assert_eq!(
+            EntryInterruptInfo::exception(ExceptionVector::InvalidOpcode).raw(),
+            0x8000_0306
+        );
+        assert_eq!(
+            EntryInterruptInfo::external_interrupt(0x20).raw(),
+            0x8000_0020
+        );
+        assert_eq!(
+            EntryInterruptInfo::software_interrupt(0x80, 2).raw(),
+            0x8000_0480
+        );
+    }
+
+    #[test]
+    fn pml_buffer() {
+        let mut buffer = PmlBuffer::new();
+        buffer.entries[510] = 0x1234_5000;
+        buffer.entries[511] = 0x6789_a000;
+        assert_eq!(buffer.logged(PmlBuffer::EMPTY_INDEX).count(), 0);
+        let logged = buffer.logged(509);
+        assert_eq!(logged.len(), 2);
+        assert!(logged.eq([0x1234_5000, 0x6789_a000]
+            .iter()
+            .map(|&addr| PhysAddr::new(addr))));
+        assert_eq!(buffer.logged(0xffff).len(), PML_ENTRIES);
+    }
+
+    #[test]
+    fn msr_bitmap() {
+        let mut bitmap = MsrBitmap::new();
+        bitmap.set_read_intercept(0x10, true);
+        bitmap.set_write_intercept(0xc000_0080, true);
+        assert_eq!(bitmap.bits[2], 1);
+        assert_eq!(bitmap.bits[3072 + 16], 1);
+        assert!(bitmap.read_intercepted(0x10) && !bitmap.write_intercepted(0x10));
+        assert!(bitmap.write_intercepted(0xc000_0080) && !bitmap.read_intercepted(0xc000_0080));
+        assert!(bitmap.read_intercepted(0x4000_0000));
+        bitmap.set_read_intercept(0x10, false);
+        assert!(!bitmap.read_intercepted(0x10));
+    }
+
+    #[test]
+    fn io_bitmap() {
+        let mut bitmap = IoBitmap::new();
+        bitmap.set_range_intercept(0x3f8..=0x3ff, true);
+        bitmap.set_intercept(0xffff, true);
+        assert_eq!(bitmap.bits[0x7f], 0xff);
+        assert_eq!(bitmap.bits[8191], 0x80);
+        bitmap.set_intercept(0x3f9, false);
+        assert!(bitmap.intercepted(0x3f8) && !bitmap.intercepted(0x3f9));
+        assert!(!bitmap.intercepted(0x80));
+    }
+
+    #[test]
+    fn virtual_apic_page() {
+        let mut page = VirtualApicPage::new();
+        assert_eq!(page.highest_requested(), None);
+        page.set_requested(0x30, true);
+        page.set_requested(0xec, true);
+        page.set_in_service(0x21, true);
+        assert_eq!(page.read(0x270), 1 << 12);
+        assert_eq!(page.highest_requested(), Some(0xec));
+        page.set_requested(0xec, false);
+        assert_eq!(page.highest_requested(), Some(0x30));
+        assert!(page.is_in_service(0x21) && !page.is_requested(0x21));
+        assert_eq!(page.highest_in_service(), Some(0x21));
+        page.set_tpr(0x20);
+        assert_eq!(page.tpr(), 0x20);
+    }
+
+    #[test]
+    fn apic_virtualization() {
+        let mut bitmap = EoiExitBitmap::new();
+        bitmap.set_exit(0x30, true);
+        bitmap.set_exit(0xff, true);
+        assert!(bitmap.exits(0x30) && bitmap.exits(0xff) && !bitmap.exits(0x31));
+        assert_eq!(bitmap.as_raw(), [1 << 0x30, 0, 0, 1 << 63]);
+        bitmap.set_exit(0xff, false);
+        assert_eq!(bitmap.as_raw()[3], 0);
+
+        let status = GuestInterruptStatus::from_raw(0x4130);
+        assert_eq!((status.requesting, status.servicing), (0x30, 0x41));
+        assert_eq!(status.as_raw(), 0x4130);
+
+        let mut page = VirtualApicPage::new();
+        page.set_requested(0x50, true);
+        page.set_in_service(0x20, true);
+        let status = GuestInterruptStatus::from_virtual_apic(&page);
+        assert_eq!((status.requesting, status.servicing), (0x50, 0x20));
+    }
+
+    #[test]
+    fn posted_interrupt_descriptor() {
+        assert_eq!(core::mem::size_of::<PostedInterruptDescriptor>(), 64);
+        let descriptor = PostedInterruptDescriptor::new();
+        assert!(!descriptor.set_pir(0x41));
+        assert!(descriptor.set_pir(0x41));
+        assert!(!descriptor.set_pir(0xf0));
+        assert!(!descriptor.set_on());
+        assert!(descriptor.set_on());
+        descriptor.set_notification(0xf2, 3);
+        assert!(descriptor.is_on());
+        assert_eq!(descriptor.notification_vector(), 0xf2);
+        assert_eq!(descriptor.notification_destination(), 3);
+        assert!(descriptor.drain().eq([0x41, 0xf0].iter().copied()));
+        assert!(!descriptor.is_on() && !descriptor.is_pending(0x41));
+    }
+
+    #[test]
+    fn tsc_offset() {
+        assert_eq!(tsc_offset_for(1000, 300, TSC_MULTIPLIER_ONE), 700);
+        assert_eq!(
+            tsc_offset_for(0, 300, TSC_MULTIPLIER_ONE),
+            0u64.wrapping_sub(300)
+        );
+        // a guest TSC running at half the host frequency
+        assert_eq!(tsc_offset_for(1000, 300, TSC_MULTIPLIER_ONE / 2), 850);
+    }
+
+    #[test]
+    fn exception_bitmap() {
+        use crate::structures::idt::PageFaultErrorCode;
+
+        let bitmap = ExceptionBitmap::from_exception(ExceptionVector::Page)
+            | ExceptionBitmap::from_vector(9);
+        assert_eq!(bitmap.bits(), 1 << 14 | 1 << 9);
+        assert!(bitmap.intercepts(9) && bitmap.intercepts(14));
+        assert!(!bitmap.intercepts(13) && !bitmap.intercepts(40));
+
+        // intercept only write faults
+        let write = PageFaultErrorCode::CAUSED_BY_WRITE.bits() as u32;
+        assert!(bitmap.intercepts_page_fault(write | 1, write, write));
+        assert!(!bitmap.intercepts_page_fault(1, write, write));
+        // with the bit clear, the filter is inverted
+        assert!(ExceptionBitmap::empty().intercepts_page_fault(1, write, write));
+        assert!(bitmap.intercepts_page_fault(0, 0, 0));
+        assert!(!bitmap.intercepts_page_fault(0, 0, u32::MAX));
+    }
+
+    #[test]
+    fn control_register_shadow() {
+        // PE, NE and PG must be set
+        let fixed = VmxFixedBits {
+            fixed_0: 0x8000_0021,
+            fixed_1: 0xffff_ffff,
+        };
+        // own CD and NW and keep the cache enabled
+        let cr0 = ControlRegisterShadow::new(0x6000_0010, 0x6000_0000, 0, fixed);
+        assert_eq!(cr0.value, 0x8000_0031);
+        assert_eq!(cr0.mask, 0xffff_ffff_0000_0000 | 0xe000_0021);
+        assert_eq!(cr0.guest_value(), 0x6000_0010);
+        assert!(fixed.is_allowed(cr0.value));
+
+        assert!(!cr0.write_causes_exit(0x6000_0018));
+        assert!(cr0.write_causes_exit(0x0000_0010));
+        assert!(cr0.write_causes_exit(0x6000_0011));
+        let cr0 = cr0.with_guest_value(0x6000_0011);
+        assert_eq!(cr0.guest_value(), 0x6000_0011);
+        assert_eq!(cr0.value, 0x8000_0031);
+    }
+
+    #[test]
+    fn guest_segment() {
+        let selector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+        let segment = GuestSegment::from_descriptor(selector, &Descriptor::kernel_code_segment());
+        assert_eq!(segment.access_rights.bits(), 0x2099);
+        assert_eq!(segment.base, 0);
+
+        // a flat 32-bit data segment with base 0x1000
+        let descriptor = Descriptor::UserSegment(0x00cf_9300_1000_ffff);
+        let segment = GuestSegment::from_descriptor(selector, &descriptor);
+        assert_eq!(segment.base, 0x1000);
+        assert_eq!(segment.limit, 0xffff_ffff);
+        assert_eq!(segment.access_rights.bits(), 0xc093);
+        assert_eq!(
+            segment.access_rights.to_descriptor_bits(),
+            0x00c0_9300_0000_0000
+        );
+
+        let tss = Descriptor::SystemSegment(0x1200_8934_5678_0067, 0xffff_8000);
+        let segment = GuestSegment::from_descriptor(selector, &tss);
+        assert_eq!(segment.base, 0xffff_8000_1234_5678);
+        assert_eq!(segment.limit, 0x67);
+        assert_eq!(segment.access_rights.segment_type(), 11);
+    }
+
+    #[test]
+    fn msr_list() {
+        assert_eq!(core::mem::size_of::<MsrList>(), 4096);
+        let mut list = MsrList::new();
+        list.set(0xc000_0080, 0xd01).unwrap();
+        list.set(0x174, 8).unwrap();
+        list.set(0xc000_0080, 0x501).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.get(0xc000_0080), Some(0x501));
+        assert_eq!(list.get(0x175), None);
+        for i in 2..MSR_LIST_ENTRIES as u32 {
+            list.push(MsrEntry::new(i, 0)).unwrap();
+        }
+        assert_eq!(list.push(MsrEntry::new(1, 2)), Err(MsrEntry::new(1, 2)));
+    }
+
+    #[test]
+    fn vmcs_access_bitmap() {
+        let mut bitmap = VmcsAccessBitmap::new();
+        bitmap.set_intercept(VmcsField::GuestRip, true);
+        assert_eq!(bitmap.bits[0x681e / 8], 1 << (0x681e % 8));
+        assert!(bitmap.intercepted(VmcsField::GuestRip));
+        assert!(!bitmap.intercepted(VmcsField::GuestRsp));
+        let mut bitmap = VmcsAccessBitmap::intercept_all();
+        bitmap.set_intercept(VmcsField::GuestRip, false);
+        assert!(!bitmap.intercepted(VmcsField::GuestRip));
+        assert!(bitmap.intercepted(VmcsField::GuestRsp));
+    }
+
+    #[test]
+    fn real_mode_segment() {
+        let segment = GuestSegment::real_mode(0xb800, SegmentAccessRights::WRITABLE);
+        assert_eq!(segment.base, 0xb_8000);
+        assert_eq!(segment.access_rights.bits(), 0x93);
+        let segment = GuestSegment::real_mode(
+            0xf000,
+            SegmentAccessRights::EXECUTABLE | SegmentAccessRights::WRITABLE,
+        );
+        assert_eq!(segment.access_rights.bits(), 0x9b);
+    }
+}
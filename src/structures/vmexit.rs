@@ -0,0 +1,808 @@
+//! Information about VM exits, read from the VM-exit information fields of the VMCS.
+//!
+//! See chapter 28 "VM Exits" and appendix C "VMX Basic Exit Reasons" of the Intel SDM,
+//! volume 3.
+
+use crate::structures::vmcs::{EntryInterruptInfo, InterruptionType};
+use bitflags::bitflags;
+
+/// Defines the [`ExitReason`] enum with the given basic exit reasons and the conversions from
+/// and to their numbers.
+macro_rules! exit_reasons {
+    ($(
+        $(#[$attr:meta])*
+        $reason:ident = $number:literal,
+    )*) => {
+        /// The basic exit reason of a VM exit, stored in bits 0 to 15 of the exit-reason field.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum ExitReason {
+            $(
+                $(#[$attr])*
+                $reason,
+            )*
+            /// A basic exit reason that is not defined by the SDM.
+            Unknown(u16),
+        }
+
+        impl ExitReason {
+            /// Decodes the basic exit reason of the given exit-reason field, ignoring the
+            /// high 16 bits.
+            #[inline]
+            pub const fn from_raw(raw: u32) -> ExitReason {
+                match raw as u16 {
+                    $($number => ExitReason::$reason,)*
+                    other => ExitReason::Unknown(other),
+                }
+            }
+
+            /// Returns the number of the basic exit reason.
+            #[inline]
+            pub const fn as_raw(self) -> u16 {
+                match self {
+                    $(ExitReason::$reason => $number,)*
+                    ExitReason::Unknown(number) => number,
+                }
+            }
+        }
+    };
+}
+
+exit_reasons! {
+    /// An exception or a non-maskable interrupt, see the VM-exit interruption information.
+    ExceptionOrNmi = 0,
+    /// An external interrupt.
+    ExternalInterrupt = 1,
+    /// A triple fault.
+    TripleFault = 2,
+    /// An INIT signal.
+    InitSignal = 3,
+    /// A start-up IPI.
+    StartupIpi = 4,
+    /// An I/O system-management interrupt.
+    IoSmi = 5,
+    /// A system-management interrupt that isn't an I/O SMI.
+    OtherSmi = 6,
+    /// The interrupt window opened, with "interrupt-window exiting" set.
+    InterruptWindow = 7,
+    /// The NMI window opened, with "NMI-window exiting" set.
+    NmiWindow = 8,
+    /// A task switch.
+    TaskSwitch = 9,
+    /// `cpuid`.
+    Cpuid = 10,
+    /// `getsec`.
+    Getsec = 11,
+    /// `hlt`.
+    Hlt = 12,
+    /// `invd`.
+    Invd = 13,
+    /// `invlpg`.
+    Invlpg = 14,
+    /// `rdpmc`.
+    Rdpmc = 15,
+    /// `rdtsc`.
+    Rdtsc = 16,
+    /// `rsm` in SMM.
+    Rsm = 17,
+    /// `vmcall`.
+    Vmcall = 18,
+    /// `vmclear`.
+    Vmclear = 19,
+    /// `vmlaunch`.
+    Vmlaunch = 20,
+    /// `vmptrld`.
+    Vmptrld = 21,
+    /// `vmptrst`.
+    Vmptrst = 22,
+    /// `vmread`.
+    Vmread = 23,
+    /// `vmresume`.
+    Vmresume = 24,
+    /// `vmwrite`.
+    Vmwrite = 25,
+    /// `vmxoff`.
+    Vmxoff = 26,
+    /// `vmxon`.
+    Vmxon = 27,
+    /// A move to or from a control register, `clts` or `lmsw`.
+    CrAccess = 28,
+    /// A move to or from a debug register.
+    DrAccess = 29,
+    /// An I/O instruction.
+    IoInstruction = 30,
+    /// `rdmsr`.
+    Rdmsr = 31,
+    /// `wrmsr`.
+    Wrmsr = 32,
+    /// A VM entry failure due to invalid guest state.
+    InvalidGuestState = 33,
+    /// A VM entry failure due to MSR loading.
+    MsrLoading = 34,
+    /// `mwait`.
+    Mwait = 36,
+    /// The monitor trap flag.
+    MonitorTrapFlag = 37,
+    /// `monitor`.
+    Monitor = 39,
+    /// `pause`.
+    Pause = 40,
+    /// A VM entry failure due to a machine check event.
+    MachineCheckDuringEntry = 41,
+    /// The TPR dropped below the TPR threshold.
+    TprBelowThreshold = 43,
+    /// An access to the APIC-access page.
+    ApicAccess = 44,
+    /// A virtualized end of interrupt.
+    VirtualizedEoi = 45,
+    /// An access to the GDTR or IDTR.
+    GdtrIdtrAccess = 46,
+    /// An access to the LDTR or TR.
+    LdtrTrAccess = 47,
+    /// An EPT violation.
+    EptViolation = 48,
+    /// An EPT misconfiguration.
+    EptMisconfiguration = 49,
+    /// `invept`.
+    Invept = 50,
+    /// `rdtscp`.
+    Rdtscp = 51,
+    /// The VMX-preemption timer expired.
+    PreemptionTimer = 52,
+    /// `invvpid`.
+    Invvpid = 53,
+    /// `wbinvd` or `wbnoinvd`.
+    Wbinvd = 54,
+    /// `xsetbv`.
+    Xsetbv = 55,
+    /// A write to the APIC-access page that must be completed by the hypervisor.
+    ApicWrite = 56,
+    /// `rdrand`.
+    Rdrand = 57,
+    /// `invpcid`.
+    Invpcid = 58,
+    /// A failed `vmfunc`.
+    Vmfunc = 59,
+    /// `encls`.
+    Encls = 60,
+    /// `rdseed`.
+    Rdseed = 61,
+    /// The page-modification log is full.
+    PageModificationLogFull = 62,
+    /// `xsaves`.
+    Xsaves = 63,
+    /// `xrstors`.
+    Xrstors = 64,
+    /// `pconfig`.
+    Pconfig = 65,
+    /// A sub-page permission related event.
+    SppRelated = 66,
+    /// `umwait`.
+    Umwait = 67,
+    /// `tpause`.
+    Tpause = 68,
+    /// `loadiwkey`.
+    Loadiwkey = 69,
+    /// `enclv`.
+    Enclv = 70,
+    /// A PASID translation failure of `enqcmd`.
+    EnqcmdPasidTranslationFailure = 72,
+    /// A PASID translation failure of `enqcmds`.
+    EnqcmdsPasidTranslationFailure = 73,
+    /// A bus lock.
+    BusLock = 74,
+    /// An instruction that didn't complete within the timeout.
+    InstructionTimeout = 75,
+    /// `seamcall`.
+    Seamcall = 76,
+    /// `tdcall`.
+    Tdcall = 77,
+}
+
+bitflags! {
+    /// The flags in the high 16 bits of the exit-reason field.
+    pub struct ExitReasonFlags: u32 {
+        /// The VM exit occurred from an enclave.
+        const ENCLAVE_MODE = 1 << 27;
+        /// A monitor trap flag VM exit was pending.
+        const PENDING_MTF = 1 << 28;
+        /// The VM exit occurred from VMX root operation, e.g. an SMM VM exit.
+        const EXIT_FROM_ROOT = 1 << 29;
+        /// VM entry failed, and the basic exit reason describes why.
+        const ENTRY_FAILURE = 1 << 31;
+    }
+}
+
+/// The decoded exit-reason field of the VMCS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitReasonField {
+    /// The basic exit reason.
+    pub reason: ExitReason,
+    /// The flags in the high 16 bits.
+    pub flags: ExitReasonFlags,
+}
+
+impl ExitReasonField {
+    /// Decodes the value of the exit-reason field.
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Self {
+        ExitReasonField {
+            reason: ExitReason::from_raw(raw),
+            flags: ExitReasonFlags::from_bits_truncate(raw),
+        }
+    }
+
+    /// Returns whether VM entry failed, in which case the guest state wasn't changed.
+    #[inline]
+    pub const fn is_entry_failure(&self) -> bool {
+        self.flags.contains(ExitReasonFlags::ENTRY_FAILURE)
+    }
+
+    /// Returns whether the VM exit occurred from an enclave.
+    #[inline]
+    pub const fn is_enclave_mode(&self) -> bool {
+        self.flags.contains(ExitReasonFlags::ENCLAVE_MODE)
+    }
+}
+
+/// An event whose delivery was interrupted by a VM exit, read from the IDT-vectoring
+/// information and error code fields.
+///
+/// The VM exit occurred before the event was delivered to the guest, so it has to be injected
+/// again on the next VM entry, using [`reinject`](Self::reinject).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdtVectoringInfo {
+    /// The vector of the event.
+    pub vector: u8,
+    /// The type of the event.
+    pub kind: InterruptionType,
+    /// The error code delivered with the event.
+    pub error_code: Option<u32>,
+    /// The length of the instruction that caused a software interrupt or exception, taken
+    /// from the VM-exit instruction length field.
+    pub instruction_length: u32,
+}
+
+impl IdtVectoringInfo {
+    /// Decodes the IDT-vectoring information field, together with the IDT-vectoring error code
+    /// and the VM-exit instruction length.
+    ///
+    /// Returns `None` if the valid bit isn't set, i.e. if the VM exit did not interrupt the
+    /// delivery of an event.
+    #[inline]
+    pub const fn from_raw(info: u32, error_code: u32, instruction_length: u32) -> Option<Self> {
+        if info & (1 << 31) == 0 {
+            return None;
+        }
+        let kind = match InterruptionType::from_raw((info >> 8) as u8) {
+            Some(kind) => kind,
+            None => return None,
+        };
+        Some(IdtVectoringInfo {
+            vector: info as u8,
+            kind,
+            error_code: if info & (1 << 11) != 0 {
+                Some(error_code)
+            } else {
+                None
+            },
+            instruction_length,
+        })
+    }
+
+    /// Returns the event to inject on the next VM entry to complete the interrupted delivery.
+    #[inline]
+    pub const fn reinject(&self) -> EntryInterruptInfo {
+        let event = EntryInterruptInfo::new(self.vector, self.kind);
+        let event = match self.error_code {
+            Some(error_code) => event.with_error_code(error_code),
+            None => event,
+        };
+        if self.kind.is_software() {
+            event.with_instruction_length(self.instruction_length)
+        } else {
+            event
+        }
+    }
+}
+
+/// The kind of a control register access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrAccessType {
+    /// A move to the control register.
+    MovToCr,
+    /// A move from the control register.
+    MovFromCr,
+    /// `clts`.
+    Clts,
+    /// `lmsw`.
+    Lmsw,
+}
+
+/// The exit qualification of a VM exit caused by a control register access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrAccessQualification {
+    /// The number of the control register, 0 for `clts` and `lmsw`.
+    pub cr: u8,
+    /// The kind of the access.
+    pub access: CrAccessType,
+    /// Whether the operand of `lmsw` is in memory instead of a register.
+    pub lmsw_memory_operand: bool,
+    /// The general-purpose register of a move, in the order of the instruction encoding:
+    /// 0 for RAX, 1 for RCX, up to 15 for R15.
+    pub register: u8,
+    /// The source operand of `lmsw`.
+    pub lmsw_source: u16,
+}
+
+impl CrAccessQualification {
+    /// Decodes the exit qualification.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        CrAccessQualification {
+            cr: raw as u8 & 0xf,
+            access: match (raw >> 4) & 0b11 {
+                0 => CrAccessType::MovToCr,
+                1 => CrAccessType::MovFromCr,
+                2 => CrAccessType::Clts,
+                _ => CrAccessType::Lmsw,
+            },
+            lmsw_memory_operand: raw & (1 << 6) != 0,
+            register: (raw >> 8) as u8 & 0xf,
+            lmsw_source: (raw >> 16) as u16,
+        }
+    }
+}
+
+/// The exit qualification of a VM exit caused by a move to or from a debug register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrAccessQualification {
+    /// The number of the debug register.
+    pub dr: u8,
+    /// Whether the debug register is read instead of written.
+    pub is_read: bool,
+    /// The general-purpose register, encoded like [`CrAccessQualification::register`].
+    pub register: u8,
+}
+
+impl DrAccessQualification {
+    /// Decodes the exit qualification.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        DrAccessQualification {
+            dr: raw as u8 & 0b111,
+            is_read: raw & (1 << 4) != 0,
+            register: (raw >> 8) as u8 & 0xf,
+        }
+    }
+}
+
+/// The exit qualification of a VM exit caused by an I/O instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoQualification {
+    /// The size of the access in bytes: 1, 2 or 4.
+    pub size: u8,
+    /// Whether the instruction reads from the port (`in`, `ins`) instead of writing to it.
+    pub is_in: bool,
+    /// Whether the instruction is a string instruction (`ins`, `outs`).
+    pub is_string: bool,
+    /// Whether the instruction has a `rep` prefix.
+    pub is_rep: bool,
+    /// Whether the port is encoded as an immediate instead of in DX.
+    pub is_immediate: bool,
+    /// The port number.
+    pub port: u16,
+}
+
+impl IoQualification {
+    /// Decodes the exit qualification.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        IoQualification {
+            size: (raw as u8 & 0b111) + 1,
+            is_in: raw & (1 << 3) != 0,
+            is_string: raw & (1 << 4) != 0,
+            is_rep: raw & (1 << 5) != 0,
+            is_immediate: raw & (1 << 6) != 0,
+            port: (raw >> 16) as u16,
+        }
+    }
+}
+
+/// The address size of an instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSize {
+    /// 16-bit addressing.
+    Bits16,
+    /// 32-bit addressing.
+    Bits32,
+    /// 64-bit addressing.
+    Bits64,
+}
+
+impl AddressSize {
+    /// Returns the mask of the bits of an address of this size.
+    #[inline]
+    pub const fn mask(self) -> u64 {
+        match self {
+            AddressSize::Bits16 => 0xffff,
+            AddressSize::Bits32 => 0xffff_ffff,
+            AddressSize::Bits64 => !0,
+        }
+    }
+}
+
+/// A segment register, in the order of the instruction encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentRegister {
+    /// The ES register.
+    Es,
+    /// The CS register.
+    Cs,
+    /// The SS register.
+    Ss,
+    /// The DS register.
+    Ds,
+    /// The FS register.
+    Fs,
+    /// The GS register.
+    Gs,
+}
+
+/// The instruction of a VM exit caused by an access to the GDTR or IDTR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTableInstruction {
+    /// `sgdt`.
+    Sgdt,
+    /// `sidt`.
+    Sidt,
+    /// `lgdt`.
+    Lgdt,
+    /// `lidt`.
+    Lidt,
+}
+
+/// The instruction of a VM exit caused by an access to the LDTR or TR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LdtrTrInstruction {
+    /// `sldt`.
+    Sldt,
+    /// `str`.
+    Str,
+    /// `lldt`.
+    Lldt,
+    /// `ltr`.
+    Ltr,
+}
+
+/// The VM-exit instruction-information field, which describes the operands of the
+/// instruction that caused a VM exit.
+///
+/// The layout depends on the exit reason, but the fields that several formats have in common
+/// are at the same bits. Each accessor documents the exits for which its field is defined.
+/// See section 28.2.5 "Information for VM Exits Due to Instruction Execution" of the Intel SDM,
+/// volume 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct InstructionInformation(pub u32);
+
+impl InstructionInformation {
+    /// Returns the address size of the memory operand.
+    ///
+    /// Defined for INS/OUTS, descriptor-table, LLDT/LTR, VMX, INVEPT/INVPCID/INVVPID,
+    /// VMREAD/VMWRITE and XSAVES/XRSTORS exits.
+    #[inline]
+    pub const fn address_size(self) -> AddressSize {
+        match (self.0 >> 7) & 0b111 {
+            0 => AddressSize::Bits16,
+            1 => AddressSize::Bits32,
+            _ => AddressSize::Bits64,
+        }
+    }
+
+    /// Returns the segment register of the memory operand.
+    ///
+    /// Defined for the same exits as [`address_size`](Self::address_size). For OUTS, this is
+    /// the segment of the source operand, INS always uses ES.
+    #[inline]
+    pub const fn segment(self) -> SegmentRegister {
+        match (self.0 >> 15) & 0b111 {
+            0 => SegmentRegister::Es,
+            1 => SegmentRegister::Cs,
+            2 => SegmentRegister::Ss,
+            3 => SegmentRegister::Ds,
+            4 => SegmentRegister::Fs,
+            _ => SegmentRegister::Gs,
+        }
+    }
+
+    /// Returns whether the operand is a register instead of a memory operand.
+    ///
+    /// Defined for LLDT/LTR and VMREAD/VMWRITE exits, the operands of all other exits with a
+    /// memory operand are always in memory.
+    #[inline]
+    pub const fn is_register_operand(self) -> bool {
+        self.0 & (1 << 10) != 0
+    }
+
+    /// Returns the scale factor of the index register: 1, 2, 4 or 8.
+    ///
+    /// Defined for exits with memory operands, except INS/OUTS.
+    #[inline]
+    pub const fn scale(self) -> u8 {
+        1 << (self.0 & 0b11)
+    }
+
+    /// Returns the index register of the memory operand, in the order of the instruction
+    /// encoding, or `None` if there is no index.
+    ///
+    /// Defined for the same exits as [`scale`](Self::scale).
+    #[inline]
+    pub const fn index_register(self) -> Option<u8> {
+        if self.0 & (1 << 22) == 0 {
+            Some((self.0 >> 18) as u8 & 0xf)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the base register of the memory operand, in the order of the instruction
+    /// encoding, or `None` if there is no base.
+    ///
+    /// Defined for the same exits as [`scale`](Self::scale).
+    #[inline]
+    pub const fn base_register(self) -> Option<u8> {
+        if self.0 & (1 << 27) == 0 {
+            Some((self.0 >> 23) as u8 & 0xf)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the register operand in bits 3 to 6.
+    ///
+    /// This is the register operand of LLDT/LTR and VMREAD/VMWRITE exits if
+    /// [`is_register_operand`](Self::is_register_operand) is set, and the destination of
+    /// RDRAND/RDSEED/TPAUSE/UMWAIT exits.
+    #[inline]
+    pub const fn register_1(self) -> u8 {
+        (self.0 >> 3) as u8 & 0xf
+    }
+
+    /// Returns the register operand in bits 28 to 31.
+    ///
+    /// This is the field encoding register of VMREAD/VMWRITE exits and the type register of
+    /// INVEPT/INVPCID/INVVPID exits.
+    #[inline]
+    pub const fn register_2(self) -> u8 {
+        (self.0 >> 28) as u8
+    }
+
+    /// Returns the instruction of a VM exit caused by an access to the GDTR or IDTR.
+    #[inline]
+    pub const fn descriptor_table_instruction(self) -> DescriptorTableInstruction {
+        match (self.0 >> 28) & 0b11 {
+            0 => DescriptorTableInstruction::Sgdt,
+            1 => DescriptorTableInstruction::Sidt,
+            2 => DescriptorTableInstruction::Lgdt,
+            _ => DescriptorTableInstruction::Lidt,
+        }
+    }
+
+    /// Returns the instruction of a VM exit caused by an access to the LDTR or TR.
+    #[inline]
+    pub const fn ldtr_tr_instruction(self) -> LdtrTrInstruction {
+        match (self.0 >> 28) & 0b11 {
+            0 => LdtrTrInstruction::Sldt,
+            1 => LdtrTrInstruction::Str,
+            2 => LdtrTrInstruction::Lldt,
+            _ => LdtrTrInstruction::Ltr,
+        }
+    }
+
+    /// Returns the effective address of the memory operand, i.e. the offset into its segment.
+    ///
+    /// The displacement is the exit qualification of the VM exit, and `register` returns the
+    /// value of the general-purpose register with the given number. Returns `None` for
+    /// register operands.
+    ///
+    /// Defined for the same exits as [`scale`](Self::scale).
+    pub fn effective_address<F>(self, displacement: u64, mut register: F) -> Option<u64>
+    where
+        F: FnMut(u8) -> u64,
+    {
+        if self.is_register_operand() {
+            return None;
+        }
+        let mut address = displacement;
+        if let Some(base) = self.base_register() {
+            address = address.wrapping_add(register(base));
+        }
+        if let Some(index) = self.index_register() {
+            address = address.wrapping_add(register(index).wrapping_mul(self.scale().into()));
+        }
+        Some(address & self.address_size().mask())
+    }
+}
+
+bitflags! {
+    /// The exit qualification of a VM exit caused by an EPT violation.
+    pub struct EptViolationQualification: u64 {
+        /// The access was a data read.
+        const READ = 1;
+        /// The access was a data write.
+        const WRITE = 1 << 1;
+        /// The access was an instruction fetch.
+        const INSTRUCTION_FETCH = 1 << 2;
+        /// The guest-physical address was readable.
+        const READABLE = 1 << 3;
+        /// The guest-physical address was writable.
+        const WRITABLE = 1 << 4;
+        /// The guest-physical address was executable, for supervisor-mode linear addresses if
+        /// mode-based execute control is enabled.
+        const EXECUTABLE = 1 << 5;
+        /// The guest-physical address was executable for user-mode linear addresses, if
+        /// mode-based execute control is enabled.
+        const USER_EXECUTABLE = 1 << 6;
+        /// The guest-linear address field is valid.
+        const GUEST_LINEAR_ADDRESS_VALID = 1 << 7;
+        /// The access was the translation of a linear address, not an access to the guest
+        /// paging structures. Only valid if `GUEST_LINEAR_ADDRESS_VALID` is set.
+        const TRANSLATED_ACCESS = 1 << 8;
+        /// The linear address was a user-mode address.
+        const USER_MODE_LINEAR_ADDRESS = 1 << 9;
+        /// The linear address was writable.
+        const READ_WRITE_PAGE = 1 << 10;
+        /// The linear address was not executable.
+        const EXECUTE_DISABLE_PAGE = 1 << 11;
+        /// The violation occurred while `iret` unblocked NMIs.
+        const NMI_UNBLOCKING_IRET = 1 << 12;
+        /// The access was a shadow-stack access.
+        const SHADOW_STACK = 1 << 13;
+        /// The guest-physical address was a supervisor shadow-stack page.
+        const SUPERVISOR_SHADOW_STACK = 1 << 14;
+        /// The access was subject to guest-paging verification.
+        const GUEST_PAGING_VERIFICATION = 1 << 15;
+        /// The access was asynchronous to instruction execution.
+        const ASYNCHRONOUS = 1 << 16;
+    }
+}
+
+/// The kind of an SPP-related event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SppEventKind {
+    /// The lookup reached an SPP entry that isn't valid, so the SPP tables have to be filled
+    /// in for the page.
+    Miss,
+    /// The lookup reached an SPP entry with reserved bits set.
+    Misconfiguration,
+}
+
+/// The exit qualification of a VM exit caused by an SPP-related event.
+///
+/// The guest-physical address of the access is in the guest-physical address field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SppEventQualification {
+    /// The kind of the event.
+    pub kind: SppEventKind,
+    /// Whether the event occurred while `iret` unblocked NMIs.
+    pub nmi_unblocking_iret: bool,
+}
+
+impl SppEventQualification {
+    /// Decodes the exit qualification.
+    #[inline]
+    pub const fn from_raw(raw: u64) -> Self {
+        SppEventQualification {
+            kind: if raw & (1 << 11) != 0 {
+                SppEventKind::Misconfiguration
+            } else {
+                SppEventKind::Miss
+            },
+            nmi_unblocking_iret: raw & (1 << 12) != 0,
+        }
+    }
+}
+
+bitflags! {
+    /// The exit qualification of a VM exit caused by a debug exception, which holds the bits
+    /// that would have been set in DR6.
+    pub struct DebugExceptionQualification: u64 {
+        /// Breakpoint condition 0 was met.
+        const B0 = 1;
+        /// Breakpoint condition 1 was met.
+        const B1 = 1 << 1;
+        /// Breakpoint condition 2 was met.
+        const B2 = 1 << 2;
+        /// Breakpoint condition 3 was met.
+        const B3 = 1 << 3;
+        /// A debug register was accessed while general detect was enabled.
+        const BD = 1 << 13;
+        /// The exception was caused by a single step.
+        const BS = 1 << 14;
+        /// The exception occurred inside an RTM region.
+        const RTM = 1 << 16;
+    }
+}
+
+impl_flags_display!(
+    ExitReasonFlags,
+    EptViolationQualification,
+    DebugExceptionQualification
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_reason_field() {
+        let field = ExitReasonField::from_raw(0x8000_0021);
+        assert_eq!(field.reason, ExitReason::InvalidGuestState);
+        assert!(field.is_entry_failure());
+        assert!(!field.is_enclave_mode());
+        assert_eq!(ExitReason::from_raw(48), ExitReason::EptViolation);
+        assert_eq!(ExitReason::from_raw(35), ExitReason::Unknown(35));
+        for number in 0..80 {
+            assert_eq!(ExitReason::from_raw(number).as_raw(), number as u16);
+        }
+    }
+
+    #[test]
+    fn exit_qualifications() {
+        // mov cr3, rbx
+        let cr = CrAccessQualification::from_raw(0x303);
+        assert_eq!(
+            (cr.cr, cr.access, cr.register),
+            (3, CrAccessType::MovToCr, 3)
+        );
+        // in al, 0x60
+        let io = IoQualification::from_raw(0x0060_0048);
+        assert_eq!((io.size, io.is_in, io.is_immediate), (1, true, true));
+        assert_eq!(io.port, 0x60);
+        // rep outsw with the port in dx
+        let io = IoQualification::from_raw(0x03f8_0031);
+        assert_eq!(
+            (io.size, io.is_in, io.is_string, io.is_rep),
+            (2, false, true, true)
+        );
+        let spp = SppEventQualification::from_raw(1 << 11 | 1 << 12);
+        assert_eq!(spp.kind, SppEventKind::Misconfiguration);
+        assert!(spp.nmi_unblocking_iret);
+    }
+
+    #[test]
+    fn instruction_information() {
+        // lgdt [rax + rcx * 4 + disp] with 64-bit addressing
+        let info = InstructionInformation(0x2004_0102 | 3 << 15);
+        assert_eq!(info.address_size(), AddressSize::Bits64);
+        assert_eq!(info.segment(), SegmentRegister::Ds);
+        assert_eq!(
+            info.descriptor_table_instruction(),
+            DescriptorTableInstruction::Lgdt
+        );
+        assert_eq!(
+            (info.base_register(), info.index_register()),
+            (Some(0), Some(1))
+        );
+        let address = info.effective_address(0x10, |register| match register {
+            0 => 0x1000,
+            _ => 0x20,
+        });
+        assert_eq!(address, Some(0x1090));
+        // no base and no index
+        assert_eq!(
+            InstructionInformation(1 << 27 | 1 << 22).effective_address(0x42, |_| 1),
+            Some(0x42)
+        );
+    }
+
+    #[test]
+    fn idt_vectoring_info() {
+        assert_eq!(IdtVectoringInfo::from_raw(0x0b0e, 2, 0), None);
+        // a page fault with error code 2, interrupted by an EPT violation
+        let info = IdtVectoringInfo::from_raw(0x8000_0b0e, 2, 3).unwrap();
+        assert_eq!(info.kind, InterruptionType::HardwareException);
+        assert_eq!(info.reinject().raw(), 0x8000_0b0e);
+        assert_eq!(info.reinject().error_code(), Some(2));
+    }
+}
@@ -0,0 +1,215 @@
+//! Enabling AMD SVM.
+//!
+//! SVM is enabled with [`enable`], which checks that the processor and the firmware allow SVM,
+//! sets EFER.SVME and sets up the host save area, so that guests can be entered with
+//! [`vmrun`](crate::instructions::svm::vmrun).
+//!
+//! Cached guest translations are invalidated with [`flush_guest_tlb`], either immediately with
+//! `invlpga` or through the TLB control of the VMCB on the next `vmrun`.
+
+use crate::cpuid::{cpuid, CpuidResult};
+use crate::instructions::svm;
+use crate::registers::model_specific::{Efer, EferFlags, VmCr, VmCrFlags, VmHsavePa};
+use crate::structures::paging::PhysFrame;
+use crate::structures::vmcb::{TlbControl, VmcbControlArea};
+use crate::VirtAddr;
+use core::fmt;
+
+/// The reason why SVM can't be enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableError {
+    /// The processor doesn't support SVM, according to CPUID leaf 0x8000_0001.
+    Unsupported,
+    /// The firmware disabled SVM and locked the VM_CR register.
+    DisabledByFirmware,
+}
+
+impl fmt::Display for EnableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnableError::Unsupported => f.write_str("SVM is not supported by the processor"),
+            EnableError::DisabledByFirmware => f.write_str("SVM is disabled by the firmware"),
+        }
+    }
+}
+
+/// Returns whether the processor supports SVM, according to CPUID leaf 0x8000_0001, ecx
+/// bit 2.
+#[inline]
+pub fn is_supported() -> bool {
+    is_supported_with(cpuid)
+}
+
+fn is_supported_with<F>(mut query: F) -> bool
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    query(0x8000_0000, 0).eax >= 0x8000_0001 && query(0x8000_0001, 0).ecx & (1 << 2) != 0
+}
+
+/// Enables SVM on the current processor, with the given frame as host save area.
+///
+/// Checks that the processor supports SVM and that the VM_CR register doesn't disable it. If
+/// the firmware disabled SVM without locking the register, SVM is allowed again. Then
+/// EFER.SVME is set and the frame is written to the VM_HSAVE_PA register.
+///
+/// ## Safety
+///
+/// This function is unsafe because the processor writes the host state to the frame on every
+/// `vmrun`, so the caller must ensure that the frame isn't used for anything else while SVM
+/// is enabled.
+pub unsafe fn enable(host_save_area: PhysFrame) -> Result<(), EnableError> {
+    if !is_supported() {
+        return Err(EnableError::Unsupported);
+    }
+
+    if let Some(flags) = enabled_vm_cr(VmCr::read())? {
+        // the register is unlocked, so clearing the flag doesn't fault
+        VmCr::write(flags);
+    }
+
+    // SVME only allows executing the SVM instructions
+    Efer::write(Efer::read() | EferFlags::SECURE_VIRTUAL_MACHINE_ENABLE);
+    VmHsavePa::write(host_save_area);
+    Ok(())
+}
+
+/// Returns the SVM control flags that allow SVM again, or `None` if SVM isn't disabled.
+fn enabled_vm_cr(flags: VmCrFlags) -> Result<Option<VmCrFlags>, EnableError> {
+    if !flags.contains(VmCrFlags::SVM_DISABLE) {
+        Ok(None)
+    } else if flags.contains(VmCrFlags::LOCK) {
+        Err(EnableError::DisabledByFirmware)
+    } else {
+        Ok(Some(flags - VmCrFlags::SVM_DISABLE))
+    }
+}
+
+/// Returns whether the processor supports flushing the TLB entries of a single ASID with
+/// [`TlbControl::FlushAsid`], according to CPUID leaf 0x8000_000A, edx bit 6.
+#[inline]
+pub fn flush_by_asid_supported() -> bool {
+    flush_by_asid_supported_with(cpuid)
+}
+
+fn flush_by_asid_supported_with<F>(mut query: F) -> bool
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    query(0x8000_0000, 0).eax >= 0x8000_000a && query(0x8000_000a, 0).edx & (1 << 6) != 0
+}
+
+/// The guest translations that [`flush_guest_tlb`] invalidates at least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbFlushScope {
+    /// The translation of a guest virtual address in the ASID of the VMCB, invalidated
+    /// immediately with `invlpga`.
+    Address(VirtAddr),
+    /// All translations of the ASID of the VMCB, including those derived from the nested page
+    /// tables, invalidated on the next `vmrun`.
+    Asid,
+    /// The translations of all ASIDs including the host, invalidated on the next `vmrun`.
+    All,
+}
+
+/// Invalidates cached guest translations of the given VMCB.
+///
+/// If the processor doesn't support flushing a single ASID, [`Asid`](TlbFlushScope::Asid)
+/// falls back to flushing the whole TLB. A flush requested through the TLB control isn't
+/// narrowed by a later request, the TLB control should be reset to
+/// [`DoNothing`](TlbControl::DoNothing) after the next `vmrun`.
+///
+/// ## Safety
+///
+/// This function is unsafe because `invlpga` must be executed in ring 0 with SVM enabled.
+pub unsafe fn flush_guest_tlb(control: &mut VmcbControlArea, scope: TlbFlushScope) {
+    match scope {
+        TlbFlushScope::Address(addr) => svm::invlpga(addr, control.guest_asid),
+        TlbFlushScope::Asid | TlbFlushScope::All => {
+            request_flush(control, scope, flush_by_asid_supported())
+        }
+    }
+}
+
+/// Sets the TLB control for a flush on the next `vmrun`, without narrowing a flush that was
+/// already requested.
+fn request_flush(control: &mut VmcbControlArea, scope: TlbFlushScope, flush_by_asid: bool) {
+    let requested = match scope {
+        TlbFlushScope::Asid if flush_by_asid => TlbControl::FlushAsid,
+        _ => TlbControl::FlushAll,
+    };
+    match control.tlb_control() {
+        Some(TlbControl::FlushAll) => {}
+        Some(TlbControl::FlushAsid) if requested == TlbControl::FlushAsid => {}
+        _ => control.set_tlb_control(requested),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn support() {
+        use crate::cpuid::fake as query;
+
+        assert!(is_supported_with(query(&[
+            (0x8000_0000, [0x8000_0008, 0, 0, 0]),
+            (0x8000_0001, [0, 0, 1 << 2, 0]),
+        ])));
+        // the leaf isn't read if it doesn't exist
+        assert!(!is_supported_with(query(&[
+            (0x8000_0000, [0x8000_0000, 0, 0, 0]),
+            (0x8000_0001, [0, 0, 1 << 2, 0]),
+        ])));
+        assert!(!is_supported_with(query(&[(
+            0x8000_0000,
+            [0x8000_0008, 0, 0, 0]
+        )])));
+    }
+
+    #[test]
+    fn flush_by_asid_support() {
+        use crate::cpuid::fake as query;
+
+        assert!(flush_by_asid_supported_with(query(&[
+            (0x8000_0000, [0x8000_001f, 0, 0, 0]),
+            (0x8000_000a, [1, 0x8000, 0, 1 << 6]),
+        ])));
+        assert!(!flush_by_asid_supported_with(query(&[
+            (0x8000_0000, [0x8000_0008, 0, 0, 0]),
+            (0x8000_000a, [1, 0x8000, 0, 1 << 6]),
+        ])));
+        assert!(!flush_by_asid_supported_with(query(&[
+            (0x8000_0000, [0x8000_001f, 0, 0, 0]),
+            (0x8000_000a, [1, 0x8000, 0, !(1 << 6)]),
+        ])));
+    }
+
+    #[test]
+    fn vm_cr() {
+        let disabled = VmCrFlags::from_bits_truncate(0x14);
+        assert_eq!(disabled, VmCrFlags::DISABLE_A20M | VmCrFlags::SVM_DISABLE);
+        assert_eq!(enabled_vm_cr(disabled), Ok(Some(VmCrFlags::DISABLE_A20M)));
+        assert_eq!(enabled_vm_cr(VmCrFlags::LOCK), Ok(None));
+        assert_eq!(
+            enabled_vm_cr(VmCrFlags::from_bits_truncate(0x18)),
+            Err(EnableError::DisabledByFirmware)
+        );
+    }
+
+    #[test]
+    fn request_flush_widens() {
+        let mut control = VmcbControlArea::new();
+        request_flush(&mut control, TlbFlushScope::Asid, true);
+        assert_eq!(control.tlb_control(), Some(TlbControl::FlushAsid));
+        request_flush(&mut control, TlbFlushScope::All, true);
+        assert_eq!(control.tlb_control(), Some(TlbControl::FlushAll));
+        request_flush(&mut control, TlbFlushScope::Asid, true);
+        assert_eq!(control.tlb_control(), Some(TlbControl::FlushAll));
+
+        let mut control = VmcbControlArea::new();
+        request_flush(&mut control, TlbFlushScope::Asid, false);
+        assert_eq!(control.tlb_control(), Some(TlbControl::FlushAll));
+    }
+}
@@ -0,0 +1,219 @@
+//! Helpers for timekeeping with the time stamp counter (TSC).
+//!
+//! The TSC is read with [`read_tsc`] and counts at the frequency returned by
+//! [`tsc_frequency`]. The frequency is reported by several CPUID leaves, which differ in
+//! precision, so the result carries the [`Confidence`] of its source. If the processor
+//! doesn't report the frequency, it can be measured against another timer using
+//! [`calibrate_tsc`].
+
+use crate::cpuid::{cpuid, has_leaf, CpuidResult};
+
+/// How the TSC frequency was determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencySource {
+    /// Computed from the core crystal clock and the TSC ratio of CPUID leaf 0x15.
+    CrystalClock,
+    /// Reported by the hypervisor through the timing leaf 0x4000_0010.
+    Hypervisor,
+    /// The processor base frequency of CPUID leaf 0x16.
+    ProcessorBaseFrequency,
+    /// Measured against another timer.
+    Calibration,
+}
+
+/// The expected precision of a [`TscFrequency`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// The frequency is measured and includes the error of the reference timer.
+    Low,
+    /// The frequency is a nominal value that is rounded to whole megahertz.
+    Medium,
+    /// The frequency is reported exactly by the processor or the hypervisor.
+    High,
+}
+
+/// A TSC frequency, tagged with the way it was determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TscFrequency {
+    /// The frequency in hertz.
+    pub hz: u64,
+    /// The way the frequency was determined.
+    pub source: FrequencySource,
+}
+
+impl TscFrequency {
+    /// Reads the TSC frequency using the given function instead of the `cpuid` instruction.
+    ///
+    /// The function is called with a leaf and sub-leaf and must return the corresponding
+    /// CPUID result. See [`tsc_frequency`] for the leaves that are used.
+    pub fn read_with<F>(mut query: F) -> Option<TscFrequency>
+    where
+        F: FnMut(u32, u32) -> CpuidResult,
+    {
+        let found = |hz: u64, source| match hz {
+            0 => None,
+            hz => Some(TscFrequency { hz, source }),
+        };
+
+        if has_leaf(0x15, &mut query) {
+            // eax is the denominator and ebx the numerator of the TSC/crystal ratio, ecx
+            // the crystal frequency in hertz, if enumerated
+            let ratio = query(0x15, 0);
+            if ratio.eax != 0 {
+                let hz = u64::from(ratio.ecx) * u64::from(ratio.ebx) / u64::from(ratio.eax);
+                if let Some(frequency) = found(hz, FrequencySource::CrystalClock) {
+                    return Some(frequency);
+                }
+            }
+        }
+
+        // the hypervisor leaves are only valid if CPUID 1, ecx bit 31 is set
+        if query(1, 0).ecx & (1 << 31) != 0 && has_leaf(0x4000_0010, &mut query) {
+            let khz = u64::from(query(0x4000_0010, 0).eax);
+            if let Some(frequency) = found(khz * 1_000, FrequencySource::Hypervisor) {
+                return Some(frequency);
+            }
+        }
+
+        if has_leaf(0x16, &mut query) {
+            let mhz = u64::from(query(0x16, 0).eax & 0xffff);
+            return found(mhz * 1_000_000, FrequencySource::ProcessorBaseFrequency);
+        }
+
+        None
+    }
+
+    /// Returns the expected precision of the frequency.
+    #[inline]
+    pub fn confidence(&self) -> Confidence {
+        match self.source {
+            FrequencySource::CrystalClock | FrequencySource::Hypervisor => Confidence::High,
+            FrequencySource::ProcessorBaseFrequency => Confidence::Medium,
+            FrequencySource::Calibration => Confidence::Low,
+        }
+    }
+}
+
+/// Reads the time stamp counter using the `rdtsc` instruction.
+#[inline]
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Returns whether the TSC runs at a constant rate in all power states, as reported by
+/// CPUID leaf 0x8000_0007.
+///
+/// Without an invariant TSC, the frequency may change with the processor frequency, so neither
+/// the reported nor a calibrated frequency can be relied on for timekeeping.
+#[inline]
+pub fn is_tsc_invariant() -> bool {
+    has_leaf(0x8000_0007, &mut cpuid) && cpuid(0x8000_0007, 0).edx & (1 << 8) != 0
+}
+
+/// Returns the TSC frequency reported by the processor or the hypervisor.
+///
+/// The sources are tried in the order of their precision: the crystal clock ratio of CPUID
+/// leaf 0x15, the hypervisor timing leaf 0x4000_0010, and the processor base frequency of
+/// leaf 0x16. Returns `None` if none of them reports a frequency.
+#[inline]
+pub fn tsc_frequency() -> Option<TscFrequency> {
+    TscFrequency::read_with(cpuid)
+}
+
+/// Measures the TSC frequency against another timer.
+///
+/// The `wait` function must busy wait for the given number of microseconds, e.g. using
+/// [`pit_wait`](crate::structures::pit::pit_wait). Longer durations give more precise results.
+///
+/// Panics if `microseconds` is zero.
+pub fn calibrate_tsc<F>(microseconds: u64, wait: F) -> TscFrequency
+where
+    F: FnOnce(u64),
+{
+    assert!(
+        microseconds > 0,
+        "the calibration duration must not be zero"
+    );
+    let start = read_tsc();
+    wait(microseconds);
+    let ticks = read_tsc().wrapping_sub(start);
+    TscFrequency {
+        hz: ticks_per_second(ticks, microseconds),
+        source: FrequencySource::Calibration,
+    }
+}
+
+/// Converts the number of ticks in the given duration to a frequency, without overflowing
+/// for long durations.
+#[inline]
+fn ticks_per_second(ticks: u64, microseconds: u64) -> u64 {
+    (u128::from(ticks) * 1_000_000 / u128::from(microseconds)) as u64
+}
+
+/// Returns the TSC frequency reported by CPUID, or measures it using channel 2 of the PIT
+/// for 10 milliseconds if it isn't reported.
+///
+/// ## Safety
+///
+/// This function is unsafe because the caller must ensure that channel 2 of the PIT is not
+/// used concurrently.
+pub unsafe fn tsc_frequency_or_calibrate() -> TscFrequency {
+    use crate::structures::pit::pit_wait;
+
+    tsc_frequency().unwrap_or_else(|| calibrate_tsc(10_000, |us| pit_wait(us)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuid::fake as query;
+
+    #[test]
+    fn calibration() {
+        assert_eq!(ticks_per_second(30_000_000, 10_000), 3_000_000_000);
+        // 10 minutes at 3 GHz overflow the multiplication in 64 bits
+        assert_eq!(
+            ticks_per_second(1_800_000_000_000, 600_000_000),
+            3_000_000_000
+        );
+        let frequency = calibrate_tsc(1, |_| {});
+        assert_eq!(frequency.source, FrequencySource::Calibration);
+    }
+
+    #[test]
+    #[should_panic]
+    fn calibration_without_duration() {
+        calibrate_tsc(0, |_| {});
+    }
+
+    #[test]
+    fn frequency_sources() {
+        // 24 MHz crystal with a TSC ratio of 125/2
+        let crystal = [(0, [0x16, 0, 0, 0]), (0x15, [2, 125, 24_000_000, 0])];
+        let frequency = TscFrequency::read_with(query(&crystal)).unwrap();
+        assert_eq!(frequency.hz, 1_500_000_000);
+        assert_eq!(frequency.confidence(), Confidence::High);
+
+        // crystal frequency not enumerated, falls back to the base frequency
+        let base = [
+            (0, [0x16, 0, 0, 0]),
+            (0x15, [2, 125, 0, 0]),
+            (0x16, [2_100, 0, 0, 0]),
+        ];
+        let frequency = TscFrequency::read_with(query(&base)).unwrap();
+        assert_eq!(frequency.hz, 2_100_000_000);
+        assert_eq!(frequency.source, FrequencySource::ProcessorBaseFrequency);
+
+        let hypervisor = [
+            (0, [0xd, 0, 0, 0]),
+            (1, [0, 0, 1 << 31, 0]),
+            (0x4000_0000, [0x4000_0010, 0, 0, 0]),
+            (0x4000_0010, [2_400_000, 0, 0, 0]),
+        ];
+        let frequency = TscFrequency::read_with(query(&hypervisor)).unwrap();
+        assert_eq!(frequency.hz, 2_400_000_000);
+        assert_eq!(frequency.source, FrequencySource::Hypervisor);
+
+        assert_eq!(TscFrequency::read_with(query(&[(0, [0xd, 0, 0, 0])])), None);
+    }
+}
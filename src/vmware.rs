@@ -0,0 +1,331 @@
+//! The VMware backdoor, an I/O port through which guests call services of the host.
+//!
+//! A backdoor call is an `in` from port 0x5658 with the magic value in EAX and the command in
+//! ECX. VMware intercepts it and returns the results in the general-purpose registers. The
+//! availability of the backdoor is checked with [`version`].
+//!
+//! Longer requests, e.g. the RPCI commands of the guest tools, are exchanged as messages over
+//! an [`RpcChannel`], which is opened, used for one or more request-reply pairs with
+//! [`send`](RpcChannel::send) and [`receive`](RpcChannel::receive), and closed again.
+
+use crate::cpuid::hypervisor::{HypervisorInfo, HypervisorVendor};
+#[cfg(not(feature = "external_asm"))]
+use core::arch::asm;
+use core::fmt;
+
+/// The value of EAX for backdoor calls, `VMXh`.
+pub const BACKDOOR_MAGIC: u32 = 0x564d_5868;
+
+/// The I/O port of the backdoor.
+pub const BACKDOOR_PORT: u16 = 0x5658;
+
+/// The protocol of RPC channels for requests of the guest, e.g. `info-get`.
+pub const RPCI_PROTOCOL: u32 = 0x4943_5052;
+
+/// The protocol of RPC channels for requests of the host, which the guest tools poll.
+pub const TCLO_PROTOCOL: u32 = 0x4f4c_4354;
+
+/// The commands of the backdoor, passed in the lower half of ECX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum BackdoorCommand {
+    /// Returns the processor frequency in MHz in EAX.
+    GetMhz = 1,
+    /// Returns the version of the backdoor in EAX, [`BACKDOOR_MAGIC`] in EBX and the product
+    /// type in ECX.
+    GetVersion = 10,
+    /// Operates an RPC channel, the sub-command is passed in the upper half of ECX.
+    Message = 30,
+    /// Returns the host time in seconds in ESI:EDX and the microseconds in EBX.
+    GetTimeFull = 46,
+}
+
+/// The registers passed to and returned from a backdoor call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct BackdoorRegisters {
+    /// The value of EAX, [`BACKDOOR_MAGIC`] on entry.
+    pub eax: u32,
+    /// The value of EBX, usually the argument on entry.
+    pub ebx: u32,
+    /// The value of ECX, the command on entry.
+    pub ecx: u32,
+    /// The value of EDX, the port and the channel on entry.
+    pub edx: u32,
+    /// The value of ESI.
+    pub esi: u32,
+    /// The value of EDI.
+    pub edi: u32,
+}
+
+impl BackdoorRegisters {
+    /// Creates the registers of a backdoor call with the given command and argument.
+    #[inline]
+    pub const fn new(command: BackdoorCommand, arg: u32) -> Self {
+        BackdoorRegisters {
+            eax: BACKDOOR_MAGIC,
+            ebx: arg,
+            ecx: command as u32,
+            edx: BACKDOOR_PORT as u32,
+            esi: 0,
+            edi: 0,
+        }
+    }
+
+    /// Creates the registers of a message sub-command on the given channel, passing the
+    /// cookies of the channel in ESI and EDI.
+    #[inline]
+    const fn message(sub_command: u16, channel: &RpcChannel, arg: u32) -> Self {
+        BackdoorRegisters {
+            eax: BACKDOOR_MAGIC,
+            ebx: arg,
+            ecx: BackdoorCommand::Message as u32 | (sub_command as u32) << 16,
+            edx: BACKDOOR_PORT as u32 | (channel.id as u32) << 16,
+            esi: channel.cookie_high,
+            edi: channel.cookie_low,
+        }
+    }
+
+    /// Returns the message status in the upper half of ECX.
+    #[inline]
+    const fn message_status(&self) -> u16 {
+        (self.ecx >> 16) as u16
+    }
+}
+
+/// Performs a backdoor call with the given registers and returns their values after the
+/// call.
+///
+/// ## Safety
+///
+/// This function is unsafe because outside of VMware the `in` reads from a port that may be
+/// used by a device, or causes a general protection fault if I/O ports aren't accessible, and
+/// because the effects of a call depend on its command.
+#[inline]
+pub unsafe fn backdoor(mut regs: BackdoorRegisters) -> BackdoorRegisters {
+    #[cfg(not(feature = "external_asm"))]
+    asm!(
+        // LLVM reserves rbx, so it is swapped with a scratch register around the call
+        "xchg {rbx:r}, rbx",
+        "in eax, dx",
+        "xchg {rbx:r}, rbx",
+        rbx = inout(reg) regs.ebx,
+        inout("eax") regs.eax,
+        inout("ecx") regs.ecx,
+        inout("edx") regs.edx,
+        inout("esi") regs.esi,
+        inout("edi") regs.edi,
+        options(nostack),
+    );
+
+    #[cfg(feature = "external_asm")]
+    crate::asm::x86_64_asm_vmware_backdoor(&mut regs);
+
+    regs
+}
+
+/// The version of the backdoor, returned by [`BackdoorCommand::GetVersion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackdoorVersion {
+    /// The version of the backdoor protocol.
+    pub version: u32,
+    /// The product type, e.g. 2 for ESXi.
+    pub product: u32,
+}
+
+/// Returns the version of the backdoor, or `None` if the processor doesn't run under VMware.
+///
+/// The backdoor is only called if the hypervisor signature is the one of VMware.
+///
+/// ## Safety
+///
+/// This function is unsafe because the backdoor call causes a general protection fault if
+/// I/O ports aren't accessible, e.g. in user mode.
+pub unsafe fn version() -> Option<BackdoorVersion> {
+    match HypervisorInfo::read() {
+        Some(info) if info.vendor == HypervisorVendor::VMware => {}
+        _ => return None,
+    }
+    let regs = backdoor(BackdoorRegisters::new(BackdoorCommand::GetVersion, !0));
+    if regs.ebx != BACKDOOR_MAGIC || regs.eax == !0 {
+        return None;
+    }
+    Some(BackdoorVersion {
+        version: regs.eax,
+        product: regs.ecx,
+    })
+}
+
+/// The sub-commands of [`BackdoorCommand::Message`].
+mod message {
+    pub const OPEN: u16 = 0;
+    pub const SEND_SIZE: u16 = 1;
+    pub const SEND_PAYLOAD: u16 = 2;
+    pub const RECEIVE_SIZE: u16 = 3;
+    pub const RECEIVE_PAYLOAD: u16 = 4;
+    pub const RECEIVE_STATUS: u16 = 5;
+    pub const CLOSE: u16 = 6;
+
+    /// The sub-command succeeded.
+    pub const STATUS_SUCCESS: u16 = 0x0001;
+    /// A reply is available.
+    pub const STATUS_DO_RECEIVE: u16 = 0x0002;
+
+    /// Requests cookies that authenticate the channel.
+    pub const FLAG_COOKIE: u32 = 0x8000_0000;
+}
+
+/// The error of an RPC channel operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcError {
+    /// The host didn't report success for a sub-command, contains the message status.
+    Failed(u16),
+    /// The reply didn't fit into the buffer, contains the length of the reply. The part that
+    /// fits was written to the buffer.
+    BufferTooSmall(usize),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Failed(status) => write!(f, "RPC failed with status {:#x}", status),
+            RpcError::BufferTooSmall(len) => {
+                write!(
+                    f,
+                    "the RPC reply of {} bytes doesn't fit into the buffer",
+                    len
+                )
+            }
+        }
+    }
+}
+
+/// An open RPC channel of the backdoor.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RpcChannel {
+    id: u16,
+    cookie_high: u32,
+    cookie_low: u32,
+}
+
+impl RpcChannel {
+    /// Opens an RPC channel with the given protocol, e.g. [`RPCI_PROTOCOL`].
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the processor must run under VMware, see [`version`].
+    pub unsafe fn open(protocol: u32) -> Result<RpcChannel, RpcError> {
+        let closed = RpcChannel {
+            id: 0,
+            cookie_high: 0,
+            cookie_low: 0,
+        };
+        let regs =
+            BackdoorRegisters::message(message::OPEN, &closed, protocol | message::FLAG_COOKIE);
+        let regs = check(backdoor(regs))?;
+        Ok(RpcChannel {
+            id: (regs.edx >> 16) as u16,
+            cookie_high: regs.esi,
+            cookie_low: regs.edi,
+        })
+    }
+
+    /// Sends a request over the channel.
+    pub fn send(&mut self, request: &[u8]) -> Result<(), RpcError> {
+        self.call(message::SEND_SIZE, request.len() as u32)?;
+        for chunk in request.chunks(4) {
+            let mut bytes = [0; 4];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            self.call(message::SEND_PAYLOAD, u32::from_le_bytes(bytes))?;
+        }
+        Ok(())
+    }
+
+    /// Receives the reply to the last request into the buffer and returns its length.
+    ///
+    /// Returns 0 if there is no reply.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, RpcError> {
+        let regs = self.call(message::RECEIVE_SIZE, 0)?;
+        if regs.message_status() & message::STATUS_DO_RECEIVE == 0 {
+            return Ok(0);
+        }
+        let len = regs.ebx as usize;
+        for offset in (0..len).step_by(4) {
+            let regs = self.call(message::RECEIVE_PAYLOAD, u32::from(message::STATUS_SUCCESS))?;
+            let bytes = regs.ebx.to_le_bytes();
+            let end = len.min(offset + 4).min(buffer.len());
+            if offset < end {
+                buffer[offset..end].copy_from_slice(&bytes[..end - offset]);
+            }
+        }
+        self.call(message::RECEIVE_STATUS, u32::from(message::STATUS_SUCCESS))?;
+        if len > buffer.len() {
+            return Err(RpcError::BufferTooSmall(len));
+        }
+        Ok(len)
+    }
+
+    /// Closes the channel.
+    pub fn close(mut self) -> Result<(), RpcError> {
+        self.call(message::CLOSE, 0).map(|_| ())
+    }
+
+    fn call(&mut self, sub_command: u16, arg: u32) -> Result<BackdoorRegisters, RpcError> {
+        let regs = BackdoorRegisters::message(sub_command, self, arg);
+        // the channel was opened, so the processor runs under VMware
+        check(unsafe { backdoor(regs) })
+    }
+}
+
+/// Sends an RPCI request, e.g. `info-get guestinfo.name`, over a new channel and receives
+/// the reply into the buffer.
+///
+/// Returns the length of the reply, which starts with `1 ` on success.
+///
+/// ## Safety
+///
+/// This function is unsafe because the processor must run under VMware, see [`version`].
+pub unsafe fn rpci(request: &[u8], reply: &mut [u8]) -> Result<usize, RpcError> {
+    let mut channel = RpcChannel::open(RPCI_PROTOCOL)?;
+    let result = channel.send(request).and_then(|()| channel.receive(reply));
+    channel.close()?;
+    result
+}
+
+/// Returns the registers if the message status reports success.
+#[inline]
+fn check(regs: BackdoorRegisters) -> Result<BackdoorRegisters, RpcError> {
+    match regs.message_status() & message::STATUS_SUCCESS {
+        0 => Err(RpcError::Failed(regs.message_status())),
+        _ => Ok(regs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_registers() {
+        let channel = RpcChannel {
+            id: 3,
+            cookie_high: 0x1111,
+            cookie_low: 0x2222,
+        };
+        let regs = BackdoorRegisters::message(message::SEND_SIZE, &channel, 16);
+        assert_eq!((regs.eax, regs.ebx), (BACKDOOR_MAGIC, 16));
+        assert_eq!((regs.ecx, regs.edx), (0x0001_001e, 0x0003_5658));
+        assert_eq!((regs.esi, regs.edi), (0x1111, 0x2222));
+
+        let reply = BackdoorRegisters {
+            ecx: 0x0003 << 16,
+            ..regs
+        };
+        assert_eq!(check(reply), Ok(reply));
+        let closed = BackdoorRegisters {
+            ecx: 0x0004 << 16,
+            ..regs
+        };
+        assert_eq!(check(closed), Err(RpcError::Failed(4)));
+    }
+}
@@ -0,0 +1,515 @@
+//! Enabling VMX and a virtual CPU built on it.
+//!
+//! VMX is enabled with [`enable`], which checks that the processor and the firmware allow VMX
+//! and sets CR4.VMXE, so that VMX root operation can be entered with
+//! [`vmxon`](crate::instructions::vmx::vmxon).
+//!
+//! A [`VmxVcpu`] bundles the VMCS region of a guest processor with its VPID, the launch state
+//! of the VMCS and the guest general-purpose registers, which aren't part of the VMCS. The
+//! guest is entered with [`VmxVcpu::run`], which uses `vmlaunch` the first time and
+//! `vmresume` afterwards, and returns the reason of the next VM exit.
+//!
+//! The processor must be in VMX root operation and the VMCS must be set up through [`Vmcs`]
+//! after [`VmxVcpu::init`] was called.
+//!
+//! Cached guest translations are invalidated with [`flush_guest_tlb`], which picks the
+//! narrowest `invept` or `invvpid` type that the processor supports.
+
+use crate::cpuid::{cpuid, CpuidResult};
+use crate::instructions::vmx::{
+    self, GuestRegisters, InveptType, InvvpidType, VmInstructionError, VmxError,
+};
+use crate::registers::control::{Cr0, Cr4, Cr4Flags};
+use crate::registers::model_specific::{
+    EptVpidCapabilities, FeatureControl, FeatureControlFlags, VmxEptVpidCap, VmxFixedBits,
+};
+use crate::structures::paging::ept::Eptp;
+use crate::structures::vmcs::{Vmcs, VmcsField16, VmcsRegion};
+use crate::structures::vmexit::ExitReasonField;
+use crate::{PhysAddr, VirtAddr};
+use core::fmt;
+
+/// The reason why VMX can't be enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableError {
+    /// The processor doesn't support VMX, according to CPUID leaf 1.
+    Unsupported,
+    /// The firmware locked the feature control register without allowing VMX outside SMX
+    /// operation.
+    DisabledByFirmware,
+    /// CR0 has a value that isn't allowed in VMX operation, e.g. because paging is disabled.
+    InvalidCr0 {
+        /// The current value of CR0.
+        value: u64,
+        /// The fixed bits of CR0.
+        fixed: VmxFixedBits,
+    },
+    /// CR4 would have a value that isn't allowed in VMX operation.
+    InvalidCr4 {
+        /// The value of CR4, with VMXE set.
+        value: u64,
+        /// The fixed bits of CR4.
+        fixed: VmxFixedBits,
+    },
+}
+
+impl fmt::Display for EnableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnableError::Unsupported => f.write_str("VMX is not supported by the processor"),
+            EnableError::DisabledByFirmware => f.write_str("VMX is disabled by the firmware"),
+            EnableError::InvalidCr0 { value, fixed } => write!(
+                f,
+                "CR0 {:#x} is not allowed in VMX operation (fixed 0 {:#x}, fixed 1 {:#x})",
+                value, fixed.fixed_0, fixed.fixed_1
+            ),
+            EnableError::InvalidCr4 { value, fixed } => write!(
+                f,
+                "CR4 {:#x} is not allowed in VMX operation (fixed 0 {:#x}, fixed 1 {:#x})",
+                value, fixed.fixed_0, fixed.fixed_1
+            ),
+        }
+    }
+}
+
+/// Returns whether the processor supports VMX, according to CPUID leaf 1, ecx bit 5.
+#[inline]
+pub fn is_supported() -> bool {
+    is_supported_with(cpuid)
+}
+
+fn is_supported_with<F>(mut query: F) -> bool
+where
+    F: FnMut(u32, u32) -> CpuidResult,
+{
+    query(1, 0).ecx & (1 << 5) != 0
+}
+
+/// Enables VMX on the current processor.
+///
+/// Checks that the processor supports VMX and that the feature control register allows VMX
+/// outside SMX operation. If the firmware left the register unlocked, VMX is allowed and the
+/// register is locked. Then CR0 and CR4 are checked against the fixed bits of VMX operation
+/// and CR4.VMXE is set.
+///
+/// The control registers aren't adjusted, so an error is returned if e.g. CR0.NE is clear.
+pub fn enable() -> Result<(), EnableError> {
+    if !is_supported() {
+        return Err(EnableError::Unsupported);
+    }
+
+    if let Some(flags) = locked_feature_control(FeatureControl::read())? {
+        // the register is unlocked, so writing it doesn't fault
+        unsafe { FeatureControl::write(flags) };
+    }
+
+    let cr4 = Cr4::read() | Cr4Flags::VIRTUAL_MACHINE_EXTENSIONS;
+    check_control_registers(
+        Cr0::read_raw(),
+        VmxFixedBits::cr0(),
+        cr4.bits(),
+        VmxFixedBits::cr4(),
+    )?;
+    // VMXE only allows entering VMX operation
+    unsafe { Cr4::write(cr4) };
+    Ok(())
+}
+
+/// Returns the feature control flags that allow VMX outside SMX operation and lock the
+/// register, or `None` if the firmware already locked it with VMX allowed.
+fn locked_feature_control(
+    flags: FeatureControlFlags,
+) -> Result<Option<FeatureControlFlags>, EnableError> {
+    if !flags.contains(FeatureControlFlags::LOCKED) {
+        Ok(Some(
+            flags | FeatureControlFlags::VMX_OUTSIDE_SMX | FeatureControlFlags::LOCKED,
+        ))
+    } else if flags.contains(FeatureControlFlags::VMX_OUTSIDE_SMX) {
+        Ok(None)
+    } else {
+        Err(EnableError::DisabledByFirmware)
+    }
+}
+
+/// Checks the values of CR0 and CR4 against their fixed bits in VMX operation.
+fn check_control_registers(
+    cr0: u64,
+    cr0_fixed: VmxFixedBits,
+    cr4: u64,
+    cr4_fixed: VmxFixedBits,
+) -> Result<(), EnableError> {
+    if !cr0_fixed.is_allowed(cr0) {
+        return Err(EnableError::InvalidCr0 {
+            value: cr0,
+            fixed: cr0_fixed,
+        });
+    }
+    if !cr4_fixed.is_allowed(cr4) {
+        return Err(EnableError::InvalidCr4 {
+            value: cr4,
+            fixed: cr4_fixed,
+        });
+    }
+    Ok(())
+}
+
+/// The guest translations that [`flush_guest_tlb`] invalidates at least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbFlushScope {
+    /// The linear and combined translations of a guest linear address for a VPID.
+    Address {
+        /// The VPID of the guest, must not be 0.
+        vpid: u16,
+        /// The guest linear address.
+        addr: VirtAddr,
+    },
+    /// The linear and combined translations of a VPID, which must not be 0.
+    Vpid(u16),
+    /// The guest-physical and combined translations derived from an EPT pointer.
+    Ept(Eptp),
+    /// All guest translations, derived from any EPT pointer or tagged with any VPID except 0.
+    All,
+}
+
+/// Invalidates cached guest translations with the narrowest invalidation that the processor
+/// supports, according to `IA32_VMX_EPT_VPID_CAP`.
+///
+/// See [`flush_guest_tlb_with`] for the fallbacks.
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation.
+pub unsafe fn flush_guest_tlb(scope: TlbFlushScope) -> Result<(), VmxError> {
+    flush_guest_tlb_with(scope, &VmxEptVpidCap::read())
+}
+
+/// Invalidates cached guest translations with the narrowest invalidation that is supported
+/// according to the given capabilities, e.g. when they were read once at startup.
+///
+/// An unsupported individual-address `invvpid` falls back to single-context and an unsupported
+/// single-context `invept` or `invvpid` falls back to all-context, which may invalidate more
+/// translations than requested. [`All`](TlbFlushScope::All) uses both instructions, skipping
+/// an instruction that isn't supported at all.
+///
+/// If no suitable type is supported, nothing is invalidated and the error is the one reported
+/// by the instruction for an unsupported type, i.e.
+/// [`InvalidInveptInvvpidOperand`](VmInstructionError::InvalidInveptInvvpidOperand).
+///
+/// ## Safety
+///
+/// This function is unsafe because it must be executed in VMX root operation and the
+/// capabilities must be those of the current processor.
+pub unsafe fn flush_guest_tlb_with(
+    scope: TlbFlushScope,
+    capabilities: &EptVpidCapabilities,
+) -> Result<(), VmxError> {
+    match scope {
+        TlbFlushScope::Address { vpid, addr } => {
+            let kind = invvpid_type(InvvpidType::IndividualAddress, capabilities)?;
+            vmx::invvpid(kind, vpid, addr.as_u64())
+        }
+        TlbFlushScope::Vpid(vpid) => {
+            let kind = invvpid_type(InvvpidType::SingleContext, capabilities)?;
+            vmx::invvpid(kind, vpid, 0)
+        }
+        TlbFlushScope::Ept(eptp) => {
+            let kind = invept_type(InveptType::SingleContext, capabilities)?;
+            vmx::invept(kind, eptp.as_raw())
+        }
+        TlbFlushScope::All => {
+            if !capabilities.invept && !capabilities.invvpid {
+                return Err(UNSUPPORTED_TYPE);
+            }
+            if capabilities.invept {
+                vmx::invept(invept_type(InveptType::AllContext, capabilities)?, 0)?;
+            }
+            if capabilities.invvpid {
+                vmx::invvpid(invvpid_type(InvvpidType::AllContext, capabilities)?, 0, 0)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The error of `invept` and `invvpid` with an unsupported type.
+const UNSUPPORTED_TYPE: VmxError = VmxError::Valid(VmInstructionError::InvalidInveptInvvpidOperand);
+
+/// Returns the narrowest supported `invept` type that invalidates at least the translations of
+/// the given type.
+fn invept_type(
+    kind: InveptType,
+    capabilities: &EptVpidCapabilities,
+) -> Result<InveptType, VmxError> {
+    if !capabilities.invept {
+        return Err(UNSUPPORTED_TYPE);
+    }
+    match kind {
+        InveptType::SingleContext if capabilities.invept_single_context => {
+            Ok(InveptType::SingleContext)
+        }
+        _ if capabilities.invept_all_context => Ok(InveptType::AllContext),
+        _ => Err(UNSUPPORTED_TYPE),
+    }
+}
+
+/// Returns the narrowest supported `invvpid` type that invalidates at least the translations
+/// of the given type.
+///
+/// The single-context type retaining globals is never used as a fallback, since it keeps the
+/// global translations of the VPID.
+fn invvpid_type(
+    kind: InvvpidType,
+    capabilities: &EptVpidCapabilities,
+) -> Result<InvvpidType, VmxError> {
+    if !capabilities.invvpid {
+        return Err(UNSUPPORTED_TYPE);
+    }
+    match kind {
+        InvvpidType::IndividualAddress if capabilities.invvpid_individual_address => {
+            Ok(InvvpidType::IndividualAddress)
+        }
+        InvvpidType::SingleContextRetainingGlobals
+            if capabilities.invvpid_single_context_retaining_globals =>
+        {
+            Ok(InvvpidType::SingleContextRetainingGlobals)
+        }
+        InvvpidType::IndividualAddress
+        | InvvpidType::SingleContext
+        | InvvpidType::SingleContextRetainingGlobals
+            if capabilities.invvpid_single_context =>
+        {
+            Ok(InvvpidType::SingleContext)
+        }
+        _ if capabilities.invvpid_all_context => Ok(InvvpidType::AllContext),
+        _ => Err(UNSUPPORTED_TYPE),
+    }
+}
+
+/// The state of a virtual CPU.
+///
+/// The host general-purpose registers are saved on the stack of [`run`](Self::run) while the
+/// guest runs, so they don't need a save area here. The host RSP and RIP fields of the VMCS
+/// are written on every entry.
+#[derive(Debug)]
+pub struct VmxVcpu {
+    vmcs: VmcsRegion,
+    vpid: u16,
+    launched: bool,
+    phys_addr: Option<PhysAddr>,
+    /// The general-purpose registers of the guest, loaded on VM entry and stored on VM exit.
+    pub guest: GuestRegisters,
+}
+
+impl VmxVcpu {
+    /// Creates a virtual CPU with the given VPID and a VMCS region with the revision identifier
+    /// of the current processor.
+    ///
+    /// A VPID of 0 is reserved for the host and means that VPIDs aren't used.
+    #[inline]
+    pub fn new(vpid: u16) -> Self {
+        VmxVcpu {
+            vmcs: VmcsRegion::new(),
+            vpid,
+            launched: false,
+            phys_addr: None,
+            guest: GuestRegisters::default(),
+        }
+    }
+
+    /// Returns the VPID of the virtual CPU.
+    #[inline]
+    pub fn vpid(&self) -> u16 {
+        self.vpid
+    }
+
+    /// Returns whether the VMCS was launched, i.e. whether the next entry uses `vmresume`.
+    #[inline]
+    pub fn is_launched(&self) -> bool {
+        self.launched
+    }
+
+    /// Returns the VMCS region of the virtual CPU.
+    #[inline]
+    pub fn vmcs_region(&self) -> &VmcsRegion {
+        &self.vmcs
+    }
+
+    /// Initializes the VMCS and makes it the current VMCS of the processor.
+    ///
+    /// The region is cleared and loaded, and the VPID is written if it isn't 0. The rest of the
+    /// VMCS has to be set up through [`Vmcs::current`] before [`run`](Self::run) is called.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that `phys_addr` is the physical
+    /// address of the VMCS region, see [`VmcsRegion::as_phys_addr`], and that the virtual CPU
+    /// isn't moved while the address is used.
+    pub unsafe fn init(&mut self, phys_addr: PhysAddr) -> Result<(), VmxError> {
+        vmx::vmclear(phys_addr)?;
+        self.launched = false;
+        self.phys_addr = Some(phys_addr);
+        vmx::vmptrld(phys_addr)?;
+        if self.vpid != 0 {
+            Vmcs::current().write16(VmcsField16::VirtualProcessorId, self.vpid)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the VMCS the current VMCS of the processor, e.g. after another VMCS was loaded.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if [`init`](Self::init) wasn't called.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the VMCS must not be active on another processor.
+    pub unsafe fn load(&mut self) -> Result<(), VmxError> {
+        vmx::vmptrld(self.phys_addr())
+    }
+
+    /// Clears the VMCS, which writes its data to the region and sets the launch state to
+    /// clear, e.g. before the virtual CPU is moved to another processor.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if [`init`](Self::init) wasn't called.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the caller must ensure that the VMCS isn't active on
+    /// another processor.
+    pub unsafe fn clear(&mut self) -> Result<(), VmxError> {
+        vmx::vmclear(self.phys_addr())?;
+        self.launched = false;
+        Ok(())
+    }
+
+    /// Enters the guest and returns the reason of the next VM exit.
+    ///
+    /// The guest is entered with `vmlaunch` if the VMCS wasn't launched yet and with
+    /// `vmresume` otherwise. The VMCS is marked as launched unless VM entry failed, which is
+    /// reported either as an error or as an exit reason with
+    /// [`is_entry_failure`](ExitReasonField::is_entry_failure) set.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because the VMCS of the virtual CPU must be the current VMCS
+    /// and the guest and host state must be set up in a way that doesn't break memory safety
+    /// of the host.
+    ///
+    /// Like [`vmentry`](vmx::vmentry), this only switches the general-purpose registers. The
+    /// caller must switch the x87, SSE and AVX state of the guest, or ensure that neither the
+    /// host nor the guest depend on it.
+    pub unsafe fn run(&mut self) -> Result<ExitReasonField, VmxError> {
+        vmx::vmentry(&mut self.guest, self.launched)?;
+        let reason = Vmcs::current().exit_reason()?;
+        if !reason.is_entry_failure() {
+            self.launched = true;
+        }
+        Ok(reason)
+    }
+
+    fn phys_addr(&self) -> PhysAddr {
+        self.phys_addr.expect("the VMCS wasn't initialized")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn support() {
+        use crate::cpuid::fake as query;
+
+        assert!(is_supported_with(query(&[(1, [0, 0, 1 << 5, 0])])));
+        assert!(!is_supported_with(query(&[(1, [0, 0, !(1 << 5), 0])])));
+    }
+
+    #[test]
+    fn feature_control() {
+        let locked_vmx = FeatureControlFlags::LOCKED | FeatureControlFlags::VMX_OUTSIDE_SMX;
+        assert_eq!(
+            locked_feature_control(FeatureControlFlags::LMCE_ON),
+            Ok(Some(locked_vmx | FeatureControlFlags::LMCE_ON))
+        );
+        assert_eq!(locked_feature_control(locked_vmx), Ok(None));
+        assert_eq!(
+            locked_feature_control(
+                FeatureControlFlags::LOCKED | FeatureControlFlags::VMX_INSIDE_SMX
+            ),
+            Err(EnableError::DisabledByFirmware)
+        );
+        assert_eq!(FeatureControlFlags::from_bits_truncate(0x5), locked_vmx);
+    }
+
+    #[test]
+    fn control_register_checks() {
+        // PE, NE and PG must be 1, CD and NW may be 1; VMXE must be 1
+        let cr0_fixed = VmxFixedBits {
+            fixed_0: 0x8000_0021,
+            fixed_1: 0xffff_ffff,
+        };
+        let cr4_fixed = VmxFixedBits {
+            fixed_0: 0x2000,
+            fixed_1: 0x3767ff,
+        };
+        assert_eq!(
+            check_control_registers(0x8005_0033, cr0_fixed, 0x3426e0, cr4_fixed),
+            Ok(())
+        );
+        assert_eq!(
+            check_control_registers(0x8005_0013, cr0_fixed, 0x3426e0, cr4_fixed),
+            Err(EnableError::InvalidCr0 {
+                value: 0x8005_0013,
+                fixed: cr0_fixed
+            })
+        );
+        // the reserved bit 15 of CR4 may not be 1
+        assert_eq!(
+            check_control_registers(0x8005_0033, cr0_fixed, 0x34a6e0, cr4_fixed),
+            Err(EnableError::InvalidCr4 {
+                value: 0x34a6e0,
+                fixed: cr4_fixed
+            })
+        );
+    }
+
+    #[test]
+    fn invalidation_fallbacks() {
+        let mut capabilities = EptVpidCapabilities::from_raw(0);
+        assert_eq!(
+            invvpid_type(InvvpidType::SingleContext, &capabilities),
+            Err(UNSUPPORTED_TYPE)
+        );
+
+        capabilities.invvpid = true;
+        capabilities.invvpid_all_context = true;
+        assert_eq!(
+            invvpid_type(InvvpidType::IndividualAddress, &capabilities),
+            Ok(InvvpidType::AllContext)
+        );
+        capabilities.invvpid_single_context = true;
+        assert_eq!(
+            invvpid_type(InvvpidType::IndividualAddress, &capabilities),
+            Ok(InvvpidType::SingleContext)
+        );
+        capabilities.invvpid_individual_address = true;
+        assert_eq!(
+            invvpid_type(InvvpidType::IndividualAddress, &capabilities),
+            Ok(InvvpidType::IndividualAddress)
+        );
+
+        capabilities.invept = true;
+        capabilities.invept_single_context = true;
+        assert_eq!(
+            invept_type(InveptType::SingleContext, &capabilities),
+            Ok(InveptType::SingleContext)
+        );
+        assert_eq!(
+            invept_type(InveptType::AllContext, &capabilities),
+            Err(UNSUPPORTED_TYPE)
+        );
+    }
+}
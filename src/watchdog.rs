@@ -0,0 +1,374 @@
+//! A hard lockup watchdog based on the performance monitoring unit (PMU).
+//!
+//! The watchdog programs a general-purpose performance counter to count unhalted core cycles
+//! and to overflow after a fixed number of cycles. The overflow is delivered as an NMI through
+//! the performance counter entry of the local APIC, so it also arrives while interrupts are
+//! disabled. The kernel marks progress with [`touch`], e.g. in its timer interrupt handler. If
+//! several consecutive NMIs see no progress, the processor is considered locked up and a
+//! callback is invoked with the interrupted context.
+//!
+//! Since halted cycles are not counted, idle processors don't receive watchdog NMIs.
+//!
+//! The state is tracked per processor, so [`cpu_local::init`] must have been called before
+//! any function of this module is used, which is why they are unsafe. The [`Watchdog`] type
+//! can also be used on its own, e.g. as part of a custom per-CPU structure.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use x86_64::watchdog;
+//!
+//! // on every processor: an NMI every ~2^30 cycles, a lockup after 8 NMIs without progress
+//! unsafe { watchdog::start(&mut apic, 0, 1 << 30, 8) }.unwrap();
+//!
+//! extern "x86-interrupt" fn timer_handler(_frame: InterruptStackFrame) {
+//!     unsafe { watchdog::touch() };
+//!     // ...
+//! }
+//!
+//! extern "x86-interrupt" fn nmi_handler(frame: InterruptStackFrame) {
+//!     let _nmi = unsafe { x86_64::nmi::enter() };
+//!     if unsafe { watchdog::handle_nmi(&mut apic, &frame, |frame| report_lockup(frame)) } {
+//!         return;
+//!     }
+//!     // other NMI sources ...
+//! }
+//! ```
+
+use crate::cpu_local;
+use crate::cpuid::perfmon::{ArchEvent, PerfmonInfo};
+use crate::registers::model_specific::{
+    PerfEvtSel, PerfEvtSelFlags, PerfGlobalCtrl, PerfGlobalOvfCtrl, Pmc,
+};
+use crate::structures::apic::{Apic, DeliveryMode, Lvt, LvtEntry};
+use crate::structures::idt::InterruptStackFrame;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// The maximum period in cycles, limited by the 32-bit writes to the counters.
+pub const MAX_PERIOD: u64 = (1 << 31) - 1;
+
+/// The error returned by [`Watchdog::start`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// The processor has no architectural PMU or can't count unhalted core cycles.
+    Unsupported,
+    /// The processor doesn't have the requested general-purpose counter.
+    InvalidCounter(u8),
+    /// The period is zero or larger than [`MAX_PERIOD`].
+    InvalidPeriod(u64),
+}
+
+impl fmt::Display for WatchdogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchdogError::Unsupported => {
+                f.write_str("the processor can't count unhalted core cycles")
+            }
+            WatchdogError::InvalidCounter(counter) => {
+                write!(f, "the processor has no performance counter {}", counter)
+            }
+            WatchdogError::InvalidPeriod(period) => write!(
+                f,
+                "the period of {} cycles is not in the range 1..={}",
+                period, MAX_PERIOD
+            ),
+        }
+    }
+}
+
+/// The watchdog state of a single processor.
+#[derive(Debug)]
+pub struct Watchdog {
+    running: AtomicBool,
+    counter: AtomicU32,
+    version: AtomicU32,
+    counter_width: AtomicU32,
+    period: AtomicU64,
+    threshold: AtomicU32,
+    progress: AtomicU64,
+    seen_progress: AtomicU64,
+    stalls: AtomicU32,
+}
+
+impl Watchdog {
+    /// Creates a stopped watchdog.
+    #[inline]
+    pub const fn new() -> Watchdog {
+        Watchdog {
+            running: AtomicBool::new(false),
+            counter: AtomicU32::new(0),
+            version: AtomicU32::new(0),
+            counter_width: AtomicU32::new(0),
+            period: AtomicU64::new(0),
+            threshold: AtomicU32::new(0),
+            progress: AtomicU64::new(0),
+            seen_progress: AtomicU64::new(0),
+            stalls: AtomicU32::new(0),
+        }
+    }
+
+    /// Starts the watchdog on the current processor.
+    ///
+    /// Programs the given general-purpose counter to raise an NMI every `period` unhalted
+    /// cycles, and reports a lockup after `threshold` consecutive NMIs without a call to
+    /// [`touch`](Watchdog::touch). The lockup is thus detected after roughly
+    /// `period * threshold` cycles of the locked processor.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because it overwrites the performance counter and the
+    /// performance counter LVT entry of the local APIC. The caller must ensure that neither is
+    /// used by other code, and that the `apic` belongs to the current processor, whose NMI
+    /// handler calls [`handle_nmi`](Watchdog::handle_nmi).
+    pub unsafe fn start<A: Apic>(
+        &self,
+        apic: &mut A,
+        counter: u8,
+        period: u64,
+        threshold: u32,
+    ) -> Result<(), WatchdogError> {
+        let info = PerfmonInfo::read().ok_or(WatchdogError::Unsupported)?;
+        if !info.is_event_available(ArchEvent::UnhaltedCoreCycles) {
+            return Err(WatchdogError::Unsupported);
+        }
+        if counter >= info.counters {
+            return Err(WatchdogError::InvalidCounter(counter));
+        }
+        if period == 0 || period > MAX_PERIOD {
+            return Err(WatchdogError::InvalidPeriod(period));
+        }
+
+        self.counter.store(u32::from(counter), Ordering::Relaxed);
+        self.version
+            .store(u32::from(info.version), Ordering::Relaxed);
+        self.counter_width
+            .store(u32::from(info.counter_width), Ordering::Relaxed);
+        self.period.store(period, Ordering::Relaxed);
+        self.threshold.store(threshold.max(1), Ordering::Relaxed);
+        self.seen_progress
+            .store(self.progress.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.stalls.store(0, Ordering::Relaxed);
+
+        let mut entry = LvtEntry::new(0);
+        entry.set_delivery_mode(DeliveryMode::Nmi);
+        apic.set_lvt(Lvt::PerformanceCounter, entry);
+
+        let (event, unit_mask) = ArchEvent::UnhaltedCoreCycles.event_select();
+        PerfEvtSel::write(counter, event, unit_mask, PerfEvtSelFlags::empty());
+        Pmc::write(counter, reload_value(period));
+        self.running.store(true, Ordering::Release);
+        if info.version >= 2 {
+            PerfGlobalOvfCtrl::clear(1 << counter);
+            PerfGlobalCtrl::write(PerfGlobalCtrl::read() | 1 << counter);
+        }
+        let flags = PerfEvtSelFlags::USER_MODE
+            | PerfEvtSelFlags::OS_MODE
+            | PerfEvtSelFlags::APIC_INTERRUPT
+            | PerfEvtSelFlags::ENABLE;
+        PerfEvtSel::write(counter, event, unit_mask, flags);
+        Ok(())
+    }
+
+    /// Stops the watchdog on the current processor and masks the performance counter LVT
+    /// entry of the given local APIC.
+    ///
+    /// ## Safety
+    ///
+    /// This function is unsafe because it writes the performance counter registers that were
+    /// checked by [`start`](Watchdog::start). The caller must ensure that the watchdog was
+    /// started on the current processor, and that the `apic` belongs to it.
+    pub unsafe fn stop<A: Apic>(&self, apic: &mut A) {
+        if !self.running.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let counter = self.counter.load(Ordering::Relaxed) as u8;
+        PerfEvtSel::write(counter, 0, 0, PerfEvtSelFlags::empty());
+        if self.version.load(Ordering::Relaxed) >= 2 {
+            PerfGlobalCtrl::write(PerfGlobalCtrl::read() & !(1 << counter));
+        }
+        apic.mask_lvt(Lvt::PerformanceCounter);
+    }
+
+    /// Returns whether the watchdog is running.
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Marks that the processor is making progress.
+    #[inline]
+    pub fn touch(&self) {
+        self.progress.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Handles an NMI on the current processor.
+    ///
+    /// Returns `false` if the NMI was not caused by the watchdog counter, so that it can be
+    /// passed on to other NMI sources. Otherwise it rearms the counter, calls `on_lockup` with
+    /// the interrupted context if the processor made no progress during the last `threshold`
+    /// NMIs, and returns `true`. Returning from `on_lockup` resets the stall count, so it is
+    /// called again after another `threshold` NMIs without progress.
+    ///
+    /// The `apic` must belong to the current processor, since the local APIC masks the
+    /// performance counter entry when it delivers the NMI.
+    pub fn handle_nmi<A, F>(&self, apic: &mut A, frame: &InterruptStackFrame, on_lockup: F) -> bool
+    where
+        A: Apic,
+        F: FnOnce(&InterruptStackFrame),
+    {
+        if !self.is_running() {
+            return false;
+        }
+        let counter = self.counter.load(Ordering::Relaxed) as u8;
+        let width = self.counter_width.load(Ordering::Relaxed);
+        if !has_overflowed(Pmc::read(counter), width) {
+            return false;
+        }
+
+        // the counter was checked and claimed by `start`
+        unsafe {
+            Pmc::write(counter, reload_value(self.period.load(Ordering::Relaxed)));
+            if self.version.load(Ordering::Relaxed) >= 2 {
+                PerfGlobalOvfCtrl::clear(1 << counter);
+            }
+        }
+        // the entry was configured by `start` and its NMI is handled right here
+        unsafe { apic.unmask_lvt(Lvt::PerformanceCounter) };
+
+        if self.is_stalled() {
+            on_lockup(frame);
+        }
+        true
+    }
+
+    /// Records an NMI and returns whether the processor made no progress during the last
+    /// `threshold` NMIs.
+    fn is_stalled(&self) -> bool {
+        let progress = self.progress.load(Ordering::Relaxed);
+        if self.seen_progress.swap(progress, Ordering::Relaxed) != progress {
+            self.stalls.store(0, Ordering::Relaxed);
+            return false;
+        }
+        let stalls = self.stalls.fetch_add(1, Ordering::Relaxed) + 1;
+        if stalls >= self.threshold.load(Ordering::Relaxed) {
+            self.stalls.store(0, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Watchdog {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the counter value that overflows after `period` increments. Only the low 32 bits
+/// are written, bit 31 is sign extended to the width of the counter.
+#[inline]
+fn reload_value(period: u64) -> u64 {
+    period.wrapping_neg() & 0xffff_ffff
+}
+
+/// Returns whether a counter that was loaded with a [`reload_value`] has overflowed, i.e.
+/// whether its most significant bit is clear again.
+#[inline]
+fn has_overflowed(value: u64, width: u32) -> bool {
+    match width {
+        1..=64 => value & (1 << (width - 1)) == 0,
+        _ => true,
+    }
+}
+
+cpu_local! {
+    static WATCHDOG: Watchdog = Watchdog::new();
+}
+
+/// Starts the watchdog on the current processor. See [`Watchdog::start`].
+///
+/// ## Safety
+///
+/// This function is unsafe for the same reasons as [`Watchdog::start`]. Also,
+/// [`cpu_local::init`] must have been called on the current processor before.
+#[inline]
+pub unsafe fn start<A: Apic>(
+    apic: &mut A,
+    counter: u8,
+    period: u64,
+    threshold: u32,
+) -> Result<(), WatchdogError> {
+    WATCHDOG.with(|watchdog| watchdog.start(apic, counter, period, threshold))
+}
+
+/// Stops the watchdog on the current processor. See [`Watchdog::stop`].
+///
+/// ## Safety
+///
+/// This function is unsafe for the same reasons as [`Watchdog::stop`]. Also,
+/// [`cpu_local::init`] must have been called on the current processor before.
+#[inline]
+pub unsafe fn stop<A: Apic>(apic: &mut A) {
+    WATCHDOG.with(|watchdog| watchdog.stop(apic))
+}
+
+/// Marks that the current processor is making progress.
+///
+/// ## Safety
+///
+/// This function is unsafe because [`cpu_local::init`] must have been called on the current
+/// processor before.
+#[inline]
+pub unsafe fn touch() {
+    WATCHDOG.with(|watchdog| watchdog.touch())
+}
+
+/// Handles an NMI on the current processor. See [`Watchdog::handle_nmi`].
+///
+/// ## Safety
+///
+/// This function is unsafe because [`cpu_local::init`] must have been called on the current
+/// processor before.
+#[inline]
+pub unsafe fn handle_nmi<A, F>(apic: &mut A, frame: &InterruptStackFrame, on_lockup: F) -> bool
+where
+    A: Apic,
+    F: FnOnce(&InterruptStackFrame),
+{
+    // not `with`: maskable interrupts are already blocked in an NMI handler, and the state is
+    // only accessed through atomics, so sharing it with the interrupted code is fine
+    (*WATCHDOG.as_ptr()).handle_nmi(apic, frame, on_lockup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_overflow() {
+        let reload = reload_value(1 << 30);
+        assert_eq!(reload, 0xc000_0000);
+        // the processor sign extends the written value to 48 bits
+        let counter = 0xffff_c000_0000;
+        assert!(!has_overflowed(counter, 48));
+        assert!(has_overflowed((counter + (1 << 30)) & 0xffff_ffff_ffff, 48));
+    }
+
+    #[test]
+    fn stall_detection() {
+        let watchdog = Watchdog::new();
+        watchdog.threshold.store(2, Ordering::Relaxed);
+
+        watchdog.touch();
+        assert!(!watchdog.is_stalled());
+        assert!(!watchdog.is_stalled());
+        assert!(watchdog.is_stalled());
+        // the stall count is reset after a lockup was reported
+        assert!(!watchdog.is_stalled());
+        watchdog.touch();
+        assert!(!watchdog.is_stalled());
+    }
+}